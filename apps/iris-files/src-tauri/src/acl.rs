@@ -0,0 +1,639 @@
+//! Access control for serving the local HTTP server on the LAN
+//!
+//! The server normally binds to loopback only. With `lan: true` in `acl.json`
+//! it binds to all interfaces; `bind` picks the address instead (`::1`,
+//! `0.0.0.0`, `::` or one interface's), and `port` the port, moving on to
+//! one of the next few if taken. With `tls: true` as well, LAN clients are
+//! served over HTTPS on `tlsPort` (see [`crate::tls`]) and plain HTTP stays
+//! on loopback. Requests from anything but this machine (loopback, or the
+//! address bound to) have to pass these rules:
+//!
+//! - the client IP must match an entry of `allow` (single IPs or CIDR subnets)
+//! - if `token` is set, `Authorization: Bearer <token>` must carry it
+//! - only /htree and /hls are reachable; the signer, relay proxy and local
+//!   search stay loopback-only
+//! - trees are served only if their exposure is `public`, looked up as
+//!   `npub/treeName`, then `npub`, then `defaultExposure` (private unless
+//!   set). naddr paths are looked up by the npub and tree name they
+//!   address. An nevent path names a tree only once its event is fetched,
+//!   so it's public only if no rule makes any tree private. nhash and HLS
+//!   paths use `defaultExposure`.
+//!
+//! Paired devices are held to their permissions instead of the allowlist and
+//! token (see [`crate::pairing`]); `read` devices still only see what the
+//! exposure rules make public. `/pair` is open to anyone with a pairing code.
+//!
+//! Rules are edited through `get_acl_rules` / `set_acl_rules`; a change of
+//! `lan`, `bind`, `port` or `tls` takes effect the next time the server starts.
+
+use crate::deep_link::KIND_TREE_ROOT;
+use crate::pairing::Permission;
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hashtree_gateway::url_decode;
+use nostr_sdk::nips::nip19::{FromBech32, Nip19, ToBech32};
+use nostr_sdk::Kind;
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// File in the data dir holding the rules
+const ACL_FILE: &str = "acl.json";
+
+/// Port the server listens on unless configured otherwise
+pub const DEFAULT_PORT: u16 = 21417;
+
+/// Port LAN clients are served HTTPS on unless configured otherwise
+pub const DEFAULT_TLS_PORT: u16 = 21443;
+
+/// Whether LAN clients may read a tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Exposure {
+    Public,
+    #[default]
+    Private,
+}
+
+/// Rules as stored in `acl.json` and edited from the frontend
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AclConfig {
+    /// Bind to all interfaces instead of loopback
+    #[serde(default)]
+    pub lan: bool,
+    /// Address to bind to, overriding `lan`
+    #[serde(default)]
+    pub bind: Option<IpAddr>,
+    /// Port to listen on, [`DEFAULT_PORT`] if unset
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Serve LAN clients over HTTPS only
+    #[serde(default)]
+    pub tls: bool,
+    /// Port of the HTTPS listener, [`DEFAULT_TLS_PORT`] if unset
+    #[serde(default)]
+    pub tls_port: Option<u16>,
+    /// Client IPs or subnets ("192.168.1.0/24", "fd00::/8") allowed in
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Bearer token LAN clients must present, if any
+    #[serde(default)]
+    pub token: Option<String>,
+    /// Exposure of trees without a rule, and of nhash/HLS paths
+    #[serde(default)]
+    pub default_exposure: Exposure,
+    /// "npub/treeName" or "npub" -> exposure
+    #[serde(default)]
+    pub trees: HashMap<String, Exposure>,
+}
+
+/// One parsed `allow` entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IpRule {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRule {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid IP address: {}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .ok_or_else(|| format!("Invalid prefix length: {}", s))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    fn matches(&self, ip: IpAddr) -> bool {
+        // Clients on a dual-stack socket show up as IPv4-mapped IPv6
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Validated rules
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    config: AclConfig,
+    allow: Vec<IpRule>,
+}
+
+impl Acl {
+    pub fn new(config: AclConfig) -> Result<Self, String> {
+        let allow = config
+            .allow
+            .iter()
+            .map(|rule| IpRule::parse(rule))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { config, allow })
+    }
+
+    pub fn config(&self) -> &AclConfig {
+        &self.config
+    }
+
+    /// Address the plain HTTP listener should listen on; loopback of the
+    /// same family when LAN clients are served over HTTPS
+    pub fn bind_addr(&self) -> SocketAddr {
+        let ip = match self.listen_ip() {
+            ip if self.tls_addr().is_none() => ip,
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        };
+        SocketAddr::new(ip, self.config.port.unwrap_or(DEFAULT_PORT))
+    }
+
+    /// Address of the HTTPS listener for LAN clients, if there is one
+    pub fn tls_addr(&self) -> Option<SocketAddr> {
+        let ip = self.listen_ip();
+        (self.config.tls && !ip.is_loopback())
+            .then(|| SocketAddr::new(ip, self.config.tls_port.unwrap_or(DEFAULT_TLS_PORT)))
+    }
+
+    fn listen_ip(&self) -> IpAddr {
+        self.config.bind.unwrap_or(if self.config.lan {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        })
+    }
+
+    /// Decide a request from `ip` for `path`; `Err` carries the status to answer with
+    pub fn check(
+        &self,
+        ip: IpAddr,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> Result<(), StatusCode> {
+        // Clients on a dual-stack socket show up as IPv4-mapped IPv6
+        let ip = ip.to_canonical();
+        if ip.is_loopback() {
+            return Ok(());
+        }
+        if !self.allow.iter().any(|rule| rule.matches(ip)) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        if let Some(token) = &self.config.token {
+            let presented = authorization.and_then(|v| v.strip_prefix("Bearer "));
+            if !presented.is_some_and(|p| constant_time_eq(p.as_bytes(), token.as_bytes())) {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+        }
+
+        match self.content_exposure(path) {
+            Some(Exposure::Public) => Ok(()),
+            // Same answer as a missing tree, so private names don't leak
+            Some(Exposure::Private) => Err(StatusCode::NOT_FOUND),
+            None => Err(StatusCode::FORBIDDEN),
+        }
+    }
+
+    /// Whether a `read` device may see `path`: tree content has to be
+    /// public; profile pictures aren't covered by the rules
+    pub fn exposes(&self, path: &str) -> bool {
+        self.content_exposure(path) != Some(Exposure::Private)
+    }
+
+    /// Exposure of /htree and /hls paths, None for other paths
+    fn content_exposure(&self, path: &str) -> Option<Exposure> {
+        if let Some(rest) = path.strip_prefix("/htree/") {
+            Some(self.tree_exposure(rest))
+        } else if path.starts_with("/hls/") {
+            Some(self.config.default_exposure)
+        } else {
+            None
+        }
+    }
+
+    /// Exposure for an /htree path (without the prefix)
+    fn tree_exposure(&self, path: &str) -> Exposure {
+        let mut segments = path.split('/');
+        let owner = segments.next().unwrap_or("");
+        if owner.starts_with("naddr1") || owner.starts_with("nevent1") {
+            return self.nip19_exposure(owner);
+        }
+        match segments.next() {
+            Some(tree) => self.named_tree_exposure(owner, &url_decode(tree)),
+            None => self.owner_exposure(owner),
+        }
+    }
+
+    fn named_tree_exposure(&self, owner: &str, tree: &str) -> Exposure {
+        match self.config.trees.get(&format!("{}/{}", owner, tree)) {
+            Some(exposure) => *exposure,
+            None => self.owner_exposure(owner),
+        }
+    }
+
+    fn owner_exposure(&self, owner: &str) -> Exposure {
+        self.config
+            .trees
+            .get(owner)
+            .copied()
+            .unwrap_or(self.config.default_exposure)
+    }
+
+    /// Exposure of the tree an naddr or nevent resolves to
+    fn nip19_exposure(&self, identifier: &str) -> Exposure {
+        match Nip19::from_bech32(identifier) {
+            Ok(Nip19::Coordinate(coordinate))
+                if coordinate.kind == Kind::from(KIND_TREE_ROOT)
+                    && !coordinate.identifier.is_empty() =>
+            {
+                match coordinate.public_key.to_bech32() {
+                    Ok(npub) => self.named_tree_exposure(&npub, &coordinate.identifier),
+                    Err(_) => Exposure::Private,
+                }
+            }
+            // Could be any tree
+            Ok(Nip19::Event(_))
+                if self.config.default_exposure == Exposure::Public
+                    && self.config.trees.values().all(|e| *e == Exposure::Public) =>
+            {
+                Exposure::Public
+            }
+            _ => Exposure::Private,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn load_acl(data: &str) -> Result<Acl, String> {
+    Acl::new(serde_json::from_str(data).map_err(|e| e.to_string())?)
+}
+
+/// Rules plus where they're persisted
+struct AclStore {
+    path: Option<PathBuf>,
+    acl: RwLock<Acl>,
+}
+
+static GLOBAL_ACL: OnceCell<AclStore> = OnceCell::new();
+
+/// Load `acl.json` from the data dir; must run before the server starts
+pub fn init_acl(data_dir: &Path) {
+    let _ = GLOBAL_ACL.get_or_init(|| {
+        let path = data_dir.join(ACL_FILE);
+        let acl = match std::fs::read_to_string(&path) {
+            Ok(data) => load_acl(&data).unwrap_or_else(|e| {
+                warn!("Ignoring invalid {:?}: {}", path, e);
+                Acl::default()
+            }),
+            Err(_) => Acl::default(),
+        };
+        if !acl.listen_ip().is_loopback() {
+            info!("LAN access enabled for {} rule(s)", acl.allow.len());
+        }
+        AclStore {
+            path: Some(path),
+            acl: RwLock::new(acl),
+        }
+    });
+}
+
+fn store() -> &'static AclStore {
+    GLOBAL_ACL.get_or_init(|| AclStore {
+        path: None,
+        acl: RwLock::new(Acl::default()),
+    })
+}
+
+/// Address the plain HTTP listener should listen on
+pub fn bind_addr() -> SocketAddr {
+    store().acl.read().bind_addr()
+}
+
+/// Address of the HTTPS listener for LAN clients, if there is one
+pub fn tls_addr() -> Option<SocketAddr> {
+    store().acl.read().tls_addr()
+}
+
+/// Whether a client at `ip` is this machine: loopback, or the address the
+/// server is bound to, which the app itself uses when that's a LAN address
+fn is_local(ip: IpAddr) -> bool {
+    ip.is_loopback()
+        || crate::htree::server_addr().is_some_and(|addr| addr.ip().to_canonical() == ip)
+}
+
+/// Middleware enforcing the rules for non-loopback clients
+pub async fn acl_middleware(request: Request, next: Next) -> Response {
+    // In-process requests have no connection info and are local
+    let Some(ip) = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip().to_canonical())
+    else {
+        return next.run(request).await;
+    };
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+
+    let path = request.uri().path();
+    let decision = if is_local(ip) || crate::pairing::is_pairing_path(path) {
+        Ok(())
+    } else if let Some(permission) = crate::pairing::device_permission(authorization) {
+        // Paired devices are held to their permission instead of the rules
+        if !permission.allows(request.method(), path) {
+            Err(StatusCode::FORBIDDEN)
+        } else if permission == Permission::Read && !store().acl.read().exposes(path) {
+            Err(StatusCode::NOT_FOUND)
+        } else {
+            Ok(())
+        }
+    } else {
+        store().acl.read().check(ip, path, authorization)
+    };
+    if let Err(status) = decision {
+        warn!("ACL denied {} {} ({})", ip, request.uri().path(), status);
+        let mut response = status.into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        return response;
+    }
+    next.run(request).await
+}
+
+/// Tauri command returning the current LAN access rules
+#[tauri::command]
+pub fn get_acl_rules() -> AclConfig {
+    store().acl.read().config.clone()
+}
+
+/// Tauri command replacing the LAN access rules and saving them
+#[tauri::command]
+pub fn set_acl_rules(rules: AclConfig) -> Result<(), String> {
+    let acl = Acl::new(rules)?;
+    let store = store();
+    if let Some(path) = &store.path {
+        let data = serde_json::to_string_pretty(acl.config()).map_err(|e| e.to_string())?;
+        crate::atomic_file::write(path, data)
+            .map_err(|e| format!("Failed to save {:?}: {}", path, e))?;
+    }
+    *store.acl.write() = acl;
+    info!("Updated ACL rules");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_rules() {
+        let subnet = IpRule::parse("192.168.1.0/24").unwrap();
+        assert!(subnet.matches(ip("192.168.1.77")));
+        assert!(subnet.matches(ip("::ffff:192.168.1.77")));
+        assert!(!subnet.matches(ip("192.168.2.1")));
+        assert!(IpRule::parse("0.0.0.0/0").unwrap().matches(ip("8.8.8.8")));
+        assert!(IpRule::parse("10.0.0.5").unwrap().matches(ip("10.0.0.5")));
+        assert!(!IpRule::parse("10.0.0.5").unwrap().matches(ip("10.0.0.6")));
+        assert!(IpRule::parse("fd00::/8").unwrap().matches(ip("fd12::1")));
+        assert!(IpRule::parse("10.0.0.0/33").is_err());
+        assert!(IpRule::parse("lan").is_err());
+    }
+
+    #[test]
+    fn test_check_allowlist_token_and_routes() {
+        let acl = Acl::new(AclConfig {
+            lan: true,
+            allow: vec!["192.168.1.0/24".to_string()],
+            token: Some("secret".to_string()),
+            default_exposure: Exposure::Public,
+            trees: HashMap::new(),
+            ..Default::default()
+        })
+        .unwrap();
+        let lan = ip("192.168.1.10");
+
+        // Loopback bypasses every rule, over IPv4 and IPv6 sockets alike
+        assert_eq!(acl.check(ip("127.0.0.1"), "/nip07", None), Ok(()));
+        assert_eq!(acl.check(ip("::ffff:127.0.0.1"), "/nip07", None), Ok(()));
+        assert_eq!(
+            acl.check(ip("10.0.0.1"), "/htree/nhash1x/a", Some("Bearer secret")),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            acl.check(lan, "/htree/nhash1x/a", None),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            acl.check(lan, "/htree/nhash1x/a", Some("Bearer wrong")),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            acl.check(lan, "/htree/nhash1x/a", Some("Bearer secret")),
+            Ok(())
+        );
+        assert_eq!(acl.check(lan, "/hls/seg.ts", Some("Bearer secret")), Ok(()));
+        // The signer and relay proxy never leave loopback
+        assert_eq!(
+            acl.check(lan, "/nip07", Some("Bearer secret")),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            acl.check(lan, "/relay", Some("Bearer secret")),
+            Err(StatusCode::FORBIDDEN)
+        );
+    }
+
+    #[test]
+    fn test_tree_exposure_rules() {
+        let config: AclConfig = serde_json::from_str(
+            r#"{
+                "allow": ["0.0.0.0/0"],
+                "trees": {
+                    "npub1alice": "public",
+                    "npub1alice/My Notes": "private",
+                    "npub1bob/videos": "public"
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.default_exposure, Exposure::Private);
+        let acl = Acl::new(config).unwrap();
+        let lan = ip("192.168.1.10");
+
+        assert_eq!(
+            acl.check(lan, "/htree/npub1alice/public/a.txt", None),
+            Ok(())
+        );
+        assert_eq!(
+            acl.check(lan, "/htree/npub1alice/My%20Notes/a.txt", None),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(acl.check(lan, "/htree/npub1bob/videos/a.mp4", None), Ok(()));
+        assert_eq!(
+            acl.check(lan, "/htree/npub1bob/private/a.txt", None),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            acl.check(lan, "/htree/nhash1abc/a.txt", None),
+            Err(StatusCode::NOT_FOUND)
+        );
+
+        // What read devices see
+        assert!(acl.exposes("/htree/npub1alice/public/a.txt"));
+        assert!(!acl.exposes("/htree/npub1alice/My%20Notes/a.txt"));
+        assert!(!acl.exposes("/hls/seg.ts"));
+        assert!(acl.exposes("/profile/npub1alice/picture"));
+    }
+
+    #[test]
+    fn test_naddr_and_nevent_paths_follow_tree_rules() {
+        use nostr_sdk::nips::nip01::Coordinate;
+        use nostr_sdk::nips::nip19::Nip19Event;
+        use nostr_sdk::{EventId, Keys};
+
+        let keys = Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        let naddr = |tree: &str| {
+            Coordinate::new(Kind::from(KIND_TREE_ROOT), keys.public_key())
+                .identifier(tree)
+                .to_bech32()
+                .unwrap()
+        };
+        let nevent = Nip19Event::new(EventId::all_zeros(), Vec::<String>::new())
+            .to_bech32()
+            .unwrap();
+
+        let mut trees = HashMap::new();
+        trees.insert(format!("{}/diary", npub), Exposure::Private);
+        let acl = Acl::new(AclConfig {
+            allow: vec!["0.0.0.0/0".to_string()],
+            default_exposure: Exposure::Public,
+            trees,
+            ..Default::default()
+        })
+        .unwrap();
+        let lan = ip("192.168.1.10");
+
+        assert_eq!(
+            acl.check(lan, &format!("/htree/{}/a.txt", naddr("photos")), None),
+            Ok(())
+        );
+        assert_eq!(
+            acl.check(lan, &format!("/htree/{}/a.txt", naddr("diary")), None),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert!(!acl.exposes(&format!("/htree/{}/a.txt", naddr("diary"))));
+        // An event could name the private tree
+        assert!(!acl.exposes(&format!("/htree/{}/a.txt", nevent)));
+
+        let open = Acl::new(AclConfig {
+            default_exposure: Exposure::Public,
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(open.exposes(&format!("/htree/{}/a.txt", nevent)));
+    }
+
+    #[test]
+    fn test_bind_addr() {
+        let bind = |config: AclConfig| Acl::new(config).unwrap().bind_addr();
+        assert_eq!(bind(AclConfig::default()).to_string(), "127.0.0.1:21417");
+        let lan = AclConfig {
+            lan: true,
+            ..Default::default()
+        };
+        assert_eq!(bind(lan.clone()).to_string(), "0.0.0.0:21417");
+        let config = AclConfig {
+            bind: Some(ip("::1")),
+            port: Some(8080),
+            ..lan
+        };
+        assert_eq!(bind(config).to_string(), "[::1]:8080");
+
+        let config: AclConfig = serde_json::from_str(r#"{"bind":"::","port":0}"#).unwrap();
+        assert_eq!(config.bind, Some(ip("::")));
+        assert_eq!(config.port, Some(0));
+    }
+
+    #[test]
+    fn test_tls_moves_plain_http_to_loopback() {
+        let acl = |config: AclConfig| Acl::new(config).unwrap();
+        let tls = AclConfig {
+            tls: true,
+            ..Default::default()
+        };
+        // Nothing to encrypt on loopback
+        assert_eq!(acl(tls.clone()).tls_addr(), None);
+        assert_eq!(acl(tls.clone()).bind_addr().to_string(), "127.0.0.1:21417");
+
+        let lan = AclConfig {
+            lan: true,
+            ..tls.clone()
+        };
+        assert_eq!(acl(lan.clone()).bind_addr().to_string(), "127.0.0.1:21417");
+        assert_eq!(
+            acl(lan).tls_addr().map(|a| a.to_string()),
+            Some("0.0.0.0:21443".to_string())
+        );
+
+        let v6 = AclConfig {
+            bind: Some(ip("::")),
+            tls_port: Some(8443),
+            ..tls
+        };
+        assert_eq!(acl(v6.clone()).bind_addr().to_string(), "[::1]:21417");
+        assert_eq!(
+            acl(v6).tls_addr().map(|a| a.to_string()),
+            Some("[::]:8443".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_rules() {
+        let config = AclConfig {
+            allow: vec!["not-an-ip".to_string()],
+            ..Default::default()
+        };
+        assert!(Acl::new(config).is_err());
+    }
+}
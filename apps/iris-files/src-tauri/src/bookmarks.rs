@@ -0,0 +1,647 @@
+//! Bookmarks using heed (LMDB), kept alongside history
+//!
+//! A bookmark is an app route saved on purpose, optionally filed in a
+//! folder and tagged. Search suggestions list bookmarks that match above
+//! plain history. `publish_bookmarks` merges them into our NIP-51 bookmark
+//! list from the relays as private `r` items, so other Nostr clients can
+//! pick them up. The list's other items are kept; those of bookmarks removed
+//! here since the last publish are dropped.
+
+use heed::types::{Bytes, Str, Unit};
+use heed::{Database, Env, EnvOpenOptions};
+use nostr_sdk::nips::nip44;
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::history::{fuzzy_match_string, HistoryEntry, HistorySearchResult};
+use crate::worker::WorkerState;
+
+/// NIP-51 bookmark list
+const KIND_BOOKMARKS: u16 = 10003;
+
+/// Bookmark stored in the database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub path: String,
+    pub label: String,
+    pub entry_type: String, // same types as history entries
+    pub npub: Option<String>,
+    pub tree_name: Option<String>,
+    pub folder: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: u64, // Unix timestamp ms
+}
+
+/// Search result returned to frontend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookmarkSearchResult {
+    pub bookmark: Bookmark,
+    pub score: f64,
+}
+
+/// Bookmark store using heed/LMDB, keyed by path
+pub struct BookmarkStore {
+    env: Env,
+    db: Database<Str, Bytes>,
+    /// Paths removed since the bookmark list was last published
+    removed: Database<Str, Unit>,
+    /// Held while fetching, merging and publishing the bookmark list
+    publish: Mutex<()>,
+}
+
+impl BookmarkStore {
+    /// Open or create the bookmark database
+    pub fn new(data_dir: &Path) -> Result<Self, String> {
+        let bookmarks_dir = data_dir.join("bookmarks");
+        std::fs::create_dir_all(&bookmarks_dir)
+            .map_err(|e| format!("Failed to create bookmarks dir: {}", e))?;
+
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(10 * 1024 * 1024)
+                .max_dbs(2)
+                .open(&bookmarks_dir)
+                .map_err(|e| format!("Failed to open bookmarks db: {}", e))?
+        };
+        if let Ok(cleared) = env.clear_stale_readers() {
+            if cleared > 0 {
+                debug!("Cleared {} stale LMDB readers for bookmark store", cleared);
+            }
+        }
+
+        let mut wtxn = env
+            .write_txn()
+            .map_err(|e| format!("Failed to start txn: {}", e))?;
+        let db = env
+            .create_database(&mut wtxn, Some("bookmarks"))
+            .map_err(|e| format!("Failed to create db: {}", e))?;
+        let removed = env
+            .create_database(&mut wtxn, Some("removed"))
+            .map_err(|e| format!("Failed to create db: {}", e))?;
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+
+        Ok(Self {
+            env,
+            db,
+            removed,
+            publish: Mutex::new(()),
+        })
+    }
+
+    /// Add a bookmark, or update the one for its path. An update keeps the
+    /// original creation time.
+    pub fn add(&self, mut bookmark: Bookmark) -> Result<Bookmark, String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+
+        let existing: Option<Bookmark> = self
+            .db
+            .get(&wtxn, &bookmark.path)
+            .map_err(|e| format!("Failed to get: {}", e))?
+            .and_then(|bytes| bincode::deserialize(bytes).ok());
+        if let Some(existing) = existing {
+            bookmark.created_at = existing.created_at;
+        }
+
+        let bytes =
+            bincode::serialize(&bookmark).map_err(|e| format!("Failed to serialize: {}", e))?;
+        self.db
+            .put(&mut wtxn, &bookmark.path, &bytes)
+            .map_err(|e| format!("Failed to put: {}", e))?;
+        self.removed
+            .delete(&mut wtxn, &bookmark.path)
+            .map_err(|e| format!("Failed to delete: {}", e))?;
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+
+        debug!("Bookmarked {}", bookmark.path);
+        Ok(bookmark)
+    }
+
+    /// Remove the bookmark for `path`, false if there was none
+    pub fn remove(&self, path: &str) -> Result<bool, String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+        let removed = self
+            .db
+            .delete(&mut wtxn, path)
+            .map_err(|e| format!("Failed to delete: {}", e))?;
+        if removed {
+            self.removed
+                .put(&mut wtxn, path, &())
+                .map_err(|e| format!("Failed to put: {}", e))?;
+        }
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+        Ok(removed)
+    }
+
+    /// Paths removed since the bookmark list was last published
+    pub fn removed(&self) -> Result<HashSet<String>, String> {
+        let rtxn = self
+            .env
+            .read_txn()
+            .map_err(|e| format!("Failed to start read txn: {}", e))?;
+        let iter = self
+            .removed
+            .iter(&rtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?;
+        let mut paths = HashSet::new();
+        for item in iter {
+            let (path, ()) = item.map_err(|e| format!("Iter error: {}", e))?;
+            paths.insert(path.to_string());
+        }
+        Ok(paths)
+    }
+
+    /// Forget `paths` as removed, once a published list no longer has them
+    pub fn clear_removed(&self, paths: &HashSet<String>) -> Result<(), String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+        for path in paths {
+            self.removed
+                .delete(&mut wtxn, path)
+                .map_err(|e| format!("Failed to delete: {}", e))?;
+        }
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))
+    }
+
+    /// All bookmarks, newest first
+    pub fn all(&self) -> Result<Vec<Bookmark>, String> {
+        let rtxn = self
+            .env
+            .read_txn()
+            .map_err(|e| format!("Failed to start read txn: {}", e))?;
+        let iter = self
+            .db
+            .iter(&rtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?;
+
+        let mut bookmarks = Vec::new();
+        for item in iter {
+            let (_key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
+            if let Ok(bookmark) = bincode::deserialize::<Bookmark>(value) {
+                bookmarks.push(bookmark);
+            }
+        }
+        bookmarks.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(bookmarks)
+    }
+
+    /// Bookmarks in `folder` (None for any) carrying `tag`, newest first
+    pub fn list(&self, folder: Option<&str>, tag: Option<&str>) -> Result<Vec<Bookmark>, String> {
+        Ok(self
+            .all()?
+            .into_iter()
+            .filter(|b| folder.map_or(true, |folder| b.folder.as_deref() == Some(folder)))
+            .filter(|b| tag.map_or(true, |tag| b.tags.iter().any(|t| t == tag)))
+            .collect())
+    }
+
+    /// Search bookmarks with fuzzy matching
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<BookmarkSearchResult>, String> {
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let query_lower = query.to_lowercase();
+        let mut results: Vec<BookmarkSearchResult> = self
+            .all()?
+            .into_iter()
+            .filter_map(|bookmark| {
+                let score = fuzzy_score(&query_lower, &bookmark);
+                (score > 0.0).then_some(BookmarkSearchResult { bookmark, score })
+            })
+            .collect();
+
+        // Sort by score descending, then newest first
+        results.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| b.bookmark.created_at.cmp(&a.bookmark.created_at))
+        });
+        results.truncate(limit);
+        Ok(results)
+    }
+}
+
+/// Calculate fuzzy match score for a bookmark, 0.0 for no match
+fn fuzzy_score(query: &str, bookmark: &Bookmark) -> f64 {
+    let mut max_score = fuzzy_match_string(query, &bookmark.label.to_lowercase());
+    max_score = max_score.max(fuzzy_match_string(query, &bookmark.path.to_lowercase()) * 0.8);
+    if let Some(ref tree_name) = bookmark.tree_name {
+        max_score = max_score.max(fuzzy_match_string(query, &tree_name.to_lowercase()) * 0.7);
+    }
+    if let Some(ref folder) = bookmark.folder {
+        max_score = max_score.max(fuzzy_match_string(query, &folder.to_lowercase()) * 0.7);
+    }
+    for tag in &bookmark.tags {
+        max_score = max_score.max(fuzzy_match_string(query, &tag.to_lowercase()) * 0.7);
+    }
+    max_score
+}
+
+/// History suggestions with matching bookmarks first. Bookmarks that were
+/// never visited are listed as entries without visits.
+pub fn rank_bookmarked(
+    history: Vec<HistorySearchResult>,
+    bookmarks: Vec<BookmarkSearchResult>,
+    limit: usize,
+) -> Vec<HistorySearchResult> {
+    let bookmarked: HashSet<String> = bookmarks.iter().map(|b| b.bookmark.path.clone()).collect();
+    let (mut visited, rest): (Vec<_>, Vec<_>) = history
+        .into_iter()
+        .partition(|result| bookmarked.contains(&result.entry.path));
+
+    let mut results: Vec<HistorySearchResult> = bookmarks
+        .into_iter()
+        .map(|BookmarkSearchResult { bookmark, score }| {
+            let entry = match visited.iter().position(|r| r.entry.path == bookmark.path) {
+                Some(i) => visited.swap_remove(i).entry,
+                None => HistoryEntry {
+                    path: bookmark.path,
+                    label: bookmark.label,
+                    entry_type: bookmark.entry_type,
+                    npub: bookmark.npub,
+                    tree_name: bookmark.tree_name,
+                    visit_count: 0,
+                    last_visited: bookmark.created_at,
+                    first_visited: bookmark.created_at,
+                },
+            };
+            HistorySearchResult {
+                entry,
+                score,
+                bookmarked: true,
+            }
+        })
+        .collect();
+    results.extend(rest);
+    results.truncate(limit);
+    results
+}
+
+/// `r` item of the bookmark of `path`
+fn bookmark_item(path: &str) -> Vec<String> {
+    vec![
+        "r".to_string(),
+        format!("htree://{}", path.trim_start_matches('/')),
+    ]
+}
+
+/// Our NIP-51 bookmark list (kind 10003) `existing` with `bookmarks` added
+/// as private `htree://` items, readable only by us, and the items of
+/// `removed` paths dropped. Its other items, public and private, are kept.
+pub fn merge_bookmark_list(
+    keys: &Keys,
+    existing: Option<&Event>,
+    bookmarks: &[Bookmark],
+    removed: &HashSet<String>,
+) -> Result<Event, String> {
+    let removed: HashSet<Vec<String>> = removed.iter().map(|path| bookmark_item(path)).collect();
+    let mut public = Vec::new();
+    let mut private: Vec<Vec<String>> = Vec::new();
+    if let Some(existing) = existing {
+        if existing.pubkey != keys.public_key() || existing.kind != Kind::from(KIND_BOOKMARKS) {
+            return Err("Not our bookmark list".to_string());
+        }
+        public = existing
+            .tags
+            .iter()
+            .filter(|tag| !removed.contains(tag.as_slice()))
+            .cloned()
+            .collect();
+        if !existing.content.is_empty() {
+            let plaintext =
+                nip44::decrypt(keys.secret_key(), &keys.public_key(), &existing.content)
+                    .map_err(|e| format!("NIP-44 decryption failed: {}", e))?;
+            private = serde_json::from_str(&plaintext)
+                .map_err(|e| format!("Invalid bookmark list items: {}", e))?;
+            private.retain(|item| !removed.contains(item));
+        }
+    }
+
+    let mut listed: HashSet<Vec<String>> = private.iter().cloned().collect();
+    listed.extend(public.iter().map(|tag: &Tag| tag.as_slice().to_vec()));
+    for bookmark in bookmarks {
+        let item = bookmark_item(&bookmark.path);
+        if listed.insert(item.clone()) {
+            private.push(item);
+        }
+    }
+
+    let plaintext = serde_json::to_string(&private)
+        .map_err(|e| format!("Failed to encode bookmarks: {}", e))?;
+    let content = nip44::encrypt(
+        keys.secret_key(),
+        &keys.public_key(),
+        plaintext,
+        nip44::Version::V2,
+    )
+    .map_err(|e| format!("NIP-44 encryption failed: {}", e))?;
+    EventBuilder::new(Kind::from(KIND_BOOKMARKS), content, public)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+// ============================================================================
+// Tauri Commands
+// ============================================================================
+
+/// The guest session's own bookmarks while one is on, the persistent ones otherwise
+pub fn active_store(bookmarks: &Arc<BookmarkStore>, worker: &WorkerState) -> Arc<BookmarkStore> {
+    worker
+        .guest_bookmarks()
+        .unwrap_or_else(|| bookmarks.clone())
+}
+
+/// Bookmark a route, or update its bookmark
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub fn add_bookmark(
+    path: String,
+    label: String,
+    entry_type: String,
+    npub: Option<String>,
+    tree_name: Option<String>,
+    folder: Option<String>,
+    tags: Option<Vec<String>>,
+    bookmarks: tauri::State<'_, Arc<BookmarkStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<Bookmark, String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let bookmark = Bookmark {
+        path,
+        label,
+        entry_type,
+        npub,
+        tree_name,
+        folder: folder.filter(|f| !f.trim().is_empty()),
+        tags: tags.unwrap_or_default(),
+        created_at: now,
+    };
+
+    active_store(&bookmarks, &worker).add(bookmark)
+}
+
+/// Remove a bookmark
+#[tauri::command]
+pub fn remove_bookmark(
+    path: String,
+    bookmarks: tauri::State<'_, Arc<BookmarkStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<bool, String> {
+    active_store(&bookmarks, &worker).remove(&path)
+}
+
+/// List bookmarks, optionally of one folder or tag
+#[tauri::command]
+pub fn list_bookmarks(
+    folder: Option<String>,
+    tag: Option<String>,
+    bookmarks: tauri::State<'_, Arc<BookmarkStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<Vec<Bookmark>, String> {
+    active_store(&bookmarks, &worker).list(folder.as_deref(), tag.as_deref())
+}
+
+/// Search bookmarks with fuzzy matching
+#[tauri::command]
+pub fn search_bookmarks(
+    query: String,
+    limit: usize,
+    bookmarks: tauri::State<'_, Arc<BookmarkStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<Vec<BookmarkSearchResult>, String> {
+    active_store(&bookmarks, &worker).search(&query, limit)
+}
+
+/// Merge the current bookmarks into our NIP-51 bookmark list from the
+/// relays and publish it. Returns the event id.
+#[tauri::command]
+pub async fn publish_bookmarks(
+    app: tauri::AppHandle,
+    bookmarks: tauri::State<'_, Arc<BookmarkStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<String, String> {
+    if worker.is_guest() {
+        return Err("Not available in guest mode".to_string());
+    }
+    let keys = worker.nostr.get_keys().ok_or("No signing identity set")?;
+    worker
+        .nostr
+        .ensure_client(Some(app), Some(worker.ndb.clone()))
+        .await?;
+    let _publish = bookmarks.publish.lock().await;
+
+    // A fetch no relay answered is an error rather than an empty list:
+    // publishing from nothing would drop the bookmarks of other clients
+    let filter = nostr_sdk::Filter::new()
+        .kind(Kind::from(KIND_BOOKMARKS))
+        .author(keys.public_key())
+        .limit(1);
+    let lists = worker
+        .nostr
+        .fetch_events_answered(vec![filter], std::time::Duration::from_secs(3))
+        .await
+        .map_err(|e| format!("Failed to fetch the bookmark list: {}", e))?;
+    let existing = lists.iter().max_by_key(|event| event.created_at);
+
+    let removed = bookmarks.removed()?;
+    let event = merge_bookmark_list(&keys, existing, &bookmarks.all()?, &removed)?;
+    let event_json =
+        serde_json::to_value(&event).map_err(|e| format!("Failed to encode event: {}", e))?;
+    let event_id = worker.nostr.publish(event_json).await?;
+    bookmarks.clear_removed(&removed)?;
+    Ok(event_id.to_hex())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn bookmark(path: &str, label: &str, folder: Option<&str>, tags: &[&str]) -> Bookmark {
+        Bookmark {
+            path: path.to_string(),
+            label: label.to_string(),
+            entry_type: "tree".to_string(),
+            npub: None,
+            tree_name: None,
+            folder: folder.map(str::to_string),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            created_at: 1000,
+        }
+    }
+
+    #[test]
+    fn test_bookmark_store() {
+        let dir = tempdir().unwrap();
+        let store = BookmarkStore::new(dir.path()).unwrap();
+        store
+            .add(bookmark(
+                "/a",
+                "Holiday photos",
+                Some("Photos"),
+                &["family"],
+            ))
+            .unwrap();
+        let mut later = bookmark("/b", "Recipes", None, &["food"]);
+        later.created_at = 2000;
+        store.add(later).unwrap();
+
+        // Updating keeps the creation time
+        let mut update = bookmark("/a", "Summer photos", Some("Photos"), &["family"]);
+        update.created_at = 3000;
+        assert_eq!(store.add(update).unwrap().created_at, 1000);
+
+        let all = store.list(None, None).unwrap();
+        assert_eq!(
+            all.iter().map(|b| b.path.as_str()).collect::<Vec<_>>(),
+            ["/b", "/a"]
+        );
+        assert_eq!(
+            store.list(Some("Photos"), None).unwrap()[0].label,
+            "Summer photos"
+        );
+        assert_eq!(store.list(None, Some("food")).unwrap()[0].path, "/b");
+        assert!(store.list(Some("Photos"), Some("food")).unwrap().is_empty());
+
+        assert_eq!(store.search("family", 10).unwrap()[0].bookmark.path, "/a");
+        assert!(store.remove("/a").unwrap());
+        assert!(!store.remove("/a").unwrap());
+        assert!(store.search("summer", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_bookmarks_rank_above_history() {
+        let entry = |path: &str, visits: u32| HistoryEntry {
+            path: path.to_string(),
+            label: path.to_string(),
+            entry_type: "tree".to_string(),
+            npub: None,
+            tree_name: None,
+            visit_count: visits,
+            last_visited: 500,
+            first_visited: 100,
+        };
+        let history = vec![
+            HistorySearchResult {
+                entry: entry("/often", 50),
+                score: 9.0,
+                bookmarked: false,
+            },
+            HistorySearchResult {
+                entry: entry("/saved", 2),
+                score: 4.0,
+                bookmarked: false,
+            },
+        ];
+        let bookmarks = vec![
+            BookmarkSearchResult {
+                bookmark: bookmark("/saved", "Saved", None, &[]),
+                score: 4.0,
+            },
+            BookmarkSearchResult {
+                bookmark: bookmark("/unvisited", "Unvisited", None, &[]),
+                score: 2.0,
+            },
+        ];
+
+        let ranked = rank_bookmarked(history, bookmarks, 10);
+        let paths: Vec<_> = ranked.iter().map(|r| r.entry.path.as_str()).collect();
+        assert_eq!(paths, ["/saved", "/unvisited", "/often"]);
+        // A visited bookmark keeps its history
+        assert_eq!(ranked[0].entry.visit_count, 2);
+        assert_eq!(ranked[1].entry.visit_count, 0);
+        assert!(ranked[1].bookmarked && !ranked[2].bookmarked);
+        assert_eq!(rank_bookmarked(Vec::new(), Vec::new(), 10).len(), 0);
+    }
+
+    #[test]
+    fn test_bookmark_list_is_private() {
+        let keys = Keys::generate();
+        let event = merge_bookmark_list(
+            &keys,
+            None,
+            &[bookmark("/nhash1abc/a.md", "A", None, &[])],
+            &HashSet::new(),
+        )
+        .unwrap();
+        assert_eq!(event.kind, Kind::from(KIND_BOOKMARKS));
+        assert!(event.tags.is_empty());
+        let items = nip44::decrypt(keys.secret_key(), &keys.public_key(), &event.content).unwrap();
+        assert_eq!(items, r#"[["r","htree://nhash1abc/a.md"]]"#);
+    }
+
+    #[test]
+    fn test_bookmark_list_keeps_other_items() {
+        let keys = Keys::generate();
+        let note = vec!["e".to_string(), "ab".repeat(32)];
+        let private = vec![note.clone(), bookmark_item("/kept"), bookmark_item("/gone")];
+        let content = nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            serde_json::to_string(&private).unwrap(),
+            nip44::Version::V2,
+        )
+        .unwrap();
+        let existing =
+            EventBuilder::new(Kind::from(KIND_BOOKMARKS), content, [Tag::hashtag("rust")])
+                .to_event(&keys)
+                .unwrap();
+
+        let removed = HashSet::from(["/gone".to_string()]);
+        let merged = merge_bookmark_list(
+            &keys,
+            Some(&existing),
+            &[bookmark("/new", "New", None, &[])],
+            &removed,
+        )
+        .unwrap();
+        assert_eq!(merged.tags, existing.tags);
+        let items: Vec<Vec<String>> = serde_json::from_str(
+            &nip44::decrypt(keys.secret_key(), &keys.public_key(), &merged.content).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(items, [note, bookmark_item("/kept"), bookmark_item("/new")]);
+
+        let other = Keys::generate();
+        assert!(merge_bookmark_list(&other, Some(&existing), &[], &removed).is_err());
+    }
+
+    #[test]
+    fn test_removed_bookmarks_are_remembered() {
+        let dir = tempdir().unwrap();
+        let store = BookmarkStore::new(dir.path()).unwrap();
+        store.add(bookmark("/a", "A", None, &[])).unwrap();
+        store.add(bookmark("/b", "B", None, &[])).unwrap();
+        assert!(store.remove("/a").unwrap());
+        assert!(store.remove("/b").unwrap());
+        assert!(!store.remove("/c").unwrap());
+        // Bookmarking again takes it back
+        store.add(bookmark("/b", "B", None, &[])).unwrap();
+
+        let removed = store.removed().unwrap();
+        assert_eq!(removed, HashSet::from(["/a".to_string()]));
+        store.clear_removed(&removed).unwrap();
+        assert!(store.removed().unwrap().is_empty());
+    }
+}
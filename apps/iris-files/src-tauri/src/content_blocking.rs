@@ -0,0 +1,524 @@
+//! Ad and tracker blocking for external web content
+//!
+//! Child webviews showing http(s) pages can have requests to ad and tracker
+//! domains refused. The rules are EasyList-style: `||domain^` blocks a
+//! domain and its subdomains, `@@||domain^` excepts one, and hosts-file lines
+//! (`0.0.0.0 domain`) work too. Rules about paths, cosmetic filters and the
+//! like are skipped; only whole domains are matched. A few well-known domains
+//! are built in, and `blocklist.txt` in the data dir adds more.
+//!
+//! Blocking is off until enabled, and can be turned off for single sites.
+//! Navigations to blocked domains are refused by the webview's navigation
+//! hook, and every connection the webview makes by its filter proxy (see
+//! [`crate::filter_proxy`]). An initialization script compiled from the same
+//! rules also stops the page's own requests (fetch, XHR, beacons, sockets,
+//! and the src of scripts, images and frames added later) before they're
+//! made. Blocked requests are counted per site and reported with
+//! [`BLOCKED_EVENT`].
+
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, Runtime};
+use tracing::{info, warn};
+
+/// File in the data dir holding the settings
+const SETTINGS_FILE: &str = "content_blocking.json";
+
+/// File in the data dir with rules of the user's
+const BLOCKLIST_FILE: &str = "blocklist.txt";
+
+/// Event with the updated count of a site, `{origin, blocked}`
+pub const BLOCKED_EVENT: &str = "content-blocked";
+
+/// Built-in rules
+const DEFAULT_RULES: &str = "\
+! Ads
+||doubleclick.net^
+||googlesyndication.com^
+||googleadservices.com^
+||adservice.google.com^
+||amazon-adsystem.com^
+||adnxs.com^
+||criteo.com^
+||taboola.com^
+||outbrain.com^
+! Trackers
+||google-analytics.com^
+||googletagmanager.com^
+||scorecardresearch.com^
+||quantserve.com^
+||hotjar.com^
+||connect.facebook.net^
+";
+
+/// Domains blocked by a rule list, with their subdomains
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Blocklist {
+    domains: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl Blocklist {
+    /// Compile the domain rules of `text`, skipping the rest
+    pub fn parse(text: &str) -> Self {
+        let mut list = Self::default();
+        list.extend(text);
+        list
+    }
+
+    fn extend(&mut self, text: &str) {
+        for line in text.lines() {
+            let Some((domain, exception)) = parse_rule(line) else {
+                continue;
+            };
+            if exception {
+                self.exceptions.insert(domain);
+            } else {
+                self.domains.insert(domain);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Whether `host` or a domain above it is blocked and not excepted
+    pub fn blocks_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let suffixes = || {
+            std::iter::successors(Some(host.as_str()), |h| {
+                h.split_once('.').map(|(_, rest)| rest)
+            })
+        };
+        suffixes().any(|d| self.domains.contains(d))
+            && !suffixes().any(|d| self.exceptions.contains(d))
+    }
+
+    /// Whether a request to `url` is blocked; only web URLs can be
+    pub fn blocks_url(&self, url: &tauri::Url) -> bool {
+        matches!(url.scheme(), "http" | "https" | "ws" | "wss")
+            && url.host_str().is_some_and(|host| self.blocks_host(host))
+    }
+}
+
+/// The domain of a rule, and whether it's an exception
+fn parse_rule(line: &str) -> Option<(String, bool)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('!') || line.starts_with('[') || line.starts_with('#') {
+        return None;
+    }
+    if line.contains("##") || line.contains("#@#") || line.contains("#?#") {
+        return None;
+    }
+
+    let mut words = line.split_whitespace();
+    let first = words.next()?;
+    let (rule, exception) = if matches!(first, "0.0.0.0" | "127.0.0.1") {
+        (words.next()?, false)
+    } else {
+        match first.strip_prefix("@@") {
+            Some(rule) => (rule, true),
+            None => (first, false),
+        }
+    };
+
+    let rule = rule.split('$').next().unwrap_or("");
+    let rule = rule.strip_prefix("||").unwrap_or(rule);
+    let domain = rule.strip_suffix('^').unwrap_or(rule).to_ascii_lowercase();
+    let valid = domain.contains('.')
+        && !domain.starts_with('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    valid.then_some((domain, exception))
+}
+
+/// Origin of a page, as sites are told apart by
+pub fn page_origin(url: &tauri::Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Settings {
+    enabled: bool,
+    /// Origins blocking is turned off for
+    disabled_origins: BTreeSet<String>,
+}
+
+/// Blocking as shown by `get_content_blocking`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentBlockingStatus {
+    pub enabled: bool,
+    pub disabled_origins: Vec<String>,
+    /// Number of blocked domains
+    pub rules: usize,
+    /// Requests blocked since start, by page origin
+    pub blocked: BTreeMap<String, u64>,
+}
+
+struct ContentBlocking {
+    dir: Option<PathBuf>,
+    settings: RwLock<Settings>,
+    list: RwLock<Blocklist>,
+    blocked: RwLock<BTreeMap<String, u64>>,
+}
+
+impl ContentBlocking {
+    fn new(dir: Option<PathBuf>, settings: Settings) -> Self {
+        let blocking = Self {
+            dir,
+            settings: RwLock::new(settings),
+            list: RwLock::new(Blocklist::default()),
+            blocked: RwLock::new(BTreeMap::new()),
+        };
+        blocking.load_list();
+        blocking
+    }
+
+    /// Compile the built-in rules and `blocklist.txt`
+    fn load_list(&self) -> usize {
+        let mut list = Blocklist::parse(DEFAULT_RULES);
+        if let Some(dir) = &self.dir {
+            if let Ok(text) = std::fs::read_to_string(dir.join(BLOCKLIST_FILE)) {
+                list.extend(&text);
+            }
+        }
+        let len = list.len();
+        *self.list.write() = list;
+        len
+    }
+
+    fn save(&self, settings: &Settings) -> Result<(), String> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec_pretty(settings)
+            .map_err(|e| format!("Failed to encode content blocking settings: {}", e))?;
+        crate::atomic_file::write(&dir.join(SETTINGS_FILE), data)
+            .map_err(|e| format!("Failed to save content blocking settings: {}", e))
+    }
+
+    fn active_for(&self, origin: &str) -> bool {
+        let settings = self.settings.read();
+        settings.enabled && !settings.disabled_origins.contains(origin)
+    }
+
+    /// Count `count` requests blocked on `origin`, returning its total
+    fn count(&self, origin: &str, count: u64) -> u64 {
+        let mut blocked = self.blocked.write();
+        let total = blocked.entry(origin.to_string()).or_default();
+        *total += count;
+        *total
+    }
+
+    fn status(&self) -> ContentBlockingStatus {
+        let settings = self.settings.read();
+        ContentBlockingStatus {
+            enabled: settings.enabled,
+            disabled_origins: settings.disabled_origins.iter().cloned().collect(),
+            rules: self.list.read().len(),
+            blocked: self.blocked.read().clone(),
+        }
+    }
+
+    /// Initialization script enforcing the rules in pages, if blocking is on
+    fn script(&self) -> Option<String> {
+        let settings = self.settings.read();
+        if !settings.enabled {
+            return None;
+        }
+        let list = self.list.read();
+        let json = |set: &HashSet<String>| serde_json::to_string(set).unwrap_or_default();
+        let disabled = serde_json::to_string(&settings.disabled_origins).unwrap_or_default();
+        Some(format!(
+            r#"
+(function() {{
+  if (!/^https?:$/.test(location.protocol)) return;
+  if ({disabled}.includes(location.origin)) return;
+  const DOMAINS = new Set({domains});
+  const EXCEPTIONS = new Set({exceptions});
+
+  function suffixes(host) {{
+    const parts = host.toLowerCase().replace(/\.$/, '').split('.');
+    return parts.map((_, i) => parts.slice(i).join('.'));
+  }}
+
+  function blocked(url) {{
+    let parsed;
+    try {{
+      parsed = new URL(String(url), location.href);
+    }} catch {{
+      return false;
+    }}
+    if (!/^(https?|wss?):$/.test(parsed.protocol)) return false;
+    const hosts = suffixes(parsed.hostname);
+    return hosts.some((h) => DOMAINS.has(h)) && !hosts.some((h) => EXCEPTIONS.has(h));
+  }}
+
+  let pending = 0;
+  function report() {{
+    pending++;
+    if (pending > 1) return;
+    setTimeout(() => {{
+      const count = pending;
+      pending = 0;
+      window.__htreeReportBlocked?.(count);
+    }}, 1000);
+  }}
+
+  const originalFetch = window.fetch;
+  window.fetch = function(input, init) {{
+    const url = input instanceof Request ? input.url : input;
+    if (blocked(url)) {{
+      report();
+      return Promise.reject(new TypeError('Blocked by content blocker'));
+    }}
+    return originalFetch.apply(this, arguments);
+  }};
+
+  const originalOpen = XMLHttpRequest.prototype.open;
+  XMLHttpRequest.prototype.open = function(method, url) {{
+    if (blocked(url)) {{
+      report();
+      throw new TypeError('Blocked by content blocker');
+    }}
+    return originalOpen.apply(this, arguments);
+  }};
+
+  if (navigator.sendBeacon) {{
+    const originalBeacon = navigator.sendBeacon.bind(navigator);
+    navigator.sendBeacon = function(url, data) {{
+      if (blocked(url)) {{
+        report();
+        return false;
+      }}
+      return originalBeacon(url, data);
+    }};
+  }}
+
+  for (const name of ['WebSocket', 'EventSource']) {{
+    const Original = window[name];
+    if (!Original) continue;
+    const Wrapped = function(url, options) {{
+      if (blocked(url)) {{
+        report();
+        throw new TypeError('Blocked by content blocker');
+      }}
+      return new Original(url, options);
+    }};
+    Wrapped.prototype = Original.prototype;
+    for (const key of ['CONNECTING', 'OPEN', 'CLOSING', 'CLOSED']) {{
+      if (key in Original) Wrapped[key] = Original[key];
+    }}
+    window[name] = Wrapped;
+  }}
+
+  function neutralize(node) {{
+    if (!(node instanceof Element)) return;
+    for (const el of [node, ...node.querySelectorAll('script[src], img[src], iframe[src]')]) {{
+      const src = el.getAttribute && el.getAttribute('src');
+      if (src && /^(SCRIPT|IMG|IFRAME)$/.test(el.tagName) && blocked(src)) {{
+        el.removeAttribute('src');
+        if (el.tagName === 'SCRIPT') el.type = 'text/blocked';
+        report();
+      }}
+    }}
+  }}
+
+  new MutationObserver((mutations) => {{
+    for (const mutation of mutations) {{
+      if (mutation.type === 'attributes') neutralize(mutation.target);
+      for (const node of mutation.addedNodes) neutralize(node);
+    }}
+  }}).observe(document, {{ childList: true, subtree: true, attributes: true, attributeFilter: ['src'] }});
+}})();
+"#,
+            disabled = disabled,
+            domains = json(&list.domains),
+            exceptions = json(&list.exceptions),
+        ))
+    }
+}
+
+static GLOBAL_BLOCKING: OnceCell<ContentBlocking> = OnceCell::new();
+
+/// Load `content_blocking.json` and `blocklist.txt` from the data dir
+pub fn init_content_blocking(data_dir: &Path) {
+    let _ = GLOBAL_BLOCKING.get_or_init(|| {
+        let settings = std::fs::read(data_dir.join(SETTINGS_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        ContentBlocking::new(Some(data_dir.to_path_buf()), settings)
+    });
+}
+
+fn blocking() -> &'static ContentBlocking {
+    GLOBAL_BLOCKING.get_or_init(|| ContentBlocking::new(None, Settings::default()))
+}
+
+/// Initialization script for a webview of external pages, if blocking is on
+pub fn blocking_script() -> Option<String> {
+    blocking().script()
+}
+
+/// Whether blocking is on, for the sites it isn't turned off for
+pub fn is_enabled() -> bool {
+    blocking().settings.read().enabled
+}
+
+/// Whether a webview on `page` (its URL before navigating, if known) may
+/// not navigate to `url`; a refused navigation is counted
+pub fn blocks_navigation<R: Runtime>(
+    app: &AppHandle<R>,
+    page: Option<&tauri::Url>,
+    url: &tauri::Url,
+) -> bool {
+    refuses(app, page, url, "navigation")
+}
+
+/// Whether a webview on `page` may not connect to `url`, as asked by its
+/// filter proxy; a refused request is counted
+pub fn blocks_request<R: Runtime>(
+    app: &AppHandle<R>,
+    page: Option<&tauri::Url>,
+    url: &tauri::Url,
+) -> bool {
+    refuses(app, page, url, "request")
+}
+
+fn refuses<R: Runtime>(
+    app: &AppHandle<R>,
+    page: Option<&tauri::Url>,
+    url: &tauri::Url,
+    what: &str,
+) -> bool {
+    let blocking = blocking();
+    let origin = page.map_or_else(|| page_origin(url), page_origin);
+    if !blocking.active_for(&origin) || !blocking.list.read().blocks_url(url) {
+        return false;
+    }
+    info!("Blocked {} to {} on {}", what, url, origin);
+    record_blocked(app, &origin, 1);
+    true
+}
+
+fn record_blocked<R: Runtime>(app: &AppHandle<R>, origin: &str, count: u64) {
+    let blocked = blocking().count(origin, count);
+    let _ = app.emit(
+        BLOCKED_EVENT,
+        serde_json::json!({ "origin": origin, "blocked": blocked }),
+    );
+}
+
+/// Count `count` requests the script blocked on `origin`, as reported
+/// through the webview's `blocked` events
+pub fn report_blocked<R: Runtime>(app: &AppHandle<R>, origin: &str, count: u64) {
+    if blocking().active_for(origin) {
+        record_blocked(app, origin, count);
+    }
+}
+
+#[tauri::command]
+pub fn get_content_blocking() -> ContentBlockingStatus {
+    blocking().status()
+}
+
+/// Tauri command turning blocking on or off, for every site or just
+/// `origin`. Applies to webviews created afterwards.
+#[tauri::command]
+pub fn set_content_blocking(enabled: bool, origin: Option<String>) -> Result<(), String> {
+    let blocking = blocking();
+    let mut settings = blocking.settings.read().clone();
+    match origin {
+        Some(origin) if enabled => {
+            settings.disabled_origins.remove(&origin);
+        }
+        Some(origin) => {
+            settings.disabled_origins.insert(origin);
+        }
+        None => settings.enabled = enabled,
+    }
+    blocking.save(&settings)?;
+    *blocking.settings.write() = settings;
+    Ok(())
+}
+
+/// Tauri command compiling the rules again after `blocklist.txt` changed,
+/// returning the number of blocked domains
+#[tauri::command]
+pub fn reload_blocklist() -> usize {
+    let len = blocking().load_list();
+    if len == 0 {
+        warn!("Content blocklist is empty");
+    }
+    info!("Compiled {} blocked domains", len);
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist_matches_domains_and_subdomains() {
+        let list = Blocklist::parse(
+            "[Adblock Plus 2.0]\n\
+             ! comment\n\
+             ||ads.example^\n\
+             ||tracker.example^$third-party\n\
+             @@||good.ads.example^\n\
+             0.0.0.0 hosts.example\n\
+             example.com##.banner\n\
+             /banner/*\n",
+        );
+        assert_eq!(list.len(), 3);
+        assert!(list.blocks_host("ads.example"));
+        assert!(list.blocks_host("cdn.ads.example"));
+        assert!(list.blocks_host("Tracker.Example."));
+        assert!(list.blocks_host("hosts.example"));
+        assert!(!list.blocks_host("good.ads.example"));
+        assert!(!list.blocks_host("notads.example"));
+        assert!(!list.blocks_host("example.com"));
+
+        let url = |s: &str| tauri::Url::parse(s).unwrap();
+        assert!(list.blocks_url(&url("https://cdn.ads.example/a.js")));
+        assert!(list.blocks_url(&url("wss://ads.example/socket")));
+        assert!(!list.blocks_url(&url("htree://ads.example/")));
+    }
+
+    #[test]
+    fn test_blocking_can_be_disabled_per_origin() {
+        let blocking = ContentBlocking::new(None, Settings::default());
+        assert!(blocking.script().is_none());
+        assert!(!blocking.active_for("https://news.example"));
+
+        blocking.settings.write().enabled = true;
+        assert!(blocking.active_for("https://news.example"));
+        let script = blocking.script().unwrap();
+        assert!(script.contains("\"doubleclick.net\""));
+
+        blocking
+            .settings
+            .write()
+            .disabled_origins
+            .insert("https://news.example".to_string());
+        assert!(!blocking.active_for("https://news.example"));
+        let script = blocking.script().unwrap();
+        assert!(script.contains("[\"https://news.example\"]"));
+
+        assert_eq!(blocking.count("https://news.example", 2), 2);
+        assert_eq!(blocking.count("https://news.example", 3), 5);
+        assert_eq!(blocking.status().blocked["https://news.example"], 5);
+    }
+}
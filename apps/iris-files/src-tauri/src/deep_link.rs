@@ -0,0 +1,275 @@
+//! Opening htree://, web+htree: and nostr: links from the OS
+//!
+//! The app is registered as the handler for these schemes. An opened link
+//! is turned into an app route (`/npub1.../tree/path`, `/nhash1...`) and sent
+//! to the main window as a `deep-link-open` event. `nevent`/`note` links are
+//! looked up on the relays first and open the tree the event is the root of,
+//! or else its author's profile.
+//!
+//! A link that launched the app arrives before the frontend listens, so the
+//! route is kept until the frontend picks it up with
+//! `take_pending_deep_link` once it's loaded.
+
+use nostr_sdk::nips::nip19::{FromBech32, Nip19, ToBech32};
+use nostr_sdk::{EventId, Filter, Kind, PublicKey};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_deep_link::DeepLinkExt;
+use tracing::{info, warn};
+
+use crate::worker::WorkerState;
+
+/// Schemes registered with the OS, as in `plugins.deep-link` of tauri.conf.json
+const SCHEMES: &[&str] = &["htree", "web+htree", "nostr"];
+/// Kind of hashtree root events
+pub(crate) const KIND_TREE_ROOT: u16 = 30078;
+
+/// Route of a link opened before the frontend was ready for it
+static PENDING_ROUTE: Mutex<Option<String>> = Mutex::new(None);
+static FRONTEND_READY: AtomicBool = AtomicBool::new(false);
+
+/// Where a deep link points
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeepLink {
+    /// An app route
+    Route(String),
+    /// A Nostr event, to be looked up
+    Event {
+        id: EventId,
+        author: Option<PublicKey>,
+    },
+}
+
+/// Parse a deep link, None for unsupported links. Secret keys (`nsec`) are
+/// never accepted.
+pub fn parse_deep_link(url: &str) -> Option<DeepLink> {
+    let (scheme, rest) = url.split_once(':')?;
+    let scheme = scheme.to_ascii_lowercase();
+    if !SCHEMES.contains(&scheme.as_str()) {
+        return None;
+    }
+    let rest = rest.strip_prefix("//").unwrap_or(rest);
+
+    if scheme == "nostr" {
+        return parse_nip19(rest.trim_end_matches('/'));
+    }
+
+    let (host, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, ""),
+    };
+    if host == "nip07" {
+        return None;
+    }
+    let route = crate::htree::resolve_htree_url_to_path(host, path);
+    let route = if route.starts_with('/') {
+        route
+    } else {
+        format!("/{}", route)
+    };
+    let first = route[1..].split('/').next().unwrap_or_default();
+    let valid = (first.starts_with("npub1") && first.len() >= 63)
+        || first.starts_with("nhash1")
+        || first.starts_with("npath1");
+    valid.then_some(DeepLink::Route(route))
+}
+
+/// NIP-21 `nostr:` identifiers
+fn parse_nip19(identifier: &str) -> Option<DeepLink> {
+    let route = |pubkey: &PublicKey| pubkey.to_bech32().ok().map(|npub| format!("/{}", npub));
+    match Nip19::from_bech32(identifier).ok()? {
+        Nip19::Pubkey(pubkey) => route(&pubkey).map(DeepLink::Route),
+        Nip19::Profile(profile) => route(&profile.public_key).map(DeepLink::Route),
+        Nip19::Coordinate(coordinate) => {
+            let profile = route(&coordinate.public_key)?;
+            if coordinate.kind == Kind::from(KIND_TREE_ROOT) && !coordinate.identifier.is_empty() {
+                Some(DeepLink::Route(format!(
+                    "{}/{}",
+                    profile, coordinate.identifier
+                )))
+            } else {
+                Some(DeepLink::Route(profile))
+            }
+        }
+        Nip19::EventId(id) => Some(DeepLink::Event { id, author: None }),
+        Nip19::Event(event) => Some(DeepLink::Event {
+            id: event.event_id,
+            author: event.author,
+        }),
+        _ => None,
+    }
+}
+
+/// App route for `link`, looking events up on the relays
+async fn resolve(state: &WorkerState, link: DeepLink) -> Option<String> {
+    let (id, author) = match link {
+        DeepLink::Route(route) => return Some(route),
+        DeepLink::Event { id, author } => (id, author),
+    };
+    let events = state
+        .nostr
+        .fetch_events(vec![Filter::new().id(id)])
+        .await
+        .unwrap_or_default();
+    let event = events.into_iter().find(|event| event.id == id);
+    let author = event.as_ref().map(|event| event.pubkey).or(author)?;
+    let npub = author.to_bech32().ok()?;
+    let tree = event
+        .filter(|event| event.kind == Kind::from(KIND_TREE_ROOT))
+        .and_then(|event| event.identifier().map(str::to_string))
+        .filter(|tree| !tree.is_empty());
+    Some(match tree {
+        Some(tree) => format!("/{}/{}", npub, tree),
+        None => format!("/{}", npub),
+    })
+}
+
+/// Handle links the app was launched with and listen for more
+pub fn init_deep_links(app: &AppHandle) {
+    // Installed bundles register the schemes; this covers dev builds and
+    // AppImages on Linux, and running from the build dir on Windows
+    #[cfg(any(windows, target_os = "linux"))]
+    if let Err(e) = app.deep_link().register_all() {
+        warn!("Failed to register deep link schemes: {}", e);
+    }
+
+    match app.deep_link().get_current() {
+        Ok(Some(urls)) => {
+            for url in urls {
+                open_url(app, url.to_string());
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to read launch deep link: {}", e),
+    }
+
+    let handle = app.clone();
+    app.deep_link().on_open_url(move |event| {
+        for url in event.urls() {
+            open_url(&handle, url.to_string());
+        }
+    });
+}
+
+/// Route `url` to the main window, or keep it until the frontend is ready
+pub fn open_url(app: &AppHandle, url: String) {
+    let Some(link) = parse_deep_link(&url) else {
+        warn!("Ignoring unsupported deep link: {}", url);
+        return;
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app.state::<Arc<WorkerState>>();
+        let Some(route) = resolve(&state, link).await else {
+            warn!("Deep link leads nowhere: {}", url);
+            return;
+        };
+        info!("Opening deep link {} at {}", url, route);
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+        let mut pending = PENDING_ROUTE.lock();
+        if FRONTEND_READY.load(Ordering::SeqCst) {
+            drop(pending);
+            let _ = app.emit("deep-link-open", serde_json::json!({ "path": route }));
+        } else {
+            *pending = Some(route);
+        }
+    });
+}
+
+/// Route of the link the app was opened with, if any. From here on links
+/// are sent to the frontend as `deep-link-open` events.
+#[tauri::command]
+pub fn take_pending_deep_link() -> Option<String> {
+    let mut pending = PENDING_ROUTE.lock();
+    FRONTEND_READY.store(true, Ordering::SeqCst);
+    pending.take()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::nips::nip01::Coordinate;
+    use nostr_sdk::nips::nip19::{Nip19Event, Nip19Profile};
+    use nostr_sdk::Keys;
+
+    fn route(url: &str) -> Option<String> {
+        match parse_deep_link(url)? {
+            DeepLink::Route(route) => Some(route),
+            DeepLink::Event { .. } => None,
+        }
+    }
+
+    #[test]
+    fn test_htree_links() {
+        let npub = Keys::generate().public_key().to_bech32().unwrap();
+        assert_eq!(
+            route(&format!("htree://{}.photos/2024/a.jpg", npub)),
+            Some(format!("/{}/photos/2024/a.jpg", npub))
+        );
+        assert_eq!(
+            route("htree://nhash1abc/readme.md"),
+            Some("/nhash1abc/readme.md".to_string())
+        );
+        assert_eq!(
+            route(&format!("web+htree:{}/photos", npub)),
+            Some(format!("/{}/photos", npub))
+        );
+        assert_eq!(
+            route("web+htree://nhash1abc"),
+            Some("/nhash1abc".to_string())
+        );
+        assert_eq!(route("htree://nip07/getPublicKey"), None);
+        assert_eq!(route("htree://example.com/x"), None);
+        assert_eq!(route("https://iris.to/"), None);
+    }
+
+    #[test]
+    fn test_nostr_links() {
+        let keys = Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        assert_eq!(
+            route(&format!("nostr:{}", npub)),
+            Some(format!("/{}", npub))
+        );
+
+        let nprofile = Nip19Profile::new(keys.public_key(), Vec::<String>::new())
+            .unwrap()
+            .to_bech32()
+            .unwrap();
+        assert_eq!(
+            route(&format!("nostr:{}", nprofile)),
+            Some(format!("/{}", npub))
+        );
+
+        let naddr = Coordinate::new(Kind::from(KIND_TREE_ROOT), keys.public_key())
+            .identifier("photos")
+            .to_bech32()
+            .unwrap();
+        assert_eq!(
+            route(&format!("nostr:{}", naddr)),
+            Some(format!("/{}/photos", npub))
+        );
+
+        let id = EventId::all_zeros();
+        let nevent = Nip19Event::new(id, Vec::<String>::new())
+            .author(keys.public_key())
+            .to_bech32()
+            .unwrap();
+        assert_eq!(
+            parse_deep_link(&format!("nostr:{}", nevent)),
+            Some(DeepLink::Event {
+                id,
+                author: Some(keys.public_key()),
+            })
+        );
+
+        let nsec = keys.secret_key().to_bech32().unwrap();
+        assert_eq!(parse_deep_link(&format!("nostr:{}", nsec)), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Filter proxies enforcing content blocking
+//!
+//! The script of [`crate::content_blocking`] runs in the page, which can
+//! undo it, so while blocking is on each child webview of external pages
+//! browses through a proxy of its own on loopback. It refuses connections to
+//! blocked domains when blocking is on for the site the webview shows, and
+//! passes the rest on, directly or through the web proxy if one is set.
+//! HTTPS and WebSocket connections arrive as CONNECT requests; plain HTTP
+//! ones are passed on one request per connection, so each is checked.
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, Runtime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use crate::proxy::Component;
+
+/// Longest request head taken from a webview
+const MAX_HEAD_BYTES: usize = 64 * 1024;
+
+/// Accept loops of the running proxies, by webview label
+static FILTERS: Lazy<Mutex<HashMap<String, JoinHandle<()>>>> = Lazy::new(Default::default);
+
+/// A request a webview made of the proxy
+#[derive(Debug, PartialEq)]
+struct Request {
+    /// Whether it's a CONNECT tunnel rather than a plain HTTP request
+    tunnel: bool,
+    host: String,
+    port: u16,
+    /// URL checked against the blocklist
+    url: tauri::Url,
+    /// Head passed on for a plain HTTP request
+    head: String,
+}
+
+impl Request {
+    /// Parse the head of a proxy request, up to its blank line
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut parts = lines.next()?.split(' ');
+        let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() || !version.starts_with("HTTP/1.") {
+            return None;
+        }
+
+        if method.eq_ignore_ascii_case("CONNECT") {
+            let url = tauri::Url::parse(&format!("https://{}/", target)).ok()?;
+            return Some(Self {
+                tunnel: true,
+                host: url.host_str()?.to_string(),
+                port: url.port_or_known_default()?,
+                url,
+                head: String::new(),
+            });
+        }
+
+        let url = tauri::Url::parse(target).ok()?;
+        if url.scheme() != "http" {
+            return None;
+        }
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        // Without keep-alive the next request comes on a connection of its
+        // own, to be checked in turn; upgrades keep theirs
+        let mut upgrade = false;
+        let mut forwarded = format!("{} {} HTTP/1.1\r\n", method, path);
+        for line in lines.filter(|line| !line.is_empty()) {
+            let name = line.split(':').next().unwrap_or("").trim();
+            if name.eq_ignore_ascii_case("connection") {
+                upgrade |= line.to_ascii_lowercase().contains("upgrade");
+                continue;
+            }
+            if name.eq_ignore_ascii_case("proxy-connection")
+                || name.eq_ignore_ascii_case("proxy-authorization")
+            {
+                continue;
+            }
+            forwarded.push_str(line);
+            forwarded.push_str("\r\n");
+        }
+        forwarded.push_str(if upgrade {
+            "Connection: upgrade\r\n\r\n"
+        } else {
+            "Connection: close\r\n\r\n"
+        });
+
+        Some(Self {
+            tunnel: false,
+            host: url.host_str()?.to_string(),
+            port: url.port_or_known_default()?,
+            url,
+            head: forwarded,
+        })
+    }
+}
+
+/// Start the filter proxy of webview `label`, replacing any it had,
+/// returning the URL to browse through
+pub async fn start<R: Runtime>(app: &AppHandle<R>, label: &str) -> Result<tauri::Url, String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .map_err(|e| format!("Failed to start filter proxy: {}", e))?;
+    let addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start filter proxy: {}", e))?;
+
+    let app = app.clone();
+    let webview = label.to_string();
+    let task = tauri::async_runtime::spawn(async move {
+        loop {
+            let client = match listener.accept().await {
+                Ok((client, _)) => client,
+                Err(e) => {
+                    warn!("Filter proxy of {} failed to accept: {}", webview, e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let app = app.clone();
+            let webview = webview.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve(&app, &webview, client).await {
+                    debug!("Filter proxy of {}: {}", webview, e);
+                }
+            });
+        }
+    });
+    if let Some(old) = FILTERS.lock().insert(label.to_string(), task) {
+        old.abort();
+    }
+    Ok(tauri::Url::parse(&format!("http://{}", addr)).expect("socket addresses make valid URLs"))
+}
+
+/// Stop the filter proxy of webview `label`, if it has one
+pub fn stop(label: &str) {
+    if let Some(task) = FILTERS.lock().remove(label) {
+        task.abort();
+    }
+}
+
+/// Read a request head, returning it and the bytes that came after it
+async fn read_head(client: &mut TcpStream) -> std::io::Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            let head = String::from_utf8(buf)
+                .map_err(|_| std::io::Error::other("request head isn't UTF-8"))?;
+            return Ok((head, rest));
+        }
+        if buf.len() > MAX_HEAD_BYTES {
+            return Err(std::io::Error::other("request head too long"));
+        }
+        let n = client.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+async fn respond(client: &mut TcpStream, status: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    client.write_all(response.as_bytes()).await
+}
+
+/// Connect to `host`, through the web proxy if one is set
+async fn connect(host: &str, port: u16) -> std::io::Result<TcpStream> {
+    match crate::proxy::socket_addr(Component::Web) {
+        Some(proxy) => tokio_socks::tcp::Socks5Stream::connect(proxy, (host, port))
+            .await
+            .map(tokio_socks::tcp::Socks5Stream::into_inner)
+            .map_err(std::io::Error::other),
+        None => TcpStream::connect((host, port)).await,
+    }
+}
+
+async fn serve<R: Runtime>(
+    app: &AppHandle<R>,
+    label: &str,
+    mut client: TcpStream,
+) -> std::io::Result<()> {
+    let (head, rest) = read_head(&mut client).await?;
+    let Some(request) = Request::parse(&head) else {
+        return respond(&mut client, "400 Bad Request").await;
+    };
+
+    let page = app
+        .get_webview(label)
+        .and_then(|webview| webview.url().ok());
+    if crate::content_blocking::blocks_request(app, page.as_ref(), &request.url) {
+        return respond(&mut client, "403 Forbidden").await;
+    }
+
+    let mut upstream = match connect(&request.host, request.port).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            debug!("Filter proxy failed to reach {}: {}", request.host, e);
+            return respond(&mut client, "502 Bad Gateway").await;
+        }
+    };
+    if request.tunnel {
+        client
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await?;
+    } else {
+        upstream.write_all(request.head.as_bytes()).await?;
+    }
+    upstream.write_all(&rest).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        let tunnel =
+            Request::parse("CONNECT ads.example:443 HTTP/1.1\r\nHost: ads.example:443").unwrap();
+        assert!(tunnel.tunnel);
+        assert_eq!((tunnel.host.as_str(), tunnel.port), ("ads.example", 443));
+        assert_eq!(tunnel.url.as_str(), "https://ads.example/");
+
+        let plain = Request::parse(
+            "GET http://news.example:8080/a?b=1 HTTP/1.1\r\n\
+             Host: news.example:8080\r\n\
+             Proxy-Connection: keep-alive\r\n\
+             Connection: keep-alive",
+        )
+        .unwrap();
+        assert!(!plain.tunnel);
+        assert_eq!((plain.host.as_str(), plain.port), ("news.example", 8080));
+        assert_eq!(
+            plain.head,
+            "GET /a?b=1 HTTP/1.1\r\nHost: news.example:8080\r\nConnection: close\r\n\r\n"
+        );
+
+        let upgrade = Request::parse(
+            "GET http://chat.example/ws HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket",
+        )
+        .unwrap();
+        assert!(upgrade
+            .head
+            .ends_with("Upgrade: websocket\r\nConnection: upgrade\r\n\r\n"));
+
+        assert!(Request::parse("GET /relative HTTP/1.1").is_none());
+        assert!(Request::parse("GET ftp://files.example/ HTTP/1.1").is_none());
+        assert!(Request::parse("nonsense").is_none());
+    }
+}
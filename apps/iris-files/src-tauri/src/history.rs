@@ -1,21 +1,38 @@
 //! History storage and search using heed (LMDB)
 //!
-//! Stores navigation history for fuzzy search suggestions.
+//! Stores navigation history for fuzzy search suggestions, ranked by match
+//! quality and frecency, and the most visited trees for a new-tab page.
 //! Uses heed for fast KV storage with LMDB backend.
 
 use heed::types::{Bytes, Str};
-use heed::{Database, Env, EnvOpenOptions};
+use heed::{CompactionOption, Database, Env, EnvOpenOptions};
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tracing::debug;
 
+use crate::bookmarks::{self, BookmarkStore};
+use crate::worker::WorkerState;
+
+/// Labels of webviews opened in private mode
+static PRIVATE_WEBVIEWS: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+
 /// Maximum number of history entries to store
 const MAX_HISTORY_ENTRIES: usize = 1000;
 
+/// Weight of log frecency against fuzzy match quality in search scores
+const FRECENCY_WEIGHT: f64 = 1.0;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Time between expiry runs while visits are recorded
+const EXPIRY_INTERVAL_MS: u64 = 60 * 60 * 1000;
+
 /// History entry stored in the database
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HistoryEntry {
     pub path: String,
     pub label: String,
@@ -27,18 +44,48 @@ pub struct HistoryEntry {
     pub first_visited: u64,
 }
 
+/// Visits to one tree (or profile or hash) summed over its paths, for
+/// a new-tab page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TopSite {
+    /// Route of the tree root, profile or hash
+    pub path: String,
+    pub label: String,
+    pub npub: Option<String>,
+    pub tree_name: Option<String>,
+    pub visit_count: u32,
+    pub last_visited: u64,
+    pub frecency: f64,
+}
+
 /// Search result returned to frontend
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HistorySearchResult {
     pub entry: HistoryEntry,
     pub score: f64,
+    /// Matched a bookmark, which ranks it above plain history
+    #[serde(default)]
+    pub bookmarked: bool,
+}
+
+/// How long history is kept, in `retention.json` of the history dir
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RetentionSettings {
+    /// Entries not visited for this many days expire; None keeps them
+    days: Option<u32>,
 }
 
 /// History store using heed/LMDB
 pub struct HistoryStore {
     env: Env,
     db: Database<Str, Bytes>,
+    /// When each deleted path was deleted (big-endian ms), so merging
+    /// another device's history doesn't bring it back
+    deleted: Database<Str, Bytes>,
     entry_count: RwLock<usize>,
+    retention_path: PathBuf,
+    retention: RwLock<RetentionSettings>,
+    last_expiry: AtomicU64,
 }
 
 impl HistoryStore {
@@ -52,7 +99,7 @@ impl HistoryStore {
         let env = unsafe {
             EnvOpenOptions::new()
                 .map_size(10 * 1024 * 1024) // 10MB should be plenty for history
-                .max_dbs(1)
+                .max_dbs(2)
                 .open(&history_dir)
                 .map_err(|e| format!("Failed to open history db: {}", e))?
         };
@@ -69,6 +116,9 @@ impl HistoryStore {
         let db = env
             .create_database(&mut wtxn, Some("history"))
             .map_err(|e| format!("Failed to create db: {}", e))?;
+        let deleted = env
+            .create_database(&mut wtxn, Some("deleted"))
+            .map_err(|e| format!("Failed to create db: {}", e))?;
         wtxn.commit()
             .map_err(|e| format!("Failed to commit: {}", e))?;
 
@@ -80,15 +130,41 @@ impl HistoryStore {
             db.len(&rtxn).unwrap_or(0) as usize
         };
 
-        Ok(Self {
+        let retention_path = history_dir.join("retention.json");
+        let retention = std::fs::read(&retention_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+
+        let store = Self {
             env,
             db,
+            deleted,
             entry_count: RwLock::new(count),
-        })
+            retention_path,
+            retention: RwLock::new(retention),
+            last_expiry: AtomicU64::new(0),
+        };
+        store.expire()?;
+        Ok(store)
+    }
+
+    /// Write a consistent, compacted copy of the database into `dir`, for
+    /// export
+    pub fn copy_to(&self, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        self.env
+            .copy_to_file(dir.join("data.mdb"), CompactionOption::Enabled)
+            .map_err(|e| format!("Failed to copy history db: {}", e))?;
+        Ok(())
     }
 
     /// Record a history visit (insert or update)
     pub fn record_visit(&self, entry: HistoryEntry) -> Result<(), String> {
+        if now_ms().saturating_sub(self.last_expiry.load(Ordering::Relaxed)) > EXPIRY_INTERVAL_MS {
+            self.expire()?;
+        }
+
         let mut wtxn = self
             .env
             .write_txn()
@@ -123,6 +199,9 @@ impl HistoryStore {
         self.db
             .put(&mut wtxn, &updated_entry.path, &bytes)
             .map_err(|e| format!("Failed to put: {}", e))?;
+        self.deleted
+            .delete(&mut wtxn, &updated_entry.path)
+            .map_err(|e| format!("Failed to delete: {}", e))?;
 
         wtxn.commit()
             .map_err(|e| format!("Failed to commit: {}", e))?;
@@ -180,6 +259,7 @@ impl HistoryStore {
             .map_err(|e| format!("Failed to start read txn: {}", e))?;
 
         let query_lower = query.to_lowercase();
+        let now = now_ms();
         let mut results: Vec<HistorySearchResult> = Vec::new();
 
         let iter = self
@@ -190,10 +270,15 @@ impl HistoryStore {
         for item in iter {
             let (_key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
             if let Ok(entry) = bincode::deserialize::<HistoryEntry>(value) {
-                // Calculate fuzzy score
+                // Match quality, lifted by how often and how lately it's visited
                 let score = fuzzy_score(&query_lower, &entry);
                 if score > 0.0 {
-                    results.push(HistorySearchResult { entry, score });
+                    let score = score + FRECENCY_WEIGHT * frecency(&entry, now).ln_1p();
+                    results.push(HistorySearchResult {
+                        entry,
+                        score,
+                        bookmarked: false,
+                    });
                 }
             }
         }
@@ -237,6 +322,273 @@ impl HistoryStore {
 
         Ok(entries)
     }
+
+    /// Delete the entry for `path`, false if there was none
+    pub fn delete_entry(&self, path: &str) -> Result<bool, String> {
+        Ok(self.delete_where(true, |entry| entry.path == path)? > 0)
+    }
+
+    /// Delete entries last visited between `from` and `to` (Unix ms,
+    /// inclusive). Returns how many were deleted.
+    pub fn delete_range(&self, from: u64, to: u64) -> Result<usize, String> {
+        self.delete_where(true, |entry| {
+            entry.last_visited >= from && entry.last_visited <= to
+        })
+    }
+
+    /// Delete all entries, returning how many there were
+    pub fn clear(&self) -> Result<usize, String> {
+        self.delete_where(true, |_| true)
+    }
+
+    /// Days entries are kept since their last visit, None for no limit
+    pub fn retention_days(&self) -> Option<u32> {
+        self.retention.read().days
+    }
+
+    /// Set and save the retention window, expiring entries outside it now.
+    /// Returns how many expired.
+    pub fn set_retention_days(&self, days: Option<u32>) -> Result<usize, String> {
+        let settings = RetentionSettings { days };
+        let data = serde_json::to_vec_pretty(&settings)
+            .map_err(|e| format!("Failed to encode history retention: {}", e))?;
+        std::fs::write(&self.retention_path, data)
+            .map_err(|e| format!("Failed to save history retention: {}", e))?;
+        *self.retention.write() = settings;
+        self.expire()
+    }
+
+    /// Delete entries outside the retention window. Returns how many.
+    pub fn expire(&self) -> Result<usize, String> {
+        self.last_expiry.store(now_ms(), Ordering::Relaxed);
+        let cutoff = self.retention_cutoff();
+        if cutoff == 0 {
+            return Ok(0);
+        }
+        // Expired entries from other devices are skipped on merge, so no
+        // tombstones are needed
+        let expired = self.delete_where(false, |entry| entry.last_visited < cutoff)?;
+        if expired > 0 {
+            debug!("Expired {} history entries", expired);
+        }
+        Ok(expired)
+    }
+
+    /// Oldest last visit still within the retention window, 0 for no limit
+    fn retention_cutoff(&self) -> u64 {
+        match self.retention_days() {
+            Some(days) => now_ms().saturating_sub(days as u64 * DAY_MS),
+            None => 0,
+        }
+    }
+
+    /// Delete entries matching `pred`, leaving tombstones if `tombstone`
+    fn delete_where(
+        &self,
+        tombstone: bool,
+        pred: impl Fn(&HistoryEntry) -> bool,
+    ) -> Result<usize, String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+
+        let mut doomed: Vec<String> = Vec::new();
+        for item in self
+            .db
+            .iter(&wtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?
+        {
+            let (key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
+            if let Ok(entry) = bincode::deserialize::<HistoryEntry>(value) {
+                if pred(&entry) {
+                    doomed.push(key.to_string());
+                }
+            }
+        }
+        if doomed.is_empty() {
+            return Ok(0);
+        }
+
+        let now = now_ms().to_be_bytes();
+        for path in &doomed {
+            self.db
+                .delete(&mut wtxn, path)
+                .map_err(|e| format!("Failed to delete: {}", e))?;
+            if tombstone {
+                self.deleted
+                    .put(&mut wtxn, path, &now[..])
+                    .map_err(|e| format!("Failed to put: {}", e))?;
+            }
+        }
+        let count = self
+            .db
+            .len(&wtxn)
+            .map_err(|e| format!("Failed to count: {}", e))? as usize;
+
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+        *self.entry_count.write() = count;
+        Ok(doomed.len())
+    }
+
+    /// Most frecent trees, profiles and hashes, with the visits to all
+    /// their paths counted together
+    pub fn get_top_sites(&self, limit: usize) -> Result<Vec<TopSite>, String> {
+        Ok(top_sites(self.entries()?, now_ms(), limit))
+    }
+
+    /// All entries, in no particular order
+    pub fn entries(&self) -> Result<Vec<HistoryEntry>, String> {
+        let rtxn = self
+            .env
+            .read_txn()
+            .map_err(|e| format!("Failed to start read txn: {}", e))?;
+        let iter = self
+            .db
+            .iter(&rtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?;
+
+        let mut entries = Vec::new();
+        for item in iter {
+            let (_key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
+            if let Ok(entry) = bincode::deserialize::<HistoryEntry>(value) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Merge entries from another device into this history, keeping the
+    /// most recent entries if that takes it over capacity. Entries deleted
+    /// here since their last visit, or outside the retention window, are
+    /// skipped. Returns how many entries were added or changed.
+    pub fn merge(&self, remote: Vec<HistoryEntry>) -> Result<usize, String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+
+        let cutoff = self.retention_cutoff();
+        let mut changed = 0;
+        for entry in remote {
+            if entry.last_visited < cutoff {
+                continue;
+            }
+            let deleted_at = self
+                .deleted
+                .get(&wtxn, &entry.path)
+                .map_err(|e| format!("Failed to get: {}", e))?
+                .and_then(|bytes| bytes.try_into().ok())
+                .map(u64::from_be_bytes);
+            if deleted_at.is_some_and(|deleted_at| entry.last_visited <= deleted_at) {
+                continue;
+            }
+            let local: Option<HistoryEntry> = self
+                .db
+                .get(&wtxn, &entry.path)
+                .map_err(|e| format!("Failed to get: {}", e))?
+                .and_then(|bytes| bincode::deserialize(bytes).ok());
+            let Some(merged) = merge_entry(local.as_ref(), entry) else {
+                continue;
+            };
+            let bytes =
+                bincode::serialize(&merged).map_err(|e| format!("Failed to serialize: {}", e))?;
+            self.db
+                .put(&mut wtxn, &merged.path, &bytes)
+                .map_err(|e| format!("Failed to put: {}", e))?;
+            changed += 1;
+        }
+
+        // Drop the oldest entries over capacity
+        let mut visits: Vec<(String, u64)> = Vec::new();
+        for item in self
+            .db
+            .iter(&wtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?
+        {
+            let (key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
+            if let Ok(entry) = bincode::deserialize::<HistoryEntry>(value) {
+                visits.push((key.to_string(), entry.last_visited));
+            }
+        }
+        visits.sort_by_key(|(_, ts)| std::cmp::Reverse(*ts));
+        for (path, _) in visits.iter().skip(MAX_HISTORY_ENTRIES) {
+            self.db
+                .delete(&mut wtxn, path)
+                .map_err(|e| format!("Failed to delete: {}", e))?;
+        }
+        let count = self
+            .db
+            .len(&wtxn)
+            .map_err(|e| format!("Failed to count: {}", e))? as usize;
+
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+        *self.entry_count.write() = count;
+
+        debug!("Merged {} history entries", changed);
+        Ok(changed)
+    }
+}
+
+/// `remote` merged into `local`: the later visit's label and metadata, the
+/// higher visit count and the earlier first visit. None if that's `local`.
+fn merge_entry(local: Option<&HistoryEntry>, remote: HistoryEntry) -> Option<HistoryEntry> {
+    let Some(local) = local else {
+        return Some(remote);
+    };
+    let mut merged = if remote.last_visited > local.last_visited {
+        remote.clone()
+    } else {
+        local.clone()
+    };
+    merged.visit_count = local.visit_count.max(remote.visit_count);
+    merged.first_visited = local.first_visited.min(remote.first_visited);
+    (merged != *local).then_some(merged)
+}
+
+/// Group entries by tree, or by profile or path outside trees, most
+/// frecent first
+fn top_sites(entries: Vec<HistoryEntry>, now: u64, limit: usize) -> Vec<TopSite> {
+    let mut sites: std::collections::HashMap<String, TopSite> = std::collections::HashMap::new();
+    for entry in entries {
+        let path = match (&entry.npub, &entry.tree_name) {
+            (Some(npub), Some(tree_name)) => format!("/{}/{}", npub, tree_name),
+            (Some(npub), None) => format!("/{}", npub),
+            _ => entry.path.clone(),
+        };
+        let frecency = frecency(&entry, now);
+        let site = sites.entry(path.clone()).or_insert_with(|| TopSite {
+            label: entry
+                .tree_name
+                .clone()
+                .unwrap_or_else(|| entry.label.clone()),
+            path,
+            npub: entry.npub.clone(),
+            tree_name: entry.tree_name.clone(),
+            visit_count: 0,
+            last_visited: 0,
+            frecency: 0.0,
+        });
+        // The root's own label beats a name derived from a subpath
+        if entry.path == site.path {
+            site.label = entry.label.clone();
+        }
+        site.visit_count += entry.visit_count;
+        site.last_visited = site.last_visited.max(entry.last_visited);
+        site.frecency += frecency;
+    }
+
+    let mut sites: Vec<TopSite> = sites.into_values().collect();
+    sites.sort_by(|a, b| {
+        b.frecency
+            .partial_cmp(&a.frecency)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b.last_visited.cmp(&a.last_visited))
+    });
+    sites.truncate(limit);
+    sites
 }
 
 /// Calculate fuzzy match score for a history entry
@@ -258,15 +610,34 @@ fn fuzzy_score(query: &str, entry: &HistoryEntry) -> f64 {
         max_score = max_score.max(fuzzy_match_string(query, &tree_lower) * 0.7);
     }
 
-    // Boost by visit frequency (log scale)
-    let freq_boost = (entry.visit_count as f64).ln_1p() * 0.1;
+    max_score
+}
+
+/// Firefox-style frecency: visit count weighted by the age of the last
+/// visit, in buckets of 4, 14, 31 and 90 days
+pub fn frecency(entry: &HistoryEntry, now: u64) -> f64 {
+    let age_days = now.saturating_sub(entry.last_visited) / DAY_MS;
+    let weight = match age_days {
+        0..=3 => 100.0,
+        4..=14 => 70.0,
+        15..=31 => 50.0,
+        32..=90 => 30.0,
+        _ => 10.0,
+    };
+    weight * entry.visit_count.max(1) as f64
+}
 
-    max_score + freq_boost
+/// Current time as a Unix timestamp in ms
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
 
 /// Fuzzy match a query against a target string
 /// Uses subsequence matching with bonuses for consecutive/word-boundary matches
-fn fuzzy_match_string(query: &str, target: &str) -> f64 {
+pub(crate) fn fuzzy_match_string(query: &str, target: &str) -> f64 {
     if query.is_empty() || target.is_empty() {
         return 0.0;
     }
@@ -335,20 +706,48 @@ fn fuzzy_match_string(query: &str, target: &str) -> f64 {
 // Tauri Commands
 // ============================================================================
 
-/// Record a history visit
+/// Mark the webview `label` as private or not. Labels are reused, so this is
+/// set each time a webview is created.
+pub fn set_private_webview(label: &str, private: bool) {
+    let mut labels = PRIVATE_WEBVIEWS.write();
+    if private {
+        labels.insert(label.to_string());
+    } else {
+        labels.remove(label);
+    }
+}
+
+/// Whether `label` was opened in private mode
+pub fn is_private_webview(label: &str) -> bool {
+    PRIVATE_WEBVIEWS.read().contains(label)
+}
+
+/// The guest session's own history while one is on, the persistent one otherwise
+fn active_store(history: &Arc<HistoryStore>, worker: &WorkerState) -> Arc<HistoryStore> {
+    worker.guest_history().unwrap_or_else(|| history.clone())
+}
+
+/// Record a history visit. Private navigations, and visits in a webview
+/// opened in private mode, are dropped without touching the store.
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub fn record_history_visit(
     path: String,
     label: String,
     entry_type: String,
     npub: Option<String>,
     tree_name: Option<String>,
+    webview_label: Option<String>,
+    private: Option<bool>,
     history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
 ) -> Result<(), String> {
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_millis() as u64)
-        .unwrap_or(0);
+    if private.unwrap_or(false) || webview_label.as_deref().is_some_and(is_private_webview) {
+        debug!("Not recording private visit");
+        return Ok(());
+    }
+
+    let now = now_ms();
 
     let entry = HistoryEntry {
         path,
@@ -361,17 +760,21 @@ pub fn record_history_visit(
         first_visited: now,
     };
 
-    history.record_visit(entry)
+    active_store(&history, &worker).record_visit(entry)
 }
 
-/// Search history with fuzzy matching
+/// Search history with fuzzy matching, matching bookmarks first
 #[tauri::command]
 pub fn search_history(
     query: String,
     limit: usize,
     history: tauri::State<'_, Arc<HistoryStore>>,
+    bookmark_store: tauri::State<'_, Arc<BookmarkStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
 ) -> Result<Vec<HistorySearchResult>, String> {
-    history.search(&query, limit)
+    let results = active_store(&history, &worker).search(&query, limit)?;
+    let bookmarked = bookmarks::active_store(&bookmark_store, &worker).search(&query, limit)?;
+    Ok(bookmarks::rank_bookmarked(results, bookmarked, limit))
 }
 
 /// Get recent history entries
@@ -379,8 +782,69 @@ pub fn search_history(
 pub fn get_recent_history(
     limit: usize,
     history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
 ) -> Result<Vec<HistoryEntry>, String> {
-    history.get_recent(limit)
+    active_store(&history, &worker).get_recent(limit)
+}
+
+/// Delete one history entry
+#[tauri::command]
+pub fn delete_history_entry(
+    path: String,
+    history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<bool, String> {
+    active_store(&history, &worker).delete_entry(&path)
+}
+
+/// Delete history entries last visited between two Unix timestamps (ms)
+#[tauri::command]
+pub fn delete_history_range(
+    from_ts: u64,
+    to_ts: u64,
+    history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<usize, String> {
+    active_store(&history, &worker).delete_range(from_ts, to_ts)
+}
+
+/// Delete all history
+#[tauri::command]
+pub fn clear_history(
+    history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<usize, String> {
+    active_store(&history, &worker).clear()
+}
+
+/// Days history is kept, None for no limit
+#[tauri::command]
+pub fn get_history_retention(
+    history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Option<u32> {
+    active_store(&history, &worker).retention_days()
+}
+
+/// Keep history for `days` after the last visit (None for no limit),
+/// returning how many entries expired right away
+#[tauri::command]
+pub fn set_history_retention(
+    days: Option<u32>,
+    history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<usize, String> {
+    active_store(&history, &worker).set_retention_days(days)
+}
+
+/// Most visited trees, profiles and hashes, for a new-tab page
+#[tauri::command]
+pub fn get_top_sites(
+    limit: usize,
+    history: tauri::State<'_, Arc<HistoryStore>>,
+    worker: tauri::State<'_, Arc<WorkerState>>,
+) -> Result<Vec<TopSite>, String> {
+    active_store(&history, &worker).get_top_sites(limit)
 }
 
 #[cfg(test)]
@@ -460,4 +924,166 @@ mod tests {
         let recent = store.get_recent(10).unwrap();
         assert_eq!(recent[0].visit_count, 3);
     }
+
+    #[test]
+    fn test_history_merge() {
+        let dir = tempdir().unwrap();
+        let store = HistoryStore::new(dir.path()).unwrap();
+        let entry = |path: &str, label: &str, visits: u32, first: u64, last: u64| HistoryEntry {
+            path: path.to_string(),
+            label: label.to_string(),
+            entry_type: "tree".to_string(),
+            npub: None,
+            tree_name: None,
+            visit_count: visits,
+            last_visited: last,
+            first_visited: first,
+        };
+        store
+            .record_visit(entry("/a", "Old label", 1, 100, 100))
+            .unwrap();
+        store
+            .record_visit(entry("/b", "Local", 5, 50, 900))
+            .unwrap();
+
+        let changed = store
+            .merge(vec![
+                entry("/a", "New label", 3, 200, 500),
+                entry("/b", "Remote", 2, 10, 800),
+                entry("/c", "Only remote", 1, 300, 300),
+            ])
+            .unwrap();
+        assert_eq!(changed, 3);
+
+        let recent = store.get_recent(10).unwrap();
+        assert_eq!(
+            recent,
+            vec![
+                entry("/b", "Local", 5, 10, 900),
+                entry("/a", "New label", 3, 100, 500),
+                entry("/c", "Only remote", 1, 300, 300),
+            ]
+        );
+        // Merging the same entries again changes nothing
+        assert_eq!(store.merge(recent).unwrap(), 0);
+    }
+
+    fn visited(path: &str, tree: Option<(&str, &str)>, visits: u32, days_ago: u64) -> HistoryEntry {
+        let now = now_ms();
+        HistoryEntry {
+            path: path.to_string(),
+            label: path.to_string(),
+            entry_type: "tree".to_string(),
+            npub: tree.map(|(npub, _)| npub.to_string()),
+            tree_name: tree.map(|(_, name)| name.to_string()),
+            visit_count: visits,
+            last_visited: now - days_ago * DAY_MS,
+            first_visited: now - 365 * DAY_MS,
+        }
+    }
+
+    #[test]
+    fn test_frecency_outranks_stale_exact_match() {
+        let now = now_ms();
+        let recent = visited("/photos-2024", None, 20, 1);
+        let mut stale = visited("/photos", None, 1, 200);
+        stale.label = "photos".to_string();
+        assert!(frecency(&recent, now) > frecency(&stale, now));
+        // Same visits, more recent wins
+        assert!(
+            frecency(&visited("/a", None, 3, 2), now) > frecency(&visited("/a", None, 3, 20), now)
+        );
+
+        let dir = tempdir().unwrap();
+        let store = HistoryStore::new(dir.path()).unwrap();
+        store.record_visit(stale).unwrap();
+        store.record_visit(recent).unwrap();
+        let results = store.search("photos", 10).unwrap();
+        assert_eq!(results[0].entry.path, "/photos-2024");
+    }
+
+    #[test]
+    fn test_top_sites_aggregate_per_tree() {
+        let photos = Some(("npub1a", "photos"));
+        let mut profile = visited("/npub1a", None, 1, 0);
+        profile.npub = Some("npub1a".to_string());
+        let entries = vec![
+            visited("/npub1a/photos", photos, 2, 1),
+            visited("/npub1a/photos/2024/a.jpg", photos, 3, 0),
+            profile,
+            visited("/nhash1abc", None, 1, 100),
+            visited("/npub1b/docs/readme.md", Some(("npub1b", "docs")), 4, 40),
+        ];
+
+        let sites = top_sites(entries, now_ms(), 10);
+        let paths: Vec<_> = sites.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            ["/npub1a/photos", "/npub1b/docs", "/npub1a", "/nhash1abc"]
+        );
+        assert_eq!(sites[0].visit_count, 5);
+        // The root's label is used once it was visited
+        assert_eq!(sites[0].label, "/npub1a/photos");
+        assert_eq!(sites[1].label, "docs");
+        assert_eq!(top_sites(Vec::new(), now_ms(), 10).len(), 0);
+    }
+
+    #[test]
+    fn test_delete_and_retention() {
+        let dir = tempdir().unwrap();
+        let store = HistoryStore::new(dir.path()).unwrap();
+        for entry in [
+            visited("/today", None, 1, 0),
+            visited("/last-week", None, 1, 7),
+            visited("/last-year", None, 1, 365),
+            visited("/gone", None, 1, 0),
+        ] {
+            store.record_visit(entry).unwrap();
+        }
+
+        let remote = visited("/gone", None, 5, 0);
+        assert!(store.delete_entry("/gone").unwrap());
+        assert!(!store.delete_entry("/gone").unwrap());
+        // A deleted entry isn't merged back from another device...
+        assert_eq!(store.merge(vec![remote]).unwrap(), 0);
+
+        let now = now_ms();
+        assert_eq!(
+            store
+                .delete_range(now - 8 * DAY_MS, now - 6 * DAY_MS)
+                .unwrap(),
+            1
+        );
+
+        // Nothing expires without a retention window
+        assert_eq!(store.expire().unwrap(), 0);
+        assert_eq!(store.set_retention_days(Some(30)).unwrap(), 1);
+        assert_eq!(store.retention_days(), Some(30));
+        // ...and neither is an expired one
+        assert_eq!(store.merge(vec![visited("/old", None, 1, 60)]).unwrap(), 0);
+        let paths: Vec<_> = store
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(paths, ["/today"]);
+
+        // Visiting again undoes the deletion
+        let mut revisit = visited("/gone", None, 1, 0);
+        revisit.last_visited = now_ms() + 1;
+        store.record_visit(revisit).unwrap();
+        assert_eq!(store.clear().unwrap(), 2);
+        assert!(store.get_recent(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_private_webviews() {
+        set_private_webview("test-private", true);
+        assert!(is_private_webview("test-private"));
+        assert!(!is_private_webview("test-other"));
+        // A label reused for a normal webview records again
+        set_private_webview("test-private", false);
+        assert!(!is_private_webview("test-private"));
+    }
 }
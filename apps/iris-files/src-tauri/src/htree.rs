@@ -5,41 +5,101 @@
 //!
 //! Routes:
 //! - /htree/{npub}/{treeName}/{path} - Npub-based file access (mutable)
-//! - /htree/{nhash}/{filename} - Direct nhash access (content-addressed)
+//! - /htree/{nhash}/{filename} - Direct nhash access (content-addressed); the
+//!   nhash's size, MIME type and filename hints, if any, give the headers
+//!   without fetching the root block first
+//! - /htree/{naddr|nevent}/{path} - tree of a NIP-19 address or root event, as
+//!   copied from Nostr clients; their relay hints are used to resolve it
+//! - /search?q=...&npub=... - Search the local tree content index
+//! - POST /extract - reader-mode article of HTML, or of an HTML file by htree
+//!   path (see `reader`)
+//! - /htree/...?format=hls - HLS playlist for a video (requires ffmpeg)
+//! - /hls/{hash}.ts - HLS segments produced by the transcoder
+//! - /htree/...?resize=800&format=webp&rotate=90 - image transforms; ?clip=30-60 for audio
+//! - /htree/{dir}?format=tracks - video, thumbnail, subtitle and audio track manifest
+//! - /htree/...?format=vtt[&track=N] - sidecar .srt or embedded subtitle as WebVTT
+//! - /htree/{npub}/{treeName}/...?k={secret} - link-visible tree opened via its share URL
+//! - /htree/...?proof=1 - JSON Merkle proof of the entry against its tree root
+//! - /htree/....md?render=html - markdown rendered to sanitized HTML (see `markdown`)
+//! - /profile/{npub}/picture - profile picture, kept in the blob store (see `profile_picture`)
+//!
+//! An npub root served from the root cache is re-resolved in the background
+//! once it's over `REVALIDATE_AFTER` old; if the tree has a newer root, it
+//! replaces the cached one and `htree-root-updated` ([`RootUpdated`]) is
+//! emitted, so the UI can offer a reload.
+//!
+//! Files of npub paths are served with `Last-Modified` set to the
+//! `created_at` of the tree's root event and `Cache-Control: no-cache`, so
+//! webviews revalidate them with `If-Modified-Since` and get 304 until the
+//! tree is republished. Roots cached by the frontend have no event time and
+//! go without.
+//!
+//! Npub responses carry `X-Htree-Signature: verified|unsigned` for the tree's
+//! signed manifest; a root whose manifest doesn't check out is refused (502).
+//! Trees of owners beyond the WoT policy's flag distance get
+//! `X-Htree-Wot: untrusted`, for the page to blur them. Trees of pubkeys on
+//! our mute list are refused (403). HTML and SVG from trees of owners beyond
+//! the policy's sandbox distance are served with [`SANDBOX_CSP`], so a page
+//! dropped in a shared folder can't reach other servers, navigate the app
+//! or use the server's origin.
+//!
+//! Each request runs in an `htree{id=..}` tracing span, echoed back as
+//! `X-Request-Id`, so its resolve, fetch and serve logs can be told apart.
+//!
+//! /htree, /hls, /profile, /nip07 and /relay are rate limited per `Origin` (see
+//! `rate_limit`); over-budget requests get 429 with `Retry-After`. Clients
+//! other than loopback, possible once LAN access is on, go through `acl`.
 
 use axum::{
     body::Body,
-    extract::{OriginalUri, State},
-    http::{header, HeaderMap, StatusCode},
+    extract::{DefaultBodyLimit, OriginalUri, Query, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::{any, get, post},
     Json, Router,
 };
-use hashtree_blossom::{BlossomClient, BlossomStore};
+use hashtree_blossom::BlossomClient;
 use hashtree_core::{
-    from_hex, is_tree_node, nhash_decode, to_hex, Cid, HashTree, HashTreeConfig, Store, StoreError,
+    from_hex, is_tree_node, nhash_decode, to_hex, Cid, HashTree, HashTreeConfig, LinkType, Store,
 };
 use hashtree_fs::FsBlobStore;
+use hashtree_gateway::manifest::{check_root, SignatureStatus};
+use hashtree_gateway::{
+    content_disposition, guess_mime_type, is_mime_type, is_npub, next_request_id,
+    normalize_path, parse_range_header, query_param, url_decode, CombinedStore,
+};
 use hashtree_resolver::{
     nostr::{NostrResolverConfig, NostrRootResolver},
-    RootResolver,
+    ResolverError, RootResolver,
 };
 use lru::LruCache;
-use nostr_sdk::Keys;
+use nostr_sdk::nips::nip19::{FromBech32, Nip19, ToBech32};
+use nostr_sdk::{Keys, Kind, RelayOptions};
 use parking_lot::RwLock;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
-use tauri::{AppHandle, Emitter};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager};
 use thiserror::Error;
 use tokio::net::TcpListener;
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{debug, error, info, warn};
-
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
+
+use crate::acl::acl_middleware;
+use crate::deep_link::KIND_TREE_ROOT;
+use crate::markdown::{self, is_markdown, LinkBase, MAX_MARKDOWN_BYTES, RENDERED_CSP};
+use crate::profile_picture::{picture_url, PictureCache, AVATAR_FILES, PROFILE_TREE};
+use crate::proxy::{self, Component};
+use crate::rate_limit::{self, rate_limit_middleware, LimitClass};
 use crate::relay_proxy::{handle_relay_websocket, RelayProxyState};
+use crate::tracks::{apply_probe, build_manifest, is_video_file, srt_to_vtt, SUBTITLE_DIRS};
+use crate::transcode::{detect_ffmpeg, MediaTransform, Transcoder};
+use crate::worker::{WorkerCid, WorkerState};
 
 /// Default Blossom servers for fetching blobs (matches web app defaults)
 const DEFAULT_BLOSSOM_SERVERS: &[&str] = &[
@@ -56,12 +116,20 @@ const DEFAULT_NOSTR_RELAYS: &[&str] = &[
     "wss://temp.iris.to",
 ];
 
-/// npub pattern: npub1 followed by 58 bech32 characters
-fn is_npub(s: &str) -> bool {
-    s.len() == 63
-        && s.starts_with("npub1")
-        && s.chars().skip(5).all(|c| c.is_ascii_alphanumeric())
-}
+/// Response header reporting a tree's manifest signature status
+const SIGNATURE_HEADER: &str = "x-htree-signature";
+
+/// Response header flagging trees of owners the WoT policy doesn't trust
+const WOT_HEADER: &str = "x-htree-wot";
+
+/// Response header carrying the id of the request's tracing span
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Content Security Policy of documents from untrusted trees: an opaque
+/// origin, no top navigation, and no network beyond the serving origin
+pub const SANDBOX_CSP: &str = "sandbox allow-scripts allow-forms allow-popups allow-modals; \
+    default-src 'self' 'unsafe-inline' 'unsafe-eval' data: blob:; connect-src 'self'; \
+    form-action 'self'; base-uri 'self'; frame-ancestors 'self'";
 
 #[derive(Error, Debug)]
 pub enum HtreeError {
@@ -77,6 +145,10 @@ pub enum HtreeError {
     Store(String),
     #[error("IO error: {0}")]
     Io(String),
+    #[error("Signature mismatch: {0}")]
+    BadSignature(String),
+    #[error("Muted: {0}")]
+    Muted(String),
 }
 
 impl IntoResponse for HtreeError {
@@ -90,64 +162,32 @@ impl IntoResponse for HtreeError {
                 warn!("htree bad request: {}", self);
                 (StatusCode::BAD_REQUEST, self.to_string())
             }
+            HtreeError::BadSignature(_) => {
+                warn!("htree refused: {}", self);
+                (StatusCode::BAD_GATEWAY, self.to_string())
+            }
+            HtreeError::Muted(_) => {
+                debug!("htree refused: {}", self);
+                (StatusCode::FORBIDDEN, self.to_string())
+            }
             _ => {
                 error!("htree error: {}", self);
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
             }
         };
 
-        Response::builder()
+        let mut response = Response::builder()
             .status(status)
             .header(header::CONTENT_TYPE, "text/plain")
             .body(Body::from(message))
-            .unwrap()
-    }
-}
-
-/// Guess MIME type from file path/extension
-fn guess_mime_type(path: &str) -> &'static str {
-    let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
-    match ext.as_str() {
-        // Video
-        "mp4" | "m4v" => "video/mp4",
-        "webm" => "video/webm",
-        "ogg" | "ogv" => "video/ogg",
-        "mov" => "video/quicktime",
-        "avi" => "video/x-msvideo",
-        "mkv" => "video/x-matroska",
-        // Audio
-        "mp3" => "audio/mpeg",
-        "wav" => "audio/wav",
-        "flac" => "audio/flac",
-        "m4a" | "aac" => "audio/mp4",
-        "oga" => "audio/ogg",
-        // Images
-        "jpg" | "jpeg" => "image/jpeg",
-        "png" => "image/png",
-        "gif" => "image/gif",
-        "webp" => "image/webp",
-        "svg" => "image/svg+xml",
-        "ico" => "image/x-icon",
-        // Documents
-        "pdf" => "application/pdf",
-        "txt" => "text/plain",
-        "md" => "text/markdown",
-        "html" | "htm" => "text/html",
-        "css" => "text/css",
-        "js" => "application/javascript",
-        "json" => "application/json",
-        "xml" => "application/xml",
-        // Archives
-        "zip" => "application/zip",
-        "tar" => "application/x-tar",
-        "gz" => "application/gzip",
-        // Code
-        "ts" | "tsx" => "text/typescript",
-        "jsx" => "text/javascript",
-        "py" => "text/x-python",
-        "rs" => "text/x-rust",
-        "go" => "text/x-go",
-        _ => "application/octet-stream",
+            .unwrap();
+        if let HtreeError::BadSignature(_) = self {
+            response.headers_mut().insert(
+                SIGNATURE_HEADER,
+                HeaderValue::from_static(SignatureStatus::Invalid.as_str()),
+            );
+        }
+        response
     }
 }
 
@@ -174,8 +214,10 @@ fn is_thumbnail_request(path: &str) -> bool {
 }
 
 /// Tree visibility types (matches TypeScript TreeVisibility)
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum TreeVisibility {
+    #[default]
     Public,
     LinkVisible,
     Private,
@@ -196,130 +238,142 @@ impl TreeVisibility {
 struct CachedRoot {
     cid: Cid,
     visibility: TreeVisibility,
-    #[allow(dead_code)]
+    /// When the root was last resolved or checked
     timestamp: std::time::Instant,
+    /// `created_at` of the root event, for roots resolved from Nostr
+    created_at: Option<u64>,
 }
 
-/// Combined store that checks local filesystem first, then Blossom
-/// This allows serving data that's stored locally but not yet synced to Blossom
-pub struct CombinedStore {
-    local: Arc<FsBlobStore>,
-    blossom: Arc<BlossomStore>,
-}
-
-impl CombinedStore {
-    pub fn new(local: Arc<FsBlobStore>, blossom: Arc<BlossomStore>) -> Self {
-        Self { local, blossom }
-    }
-}
+/// Cached roots served after this long are re-resolved in the background
+const REVALIDATE_AFTER: Duration = Duration::from_secs(30);
 
-#[async_trait::async_trait]
-impl Store for CombinedStore {
-    async fn get(&self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError> {
-        // Try local store first (FsBlobStore implements Store directly)
-        if let Ok(Some(data)) = self.local.get(hash).await {
-            debug!("Found blob {} in local store ({} bytes)", &to_hex(hash)[..8], data.len());
-            return Ok(Some(data));
-        }
-
-        // Fall back to Blossom
-        match self.blossom.get(hash).await {
-            Ok(Some(data)) => {
-                debug!("Found blob {} in Blossom ({} bytes)", &to_hex(hash)[..8], data.len());
-                // Cache locally for future requests
-                match self.local.put(*hash, data.clone()).await {
-                    Ok(_) => debug!("Cached blob {} locally", &to_hex(hash)[..8]),
-                    Err(e) => warn!("Failed to cache blob locally: {}", e),
-                }
-                Ok(Some(data))
-            }
-            Ok(None) => {
-                debug!("Blob {} not found in local or Blossom", &to_hex(hash)[..8]);
-                Ok(None)
-            }
-            Err(e) => {
-                warn!("Blossom fetch error for {}: {}", &to_hex(hash)[..8], e);
-                Err(StoreError::Other(e.to_string()))
-            }
-        }
-    }
+/// Event emitted when a cached root turns out to be outdated
+pub const ROOT_UPDATED_EVENT: &str = "htree-root-updated";
 
-    async fn put(&self, hash: [u8; 32], data: Vec<u8>) -> Result<bool, StoreError> {
-        // FsBlobStore implements Store directly
-        self.local.put(hash, data).await
-    }
+/// Payload of `htree-root-updated`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootUpdated {
+    pub npub: String,
+    pub tree_name: String,
+    pub hash: String,
+    pub previous_hash: String,
+}
 
-    async fn has(&self, hash: &[u8; 32]) -> Result<bool, StoreError> {
-        // Check local first
-        if self.local.has(hash).await? {
-            return Ok(true);
-        }
+/// Local blob store under `dir`, falling back to Blossom
+fn open_store(dir: &Path) -> Arc<CombinedStore> {
+    // Create local blob store using FsBlobStore from hashtree-fs
+    let blobs_path = dir.join("blobs");
+    let local_store = Arc::new(
+        FsBlobStore::with_max_bytes(&blobs_path, DEFAULT_MAX_BYTES)
+            .expect("Failed to create blob store"),
+    );
 
-        // Check Blossom
-        self.blossom
-            .has(hash)
-            .await
-            .map_err(|e| StoreError::Other(e.to_string()))
-    }
+    // Combined store: local first, then Blossom
+    Arc::new(CombinedStore::new(local_store, blossom_client()))
+}
 
-    async fn delete(&self, hash: &[u8; 32]) -> Result<bool, StoreError> {
-        // Only delete from local store
-        self.local.delete(hash).await
-    }
+/// Blossom client for fetching blobs, through the current proxy
+fn blossom_client() -> BlossomClient {
+    let keys = Keys::generate();
+    BlossomClient::new_empty(keys)
+        .with_read_servers(DEFAULT_BLOSSOM_SERVERS.iter().map(|s| s.to_string()).collect())
+        .with_proxy(proxy::reqwest_proxy(Component::Blossom))
 }
 
 /// Shared state for the htree server
 #[derive(Clone)]
 pub struct HtreeState {
     resolver: Arc<RwLock<Option<Arc<NostrRootResolver>>>>,
-    store: Arc<CombinedStore>,
+    store: Arc<RwLock<Arc<CombinedStore>>>,
+    /// Store of the guest session, used instead of `store` while one is on
+    guest_store: Arc<RwLock<Option<Arc<CombinedStore>>>>,
     root_cache: Arc<RwLock<LruCache<String, CachedRoot>>>,
+    /// Manifest check results by "npub/roothash"
+    signatures: Arc<RwLock<LruCache<String, SignatureStatus>>>,
+    /// Markdown files rendered to HTML, by file hash and link base
+    rendered: Arc<RwLock<LruCache<String, Arc<String>>>>,
+    transcoder: Arc<Transcoder>,
+    pictures: Arc<PictureCache>,
 }
 
 /// Default max storage: 1GB
 const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
 
+/// Root cache key of a link-visible tree opened with `secret`
+fn link_cache_key(key: &str, secret: &[u8; 32]) -> String {
+    format!("{}#{}", key, &to_hex(&hashtree_core::sha256(secret))[..16])
+}
+
 impl HtreeState {
     /// Create a new HtreeState with local blob store at data_dir
     pub fn new(data_dir: PathBuf) -> Self {
-        // Create local blob store using FsBlobStore from hashtree-fs
-        let blobs_path = data_dir.join("blobs");
-        let local_store = Arc::new(
-            FsBlobStore::with_max_bytes(&blobs_path, DEFAULT_MAX_BYTES)
-                .expect("Failed to create blob store"),
-        );
-
-        // Create Blossom client for fetching blobs
-        let keys = Keys::generate();
-        let blossom_client = BlossomClient::new_empty(keys)
-            .with_read_servers(DEFAULT_BLOSSOM_SERVERS.iter().map(|s| s.to_string()).collect());
-        let blossom_store = Arc::new(BlossomStore::new(blossom_client));
-
-        // Combined store: local first, then Blossom
-        let store = Arc::new(CombinedStore::new(local_store, blossom_store));
-
         Self {
             resolver: Arc::new(RwLock::new(None)),
-            store,
+            store: Arc::new(RwLock::new(open_store(&data_dir))),
+            guest_store: Arc::new(RwLock::new(None)),
             root_cache: Arc::new(RwLock::new(LruCache::new(
                 NonZeroUsize::new(1000).unwrap(),
             ))),
+            signatures: Arc::new(RwLock::new(LruCache::new(
+                NonZeroUsize::new(1000).unwrap(),
+            ))),
+            rendered: Arc::new(RwLock::new(LruCache::new(NonZeroUsize::new(100).unwrap()))),
+            transcoder: Arc::new(Transcoder::new(&data_dir, detect_ffmpeg())),
+            pictures: Arc::new(PictureCache::new(&data_dir)),
+        }
+    }
+
+    /// Blob store of the guest session if one is on, the persistent one otherwise
+    fn store(&self) -> Arc<CombinedStore> {
+        self.guest_store
+            .read()
+            .clone()
+            .unwrap_or_else(|| self.store.read().clone())
+    }
+
+    /// Rebuild the resolver and Blossom clients with the current proxy
+    async fn apply_proxy(&self) {
+        let resolver = self.resolver.write().take();
+        if let Some(resolver) = resolver {
+            let _ = resolver.stop().await;
+        }
+        let rebuild = |store: &Arc<CombinedStore>| {
+            Arc::new(CombinedStore::new(store.local().clone(), blossom_client()))
+        };
+        let store = rebuild(&self.store.read());
+        *self.store.write() = store;
+        let mut guest_store = self.guest_store.write();
+        if let Some(store) = guest_store.as_mut() {
+            *store = rebuild(store);
         }
     }
 
     fn cache_root(&self, npub: &str, tree_name: &str, cid: Cid, visibility: TreeVisibility) {
         let cache_key = format!("{}/{}", npub, tree_name);
         let mut cache = self.root_cache.write();
+        // The frontend doesn't know the event time; keep it for the same root
+        let created_at = cache
+            .peek(&cache_key)
+            .filter(|entry| entry.cid.hash == cid.hash)
+            .and_then(|entry| entry.created_at);
         cache.put(
             cache_key,
             CachedRoot {
                 cid,
                 visibility,
                 timestamp: std::time::Instant::now(),
+                created_at,
             },
         );
     }
 
+    /// `created_at` of the root event of a cached tree, if known
+    fn root_created_at(&self, npub: &str, tree_name: &str) -> Option<u64> {
+        let cache_key = format!("{}/{}", npub, tree_name);
+        self.root_cache.read().peek(&cache_key)?.created_at
+    }
+
     /// Initialize the Nostr resolver (called lazily on first request)
     async fn ensure_resolver(&self) -> Result<(), HtreeError> {
         // Check if already initialized
@@ -337,7 +391,8 @@ impl HtreeState {
             secret_key: None,
         };
 
-        let resolver = NostrRootResolver::new(config)
+        let relay_opts = proxy::relay_options(RelayOptions::new());
+        let resolver = NostrRootResolver::with_relay_options(config, relay_opts)
             .await
             .map_err(|e| HtreeError::Resolver(e.to_string()))?;
 
@@ -347,8 +402,22 @@ impl HtreeState {
         Ok(())
     }
 
-    /// Resolve npub/treeName to Cid
-    async fn resolve_tree(&self, npub: &str, tree_name: &str) -> Result<Cid, HtreeError> {
+    /// Resolve npub/treeName to Cid, also asking the relays of `hints` when
+    /// given. With the secret of a link-visible share URL the root is
+    /// unmasked, and cached under that secret only.
+    #[instrument(level = "debug", skip(self, secret, hints))]
+    async fn resolve_tree(
+        &self,
+        npub: &str,
+        tree_name: &str,
+        secret: Option<&[u8; 32]>,
+        hints: Option<&NostrRootResolver>,
+    ) -> Result<Cid, HtreeError> {
+        if let Some(secret) = secret {
+            return self
+                .resolve_link_visible(npub, tree_name, secret, hints)
+                .await;
+        }
         let cache_key = format!("{}/{}", npub, tree_name);
 
         // Check cache first
@@ -359,12 +428,14 @@ impl HtreeState {
         if let Some(entry) = cached {
             if entry.cid.key.is_some() {
                 debug!("Cache hit for {}", cache_key);
+                self.revalidate_if_due(npub, tree_name);
                 return Ok(entry.cid);
             }
 
-            if let Ok(Some(data)) = self.store.get(&entry.cid.hash).await {
+            if let Ok(Some(data)) = self.store().get(&entry.cid.hash).await {
                 if is_tree_node(&data) {
                     debug!("Cache hit for {}", cache_key);
+                    self.revalidate_if_due(npub, tree_name);
                     return Ok(entry.cid);
                 }
             }
@@ -372,10 +443,34 @@ impl HtreeState {
             debug!("Cache entry missing key for {}, refreshing", cache_key);
         }
 
-        // Resolve from Nostr
-        self.ensure_resolver().await?;
+        let (cid, created_at) = self.resolve_from_nostr(&cache_key, hints).await?;
 
-        let key = format!("{}/{}", npub, tree_name);
+        // Cache the result (default to Public visibility when resolved from Nostr)
+        {
+            let mut cache = self.root_cache.write();
+            cache.put(
+                cache_key,
+                CachedRoot {
+                    cid: cid.clone(),
+                    visibility: TreeVisibility::Public,
+                    timestamp: std::time::Instant::now(),
+                    created_at: Some(created_at),
+                },
+            );
+        }
+
+        Ok(cid)
+    }
+
+    /// Resolve `npub/treeName` from Nostr, bypassing the root cache, to its
+    /// root and the root event's `created_at`. With `hints` their relays are
+    /// asked too, and the newest root either has wins.
+    async fn resolve_from_nostr(
+        &self,
+        key: &str,
+        hints: Option<&NostrRootResolver>,
+    ) -> Result<(Cid, u64), HtreeError> {
+        self.ensure_resolver().await?;
         debug!("Resolving tree: {}", key);
 
         // Clone the resolver to avoid holding the lock across await
@@ -387,34 +482,261 @@ impl HtreeState {
                 .clone()
         };
 
-        let cid = tokio::time::timeout(Duration::from_secs(10), resolver.resolve(&key))
-            .await
-            .map_err(|_| HtreeError::Resolver("Timeout resolving tree".into()))?
-            .map_err(|e| HtreeError::Resolver(e.to_string()))?
-            .ok_or_else(|| HtreeError::TreeNotFound(key.clone()))?;
+        const TIMEOUT: &str = "Timeout resolving tree";
+        let hinted = async {
+            match hints {
+                Some(hints) => Some(timed(hints.resolve_timestamped(key), TIMEOUT).await),
+                None => None,
+            }
+        };
+        let (ours, hinted) =
+            tokio::join!(timed(resolver.resolve_timestamped(key), TIMEOUT), hinted);
+        found_either(ours, hinted)?
+            .into_iter()
+            .max_by_key(|(_, created_at)| *created_at)
+            .ok_or_else(|| HtreeError::TreeNotFound(key.to_string()))
+    }
 
-        // Cache the result (default to Public visibility when resolved from Nostr)
+    /// Re-resolve a cached root in the background if it's due, emitting
+    /// `htree-root-updated` when it has changed
+    fn revalidate_if_due(&self, npub: &str, tree_name: &str) {
+        let Some(previous) = self.claim_revalidation(npub, tree_name) else {
+            return;
+        };
+        let state = self.clone();
+        let (npub, tree_name) = (npub.to_string(), tree_name.to_string());
+        tokio::spawn(
+            async move {
+                let key = format!("{}/{}", npub, tree_name);
+                let (cid, created_at) = match state.resolve_from_nostr(&key, None).await {
+                    Ok(resolved) => resolved,
+                    Err(e) => {
+                        debug!("Failed to revalidate {}: {}", key, e);
+                        return;
+                    }
+                };
+                let Some(update) =
+                    state.apply_revalidated(&npub, &tree_name, &previous, cid, created_at)
+                else {
+                    return;
+                };
+                info!("Newer root for {}: {}", key, update.hash);
+                if let Some(app) = APP_HANDLE.get() {
+                    let _ = app.emit(ROOT_UPDATED_EVENT, &update);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    /// The cached root of a public tree last checked over
+    /// `REVALIDATE_AFTER` ago, marked checked so concurrent requests don't
+    /// revalidate it again. Roots of other trees come with keys from the
+    /// frontend that the resolver doesn't have.
+    fn claim_revalidation(&self, npub: &str, tree_name: &str) -> Option<Cid> {
+        let cache_key = format!("{}/{}", npub, tree_name);
+        let mut cache = self.root_cache.write();
+        let entry = cache.peek_mut(&cache_key)?;
+        if entry.visibility != TreeVisibility::Public
+            || entry.timestamp.elapsed() < REVALIDATE_AFTER
         {
-            let mut cache = self.root_cache.write();
-            cache.put(
-                cache_key,
-                CachedRoot {
-                    cid: cid.clone(),
-                    visibility: TreeVisibility::Public,
-                    timestamp: std::time::Instant::now(),
-                },
-            );
+            return None;
+        }
+        entry.timestamp = Instant::now();
+        Some(entry.cid.clone())
+    }
+
+    /// Cache a re-resolved root in place of `previous`, unless it's the
+    /// same or the cache moved on meanwhile. The same root only takes the
+    /// event time, in case it was republished.
+    fn apply_revalidated(
+        &self,
+        npub: &str,
+        tree_name: &str,
+        previous: &Cid,
+        cid: Cid,
+        created_at: u64,
+    ) -> Option<RootUpdated> {
+        let cache_key = format!("{}/{}", npub, tree_name);
+        let mut cache = self.root_cache.write();
+        let entry = cache.peek_mut(&cache_key)?;
+        if entry.cid.hash != previous.hash {
+            return None;
+        }
+        if cid.hash == previous.hash {
+            entry.created_at = Some(created_at);
+            return None;
+        }
+        let update = RootUpdated {
+            npub: npub.to_string(),
+            tree_name: tree_name.to_string(),
+            hash: to_hex(&cid.hash),
+            previous_hash: to_hex(&previous.hash),
+        };
+        cache.put(
+            cache_key,
+            CachedRoot {
+                cid,
+                visibility: TreeVisibility::Public,
+                timestamp: Instant::now(),
+                created_at: Some(created_at),
+            },
+        );
+        Some(update)
+    }
+
+    /// Npub and tree name an naddr (kind 30078 address) or nevent (a tree's
+    /// root event) points at, along with a resolver for its relay hints, so
+    /// links from clients on other relays resolve. The hint relays are only
+    /// asked for this request: `stop` the resolver once it's done.
+    async fn resolve_nip19(
+        &self,
+        identifier: &str,
+    ) -> Result<(String, String, Option<NostrRootResolver>), HtreeError> {
+        let not_a_tree = || HtreeError::InvalidPath(format!("Not a hashtree link: {}", identifier));
+        let (relays, address) = match Nip19::from_bech32(identifier)
+            .map_err(|e| HtreeError::InvalidPath(e.to_string()))?
+        {
+            Nip19::Coordinate(coordinate) => {
+                if coordinate.kind != Kind::from(KIND_TREE_ROOT) || coordinate.identifier.is_empty()
+                {
+                    return Err(not_a_tree());
+                }
+                let address = (coordinate.public_key, coordinate.identifier.clone());
+                (coordinate.relays, Ok(address))
+            }
+            Nip19::Event(event) => (event.relays, Err(event.event_id)),
+            _ => return Err(not_a_tree()),
+        };
+
+        self.ensure_resolver().await?;
+        let resolver = {
+            let resolver_guard = self.resolver.read();
+            resolver_guard
+                .as_ref()
+                .ok_or_else(|| HtreeError::Resolver("Resolver not initialized".into()))?
+                .clone()
+        };
+        let hints = resolver.for_hints(&relays).await;
+
+        let address = match address {
+            Ok(address) => Ok(address),
+            Err(id) => {
+                const TIMEOUT: &str = "Timeout resolving event";
+                let hinted = async {
+                    match &hints {
+                        Some(hints) => Some(timed(hints.tree_of_event(&id), TIMEOUT).await),
+                        None => None,
+                    }
+                };
+                let (ours, hinted) =
+                    tokio::join!(timed(resolver.tree_of_event(&id), TIMEOUT), hinted);
+                found_either(ours, hinted).and_then(|found| {
+                    found
+                        .into_iter()
+                        .next()
+                        .ok_or_else(|| HtreeError::TreeNotFound(identifier.to_string()))
+                })
+            }
+        };
+        let named = address.and_then(|(pubkey, tree_name)| {
+            let npub = pubkey
+                .to_bech32()
+                .map_err(|e| HtreeError::InvalidPath(e.to_string()))?;
+            Ok((npub, tree_name))
+        });
+        match named {
+            Ok((npub, tree_name)) => Ok((npub, tree_name, hints)),
+            Err(e) => {
+                if let Some(hints) = hints {
+                    let _ = hints.stop().await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Resolve a link-visible tree with the secret from its share URL (`?k=`).
+    /// The unmasked root is cached by the secret, so requests without it
+    /// can't reach the tree through the cache.
+    async fn resolve_link_visible(
+        &self,
+        npub: &str,
+        tree_name: &str,
+        secret: &[u8; 32],
+        hints: Option<&NostrRootResolver>,
+    ) -> Result<Cid, HtreeError> {
+        let key = format!("{}/{}", npub, tree_name);
+        let cache_key = link_cache_key(&key, secret);
+        if let Some(entry) = self.root_cache.read().peek(&cache_key) {
+            debug!("Cache hit for link-visible {}", key);
+            return Ok(entry.cid.clone());
         }
 
+        self.ensure_resolver().await?;
+        let resolver = {
+            let resolver_guard = self.resolver.read();
+            resolver_guard
+                .as_ref()
+                .ok_or_else(|| HtreeError::Resolver("Resolver not initialized".into()))?
+                .clone()
+        };
+
+        const TIMEOUT: &str = "Timeout resolving tree";
+        let hinted = async {
+            match hints {
+                Some(hints) => Some(timed(hints.resolve_shared(&key, secret), TIMEOUT).await),
+                None => None,
+            }
+        };
+        let (ours, hinted) = tokio::join!(
+            timed(resolver.resolve_shared(&key, secret), TIMEOUT),
+            hinted
+        );
+        let cid = found_either(ours, hinted)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| HtreeError::TreeNotFound(key.clone()))?;
+
+        self.root_cache.write().put(
+            cache_key,
+            CachedRoot {
+                cid: cid.clone(),
+                visibility: TreeVisibility::LinkVisible,
+                timestamp: std::time::Instant::now(),
+                created_at: None,
+            },
+        );
         Ok(cid)
     }
 
+    /// Check a tree root reached via `npub` against the owner's signed
+    /// manifest. Results are cached per root, which is content-addressed.
+    async fn verify_root(&self, npub: &str, root: &Cid) -> Result<SignatureStatus, HtreeError> {
+        let cache_key = format!("{}/{}", npub, to_hex(&root.hash));
+        if let Some(status) = self.signatures.read().peek(&cache_key) {
+            return Ok(*status);
+        }
+
+        let owner = nostr_sdk::PublicKey::parse(npub)
+            .map_err(|e| HtreeError::InvalidPath(e.to_string()))?;
+        let tree = HashTree::new(HashTreeConfig::new(self.store()));
+        let status = check_root(&tree, &owner, root)
+            .await
+            .map_err(HtreeError::Store)?;
+
+        self.signatures.write().put(cache_key, status);
+        Ok(status)
+    }
+
     /// Resolve a path within a tree to get the file's Cid
+    #[instrument(level = "debug", skip(self, root_cid))]
     async fn resolve_path(&self, root_cid: &Cid, path: &str) -> Result<Cid, HtreeError> {
-        let tree = HashTree::new(HashTreeConfig::new(self.store.clone()));
+        let tree = HashTree::new(HashTreeConfig::new(self.store()));
+        let path = normalize_path(path).map_err(HtreeError::InvalidPath)?;
 
         let cid = tree
-            .resolve_path(root_cid, path)
+            .resolve_path(root_cid, &path)
             .await
             .map_err(|e| HtreeError::Store(e.to_string()))?
             .ok_or_else(|| HtreeError::FileNotFound(path.to_string()))?;
@@ -427,7 +749,7 @@ impl HtreeState {
         root_cid: &Cid,
         dir_path: &str,
     ) -> Result<Option<String>, HtreeError> {
-        let tree = HashTree::new(HashTreeConfig::new(self.store.clone()));
+        let tree = HashTree::new(HashTreeConfig::new(self.store()));
 
         let dir_cid = if dir_path.is_empty() {
             root_cid.clone()
@@ -495,8 +817,9 @@ impl HtreeState {
     }
 
     /// Read file content from a Cid
+    #[instrument(level = "debug", skip_all, fields(hash = %to_hex(&cid.hash)))]
     async fn read_file(&self, cid: &Cid) -> Result<Vec<u8>, HtreeError> {
-        let tree = HashTree::new(HashTreeConfig::new(self.store.clone()));
+        let tree = HashTree::new(HashTreeConfig::new(self.store()));
 
         tree.get(cid)
             .await
@@ -506,13 +829,14 @@ impl HtreeState {
 
     /// Read a byte range from a file (fetches only necessary chunks)
     /// This is more efficient than read_file() for partial reads of large files.
+    #[instrument(level = "debug", skip(self, cid), fields(hash = %to_hex(&cid.hash)))]
     async fn read_file_range(
         &self,
         cid: &Cid,
         start: u64,
         end: Option<u64>,
     ) -> Result<Vec<u8>, HtreeError> {
-        let tree = HashTree::new(HashTreeConfig::new(self.store.clone()));
+        let tree = HashTree::new(HashTreeConfig::new(self.store()));
 
         tree.read_file_range(&cid.hash, start, end)
             .await
@@ -522,7 +846,7 @@ impl HtreeState {
 
     /// Get the total size of a file without loading all its content
     async fn get_file_size(&self, cid: &Cid) -> Result<u64, HtreeError> {
-        let tree = HashTree::new(HashTreeConfig::new(self.store.clone()));
+        let tree = HashTree::new(HashTreeConfig::new(self.store()));
 
         tree.get_size(&cid.hash)
             .await
@@ -534,7 +858,7 @@ impl HtreeState {
         &self,
         nhash: &str,
         filename: Option<&str>,
-    ) -> Result<(Cid, String), HtreeError> {
+    ) -> Result<Resolved, HtreeError> {
         debug!("Resolving nhash: {}", nhash);
 
         let nhash_data =
@@ -547,39 +871,80 @@ impl HtreeState {
         };
 
         // If nhash has a path, resolve it
-        let mut file_cid = if !nhash_data.path.is_empty() {
-            let path = nhash_data.path.join("/");
-            self.resolve_path(&cid, &path).await?
+        let mut inner_path = nhash_data.path.join("/");
+        let mut hinted = true;
+        let mut file_cid = if !inner_path.is_empty() {
+            self.resolve_path(&cid, &inner_path).await?
         } else {
-            cid
+            cid.clone()
         };
 
         // Resolve filename within the nhash root when provided.
         if let Some(path) = filename {
             if !path.is_empty() {
-                if path.contains('/') {
+                let found = if path.contains('/') {
                     file_cid = self.resolve_path(&file_cid, path).await?;
+                    true
                 } else {
                     match self.resolve_path(&file_cid, path).await {
-                        Ok(resolved) => file_cid = resolved,
-                        Err(HtreeError::FileNotFound(_)) => {}
+                        Ok(resolved) => {
+                            file_cid = resolved;
+                            true
+                        }
+                        Err(HtreeError::FileNotFound(_)) => false,
                         Err(e) => return Err(e),
                     }
+                };
+                if found {
+                    // The hints describe the nhash's target, not an entry in it
+                    hinted = false;
+                    inner_path = [inner_path.as_str(), path]
+                        .iter()
+                        .filter(|p| !p.is_empty())
+                        .copied()
+                        .collect::<Vec<_>>()
+                        .join("/");
                 }
             }
         }
 
-        let mime_type = guess_mime_type(filename.unwrap_or("file"));
-        Ok((file_cid, mime_type.to_string()))
+        let (size, hinted_type, hinted_name) = if hinted {
+            let mime_type = nhash_data.mime_type.filter(|t| is_mime_type(t));
+            (nhash_data.size, mime_type, nhash_data.filename)
+        } else {
+            (None, None, None)
+        };
+        let content_type = hinted_type.unwrap_or_else(|| {
+            let name = filename.or(hinted_name.as_deref()).unwrap_or("file");
+            guess_mime_type(name).to_string()
+        });
+        Ok(Resolved {
+            cid: file_cid,
+            content_type,
+            size,
+            filename: hinted_name,
+            root: cid,
+            inner_path,
+            signature: None,
+            untrusted: false,
+            sandboxed: wot_sandboxes_unowned(),
+            last_modified: None,
+        })
     }
 
-    /// Resolve npub path to Cid and mime type (without reading content)
+    /// Resolve npub path to Cid, mime type and the root's signature status
+    /// (without reading content). Roots with a bad manifest are refused.
     async fn resolve_npub(
         &self,
         npub: &str,
         tree_name: &str,
         file_path: &str,
-    ) -> Result<(Cid, String), HtreeError> {
+        secret: Option<&[u8; 32]>,
+        hints: Option<&NostrRootResolver>,
+    ) -> Result<Resolved, HtreeError> {
+        if is_muted(npub) {
+            return Err(HtreeError::Muted(npub.to_string()));
+        }
         let mut tree_name = tree_name.to_string();
         let mut file_path = file_path.to_string();
         debug!(
@@ -590,7 +955,7 @@ impl HtreeState {
         );
 
         // Resolve tree root
-        let root_cid = match self.resolve_tree(npub, &tree_name).await {
+        let root_cid = match self.resolve_tree(npub, &tree_name, secret, hints).await {
             Ok(cid) => cid,
             Err(HtreeError::TreeNotFound(_)) if !file_path.is_empty() => {
                 let mut parts = file_path.splitn(2, '/');
@@ -602,7 +967,7 @@ impl HtreeState {
                 }
 
                 let alt_tree_name = format!("{}/{}", tree_name, first);
-                match self.resolve_tree(npub, &alt_tree_name).await {
+                match self.resolve_tree(npub, &alt_tree_name, secret, hints).await {
                     Ok(cid) => {
                         tree_name = alt_tree_name;
                         file_path = rest.to_string();
@@ -626,6 +991,11 @@ impl HtreeState {
                 .unwrap_or_else(|| "none".to_string())
         );
 
+        let signature = self.verify_root(npub, &root_cid).await?;
+        if signature == SignatureStatus::Invalid {
+            return Err(HtreeError::BadSignature(format!("{}/{}", npub, tree_name)));
+        }
+
         let resolved_path = if is_thumbnail_request(&file_path) {
             let dir_path = file_path.strip_suffix("/thumbnail").unwrap_or("");
             self.find_thumbnail_in_dir(&root_cid, dir_path)
@@ -637,7 +1007,7 @@ impl HtreeState {
 
         // Navigate to file if path is provided
         let file_cid = if resolved_path.is_empty() {
-            root_cid
+            root_cid.clone()
         } else {
             self.resolve_path(&root_cid, &resolved_path).await?
         };
@@ -648,49 +1018,89 @@ impl HtreeState {
             &resolved_path
         });
 
-        Ok((file_cid, mime_type.to_string()))
+        Ok(Resolved {
+            cid: file_cid,
+            content_type: mime_type.to_string(),
+            size: None,
+            filename: None,
+            root: root_cid,
+            inner_path: resolved_path,
+            signature: Some(signature),
+            untrusted: wot_flags(npub),
+            sandboxed: wot_sandboxes(npub),
+            last_modified: self.root_created_at(npub, &tree_name),
+        })
     }
 }
 
-// Remove Default impl - HtreeState now requires data_dir
-
-/// Parse Range header value like "bytes=0-999" or "bytes=500-"
-fn parse_range_header(range_header: &str, total_size: usize) -> Option<(usize, usize)> {
-    if total_size == 0 {
-        return None;
-    }
-    let range = range_header.strip_prefix("bytes=")?;
-    let parts: Vec<&str> = range.split('-').collect();
-    if parts.len() != 2 {
-        return None;
-    }
+/// An htree path resolved to its target, without the content loaded
+struct Resolved {
+    cid: Cid,
+    content_type: String,
+    /// Size and suggested filename of `cid`, from nhash hints
+    size: Option<u64>,
+    filename: Option<String>,
+    /// Tree root the path was resolved from, and the path below it
+    root: Cid,
+    inner_path: String,
+    /// Signature status of npub roots; nhash roots are self-verifying
+    signature: Option<SignatureStatus>,
+    /// Owner of an npub root is beyond the WoT policy's flag distance
+    untrusted: bool,
+    /// Owner of an npub root is beyond the WoT policy's sandbox distance
+    sandboxed: bool,
+    /// When an npub root was published (unix seconds), for `Last-Modified`
+    last_modified: Option<u64>,
+}
 
-    let start: usize = if parts[0].is_empty() {
-        // Suffix range like "-500" means last 500 bytes
-        let suffix_len: usize = parts[1].parse().ok()?;
-        total_size.saturating_sub(suffix_len)
-    } else {
-        parts[0].parse().ok()?
-    };
+/// Whether the WoT policy flags trees of `npub`
+fn wot_flags(npub: &str) -> bool {
+    APP_HANDLE
+        .get()
+        .and_then(|app| app.try_state::<Arc<WorkerState>>())
+        .is_some_and(|state| state.wot.flags(npub))
+}
 
-    let end: usize = if parts[1].is_empty() {
-        // Open-ended range like "500-" means from 500 to end
-        total_size - 1
-    } else {
-        parts[1].parse().ok()?
-    };
+/// Whether the WoT policy sandboxes HTML from trees of `npub`
+fn wot_sandboxes(npub: &str) -> bool {
+    APP_HANDLE
+        .get()
+        .and_then(|app| app.try_state::<Arc<WorkerState>>())
+        .is_some_and(|state| state.wot.sandboxes(npub))
+}
 
-    // Validate range
-    if start > end || start >= total_size {
-        return None;
-    }
+/// Whether the WoT policy sandboxes HTML of no known owner (nhash content)
+fn wot_sandboxes_unowned() -> bool {
+    APP_HANDLE
+        .get()
+        .and_then(|app| app.try_state::<Arc<WorkerState>>())
+        .is_some_and(|state| state.wot.policy().sandboxes(None))
+}
 
-    // Clamp end to file size
-    let end = end.min(total_size - 1);
+/// CSP to serve a `content_type` response with: documents that can run
+/// scripts get [`SANDBOX_CSP`] when their tree is `sandboxed`
+fn content_security_policy(sandboxed: bool, content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    let active = matches!(
+        mime.to_ascii_lowercase().as_str(),
+        "text/html" | "application/xhtml+xml" | "image/svg+xml"
+    );
+    (sandboxed && active).then_some(SANDBOX_CSP)
+}
 
-    Some((start, end))
+/// Whether `pubkey` (hex or npub) is on our mute list
+pub fn is_muted(pubkey: &str) -> bool {
+    APP_HANDLE
+        .get()
+        .and_then(|app| app.try_state::<Arc<WorkerState>>())
+        .is_some_and(|state| state.mutes.is_muted(pubkey))
 }
 
+// Remove Default impl - HtreeState now requires data_dir
+
+/// Reads the requested byte range of a file, or all of it. Range bounds and
+/// totals come from the tree itself, never from a link's `size` hint, which
+/// is only trusted for headers sent before any content is read
 async fn read_range_or_full(
     state: &HtreeState,
     file_cid: &Cid,
@@ -724,8 +1134,26 @@ async fn read_range_or_full(
 #[axum::debug_handler]
 async fn handle_htree_request(
     State(state): State<HtreeState>,
+    method: Method,
     headers: HeaderMap,
     uri: OriginalUri,
+) -> Response {
+    let id = next_request_id();
+    let span = info_span!("htree", id, path = %uri.path());
+    let mut response = serve_htree_request(&state, &method, &headers, &uri)
+        .instrument(span)
+        .await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, HeaderValue::from(id));
+    response
+}
+
+async fn serve_htree_request(
+    state: &HtreeState,
+    method: &Method,
+    headers: &HeaderMap,
+    uri: &OriginalUri,
 ) -> Response {
     // Get raw path from URI (preserves percent-encoding)
     let raw_path = uri.path();
@@ -733,14 +1161,130 @@ async fn handle_htree_request(
     let path = raw_path.strip_prefix("/htree/").unwrap_or(raw_path);
     debug!("htree request: raw_path={}, path={}", raw_path, path);
 
+    // Link-visible share URLs carry the key-unmasking secret as ?k=
+    let link_secret = query_param(uri.query(), "k");
+
     // First resolve the path to get CID and mime type (without loading file content)
-    let (file_cid, content_type) = match resolve_htree_inner(&state, &path).await {
+    let resolved = match resolve_htree_inner(state, path, link_secret).await {
         Ok(result) => result,
         Err(e) => return e.into_response(),
     };
+    let signature = resolved.signature;
+    let untrusted = resolved.untrusted;
+    let csp = content_security_policy(resolved.sandboxed, &resolved.content_type);
+
+    let mut response = if query_param(uri.query(), "proof") == Some("1") {
+        serve_proof(state, &resolved).await
+    } else {
+        serve_resolved(state, method, headers, uri, path, resolved).await
+    };
+    if let Some(signature) = signature {
+        response
+            .headers_mut()
+            .insert(SIGNATURE_HEADER, HeaderValue::from_static(signature.as_str()));
+    }
+    if untrusted {
+        response
+            .headers_mut()
+            .insert(WOT_HEADER, HeaderValue::from_static("untrusted"));
+    }
+    if let Some(csp) = csp {
+        response.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(csp),
+        );
+    }
+    response
+}
 
+/// JSON Merkle proof of a resolved entry against its tree root, letting a
+/// thin client check a single file without fetching the rest of the tree
+async fn serve_proof(state: &HtreeState, resolved: &Resolved) -> Response {
+    let tree = HashTree::new(HashTreeConfig::new(state.store()));
+    match tree.prove(&resolved.root, &resolved.inner_path).await {
+        Ok(proof) => Json(proof.to_json()).into_response(),
+        Err(e) => HtreeError::Store(e.to_string()).into_response(),
+    }
+}
+
+/// Serve a resolved htree path: playlists, transforms, ranges or the file
+async fn serve_resolved(
+    state: &HtreeState,
+    method: &Method,
+    headers: &HeaderMap,
+    uri: &OriginalUri,
+    path: &str,
+    resolved: Resolved,
+) -> Response {
+    let Resolved {
+        cid: file_cid,
+        content_type,
+        size,
+        filename,
+        last_modified,
+        ..
+    } = resolved;
+    match query_param(uri.query(), "format") {
+        Some("hls") => return serve_hls_playlist(state, &file_cid).await,
+        Some("tracks") => {
+            let link_secret = query_param(uri.query(), "k");
+            return serve_track_manifest(state, path, link_secret, &file_cid).await;
+        }
+        Some("vtt") => {
+            return serve_vtt(state, path, &file_cid, query_param(uri.query(), "track")).await
+        }
+        _ => {}
+    }
+    if query_param(uri.query(), "render") == Some("html") && is_markdown(path, &content_type) {
+        let link_secret = query_param(uri.query(), "k");
+        return serve_markdown(state, &file_cid, path, link_secret, size).await;
+    }
+
+    let query = uri.query();
+    match MediaTransform::from_params(
+        query_param(query, "resize"),
+        query_param(query, "format"),
+        query_param(query, "rotate"),
+        query_param(query, "clip"),
+    ) {
+        Ok(Some(transform)) => {
+            return serve_transform(state, &file_cid, path, &content_type, &transform).await
+        }
+        Ok(None) => {}
+        Err(e) => return HtreeError::InvalidPath(e).into_response(),
+    }
+
+    if let Some(last_modified) = last_modified {
+        if not_modified_since(headers, last_modified) {
+            return with_last_modified(Response::builder(), Some(last_modified))
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap();
+        }
+    }
+
+    let disposition = filename.as_deref().map(content_disposition);
     let range_header = headers.get(header::RANGE).and_then(|h| h.to_str().ok());
-    let (data, range_info) = match read_range_or_full(&state, &file_cid, range_header).await {
+    if method == Method::HEAD && range_header.is_none() {
+        let size = match size {
+            Some(size) => size,
+            None => match state.get_file_size(&file_cid).await {
+                Ok(size) => size,
+                Err(e) => return e.into_response(),
+            },
+        };
+        let mut response = with_last_modified(Response::builder(), last_modified)
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CONTENT_LENGTH, size)
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(disposition) = disposition {
+            response = response.header(header::CONTENT_DISPOSITION, disposition);
+        }
+        return response.body(Body::empty()).unwrap();
+    }
+
+    let (data, range_info) = match read_range_or_full(state, &file_cid, range_header).await {
         Ok(result) => result,
         Err(e) => return e.into_response(),
     };
@@ -754,40 +1298,467 @@ async fn handle_htree_request(
             content_length, start, end, total_size, content_type
         );
 
-        return Response::builder()
+        let mut response = with_last_modified(Response::builder(), last_modified)
             .status(StatusCode::PARTIAL_CONTENT)
             .header(header::CONTENT_TYPE, content_type)
             .header(header::CONTENT_LENGTH, content_length)
             .header(header::CONTENT_RANGE, content_range)
-            .header(header::ACCEPT_RANGES, "bytes")
-            .body(Body::from(data))
-            .unwrap();
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(disposition) = disposition {
+            response = response.header(header::CONTENT_DISPOSITION, disposition);
+        }
+        return response.body(Body::from(data)).unwrap();
     }
 
     info!("htree response: {} bytes, type={}", data.len(), content_type);
-    Response::builder()
+    let mut response = with_last_modified(Response::builder(), last_modified)
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, content_type)
         .header(header::CONTENT_LENGTH, data.len())
-        .header(header::ACCEPT_RANGES, "bytes")
-        .body(Body::from(data))
-        .unwrap()
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(disposition) = disposition {
+        response = response.header(header::CONTENT_DISPOSITION, disposition);
+    }
+    response.body(Body::from(data)).unwrap()
 }
 
-/// URL-decode a string (percent-decode)
-fn url_decode(s: &str) -> String {
-    percent_encoding::percent_decode_str(s)
-        .decode_utf8_lossy()
-        .into_owned()
+/// `Last-Modified` and `Cache-Control: no-cache` for a file of a mutable
+/// path published at `last_modified`, so it's revalidated on every use
+/// rather than kept for a heuristic time
+fn with_last_modified(
+    response: axum::http::response::Builder,
+    last_modified: Option<u64>,
+) -> axum::http::response::Builder {
+    let Some(last_modified) = last_modified else {
+        return response;
+    };
+    let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(last_modified));
+    response
+        .header(header::LAST_MODIFIED, date)
+        .header(header::CACHE_CONTROL, "no-cache")
+}
+
+/// Whether the request's `If-Modified-Since` is no earlier than
+/// `last_modified`, i.e. the client's copy is current
+fn not_modified_since(headers: &HeaderMap, last_modified: u64) -> bool {
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .and_then(|since| since.duration_since(UNIX_EPOCH).ok())
+        .is_some_and(|since| since.as_secs() >= last_modified)
+}
+
+/// Serve an HLS playlist for a video, packaging it with ffmpeg on first request
+async fn serve_hls_playlist(state: &HtreeState, file_cid: &Cid) -> Response {
+    if !state.transcoder.is_available() {
+        return Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("HLS requires ffmpeg (set HTREE_FFMPEG or add it to PATH)"))
+            .unwrap();
+    }
+
+    let playlist = match state
+        .transcoder
+        .cached_playlist(state.store().as_ref(), &file_cid.hash)
+        .await
+    {
+        Some(playlist) => playlist,
+        None => {
+            let input = match state.read_file(file_cid).await {
+                Ok(data) => data,
+                Err(e) => return e.into_response(),
+            };
+            match state
+                .transcoder
+                .package_hls(state.store().as_ref(), &file_cid.hash, input)
+                .await
+            {
+                Ok(playlist) => playlist,
+                Err(e) => return HtreeError::Io(e).into_response(),
+            }
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")
+        .header(header::CONTENT_LENGTH, playlist.len())
+        .body(Body::from(playlist))
+        .unwrap()
+}
+
+/// Serve a resized/rotated/converted image or a clipped audio file
+async fn serve_transform(
+    state: &HtreeState,
+    file_cid: &Cid,
+    path: &str,
+    content_type: &str,
+    transform: &MediaTransform,
+) -> Response {
+    let expected = if transform.is_audio() {
+        "audio/"
+    } else {
+        "image/"
+    };
+    if !content_type.starts_with(expected) {
+        return HtreeError::InvalidPath(format!("Transform not supported for {}", content_type))
+            .into_response();
+    }
+
+    transform_response(state, file_cid, path, transform).await
+}
+
+/// Run a transform through the transcoder's cache and serve the result
+async fn transform_response(
+    state: &HtreeState,
+    file_cid: &Cid,
+    path: &str,
+    transform: &MediaTransform,
+) -> Response {
+    let source_ext = path.rsplit('.').next().unwrap_or_default();
+    let input = async { state.read_file(file_cid).await.map_err(|e| e.to_string()) };
+    match state
+        .transcoder
+        .transform(
+            state.store().as_ref(),
+            &file_cid.hash,
+            source_ext,
+            transform,
+            input,
+        )
+        .await
+    {
+        Ok((data, ext)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, guess_mime_type(&format!("x.{}", ext)))
+            .header(header::CONTENT_LENGTH, data.len())
+            .body(Body::from(data))
+            .unwrap(),
+        Err(e) if !state.transcoder.is_available() => Response::builder()
+            .status(StatusCode::NOT_IMPLEMENTED)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(e))
+            .unwrap(),
+        Err(e) => HtreeError::Io(e).into_response(),
+    }
+}
+
+/// Serve a markdown file rendered to HTML, cached by content and link base
+async fn serve_markdown(
+    state: &HtreeState,
+    file_cid: &Cid,
+    path: &str,
+    link_secret: Option<&str>,
+    size: Option<u64>,
+) -> Response {
+    let base = LinkBase::for_path(path).map(|base| match link_secret {
+        Some(secret) => base.with_secret(secret),
+        None => base,
+    });
+    let Some(base) = base else {
+        return HtreeError::InvalidPath(format!("Not a file path: {}", path)).into_response();
+    };
+    let key = base.cache_key(&to_hex(&file_cid.hash));
+    let cached = state.rendered.write().get(&key).cloned();
+    let html = match cached {
+        Some(html) => html,
+        None => {
+            let size = match size {
+                Some(size) => size,
+                None => match state.get_file_size(file_cid).await {
+                    Ok(size) => size,
+                    Err(e) => return e.into_response(),
+                },
+            };
+            if size > MAX_MARKDOWN_BYTES {
+                return HtreeError::InvalidPath(format!(
+                    "Markdown file too large to render: {} bytes",
+                    size
+                ))
+                .into_response();
+            }
+            let source = match state.read_file(file_cid).await {
+                Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+                Err(e) => return e.into_response(),
+            };
+            let title = url_decode(path.rsplit('/').next().unwrap_or_default());
+            let rendered =
+                tokio::task::spawn_blocking(move || markdown::render(&source, &base, &title));
+            let html = match rendered.await {
+                Ok(html) => Arc::new(html),
+                Err(e) => return HtreeError::Io(e.to_string()).into_response(),
+            };
+            state.rendered.write().put(key, html.clone());
+            html
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CONTENT_LENGTH, html.len())
+        .header(header::CONTENT_SECURITY_POLICY, RENDERED_CSP)
+        .body(Body::from(html.as_bytes().to_vec()))
+        .unwrap()
+}
+
+/// Serve the track manifest for a video folder (or the folder of a video file)
+async fn serve_track_manifest(
+    state: &HtreeState,
+    path: &str,
+    link_secret: Option<&str>,
+    file_cid: &Cid,
+) -> Response {
+    let tree = HashTree::new(HashTreeConfig::new(state.store()));
+
+    let is_dir = match tree.is_dir(file_cid).await {
+        Ok(is_dir) => is_dir,
+        Err(e) => return HtreeError::Store(e.to_string()).into_response(),
+    };
+    let (dir_cid, video) = if is_dir {
+        (file_cid.clone(), None)
+    } else {
+        let Some((parent, name)) = path.trim_end_matches('/').rsplit_once('/') else {
+            return HtreeError::InvalidPath(path.to_string()).into_response();
+        };
+        let name = url_decode(name);
+        if !is_video_file(&name) {
+            return HtreeError::InvalidPath(format!("Not a video or folder: {}", name))
+                .into_response();
+        }
+        match resolve_htree_inner(state, parent, link_secret).await {
+            Ok(resolved) => (resolved.cid, Some(name)),
+            Err(e) => return e.into_response(),
+        }
+    };
+
+    let entries = match tree.list_directory(&dir_cid).await {
+        Ok(entries) => entries,
+        Err(e) => return HtreeError::Store(e.to_string()).into_response(),
+    };
+
+    let mut files = Vec::new();
+    for entry in &entries {
+        if entry.link_type != LinkType::Dir {
+            files.push(entry.name.clone());
+        } else if SUBTITLE_DIRS.contains(&entry.name.as_str()) {
+            let sub_cid = Cid {
+                hash: entry.hash,
+                key: entry.key,
+            };
+            if let Ok(sub_entries) = tree.list_directory(&sub_cid).await {
+                files.extend(
+                    sub_entries
+                        .iter()
+                        .filter(|e| e.link_type != LinkType::Dir)
+                        .map(|e| format!("{}/{}", entry.name, e.name)),
+                );
+            }
+        }
+    }
+
+    let mut manifest = build_manifest(&files, video.as_deref());
+    manifest.thumbnail = THUMBNAIL_PATTERNS
+        .iter()
+        .find(|pattern| files.iter().any(|f| f == *pattern))
+        .map(|pattern| pattern.to_string());
+
+    let video_entry = manifest
+        .video
+        .as_ref()
+        .and_then(|name| entries.iter().find(|e| &e.name == name));
+    if let (Some(entry), true) = (video_entry, state.transcoder.is_available()) {
+        let video_cid = Cid {
+            hash: entry.hash,
+            key: entry.key,
+        };
+        let input = async { state.read_file(&video_cid).await.map_err(|e| e.to_string()) };
+        let probed = state.transcoder.probe_streams(&video_cid.hash, input).await;
+        if let Err(e) = probed.and_then(|json| apply_probe(&mut manifest, &json)) {
+            warn!("Failed to probe tracks of {}: {}", entry.name, e);
+        }
+    }
+
+    (StatusCode::OK, Json(manifest)).into_response()
+}
+
+/// Serve a subtitle as WebVTT: a sidecar .srt converted in place, or an
+/// embedded stream (`track=N`) extracted with ffmpeg
+async fn serve_vtt(
+    state: &HtreeState,
+    path: &str,
+    file_cid: &Cid,
+    track: Option<&str>,
+) -> Response {
+    if let Some(track) = track {
+        let Ok(index) = track.parse::<u32>() else {
+            return HtreeError::InvalidPath(format!("Invalid track: {}", track)).into_response();
+        };
+        return transform_response(state, file_cid, path, &MediaTransform::subtitle(index)).await;
+    }
+
+    let data = match state.read_file(file_cid).await {
+        Ok(data) => data,
+        Err(e) => return e.into_response(),
+    };
+    let vtt = if path.to_lowercase().ends_with(".srt") {
+        srt_to_vtt(&String::from_utf8_lossy(&data)).into_bytes()
+    } else if path.to_lowercase().ends_with(".vtt") {
+        data
+    } else {
+        return HtreeError::InvalidPath(format!("Not a subtitle file: {}", path)).into_response();
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/vtt")
+        .header(header::CONTENT_LENGTH, vtt.len())
+        .body(Body::from(vtt))
+        .unwrap()
+}
+
+/// Serve an HLS segment stored by the transcoder
+async fn handle_hls_segment(
+    State(state): State<HtreeState>,
+    axum::extract::Path(name): axum::extract::Path<String>,
+) -> Response {
+    let hash = match name.strip_suffix(".ts").and_then(|hex| from_hex(hex).ok()) {
+        Some(hash) => hash,
+        None => return HtreeError::InvalidPath(name).into_response(),
+    };
+
+    match state.store().get(&hash).await {
+        Ok(Some(data)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "video/mp2t")
+            .header(header::CONTENT_LENGTH, data.len())
+            .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .body(Body::from(data))
+            .unwrap(),
+        Ok(None) => HtreeError::FileNotFound(name).into_response(),
+        Err(e) => HtreeError::Store(e.to_string()).into_response(),
+    }
+}
+
+/// Profile picture of an npub: the kind 0 picture URL, fetched once and
+/// served from the blob store, or the avatar file of its profile tree
+async fn handle_profile_picture(
+    State(state): State<HtreeState>,
+    headers: HeaderMap,
+    axum::extract::Path(npub): axum::extract::Path<String>,
+) -> Response {
+    if !is_npub(&npub) {
+        return HtreeError::InvalidPath(npub).into_response();
+    }
+    if is_muted(&npub) {
+        return HtreeError::Muted(npub).into_response();
+    }
+
+    let (data, content_type) = match profile_picture(&state, &npub).await {
+        Ok(picture) => picture,
+        Err(e) => return e.into_response(),
+    };
+    let etag = format!("\"{}\"", to_hex(&hashtree_core::sha256(&data)));
+    let response = Response::builder()
+        .header(header::ETAG, &etag)
+        // Short, so a changed picture shows up without a reload
+        .header(header::CACHE_CONTROL, "public, max-age=3600");
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        return response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    response
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, data.len())
+        .body(Body::from(data))
+        .unwrap()
+}
+
+async fn profile_picture(state: &HtreeState, npub: &str) -> Result<(Vec<u8>, String), HtreeError> {
+    let worker = APP_HANDLE
+        .get()
+        .and_then(|app| Some((app, app.try_state::<Arc<WorkerState>>()?)));
+    let profile = match worker {
+        Some((app, worker)) => crate::worker::get_profile(worker.inner().clone(), app, npub)
+            .await
+            .unwrap_or_else(|e| {
+                debug!("No profile for {}: {}", npub, e);
+                None
+            }),
+        None => None,
+    };
+
+    if let Some(url) = profile.as_ref().and_then(picture_url) {
+        let store = state.store();
+        if let Some(picture) = state.pictures.cached(store.as_ref(), url).await {
+            return Ok(picture);
+        }
+        match state.pictures.fetch(store.as_ref(), url).await {
+            Ok(picture) => return Ok(picture),
+            Err(e) => warn!("Picture of {} unavailable: {}", npub, e),
+        }
+    }
+
+    let root = state.resolve_tree(npub, PROFILE_TREE, None, None).await?;
+    for name in AVATAR_FILES {
+        if let Ok(cid) = state.resolve_path(&root, name).await {
+            let data = state.read_file(&cid).await?;
+            return Ok((data, guess_mime_type(name).to_string()));
+        }
+    }
+    Err(HtreeError::FileNotFound(format!("{}/picture", npub)))
+}
+
+/// Run a resolver `query`, failing after 10 seconds with `timeout_error`
+async fn timed<T>(
+    query: impl std::future::Future<Output = Result<Option<T>, ResolverError>>,
+    timeout_error: &str,
+) -> Result<Option<T>, HtreeError> {
+    tokio::time::timeout(Duration::from_secs(10), query)
+        .await
+        .map_err(|_| HtreeError::Resolver(timeout_error.into()))?
+        .map_err(|e| HtreeError::Resolver(e.to_string()))
+}
+
+/// What a lookup on our relays and on the relay hints of the request, if
+/// it has any, found: ours first. Fails only if neither found anything and
+/// one of them failed.
+fn found_either<T>(
+    ours: Result<Option<T>, HtreeError>,
+    hinted: Option<Result<Option<T>, HtreeError>>,
+) -> Result<Vec<T>, HtreeError> {
+    let mut found = Vec::new();
+    let mut error = None;
+    for result in std::iter::once(ours).chain(hinted) {
+        match result {
+            Ok(Some(value)) => found.push(value),
+            Ok(None) => {}
+            Err(e) => error = error.or(Some(e)),
+        }
+    }
+    match error {
+        Some(e) if found.is_empty() => Err(e),
+        _ => Ok(found),
+    }
 }
 
 /// Resolve path to Cid and mime type without loading file content.
 /// This is used for efficient range requests where we need to know the file
-/// before deciding how much to read.
+/// before deciding how much to read. `link_secret` is the hex `?k=` of a
+/// link-visible share URL.
 async fn resolve_htree_inner(
     state: &HtreeState,
     path: &str,
-) -> Result<(Cid, String), HtreeError> {
+    link_secret: Option<&str>,
+) -> Result<Resolved, HtreeError> {
     let path = path.trim_start_matches('/');
     let parts: Vec<&str> = path.splitn(2, '/').collect();
 
@@ -798,31 +1769,51 @@ async fn resolve_htree_inner(
     let first = parts[0];
     let rest = parts.get(1).copied().unwrap_or("");
 
+    // Decoded paths below the root, refused if they try to escape it
+    let decode = |p: &str| normalize_path(&url_decode(p)).map_err(HtreeError::InvalidPath);
+    let secret = link_secret
+        .map(|hex| {
+            hashtree_core::key_from_hex(hex)
+                .map_err(|_| HtreeError::InvalidPath("Invalid link secret".into()))
+        })
+        .transpose()?;
+
     if first.starts_with("nhash1") {
-        let filename = if rest.is_empty() {
-            None
-        } else {
-            Some(url_decode(rest))
-        };
+        let filename = Some(decode(rest)?).filter(|f| !f.is_empty());
         state.resolve_nhash(first, filename.as_deref()).await
     } else if is_npub(first) {
         let rest_parts: Vec<&str> = rest.splitn(2, '/').collect();
         let tree_name_encoded = rest_parts.first().ok_or_else(|| {
             HtreeError::InvalidPath("Missing tree name in npub path".into())
         })?;
-        if tree_name_encoded.is_empty() {
+        let tree_name = decode(tree_name_encoded)?;
+        if tree_name.is_empty() {
             return Err(HtreeError::InvalidPath("Empty tree name".into()));
         }
-        let tree_name = url_decode(tree_name_encoded);
-        let file_path = rest_parts
-            .get(1)
-            .map(|p| url_decode(p))
-            .unwrap_or_default();
+        let file_path = decode(rest_parts.get(1).copied().unwrap_or(""))?;
 
-        state.resolve_npub(first, &tree_name, &file_path).await
+        state
+            .resolve_npub(first, &tree_name, &file_path, secret.as_ref(), None)
+            .await
+    } else if first.starts_with("naddr1") || first.starts_with("nevent1") {
+        let file_path = decode(rest)?;
+        let (npub, tree_name, hints) = state.resolve_nip19(first).await?;
+        let resolved = state
+            .resolve_npub(
+                &npub,
+                &tree_name,
+                &file_path,
+                secret.as_ref(),
+                hints.as_ref(),
+            )
+            .await;
+        if let Some(hints) = hints {
+            let _ = hints.stop().await;
+        }
+        resolved
     } else {
         Err(HtreeError::InvalidPath(format!(
-            "Path must start with npub or nhash: {}",
+            "Path must start with npub, nhash, naddr or nevent: {}",
             first
         )))
     }
@@ -855,17 +1846,38 @@ pub fn resolve_htree_url_to_path(host: &str, raw_path: &str) -> String {
     }
 }
 
-/// Global server port - set when server starts
-static SERVER_PORT: once_cell::sync::OnceCell<u16> = once_cell::sync::OnceCell::new();
+/// Global server address - set when server starts
+static SERVER_ADDR: once_cell::sync::OnceCell<SocketAddr> = once_cell::sync::OnceCell::new();
 static APP_HANDLE: once_cell::sync::OnceCell<AppHandle> = once_cell::sync::OnceCell::new();
 
 pub fn set_app_handle(app: AppHandle) {
     let _ = APP_HANDLE.set(app);
 }
 
+pub fn app_handle() -> Option<&'static AppHandle> {
+    APP_HANDLE.get()
+}
+
 /// Get the htree server port (if running)
 pub fn get_server_port() -> Option<u16> {
-    SERVER_PORT.get().copied()
+    SERVER_ADDR.get().map(|addr| addr.port())
+}
+
+/// Address the htree server is bound to (if running)
+pub fn server_addr() -> Option<SocketAddr> {
+    SERVER_ADDR.get().copied()
+}
+
+/// URL of the htree server (if running); one bound to all interfaces is
+/// reached over loopback
+pub fn server_url() -> Option<String> {
+    let addr = SERVER_ADDR.get()?;
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    Some(format!("http://{}", SocketAddr::new(ip, addr.port())))
 }
 
 /// Handle NIP-07 HTTP requests from webviews
@@ -930,6 +1942,37 @@ async fn handle_nip07_request(
             }),
         );
     }
+    if !nip07_state.allows_nostr(&request.origin, session_token) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(crate::nip07::Nip07Response {
+                result: None,
+                error: Some("window.nostr is disabled for this page".to_string()),
+            }),
+        );
+    }
+
+    // Signing has a much smaller budget than other NIP-07 calls
+    if request.method == "signEvent" {
+        if let Err(retry_after) =
+            rate_limit::limiter().check(&request.origin, LimitClass::SignEvent, Instant::now())
+        {
+            warn!(
+                "[NIP-07 HTTP] signEvent rate limited for {}",
+                request.origin
+            );
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(crate::nip07::Nip07Response {
+                    result: None,
+                    error: Some(format!(
+                        "Rate limited, retry in {}s",
+                        retry_after.as_secs().max(1)
+                    )),
+                }),
+            );
+        }
+    }
 
     // Process the NIP-07 request
     let response = crate::nip07::handle_nip07_request(
@@ -944,6 +1987,114 @@ async fn handle_nip07_request(
     (StatusCode::OK, Json(response))
 }
 
+/// Query parameters for /search
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    q: Option<String>,
+    npub: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Default and maximum number of hits returned by /search
+const DEFAULT_SEARCH_LIMIT: usize = 50;
+const MAX_SEARCH_LIMIT: usize = 500;
+
+fn search_index_json(
+    index: &crate::worker::SearchIndex,
+    params: &SearchParams,
+) -> (StatusCode, serde_json::Value) {
+    let query = match params.q.as_deref().map(str::trim) {
+        Some(q) if !q.is_empty() => q,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                json!({ "error": "Missing query parameter q" }),
+            );
+        }
+    };
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .min(MAX_SEARCH_LIMIT);
+
+    match index.search(query, params.npub.as_deref(), limit) {
+        Ok(hits) => (StatusCode::OK, json!({ "query": query, "hits": hits })),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, json!({ "error": e })),
+    }
+}
+
+/// Handle GET /search?q=...&npub=...&limit=...
+async fn handle_search_request(Query(params): Query<SearchParams>) -> impl IntoResponse {
+    let worker_state = match crate::nip07::get_worker_state() {
+        Some(state) => state,
+        None => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "error": "Worker state not initialized" })),
+            );
+        }
+    };
+
+    let (status, body) = search_index_json(&worker_state.search, &params);
+    (status, Json(body))
+}
+
+/// Body of POST /extract: a page's HTML, or the htree path of an HTML file
+#[derive(Debug, Deserialize)]
+pub struct ExtractRequest {
+    html: Option<String>,
+    path: Option<String>,
+}
+
+/// Read an HTML file of a tree for extraction
+async fn read_html_file(state: &HtreeState, path: &str) -> Result<String, HtreeError> {
+    let path = path.trim_start_matches('/');
+    let path = path.strip_prefix("htree/").unwrap_or(path);
+    let resolved = resolve_htree_inner(state, path, None).await?;
+    if !matches!(
+        resolved.content_type.split(';').next().map(str::trim),
+        Some("text/html" | "application/xhtml+xml")
+    ) {
+        return Err(HtreeError::InvalidPath(format!(
+            "Not an HTML file: {}",
+            resolved.content_type
+        )));
+    }
+    let size = match resolved.size {
+        Some(size) => size,
+        None => state.get_file_size(&resolved.cid).await?,
+    };
+    if size > crate::reader::MAX_EXTRACT_BYTES as u64 {
+        return Err(HtreeError::InvalidPath(format!(
+            "HTML file too large to extract: {} bytes",
+            size
+        )));
+    }
+    let data = state.read_file(&resolved.cid).await?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Handle POST /extract with `{ html }` or `{ path }`
+async fn handle_extract_request(
+    State(state): State<HtreeState>,
+    Json(request): Json<ExtractRequest>,
+) -> Response {
+    let html = match (request.html, request.path) {
+        (Some(html), _) => html,
+        (None, Some(path)) => match read_html_file(&state, &path).await {
+            Ok(html) => html,
+            Err(e) => return e.into_response(),
+        },
+        (None, None) => {
+            return HtreeError::InvalidPath("Missing html or path".into()).into_response()
+        }
+    };
+    match tokio::task::spawn_blocking(move || crate::reader::extract(&html)).await {
+        Ok(article) => Json(article).into_response(),
+        Err(e) => HtreeError::Io(e.to_string()).into_response(),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct WebviewEventRequest {
@@ -953,6 +2104,25 @@ pub struct WebviewEventRequest {
     url: Option<String>,
     source: Option<String>,
     action: Option<String>,
+    /// Link and image, video or audio under a `contextmenu` event
+    link_url: Option<String>,
+    media_url: Option<String>,
+    /// Requests refused by the content blocker, for `blocked` events
+    count: Option<u64>,
+}
+
+/// Event asking the main window for a context menu of a child webview
+pub const CONTEXT_MENU_EVENT: &str = "child-webview-context-menu";
+
+fn emit_context_menu(app: &AppHandle, request: &WebviewEventRequest) {
+    let _ = app.emit(
+        CONTEXT_MENU_EVENT,
+        json!({
+            "label": request.label,
+            "linkUrl": request.link_url,
+            "mediaUrl": request.media_url
+        }),
+    );
 }
 
 async fn handle_webview_event(
@@ -998,6 +2168,12 @@ async fn handle_webview_event(
         }
     };
 
+    // A suspended webview reports its parked page
+    if crate::webviews::is_suspended(&request.label) {
+        return (StatusCode::OK, Json(json!({ "ok": true })));
+    }
+    crate::webviews::touch_webview(&request.label);
+
     match request.kind.as_str() {
         "location" => {
             let url = match request.url {
@@ -1038,6 +2214,11 @@ async fn handle_webview_event(
                 }),
             );
         }
+        "contextmenu" => emit_context_menu(&app_handle, &request),
+        "blocked" => {
+            let count = request.count.unwrap_or(1);
+            crate::content_blocking::report_blocked(&app_handle, &request.origin, count);
+        }
         _ => {
             return (
                 StatusCode::BAD_REQUEST,
@@ -1064,6 +2245,12 @@ pub async fn webview_event(
         return Err("Invalid session token".to_string());
     }
 
+    // A suspended webview reports its parked page
+    if crate::webviews::is_suspended(&payload.label) {
+        return Ok(());
+    }
+    crate::webviews::touch_webview(&payload.label);
+
     match payload.kind.as_str() {
         "location" => {
             let url = payload
@@ -1094,6 +2281,11 @@ pub async fn webview_event(
                 }),
             );
         }
+        "contextmenu" => emit_context_menu(&app, &payload),
+        "blocked" => {
+            let count = payload.count.unwrap_or(1);
+            crate::content_blocking::report_blocked(&app, &payload.origin, count);
+        }
         _ => {
             return Err("Invalid event kind".to_string());
         }
@@ -1102,15 +2294,198 @@ pub async fn webview_event(
     Ok(())
 }
 
+/// The target of an htree:// link or image, resolved for saving it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveTarget {
+    pub cid: WorkerCid,
+    /// Name to suggest in the save dialog
+    pub filename: String,
+    pub content_type: String,
+    pub size: Option<u64>,
+}
+
+/// Name to save a resolved file under: the nhash filename hint, or the last
+/// segment of its path
+fn save_filename(hint: Option<&str>, inner_path: &str) -> String {
+    hint.or_else(|| inner_path.rsplit('/').find(|s| !s.is_empty()))
+        .unwrap_or("download")
+        .to_string()
+}
+
+/// Resolve an htree:// URL, e.g. a link in a child webview, to the CID it
+/// points at
+pub async fn resolve_save_target(url: &tauri::Url) -> Result<SaveTarget, String> {
+    if url.scheme() != "htree" {
+        return Err(format!("Not an htree URL: {}", url));
+    }
+    let state = GLOBAL_HTREE_STATE
+        .get()
+        .ok_or_else(|| "htree state not initialized".to_string())?;
+    let path = resolve_htree_url_to_path(url.host_str().unwrap_or(""), url.path());
+    let link_secret = url
+        .query_pairs()
+        .find(|(name, _)| name == "k")
+        .map(|(_, value)| value);
+    let resolved = resolve_htree_inner(state, &path, link_secret.as_deref())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(SaveTarget {
+        cid: WorkerCid {
+            hash: to_hex(&resolved.cid.hash),
+            key: resolved.cid.key.map(|k| to_hex(&k)),
+        },
+        filename: save_filename(resolved.filename.as_deref(), &resolved.inner_path),
+        content_type: resolved.content_type,
+        size: resolved.size,
+    })
+}
+
+/// Tauri command resolving the htree:// URL of a link or image, so the save
+/// dialog can suggest its name
+#[tauri::command]
+pub async fn resolve_webview_target(url: String) -> Result<SaveTarget, String> {
+    let url = tauri::Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
+    resolve_save_target(&url).await
+}
+
+/// Tauri command saving a link or image of a child webview to `dest_path`
+/// through the download manager, reporting progress under `id`
+#[tauri::command]
+pub async fn save_webview_target(
+    app: tauri::AppHandle,
+    id: String,
+    url: String,
+    dest_path: String,
+) -> Result<(), String> {
+    let url = tauri::Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
+    let dest = PathBuf::from(&dest_path);
+    match url.scheme() {
+        "htree" => {
+            let target = resolve_save_target(&url).await?;
+            let state = app
+                .try_state::<Arc<WorkerState>>()
+                .ok_or("WorkerState not found")?;
+            let stats = state
+                .download_to_disk(&app, &id, &target.cid, &dest)
+                .await?;
+            info!("Saved {} ({} bytes) to {}", url, stats.bytes, dest_path);
+        }
+        "http" | "https" => {
+            let client = proxy::apply(reqwest::Client::builder(), Component::Web)
+                .connect_timeout(Duration::from_secs(30))
+                .build()
+                .map_err(|e| format!("HTTP client error: {}", e))?;
+            let response = client
+                .get(url.clone())
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| format!("Failed to fetch {}: {}", url, e))?;
+            let state = app
+                .try_state::<Arc<WorkerState>>()
+                .ok_or("WorkerState not found")?;
+            let stats = state
+                .download_web_to_disk(&app, &id, response, &dest)
+                .await?;
+            info!("Saved {} ({} bytes) to {}", url, stats.bytes, dest_path);
+        }
+        scheme => return Err(format!("Can't save {} URLs", scheme)),
+    }
+    Ok(())
+}
+
 /// Start the htree HTTP server
 /// Returns the port number the server is listening on
 /// data_dir is the Tauri app data directory where blobs are stored
 pub async fn start_server(data_dir: PathBuf) -> Result<u16, HtreeError> {
-    // Bind to a fixed port on localhost for predictable URL
-    let listener = TcpListener::bind(("127.0.0.1", 21417))
-        .await
+    // Bind to the configured address; beyond localhost only when the ACL
+    // enables LAN access or names another address
+    let listener = bind_with_fallback(crate::acl::bind_addr()).await?;
+    let port = start_server_with_listener(data_dir.clone(), listener).await?;
+
+    // LAN clients over HTTPS only, if so configured; the app keeps plain
+    // HTTP on loopback
+    if let Some(addr) = crate::acl::tls_addr() {
+        if let Err(e) = start_tls_server(data_dir, addr).await {
+            error!("Failed to start HTTPS listener: {}", e);
+        }
+    }
+    Ok(port)
+}
+
+/// Serve LAN clients over HTTPS at `addr`, with a certificate of our local CA
+async fn start_tls_server(data_dir: PathBuf, addr: SocketAddr) -> Result<(), HtreeError> {
+    let listener = bind_with_fallback(addr).await?;
+    let bound = listener
+        .local_addr()
         .map_err(|e| HtreeError::Io(e.to_string()))?;
-    start_server_with_listener(data_dir, listener).await
+    // A dual-stack `::` listener is reached over both families
+    let lan_ips = if bound.ip().is_unspecified() {
+        crate::tls::lan_ips(bound.is_ipv6())
+    } else {
+        vec![bound.ip()]
+    };
+
+    let ca = crate::tls::local_ca(&data_dir).map_err(HtreeError::Io)?;
+    let cert = ca.issue(&lan_ips).map_err(HtreeError::Io)?;
+    let config = axum_server::tls_rustls::RustlsConfig::from_pem(
+        cert.chain_pem.into_bytes(),
+        cert.key_pem.into_bytes(),
+    )
+    .await
+    .map_err(|e| HtreeError::Io(format!("Invalid TLS certificate: {}", e)))?;
+
+    let host = lan_ips.first().copied().unwrap_or(bound.ip());
+    crate::tls::announce(crate::tls::TlsInfo {
+        url: format!("https://{}", SocketAddr::new(host, bound.port())),
+        fingerprint: crate::tls::fingerprint(ca.pem()).map_err(HtreeError::Io)?,
+        ca_pem: ca.pem().to_string(),
+    });
+
+    let listener = listener
+        .into_std()
+        .map_err(|e| HtreeError::Io(e.to_string()))?;
+    let app = router(data_dir).into_make_service_with_connect_info::<SocketAddr>();
+    tokio::spawn(async move {
+        if let Err(e) = axum_server::from_tcp_rustls(listener, config)
+            .serve(app)
+            .await
+        {
+            error!("htree HTTPS server error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Ports after the configured one tried when it's taken
+const PORT_FALLBACKS: u16 = 10;
+
+/// Bind to `addr`, or if its port is taken to one of the next ports. Never
+/// to a random one: the port is part of the origin webview storage and
+/// paired devices are tied to, so keeping near it beats silently moving.
+async fn bind_with_fallback(addr: SocketAddr) -> Result<TcpListener, HtreeError> {
+    let mut last_error = None;
+    let ports = (0..=PORT_FALLBACKS).filter_map(|i| addr.port().checked_add(i));
+    for port in ports {
+        match TcpListener::bind(SocketAddr::new(addr.ip(), port)).await {
+            Ok(listener) => {
+                if port != addr.port() {
+                    warn!(
+                        "Port {} is taken, listening on {} instead",
+                        addr.port(),
+                        port
+                    );
+                }
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => last_error = Some(e),
+            Err(e) => return Err(HtreeError::Io(format!("Failed to bind {}: {}", addr, e))),
+        }
+    }
+    let error = last_error.map(|e| e.to_string()).unwrap_or_default();
+    let message = format!("Failed to bind {}: {}", addr, error);
+    Err(HtreeError::Io(message))
 }
 
 /// Start the htree HTTP server on a specific port (use 0 for ephemeral)
@@ -1125,6 +2500,30 @@ async fn start_server_with_listener(
     data_dir: PathBuf,
     listener: TcpListener,
 ) -> Result<u16, HtreeError> {
+    let app = router(data_dir);
+
+    let addr = listener
+        .local_addr()
+        .map_err(|e| HtreeError::Io(e.to_string()))?;
+
+    let port = addr.port();
+    SERVER_ADDR.set(addr).ok();
+
+    info!("htree server listening on http://{}", addr);
+
+    // Spawn the server in the background
+    tokio::spawn(async move {
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("htree server error: {}", e);
+        }
+    });
+
+    Ok(port)
+}
+
+/// Routes of the server, with the ACL and rate limits
+fn router(data_dir: PathBuf) -> Router {
     let state = GLOBAL_HTREE_STATE
         .get_or_init(|| HtreeState::new(data_dir.clone()))
         .clone();
@@ -1139,14 +2538,24 @@ async fn start_server_with_listener(
             header::CONTENT_RANGE,
             header::CONTENT_LENGTH,
             header::CONTENT_TYPE,
+            HeaderName::from_static(SIGNATURE_HEADER),
+            HeaderName::from_static(REQUEST_ID_HEADER),
         ]);
 
-    // Create relay proxy state
-    let relay_state = RelayProxyState::new();
+    let relay_state = GLOBAL_RELAY_PROXY_STATE
+        .get_or_init(RelayProxyState::new)
+        .clone();
 
     // Build the combined app with htree, relay, and nip07 routes
     let htree_router = Router::new()
         .route("/htree/{*path}", get(handle_htree_request))
+        .route("/hls/{name}", get(handle_hls_segment))
+        .route("/profile/{npub}/picture", get(handle_profile_picture))
+        .route(
+            "/extract",
+            post(handle_extract_request)
+                .layer(DefaultBodyLimit::max(crate::reader::MAX_EXTRACT_BYTES)),
+        )
         .with_state(state);
 
     let relay_router = Router::new()
@@ -1155,38 +2564,37 @@ async fn start_server_with_listener(
 
     let nip07_router = Router::new().route("/nip07", post(handle_nip07_request));
     let webview_router = Router::new().route("/webview", post(handle_webview_event));
+    let search_router = Router::new().route("/search", get(handle_search_request));
+    let pairing_router = Router::new()
+        .route("/pair", post(crate::pairing::handle_pair))
+        .route("/pair/ca", get(crate::pairing::handle_pair_ca));
+    let remote_router = Router::new().route(
+        crate::remote::REMOTE_PATH,
+        post(crate::remote::handle_remote_request)
+            .layer(DefaultBodyLimit::max(crate::remote::MAX_REQUEST_BYTES)),
+    );
 
-    let app = htree_router
+    let web_router = htree_router
         .merge(relay_router)
         .merge(nip07_router)
         .merge(webview_router)
+        .merge(search_router)
+        .merge(pairing_router)
+        .layer(middleware::from_fn(rate_limit_middleware))
+        .layer(middleware::from_fn(acl_middleware))
         .layer(cors);
+    // Paired devices aren't web pages, so the remote API gets no CORS
+    let remote_router = remote_router
+        .layer(middleware::from_fn(rate_limit_middleware))
+        .layer(middleware::from_fn(acl_middleware));
 
-    let addr = listener
-        .local_addr()
-        .map_err(|e| HtreeError::Io(e.to_string()))?;
-
-    let port = addr.port();
-    SERVER_PORT.set(port).ok();
-
-    info!("htree server listening on http://127.0.0.1:{}", port);
-
-    // Spawn the server in the background
-    tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, app).await {
-            error!("htree server error: {}", e);
-        }
-    });
-
-    Ok(port)
+    web_router.merge(remote_router)
 }
 
-/// Tauri command to get the htree server URL
-/// Returns the custom protocol URL for htree:// scheme
+/// Tauri command to get the htree server URL, as actually bound
 #[tauri::command]
 pub fn get_htree_server_url() -> Option<String> {
-    // Return fixed HTTP server URL - bind uses IPv4 loopback
-    Some("http://127.0.0.1:21417".to_string())
+    server_url()
 }
 
 /// Cache tree roots from the frontend for faster /thumbnail resolution.
@@ -1215,11 +2623,32 @@ pub fn cache_tree_root(
 // Global state for URI scheme protocol handler
 static GLOBAL_HTREE_STATE: once_cell::sync::OnceCell<HtreeState> = once_cell::sync::OnceCell::new();
 
+static GLOBAL_RELAY_PROXY_STATE: once_cell::sync::OnceCell<RelayProxyState> =
+    once_cell::sync::OnceCell::new();
+
 /// Initialize the global htree state for the URI scheme protocol
 pub fn init_htree_state(data_dir: PathBuf) {
     let _ = GLOBAL_HTREE_STATE.get_or_init(|| HtreeState::new(data_dir));
 }
 
+/// Reconnect the server's resolver, Blossom and relay proxy clients through
+/// the current proxy
+pub async fn apply_proxy() {
+    if let Some(state) = GLOBAL_HTREE_STATE.get() {
+        state.apply_proxy().await;
+    }
+    if let Some(relay_state) = GLOBAL_RELAY_PROXY_STATE.get() {
+        relay_state.apply_proxy().await;
+    }
+}
+
+/// Serve blobs from a guest session's dir, or from the data dir again when None
+pub fn set_guest_dir(dir: Option<&Path>) {
+    if let Some(state) = GLOBAL_HTREE_STATE.get() {
+        *state.guest_store.write() = dir.map(open_store);
+    }
+}
+
 /// Handle NIP-07 requests via htree://nip07/ protocol
 /// This allows HTTPS child webviews to use window.nostr without mixed content issues
 fn handle_nip07_protocol_request(
@@ -1272,15 +2701,38 @@ fn handle_nip07_protocol_request(
         }
     };
 
-    // Get NIP-07 state for permissions (optional)
-    let nip07_state = crate::nip07::get_nip07_state();
-    let permissions = nip07_state.as_ref().map(|s| &*s.permissions);
+    // The claimed origin must be the page's: only its session has the token
+    let Some(nip07_state) = crate::nip07::get_nip07_state() else {
+        return tauri::http::Response::builder()
+            .status(500)
+            .header("content-type", "application/json")
+            .header("access-control-allow-origin", "*")
+            .body(b"{\"error\":\"NIP-07 state not initialized\"}".to_vec())
+            .unwrap();
+    };
+    let token = nip07_request.session_token.as_deref().unwrap_or("");
+    if !nip07_state.allows_nostr(&nip07_request.origin, token) {
+        warn!(
+            "[htree://nip07] Refused request from {}: no session with window.nostr",
+            nip07_request.origin
+        );
+        let response = crate::nip07::Nip07Response {
+            result: None,
+            error: Some("Invalid session token".to_string()),
+        };
+        return tauri::http::Response::builder()
+            .status(403)
+            .header("content-type", "application/json")
+            .header("access-control-allow-origin", "*")
+            .body(serde_json::to_vec(&response).unwrap_or_default())
+            .unwrap();
+    }
 
     // Process the request
     let response = tauri::async_runtime::block_on(async {
         crate::nip07::handle_nip07_request(
             &worker_state,
-            permissions,
+            Some(&nip07_state.permissions),
             &nip07_request.method,
             &nip07_request.params,
             &nip07_request.origin,
@@ -1302,6 +2754,13 @@ fn handle_nip07_protocol_request(
         .unwrap()
 }
 
+/// CSP of the [`WebviewPolicy`](crate::nip07::WebviewPolicy) of htree
+/// webview `label`, if it restricts the network
+fn webview_csp(label: &str) -> Option<String> {
+    let policy = crate::nip07::get_nip07_state()?.policy(label)?;
+    policy.csp(&get_htree_server_url()?)
+}
+
 /// Handle htree:// URI scheme protocol requests
 /// This is called by Tauri's register_uri_scheme_protocol
 ///
@@ -1316,7 +2775,7 @@ fn handle_nip07_protocol_request(
 ///    - htree:///htree/nhash1abc.../path
 ///    - htree:///htree/npub1xyz/treename/path
 pub fn handle_htree_protocol<R: tauri::Runtime>(
-    _ctx: tauri::UriSchemeContext<'_, R>,
+    ctx: tauri::UriSchemeContext<'_, R>,
     request: tauri::http::Request<Vec<u8>>,
 ) -> tauri::http::Response<Vec<u8>> {
     let uri = request.uri();
@@ -1350,6 +2809,9 @@ pub fn handle_htree_protocol<R: tauri::Runtime>(
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string());
 
+    let id = next_request_id();
+    let span = info_span!("htree", id, path = %path);
+    let _enter = span.enter();
     info!("htree:// protocol request: raw_path={}, path={}", raw_path, path);
 
     // Get global state
@@ -1363,24 +2825,54 @@ pub fn handle_htree_protocol<R: tauri::Runtime>(
         }
     };
 
+    let link_secret = resolved_path
+        .split_once('?')
+        .and_then(|(_, query)| query_param(Some(query), "k"));
+
     // Use tokio runtime to run async code with efficient range support
     let result = tauri::async_runtime::block_on(async {
         // First resolve the path to get CID and mime type (without loading file content)
-        let (file_cid, content_type) = resolve_htree_inner(state, path).await?;
+        let resolved = resolve_htree_inner(state, path, link_secret).await?;
 
         let (data, range_info) =
-            read_range_or_full(state, &file_cid, range_header.as_deref()).await?;
-        Ok((content_type, data, range_info))
+            read_range_or_full(state, &resolved.cid, range_header.as_deref()).await?;
+        let csp = content_security_policy(resolved.sandboxed, &resolved.content_type);
+        Ok((
+            resolved.content_type,
+            resolved.filename,
+            data,
+            range_info,
+            resolved.signature,
+            resolved.untrusted,
+            csp,
+        ))
     });
 
     match result {
-        Ok((content_type, data, range_info)) => {
+        Ok((content_type, filename, data, range_info, signature, untrusted, csp)) => {
+            let mut builder = tauri::http::Response::builder().header(REQUEST_ID_HEADER, id);
+            if let Some(filename) = filename {
+                builder = builder.header("content-disposition", content_disposition(&filename));
+            }
+            if let Some(signature) = signature {
+                builder = builder.header(SIGNATURE_HEADER, signature.as_str());
+            }
+            if untrusted {
+                builder = builder.header(WOT_HEADER, "untrusted");
+            }
+            if let Some(csp) = csp {
+                builder = builder.header("content-security-policy", csp);
+            }
+            // The webview's own policy, enforced on top of the content's
+            if let Some(csp) = webview_csp(ctx.webview_label()) {
+                builder = builder.header("content-security-policy", csp);
+            }
             if let Some((start, end, total_size)) = range_info {
                 let content_length = data.len();
                 let content_range = format!("bytes {}-{}/{}", start, end, total_size);
                 info!("htree:// protocol 206 response: range={}", content_range);
 
-                return tauri::http::Response::builder()
+                return builder
                     .status(206)
                     .header("content-type", content_type)
                     .header("content-length", content_length.to_string())
@@ -1393,7 +2885,7 @@ pub fn handle_htree_protocol<R: tauri::Runtime>(
             info!("htree:// protocol success: path={}, content_type={}, size={}", path, content_type, data.len());
 
             // Full response
-            tauri::http::Response::builder()
+            builder
                 .status(200)
                 .header("content-type", content_type)
                 .header("content-length", data.len().to_string())
@@ -1406,6 +2898,8 @@ pub fn handle_htree_protocol<R: tauri::Runtime>(
             let (status, message) = match &e {
                 HtreeError::FileNotFound(msg) | HtreeError::TreeNotFound(msg) => (404, msg.clone()),
                 HtreeError::InvalidPath(msg) => (400, msg.clone()),
+                HtreeError::BadSignature(_) => (502, e.to_string()),
+                HtreeError::Muted(_) => (403, e.to_string()),
                 _ => (500, e.to_string()),
             };
             tauri::http::Response::builder()
@@ -1423,12 +2917,71 @@ mod tests {
     use hashtree_core::{DirEntry, HashTree, HashTreeConfig, LinkType, nhash_encode};
     use tempfile::tempdir;
 
+    #[test]
+    fn revalidation_replaces_outdated_public_roots() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let old = Cid {
+            hash: [1; 32],
+            key: None,
+        };
+        let new = Cid {
+            hash: [2; 32],
+            key: None,
+        };
+        state.cache_root("npub1a", "docs", old.clone(), TreeVisibility::Public);
+        state.cache_root("npub1a", "secret", old.clone(), TreeVisibility::Private);
+        // Just resolved
+        assert!(state.claim_revalidation("npub1a", "docs").is_none());
+
+        // The monotonic clock may not go back that far right after boot
+        let Some(outdated) = Instant::now().checked_sub(REVALIDATE_AFTER) else {
+            return;
+        };
+        for key in ["npub1a/docs", "npub1a/secret"] {
+            let mut cache = state.root_cache.write();
+            cache.peek_mut(key).unwrap().timestamp = outdated;
+        }
+        assert_eq!(
+            state.claim_revalidation("npub1a", "docs"),
+            Some(old.clone())
+        );
+        assert!(state.claim_revalidation("npub1a", "docs").is_none());
+        assert!(state.claim_revalidation("npub1a", "secret").is_none());
+
+        assert!(state
+            .apply_revalidated("npub1a", "docs", &old, old.clone(), 100)
+            .is_none());
+        assert_eq!(state.root_created_at("npub1a", "docs"), Some(100));
+        let update = state
+            .apply_revalidated("npub1a", "docs", &old, new.clone(), 200)
+            .expect("root changed");
+        assert_eq!(update.previous_hash, to_hex(&old.hash));
+        let payload = serde_json::to_value(&update).unwrap();
+        assert_eq!(payload["treeName"], "docs");
+        assert_eq!(payload["hash"], to_hex(&new.hash));
+        assert_eq!(
+            state.root_cache.read().peek("npub1a/docs").unwrap().cid,
+            new
+        );
+        assert_eq!(state.root_created_at("npub1a", "docs"), Some(200));
+
+        // A late result for an older root doesn't roll the cache back
+        let late = Cid {
+            hash: [3; 32],
+            key: None,
+        };
+        assert!(state
+            .apply_revalidated("npub1a", "docs", &old, late, 150)
+            .is_none());
+    }
+
     #[tokio::test]
     async fn resolve_nhash_uses_filename_path() {
         let dir = tempdir().expect("tempdir should work");
         let state = HtreeState::new(dir.path().to_path_buf());
 
-        let tree = HashTree::new(HashTreeConfig::new(state.store.clone()).public());
+        let tree = HashTree::new(HashTreeConfig::new(state.store()).public());
         let data = b"<html>ok</html>";
         let (file_cid, size) = tree.put(data).await.expect("put should work");
 
@@ -1441,13 +2994,446 @@ mod tests {
             .expect("put_directory should work");
 
         let nhash = nhash_encode(&dir_cid.hash).expect("nhash should encode");
-        let (resolved, mime_type) = state
+        let resolved = state
             .resolve_nhash(&nhash, Some("index.html"))
             .await
             .expect("resolve_nhash should work");
 
-        assert_eq!(resolved.hash, file_cid.hash);
-        assert_eq!(mime_type, "text/html");
+        assert_eq!(resolved.cid.hash, file_cid.hash);
+        assert_eq!(resolved.content_type, "text/html");
+        assert_eq!(resolved.inner_path, "index.html");
+
+        // The entry's Merkle proof checks out against the nhash root
+        let proof = tree
+            .prove(&resolved.root, &resolved.inner_path)
+            .await
+            .expect("prove should work");
+        assert_eq!(
+            hashtree_core::verify_proof(&dir_cid, &proof)
+                .await
+                .expect("proof should verify"),
+            file_cid
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_nhash_uses_hints_of_its_target() {
+        use hashtree_core::{nhash_encode_full, NHashData};
+
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let tree = HashTree::new(HashTreeConfig::new(state.store()).public());
+        let (file_cid, size) = tree.put(b"%PDF-1.7").await.expect("put should work");
+
+        let hinted = |cid: &Cid| {
+            nhash_encode_full(&NHashData {
+                hash: cid.hash,
+                size: Some(size),
+                mime_type: Some("application/pdf".to_string()),
+                filename: Some("Q3 report.pdf".to_string()),
+                ..Default::default()
+            })
+            .expect("nhash should encode")
+        };
+        let resolved = state
+            .resolve_nhash(&hinted(&file_cid), Some("download"))
+            .await
+            .expect("resolve_nhash should work");
+        assert_eq!(resolved.size, Some(size));
+        assert_eq!(resolved.content_type, "application/pdf");
+        assert_eq!(resolved.filename.as_deref(), Some("Q3 report.pdf"));
+
+        // A path below the nhash is another file: its hints don't apply
+        let entry = DirEntry::from_cid("notes.txt", &file_cid)
+            .with_size(size)
+            .with_link_type(LinkType::Blob);
+        let dir_cid = tree.put_directory(vec![entry]).await.unwrap();
+        let resolved = state
+            .resolve_nhash(&hinted(&dir_cid), Some("notes.txt"))
+            .await
+            .expect("resolve_nhash should work");
+        assert_eq!(resolved.cid.hash, file_cid.hash);
+        assert_eq!((resolved.size, resolved.filename), (None, None));
+        assert_eq!(resolved.content_type, "text/plain");
+    }
+
+    #[tokio::test]
+    async fn range_reads_ignore_the_size_hint() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let tree = HashTree::new(HashTreeConfig::new(state.store()).public());
+        let (file_cid, _) = tree.put(b"0123456789").await.expect("put should work");
+
+        // A link claiming a larger file can't stretch the range past the data
+        let nhash = hashtree_core::nhash_encode_full(&hashtree_core::NHashData {
+            hash: file_cid.hash,
+            size: Some(1_000_000),
+            ..Default::default()
+        })
+        .expect("nhash should encode");
+        let resolved = state
+            .resolve_nhash(&nhash, None)
+            .await
+            .expect("resolve_nhash should work");
+        let (data, range) = read_range_or_full(&state, &resolved.cid, Some("bytes=4-"))
+            .await
+            .expect("range read should work");
+        assert_eq!(data, b"456789");
+        assert_eq!(range, Some((4, 9, 10)));
+    }
+
+    #[test]
+    fn untrusted_documents_get_sandbox_csp() {
+        assert_eq!(
+            content_security_policy(true, "text/html"),
+            Some(SANDBOX_CSP)
+        );
+        assert_eq!(
+            content_security_policy(true, "Image/SVG+XML; charset=utf-8"),
+            Some(SANDBOX_CSP)
+        );
+        assert_eq!(content_security_policy(true, "image/png"), None);
+        assert_eq!(content_security_policy(false, "text/html"), None);
+        // One header value, without the source's line breaks
+        assert!(HeaderValue::from_static(SANDBOX_CSP).to_str().is_ok());
+        assert!(!SANDBOX_CSP.contains("  "));
+        assert!(!SANDBOX_CSP.contains("allow-same-origin"));
+        assert!(!SANDBOX_CSP.contains("allow-top-navigation"));
+    }
+
+    #[tokio::test]
+    async fn resolve_refuses_escaping_paths() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let npub = format!("npub1{}", "q".repeat(58));
+        let deep = ["d"; hashtree_gateway::MAX_PATH_SEGMENTS + 1].join("/");
+        for path in [
+            format!("{}/site/../other/index.html", npub),
+            format!("{}/site/%2e%2e/index.html", npub),
+            format!("{}/..%2Fsite/index.html", npub),
+            format!("{}/site/index.html%0A", npub),
+            format!("{}/site/{}", npub, deep),
+            "nhash1qqqq/..%2Fsecret".to_string(),
+        ] {
+            assert!(
+                matches!(
+                    resolve_htree_inner(&state, &path, None).await,
+                    Err(HtreeError::InvalidPath(_))
+                ),
+                "{} should be refused",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn head_with_hints_skips_fetching() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        // Not in any store: only the hints can answer
+        let resolved = Resolved {
+            cid: Cid {
+                hash: [7; 32],
+                key: None,
+            },
+            content_type: "video/mp4".to_string(),
+            size: Some(1234),
+            filename: Some("clip.mp4".to_string()),
+            root: Cid {
+                hash: [7; 32],
+                key: None,
+            },
+            inner_path: String::new(),
+            signature: None,
+            untrusted: false,
+            sandboxed: false,
+            last_modified: None,
+        };
+        let uri = OriginalUri("/htree/nhash1x/clip.mp4".parse().unwrap());
+        let response = serve_resolved(
+            &state,
+            &Method::HEAD,
+            &HeaderMap::new(),
+            &uri,
+            "nhash1x/clip.mp4",
+            resolved,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_LENGTH], "1234");
+        assert_eq!(headers[header::CONTENT_TYPE], "video/mp4");
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            "inline; filename=\"clip.mp4\"; filename*=UTF-8''clip.mp4"
+        );
+    }
+
+    #[tokio::test]
+    async fn npub_files_revalidate_against_root_event_time() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let cid = Cid {
+            hash: [7; 32],
+            key: None,
+        };
+        let head = |if_modified_since: Option<&'static str>| {
+            let resolved = Resolved {
+                cid: cid.clone(),
+                content_type: "text/html".to_string(),
+                size: Some(15),
+                filename: None,
+                root: cid.clone(),
+                inner_path: "index.html".to_string(),
+                signature: Some(SignatureStatus::Unsigned),
+                untrusted: false,
+                sandboxed: false,
+                // 2023-11-14T22:13:20Z
+                last_modified: Some(1_700_000_000),
+            };
+            let mut headers = HeaderMap::new();
+            if let Some(date) = if_modified_since {
+                headers.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_static(date));
+            }
+            let state = state.clone();
+            async move {
+                let uri = OriginalUri("/htree/npub1a/docs/index.html".parse().unwrap());
+                serve_resolved(
+                    &state,
+                    &Method::HEAD,
+                    &headers,
+                    &uri,
+                    "npub1a/docs/index.html",
+                    resolved,
+                )
+                .await
+            }
+        };
+
+        let response = head(None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        let response = head(Some("Tue, 14 Nov 2023 22:13:20 GMT")).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+        // Republished since the client's copy
+        let response = head(Some("Tue, 14 Nov 2023 22:00:00 GMT")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = head(Some("not a date")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The frontend caching the same root keeps the event time
+        state.cache_root("npub1a", "docs", cid.clone(), TreeVisibility::Public);
+        {
+            let mut cache = state.root_cache.write();
+            cache.peek_mut("npub1a/docs").unwrap().created_at = Some(5);
+        }
+        state.cache_root("npub1a", "docs", cid.clone(), TreeVisibility::Public);
+        assert_eq!(state.root_created_at("npub1a", "docs"), Some(5));
+        let other = Cid {
+            hash: [8; 32],
+            key: None,
+        };
+        state.cache_root("npub1a", "docs", other, TreeVisibility::Public);
+        assert_eq!(state.root_created_at("npub1a", "docs"), None);
+    }
+
+    #[tokio::test]
+    async fn verify_root_checks_manifest_signature() {
+        use hashtree_gateway::manifest::{manifest_digest, sign_manifest, MANIFEST_FILENAME};
+        use nostr_sdk::ToBech32;
+
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let tree = HashTree::new(HashTreeConfig::new(state.store()));
+
+        let owner = Keys::generate();
+        let npub = owner.public_key().to_bech32().unwrap();
+        let (file_cid, size) = tree.put(b"<html>ok</html>").await.unwrap();
+        let index = DirEntry::from_cid("index.html", &file_cid)
+            .with_size(size)
+            .with_link_type(LinkType::Blob);
+        let unsigned = tree.put_directory(vec![index.clone()]).await.unwrap();
+        assert_eq!(
+            state.verify_root(&npub, &unsigned).await.unwrap(),
+            SignatureStatus::Unsigned
+        );
+
+        let entries = tree.list_directory(&unsigned).await.unwrap();
+        let manifest = sign_manifest(&owner, &manifest_digest(&entries).unwrap()).unwrap();
+        let (manifest_cid, manifest_size) = tree.put(&manifest).await.unwrap();
+        let manifest_entry = DirEntry::from_cid(MANIFEST_FILENAME, &manifest_cid)
+            .with_size(manifest_size)
+            .with_link_type(LinkType::Blob);
+        let signed = tree
+            .put_directory(vec![index, manifest_entry.clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            state.verify_root(&npub, &signed).await.unwrap(),
+            SignatureStatus::Verified
+        );
+
+        // Same manifest next to different content, or claimed by another npub
+        let (other_cid, other_size) = tree.put(b"<html>evil</html>").await.unwrap();
+        let swapped = DirEntry::from_cid("index.html", &other_cid)
+            .with_size(other_size)
+            .with_link_type(LinkType::Blob);
+        let substituted = tree
+            .put_directory(vec![swapped, manifest_entry])
+            .await
+            .unwrap();
+        assert_eq!(
+            state.verify_root(&npub, &substituted).await.unwrap(),
+            SignatureStatus::Invalid
+        );
+        let other_npub = Keys::generate().public_key().to_bech32().unwrap();
+        assert_eq!(
+            state.verify_root(&other_npub, &signed).await.unwrap(),
+            SignatureStatus::Invalid
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_nip19_refuses_other_links() {
+        use nostr_sdk::nips::nip01::Coordinate;
+
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+        let keys = Keys::generate();
+
+        // An address of another kind, or a tree root address without a name
+        let long_form = Coordinate::new(Kind::LongFormTextNote, keys.public_key())
+            .identifier("site")
+            .to_bech32()
+            .unwrap();
+        let unnamed = Coordinate::new(Kind::from(KIND_TREE_ROOT), keys.public_key())
+            .to_bech32()
+            .unwrap();
+        let npub = keys.public_key().to_bech32().unwrap();
+        for identifier in [long_form, unnamed, npub] {
+            let result = state.resolve_nip19(&identifier).await;
+            assert!(matches!(result, Err(HtreeError::InvalidPath(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_htree_inner_validates_link_secret() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        let result =
+            resolve_htree_inner(&state, &format!("{}/shared/a.txt", npub), Some("not-hex")).await;
+        assert!(matches!(result, Err(HtreeError::InvalidPath(_))));
+    }
+
+    #[tokio::test]
+    async fn link_visible_roots_are_cached_by_secret() {
+        let dir = tempdir().expect("tempdir should work");
+        let state = HtreeState::new(dir.path().to_path_buf());
+
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        let secret = [7u8; 32];
+        let root = Cid {
+            hash: [1u8; 32],
+            key: Some([2u8; 32]),
+        };
+        state.root_cache.write().put(
+            link_cache_key(&format!("{}/shared", npub), &secret),
+            CachedRoot {
+                cid: root.clone(),
+                visibility: TreeVisibility::LinkVisible,
+                timestamp: std::time::Instant::now(),
+                created_at: None,
+            },
+        );
+
+        let resolved = state
+            .resolve_tree(npub, "shared", Some(&secret), None)
+            .await
+            .expect("cached with the secret");
+        assert_eq!(resolved.hash, root.hash);
+        assert_eq!(resolved.key, root.key);
+
+        // Nothing under the plain key, and another secret doesn't match
+        assert!(state
+            .root_cache
+            .read()
+            .peek(&format!("{}/shared", npub))
+            .is_none());
+        assert!(state
+            .root_cache
+            .read()
+            .peek(&link_cache_key(&format!("{}/shared", npub), &[8u8; 32]))
+            .is_none());
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param(Some("format=hls"), "format"), Some("hls"));
+        assert_eq!(query_param(Some("a=1&format=hls&b"), "format"), Some("hls"));
+        assert_eq!(query_param(Some("a=1&b"), "b"), Some(""));
+        assert_eq!(query_param(Some("a=1"), "format"), None);
+        assert_eq!(query_param(None, "format"), None);
+    }
+
+    #[test]
+    fn test_search_index_json() {
+        let dir = tempdir().expect("tempdir should work");
+        let index = crate::worker::SearchIndex::new(dir.path()).expect("index should open");
+        index
+            .replace_tree(
+                "npub1a",
+                "public",
+                vec![crate::worker::search::SearchEntry {
+                    npub: "npub1a".to_string(),
+                    tree_name: "public".to_string(),
+                    path: "docs/readme.md".to_string(),
+                    name: "readme.md".to_string(),
+                    hash: "a".repeat(64),
+                    size: 5,
+                    is_dir: false,
+                    text: None,
+                    media: None,
+                }],
+            )
+            .expect("replace_tree should work");
+
+        let params = SearchParams {
+            q: Some("readme".to_string()),
+            npub: None,
+            limit: None,
+        };
+        let (status, body) = search_index_json(&index, &params);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["hits"][0]["path"], "docs/readme.md");
+        assert_eq!(body["hits"][0]["treeName"], "public");
+
+        let params = SearchParams {
+            q: Some("readme".to_string()),
+            npub: Some("npub1other".to_string()),
+            limit: None,
+        };
+        let (_, body) = search_index_json(&index, &params);
+        assert!(body["hits"].as_array().unwrap().is_empty());
+
+        let params = SearchParams {
+            q: Some("  ".to_string()),
+            npub: None,
+            limit: None,
+        };
+        let (status, _) = search_index_json(&index, &params);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
     }
 
     #[test]
@@ -1494,4 +3480,22 @@ mod tests {
         let path = resolve_htree_url_to_path("", "/htree/npub1abc/treename/index.html");
         assert_eq!(path, "npub1abc/treename/index.html");
     }
+
+    #[test]
+    fn save_filename_prefers_the_nhash_hint() {
+        assert_eq!(save_filename(Some("photo.jpg"), "img/1"), "photo.jpg");
+        assert_eq!(save_filename(None, "docs/img/cat.png"), "cat.png");
+        assert_eq!(save_filename(None, "docs/"), "docs");
+        assert_eq!(save_filename(None, ""), "download");
+    }
+
+    #[tokio::test]
+    async fn bind_falls_back_to_next_free_port() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = taken.local_addr().unwrap();
+        let listener = bind_with_fallback(addr).await.unwrap();
+        let bound = listener.local_addr().unwrap();
+        assert_eq!(bound.ip(), addr.ip());
+        assert_ne!(bound.port(), addr.port());
+    }
 }
@@ -1,14 +1,35 @@
+pub mod acl;
+pub mod atomic_file;
+pub mod bookmarks;
+pub mod content_blocking;
+pub mod deep_link;
+pub mod filter_proxy;
 pub mod history;
 pub mod htree;
+pub mod markdown;
 pub mod nip07;
+pub mod pairing;
 pub mod permissions;
+pub mod profile_picture;
+pub mod proxy;
+pub mod rate_limit;
+pub mod reader;
 pub mod relay_proxy;
+pub mod remote;
+pub mod tls;
+pub mod tracks;
+pub mod transcode;
+#[cfg(any(target_os = "macos", windows, target_os = "linux"))]
+pub mod tray;
+pub mod webview_data;
+pub mod webviews;
 pub mod worker;
 
 use tauri::menu::{MenuBuilder, MenuItemBuilder, SubmenuBuilder};
 use tauri::{Emitter, Manager};
 use std::path::PathBuf;
 use tracing::info;
+use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::EnvFilter;
 
 #[cfg(test)]
@@ -78,15 +99,34 @@ fn build_menu<R: tauri::Runtime>(app: &tauri::AppHandle<R>) -> tauri::Result<tau
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    // Initialize tracing with env filter (RUST_LOG=iris=debug)
-    tracing_subscriber::fmt()
+    // Initialize tracing with env filter (RUST_LOG=iris=debug). Spans log
+    // their timing when they close; IRIS_LOG_FORMAT=json writes JSON lines.
+    let subscriber = tracing_subscriber::fmt()
         .with_env_filter(
             EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| EnvFilter::new("iris=info")),
         )
-        .init();
+        .with_span_events(FmtSpan::CLOSE);
+    if std::env::var("IRIS_LOG_FORMAT").as_deref() == Ok("json") {
+        subscriber.json().with_span_list(true).init();
+    } else {
+        subscriber.init();
+    }
+
+    let builder = tauri::Builder::default();
+
+    // A second launch (e.g. by a deep link on Windows/Linux) hands its URL
+    // to the running app instead of starting another one
+    #[cfg(any(target_os = "macos", windows, target_os = "linux"))]
+    let builder = builder.plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
+        if let Some(window) = app.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }));
 
-    tauri::Builder::default()
+    builder
         .menu(build_menu)
         .on_menu_event(|app, event| {
             match event.id().as_ref() {
@@ -115,19 +155,58 @@ pub fn run() {
         .plugin(tauri_plugin_os::init())
         .register_uri_scheme_protocol("htree", htree::handle_htree_protocol)
         .invoke_handler(tauri::generate_handler![
+            acl::get_acl_rules,
+            acl::set_acl_rules,
+            tls::get_lan_tls,
+            pairing::start_pairing,
+            pairing::list_paired_devices,
+            pairing::set_device_permission,
+            pairing::unpair_device,
+            pairing::pair_with_device,
+            remote::remote_request,
             htree::get_htree_server_url,
             htree::cache_tree_root,
             htree::webview_event,
+            htree::resolve_webview_target,
+            htree::save_webview_target,
             worker::worker_message,
+            worker::worker_blob,
+            worker::import_dropped_files,
             nip07::create_nip07_webview,
             nip07::create_htree_webview,
+            nip07::open_app_tab,
             nip07::navigate_webview,
             nip07::webview_history,
             nip07::webview_current_url,
+            nip07::webview_find,
+            nip07::webview_set_zoom,
+            webview_data::list_webview_origins,
+            webview_data::clear_webview_data,
+            webviews::list_webviews,
+            webviews::close_webview,
+            webviews::suspend_webview,
+            webviews::restore_webview,
+            webviews::get_webview_limits,
+            webviews::set_webview_limits,
+            content_blocking::get_content_blocking,
+            content_blocking::set_content_blocking,
+            content_blocking::reload_blocklist,
             nip07::nip07_request,
             history::record_history_visit,
             history::search_history,
-            history::get_recent_history
+            history::get_recent_history,
+            history::get_top_sites,
+            history::delete_history_entry,
+            history::delete_history_range,
+            history::clear_history,
+            history::get_history_retention,
+            history::set_history_retention,
+            bookmarks::add_bookmark,
+            bookmarks::remove_bookmark,
+            bookmarks::list_bookmarks,
+            bookmarks::search_bookmarks,
+            bookmarks::publish_bookmarks,
+            deep_link::take_pending_deep_link
         ])
         .on_page_load(|webview, payload| {
             // Inject NIP-07 window.nostr on page load for main window
@@ -159,10 +238,37 @@ pub fn run() {
 
             info!("App data directory: {:?}", data_dir);
 
+            // An imported state is swapped in before any store is opened
+            match worker::apply_pending_import(&data_dir) {
+                Ok(true) => info!("Restored imported state"),
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to apply imported state: {}", e),
+            }
+
+            // So is a rewrite of nostrdb staged by purgeNdb
+            match worker::apply_pending_purge(&data_dir) {
+                Ok(Some(removed)) => info!("Purged {} events from nostrdb", removed),
+                Ok(None) => {}
+                Err(e) => tracing::error!("Failed to purge nostrdb: {}", e),
+            }
+
+            // Guest sessions don't outlive the process, even one that crashed
+            worker::wipe_guest_sessions(&data_dir);
+
+            // Before anything connects anywhere
+            proxy::init_proxy(&data_dir);
+
             // Initialize htree state for URI scheme protocol (must be before webview loads)
             htree::init_htree_state(data_dir.clone());
+            rate_limit::init_rate_limits(&data_dir);
+            acl::init_acl(&data_dir);
+            pairing::init_pairing(&data_dir);
+            webview_data::init_webview_origins(&data_dir);
+            webviews::init_webviews(&data_dir);
+            content_blocking::init_content_blocking(&data_dir);
             info!("htree:// protocol initialized");
             htree::set_app_handle(app.handle().clone());
+            webviews::start_reaper(app.handle().clone());
 
             // Initialize worker state (store + tree manager + nostrdb)
             let blob_store = worker::BlobStore::new(data_dir.clone());
@@ -180,6 +286,10 @@ pub fn run() {
                 history::HistoryStore::new(&data_dir)
                     .expect("failed to initialize history store"),
             );
+            let bookmark_store = std::sync::Arc::new(
+                bookmarks::BookmarkStore::new(&data_dir)
+                    .expect("failed to initialize bookmark store"),
+            );
 
             // Initialize global state for HTTP handler access (must be before manage)
             nip07::init_global_state(nip07_state.clone(), worker_state.clone());
@@ -188,6 +298,7 @@ pub fn run() {
             app.manage(worker_state);
             app.manage(nip07_state);
             app.manage(history_store);
+            app.manage(bookmark_store);
 
             // Start the htree HTTP server with access to local blob store
             let htree_data_dir = data_dir.clone();
@@ -247,6 +358,11 @@ pub fn run() {
             // Add dialog plugin for file operations
             app.handle().plugin(tauri_plugin_dialog::init())?;
 
+            // Open htree://, web+htree: and nostr: links, including the one
+            // that launched the app
+            app.handle().plugin(tauri_plugin_deep_link::init())?;
+            deep_link::init_deep_links(app.handle());
+
             // Add autostart plugin for desktop platforms
             #[cfg(any(target_os = "macos", windows, target_os = "linux"))]
             app.handle().plugin(tauri_plugin_autostart::init(
@@ -254,10 +370,23 @@ pub fn run() {
                 Some(vec!["--minimized"]),
             ))?;
 
+            // Tray icon with sync status and quick actions - desktop only
+            #[cfg(any(target_os = "macos", windows, target_os = "linux"))]
+            tray::init_tray(app.handle())?;
+
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Dropping the guest session wipes its dir. Setup may have
+                // failed before the state was managed.
+                if let Some(state) = app.try_state::<std::sync::Arc<worker::WorkerState>>() {
+                    state.guest.write().take();
+                }
+            }
+        });
 }
 
 #[cfg(test)]
@@ -0,0 +1,322 @@
+//! Markdown files rendered to HTML for the htree server
+//!
+//! `/htree/<path>.md?render=html` serves a markdown file as a standalone HTML
+//! page, so a tree of markdown files reads as a simple wiki without a
+//! client-side renderer. The HTML from pulldown-cmark is sanitized with
+//! ammonia, so raw HTML in the file can't run scripts. Relative links and
+//! images are rewritten to absolute `/htree/` URLs of the same tree; `..`
+//! stops at the tree's root and `/page.md` means the root's page. Links to
+//! other markdown files keep `?render=html`, so following them stays in the
+//! rendered view, and links of a link-visible tree keep its `?k=` secret.
+
+use ammonia::{Builder, UrlRelative, UrlRelativeEvaluate};
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::borrow::Cow;
+
+/// Largest markdown file rendered, in bytes
+pub const MAX_MARKDOWN_BYTES: u64 = 4 * 1024 * 1024;
+
+/// CSP of rendered pages: no scripts, frames or plugins, even if
+/// sanitization missed something, and no images or media from other hosts,
+/// which would tell them who reads the page
+pub const RENDERED_CSP: &str =
+    "default-src 'none'; img-src 'self' data: blob:; media-src 'self'; style-src 'unsafe-inline'";
+
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "mkd"];
+
+const STYLE: &str = "body{max-width:48rem;margin:2rem auto;padding:0 1rem;\
+font:16px/1.6 system-ui,sans-serif;color:#222;background:#fff}\
+@media(prefers-color-scheme:dark){body{color:#ddd;background:#111}a{color:#8ab4f8}}\
+img{max-width:100%}pre{overflow-x:auto;padding:.75rem;background:rgba(127,127,127,.15)}\
+code{font-family:ui-monospace,monospace}table{border-collapse:collapse}\
+th,td{border:1px solid rgba(127,127,127,.5);padding:.25rem .5rem}\
+blockquote{margin-left:0;padding-left:1rem;border-left:3px solid rgba(127,127,127,.5)}";
+
+/// Whether a file is markdown, by MIME type or extension
+pub fn is_markdown(path: &str, content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    if mime.eq_ignore_ascii_case("text/markdown") || mime.eq_ignore_ascii_case("text/x-markdown") {
+        return true;
+    }
+    has_markdown_extension(path)
+}
+
+fn has_markdown_extension(path: &str) -> bool {
+    path.rsplit_once('.').is_some_and(|(_, ext)| {
+        MARKDOWN_EXTENSIONS
+            .iter()
+            .any(|md| ext.eq_ignore_ascii_case(md))
+    })
+}
+
+/// Where relative links of a file point: its tree's root path segments
+/// (`nhash1..`, `npub1../tree` or `naddr1..`) and its folder below the root,
+/// both percent-encoded as in the request path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkBase {
+    root: String,
+    dir: Vec<String>,
+    /// `?k=` secret of a link-visible tree, carried over to its links
+    secret: Option<String>,
+}
+
+impl LinkBase {
+    /// Base of the file at `path` (the request path after `/htree/`)
+    pub fn for_path(path: &str) -> Option<Self> {
+        let mut segments = path
+            .trim_start_matches('/')
+            .split('/')
+            .filter(|s| !s.is_empty());
+        let first = segments.next()?;
+        let root = if first.starts_with("npub1") {
+            format!("{}/{}", first, segments.next()?)
+        } else {
+            first.to_string()
+        };
+        let mut dir: Vec<String> = segments.map(str::to_string).collect();
+        // Drop the file itself
+        dir.pop();
+        Some(Self {
+            root,
+            dir,
+            secret: None,
+        })
+    }
+
+    /// Keep the `?k=` secret of a link-visible tree on rewritten links
+    pub fn with_secret(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    /// Key of renderings of a file under this base
+    pub fn cache_key(&self, file_hash: &str) -> String {
+        let key = format!("{}:{}/{}", file_hash, self.root, self.dir.join("/"));
+        match &self.secret {
+            Some(secret) => format!("{}?k={}", key, secret),
+            None => key,
+        }
+    }
+
+    /// Absolute URL of a relative link; fragments, `/htree/` URLs and
+    /// protocol-relative URLs are left alone
+    pub fn rewrite<'url>(&self, url: &'url str) -> Cow<'url, str> {
+        if url.starts_with('#') || url.starts_with("//") || url.starts_with("/htree/") {
+            return Cow::Borrowed(url);
+        }
+        let split = url.find(['?', '#']).unwrap_or(url.len());
+        let (link_path, suffix) = url.split_at(split);
+
+        let mut segments: Vec<&str> = match link_path.strip_prefix('/') {
+            Some(_) => Vec::new(),
+            None => self.dir.iter().map(String::as_str).collect(),
+        };
+        for segment in link_path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+        let mut rewritten = format!("/htree/{}", self.root);
+        for segment in &segments {
+            rewritten.push('/');
+            rewritten.push_str(segment);
+        }
+        if link_path.ends_with('/') && !segments.is_empty() {
+            rewritten.push('/');
+        }
+        let (query, fragment) = suffix.split_at(suffix.find('#').unwrap_or(suffix.len()));
+        let mut params: Vec<&str> = query
+            .strip_prefix('?')
+            .into_iter()
+            .filter(|q| !q.is_empty())
+            .collect();
+        if query.is_empty() && has_markdown_extension(link_path) {
+            params.push("render=html");
+        }
+        let secret_param = self.secret.as_ref().map(|secret| format!("k={}", secret));
+        params.extend(secret_param.as_deref());
+        if !params.is_empty() {
+            rewritten.push('?');
+            rewritten.push_str(&params.join("&"));
+        }
+        rewritten.push_str(fragment);
+        Cow::Owned(rewritten)
+    }
+}
+
+impl<'a> UrlRelativeEvaluate<'a> for LinkBase {
+    fn evaluate<'url>(&self, url: &'url str) -> Option<Cow<'url, str>> {
+        Some(self.rewrite(url))
+    }
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+}
+
+/// Render a markdown file to a sanitized HTML page; the first top-level
+/// heading, or `fallback_title`, is the page's title
+pub fn render(source: &str, base: &LinkBase, fallback_title: &str) -> String {
+    let events: Vec<Event> = Parser::new_ext(source, options()).collect();
+
+    let mut title = String::new();
+    let mut in_title = false;
+    for event in &events {
+        match event {
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            }) => in_title = true,
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) if !title.trim().is_empty() => break,
+            Event::End(TagEnd::Heading(_)) => in_title = false,
+            Event::Text(text) | Event::Code(text) if in_title => title.push_str(text),
+            _ => {}
+        }
+    }
+    let title = match title.trim() {
+        "" => fallback_title,
+        title => title,
+    };
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    let body = Builder::default()
+        .url_relative(UrlRelative::Custom(Box::new(base.clone())))
+        .clean(&unsafe_html)
+        .to_string();
+
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title><style>{}</style></head>\n<body>\n{}</body></html>\n",
+        escape_html(title),
+        STYLE,
+        body
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_markdown() {
+        assert!(is_markdown(
+            "npub1x/wiki/README.md",
+            "application/octet-stream"
+        ));
+        assert!(is_markdown(
+            "npub1x/wiki/notes",
+            "text/markdown; charset=utf-8"
+        ));
+        assert!(!is_markdown("npub1x/wiki/index.html", "text/html"));
+        assert!(!is_markdown("npub1x/wiki/md", "text/plain"));
+    }
+
+    #[test]
+    fn test_link_base_rewrite() {
+        let base = LinkBase::for_path("npub1x/My%20Wiki/docs/guide/start.md").unwrap();
+        let root = "/htree/npub1x/My%20Wiki";
+        let cases = [
+            ("next.md", "/docs/guide/next.md?render=html"),
+            ("./img/a.png", "/docs/guide/img/a.png"),
+            ("../faq.md#install", "/docs/faq.md?render=html#install"),
+            ("../../../../../etc/passwd", "/etc/passwd"),
+            ("/index.md", "/index.md?render=html"),
+            ("sub/", "/docs/guide/sub/"),
+            ("data.md?raw=1", "/docs/guide/data.md?raw=1"),
+        ];
+        for (link, expected) in cases {
+            assert_eq!(
+                base.rewrite(link),
+                format!("{}{}", root, expected),
+                "{}",
+                link
+            );
+        }
+        for kept in ["#section", "//example.com/a.png", "/htree/nhash1abc/x.md"] {
+            assert_eq!(base.rewrite(kept), kept);
+        }
+
+        let base = LinkBase::for_path("nhash1abc/README.md").unwrap();
+        assert_eq!(
+            base.rewrite("docs/a.md"),
+            "/htree/nhash1abc/docs/a.md?render=html"
+        );
+        assert_ne!(
+            base.cache_key("ff"),
+            LinkBase::for_path("nhash1abc/docs/README.md")
+                .unwrap()
+                .cache_key("ff")
+        );
+        assert_eq!(LinkBase::for_path("npub1x"), None);
+    }
+
+    #[test]
+    fn test_link_base_keeps_link_secret() {
+        let base = LinkBase::for_path("npub1x/shared/docs/start.md")
+            .unwrap()
+            .with_secret("ab12");
+        let root = "/htree/npub1x/shared";
+        let cases = [
+            ("next.md", "/docs/next.md?render=html&k=ab12"),
+            ("img/a.png", "/docs/img/a.png?k=ab12"),
+            ("../faq.md#install", "/faq.md?render=html&k=ab12#install"),
+            ("data.md?raw=1", "/docs/data.md?raw=1&k=ab12"),
+        ];
+        for (link, expected) in cases {
+            assert_eq!(
+                base.rewrite(link),
+                format!("{}{}", root, expected),
+                "{}",
+                link
+            );
+        }
+        // Other trees and hosts don't get the secret
+        for kept in ["//example.com/a.png", "/htree/npub1x/other/a.md"] {
+            assert_eq!(base.rewrite(kept), kept);
+        }
+        assert_ne!(
+            base.cache_key("ff"),
+            LinkBase::for_path("npub1x/shared/docs/start.md")
+                .unwrap()
+                .cache_key("ff")
+        );
+    }
+
+    #[test]
+    fn test_render_sanitizes_and_rewrites() {
+        let base = LinkBase::for_path("npub1x/wiki/docs/start.md").unwrap();
+        let page = render(
+            "# Start & go\n\nSee [next](next.md) and [site](https://example.com).\n\n\
+             ![logo](img/logo.png)\n\n<script>alert(1)</script>\n\
+             <a href=\"javascript:alert(1)\" onclick=\"alert(2)\">x</a>\n",
+            &base,
+            "start.md",
+        );
+        assert!(page.contains("<title>Start &amp; go</title>"));
+        assert!(page.contains("href=\"/htree/npub1x/wiki/docs/next.md?render=html\""));
+        assert!(page.contains("href=\"https://example.com\""));
+        assert!(page.contains("src=\"/htree/npub1x/wiki/docs/img/logo.png\""));
+        assert!(!page.contains("<script>"));
+        assert!(!page.contains("javascript:"));
+        assert!(!page.contains("onclick"));
+
+        let untitled = render("Just text", &base, "start.md");
+        assert!(untitled.contains("<title>start.md</title>"));
+    }
+}
@@ -4,17 +4,28 @@
 //! 1. Injecting initialization script that defines window.nostr
 //! 2. Using HTTP calls to localhost with session token for security
 //! 3. Handling NIP-07 requests via the htree HTTP server
+//!
+//! htree webviews run under a [`WebviewPolicy`] deciding whether their
+//! content may reach the network, `window.nostr` and downloads. Unless the
+//! caller gives one, it follows the WoT sandbox policy for the tree's owner.
+//! Network blocking is enforced with a CSP on the webview's htree://
+//! responses and `window.nostr` by its session, so the page can't lift
+//! either by replacing the script's wrappers.
 
 use crate::permissions::{PermissionStore, PermissionType};
-use crate::worker::WorkerState;
+use crate::worker::{WorkerState, Wot};
 use nostr_sdk::{Kind, Tag, Timestamp, UnsignedEvent};
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tauri::{AppHandle, Emitter, Manager, Runtime, WebviewBuilder, WebviewUrl};
-use tracing::{debug, info};
+use tauri::webview::{NewWindowFeatures, NewWindowResponse};
+use tauri::{
+    AppHandle, Emitter, Manager, Runtime, WebviewBuilder, WebviewUrl, WebviewWindowBuilder,
+};
+use tracing::{debug, info, warn};
 
 // ============================================
 // htree:// URL helpers for origin isolation
@@ -191,12 +202,18 @@ pub fn generate_main_window_nip07_script() -> String {
 "#.to_string()
 }
 
-/// Generate NIP-07 initialization script with server URL and session token
-pub fn generate_nip07_script(server_url: &str, session_token: &str, label: &str) -> String {
+/// Generate NIP-07 initialization script with server URL and session token.
+/// `window.nostr` is only defined if `expose_nostr`.
+pub fn generate_nip07_script(
+    server_url: &str,
+    session_token: &str,
+    label: &str,
+    expose_nostr: bool,
+) -> String {
     format!(
         r#"
 (function() {{
-  const hasNostr = !!window.nostr;
+  const hasNostr = !!window.nostr || !{};
   const SERVER_URL = "{}";
   const SESSION_TOKEN = "{}";
   const WEBVIEW_LABEL = "{}";
@@ -355,6 +372,47 @@ pub fn generate_nip07_script(server_url: &str, session_token: &str, label: &str)
   window.addEventListener('load', () => notifyLocation('load'));
   queueMicrotask(() => notifyLocation('init'));
 
+  // For the content blocker's script to report what it blocked
+  Object.defineProperty(window, '__htreeReportBlocked', {{
+    value: (count) => postWebviewEvent({{
+      kind: 'blocked',
+      label: WEBVIEW_LABEL,
+      origin: getOrigin(),
+      count
+    }})
+  }});
+
+  // Saveable links and media under a right-click go to the main window,
+  // which shows its own menu with "Save link as…" and "Save image as…".
+  // Pages handling the event themselves, selected text and other links keep
+  // their own menu.
+  const SAVEABLE = ['http:', 'https:', 'htree:'];
+  function saveableUrl(url) {{
+    try {{
+      return url && SAVEABLE.includes(new URL(url).protocol) ? url : null;
+    }} catch {{
+      return null;
+    }}
+  }}
+  document.addEventListener('contextmenu', (e) => {{
+    const target = e.target;
+    if (e.defaultPrevented || !target || !target.closest) return;
+    if (String(window.getSelection() || '')) return;
+    const link = target.closest('a[href]');
+    const media = target.closest('img, video, audio');
+    const linkUrl = link ? saveableUrl(link.href) : null;
+    const mediaUrl = media ? saveableUrl(media.currentSrc || media.src) : null;
+    if (!linkUrl && !mediaUrl) return;
+    e.preventDefault();
+    postWebviewEvent({{
+      kind: 'contextmenu',
+      label: WEBVIEW_LABEL,
+      origin: getOrigin(),
+      linkUrl,
+      mediaUrl
+    }});
+  }});
+
   function navigateHistory(action) {{
     const beforeUrl = window.location.href;
     if (action === 'back') {{
@@ -446,7 +504,8 @@ pub fn generate_nip07_script(server_url: &str, session_token: &str, label: &str)
         body: JSON.stringify({{
           method,
           params,
-          origin: getOrigin()
+          origin: getOrigin(),
+          session_token: SESSION_TOKEN
         }})
       }});
 
@@ -502,58 +561,312 @@ pub fn generate_nip07_script(server_url: &str, session_token: &str, label: &str)
 
     console.log('[NIP-07] window.nostr initialized');
   }} else {{
-    console.log('[NIP-07] window.nostr already available');
+    console.log('[NIP-07] window.nostr already available or not allowed');
   }}
 }})();
 "#,
-        server_url, session_token, label
+        expose_nostr, server_url, session_token, label
     )
 }
 
+/// What the content of an htree webview may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebviewPolicy {
+    /// Reach URLs other than htree://, data:, blob: and the htree server
+    pub external_network: bool,
+    /// Use `window.nostr`
+    pub nostr: bool,
+    /// Save files
+    pub downloads: bool,
+}
+
+impl WebviewPolicy {
+    pub fn trusted() -> Self {
+        Self {
+            external_network: true,
+            nostr: true,
+            downloads: true,
+        }
+    }
+
+    pub fn sandboxed() -> Self {
+        Self {
+            external_network: false,
+            nostr: false,
+            downloads: false,
+        }
+    }
+
+    /// Default for trees of `owner` (None for nhash content): sandboxed if
+    /// the WoT sandboxes HTML from them
+    pub fn for_owner(wot: &Wot, owner: Option<&str>) -> Self {
+        let sandboxes = match owner {
+            Some(owner) => wot.sandboxes(owner),
+            None => wot.policy().sandboxes(None),
+        };
+        if sandboxes {
+            Self::sandboxed()
+        } else {
+            Self::trusted()
+        }
+    }
+
+    /// Whether the webview may navigate to `url`
+    pub fn allows_navigation(&self, url: &tauri::Url, server_url: &str) -> bool {
+        if self.external_network {
+            return true;
+        }
+        matches!(url.scheme(), "htree" | "about" | "data" | "blob")
+            || tauri::Url::parse(server_url).is_ok_and(|server| server.origin() == url.origin())
+    }
+
+    /// CSP keeping the webview's pages off the network, if the policy does.
+    /// htree://, data:, blob: and the htree server stay reachable.
+    pub fn csp(&self, server_url: &str) -> Option<String> {
+        if self.external_network {
+            return None;
+        }
+        let server = tauri::Url::parse(server_url)
+            .map(|url| url.origin().ascii_serialization())
+            .unwrap_or_default();
+        Some(format!(
+            "default-src 'self' htree: data: blob: {server} 'unsafe-inline' 'unsafe-eval'; \
+             form-action 'self' htree: {server}",
+            server = server
+        ))
+    }
+
+    /// Initialization script applying the policy in the page, if it
+    /// restricts anything there. Its wrappers fail blocked requests early;
+    /// the [`csp`](Self::csp) is what enforces them.
+    pub fn script(&self, server_url: &str) -> Option<String> {
+        if self.external_network && self.downloads {
+            return None;
+        }
+        Some(format!(
+            r#"
+(function() {{
+  const SERVER_ORIGIN = new URL("{}").origin;
+  const BLOCK_NETWORK = {};
+  const BLOCK_DOWNLOADS = {};
+
+  function allowed(url) {{
+    try {{
+      const parsed = new URL(String(url), window.location.href);
+      return ['htree:', 'data:', 'blob:'].includes(parsed.protocol) ||
+        parsed.origin === SERVER_ORIGIN;
+    }} catch {{
+      return false;
+    }}
+  }}
+
+  function refuse(url) {{
+    console.warn('[htree] Blocked by webview policy:', String(url));
+    return new TypeError('Blocked by webview policy');
+  }}
+
+  if (BLOCK_NETWORK) {{
+    const originalFetch = window.fetch;
+    window.fetch = function(input, init) {{
+      const url = input instanceof Request ? input.url : input;
+      if (!allowed(url)) return Promise.reject(refuse(url));
+      return originalFetch.apply(this, arguments);
+    }};
+
+    const originalOpen = XMLHttpRequest.prototype.open;
+    XMLHttpRequest.prototype.open = function(method, url) {{
+      if (!allowed(url)) throw refuse(url);
+      return originalOpen.apply(this, arguments);
+    }};
+
+    for (const name of ['WebSocket', 'EventSource']) {{
+      const Original = window[name];
+      if (!Original) continue;
+      const Wrapped = function(url, options) {{
+        if (!allowed(url)) throw refuse(url);
+        return new Original(url, options);
+      }};
+      Wrapped.prototype = Original.prototype;
+      for (const key of ['CONNECTING', 'OPEN', 'CLOSING', 'CLOSED']) {{
+        if (key in Original) Wrapped[key] = Original[key];
+      }}
+      window[name] = Wrapped;
+    }}
+
+    if (navigator.sendBeacon) {{
+      const originalBeacon = navigator.sendBeacon.bind(navigator);
+      navigator.sendBeacon = function(url, data) {{
+        if (!allowed(url)) return false;
+        return originalBeacon(url, data);
+      }};
+    }}
+  }}
+
+  if (BLOCK_DOWNLOADS) {{
+    document.addEventListener('click', (event) => {{
+      const link = event.target && event.target.closest && event.target.closest('a[download]');
+      if (link) {{
+        event.preventDefault();
+        console.warn('[htree] Download blocked by webview policy');
+      }}
+    }}, true);
+  }}
+}})();
+"#,
+            server_url, !self.external_network, !self.downloads
+        ))
+    }
+}
+
+/// Event asking the main window to open `url` in a new tab, emitted for
+/// `window.open` and target=_blank links in child webviews
+pub const NEW_TAB_EVENT: &str = "child-webview-new-tab";
+
+/// New-window handler of child webview `label`: no window is opened, the
+/// URL goes to the main window as a [`NEW_TAB_EVENT`] if `allow`s it
+fn new_tab_handler<R: Runtime>(
+    app: AppHandle<R>,
+    label: String,
+    private: bool,
+    allow: impl Fn(&tauri::Url) -> bool + Send + Sync + 'static,
+) -> impl Fn(tauri::Url, NewWindowFeatures) -> NewWindowResponse<R> + Send + Sync + 'static {
+    move |url, _| {
+        if allow(&url) {
+            debug!("Child webview {} opening new tab: {}", label, url);
+            let _ = app.emit(
+                NEW_TAB_EVENT,
+                serde_json::json!({
+                    "label": label,
+                    "url": url.to_string(),
+                    "private": private
+                }),
+            );
+        } else {
+            warn!("New tab for {} blocked by webview policy", url);
+        }
+        NewWindowResponse::Deny
+    }
+}
+
+/// Number of the last tab window opened
+static LAST_TAB: AtomicU64 = AtomicU64::new(0);
+
+/// Open `url` in the app frame of a new tab window. The window's frame is
+/// labelled `app-frame-<n>` and, for a `private` tab, stays private for
+/// every page it loads.
+#[tauri::command]
+pub fn open_app_tab<R: Runtime>(
+    app: AppHandle<R>,
+    url: String,
+    private: bool,
+) -> Result<(), String> {
+    let n = LAST_TAB.fetch_add(1, Ordering::Relaxed) + 1;
+    let tab = serde_json::json!({
+        "frameLabel": format!("app-frame-{}", n),
+        "private": private,
+        "route": format!(
+            "/app/{}",
+            percent_encoding::utf8_percent_encode(&url, percent_encoding::NON_ALPHANUMERIC)
+        ),
+    });
+    // Set before the app's scripts run, which route by the hash
+    let script = format!(
+        "window.__IRIS_TAB__ = {tab}; if (!location.hash) location.hash = window.__IRIS_TAB__.route;"
+    );
+    let title = if private { "Iris (private)" } else { "Iris" };
+    WebviewWindowBuilder::new(&app, format!("app-tab-{}", n), WebviewUrl::default())
+        .title(title)
+        .inner_size(1200.0, 800.0)
+        .min_inner_size(800.0, 600.0)
+        .initialization_script(&script)
+        .build()
+        .map_err(|e| format!("Failed to open tab: {}", e))?;
+    info!("Opened tab {} (private: {}) for {}", n, private, url);
+    Ok(())
+}
+
+/// Session of a webview origin
+struct Session {
+    token: String,
+    /// Whether the page may use `window.nostr`
+    nostr: bool,
+}
+
 /// State for managing NIP-07 webviews
 pub struct Nip07State {
     pub permissions: Arc<PermissionStore>,
-    /// Map of origin -> session token (each origin gets its own token)
-    session_tokens: RwLock<HashMap<String, String>>,
+    /// Map of origin -> session (each origin gets its own token)
+    sessions: RwLock<HashMap<String, Session>>,
+    /// Policies of htree webviews, by label
+    policies: RwLock<HashMap<String, WebviewPolicy>>,
 }
 
 impl Nip07State {
     pub fn new(permissions: Arc<PermissionStore>) -> Self {
         Self {
             permissions,
-            session_tokens: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(HashMap::new()),
+            policies: RwLock::new(HashMap::new()),
         }
     }
 
-    /// Generate a new session token for an origin
-    pub fn new_session(&self, origin: &str) -> String {
+    /// Generate a new session token for an origin, whose page may use
+    /// `window.nostr` if `nostr`
+    pub fn new_session(&self, origin: &str, nostr: bool) -> String {
         let token = uuid::Uuid::new_v4().to_string();
-        self.session_tokens
-            .write()
-            .insert(origin.to_string(), token.clone());
+        self.sessions.write().insert(
+            origin.to_string(),
+            Session {
+                token: token.clone(),
+                nostr,
+            },
+        );
         token
     }
 
     /// Validate a session token for an origin
     pub fn validate_token(&self, origin: &str, token: &str) -> bool {
-        self.session_tokens
+        self.sessions
             .read()
             .get(origin)
-            .map(|t| t == token)
-            .unwrap_or(false)
+            .is_some_and(|session| session.token == token)
+    }
+
+    /// Whether the session of `origin` with `token` may use `window.nostr`
+    pub fn allows_nostr(&self, origin: &str, token: &str) -> bool {
+        self.sessions
+            .read()
+            .get(origin)
+            .is_some_and(|session| session.token == token && session.nostr)
     }
 
     /// Validate a session token without requiring a specific origin.
     pub fn validate_any_token(&self, token: &str) -> bool {
-        self.session_tokens
+        self.sessions
             .read()
             .values()
-            .any(|stored| stored == token)
+            .any(|session| session.token == token)
     }
 
     /// Clear the session token for an origin
     pub fn clear_session(&self, origin: &str) {
-        self.session_tokens.write().remove(origin);
+        self.sessions.write().remove(origin);
+    }
+
+    /// Set the policy of htree webview `label`
+    pub fn set_policy(&self, label: &str, policy: WebviewPolicy) {
+        self.policies.write().insert(label.to_string(), policy);
+    }
+
+    /// Policy of htree webview `label`, if it is one
+    pub fn policy(&self, label: &str) -> Option<WebviewPolicy> {
+        self.policies.read().get(label).copied()
+    }
+
+    /// Forget the policy of closed webview `label`
+    pub fn clear_policy(&self, label: &str) {
+        self.policies.write().remove(label);
     }
 }
 
@@ -562,6 +875,10 @@ pub struct Nip07Request {
     pub method: String,
     pub params: serde_json::Value,
     pub origin: String,
+    /// Session token, for requests over htree://nip07/ that can't carry
+    /// the header
+    #[serde(default)]
+    pub session_token: Option<String>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -582,8 +899,11 @@ pub async fn create_nip07_webview<R: Runtime>(
     y: f64,
     width: f64,
     height: f64,
+    private: Option<bool>,
 ) -> Result<(), String> {
     info!("[NIP-07] Creating webview {} for {}", label, url);
+    let private = private.unwrap_or(false);
+    crate::history::set_private_webview(&label, private);
 
     // Get htree server URL
     let server_url = crate::htree::get_htree_server_url()
@@ -605,10 +925,10 @@ pub async fn create_nip07_webview<R: Runtime>(
     let nip07_state = app
         .try_state::<Arc<Nip07State>>()
         .ok_or("Nip07State not found")?;
-    let session_token = nip07_state.new_session(&origin);
+    let session_token = nip07_state.new_session(&origin, true);
 
     // Generate the initialization script with server URL and token
-    let init_script = generate_nip07_script(&server_url, &session_token, &label);
+    let init_script = generate_nip07_script(&server_url, &session_token, &label, true);
 
     let window = app.get_window("main").ok_or("Main window not found")?;
 
@@ -631,11 +951,35 @@ pub async fn create_nip07_webview<R: Runtime>(
     let app_for_nav = app.clone();
     let label_for_nav = label.clone();
 
+    let new_tab = new_tab_handler(app.clone(), label.clone(), private, |_| true);
+
     // Create child webview with NIP-07 initialization script and navigation handler
-    let webview_builder = WebviewBuilder::new(&label, webview_url)
-        .initialization_script(&init_script)
+    let mut webview_builder =
+        WebviewBuilder::new(&label, webview_url).initialization_script(&init_script);
+    if let Some(blocking_script) = crate::content_blocking::blocking_script() {
+        webview_builder = webview_builder.initialization_script(&blocking_script);
+    }
+    // With blocking on, the filter proxy goes to the web proxy itself
+    let webview_builder = if crate::content_blocking::is_enabled() {
+        webview_builder.proxy_url(crate::filter_proxy::start(&app, &label).await?)
+    } else {
+        crate::proxy::apply_webview(webview_builder)
+    };
+    let webview_builder = webview_builder
         .auto_resize()
+        .on_new_window(new_tab)
         .on_navigation(move |nav_url| {
+            // Parking a suspended webview isn't a navigation of the user's
+            if crate::webviews::is_suspended(&label_for_nav) {
+                return true;
+            }
+            let page = app_for_nav
+                .get_webview(&label_for_nav)
+                .and_then(|webview| webview.url().ok());
+            if crate::content_blocking::blocks_navigation(&app_for_nav, page.as_ref(), nav_url) {
+                return false;
+            }
+            crate::webviews::touch_webview(&label_for_nav);
             // Emit navigation event to the main window so it can update the URL bar
             let url_str = nav_url.to_string();
             debug!("[NIP-07] Child webview navigating to: {}", url_str);
@@ -644,7 +988,8 @@ pub async fn create_nip07_webview<R: Runtime>(
                 serde_json::json!({
                     "label": label_for_nav,
                     "url": url_str,
-                    "source": "navigation"
+                    "source": "navigation",
+                    "private": private
                 }),
             );
             // Allow the navigation
@@ -658,6 +1003,7 @@ pub async fn create_nip07_webview<R: Runtime>(
             tauri::LogicalSize::new(width, height),
         )
         .map_err(|e| format!("Failed to create webview: {}", e))?;
+    crate::webviews::track_webview(&app, &label, &origin);
 
     if let Some(target_url) = navigate_after_create {
         if let Err(e) = webview.navigate(target_url) {
@@ -676,6 +1022,8 @@ pub async fn create_nip07_webview<R: Runtime>(
 /// The URL format is:
 ///   - htree://nhash1abc.../path (origin: htree://nhash1abc...)
 ///   - htree://npub1xyz.treename/path (origin: htree://npub1xyz.treename)
+///
+/// `policy` defaults to [`WebviewPolicy::for_owner`] of the npub.
 #[tauri::command]
 pub async fn create_htree_webview<R: Runtime>(
     app: AppHandle<R>,
@@ -688,6 +1036,8 @@ pub async fn create_htree_webview<R: Runtime>(
     y: f64,
     width: f64,
     height: f64,
+    private: Option<bool>,
+    policy: Option<WebviewPolicy>,
 ) -> Result<(), String> {
     // Validate input: either nhash or (npub + treename) must be provided
     let (url, origin) = if let Some(nhash) = &nhash {
@@ -714,10 +1064,24 @@ pub async fn create_htree_webview<R: Runtime>(
     let nip07_state = app
         .try_state::<Arc<Nip07State>>()
         .ok_or("Nip07State not found")?;
-    let session_token = nip07_state.new_session(&origin);
+    crate::webview_data::record_webview(&label, &origin);
+    let private = private.unwrap_or(false);
+    crate::history::set_private_webview(&label, private);
+
+    let policy = match policy {
+        Some(policy) => policy,
+        None => match app.try_state::<Arc<WorkerState>>() {
+            Some(state) => WebviewPolicy::for_owner(&state.wot, npub.as_deref()),
+            None => WebviewPolicy::trusted(),
+        },
+    };
+    info!("[htree] Webview {} policy: {:?}", label, policy);
+    // Requests of a session without window.nostr are refused, not just hidden
+    let session_token = nip07_state.new_session(&origin, policy.nostr);
+    nip07_state.set_policy(&label, policy);
 
     // Generate the initialization script with server URL and token
-    let init_script = generate_nip07_script(&server_url, &session_token, &label);
+    let init_script = generate_nip07_script(&server_url, &session_token, &label, policy.nostr);
 
     let window = app.get_window("main").ok_or("Main window not found")?;
 
@@ -727,20 +1091,47 @@ pub async fn create_htree_webview<R: Runtime>(
     // Clone for navigation callback
     let app_for_nav = app.clone();
     let label_for_nav = label.clone();
+    let server_for_tab = server_url.clone();
+    let new_tab = new_tab_handler(app.clone(), label.clone(), private, move |url| {
+        policy.allows_navigation(url, &server_for_tab)
+    });
 
     // Create child webview with htree:// URL
-    let webview_builder = WebviewBuilder::new(&label, WebviewUrl::External(parsed_url))
-        .initialization_script(&init_script)
+    let mut webview_builder = WebviewBuilder::new(&label, WebviewUrl::External(parsed_url))
+        .initialization_script(&init_script);
+    if let Some(policy_script) = policy.script(&server_url) {
+        webview_builder = webview_builder.initialization_script(&policy_script);
+    }
+    let webview_builder = crate::proxy::apply_webview(webview_builder)
         .auto_resize()
+        .on_download(move |_, _| {
+            if !policy.downloads {
+                warn!("[htree] Download blocked by webview policy");
+            }
+            policy.downloads
+        })
+        .on_new_window(new_tab)
         .on_navigation(move |nav_url| {
             let url_str = nav_url.to_string();
+            if !policy.allows_navigation(nav_url, &server_url) {
+                warn!(
+                    "[htree] Navigation to {} blocked by webview policy",
+                    url_str
+                );
+                return false;
+            }
+            if crate::webviews::is_suspended(&label_for_nav) {
+                return true;
+            }
+            crate::webviews::touch_webview(&label_for_nav);
             debug!("[htree] Child webview navigating to: {}", url_str);
             let _ = app_for_nav.emit(
                 "child-webview-location",
                 serde_json::json!({
                     "label": label_for_nav,
                     "url": url_str,
-                    "source": "navigation"
+                    "source": "navigation",
+                    "private": private
                 }),
             );
             true
@@ -753,6 +1144,7 @@ pub async fn create_htree_webview<R: Runtime>(
             tauri::LogicalSize::new(width, height),
         )
         .map_err(|e| format!("Failed to create webview: {}", e))?;
+    crate::webviews::track_webview(&app, &label, &origin);
 
     info!(
         "[htree] Webview created with session token for origin {}",
@@ -977,6 +1369,67 @@ pub fn webview_history<R: Runtime>(
     Ok(())
 }
 
+/// Zoom factors accepted by `webview_set_zoom`
+pub const MIN_ZOOM: f64 = 0.25;
+pub const MAX_ZOOM: f64 = 5.0;
+
+/// Script selecting the next or previous match of `text` in the page,
+/// wrapping around; empty `text` clears the selection
+pub fn find_script(text: &str, direction: &str) -> Result<String, String> {
+    let backwards = match direction {
+        "next" => false,
+        "previous" => true,
+        _ => return Err("Invalid find direction".to_string()),
+    };
+    if text.is_empty() {
+        return Ok("window.getSelection()?.removeAllRanges()".to_string());
+    }
+    let text = serde_json::to_string(text).map_err(|e| e.to_string())?;
+    Ok(format!(
+        "window.find({}, false, {}, true, false, false, false)",
+        text, backwards
+    ))
+}
+
+/// Find text in a child webview, like the browser's find bar. Matches are
+/// selected and scrolled to by the page itself.
+#[tauri::command]
+pub fn webview_find<R: Runtime>(
+    app: AppHandle<R>,
+    label: String,
+    text: String,
+    direction: String,
+) -> Result<(), String> {
+    let webview = app
+        .get_webview(&label)
+        .ok_or_else(|| format!("Webview {} not found", label))?;
+    let script = find_script(&text, &direction)?;
+    webview
+        .eval(&script)
+        .map_err(|e| format!("Failed to find in page: {}", e))
+}
+
+/// Set the zoom of a child webview; 1.0 is the default size
+#[tauri::command]
+pub fn webview_set_zoom<R: Runtime>(
+    app: AppHandle<R>,
+    label: String,
+    factor: f64,
+) -> Result<(), String> {
+    if !(MIN_ZOOM..=MAX_ZOOM).contains(&factor) {
+        return Err(format!(
+            "Zoom must be between {} and {}",
+            MIN_ZOOM, MAX_ZOOM
+        ));
+    }
+    let webview = app
+        .get_webview(&label)
+        .ok_or_else(|| format!("Webview {} not found", label))?;
+    webview
+        .set_zoom(factor)
+        .map_err(|e| format!("Failed to set zoom: {}", e))
+}
+
 /// Get the current URL of a child webview.
 #[tauri::command]
 pub fn webview_current_url<R: Runtime>(
@@ -0,0 +1,624 @@
+//! Pairing of trusted devices
+//!
+//! `start_pairing` makes a one-time code, valid for [`OFFER_TTL`], and a
+//! pairing URL holding it with the server's LAN address and the fingerprint
+//! of its CA. Codes and tokens only travel over HTTPS, so pairing needs
+//! `tls` on in the LAN access rules:
+//!
+//! ```text
+//! https://192.168.1.20:21443/pair#code=<code>&sha256=<fingerprint>
+//! ```
+//!
+//! shown to the user as a QR code. The other device (`pair_with_device`)
+//! checks the server's CA against the fingerprint, then trades the code at
+//! `POST /pair` for a token it sends as `Authorization: Bearer` from then on.
+//! Only a hash of each token is kept here.
+//!
+//! Requests with a paired device's token skip the ACL's allowlist (see
+//! [`crate::acl`]) and are limited by the device's permission instead:
+//! `read` allows reading the trees, HLS and profile pictures the exposure
+//! rules make public, `readWrite` any method on any of them, the relay proxy
+//! and the remote API (see [`crate::remote`]). The signer, webview events
+//! and local search stay loopback-only.
+
+use axum::{
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use hashtree_core::{sha256, to_hex};
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// File in the data dir holding the paired devices
+const DEVICES_FILE: &str = "devices.json";
+
+/// How long a pairing code can be used
+pub const OFFER_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Paths LAN clients reach without a token, to pair
+const PAIR_PATHS: &[&str] = &["/pair", "/pair/ca"];
+
+/// Paths of content a paired device can read
+const CONTENT_PREFIXES: &[&str] = &["/htree/", "/hls/", "/profile/"];
+
+/// What a paired device may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Permission {
+    Read,
+    ReadWrite,
+}
+
+impl Permission {
+    /// Whether a device with this permission may make a request
+    pub fn allows(self, method: &Method, path: &str) -> bool {
+        if path == crate::remote::REMOTE_PATH {
+            return self == Permission::ReadWrite;
+        }
+        let content = CONTENT_PREFIXES.iter().any(|p| path.starts_with(p));
+        match self {
+            Permission::Read => {
+                content && matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+            }
+            Permission::ReadWrite => content || path == "/relay",
+        }
+    }
+}
+
+/// A device paired with this one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairedDevice {
+    pub id: String,
+    pub name: String,
+    pub permission: Permission,
+    /// Unix seconds
+    pub paired_at: u64,
+}
+
+/// A device this one paired with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteDevice {
+    pub id: String,
+    /// Origin of its server
+    pub url: String,
+    pub permission: Permission,
+    /// SHA-256 of its CA, if reached over HTTPS
+    pub fingerprint: Option<String>,
+    pub paired_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceRecord {
+    #[serde(flatten)]
+    device: PairedDevice,
+    token_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoteRecord {
+    #[serde(flatten)]
+    remote: RemoteDevice,
+    token: String,
+    /// Its CA, pinned for requests to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ca_pem: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Stored {
+    devices: Vec<DeviceRecord>,
+    remotes: Vec<RemoteRecord>,
+}
+
+/// Both sides of pairing, as listed by `list_paired_devices`
+#[derive(Debug, Clone, Serialize)]
+pub struct PairedDevices {
+    pub devices: Vec<PairedDevice>,
+    pub remotes: Vec<RemoteDevice>,
+}
+
+/// A pairing code as shown to the user
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairingOffer {
+    /// Pairing URL, holding the code
+    pub url: String,
+    pub code: String,
+    pub permission: Permission,
+    /// Unix seconds
+    pub expires_at: u64,
+    /// The URL as a QR code
+    pub qr_svg: String,
+}
+
+struct Offer {
+    code: String,
+    permission: Permission,
+    expires: Instant,
+}
+
+/// Answer to a redeemed code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PairResponse {
+    pub device_id: String,
+    pub token: String,
+    pub permission: Permission,
+}
+
+struct Pairing {
+    path: Option<PathBuf>,
+    stored: RwLock<Stored>,
+    offers: Mutex<Vec<Offer>>,
+}
+
+impl Pairing {
+    fn new(path: Option<PathBuf>) -> Self {
+        let stored = path
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            stored: RwLock::new(stored),
+            offers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn save(&self, stored: &Stored) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec_pretty(stored)
+            .map_err(|e| format!("Failed to encode paired devices: {}", e))?;
+        // Holds pairing secrets and the tokens for paired servers
+        crate::atomic_file::write_private(path, data)
+            .map_err(|e| format!("Failed to save {:?}: {}", path, e))
+    }
+
+    /// A new one-time code for a device to pair with `permission`
+    fn offer(&self, permission: Permission, now: Instant) -> String {
+        let code = new_secret();
+        let mut offers = self.offers.lock();
+        offers.retain(|offer| offer.expires > now);
+        offers.push(Offer {
+            code: code.clone(),
+            permission,
+            expires: now + OFFER_TTL,
+        });
+        code
+    }
+
+    /// Pair the device presenting `code` as `name`
+    fn redeem(&self, code: &str, name: &str, now: Instant) -> Result<PairResponse, String> {
+        let offer = {
+            let mut offers = self.offers.lock();
+            offers.retain(|offer| offer.expires > now);
+            let index = offers
+                .iter()
+                .position(|offer| constant_time_eq(offer.code.as_bytes(), code.as_bytes()))
+                .ok_or("Invalid or expired pairing code")?;
+            offers.remove(index)
+        };
+
+        let token = new_secret();
+        let device = PairedDevice {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.trim().chars().take(64).collect(),
+            permission: offer.permission,
+            paired_at: unix_now(),
+        };
+        let mut stored = self.stored.write();
+        stored.devices.push(DeviceRecord {
+            device: device.clone(),
+            token_hash: token_hash(&token),
+        });
+        self.save(&stored)?;
+        info!("Paired device {} ({:?})", device.name, device.permission);
+        Ok(PairResponse {
+            device_id: device.id,
+            token,
+            permission: device.permission,
+        })
+    }
+
+    /// Permission of the device holding `token`, if paired
+    fn authenticate(&self, token: &str) -> Option<Permission> {
+        let hash = token_hash(token);
+        self.stored
+            .read()
+            .devices
+            .iter()
+            .find(|record| constant_time_eq(record.token_hash.as_bytes(), hash.as_bytes()))
+            .map(|record| record.device.permission)
+    }
+
+    fn list(&self) -> PairedDevices {
+        let stored = self.stored.read();
+        PairedDevices {
+            devices: stored.devices.iter().map(|r| r.device.clone()).collect(),
+            remotes: stored.remotes.iter().map(|r| r.remote.clone()).collect(),
+        }
+    }
+
+    fn set_permission(&self, id: &str, permission: Permission) -> Result<(), String> {
+        let mut stored = self.stored.write();
+        let record = stored
+            .devices
+            .iter_mut()
+            .find(|record| record.device.id == id)
+            .ok_or("No such device")?;
+        record.device.permission = permission;
+        self.save(&stored)
+    }
+
+    /// Forget a paired device, or a device this one paired with
+    fn unpair(&self, id: &str) -> Result<(), String> {
+        let mut stored = self.stored.write();
+        let before = stored.devices.len() + stored.remotes.len();
+        stored.devices.retain(|record| record.device.id != id);
+        stored.remotes.retain(|record| record.remote.id != id);
+        if stored.devices.len() + stored.remotes.len() == before {
+            return Err("No such device".to_string());
+        }
+        self.save(&stored)
+    }
+
+    fn add_remote(
+        &self,
+        remote: RemoteDevice,
+        token: String,
+        ca_pem: Option<String>,
+    ) -> Result<(), String> {
+        let mut stored = self.stored.write();
+        // Pairing again with the same server replaces the old token
+        stored
+            .remotes
+            .retain(|record| record.remote.url != remote.url);
+        stored.remotes.push(RemoteRecord {
+            remote,
+            token,
+            ca_pem,
+        });
+        self.save(&stored)
+    }
+
+    fn remote(&self, id: &str) -> Option<RemoteRecord> {
+        self.stored
+            .read()
+            .remotes
+            .iter()
+            .find(|record| record.remote.id == id)
+            .cloned()
+    }
+}
+
+fn new_secret() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+fn token_hash(token: &str) -> String {
+    to_hex(&sha256(token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Origin, code and CA fingerprint of a pairing URL
+fn parse_pairing_url(url: &str) -> Result<(String, String, String), String> {
+    let url = tauri::Url::parse(url.trim()).map_err(|_| "Invalid pairing URL")?;
+    if url.path() != "/pair" {
+        return Err("Invalid pairing URL".to_string());
+    }
+    if url.scheme() != "https" {
+        return Err("Pairing needs an https:// URL".to_string());
+    }
+    let fragment = url.fragment().unwrap_or("");
+    let param = |name: &str| {
+        fragment
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    };
+    let code = param("code").ok_or("Pairing URL has no code")?;
+    let fingerprint = param("sha256").ok_or("Pairing URL has no fingerprint")?;
+    Ok((url.origin().ascii_serialization(), code, fingerprint))
+}
+
+/// Fingerprint without separators, for comparing
+fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_ascii_uppercase()
+}
+
+static GLOBAL_PAIRING: OnceCell<Pairing> = OnceCell::new();
+
+/// Load the paired devices from the data dir; must run before the server starts
+pub fn init_pairing(data_dir: &Path) {
+    let _ = GLOBAL_PAIRING.get_or_init(|| Pairing::new(Some(data_dir.join(DEVICES_FILE))));
+}
+
+fn pairing() -> &'static Pairing {
+    GLOBAL_PAIRING.get_or_init(|| Pairing::new(None))
+}
+
+/// Whether LAN clients reach `path` without a token
+pub fn is_pairing_path(path: &str) -> bool {
+    PAIR_PATHS.contains(&path)
+}
+
+/// Permission of the paired device presenting `authorization`, if any
+pub fn device_permission(authorization: Option<&str>) -> Option<Permission> {
+    let token = authorization?.strip_prefix("Bearer ")?;
+    pairing().authenticate(token)
+}
+
+/// HTTP client for a paired server, trusting only its CA if it has one
+fn client(ca_pem: Option<&str>, timeout: Duration) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder().timeout(timeout);
+    if let Some(ca_pem) = ca_pem {
+        let ca = reqwest::Certificate::from_pem(ca_pem.as_bytes())
+            .map_err(|e| format!("Invalid certificate: {}", e))?;
+        builder = builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(ca);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("HTTP client error: {}", e))
+}
+
+/// A device this one paired with, ready for requests
+pub struct RemoteConnection {
+    /// Origin of its server
+    pub url: String,
+    pub token: String,
+    pub client: reqwest::Client,
+}
+
+/// Connection to the device `id` this one paired with
+pub fn remote_connection(id: &str, timeout: Duration) -> Result<RemoteConnection, String> {
+    let record = pairing().remote(id).ok_or("No such device")?;
+    Ok(RemoteConnection {
+        client: client(record.ca_pem.as_deref(), timeout)?,
+        url: record.remote.url,
+        token: record.token,
+    })
+}
+
+/// Tauri command making a pairing code for a device to pair with `permission`
+#[tauri::command]
+pub fn start_pairing(permission: Permission) -> Result<PairingOffer, String> {
+    let tls = crate::tls::get_lan_tls()
+        .ok_or("Pairing needs LAN access over HTTPS; turn on LAN access and HTTPS first")?;
+    let code = pairing().offer(permission, Instant::now());
+    let url = format!(
+        "{}/pair#code={}&sha256={}",
+        tls.url,
+        code,
+        normalize_fingerprint(&tls.fingerprint)
+    );
+    let qr_svg = qrcode::QrCode::new(url.as_bytes())
+        .map_err(|e| e.to_string())?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+    Ok(PairingOffer {
+        url,
+        code,
+        permission,
+        expires_at: unix_now() + OFFER_TTL.as_secs(),
+        qr_svg,
+    })
+}
+
+/// Tauri command listing paired devices and devices this one paired with
+#[tauri::command]
+pub fn list_paired_devices() -> PairedDevices {
+    pairing().list()
+}
+
+#[tauri::command]
+pub fn set_device_permission(id: String, permission: Permission) -> Result<(), String> {
+    pairing().set_permission(&id, permission)
+}
+
+#[tauri::command]
+pub fn unpair_device(id: String) -> Result<(), String> {
+    pairing().unpair(&id)
+}
+
+/// Tauri command pairing with the device that showed `url`, as `name`
+#[tauri::command]
+pub async fn pair_with_device(url: String, name: String) -> Result<RemoteDevice, String> {
+    let (origin, code, fingerprint) = parse_pairing_url(&url)?;
+
+    // The CA is checked against the fingerprint before anything is sent
+    let ca_pem = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| format!("HTTP client error: {}", e))?
+        .get(format!("{}/pair/ca", origin))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to reach {}: {}", origin, e))?
+        .text()
+        .await
+        .map_err(|e| format!("Failed to reach {}: {}", origin, e))?;
+    let actual = crate::tls::fingerprint(&ca_pem)?;
+    if normalize_fingerprint(&actual) != normalize_fingerprint(&fingerprint) {
+        return Err("The device's certificate doesn't match the pairing code".to_string());
+    }
+    let client = client(Some(&ca_pem), Duration::from_secs(10))?;
+
+    let response: PairResponse = client
+        .post(format!("{}/pair", origin))
+        .json(&json!({ "code": code, "name": name }))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Pairing with {} failed: {}", origin, e))?
+        .json()
+        .await
+        .map_err(|e| format!("Pairing with {} failed: {}", origin, e))?;
+
+    let remote = RemoteDevice {
+        id: response.device_id,
+        url: origin,
+        permission: response.permission,
+        fingerprint: Some(normalize_fingerprint(&fingerprint)),
+        paired_at: unix_now(),
+    };
+    pairing().add_remote(remote.clone(), response.token, Some(ca_pem))?;
+    info!("Paired with {}", remote.url);
+    Ok(remote)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PairRequest {
+    code: String,
+    name: String,
+}
+
+/// `POST /pair`: trade a pairing code for a device token
+pub async fn handle_pair(Json(request): Json<PairRequest>) -> Response {
+    match pairing().redeem(&request.code, &request.name, Instant::now()) {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => {
+            warn!("Pairing attempt refused: {}", e);
+            (StatusCode::FORBIDDEN, Json(json!({ "error": e }))).into_response()
+        }
+    }
+}
+
+/// `GET /pair/ca`: certificate of the CA, checked against the fingerprint
+pub async fn handle_pair_ca() -> Response {
+    match crate::tls::get_lan_tls() {
+        Some(tls) => (
+            [(header::CONTENT_TYPE, "application/x-pem-file")],
+            tls.ca_pem,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_one_time_and_expire() {
+        let pairing = Pairing::new(None);
+        let now = Instant::now();
+        let code = pairing.offer(Permission::Read, now);
+
+        assert!(pairing.redeem("wrong", "phone", now).is_err());
+        let response = pairing.redeem(&code, "phone", now).unwrap();
+        assert_eq!(response.permission, Permission::Read);
+        assert!(pairing.redeem(&code, "phone", now).is_err());
+
+        let late = pairing.offer(Permission::ReadWrite, now);
+        assert!(pairing.redeem(&late, "tablet", now + OFFER_TTL).is_err());
+
+        assert_eq!(
+            pairing.authenticate(&response.token),
+            Some(Permission::Read)
+        );
+        assert_eq!(pairing.authenticate(&code), None);
+    }
+
+    #[test]
+    fn test_devices_persist_without_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEVICES_FILE);
+        let pairing = Pairing::new(Some(path.clone()));
+        let code = pairing.offer(Permission::Read, Instant::now());
+        let response = pairing.redeem(&code, "phone", Instant::now()).unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&response.token));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let reloaded = Pairing::new(Some(path));
+        assert_eq!(
+            reloaded.authenticate(&response.token),
+            Some(Permission::Read)
+        );
+        reloaded
+            .set_permission(&response.device_id, Permission::ReadWrite)
+            .unwrap();
+        assert_eq!(
+            reloaded.authenticate(&response.token),
+            Some(Permission::ReadWrite)
+        );
+        reloaded.unpair(&response.device_id).unwrap();
+        assert_eq!(reloaded.authenticate(&response.token), None);
+        assert!(reloaded.list().devices.is_empty());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let read = Permission::Read;
+        assert!(read.allows(&Method::GET, "/htree/npub1a/docs/a.txt"));
+        assert!(read.allows(&Method::HEAD, "/hls/abc.ts"));
+        assert!(!read.allows(&Method::PUT, "/htree/npub1a/docs/a.txt"));
+        assert!(!read.allows(&Method::GET, "/relay"));
+
+        let write = Permission::ReadWrite;
+        assert!(write.allows(&Method::PUT, "/htree/npub1a/docs/a.txt"));
+        assert!(write.allows(&Method::GET, "/relay"));
+        assert!(write.allows(&Method::POST, crate::remote::REMOTE_PATH));
+        assert!(!read.allows(&Method::POST, crate::remote::REMOTE_PATH));
+        for path in ["/nip07", "/webview", "/search", "/extract"] {
+            assert!(!write.allows(&Method::POST, path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_parse_pairing_url() {
+        let (origin, code, fingerprint) =
+            parse_pairing_url("https://192.168.1.20:21443/pair#code=abc&sha256=AB01").unwrap();
+        assert_eq!(origin, "https://192.168.1.20:21443");
+        assert_eq!(code, "abc");
+        assert_eq!(fingerprint, "AB01");
+
+        let (origin, _, _) =
+            parse_pairing_url("https://[fd00::2]:21443/pair#code=x&sha256=AB01").unwrap();
+        assert_eq!(origin, "https://[fd00::2]:21443");
+
+        // Codes never travel in the clear, and the CA is always pinned
+        assert!(parse_pairing_url("http://10.0.0.2:21417/pair#code=x&sha256=AB01").is_err());
+        assert!(parse_pairing_url("https://10.0.0.2:21443/pair#code=x").is_err());
+        assert!(parse_pairing_url("https://10.0.0.2:21443/pair").is_err());
+        assert!(parse_pairing_url("https://10.0.0.2:21443/htree#code=x&sha256=AB01").is_err());
+        assert!(parse_pairing_url("ftp://10.0.0.2/pair#code=x&sha256=AB01").is_err());
+    }
+}
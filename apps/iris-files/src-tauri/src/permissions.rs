@@ -60,6 +60,8 @@ pub struct StoredPermission {
 pub struct PermissionStore {
     /// In-memory cache of permissions: app_origin -> (permission_type -> granted)
     cache: Arc<RwLock<HashMap<String, HashMap<PermissionType, bool>>>>,
+    /// Decisions set aside while a guest session is on
+    saved: Arc<RwLock<Option<HashMap<String, HashMap<PermissionType, bool>>>>>,
     /// Path to persist permissions (optional)
     _storage_path: Option<PathBuf>,
 }
@@ -69,6 +71,7 @@ impl PermissionStore {
     pub fn new(storage_path: Option<PathBuf>) -> Self {
         Self {
             cache: Arc::new(RwLock::new(HashMap::new())),
+            saved: Arc::new(RwLock::new(None)),
             _storage_path: storage_path,
         }
     }
@@ -142,6 +145,22 @@ impl PermissionStore {
         let cache = self.cache.read().await;
         cache.get(app_origin).cloned().unwrap_or_default()
     }
+
+    /// Start a guest session: apps are prompted afresh and nothing decided
+    /// until `end_guest` is kept
+    pub async fn begin_guest(&self) {
+        let mut saved = self.saved.write().await;
+        if saved.is_none() {
+            *saved = Some(std::mem::take(&mut *self.cache.write().await));
+        }
+    }
+
+    /// Drop the guest session's decisions and restore the earlier ones
+    pub async fn end_guest(&self) {
+        if let Some(saved) = self.saved.write().await.take() {
+            *self.cache.write().await = saved;
+        }
+    }
 }
 
 impl Default for PermissionStore {
@@ -245,4 +264,23 @@ mod tests {
         assert!(store.needs_prompt(app, &PermissionType::SignEvent).await);
         assert!(store.needs_prompt(app, &PermissionType::Encrypt).await);
     }
+
+    #[tokio::test]
+    async fn test_guest_decisions_are_discarded() {
+        let store = PermissionStore::new(None);
+        let app = "http://example.com";
+        store.grant(app, PermissionType::SignEvent, true).await;
+
+        // Guests start without earlier decisions
+        store.begin_guest().await;
+        assert!(store.needs_prompt(app, &PermissionType::SignEvent).await);
+        store.grant(app, PermissionType::Encrypt, true).await;
+
+        store.end_guest().await;
+        assert_eq!(
+            store.is_granted(app, &PermissionType::SignEvent).await,
+            Some(true)
+        );
+        assert!(store.needs_prompt(app, &PermissionType::Encrypt).await);
+    }
 }
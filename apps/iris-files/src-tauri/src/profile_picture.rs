@@ -0,0 +1,323 @@
+//! Profile pictures for `/profile/{npub}/picture`
+//!
+//! The picture is the `picture` URL of the owner's kind 0 profile. It's
+//! fetched once, put in the blob store and remembered by URL in
+//! `avatars/`, one file per URL holding the blob hash and content type, so
+//! later requests, offline ones included, are served from the store until
+//! the profile points somewhere else. Owners without a picture URL are
+//! served the avatar file of their `profile` tree, if it has one.
+//!
+//! Picture URLs come from anyone's profile, so they mustn't reach this
+//! machine or its network: hosts, those redirected to included, have to
+//! resolve to public addresses only. Through the web proxy host names are
+//! resolved by the proxy, and only address hosts can be checked.
+
+use hashtree_core::{from_hex, sha256, to_hex, Store};
+use parking_lot::Mutex;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::proxy::{self, Component};
+
+/// Tree whose avatar file stands in for a missing picture URL
+pub const PROFILE_TREE: &str = "profile";
+
+/// Avatar files looked for in the profile tree, in order
+pub const AVATAR_FILES: &[&str] = &[
+    "avatar.jpg",
+    "avatar.jpeg",
+    "avatar.png",
+    "avatar.webp",
+    "avatar.gif",
+];
+
+/// Largest picture fetched
+const MAX_PICTURE_BYTES: usize = 5 * 1024 * 1024;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most redirects followed per picture
+const MAX_REDIRECTS: usize = 5;
+
+/// The http(s) picture URL of kind 0 profile content
+pub fn picture_url(profile: &serde_json::Value) -> Option<&str> {
+    let url = profile.get("picture")?.as_str()?.trim();
+    (url.starts_with("https://") || url.starts_with("http://")).then_some(url)
+}
+
+/// Whether `ip` is reachable on the internet, not this machine, its
+/// network or a reserved range
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // Shared address space (carrier-grade NAT) and reserved
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local and link-local
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Whether `url` may be fetched: http(s), and not to a local or private
+/// address given as such. Host names are checked as they are resolved.
+fn is_fetchable_url(url: &reqwest::Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            host != "localhost" && !host.ends_with(".localhost")
+        }
+    }
+}
+
+/// System resolver keeping only public addresses
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Pictures fetched from their URLs, kept in the blob store
+pub struct PictureCache {
+    index_dir: PathBuf,
+    /// Client for fetches and the web proxy it was built with
+    client: Mutex<Option<(Option<SocketAddr>, reqwest::Client)>>,
+}
+
+impl PictureCache {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            index_dir: data_dir.join("avatars"),
+            client: Mutex::new(None),
+        }
+    }
+
+    /// The fetch client, built again when the web proxy changed
+    fn client(&self) -> Result<reqwest::Client, String> {
+        let proxy = proxy::socket_addr(Component::Web);
+        let mut client = self.client.lock();
+        if let Some((_, client)) = client.as_ref().filter(|(built_for, _)| *built_for == proxy) {
+            return Ok(client.clone());
+        }
+        let redirects = reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !is_fetchable_url(attempt.url()) {
+                attempt.error("redirect to a local address")
+            } else {
+                attempt.follow()
+            }
+        });
+        let builder = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .redirect(redirects)
+            .dns_resolver(Arc::new(PublicResolver));
+        let built = proxy::apply(builder, Component::Web)
+            .build()
+            .map_err(|e| format!("HTTP client error: {}", e))?;
+        *client = Some((proxy, built.clone()));
+        Ok(built)
+    }
+
+    fn index_file(&self, url: &str) -> PathBuf {
+        self.index_dir.join(to_hex(&sha256(url.as_bytes())))
+    }
+
+    /// Picture from `url` and its content type, if fetched before and still
+    /// in `store`
+    pub async fn cached<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        url: &str,
+    ) -> Option<(Vec<u8>, String)> {
+        let index = std::fs::read_to_string(self.index_file(url)).ok()?;
+        let (hash_hex, content_type) = index.split_once('\n')?;
+        let hash = from_hex(hash_hex.trim()).ok()?;
+        let data = store.get(&hash).await.ok()??;
+        Some((data, content_type.trim().to_string()))
+    }
+
+    /// Fetch the picture at `url` and keep it
+    pub async fn fetch<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        url: &str,
+    ) -> Result<(Vec<u8>, String), String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+        if !is_fetchable_url(&parsed) {
+            return Err(format!("Not fetching a local address: {}", url));
+        }
+        let mut response = self
+            .client()?
+            .get(parsed)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("Failed to fetch picture: {}", e))?;
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or("").trim().to_string())
+            .unwrap_or_default();
+        if !content_type.starts_with("image/") {
+            return Err(format!("Not an image: {}", content_type));
+        }
+        if response
+            .content_length()
+            .is_some_and(|len| len as usize > MAX_PICTURE_BYTES)
+        {
+            return Err("Picture too large".to_string());
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("Failed to fetch picture: {}", e))?
+        {
+            if data.len() + chunk.len() > MAX_PICTURE_BYTES {
+                return Err("Picture too large".to_string());
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        self.remember(store, url, &data, &content_type).await?;
+        debug!("Fetched {} byte picture from {}", data.len(), url);
+        Ok((data, content_type))
+    }
+
+    /// Put `data` in `store` as the picture at `url`
+    async fn remember<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        url: &str,
+        data: &[u8],
+        content_type: &str,
+    ) -> Result<(), String> {
+        let hash = sha256(data);
+        store
+            .put(hash, data.to_vec())
+            .await
+            .map_err(|e| format!("Failed to store picture: {}", e))?;
+        std::fs::create_dir_all(&self.index_dir)
+            .map_err(|e| format!("Failed to create avatars dir: {}", e))?;
+        std::fs::write(
+            self.index_file(url),
+            format!("{}\n{}", to_hex(&hash), content_type),
+        )
+        .map_err(|e| format!("Failed to write avatar index: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::MemoryStore;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_picture_url() {
+        let url = "https://example.com/a.png";
+        assert_eq!(picture_url(&json!({ "picture": url })), Some(url));
+        assert_eq!(
+            picture_url(&json!({ "picture": " data:image/png,x" })),
+            None
+        );
+        assert_eq!(picture_url(&json!({ "picture": 1 })), None);
+        assert_eq!(picture_url(&json!({ "name": "alice" })), None);
+    }
+
+    #[test]
+    fn test_local_addresses_are_not_fetched() {
+        let fetchable = |url: &str| is_fetchable_url(&reqwest::Url::parse(url).unwrap());
+        assert!(fetchable("https://example.com/a.png"));
+        assert!(fetchable("http://93.184.215.14/a.png"));
+        assert!(fetchable("http://[2606:4700::1111]/"));
+        for url in [
+            "http://127.0.0.1:21417/api",
+            "http://localhost/a.png",
+            "http://router.localhost./",
+            "http://192.168.1.1/",
+            "http://10.0.0.8/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.100.1.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "file:///etc/passwd",
+        ] {
+            assert!(!fetchable(url), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_remembered_pictures_are_served_from_store() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::new();
+        let cache = PictureCache::new(dir.path());
+        let url = "https://example.com/a.png";
+        assert!(cache.cached(&store, url).await.is_none());
+
+        cache
+            .remember(&store, url, b"png bytes", "image/png")
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.cached(&store, url).await,
+            Some((b"png bytes".to_vec(), "image/png".to_string()))
+        );
+        assert!(cache
+            .cached(&store, "https://example.com/b.png")
+            .await
+            .is_none());
+
+        // Gone from the store, e.g. evicted
+        assert!(PictureCache::new(dir.path())
+            .cached(&MemoryStore::new(), url)
+            .await
+            .is_none());
+    }
+}
@@ -0,0 +1,327 @@
+//! SOCKS5 proxy for network egress
+//!
+//! With a proxy set, e.g. Tor at `127.0.0.1:9050`, Nostr relay connections,
+//! Blossom requests and other web fetches (profile pictures, saved pages) go
+//! through it. Relays and Blossom can each be overridden to use another
+//! proxy or to connect directly. Host names are resolved by the proxy, so
+//! onion addresses work and lookups don't leak.
+//!
+//! Webviews opened while a proxy is set browse through it. WebRTC peer
+//! connections are UDP and would reveal our address to peers, so WebRTC is
+//! off while any proxy is set. On change, relays are reconnected and Blossom
+//! and resolver clients rebuilt; open webviews keep the proxy they started
+//! with.
+//!
+//! The proxy fails closed: if its settings can't be read or its address
+//! can't be used, connections go to an address nothing listens on rather
+//! than out directly.
+
+use nostr_sdk::pool::ConnectionMode;
+use nostr_sdk::RelayOptions;
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::webview::WebviewBuilder;
+use tracing::{error, info, warn};
+
+/// File in the data dir holding the proxy settings
+const SETTINGS_FILE: &str = "proxy.json";
+
+/// Where connections go when the proxy can't be used: nothing listens on
+/// port 0, so they fail instead of going out directly
+const UNREACHABLE: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+const COMPONENTS: [Component; 3] = [Component::Relays, Component::Blossom, Component::Web];
+
+/// Network clients the proxy applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Relays,
+    Blossom,
+    /// Other HTTP requests
+    Web,
+}
+
+/// Proxy of one component, instead of the global one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "mode", content = "address")]
+pub enum ProxyOverride {
+    /// Connect without a proxy
+    Direct,
+    /// SOCKS5 proxy at `host:port`
+    Socks5(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProxyConfig {
+    /// SOCKS5 proxy at `host:port` for all egress, none to connect directly
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socks5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relays: Option<ProxyOverride>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blossom: Option<ProxyOverride>,
+}
+
+impl ProxyConfig {
+    /// Proxy address `component` connects through, if any
+    pub fn address_for(&self, component: Component) -> Option<&str> {
+        let override_ = match component {
+            Component::Relays => self.relays.as_ref(),
+            Component::Blossom => self.blossom.as_ref(),
+            Component::Web => None,
+        };
+        match override_ {
+            Some(ProxyOverride::Direct) => None,
+            Some(ProxyOverride::Socks5(address)) => Some(address),
+            None => self.socks5.as_deref(),
+        }
+    }
+
+    /// Check that every address is an `ip:port` or `localhost:port`
+    pub fn validate(&self) -> Result<(), String> {
+        let overrides = [&self.relays, &self.blossom];
+        let addresses = self
+            .socks5
+            .iter()
+            .chain(overrides.into_iter().flatten().filter_map(|o| match o {
+                ProxyOverride::Direct => None,
+                ProxyOverride::Socks5(address) => Some(address),
+            }));
+        for address in addresses {
+            parse_address(address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Socket address of a proxy at `address`. Host names other than
+/// `localhost` aren't taken, so using the proxy needs no DNS lookup.
+fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = address.parse() {
+        return Ok(addr);
+    }
+    let invalid = || {
+        format!(
+            "Invalid proxy address {}: expected ip:port or localhost:port",
+            address
+        )
+    };
+    match address.rsplit_once(':') {
+        Some((host, port)) if host.eq_ignore_ascii_case("localhost") => {
+            let port: u16 = port.parse().map_err(|_| invalid())?;
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+struct Proxy {
+    dir: Option<PathBuf>,
+    config: RwLock<ProxyConfig>,
+    /// The saved settings couldn't be read: refuse all egress until new
+    /// ones are set
+    refused: AtomicBool,
+}
+
+impl Proxy {
+    fn save(&self, config: &ProxyConfig) -> Result<(), String> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec_pretty(config)
+            .map_err(|e| format!("Failed to encode proxy settings: {}", e))?;
+        crate::atomic_file::write(&dir.join(SETTINGS_FILE), data)
+            .map_err(|e| format!("Failed to save proxy settings: {}", e))
+    }
+}
+
+static GLOBAL_PROXY: OnceCell<Proxy> = OnceCell::new();
+
+/// Load the proxy settings; must run before any network client is created
+pub fn init_proxy(data_dir: &Path) {
+    let path = data_dir.join(SETTINGS_FILE);
+    let loaded = match std::fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ProxyConfig::default()),
+        Err(e) => Err(e.to_string()),
+    };
+    let refused = loaded.is_err();
+    let config = loaded.unwrap_or_else(|e| {
+        error!(
+            "Can't read proxy settings {}, refusing network egress: {}",
+            path.display(),
+            e
+        );
+        ProxyConfig::default()
+    });
+    if let Some(address) = &config.socks5 {
+        info!("Routing network traffic through SOCKS5 proxy {}", address);
+    }
+    let _ = GLOBAL_PROXY.set(Proxy {
+        dir: Some(data_dir.to_path_buf()),
+        config: RwLock::new(config),
+        refused: AtomicBool::new(refused),
+    });
+}
+
+fn proxy() -> &'static Proxy {
+    GLOBAL_PROXY.get_or_init(|| Proxy {
+        dir: None,
+        config: RwLock::new(ProxyConfig::default()),
+        refused: AtomicBool::new(false),
+    })
+}
+
+pub fn proxy_config() -> ProxyConfig {
+    proxy().config.read().clone()
+}
+
+/// Validate, apply and save `config`
+pub fn set_proxy(config: ProxyConfig) -> Result<(), String> {
+    config.validate()?;
+    let proxy = proxy();
+    proxy.save(&config)?;
+    info!("Proxy settings updated: {:?}", config);
+    *proxy.config.write() = config;
+    proxy.refused.store(false, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether any egress goes through a proxy (or is refused)
+pub fn is_proxied() -> bool {
+    COMPONENTS
+        .into_iter()
+        .any(|component| socket_addr(component).is_some())
+}
+
+/// Socket address of the proxy `component` connects through, if any
+pub fn socket_addr(component: Component) -> Option<SocketAddr> {
+    let proxy = proxy();
+    if proxy.refused.load(Ordering::Relaxed) {
+        return Some(UNREACHABLE);
+    }
+    let config = proxy.config.read();
+    let address = config.address_for(component)?;
+    match parse_address(address) {
+        Ok(addr) => Some(addr),
+        Err(e) => {
+            warn!("{}, refusing egress", e);
+            Some(UNREACHABLE)
+        }
+    }
+}
+
+/// `opts` connecting through the relay proxy, if any
+pub fn relay_options(opts: RelayOptions) -> RelayOptions {
+    match socket_addr(Component::Relays) {
+        Some(addr) => opts.connection_mode(ConnectionMode::Proxy(addr)),
+        None => opts,
+    }
+}
+
+/// Proxy for an HTTP client of `component`, if any. `socks5h` has the proxy
+/// resolve host names.
+pub fn reqwest_proxy(component: Component) -> Option<reqwest::Proxy> {
+    let addr = socket_addr(component)?;
+    Some(
+        reqwest::Proxy::all(format!("socks5h://{}", addr))
+            .expect("socket addresses make valid proxy URLs"),
+    )
+}
+
+/// `builder` with the proxy of `component` set
+pub fn apply(builder: reqwest::ClientBuilder, component: Component) -> reqwest::ClientBuilder {
+    match reqwest_proxy(component) {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    }
+}
+
+/// `builder` browsing through the web proxy, if any
+pub fn apply_webview<R: tauri::Runtime>(builder: WebviewBuilder<R>) -> WebviewBuilder<R> {
+    match socket_addr(Component::Web) {
+        Some(addr) => {
+            let url = tauri::Url::parse(&format!("socks5://{}", addr))
+                .expect("socket addresses make valid proxy URLs");
+            builder.proxy_url(url)
+        }
+        None => builder,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let mut config = ProxyConfig {
+            socks5: Some("127.0.0.1:9050".into()),
+            ..Default::default()
+        };
+        for component in [Component::Relays, Component::Blossom, Component::Web] {
+            assert_eq!(config.address_for(component), Some("127.0.0.1:9050"));
+        }
+
+        config.relays = Some(ProxyOverride::Direct);
+        config.blossom = Some(ProxyOverride::Socks5("127.0.0.1:1080".into()));
+        assert_eq!(config.address_for(Component::Relays), None);
+        assert_eq!(
+            config.address_for(Component::Blossom),
+            Some("127.0.0.1:1080")
+        );
+        assert_eq!(config.address_for(Component::Web), Some("127.0.0.1:9050"));
+
+        config.socks5 = None;
+        assert_eq!(config.address_for(Component::Web), None);
+        assert_eq!(
+            config.address_for(Component::Blossom),
+            Some("127.0.0.1:1080")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ProxyConfig::default().validate().is_ok());
+        let config = |address: &str| ProxyConfig {
+            blossom: Some(ProxyOverride::Socks5(address.into())),
+            ..Default::default()
+        };
+        assert!(config("127.0.0.1:9050").validate().is_ok());
+        assert!(config("[::1]:9050").validate().is_ok());
+        assert!(config("localhost:9050").validate().is_ok());
+        assert!(config("127.0.0.1").validate().is_err());
+        assert!(config("127.0.0.1:notaport").validate().is_err());
+        assert!(config("localhost:notaport").validate().is_err());
+        // Would need a DNS lookup
+        assert!(config("proxy.example:1080").validate().is_err());
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let config = ProxyConfig {
+            socks5: Some("127.0.0.1:9050".into()),
+            relays: Some(ProxyOverride::Direct),
+            blossom: Some(ProxyOverride::Socks5("127.0.0.1:1080".into())),
+        };
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "socks5": "127.0.0.1:9050",
+                "relays": {"mode": "direct"},
+                "blossom": {"mode": "socks5", "address": "127.0.0.1:1080"},
+            })
+        );
+        assert_eq!(serde_json::from_value::<ProxyConfig>(json).unwrap(), config);
+        assert_eq!(
+            serde_json::from_str::<ProxyConfig>("{}").unwrap(),
+            ProxyConfig::default()
+        );
+    }
+}
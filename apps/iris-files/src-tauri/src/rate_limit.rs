@@ -0,0 +1,285 @@
+//! Per-origin rate limits for the local HTTP server
+//!
+//! Embedded webviews talk to the signer and relays through /nip07 and /relay
+//! on the loopback server, so every request is counted against its `Origin`
+//! in fixed one-minute windows. Signing and publishing get their own, much
+//! smaller budgets on top of the route budget.
+//!
+//! Limits can be overridden per origin in `rate_limits.json` in the data dir:
+//!
+//! ```json
+//! {
+//!   "default": { "signEvent": 20 },
+//!   "origins": { "htree://npub1.../app": { "publish": 5 } }
+//! }
+//! ```
+//!
+//! Values are requests per minute; 0 disables the limit.
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// File in the data dir holding per-origin overrides
+const CONFIG_FILE: &str = "rate_limits.json";
+
+/// Length of one counting window
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Buckets tracked before expired windows are swept
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Origin used for requests that don't send an `Origin` header
+const NO_ORIGIN: &str = "local";
+
+/// What a request is counted as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LimitClass {
+    /// /htree, /hls and /profile file reads
+    Htree,
+    /// Any /nip07 call
+    Nip07,
+    /// /nip07 signEvent
+    SignEvent,
+    /// /relay WebSocket connections
+    Relay,
+    /// EVENT messages sent through the relay proxy
+    Publish,
+}
+
+impl LimitClass {
+    /// Requests per minute when nothing is configured
+    pub fn default_per_minute(self) -> u32 {
+        match self {
+            // Video players issue many small range requests
+            LimitClass::Htree => 3000,
+            LimitClass::Nip07 => 600,
+            LimitClass::SignEvent => 30,
+            LimitClass::Relay => 60,
+            LimitClass::Publish => 60,
+        }
+    }
+
+    /// Route-level class for a request path, if it's limited
+    pub fn for_path(path: &str) -> Option<Self> {
+        if path.starts_with("/htree/") || path.starts_with("/hls/") || path.starts_with("/profile/")
+        {
+            Some(LimitClass::Htree)
+        } else if path == "/nip07" {
+            Some(LimitClass::Nip07)
+        } else if path == "/relay" {
+            Some(LimitClass::Relay)
+        } else {
+            None
+        }
+    }
+}
+
+/// Configured limits, in requests per minute
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Overrides of the built-in defaults for every origin
+    #[serde(default)]
+    pub default: HashMap<LimitClass, u32>,
+    /// Overrides for single origins, taking precedence over `default`
+    #[serde(default)]
+    pub origins: HashMap<String, HashMap<LimitClass, u32>>,
+}
+
+impl RateLimitConfig {
+    /// Read `rate_limits.json` from `data_dir`, falling back to defaults
+    pub fn load(data_dir: &Path) -> Self {
+        let path = data_dir.join(CONFIG_FILE);
+        let Ok(data) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_str(&data) {
+            Ok(config) => {
+                info!("Loaded rate limits from {:?}", path);
+                config
+            }
+            Err(e) => {
+                warn!("Ignoring invalid {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Requests per minute allowed for `origin` in `class` (0 = unlimited)
+    pub fn limit(&self, origin: &str, class: LimitClass) -> u32 {
+        self.origins
+            .get(origin)
+            .and_then(|limits| limits.get(&class))
+            .or_else(|| self.default.get(&class))
+            .copied()
+            .unwrap_or_else(|| class.default_per_minute())
+    }
+}
+
+/// Counts requests per (origin, class) in fixed windows
+pub struct OriginLimiter {
+    config: RateLimitConfig,
+    /// (origin, class) -> (window start, requests in window)
+    buckets: Mutex<HashMap<(String, LimitClass), (Instant, u32)>>,
+}
+
+impl OriginLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request at `now`; on rejection returns how long until the
+    /// origin's window resets
+    pub fn check(&self, origin: &str, class: LimitClass, now: Instant) -> Result<(), Duration> {
+        let limit = self.config.limit(origin, class);
+        if limit == 0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= SWEEP_THRESHOLD {
+            buckets.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        }
+
+        let (start, count) = buckets
+            .entry((origin.to_string(), class))
+            .or_insert((now, 0));
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= limit {
+            return Err(WINDOW - now.duration_since(*start));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+static GLOBAL_LIMITER: OnceCell<OriginLimiter> = OnceCell::new();
+
+/// Load the configured limits; must run before the server starts to take effect
+pub fn init_rate_limits(data_dir: &Path) {
+    let _ = GLOBAL_LIMITER.get_or_init(|| OriginLimiter::new(RateLimitConfig::load(data_dir)));
+}
+
+/// The server-wide limiter (built-in defaults if never initialized)
+pub fn limiter() -> &'static OriginLimiter {
+    GLOBAL_LIMITER.get_or_init(|| OriginLimiter::new(RateLimitConfig::default()))
+}
+
+/// Origin a request is counted against
+pub fn request_origin(headers: &HeaderMap) -> &str {
+    headers
+        .get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .filter(|origin| !origin.is_empty())
+        .unwrap_or(NO_ORIGIN)
+}
+
+/// 429 response telling the client when to retry
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+    response.headers_mut().insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(retry_after.as_secs().max(1)),
+    );
+    response
+}
+
+/// Middleware counting /htree, /hls, /profile, /nip07 and /relay requests per origin
+pub async fn rate_limit_middleware(request: Request, next: Next) -> Response {
+    if let Some(class) = LimitClass::for_path(request.uri().path()) {
+        let origin = request_origin(request.headers());
+        if let Err(retry_after) = limiter().check(origin, class, Instant::now()) {
+            warn!("Rate limited {} on {:?}", origin, class);
+            return too_many_requests(retry_after);
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_per_origin_and_class() {
+        let mut config = RateLimitConfig::default();
+        config.default.insert(LimitClass::SignEvent, 2);
+        let limiter = OriginLimiter::new(config);
+        let t0 = Instant::now();
+
+        assert!(limiter.check("a", LimitClass::SignEvent, t0).is_ok());
+        assert!(limiter.check("a", LimitClass::SignEvent, t0).is_ok());
+        let retry = limiter
+            .check("a", LimitClass::SignEvent, t0 + Duration::from_secs(20))
+            .unwrap_err();
+        assert_eq!(retry, Duration::from_secs(40));
+        // Other origins and classes have their own budget
+        assert!(limiter.check("b", LimitClass::SignEvent, t0).is_ok());
+        assert!(limiter.check("a", LimitClass::Nip07, t0).is_ok());
+        // A new window resets the count
+        assert!(limiter
+            .check("a", LimitClass::SignEvent, t0 + WINDOW)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_origin_overrides() {
+        let config: RateLimitConfig = serde_json::from_str(
+            r#"{
+                "default": { "publish": 10 },
+                "origins": { "htree://app": { "publish": 0, "signEvent": 1 } }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.limit("other", LimitClass::Publish), 10);
+        assert_eq!(config.limit("other", LimitClass::SignEvent), 30);
+        assert_eq!(config.limit("htree://app", LimitClass::SignEvent), 1);
+        assert_eq!(config.limit("htree://app", LimitClass::Htree), 3000);
+
+        // 0 disables the limit
+        let limiter = OriginLimiter::new(config);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(limiter
+                .check("htree://app", LimitClass::Publish, now)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn test_classes_for_paths() {
+        assert_eq!(
+            LimitClass::for_path("/htree/nhash1abc/a.mp4"),
+            Some(LimitClass::Htree)
+        );
+        assert_eq!(
+            LimitClass::for_path("/hls/seg0.ts"),
+            Some(LimitClass::Htree)
+        );
+        assert_eq!(
+            LimitClass::for_path("/profile/npub1abc/picture"),
+            Some(LimitClass::Htree)
+        );
+        assert_eq!(LimitClass::for_path("/nip07"), Some(LimitClass::Nip07));
+        assert_eq!(LimitClass::for_path("/relay"), Some(LimitClass::Relay));
+        assert_eq!(LimitClass::for_path("/search"), None);
+    }
+}
@@ -0,0 +1,1329 @@
+//! Reader-mode extraction of articles from HTML
+//!
+//! `POST /extract` on the local server takes a page's HTML, or an htree path
+//! to an HTML file, and returns the main article as cleaned HTML and as
+//! markdown, for a reader view of documents stored in trees.
+//!
+//! The extraction follows readability: scripts, styles, navigation and
+//! elements whose class or id look like sidebars, comments or ads are
+//! dropped; paragraphs score their parent and grandparent by length and
+//! commas; the best scoring container, discounted by its link density, is the
+//! article, joined by siblings that score close to it. The article is written
+//! out with a small set of tags and attributes and sanitized with ammonia, so
+//! it can be shown without the page's scripts or styling. Links and images
+//! keep only relative, http(s) and mailto URLs.
+
+use ammonia::Builder;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Largest document extracted, in bytes
+pub const MAX_EXTRACT_BYTES: usize = 8 * 1024 * 1024;
+
+/// Deeper elements are flattened into their ancestor at this depth
+const MAX_DEPTH: usize = 256;
+
+/// Paragraphs shorter than this don't score their ancestors
+const MIN_PARAGRAPH_CHARS: usize = 25;
+
+/// Elements that never open a subtree
+const VOID_TAGS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is read as raw text up to the closing tag
+const RAW_TEXT_TAGS: &[&str] = &["script", "style", "textarea", "title", "noscript", "xmp"];
+
+/// Elements dropped with their content
+const DROP_TAGS: &[&str] = &[
+    "script", "style", "noscript", "template", "nav", "aside", "footer", "form", "button", "input",
+    "select", "textarea", "iframe", "object", "embed", "svg", "canvas", "head", "title", "meta",
+    "link", "dialog",
+];
+
+/// Elements closing an open `<p>`
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "div",
+    "dl",
+    "dd",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+/// Tags kept in the output; others are replaced by their content
+const KEEP_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// URL schemes links and images may have
+const URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Class and id words of sidebars, comments, ads and the like
+const UNLIKELY_WORDS: &[&str] = &[
+    "ad",
+    "ads",
+    "advert",
+    "banner",
+    "breadcrumb",
+    "comment",
+    "comments",
+    "cookie",
+    "disqus",
+    "footer",
+    "gdpr",
+    "header",
+    "menu",
+    "nav",
+    "newsletter",
+    "popup",
+    "promo",
+    "related",
+    "remark",
+    "replies",
+    "share",
+    "sharing",
+    "sidebar",
+    "social",
+    "sponsor",
+    "subscribe",
+    "widget",
+];
+
+/// Class and id words that keep an element despite an unlikely word
+const LIKELY_WORDS: &[&str] = &[
+    "article", "body", "column", "content", "main", "post", "story",
+];
+
+/// Class and id words scoring an element up
+const POSITIVE_WORDS: &[&str] = &[
+    "article", "blog", "body", "content", "entry", "hentry", "main", "page", "post", "story",
+    "text",
+];
+
+/// Class and id words scoring an element down
+const NEGATIVE_WORDS: &[&str] = &[
+    "ad", "banner", "comment", "footer", "footnote", "hidden", "masthead", "menu", "meta", "nav",
+    "popup", "promo", "related", "share", "shoutbox", "sidebar", "social", "sponsor", "widget",
+];
+
+/// The main article of a page
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Article {
+    pub title: Option<String>,
+    pub byline: Option<String>,
+    /// Page description, or the article's first paragraph
+    pub excerpt: Option<String>,
+    /// Cleaned article HTML
+    pub html: String,
+    pub markdown: String,
+    /// Characters of article text
+    pub text_length: usize,
+}
+
+/// Extract the main article of an HTML document
+pub fn extract(html: &str) -> Article {
+    let doc = Document::parse(html);
+    let analysis = Analysis::new(&doc);
+    let parts = analysis.article_parts();
+
+    let mut out_html = String::new();
+    let mut blocks = Vec::new();
+    for &id in &parts {
+        analysis.write_html(id, &mut out_html);
+        analysis.md_blocks(id, &mut blocks);
+    }
+    let text_length = parts.iter().map(|&id| analysis.text_len[id]).sum();
+    let excerpt = doc
+        .meta(&["description", "og:description"])
+        .or_else(|| analysis.first_paragraph(&parts));
+
+    Article {
+        title: doc
+            .meta(&["og:title"])
+            .or_else(|| doc.first_text("title"))
+            .or_else(|| doc.first_text("h1")),
+        byline: doc.meta(&["author"]),
+        excerpt,
+        html: sanitize(&out_html),
+        markdown: blocks.join("\n\n"),
+        text_length,
+    }
+}
+
+struct Element {
+    tag: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+    parent: usize,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+enum Node {
+    Element(usize),
+    Text(String),
+}
+
+/// Parsed element tree; element 0 is the document root and parents always
+/// come before their children
+struct Document {
+    elements: Vec<Element>,
+}
+
+enum Token<'a> {
+    Open {
+        tag: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    Close(String),
+    Text(&'a str),
+}
+
+impl Document {
+    fn parse(html: &str) -> Self {
+        let mut doc = Document {
+            elements: vec![Element {
+                tag: "#root".into(),
+                attrs: Vec::new(),
+                children: Vec::new(),
+                parent: 0,
+            }],
+        };
+        let mut stack = vec![0usize];
+        for token in tokenize(html) {
+            let top = *stack.last().unwrap_or(&0);
+            match token {
+                Token::Text(text) => {
+                    let text = decode_entities(text);
+                    if !text.is_empty() {
+                        doc.elements[top].children.push(Node::Text(text));
+                    }
+                }
+                Token::Open {
+                    tag,
+                    attrs,
+                    self_closing,
+                } => {
+                    let top_tag = doc.elements[top].tag.as_str();
+                    let implied_close = (top_tag == "p" && BLOCK_TAGS.contains(&tag.as_str()))
+                        || (top_tag == "li" && tag == "li")
+                        || (matches!(top_tag, "td" | "th") && matches!(tag.as_str(), "td" | "th"))
+                        || (matches!(top_tag, "dt" | "dd") && matches!(tag.as_str(), "dt" | "dd"));
+                    if implied_close && stack.len() > 1 {
+                        stack.pop();
+                    }
+                    let parent = *stack.last().unwrap_or(&0);
+                    let id = doc.elements.len();
+                    let opens = !self_closing && !VOID_TAGS.contains(&tag.as_str());
+                    doc.elements.push(Element {
+                        tag,
+                        attrs,
+                        children: Vec::new(),
+                        parent,
+                    });
+                    doc.elements[parent].children.push(Node::Element(id));
+                    if opens && stack.len() < MAX_DEPTH {
+                        stack.push(id);
+                    }
+                }
+                Token::Close(tag) => {
+                    if let Some(pos) = stack.iter().rposition(|&id| doc.elements[id].tag == tag) {
+                        if pos > 0 {
+                            stack.truncate(pos);
+                        }
+                    }
+                }
+            }
+        }
+        doc
+    }
+
+    /// Content of the first `<meta>` with one of `names` as name or property
+    fn meta(&self, names: &[&str]) -> Option<String> {
+        names.iter().find_map(|name| {
+            self.elements
+                .iter()
+                .filter(|e| e.tag == "meta")
+                .find(|e| e.attr("name").or(e.attr("property")) == Some(*name))
+                .and_then(|e| e.attr("content"))
+                .map(collapse_whitespace)
+                .filter(|s| !s.is_empty())
+        })
+    }
+
+    /// Text of the first `tag` element
+    fn first_text(&self, tag: &str) -> Option<String> {
+        let id = self.elements.iter().position(|e| e.tag == tag)?;
+        let mut text = String::new();
+        self.collect_text(id, &mut text);
+        Some(collapse_whitespace(&text)).filter(|s| !s.is_empty())
+    }
+
+    fn collect_text(&self, id: usize, out: &mut String) {
+        for child in &self.elements[id].children {
+            match child {
+                Node::Text(text) => out.push_str(text),
+                Node::Element(child) => self.collect_text(*child, out),
+            }
+        }
+    }
+}
+
+/// Split HTML into tags and text; comments, doctypes and processing
+/// instructions are skipped
+fn tokenize(html: &str) -> Vec<Token<'_>> {
+    let bytes = html.as_bytes();
+    let lower = html.to_ascii_lowercase();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let Some(lt) = html[i..].find('<').map(|p| i + p) else {
+            tokens.push(Token::Text(&html[i..]));
+            break;
+        };
+        if lt > i {
+            tokens.push(Token::Text(&html[i..lt]));
+        }
+        let rest = &html[lt..];
+        if rest.starts_with("<!--") {
+            i = rest.find("-->").map_or(bytes.len(), |p| lt + p + 3);
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            i = rest.find('>').map_or(bytes.len(), |p| lt + p + 1);
+        } else if let Some(close) = rest.strip_prefix("</") {
+            let name = tag_name(close);
+            i = rest.find('>').map_or(bytes.len(), |p| lt + p + 1);
+            if !name.is_empty() {
+                tokens.push(Token::Close(name));
+            }
+        } else if bytes.get(lt + 1).is_some_and(u8::is_ascii_alphabetic) {
+            let tag = tag_name(&rest[1..]);
+            let (attrs, self_closing, end) = parse_attrs(html, lt + 1 + tag.len());
+            i = end;
+            let raw = RAW_TEXT_TAGS.contains(&tag.as_str()) && !self_closing;
+            tokens.push(Token::Open {
+                tag: tag.clone(),
+                attrs,
+                self_closing,
+            });
+            if raw {
+                let close = format!("</{}", tag);
+                let text_end = lower[i..].find(&close).map_or(bytes.len(), |p| i + p);
+                if text_end > i {
+                    tokens.push(Token::Text(&html[i..text_end]));
+                }
+                i = html[text_end..]
+                    .find('>')
+                    .map_or(bytes.len(), |p| text_end + p + 1);
+                tokens.push(Token::Close(tag));
+            }
+        } else {
+            tokens.push(Token::Text(&html[lt..lt + 1]));
+            i = lt + 1;
+        }
+    }
+    tokens
+}
+
+fn tag_name(s: &str) -> String {
+    s.chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == ':')
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Parse attributes starting at `start`, up to the end of the tag; returns
+/// them, whether the tag was self-closing and the index after it
+fn parse_attrs(html: &str, start: usize) -> (Vec<(String, String)>, bool, usize) {
+    let bytes = html.as_bytes();
+    let mut attrs = Vec::new();
+    let mut i = start;
+    loop {
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        match bytes.get(i) {
+            None => return (attrs, false, bytes.len()),
+            Some(b'>') => return (attrs, false, i + 1),
+            Some(b'/') if bytes.get(i + 1) == Some(&b'>') => return (attrs, true, i + 2),
+            Some(b'/') => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let name_start = i;
+        while bytes
+            .get(i)
+            .is_some_and(|b| !b.is_ascii_whitespace() && !matches!(b, b'=' | b'>' | b'/'))
+        {
+            i += 1;
+        }
+        if i == name_start {
+            // Stray '=' or the like
+            i += 1;
+            continue;
+        }
+        let name = html[name_start..i].to_ascii_lowercase();
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        let mut value = String::new();
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+                i += 1;
+            }
+            match bytes.get(i) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let end = html[i + 1..]
+                        .find(quote as char)
+                        .map_or(bytes.len(), |p| i + 1 + p);
+                    value = decode_entities(&html[i + 1..end]);
+                    i = (end + 1).min(bytes.len());
+                }
+                _ => {
+                    let value_start = i;
+                    while bytes
+                        .get(i)
+                        .is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>')
+                    {
+                        i += 1;
+                    }
+                    value = decode_entities(&html[value_start..i]);
+                }
+            }
+        }
+        attrs.push((name, value));
+    }
+}
+
+/// Decode the character references common in text
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| decode_entity(&rest[1..1 + end]).map(|c| (c, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(num) = name.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "laquo" => '«',
+        "raquo" => '»',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "middot" => '·',
+        "bull" => '•',
+        _ => return None,
+    })
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether a class/id token list contains one of `words`; longer words also
+/// match inside tokens ("sidebar-left", "commentlist")
+fn has_word(tokens: &[String], words: &[&str]) -> bool {
+    words.iter().any(|word| {
+        tokens
+            .iter()
+            .any(|t| t == word || (word.len() >= 5 && t.contains(word)))
+    })
+}
+
+/// Scores and text statistics of a parsed document
+struct Analysis<'a> {
+    doc: &'a Document,
+    removed: Vec<bool>,
+    /// Characters of text below each element, excluding removed ones
+    text_len: Vec<usize>,
+    /// Of which inside links
+    link_len: Vec<usize>,
+    /// Images below each element
+    images: Vec<usize>,
+    scores: HashMap<usize, f64>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(doc: &'a Document) -> Self {
+        let n = doc.elements.len();
+        let mut removed = vec![false; n];
+        for id in 1..n {
+            let element = &doc.elements[id];
+            removed[id] = removed[element.parent] || Self::is_unwanted(element);
+        }
+
+        let mut text_len = vec![0usize; n];
+        let mut link_len = vec![0usize; n];
+        let mut images = vec![0usize; n];
+        for id in (0..n).rev() {
+            if removed[id] {
+                continue;
+            }
+            let element = &doc.elements[id];
+            let own: usize = element
+                .children
+                .iter()
+                .filter_map(|c| match c {
+                    Node::Text(text) => Some(collapse_whitespace(text).chars().count()),
+                    Node::Element(_) => None,
+                })
+                .sum();
+            text_len[id] += own;
+            if element.tag == "a" {
+                link_len[id] = text_len[id];
+            }
+            if element.tag == "img" {
+                images[id] += 1;
+            }
+            if id > 0 {
+                let parent = element.parent;
+                text_len[parent] += text_len[id];
+                link_len[parent] += link_len[id];
+                images[parent] += images[id];
+            }
+        }
+
+        let mut analysis = Analysis {
+            doc,
+            removed,
+            text_len,
+            link_len,
+            images,
+            scores: HashMap::new(),
+        };
+        analysis.score_paragraphs();
+        analysis
+    }
+
+    fn class_tokens(element: &Element) -> Vec<String> {
+        let mut names = element
+            .attr("class")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        names.push(' ');
+        names.push_str(&element.attr("id").unwrap_or_default().to_ascii_lowercase());
+        names
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Dropped tags, hidden elements and unlikely candidates
+    fn is_unwanted(element: &Element) -> bool {
+        if DROP_TAGS.contains(&element.tag.as_str()) {
+            return true;
+        }
+        let style = element
+            .attr("style")
+            .unwrap_or_default()
+            .to_ascii_lowercase()
+            .replace(' ', "");
+        if element.attr("hidden").is_some()
+            || element.attr("aria-hidden") == Some("true")
+            || style.contains("display:none")
+            || style.contains("visibility:hidden")
+        {
+            return true;
+        }
+        if matches!(
+            element.tag.as_str(),
+            "html" | "body" | "article" | "main" | "a"
+        ) {
+            return false;
+        }
+        let tokens = Self::class_tokens(element);
+        has_word(&tokens, UNLIKELY_WORDS) && !has_word(&tokens, LIKELY_WORDS)
+    }
+
+    fn class_weight(element: &Element) -> f64 {
+        let tokens = Self::class_tokens(element);
+        let mut weight = 0.0;
+        if has_word(&tokens, POSITIVE_WORDS) {
+            weight += 25.0;
+        }
+        if has_word(&tokens, NEGATIVE_WORDS) {
+            weight -= 25.0;
+        }
+        weight
+    }
+
+    fn initial_score(element: &Element) -> f64 {
+        let tag_score = match element.tag.as_str() {
+            "div" | "article" | "section" | "main" => 5.0,
+            "pre" | "td" | "blockquote" => 3.0,
+            "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+            _ => 0.0,
+        };
+        tag_score + Self::class_weight(element)
+    }
+
+    fn link_density(&self, id: usize) -> f64 {
+        match self.text_len[id] {
+            0 => 0.0,
+            len => self.link_len[id] as f64 / len as f64,
+        }
+    }
+
+    /// Elements holding text directly: paragraphs, and divs without blocks
+    fn is_paragraph(&self, id: usize) -> bool {
+        let element = &self.doc.elements[id];
+        match element.tag.as_str() {
+            "p" | "pre" | "td" | "blockquote" => true,
+            "div" => !element.children.iter().any(|c| {
+                matches!(c, Node::Element(child)
+                    if BLOCK_TAGS.contains(&self.doc.elements[*child].tag.as_str()))
+            }),
+            _ => false,
+        }
+    }
+
+    fn score_paragraphs(&mut self) {
+        for id in 1..self.doc.elements.len() {
+            if self.removed[id] || self.text_len[id] < MIN_PARAGRAPH_CHARS || !self.is_paragraph(id)
+            {
+                continue;
+            }
+            let mut text = String::new();
+            self.doc.collect_text(id, &mut text);
+            let commas = text.matches([',', '，']).count();
+            let score = 1.0 + commas as f64 + (self.text_len[id] as f64 / 100.0).floor().min(3.0);
+
+            let parent = self.doc.elements[id].parent;
+            let grandparent = self.doc.elements[parent].parent;
+            for (ancestor, divider) in [(parent, 1.0), (grandparent, 2.0)] {
+                if ancestor == 0 || (ancestor == grandparent && parent == 0) {
+                    continue;
+                }
+                let element = &self.doc.elements[ancestor];
+                *self
+                    .scores
+                    .entry(ancestor)
+                    .or_insert_with(|| Self::initial_score(element)) += score / divider;
+            }
+        }
+    }
+
+    fn final_score(&self, id: usize) -> Option<f64> {
+        self.scores
+            .get(&id)
+            .map(|score| score * (1.0 - self.link_density(id)))
+    }
+
+    /// The best container and siblings scoring close to it, in page order
+    fn article_parts(&self) -> Vec<usize> {
+        let top = self
+            .scores
+            .keys()
+            .filter_map(|&id| self.final_score(id).map(|score| (id, score)))
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)));
+        let Some((top, top_score)) = top else {
+            let body = self.doc.elements.iter().position(|e| e.tag == "body");
+            return vec![body.unwrap_or(0)];
+        };
+
+        let parent = self.doc.elements[top].parent;
+        if parent == 0 {
+            return vec![top];
+        }
+        let threshold = (top_score * 0.2).max(10.0);
+        self.doc.elements[parent]
+            .children
+            .iter()
+            .filter_map(|c| match c {
+                Node::Element(id) if !self.removed[*id] => Some(*id),
+                _ => None,
+            })
+            .filter(|&id| {
+                if id == top || self.final_score(id).is_some_and(|s| s >= threshold) {
+                    return true;
+                }
+                if self.doc.elements[id].tag != "p" {
+                    return false;
+                }
+                let len = self.text_len[id];
+                let density = self.link_density(id);
+                (len > 80 && density < 0.25)
+                    || (len > 0 && density == 0.0 && len <= 80 && {
+                        let mut text = String::new();
+                        self.doc.collect_text(id, &mut text);
+                        text.contains(". ") || text.trim_end().ends_with('.')
+                    })
+            })
+            .collect()
+    }
+
+    /// Whether an element of the article is written out
+    fn kept(&self, id: usize) -> bool {
+        if self.removed[id] {
+            return false;
+        }
+        let element = &self.doc.elements[id];
+        match element.tag.as_str() {
+            "br" | "hr" => true,
+            "img" => image_src(element).is_some(),
+            tag => {
+                if self.text_len[id] == 0 && self.images[id] == 0 {
+                    return false;
+                }
+                // Link lists and link-heavy boxes inside the article
+                let boxy = matches!(tag, "div" | "section" | "ul" | "ol" | "table" | "header");
+                !(boxy
+                    && (Self::class_weight(element) < 0.0
+                        || (self.link_density(id) > 0.5 && self.text_len[id] < 500)))
+            }
+        }
+    }
+
+    fn first_paragraph(&self, parts: &[usize]) -> Option<String> {
+        let mut pending: Vec<usize> = parts.iter().rev().copied().collect();
+        while let Some(id) = pending.pop() {
+            let element = &self.doc.elements[id];
+            if !self.kept(id) {
+                continue;
+            }
+            if element.tag == "p" {
+                let mut text = String::new();
+                self.doc.collect_text(id, &mut text);
+                let text = collapse_whitespace(&text);
+                if !text.is_empty() {
+                    return Some(text);
+                }
+            }
+            pending.extend(element.children.iter().rev().filter_map(|c| match c {
+                Node::Element(child) => Some(*child),
+                Node::Text(_) => None,
+            }));
+        }
+        None
+    }
+
+    fn write_html(&self, id: usize, out: &mut String) {
+        if !self.kept(id) {
+            return;
+        }
+        let element = &self.doc.elements[id];
+        let tag = match element.tag.as_str() {
+            "article" | "section" | "main" | "center" | "header" | "body" => "div",
+            tag if KEEP_TAGS.contains(&tag) => tag,
+            _ => {
+                self.write_children_html(id, out);
+                return;
+            }
+        };
+        out.push('<');
+        out.push_str(tag);
+        match tag {
+            "a" => {
+                if let Some(href) = element.attr("href").filter(|h| is_safe_url(h)) {
+                    push_attr(out, "href", href);
+                }
+            }
+            "img" => {
+                if let Some(src) = image_src(element) {
+                    push_attr(out, "src", src);
+                }
+                if let Some(alt) = element.attr("alt") {
+                    push_attr(out, "alt", alt);
+                }
+            }
+            "td" | "th" => {
+                for name in ["colspan", "rowspan"] {
+                    if let Some(value) = element.attr(name) {
+                        push_attr(out, name, value);
+                    }
+                }
+            }
+            _ => {}
+        }
+        out.push('>');
+        if matches!(tag, "br" | "hr" | "img") {
+            return;
+        }
+        self.write_children_html(id, out);
+        out.push_str("</");
+        out.push_str(tag);
+        out.push('>');
+    }
+
+    fn write_children_html(&self, id: usize, out: &mut String) {
+        for child in &self.doc.elements[id].children {
+            match child {
+                Node::Text(text) => escape_into(out, text, false),
+                Node::Element(child) => self.write_html(*child, out),
+            }
+        }
+    }
+
+    /// Markdown blocks of an element's content: runs of text and inline
+    /// elements become paragraphs, block elements their own blocks
+    fn md_blocks(&self, id: usize, out: &mut Vec<String>) {
+        if self.kept(id) && !self.md_block(id, out) {
+            self.md_children_blocks(id, out);
+        }
+    }
+
+    /// Write block elements with their own markdown syntax; false for
+    /// containers whose content is written as blocks
+    fn md_block(&self, id: usize, out: &mut Vec<String>) -> bool {
+        let element = &self.doc.elements[id];
+        match element.tag.as_str() {
+            tag @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                let level = tag[1..].parse().unwrap_or(1);
+                let mut text = String::new();
+                self.md_inline_children(id, &mut text);
+                let text = collapse_whitespace(&text);
+                if !text.is_empty() {
+                    out.push(format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "pre" => {
+                let mut text = String::new();
+                self.doc.collect_text(id, &mut text);
+                out.push(format!("```\n{}\n```", text.trim_matches('\n')));
+            }
+            "hr" => out.push("---".into()),
+            "blockquote" => {
+                let mut inner = Vec::new();
+                self.md_children_blocks(id, &mut inner);
+                let quoted = inner
+                    .join("\n\n")
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if !quoted.is_empty() {
+                    out.push(quoted);
+                }
+            }
+            tag @ ("ul" | "ol") => {
+                let mut items = Vec::new();
+                for child in &element.children {
+                    let Node::Element(child) = child else {
+                        continue;
+                    };
+                    if !self.kept(*child) {
+                        continue;
+                    }
+                    let marker = if tag == "ol" {
+                        format!("{}. ", items.len() + 1)
+                    } else {
+                        "- ".to_string()
+                    };
+                    let mut inner = Vec::new();
+                    self.md_children_blocks(*child, &mut inner);
+                    let indent = " ".repeat(marker.len());
+                    let item = inner
+                        .join("\n\n")
+                        .lines()
+                        .enumerate()
+                        .map(|(i, line)| match (i, line.is_empty()) {
+                            (0, _) => format!("{}{}", marker, line),
+                            (_, true) => String::new(),
+                            _ => format!("{}{}", indent, line),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    items.push(item);
+                }
+                if !items.is_empty() {
+                    out.push(items.join("\n"));
+                }
+            }
+            "table" => {
+                let rows = self.table_rows(id);
+                if let Some(first) = rows.first() {
+                    let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(1);
+                    let line = |cells: &[String]| {
+                        let mut cells = cells.to_vec();
+                        cells.resize(columns, String::new());
+                        format!("| {} |", cells.join(" | "))
+                    };
+                    let mut lines = vec![line(first), line(&vec!["---".to_string(); columns])];
+                    lines.extend(rows[1..].iter().map(|row| line(row)));
+                    out.push(lines.join("\n"));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn md_children_blocks(&self, id: usize, out: &mut Vec<String>) {
+        let mut inline = String::new();
+        for child in &self.doc.elements[id].children {
+            match child {
+                Node::Text(text) => push_collapsed(&mut inline, text),
+                Node::Element(child) if !self.kept(*child) => {}
+                Node::Element(child)
+                    if BLOCK_TAGS.contains(&self.doc.elements[*child].tag.as_str()) =>
+                {
+                    flush_paragraph(&mut inline, out);
+                    self.md_blocks(*child, out);
+                }
+                Node::Element(child) => self.md_inline(*child, &mut inline),
+            }
+        }
+        flush_paragraph(&mut inline, out);
+    }
+
+    /// Cell texts of a table's rows, through thead/tbody/tfoot
+    fn table_rows(&self, id: usize) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        for child in &self.doc.elements[id].children {
+            let Node::Element(child) = child else {
+                continue;
+            };
+            match self.doc.elements[*child].tag.as_str() {
+                "thead" | "tbody" | "tfoot" => rows.extend(self.table_rows(*child)),
+                "tr" => {
+                    let cells = self.doc.elements[*child]
+                        .children
+                        .iter()
+                        .filter_map(|c| match c {
+                            Node::Element(cell)
+                                if matches!(self.doc.elements[*cell].tag.as_str(), "td" | "th") =>
+                            {
+                                let mut text = String::new();
+                                self.md_inline_children(*cell, &mut text);
+                                Some(collapse_whitespace(&text).replace('|', "\\|"))
+                            }
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    if !cells.is_empty() {
+                        rows.push(cells);
+                    }
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    fn md_inline(&self, id: usize, out: &mut String) {
+        if !self.kept(id) {
+            return;
+        }
+        let element = &self.doc.elements[id];
+        let wrap = match element.tag.as_str() {
+            "br" => {
+                out.push('\n');
+                return;
+            }
+            "img" => {
+                if let Some(src) = image_src(element) {
+                    let alt = collapse_whitespace(element.attr("alt").unwrap_or_default());
+                    out.push_str(&format!("![{}]({})", alt, src));
+                }
+                return;
+            }
+            "a" => {
+                let mut text = String::new();
+                self.md_inline_children(id, &mut text);
+                match element.attr("href").filter(|h| is_safe_url(h)) {
+                    Some(href) if !text.trim().is_empty() => {
+                        push_wrapped(out, &text, "[", &format!("]({})", href))
+                    }
+                    _ => out.push_str(&text),
+                }
+                return;
+            }
+            "em" | "i" => "*",
+            "strong" | "b" => "**",
+            "code" => "`",
+            _ => "",
+        };
+        let mut text = String::new();
+        self.md_inline_children(id, &mut text);
+        push_wrapped(out, &text, wrap, wrap);
+    }
+
+    fn md_inline_children(&self, id: usize, out: &mut String) {
+        for child in &self.doc.elements[id].children {
+            match child {
+                Node::Text(text) => push_collapsed(out, text),
+                Node::Element(child) => self.md_inline(*child, out),
+            }
+        }
+    }
+}
+
+/// `src` of an image, or its lazy-loading `data-src` when `src` is a
+/// placeholder
+fn image_src(element: &Element) -> Option<&str> {
+    let src = element
+        .attr("src")
+        .filter(|s| !s.is_empty() && !s.starts_with("data:"));
+    src.or_else(|| element.attr("data-src"))
+        .filter(|s| is_safe_url(s))
+}
+
+/// Whether `url` is relative or of an allowed scheme, read as browsers do:
+/// without outer spaces and controls, and with tabs and newlines removed
+fn is_safe_url(url: &str) -> bool {
+    let url: String = url
+        .trim_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    let scheme_end = url.find([':', '/', '?', '#']);
+    match scheme_end {
+        _ if url.is_empty() => false,
+        Some(end) if url[end..].starts_with(':') => {
+            URL_SCHEMES.contains(&url[..end].to_ascii_lowercase().as_str())
+        }
+        _ => true,
+    }
+}
+
+/// Clean the written article with ammonia, down to the kept tags and their
+/// attributes
+fn sanitize(html: &str) -> String {
+    let tag_attributes = HashMap::from([
+        ("a", HashSet::from(["href"])),
+        ("img", HashSet::from(["src", "alt"])),
+        ("td", HashSet::from(["colspan", "rowspan"])),
+        ("th", HashSet::from(["colspan", "rowspan"])),
+    ]);
+    Builder::default()
+        .tags(KEEP_TAGS.iter().copied().collect())
+        .generic_attributes(HashSet::new())
+        .tag_attributes(tag_attributes)
+        .url_schemes(URL_SCHEMES.iter().copied().collect())
+        .clean(html)
+        .to_string()
+}
+
+fn push_attr(out: &mut String, name: &str, value: &str) {
+    out.push(' ');
+    out.push_str(name);
+    out.push_str("=\"");
+    escape_into(out, value, true);
+    out.push('"');
+}
+
+fn escape_into(out: &mut String, text: &str, attr: bool) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attr => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Append text with whitespace runs collapsed to single spaces
+fn push_collapsed(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !out.ends_with(' ') && !out.ends_with('\n') {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+/// Append `text` between `open` and `close`, keeping its outer spaces outside
+fn push_wrapped(out: &mut String, text: &str, open: &str, close: &str) {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        out.push_str(text);
+        return;
+    }
+    if text.starts_with(' ') && !out.ends_with(' ') {
+        out.push(' ');
+    }
+    out.push_str(open);
+    out.push_str(trimmed);
+    out.push_str(close);
+    if text.ends_with(' ') {
+        out.push(' ');
+    }
+}
+
+/// Move collected inline markdown into a paragraph block; line breaks
+/// become hard breaks
+fn flush_paragraph(inline: &mut String, out: &mut Vec<String>) {
+    let paragraph = inline
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("  \n");
+    if !paragraph.is_empty() {
+        out.push(paragraph);
+    }
+    inline.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<!DOCTYPE html>
+<html><head>
+  <title>Ignored title</title>
+  <meta property="og:title" content="Growing tomatoes">
+  <meta name="author" content="Alice">
+  <script>var tracker = "<p>not text</p>";</script>
+</head><body>
+  <nav class="menu"><a href="/">Home</a> <a href="/blog">Blog</a></nav>
+  <div id="sidebar"><p>Subscribe to our newsletter, it is great, really, truly.</p></div>
+  <div class="post-content">
+    <h1>Growing tomatoes</h1>
+    <p>Tomatoes need sun, water, and patience. Plant them after the last frost, in rich soil.</p>
+    <p>Water them deeply, but not often, so the roots grow down &amp; the plants
+       stay strong through the summer.<br>Mulch helps.</p>
+    <ul><li>Sun</li><li>Water, <em>regularly</em></li></ul>
+    <p>See <a href="https://example.com/guide">the guide</a> or <a href="javascript:alert(1)">this</a>.</p>
+    <img src="tomato.jpg" alt="A tomato" onerror="alert(1)">
+    <div class="comments"><p>First! Great post, thanks, love it, wow, nice.</p></div>
+  </div>
+  <footer>Copyright, all rights reserved, forever and ever.</footer>
+</body></html>"#;
+
+    #[test]
+    fn test_extract_article() {
+        let article = extract(PAGE);
+        assert_eq!(article.title.as_deref(), Some("Growing tomatoes"));
+        assert_eq!(article.byline.as_deref(), Some("Alice"));
+        assert!(article
+            .excerpt
+            .as_deref()
+            .is_some_and(|e| e.starts_with("Tomatoes need sun")));
+
+        for dropped in [
+            "Home",
+            "newsletter",
+            "First!",
+            "Copyright",
+            "tracker",
+            "onerror",
+        ] {
+            assert!(!article.html.contains(dropped), "{}", dropped);
+            assert!(!article.markdown.contains(dropped), "{}", dropped);
+        }
+        assert!(article.html.contains("<h1>Growing tomatoes</h1>"));
+        assert!(article.html.contains("roots grow down &amp; the plants"));
+        assert!(article
+            .html
+            .contains(r#"<a href="https://example.com/guide" rel="noopener noreferrer">"#));
+        assert!(article
+            .html
+            .contains(r#"<a rel="noopener noreferrer">this</a>"#));
+        assert!(article
+            .html
+            .contains(r#"<img src="tomato.jpg" alt="A tomato">"#));
+        assert!(article.text_length > 150);
+
+        assert_eq!(
+            article.markdown,
+            "# Growing tomatoes\n\n\
+             Tomatoes need sun, water, and patience. Plant them after the last frost, in rich soil.\n\n\
+             Water them deeply, but not often, so the roots grow down & the plants stay strong through the summer.  \n\
+             Mulch helps.\n\n\
+             - Sun\n\
+             - Water, *regularly*\n\n\
+             See [the guide](https://example.com/guide) or this.\n\n\
+             ![A tomato](tomato.jpg)"
+        );
+    }
+
+    #[test]
+    fn test_unsafe_urls_are_dropped() {
+        let article = extract(
+            "<body><article>\
+             <p>Links of every kind, in a paragraph long enough, to be the article.</p>\
+             <p><a href=\"java&#9;script:alert(1)\">tab</a> \
+             <a href=\" JAVASCRIPT:alert(1)\">upper</a> \
+             <a href=\"data:text/html,<script>alert(1)</script>\">data</a> \
+             <a href=\"mailto:a@example.com\">mail</a> \
+             <a href=\"../page.html\">relative</a>\
+             <img data-src=\"data:image/svg+xml,<svg onload=alert(1)>\" alt=\"x\"></p>\
+             </article></body>",
+        );
+        for unsafe_url in ["script", "data:", "svg"] {
+            assert!(
+                !article.html.to_lowercase().contains(unsafe_url),
+                "{}",
+                article.html
+            );
+            assert!(
+                !article.markdown.to_lowercase().contains(unsafe_url),
+                "{}",
+                article.markdown
+            );
+        }
+        assert!(article.html.contains(r#"href="mailto:a@example.com""#));
+        assert!(article.html.contains(r#"href="../page.html""#));
+        assert!(article.markdown.contains("[mail](mailto:a@example.com)"));
+
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("\u{1}javascript:alert(1)"));
+        assert!(!is_safe_url("vbscript:msgbox"));
+        assert!(!is_safe_url(""));
+        assert!(is_safe_url("https://example.com/a:b"));
+        assert!(is_safe_url("/path?x=a:b"));
+        assert!(is_safe_url("#top"));
+    }
+
+    #[test]
+    fn test_markdown_blocks() {
+        let article = extract(
+            "<body><article>\
+             <p>Short intro to the table below, with a few commas, and words.</p>\
+             <blockquote><p>Quoted line one.</p><p>Line two.</p></blockquote>\
+             <pre>fn main() {\n    run();\n}</pre>\
+             <table><thead><tr><th>Name</th><th>Size</th></tr></thead>\
+             <tbody><tr><td>a|b</td><td>1</td></tr></tbody></table>\
+             <ol><li>First<ul><li>nested</li></ul></li><li>Second</li></ol>\
+             </article></body>",
+        );
+        assert_eq!(
+            article.markdown,
+            "Short intro to the table below, with a few commas, and words.\n\n\
+             > Quoted line one.\n>\n> Line two.\n\n\
+             ```\nfn main() {\n    run();\n}\n```\n\n\
+             | Name | Size |\n| --- | --- |\n| a\\|b | 1 |\n\n\
+             1. First\n\n   - nested\n2. Second"
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_edge_cases() {
+        let doc = Document::parse(
+            "<p class=x data-a='1 > 2'>a &lt;b&gt; &#x41;&#66; &bogus; & c<!-- <p>hidden --><p>next",
+        );
+        let paragraphs: Vec<_> = doc.elements.iter().filter(|e| e.tag == "p").collect();
+        assert_eq!(paragraphs.len(), 2);
+        assert_eq!(paragraphs[0].attr("data-a"), Some("1 > 2"));
+        // The second <p> closes the first instead of nesting in it
+        assert_eq!(paragraphs[1].parent, paragraphs[0].parent);
+        let mut text = String::new();
+        doc.collect_text(
+            doc.elements.iter().position(|e| e.tag == "p").unwrap(),
+            &mut text,
+        );
+        assert_eq!(text, "a <b> AB &bogus; & c");
+    }
+}
@@ -2,21 +2,28 @@
 //!
 //! Provides a NIP-01 WebSocket relay endpoint that proxies to remote relays.
 //! Apps can connect to ws://localhost:{port}/relay and use standard Nostr protocol.
+//! Events by pubkeys on our mute list aren't passed on.
 
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         State,
     },
+    http::HeaderMap,
     response::IntoResponse,
 };
 use futures::{SinkExt, StreamExt};
-use nostr_sdk::{Client, Event, Filter, Kind, RelayPoolNotification};
+use nostr_sdk::{Client, Event, Filter, Kind, RelayOptions, RelayPoolNotification};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
+use crate::htree::is_muted;
+use crate::proxy;
+use crate::rate_limit::{limiter, request_origin, LimitClass};
+
 /// Default relays to proxy to
 const DEFAULT_RELAYS: &[&str] = &[
     "wss://relay.damus.io",
@@ -44,7 +51,8 @@ impl RelayProxyState {
             info!("Initializing relay proxy client...");
             let client = Client::default();
             for relay in DEFAULT_RELAYS {
-                if let Err(e) = client.add_relay(*relay).await {
+                let opts = proxy::relay_options(RelayOptions::new());
+                if let Err(e) = client.add_relay_with_opts(*relay, opts).await {
                     warn!("Failed to add relay {}: {}", relay, e);
                 }
             }
@@ -56,6 +64,25 @@ impl RelayProxyState {
             Ok(guard.as_ref().unwrap().clone())
         }
     }
+
+    /// Reconnect the relays through the current proxy
+    pub async fn apply_proxy(&self) {
+        let Some(client) = self.client.read().await.clone() else {
+            return;
+        };
+        for relay in DEFAULT_RELAYS {
+            if let Err(e) = client.remove_relay(*relay).await {
+                warn!("Failed to remove relay {}: {}", relay, e);
+                continue;
+            }
+            let opts = proxy::relay_options(RelayOptions::new());
+            if let Err(e) = client.add_relay_with_opts(*relay, opts).await {
+                warn!("Failed to add relay {}: {}", relay, e);
+            }
+        }
+        client.connect().await;
+        info!("Relay proxy reconnecting {} relays", DEFAULT_RELAYS.len());
+    }
 }
 
 impl Default for RelayProxyState {
@@ -68,13 +95,16 @@ impl Default for RelayProxyState {
 pub async fn handle_relay_websocket(
     ws: WebSocketUpgrade,
     State(state): State<RelayProxyState>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_connection(socket, state))
+    // Publishes are counted against the origin that opened the socket
+    let origin = request_origin(&headers).to_string();
+    ws.on_upgrade(|socket| handle_connection(socket, state, origin))
 }
 
 /// Handle a single WebSocket connection
-async fn handle_connection(socket: WebSocket, state: RelayProxyState) {
-    info!("New relay proxy connection");
+async fn handle_connection(socket: WebSocket, state: RelayProxyState, origin: String) {
+    info!("New relay proxy connection from {}", origin);
 
     let client = match state.ensure_client().await {
         Ok(c) => c,
@@ -104,6 +134,9 @@ async fn handle_connection(socket: WebSocket, state: RelayProxyState) {
         while let Ok(notification) = notifications.recv().await {
             match notification {
                 RelayPoolNotification::Event { event, .. } => {
+                    if is_muted(&event.pubkey.to_hex()) {
+                        continue;
+                    }
                     // Check if this event matches any of our subscriptions
                     // For now, forward all events (proper filtering would check subscription filters)
                     let subs = subs_clone.read().await;
@@ -170,7 +203,9 @@ async fn handle_connection(socket: WebSocket, state: RelayProxyState) {
                 let text_str: &str = text.as_ref();
                 debug!("Relay proxy received: {}", text_str);
 
-                if let Err(e) = handle_message(text_str, &client, &subscriptions, &tx).await {
+                if let Err(e) =
+                    handle_message(text_str, &client, &subscriptions, &tx, &origin).await
+                {
                     warn!("Error handling message: {}", e);
                     let notice = serde_json::json!(["NOTICE", format!("Error: {}", e)]);
                     let _ = tx.send(notice.to_string()).await;
@@ -207,6 +242,7 @@ async fn handle_message(
     client: &Client,
     subscriptions: &Arc<RwLock<HashMap<String, nostr_sdk::SubscriptionId>>>,
     tx: &tokio::sync::mpsc::Sender<String>,
+    origin: &str,
 ) -> Result<(), String> {
     let parsed: serde_json::Value =
         serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {}", e))?;
@@ -273,6 +309,14 @@ async fn handle_message(
             let event: Event = serde_json::from_value(event_value.clone())
                 .map_err(|e| format!("Invalid event: {}", e))?;
 
+            if let Err(retry_after) = limiter().check(origin, LimitClass::Publish, Instant::now()) {
+                warn!("Relay proxy publish rate limited for {}", origin);
+                let reason = format!("rate-limited: retry in {}s", retry_after.as_secs().max(1));
+                let msg = serde_json::json!(["OK", event.id.to_hex(), false, reason]);
+                let _ = tx.send(msg.to_string()).await;
+                return Ok(());
+            }
+
             match client.send_event(event.clone()).await {
                 Ok(_output) => {
                     let msg = serde_json::json!(["OK", event.id.to_hex(), true, ""]);
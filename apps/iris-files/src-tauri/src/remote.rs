@@ -0,0 +1,200 @@
+//! Remote control API for paired devices
+//!
+//! A paired phone uses this node as its gateway: it lists and browses trees,
+//! uploads files, publishes and pushes to Blossom with this node's storage
+//! and bandwidth. Requests are the worker's own (see [`WorkerRequest`]),
+//! posted as JSON to [`REMOTE_PATH`] and answered with the worker's response:
+//!
+//! ```text
+//! POST /api/worker
+//! Authorization: Bearer <device token>
+//! {"type": "listDir", "id": "1", "cid": {"hash": "…"}}
+//! ```
+//!
+//! Only `readWrite` devices are served, and only the requests below: worker
+//! requests take raw CIDs, which can't be held to the trees a `read` device
+//! may see, so those read through `/htree` instead. Every request needs the
+//! token, from loopback too, as any page in a local browser can reach the
+//! port, and a `Host` naming this machine. On the other end,
+//! `remote_request` sends a request to a device this one paired with, over
+//! HTTPS only.
+
+use crate::pairing::Permission;
+use crate::worker::{WorkerRequest, WorkerState};
+use axum::{
+    body::Bytes,
+    http::{header, uri::Authority, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Manager;
+
+/// Path of the remote API
+pub const REMOTE_PATH: &str = "/api/worker";
+
+/// Largest request body, for uploads as base64 `writeFile` requests
+pub const MAX_REQUEST_BYTES: usize = 64 * 1024 * 1024;
+
+/// Requests are answered when done, so pushes of large trees take a while
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Requests a `readWrite` device may make
+const REMOTE_REQUESTS: &[&str] = &[
+    "ping",
+    "getJobs",
+    "getSyncStatus",
+    "listTrees",
+    "resolveRoot",
+    "getTreeLog",
+    "listDir",
+    "readFile",
+    "readFileRange",
+    "treeStats",
+    "writeFile",
+    "createDir",
+    "deleteFile",
+    "publishTree",
+    "pushToBlossom",
+];
+
+/// Whether a device with `permission` may make a request of `kind`
+pub fn permits(permission: Permission, kind: &str) -> bool {
+    permission == Permission::ReadWrite && REMOTE_REQUESTS.contains(&kind)
+}
+
+/// Whether `host` names this machine by address, as localhost or as a
+/// `.local` name, rather than a DNS name rebound to it
+fn host_allowed(host: &str) -> bool {
+    let Ok(authority) = host.parse::<Authority>() else {
+        return false;
+    };
+    let name = authority.host().to_ascii_lowercase();
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name.parse::<IpAddr>().is_ok() || name == "localhost" || name.ends_with(".local")
+}
+
+fn error(status: StatusCode, error: impl Into<String>) -> Response {
+    (status, Json(json!({ "error": error.into() }))).into_response()
+}
+
+/// `POST /api/worker`: run a worker request for a paired device
+pub async fn handle_remote_request(uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .or_else(|| uri.authority().map(Authority::as_str));
+    if !host.is_some_and(host_allowed) {
+        return error(StatusCode::FORBIDDEN, "Unexpected Host");
+    }
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    let Some(permission) = crate::pairing::device_permission(authorization) else {
+        let mut response = error(StatusCode::UNAUTHORIZED, "Device token required");
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        return response;
+    };
+    let request: WorkerRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)),
+    };
+    let kind = request.kind();
+    if !permits(permission, kind) {
+        return error(StatusCode::FORBIDDEN, format!("{} is not allowed", kind));
+    }
+
+    let Some(app) = crate::htree::app_handle() else {
+        return error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "App handle not initialized",
+        );
+    };
+    let Some(state) = app.try_state::<Arc<WorkerState>>() else {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "Worker not initialized");
+    };
+    match crate::worker::handle_request(request, app.clone(), state).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => error(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// Tauri command sending a worker request to the paired device `device_id`,
+/// returning its response
+#[tauri::command]
+pub async fn remote_request(
+    device_id: String,
+    request: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let remote = crate::pairing::remote_connection(&device_id, REQUEST_TIMEOUT)?;
+    // The token would go out in the clear
+    if !remote.url.starts_with("https://") {
+        return Err(format!(
+            "{} isn't reached over HTTPS; pair again",
+            remote.url
+        ));
+    }
+    let response = remote
+        .client
+        .post(format!("{}{}", remote.url, REMOTE_PATH))
+        .bearer_auth(&remote.token)
+        .json(&request)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach {}: {}", remote.url, e))?;
+    let status = response.status();
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response from {}: {}", remote.url, e))?;
+    if !status.is_success() {
+        let message = body["error"].as_str().unwrap_or("request failed");
+        return Err(format!("{}: {}", remote.url, message));
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permits() {
+        for kind in [
+            "listTrees",
+            "listDir",
+            "readFile",
+            "writeFile",
+            "publishTree",
+            "pushToBlossom",
+        ] {
+            assert!(!permits(Permission::Read, kind), "{}", kind);
+            assert!(permits(Permission::ReadWrite, kind), "{}", kind);
+        }
+        // Identity, signing and settings stay with this device
+        for kind in ["init", "setIdentity", "publish", "exportState", "setProxy"] {
+            assert!(!permits(Permission::ReadWrite, kind), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn test_host_allowed() {
+        for host in [
+            "127.0.0.1:21417",
+            "localhost:21417",
+            "[::1]:21417",
+            "192.168.1.20:21443",
+            "desktop.local",
+        ] {
+            assert!(host_allowed(host), "{}", host);
+        }
+        for host in ["evil.example.com", "localhost.evil.com:21417", "", "a b"] {
+            assert!(!host_allowed(host), "{}", host);
+        }
+    }
+}
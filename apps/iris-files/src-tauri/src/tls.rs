@@ -0,0 +1,325 @@
+//! HTTPS for LAN clients of the local server
+//!
+//! With `tls: true` in `acl.json` and the server bound beyond loopback, LAN
+//! clients are served over HTTPS (see [`crate::acl`]). Certificates come from
+//! a CA generated on first use and kept in `tls/` in the data dir; a server
+//! certificate for loopback and the LAN address is issued from it at every
+//! start, so a changed address needs no new pairing.
+//!
+//! To pair a device, check that the certificate chain it's shown ends at a CA
+//! with the fingerprint logged at startup (with a QR code of the pairing URL)
+//! and returned by `get_lan_tls`, or install `tls/ca.pem` on it. The CA is
+//! name-constrained to `localhost`, `.local` names and loopback, private and
+//! link-local addresses, so even a leaked `ca.key` can't vouch for other
+//! sites; the server is only announced on such addresses.
+
+use once_cell::sync::OnceCell;
+use rcgen::{
+    BasicConstraints, CertificateParams, CidrSubnet, DistinguishedName, DnType, GeneralSubtree,
+    IsCa, KeyPair, KeyUsagePurpose, NameConstraints, SanType,
+};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Directory in the data dir holding the CA
+const TLS_DIR: &str = "tls";
+
+const CA_CERT_FILE: &str = "ca.pem";
+
+const CA_KEY_FILE: &str = "ca.key";
+
+/// Name the CA and server certificates are issued to
+const COMMON_NAME: &str = "Iris Files";
+
+/// DNS names the CA may issue for, with their subdomains
+const PERMITTED_NAMES: &[&str] = &["localhost", "local"];
+
+/// Address ranges the CA may issue for: loopback, private and link-local
+const PERMITTED_SUBNETS: &[(IpAddr, u8)] = &[
+    (IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8),
+    (IpAddr::V4(Ipv4Addr::new(172, 16, 0, 0)), 12),
+    (IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 16),
+    (IpAddr::V4(Ipv4Addr::new(169, 254, 0, 0)), 16),
+    (IpAddr::V6(Ipv6Addr::LOCALHOST), 128),
+    (IpAddr::V6(Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0)), 7),
+    (IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0)), 10),
+];
+
+/// A server certificate and its key
+pub struct ServerCert {
+    /// Server certificate followed by the CA's
+    pub chain_pem: String,
+    pub key_pem: String,
+}
+
+/// The HTTPS listener as shown to the user for pairing
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TlsInfo {
+    pub url: String,
+    /// SHA-256 of the CA certificate, as colon-separated hex
+    pub fingerprint: String,
+    pub ca_pem: String,
+}
+
+/// CA issuing the server certificates
+pub struct LocalCa {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+    /// The certificate as stored; devices pin its fingerprint
+    pem: String,
+}
+
+impl LocalCa {
+    /// Load the CA from `dir`, generating it on first use
+    pub fn load_or_create(dir: &Path) -> Result<Self, String> {
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+        if let (Ok(pem), Ok(key_pem)) = (
+            std::fs::read_to_string(&cert_path),
+            std::fs::read_to_string(&key_path),
+        ) {
+            let key = KeyPair::from_pem(&key_pem).map_err(|e| format!("Invalid CA key: {}", e))?;
+            let params = CertificateParams::from_ca_cert_pem(&pem)
+                .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+            if params.name_constraints.is_some() {
+                // Signing with the same name and key, so certificates issued
+                // chain up to the stored one
+                let cert = params
+                    .self_signed(&key)
+                    .map_err(|e| format!("Invalid CA certificate: {}", e))?;
+                return Ok(Self { cert, key, pem });
+            }
+            warn!("Replacing the unconstrained local CA; paired devices need to pair again");
+        }
+
+        let mut params = CertificateParams::default();
+        params.distinguished_name = name(&format!("{} local CA", COMMON_NAME));
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.name_constraints = Some(NameConstraints {
+            permitted_subtrees: PERMITTED_NAMES
+                .iter()
+                .map(|name| GeneralSubtree::DnsName(name.to_string()))
+                .chain(PERMITTED_SUBNETS.iter().map(|(ip, prefix)| {
+                    GeneralSubtree::IpAddress(CidrSubnet::from_addr_prefix(*ip, *prefix))
+                }))
+                .collect(),
+            excluded_subtrees: Vec::new(),
+        });
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let key = KeyPair::generate().map_err(|e| e.to_string())?;
+        let cert = params.self_signed(&key).map_err(|e| e.to_string())?;
+        let pem = cert.pem();
+
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        write_private(&key_path, &key.serialize_pem())?;
+        std::fs::write(&cert_path, &pem)
+            .map_err(|e| format!("Failed to save {:?}: {}", cert_path, e))?;
+        info!("Generated local CA in {:?}", dir);
+        Ok(Self { cert, key, pem })
+    }
+
+    pub fn pem(&self) -> &str {
+        &self.pem
+    }
+
+    /// Issue a server certificate for loopback and `ips`, which clients only
+    /// accept for the addresses the CA is constrained to
+    pub fn issue(&self, ips: &[IpAddr]) -> Result<ServerCert, String> {
+        let mut params =
+            CertificateParams::new(vec!["localhost".to_string()]).map_err(|e| e.to_string())?;
+        params.distinguished_name = name(COMMON_NAME);
+        let loopback = [
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ];
+        for ip in loopback.iter().chain(ips) {
+            params.subject_alt_names.push(SanType::IpAddress(*ip));
+        }
+        let key = KeyPair::generate().map_err(|e| e.to_string())?;
+        let cert = params
+            .signed_by(&key, &self.cert, &self.key)
+            .map_err(|e| e.to_string())?;
+        Ok(ServerCert {
+            chain_pem: format!("{}{}", cert.pem(), self.pem),
+            key_pem: key.serialize_pem(),
+        })
+    }
+}
+
+fn name(common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+/// Write `data` readable by the owner only
+fn write_private(path: &Path, data: &str) -> Result<(), String> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("Failed to save {:?}: {}", path, e))?;
+    std::io::Write::write_all(&mut file, data.as_bytes())
+        .map_err(|e| format!("Failed to save {:?}: {}", path, e))
+}
+
+/// Load or generate the CA in the data dir
+pub fn local_ca(data_dir: &Path) -> Result<LocalCa, String> {
+    LocalCa::load_or_create(&data_dir.join(TLS_DIR))
+}
+
+/// SHA-256 of the first certificate in `pem`, as colon-separated hex
+pub fn fingerprint(pem: &str) -> Result<String, String> {
+    let der = pem::parse(pem).map_err(|e| format!("Invalid certificate: {}", e))?;
+    let hash = hashtree_core::sha256(der.contents());
+    Ok(hash
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+/// Whether the CA may issue for `ip`
+fn is_permitted_ip(ip: IpAddr) -> bool {
+    PERMITTED_SUBNETS
+        .iter()
+        .any(|(net, prefix)| match (*net, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+}
+
+/// Addresses of this machine on the LAN, as used to reach the internet:
+/// IPv4, then IPv6 if `ipv6`. Only addresses the CA may issue for count, so
+/// a public IPv6 address is skipped. No packets are sent.
+pub fn lan_ips(ipv6: bool) -> Vec<IpAddr> {
+    let probe = |bind: &str, target: &str| {
+        let socket = UdpSocket::bind(bind).ok()?;
+        socket.connect(target).ok()?;
+        let ip = socket.local_addr().ok()?.ip();
+        (!ip.is_unspecified() && !ip.is_loopback() && is_permitted_ip(ip)).then_some(ip)
+    };
+    let mut ips: Vec<IpAddr> = probe("0.0.0.0:0", "192.0.2.1:80").into_iter().collect();
+    if ipv6 {
+        ips.extend(probe("[::]:0", "[2001:db8::1]:80"));
+    }
+    ips
+}
+
+/// URL a device scans to pair: the server URL with the CA fingerprint
+pub fn pairing_url(info: &TlsInfo) -> String {
+    format!("{}/#sha256={}", info.url, info.fingerprint.replace(':', ""))
+}
+
+/// `text` as a QR code drawn with block characters
+fn qr_text(text: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(text.as_bytes()).ok()?;
+    Some(
+        code.render::<qrcode::render::unicode::Dense1x2>()
+            .quiet_zone(true)
+            .build(),
+    )
+}
+
+static TLS_INFO: OnceCell<TlsInfo> = OnceCell::new();
+
+/// Keep `info` for `get_lan_tls` and print what pairing takes
+pub fn announce(info: TlsInfo) {
+    let pairing = pairing_url(&info);
+    info!(
+        "Serving LAN clients over HTTPS at {}, CA fingerprint SHA-256 {}",
+        info.url, info.fingerprint
+    );
+    if let Some(qr) = qr_text(&pairing) {
+        info!("Scan to pair {}:\n{}", pairing, qr);
+    }
+    let _ = TLS_INFO.set(info);
+}
+
+/// Tauri command returning the HTTPS listener and the CA fingerprint to
+/// pair with, if LAN clients are served over HTTPS
+#[tauri::command]
+pub fn get_lan_tls() -> Option<TlsInfo> {
+    TLS_INFO.get().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ca_is_kept_and_issues_certs() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = LocalCa::load_or_create(dir.path()).unwrap();
+        let lan: IpAddr = "192.168.1.20".parse().unwrap();
+        let cert = ca.issue(&[lan]).unwrap();
+        assert_eq!(cert.chain_pem.matches("BEGIN CERTIFICATE").count(), 2);
+        assert!(cert.chain_pem.ends_with(ca.pem()));
+        assert!(cert.key_pem.contains("PRIVATE KEY"));
+
+        // The pinned fingerprint survives a restart
+        let fingerprint = fingerprint(ca.pem()).unwrap();
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+        let reloaded = LocalCa::load_or_create(dir.path()).unwrap();
+        assert_eq!(reloaded.pem(), ca.pem());
+        assert!(reloaded.issue(&[]).unwrap().chain_pem.ends_with(ca.pem()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let key = std::fs::metadata(dir.path().join(CA_KEY_FILE)).unwrap();
+            assert_eq!(key.permissions().mode() & 0o777, 0o600);
+        }
+
+        // The CA can't vouch for other sites
+        let params = CertificateParams::from_ca_cert_pem(ca.pem()).unwrap();
+        let constraints = params.name_constraints.unwrap();
+        assert!(constraints
+            .permitted_subtrees
+            .contains(&GeneralSubtree::DnsName("local".to_string())));
+    }
+
+    #[test]
+    fn test_permitted_ips() {
+        for ip in [
+            "192.168.1.20",
+            "10.1.2.3",
+            "172.31.0.1",
+            "fd12::1",
+            "fe80::1",
+            "::1",
+        ] {
+            assert!(is_permitted_ip(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "172.32.0.1", "2001:db8::1"] {
+            assert!(!is_permitted_ip(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_pairing_url() {
+        let info = TlsInfo {
+            url: "https://192.168.1.20:21443".to_string(),
+            fingerprint: "AB:CD:01".to_string(),
+            ca_pem: String::new(),
+        };
+        assert_eq!(
+            pairing_url(&info),
+            "https://192.168.1.20:21443/#sha256=ABCD01"
+        );
+        assert!(qr_text(&pairing_url(&info)).is_some());
+    }
+}
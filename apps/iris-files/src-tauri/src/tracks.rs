@@ -0,0 +1,371 @@
+//! Subtitle and audio track discovery for video folders
+//!
+//! Builds the manifest served by `/htree/<dir>?format=tracks`: the folder's
+//! video, its thumbnail, sidecar `.srt`/`.vtt` subtitles (next to the video or
+//! in a `Subs/` folder) and, when ffprobe is available, embedded subtitle and
+//! audio streams. Every subtitle gets a URL, relative to the folder, that
+//! serves WebVTT so the player can use it directly in a `<track>` element.
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+
+/// Folder names searched for sidecar subtitles
+pub const SUBTITLE_DIRS: &[&str] = &["subs", "Subs", "subtitles", "Subtitles"];
+
+const SUBTITLE_EXTENSIONS: &[&str] = &["srt", "vtt"];
+
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mkv", "mov", "avi", "m4v"];
+
+/// Embedded subtitle codecs ffmpeg can convert to WebVTT
+const TEXT_SUBTITLE_CODECS: &[&str] = &["subrip", "ass", "ssa", "webvtt", "mov_text", "text"];
+
+/// Characters escaped in relative URLs (path separators are kept)
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackSource {
+    Sidecar,
+    Embedded,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubtitleTrack {
+    /// URL relative to the folder that serves the track as WebVTT
+    pub url: String,
+    pub label: String,
+    pub language: Option<String>,
+    pub source: TrackSource,
+    /// ffprobe stream index for embedded tracks
+    pub stream_index: Option<u32>,
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioTrack {
+    pub stream_index: u32,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub codec: Option<String>,
+    pub channels: Option<u32>,
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoManifest {
+    /// Video path relative to the folder
+    pub video: Option<String>,
+    pub thumbnail: Option<String>,
+    pub subtitles: Vec<SubtitleTrack>,
+    pub audio_tracks: Vec<AudioTrack>,
+    /// Whether embedded streams were probed (requires ffprobe)
+    pub probed: bool,
+}
+
+fn extension(name: &str) -> Option<String> {
+    let (_, ext) = name.rsplit_once('.')?;
+    Some(ext.to_lowercase())
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+pub fn is_video_file(name: &str) -> bool {
+    extension(name).is_some_and(|ext| VIDEO_EXTENSIONS.contains(&ext.as_str()))
+}
+
+fn is_subtitle_file(name: &str) -> bool {
+    extension(name).is_some_and(|ext| SUBTITLE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Percent-encode a relative path for use in a URL
+pub fn encode_relative_url(path: &str) -> String {
+    utf8_percent_encode(path, PATH_SEGMENT).to_string()
+}
+
+/// Pick the folder's main video: `video.*` first, then the first by name
+fn pick_video(files: &[String]) -> Option<String> {
+    let mut videos: Vec<&String> = files
+        .iter()
+        .filter(|f| !f.contains('/') && is_video_file(f))
+        .collect();
+    videos.sort();
+    videos
+        .iter()
+        .find(|f| f.starts_with("video."))
+        .or_else(|| videos.first())
+        .map(|f| f.to_string())
+}
+
+/// Language code and label from a subtitle name like `movie.en.forced.srt`
+fn describe_sidecar(name: &str, video_stem: &str) -> (Option<String>, String) {
+    let stem = name.rsplit_once('.').map(|(s, _)| s).unwrap_or(name);
+    let rest = stem
+        .strip_prefix(video_stem)
+        .and_then(|r| r.strip_prefix('.'))
+        .unwrap_or(stem);
+    let parts: Vec<&str> = rest.split('.').filter(|p| !p.is_empty()).collect();
+    let language = parts
+        .iter()
+        .find(|p| (2..=3).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(|p| p.to_lowercase());
+    let label = if parts.is_empty() {
+        stem.to_string()
+    } else {
+        parts.join(" ")
+    };
+    (language, label)
+}
+
+/// Build a manifest from the folder's file list. `files` are paths relative
+/// to the folder; files in [`SUBTITLE_DIRS`] are included as `Subs/name.srt`.
+/// `video` selects the video when the folder has several.
+pub fn build_manifest(files: &[String], video: Option<&str>) -> VideoManifest {
+    let video = video.map(str::to_string).or_else(|| pick_video(files));
+    let Some(video_name) = video else {
+        return VideoManifest::default();
+    };
+
+    let video_stem = video_name
+        .rsplit_once('.')
+        .map(|(s, _)| s)
+        .unwrap_or(&video_name)
+        .to_string();
+    let single_video = files
+        .iter()
+        .filter(|f| !f.contains('/') && is_video_file(f))
+        .count()
+        <= 1;
+
+    let mut sidecars: Vec<&String> = files
+        .iter()
+        .filter(|f| is_subtitle_file(f))
+        .filter(|f| {
+            let name = file_name(f);
+            single_video || name.starts_with(&format!("{}.", video_stem))
+        })
+        .collect();
+    sidecars.sort();
+
+    let subtitles = sidecars
+        .into_iter()
+        .map(|path| {
+            let (language, label) = describe_sidecar(file_name(path), &video_stem);
+            let mut url = encode_relative_url(path);
+            if extension(path).as_deref() == Some("srt") {
+                url.push_str("?format=vtt");
+            }
+            SubtitleTrack {
+                url,
+                label,
+                language,
+                source: TrackSource::Sidecar,
+                stream_index: None,
+                default: false,
+            }
+        })
+        .collect();
+
+    VideoManifest {
+        video: Some(video_name),
+        thumbnail: None,
+        subtitles,
+        audio_tracks: Vec::new(),
+        probed: false,
+    }
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    channels: Option<u32>,
+    #[serde(default)]
+    disposition: std::collections::HashMap<String, u32>,
+    #[serde(default)]
+    tags: std::collections::HashMap<String, String>,
+}
+
+/// Add embedded streams from `ffprobe -show_streams -of json` output
+pub fn apply_probe(manifest: &mut VideoManifest, probe_json: &str) -> Result<(), String> {
+    let probe: ProbeOutput =
+        serde_json::from_str(probe_json).map_err(|e| format!("Invalid ffprobe output: {}", e))?;
+    let Some(video) = manifest.video.clone() else {
+        return Ok(());
+    };
+
+    for stream in probe.streams {
+        let language = stream
+            .tags
+            .get("language")
+            .filter(|l| l.as_str() != "und")
+            .cloned();
+        let title = stream.tags.get("title").cloned();
+        let default = stream.disposition.get("default") == Some(&1);
+
+        match stream.codec_type.as_deref() {
+            Some("audio") => manifest.audio_tracks.push(AudioTrack {
+                stream_index: stream.index,
+                language,
+                title,
+                codec: stream.codec_name,
+                channels: stream.channels,
+                default,
+            }),
+            Some("subtitle")
+                if stream
+                    .codec_name
+                    .as_deref()
+                    .is_some_and(|c| TEXT_SUBTITLE_CODECS.contains(&c)) =>
+            {
+                let label = title
+                    .or_else(|| language.clone())
+                    .unwrap_or_else(|| format!("Track {}", stream.index));
+                manifest.subtitles.push(SubtitleTrack {
+                    url: format!(
+                        "{}?format=vtt&track={}",
+                        encode_relative_url(&video),
+                        stream.index
+                    ),
+                    label,
+                    language,
+                    source: TrackSource::Embedded,
+                    stream_index: Some(stream.index),
+                    default,
+                });
+            }
+            _ => {}
+        }
+    }
+    manifest.probed = true;
+    Ok(())
+}
+
+/// Convert SubRip subtitles to WebVTT
+pub fn srt_to_vtt(srt: &str) -> String {
+    let srt = srt.trim_start_matches('\u{feff}');
+    let mut out = String::from("WEBVTT\n\n");
+    for line in srt.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.contains("-->") {
+            out.push_str(&line.replace(',', "."));
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_build_manifest_sidecars() {
+        let files = names(&[
+            "movie.mkv",
+            "movie.en.srt",
+            "movie.fi.forced.vtt",
+            "Subs/movie.de.srt",
+            "notes.txt",
+        ]);
+        let manifest = build_manifest(&files, None);
+        assert_eq!(manifest.video.as_deref(), Some("movie.mkv"));
+        assert_eq!(manifest.subtitles.len(), 3);
+
+        let de = &manifest.subtitles[0];
+        assert_eq!(de.url, "Subs/movie.de.srt?format=vtt");
+        assert_eq!(de.language.as_deref(), Some("de"));
+
+        let fi = &manifest.subtitles[2];
+        assert_eq!(fi.url, "movie.fi.forced.vtt");
+        assert_eq!(fi.language.as_deref(), Some("fi"));
+        assert_eq!(fi.label, "fi forced");
+        assert_eq!(fi.source, TrackSource::Sidecar);
+    }
+
+    #[test]
+    fn test_build_manifest_multiple_videos() {
+        let files = names(&["a b.mp4", "a b.en.srt", "c.mp4", "c.en.srt"]);
+        let manifest = build_manifest(&files, Some("c.mp4"));
+        assert_eq!(manifest.subtitles.len(), 1);
+        assert_eq!(manifest.subtitles[0].url, "c.en.srt?format=vtt");
+
+        let manifest = build_manifest(&files, None);
+        assert_eq!(manifest.video.as_deref(), Some("a b.mp4"));
+        assert_eq!(manifest.subtitles[0].url, "a%20b.en.srt?format=vtt");
+
+        assert_eq!(
+            build_manifest(&names(&["x.srt"]), None),
+            VideoManifest::default()
+        );
+    }
+
+    #[test]
+    fn test_apply_probe() {
+        let mut manifest = build_manifest(&names(&["video.mkv"]), None);
+        let probe = r#"{"streams": [
+            {"index": 0, "codec_type": "video", "codec_name": "h264"},
+            {"index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2,
+             "disposition": {"default": 1}, "tags": {"language": "eng"}},
+            {"index": 2, "codec_type": "audio", "codec_name": "ac3", "channels": 6,
+             "tags": {"language": "und", "title": "Commentary"}},
+            {"index": 3, "codec_type": "subtitle", "codec_name": "subrip",
+             "tags": {"language": "fin"}},
+            {"index": 4, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle"}
+        ]}"#;
+        apply_probe(&mut manifest, probe).unwrap();
+
+        assert!(manifest.probed);
+        assert_eq!(manifest.audio_tracks.len(), 2);
+        assert!(manifest.audio_tracks[0].default);
+        assert_eq!(manifest.audio_tracks[0].language.as_deref(), Some("eng"));
+        assert_eq!(manifest.audio_tracks[1].language, None);
+        assert_eq!(
+            manifest.audio_tracks[1].title.as_deref(),
+            Some("Commentary")
+        );
+
+        assert_eq!(manifest.subtitles.len(), 1);
+        assert_eq!(manifest.subtitles[0].url, "video.mkv?format=vtt&track=3");
+        assert_eq!(manifest.subtitles[0].label, "fin");
+        assert_eq!(manifest.subtitles[0].source, TrackSource::Embedded);
+
+        assert!(apply_probe(&mut manifest, "not json").is_err());
+    }
+
+    #[test]
+    fn test_srt_to_vtt() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello, world\r\n";
+        assert_eq!(
+            srt_to_vtt(srt),
+            "WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\nHello, world\n"
+        );
+    }
+}
@@ -0,0 +1,723 @@
+//! Optional ffmpeg-backed HLS packaging for the htree server
+//!
+//! Webviews can't play many of the videos found in trees (mkv, avi, hevc).
+//! When ffmpeg is installed, `/htree/...?format=hls` remuxes (h264 sources)
+//! or transcodes the file into HLS. Segments and the playlist are stored in
+//! the blob store, content-addressed, and the source → playlist mapping is
+//! kept on disk so repeat playback is instant.
+//!
+//! The same machinery serves image and audio transforms (`?resize=800`,
+//! `?format=webp`, `?rotate=90`, `?clip=30-60`), cached the same way, and
+//! extracts embedded subtitles as WebVTT for the track manifest.
+
+use hashtree_core::{from_hex, sha256, to_hex, Hash, Store};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+/// HLS segment duration in seconds
+const SEGMENT_SECONDS: u32 = 6;
+
+/// URL prefix segments are served under
+pub const SEGMENT_ROUTE_PREFIX: &str = "/hls/";
+
+/// Find an ffmpeg binary: `HTREE_FFMPEG` if set, otherwise `ffmpeg` on PATH
+pub fn detect_ffmpeg() -> Option<PathBuf> {
+    let candidate = std::env::var_os("HTREE_FFMPEG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("ffmpeg"));
+    let works = Command::new(&candidate)
+        .arg("-version")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+    works.then_some(candidate)
+}
+
+/// Packages videos into HLS and caches the results
+pub struct Transcoder {
+    ffmpeg: Option<PathBuf>,
+    /// Directory mapping source hash → playlist hash (one file per source)
+    index_dir: PathBuf,
+    /// Directory mapping (source hash, transform) → result hash
+    transform_dir: PathBuf,
+    /// Directory caching ffprobe stream listings per source hash
+    probe_dir: PathBuf,
+    /// Only one ffmpeg job at a time; also dedups concurrent requests
+    lock: Mutex<()>,
+}
+
+impl Transcoder {
+    pub fn new(data_dir: &Path, ffmpeg: Option<PathBuf>) -> Self {
+        if let Some(ref path) = ffmpeg {
+            info!("HLS packaging enabled using {}", path.display());
+        }
+        Self {
+            ffmpeg,
+            index_dir: data_dir.join("hls"),
+            transform_dir: data_dir.join("transforms"),
+            probe_dir: data_dir.join("probe"),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.ffmpeg.is_some()
+    }
+
+    /// ffprobe's JSON stream listing for a file, cached per source hash
+    pub async fn probe_streams(
+        &self,
+        source: &Hash,
+        input: impl std::future::Future<Output = Result<Vec<u8>, String>>,
+    ) -> Result<String, String> {
+        let cache_file = self.probe_dir.join(format!("{}.json", to_hex(source)));
+        if let Ok(json) = std::fs::read_to_string(&cache_file) {
+            return Ok(json);
+        }
+
+        let ffmpeg = self
+            .ffmpeg
+            .clone()
+            .ok_or_else(|| "ffmpeg not available".to_string())?;
+        let input = input.await?;
+        let json = tokio::task::spawn_blocking(move || run_ffprobe_streams(&ffmpeg, &input))
+            .await
+            .map_err(|e| format!("Probe task failed: {}", e))??;
+
+        std::fs::create_dir_all(&self.probe_dir)
+            .map_err(|e| format!("Failed to create probe dir: {}", e))?;
+        std::fs::write(&cache_file, &json)
+            .map_err(|e| format!("Failed to write probe cache: {}", e))?;
+        Ok(json)
+    }
+
+    /// Look up a previously packaged playlist. Returns None if it (or any of
+    /// its segments) has since been evicted from the store.
+    pub async fn cached_playlist<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        source: &Hash,
+    ) -> Option<Vec<u8>> {
+        let index_file = self.index_dir.join(to_hex(source));
+        let playlist_hex = std::fs::read_to_string(index_file).ok()?;
+        let playlist_hash = from_hex(playlist_hex.trim()).ok()?;
+        let playlist = store.get(&playlist_hash).await.ok()??;
+
+        for hash in segment_hashes(&String::from_utf8_lossy(&playlist)) {
+            if !store.has(&hash).await.unwrap_or(false) {
+                debug!("HLS segment {} evicted, repackaging", to_hex(&hash));
+                return None;
+            }
+        }
+        Some(playlist)
+    }
+
+    /// Package `input` (the full source file) into HLS, store the segments
+    /// and playlist, and return the playlist
+    pub async fn package_hls<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        source: &Hash,
+        input: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let ffmpeg = self
+            .ffmpeg
+            .clone()
+            .ok_or_else(|| "ffmpeg not available".to_string())?;
+
+        let _guard = self.lock.lock().await;
+        // Another request may have packaged it while we waited
+        if let Some(playlist) = self.cached_playlist(store, source).await {
+            return Ok(playlist);
+        }
+
+        info!(
+            "Packaging {} as HLS ({} bytes)",
+            to_hex(source),
+            input.len()
+        );
+        let (playlist, segments) = tokio::task::spawn_blocking(move || run_ffmpeg(&ffmpeg, &input))
+            .await
+            .map_err(|e| format!("Transcode task failed: {}", e))??;
+
+        let mut segment_urls = std::collections::HashMap::new();
+        for (name, data) in segments {
+            let hash = sha256(&data);
+            store
+                .put(hash, data)
+                .await
+                .map_err(|e| format!("Failed to store segment: {}", e))?;
+            segment_urls.insert(
+                name,
+                format!("{}{}.ts", SEGMENT_ROUTE_PREFIX, to_hex(&hash)),
+            );
+        }
+
+        let playlist =
+            rewrite_playlist(&playlist, |name| segment_urls.get(name).cloned()).into_bytes();
+        let playlist_hash = sha256(&playlist);
+        store
+            .put(playlist_hash, playlist.clone())
+            .await
+            .map_err(|e| format!("Failed to store playlist: {}", e))?;
+
+        std::fs::create_dir_all(&self.index_dir)
+            .map_err(|e| format!("Failed to create hls dir: {}", e))?;
+        std::fs::write(self.index_dir.join(to_hex(source)), to_hex(&playlist_hash))
+            .map_err(|e| format!("Failed to write hls index: {}", e))?;
+
+        Ok(playlist)
+    }
+
+    /// Look up or produce a transformed image/audio file. Returns the result
+    /// and its file extension.
+    pub async fn transform<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        source: &Hash,
+        source_ext: &str,
+        transform: &MediaTransform,
+        input: impl std::future::Future<Output = Result<Vec<u8>, String>>,
+    ) -> Result<(Vec<u8>, String), String> {
+        let ext = transform.output_extension(source_ext);
+        let cache_file = self.transform_dir.join(to_hex(&sha256(
+            transform.cache_key(source, &ext).as_bytes(),
+        )));
+
+        if let Ok(result_hex) = std::fs::read_to_string(&cache_file) {
+            if let Ok(result_hash) = from_hex(result_hex.trim()) {
+                if let Ok(Some(data)) = store.get(&result_hash).await {
+                    return Ok((data, ext));
+                }
+            }
+        }
+
+        let ffmpeg = self
+            .ffmpeg
+            .clone()
+            .ok_or_else(|| "ffmpeg not available".to_string())?;
+        let input = input.await?;
+        let args = transform.ffmpeg_args();
+        let out_ext = ext.clone();
+        let output = tokio::task::spawn_blocking(move || {
+            run_ffmpeg_transform(&ffmpeg, &input, &args, &out_ext)
+        })
+        .await
+        .map_err(|e| format!("Transform task failed: {}", e))??;
+
+        let result_hash = sha256(&output);
+        store
+            .put(result_hash, output.clone())
+            .await
+            .map_err(|e| format!("Failed to store transform: {}", e))?;
+        std::fs::create_dir_all(&self.transform_dir)
+            .map_err(|e| format!("Failed to create transforms dir: {}", e))?;
+        std::fs::write(&cache_file, to_hex(&result_hash))
+            .map_err(|e| format!("Failed to write transform index: {}", e))?;
+
+        Ok((output, ext))
+    }
+}
+
+/// Image/audio transform requested through query parameters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaTransform {
+    /// Fit within a square of this many pixels (never upscales)
+    pub resize: Option<u32>,
+    /// Output image format: webp, jpeg or png
+    pub format: Option<String>,
+    /// Clockwise rotation: 90, 180 or 270
+    pub rotate: Option<u32>,
+    /// Audio clip in seconds (start, end)
+    pub clip: Option<(f64, f64)>,
+    /// Embedded subtitle stream to extract as WebVTT
+    pub subtitle_track: Option<u32>,
+}
+
+const MAX_RESIZE: u32 = 8192;
+const IMAGE_FORMATS: &[&str] = &["webp", "jpeg", "png"];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "ogg", "oga", "opus", "m4a", "wav", "flac"];
+
+impl MediaTransform {
+    /// Parse raw query parameter values. Returns Ok(None) when no transform
+    /// was requested.
+    pub fn from_params(
+        resize: Option<&str>,
+        format: Option<&str>,
+        rotate: Option<&str>,
+        clip: Option<&str>,
+    ) -> Result<Option<Self>, String> {
+        if resize.is_none() && format.is_none() && rotate.is_none() && clip.is_none() {
+            return Ok(None);
+        }
+
+        let resize = resize
+            .map(|r| match r.parse::<u32>() {
+                Ok(n) if (1..=MAX_RESIZE).contains(&n) => Ok(n),
+                _ => Err(format!("Invalid resize: {}", r)),
+            })
+            .transpose()?;
+
+        let format = format
+            .map(|f| {
+                let f = f.to_lowercase();
+                let f = if f == "jpg" { "jpeg".to_string() } else { f };
+                if IMAGE_FORMATS.contains(&f.as_str()) {
+                    Ok(f)
+                } else {
+                    Err(format!("Unsupported format: {}", f))
+                }
+            })
+            .transpose()?;
+
+        let rotate = rotate
+            .map(|r| match r.parse::<u32>() {
+                Ok(n @ (0 | 90 | 180 | 270)) => Ok(n),
+                _ => Err(format!("Invalid rotate: {}", r)),
+            })
+            .transpose()?
+            .filter(|&n| n != 0);
+
+        let clip = clip
+            .map(|c| {
+                let (start, end) = c
+                    .split_once('-')
+                    .ok_or_else(|| format!("Invalid clip: {}", c))?;
+                let start: f64 = start.parse().map_err(|_| format!("Invalid clip: {}", c))?;
+                let end: f64 = end.parse().map_err(|_| format!("Invalid clip: {}", c))?;
+                if start < 0.0 || end <= start {
+                    return Err(format!("Invalid clip: {}", c));
+                }
+                Ok((start, end))
+            })
+            .transpose()?;
+
+        if clip.is_some() && (resize.is_some() || format.is_some() || rotate.is_some()) {
+            return Err("clip can't be combined with image transforms".to_string());
+        }
+
+        Ok(Some(Self {
+            resize,
+            format,
+            rotate,
+            clip,
+            subtitle_track: None,
+        }))
+    }
+
+    /// Extract an embedded subtitle stream as WebVTT
+    pub fn subtitle(stream_index: u32) -> Self {
+        Self {
+            subtitle_track: Some(stream_index),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_audio(&self) -> bool {
+        self.clip.is_some()
+    }
+
+    /// Extension of the produced file
+    pub fn output_extension(&self, source_ext: &str) -> String {
+        let source_ext = source_ext.to_lowercase();
+        if self.subtitle_track.is_some() {
+            return "vtt".to_string();
+        }
+        if self.is_audio() {
+            return if AUDIO_EXTENSIONS.contains(&source_ext.as_str()) {
+                source_ext
+            } else {
+                "mp3".to_string()
+            };
+        }
+        match self.format.as_deref() {
+            Some("jpeg") => "jpg".to_string(),
+            Some(f) => f.to_string(),
+            None => match source_ext.as_str() {
+                "jpg" | "jpeg" | "png" | "webp" => source_ext,
+                _ => "png".to_string(),
+            },
+        }
+    }
+
+    fn cache_key(&self, source: &Hash, ext: &str) -> String {
+        format!(
+            "{}?resize={:?}&rotate={:?}&clip={:?}&subtitle={:?}&ext={}",
+            to_hex(source),
+            self.resize,
+            self.rotate,
+            self.clip,
+            self.subtitle_track,
+            ext
+        )
+    }
+
+    /// ffmpeg arguments between the input and the output file
+    fn ffmpeg_args(&self) -> Vec<String> {
+        if let Some(index) = self.subtitle_track {
+            return vec![
+                "-map".to_string(),
+                format!("0:{}", index),
+                "-f".to_string(),
+                "webvtt".to_string(),
+            ];
+        }
+        if let Some((start, end)) = self.clip {
+            return vec![
+                "-vn".to_string(),
+                "-ss".to_string(),
+                start.to_string(),
+                "-to".to_string(),
+                end.to_string(),
+            ];
+        }
+
+        let mut filters = Vec::new();
+        if let Some(size) = self.resize {
+            filters.push(format!(
+                "scale='min({0},iw)':'min({0},ih)':force_original_aspect_ratio=decrease",
+                size
+            ));
+        }
+        match self.rotate {
+            Some(90) => filters.push("transpose=1".to_string()),
+            Some(180) => filters.push("hflip,vflip".to_string()),
+            Some(270) => filters.push("transpose=2".to_string()),
+            _ => {}
+        }
+
+        let mut args = vec!["-frames:v".to_string(), "1".to_string()];
+        if !filters.is_empty() {
+            args.push("-vf".to_string());
+            args.push(filters.join(","));
+        }
+        args
+    }
+}
+
+/// Run a single-output ffmpeg transform in a scratch directory
+fn run_ffmpeg_transform(
+    ffmpeg: &Path,
+    input: &[u8],
+    args: &[String],
+    ext: &str,
+) -> Result<Vec<u8>, String> {
+    let work_dir = std::env::temp_dir().join(format!("htree-transform-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&work_dir).map_err(|e| format!("Failed to create work dir: {}", e))?;
+
+    let result = (|| {
+        let input_path = work_dir.join("input");
+        let output_path = work_dir.join(format!("output.{}", ext));
+        std::fs::write(&input_path, input).map_err(|e| format!("Failed to write input: {}", e))?;
+
+        let output = Command::new(ffmpeg)
+            .args(["-v", "error", "-y", "-i"])
+            .arg(&input_path)
+            .args(args)
+            .arg(&output_path)
+            .output()
+            .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "ffmpeg failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        std::fs::read(&output_path).map_err(|e| format!("Failed to read output: {}", e))
+    })();
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+    result
+}
+
+/// Run ffmpeg in a scratch directory and collect the playlist and segments
+fn run_ffmpeg(ffmpeg: &Path, input: &[u8]) -> Result<(String, Vec<(String, Vec<u8>)>), String> {
+    let work_dir = std::env::temp_dir().join(format!("htree-hls-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&work_dir).map_err(|e| format!("Failed to create work dir: {}", e))?;
+    let result = run_ffmpeg_in(ffmpeg, input, &work_dir);
+    let _ = std::fs::remove_dir_all(&work_dir);
+    result
+}
+
+fn run_ffmpeg_in(
+    ffmpeg: &Path,
+    input: &[u8],
+    work_dir: &Path,
+) -> Result<(String, Vec<(String, Vec<u8>)>), String> {
+    let input_path = work_dir.join("input");
+    std::fs::write(&input_path, input).map_err(|e| format!("Failed to write input: {}", e))?;
+
+    // h264 can be remuxed as-is; everything else gets transcoded
+    let video_args: &[&str] = if probe_video_codec(ffmpeg, &input_path).as_deref() == Some("h264") {
+        &["-c:v", "copy"]
+    } else {
+        &[
+            "-c:v", "libx264", "-preset", "veryfast", "-crf", "23", "-pix_fmt", "yuv420p",
+        ]
+    };
+
+    let output = Command::new(ffmpeg)
+        .arg("-v")
+        .arg("error")
+        .arg("-y")
+        .arg("-i")
+        .arg(&input_path)
+        .args(["-map", "0:v:0", "-map", "0:a:0?"])
+        .args(video_args)
+        .args(["-c:a", "aac", "-b:a", "160k"])
+        .args(["-f", "hls", "-hls_playlist_type", "vod"])
+        .arg("-hls_time")
+        .arg(SEGMENT_SECONDS.to_string())
+        .arg("-hls_segment_filename")
+        .arg(work_dir.join("seg%05d.ts"))
+        .arg(work_dir.join("index.m3u8"))
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let playlist = std::fs::read_to_string(work_dir.join("index.m3u8"))
+        .map_err(|e| format!("Failed to read playlist: {}", e))?;
+    let mut segments = Vec::new();
+    for line in playlist
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let data = std::fs::read(work_dir.join(line))
+            .map_err(|e| format!("Failed to read segment {}: {}", line, e))?;
+        segments.push((line.to_string(), data));
+    }
+    Ok((playlist, segments))
+}
+
+/// ffprobe binary installed next to ffmpeg
+fn ffprobe_path(ffmpeg: &Path) -> PathBuf {
+    ffmpeg.with_file_name(match ffmpeg.extension() {
+        Some(ext) => format!("ffprobe.{}", ext.to_string_lossy()),
+        None => "ffprobe".to_string(),
+    })
+}
+
+/// List a file's streams (index, type, codec, language, ...) as ffprobe JSON
+fn run_ffprobe_streams(ffmpeg: &Path, input: &[u8]) -> Result<String, String> {
+    let input_path = std::env::temp_dir().join(format!("htree-probe-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&input_path, input).map_err(|e| format!("Failed to write input: {}", e))?;
+
+    let output = Command::new(ffprobe_path(ffmpeg))
+        .args(["-v", "error", "-of", "json", "-show_entries"])
+        .arg("stream=index,codec_type,codec_name,channels:stream_tags=language,title:stream_disposition=default")
+        .arg(&input_path)
+        .output();
+    let _ = std::fs::remove_file(&input_path);
+
+    let output = output.map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("Invalid ffprobe output: {}", e))
+}
+
+/// Ask ffprobe (next to ffmpeg) for the first video stream's codec
+fn probe_video_codec(ffmpeg: &Path, input: &Path) -> Option<String> {
+    let output = Command::new(ffprobe_path(ffmpeg))
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=codec_name", "-of", "csv=p=0"])
+        .arg(input)
+        .output()
+        .ok()?;
+    let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !codec.is_empty()).then_some(codec)
+}
+
+/// Replace segment URIs in a playlist. Lines the mapper doesn't know are kept.
+fn rewrite_playlist(playlist: &str, map: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        if !line.is_empty() && !line.starts_with('#') {
+            out.push_str(&map(line).unwrap_or_else(|| line.to_string()));
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Segment hashes referenced by a rewritten playlist
+fn segment_hashes(playlist: &str) -> Vec<Hash> {
+    playlist
+        .lines()
+        .filter_map(|line| line.strip_prefix(SEGMENT_ROUTE_PREFIX))
+        .filter_map(|name| name.strip_suffix(".ts"))
+        .filter_map(|hex| from_hex(hex).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::MemoryStore;
+    use tempfile::tempdir;
+
+    const PLAYLIST: &str = "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nseg00000.ts\n#EXTINF:2.5,\nseg00001.ts\n#EXT-X-ENDLIST\n";
+
+    #[test]
+    fn test_rewrite_playlist() {
+        let rewritten = rewrite_playlist(PLAYLIST, |name| Some(format!("/hls/{}", name)));
+        assert!(rewritten.contains("\n/hls/seg00000.ts\n"));
+        assert!(rewritten.contains("\n/hls/seg00001.ts\n"));
+        assert!(rewritten.starts_with("#EXTM3U\n"));
+        assert!(rewritten.ends_with("#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn test_segment_hashes() {
+        let hash = [0xab; 32];
+        let playlist = rewrite_playlist(PLAYLIST, |name| {
+            (name == "seg00000.ts").then(|| format!("/hls/{}.ts", to_hex(&hash)))
+        });
+        assert_eq!(segment_hashes(&playlist), vec![hash]);
+    }
+
+    #[tokio::test]
+    async fn test_cached_playlist_requires_segments() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::new();
+        let transcoder = Transcoder::new(dir.path(), None);
+        assert!(!transcoder.is_available());
+
+        let source = [0x01; 32];
+        let segment = b"segment".to_vec();
+        let segment_hash = sha256(&segment);
+        let playlist = format!("#EXTM3U\n/hls/{}.ts\n", to_hex(&segment_hash)).into_bytes();
+        let playlist_hash = sha256(&playlist);
+        store.put(playlist_hash, playlist.clone()).await.unwrap();
+        std::fs::create_dir_all(dir.path().join("hls")).unwrap();
+        std::fs::write(
+            dir.path().join("hls").join(to_hex(&source)),
+            to_hex(&playlist_hash),
+        )
+        .unwrap();
+
+        // Segment missing → not served from cache
+        assert!(transcoder.cached_playlist(&store, &source).await.is_none());
+
+        store.put(segment_hash, segment).await.unwrap();
+        assert_eq!(
+            transcoder.cached_playlist(&store, &source).await,
+            Some(playlist)
+        );
+
+        // Without ffmpeg, packaging fails instead of serving the original
+        assert!(transcoder
+            .package_hls(&store, &[0x02; 32], vec![])
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_media_transform_from_params() {
+        assert_eq!(
+            MediaTransform::from_params(None, None, None, None),
+            Ok(None)
+        );
+
+        let t = MediaTransform::from_params(Some("800"), Some("JPG"), Some("90"), None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(t.resize, Some(800));
+        assert_eq!(t.format.as_deref(), Some("jpeg"));
+        assert_eq!(t.rotate, Some(90));
+        assert_eq!(t.output_extension("png"), "jpg");
+
+        let t = MediaTransform::from_params(None, None, None, Some("30-60.5"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(t.clip, Some((30.0, 60.5)));
+        assert!(t.is_audio());
+        assert_eq!(t.output_extension("flac"), "flac");
+        assert_eq!(t.output_extension("wma"), "mp3");
+
+        assert!(MediaTransform::from_params(Some("0"), None, None, None).is_err());
+        assert!(MediaTransform::from_params(None, Some("bmp"), None, None).is_err());
+        assert!(MediaTransform::from_params(None, None, Some("45"), None).is_err());
+        assert!(MediaTransform::from_params(None, None, None, Some("60-30")).is_err());
+        assert!(MediaTransform::from_params(Some("100"), None, None, Some("0-1")).is_err());
+    }
+
+    #[test]
+    fn test_media_transform_ffmpeg_args() {
+        let t = MediaTransform {
+            resize: Some(800),
+            rotate: Some(270),
+            ..Default::default()
+        };
+        let args = t.ffmpeg_args();
+        assert_eq!(args[..2], ["-frames:v", "1"]);
+        assert_eq!(
+            args[3],
+            "scale='min(800,iw)':'min(800,ih)':force_original_aspect_ratio=decrease,transpose=2"
+        );
+
+        let t = MediaTransform {
+            clip: Some((30.0, 60.0)),
+            ..Default::default()
+        };
+        assert_eq!(t.ffmpeg_args(), ["-vn", "-ss", "30", "-to", "60"]);
+
+        let t = MediaTransform::subtitle(3);
+        assert_eq!(t.ffmpeg_args(), ["-map", "0:3", "-f", "webvtt"]);
+        assert_eq!(t.output_extension("mkv"), "vtt");
+    }
+
+    #[tokio::test]
+    async fn test_transform_served_from_cache() {
+        let dir = tempdir().unwrap();
+        let store = MemoryStore::new();
+        let transcoder = Transcoder::new(dir.path(), None);
+        let transform = MediaTransform {
+            resize: Some(100),
+            ..Default::default()
+        };
+        let source = [0x03; 32];
+
+        // No ffmpeg and nothing cached
+        let result = transcoder
+            .transform(&store, &source, "png", &transform, async { Ok(vec![]) })
+            .await;
+        assert!(result.is_err());
+
+        // A cached result is served without ffmpeg or reading the source
+        let cached = b"small png".to_vec();
+        let cached_hash = sha256(&cached);
+        store.put(cached_hash, cached.clone()).await.unwrap();
+        let key = transform.cache_key(&source, "png");
+        std::fs::create_dir_all(dir.path().join("transforms")).unwrap();
+        std::fs::write(
+            dir.path()
+                .join("transforms")
+                .join(to_hex(&sha256(key.as_bytes()))),
+            to_hex(&cached_hash),
+        )
+        .unwrap();
+
+        let (data, ext) = transcoder
+            .transform(&store, &source, "png", &transform, async {
+                Err("source should not be read".to_string())
+            })
+            .await
+            .unwrap();
+        assert_eq!(data, cached);
+        assert_eq!(ext, "png");
+    }
+}
@@ -0,0 +1,187 @@
+//! System tray icon with the sync status and quick actions
+//!
+//! The tooltip and the first menu line show the aggregate sync status,
+//! refreshed every few seconds. The menu pauses or resumes syncing, runs
+//! eviction and opens recently visited trees in the main window, which
+//! gets a `tray-open` event with the route to navigate to. The icon is the
+//! one configured under `app.trayIcon`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tauri::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Emitter, Manager, Wry};
+use tracing::warn;
+
+use crate::history::HistoryStore;
+use crate::worker::{self, SyncStatus, WorkerRequest, WorkerState};
+
+const TRAY_ID: &str = "main";
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const RECENT_TREES: usize = 8;
+/// Prefix of the ids of recent tree items, followed by the route
+const OPEN_PREFIX: &str = "tray_open:";
+
+/// Menu items updated as the status changes
+struct TrayMenu {
+    status: MenuItem<Wry>,
+    pause: CheckMenuItem<Wry>,
+    recent: Submenu<Wry>,
+    recent_paths: Mutex<Vec<String>>,
+}
+
+pub fn init_tray(app: &AppHandle) -> tauri::Result<()> {
+    let status = MenuItem::with_id(app, "tray_status", "Starting…", false, None::<&str>)?;
+    let pause = CheckMenuItem::with_id(
+        app,
+        "tray_pause",
+        "Pause syncing",
+        true,
+        false,
+        None::<&str>,
+    )?;
+    let evict = MenuItem::with_id(app, "tray_evict", "Free up space", true, None::<&str>)?;
+    let recent = Submenu::with_id(app, "tray_recent", "Recent trees", false)?;
+    let show = MenuItem::with_id(app, "tray_show", "Show Iris", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, "tray_quit", "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(
+        app,
+        &[
+            &status,
+            &PredefinedMenuItem::separator(app)?,
+            &pause,
+            &evict,
+            &recent,
+            &PredefinedMenuItem::separator(app)?,
+            &show,
+            &quit,
+        ],
+    )?;
+
+    // `app.trayIcon` in tauri.conf.json creates the icon; build one if it's
+    // been left out
+    let tray = match app.tray_by_id(TRAY_ID) {
+        Some(tray) => tray,
+        None => {
+            let mut builder = TrayIconBuilder::with_id(TRAY_ID);
+            if let Some(icon) = app.default_window_icon() {
+                builder = builder.icon(icon.clone());
+            }
+            builder.build(app)?
+        }
+    };
+    tray.set_tooltip(Some("Iris"))?;
+    tray.set_menu(Some(menu))?;
+    tray.on_menu_event(handle_menu_event);
+
+    app.manage(TrayMenu {
+        status,
+        pause,
+        recent,
+        recent_paths: Mutex::new(Vec::new()),
+    });
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            refresh(&app).await;
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+        }
+    });
+    Ok(())
+}
+
+/// Bring the tray up to date with the sync status and history
+async fn refresh(app: &AppHandle) {
+    let status = app.state::<Arc<WorkerState>>().sync_status().await;
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let _ = tray.set_tooltip(Some(format!("Iris: {}", status.summary())));
+    }
+    let menu = app.state::<TrayMenu>();
+    if let Err(e) = update_menu(app, &menu, &status) {
+        warn!("Failed to update tray menu: {}", e);
+    }
+}
+
+fn update_menu(app: &AppHandle, menu: &TrayMenu, status: &SyncStatus) -> tauri::Result<()> {
+    menu.status.set_text(status.summary())?;
+    menu.pause.set_checked(status.paused)?;
+
+    let trees = recent_trees(app);
+    let paths: Vec<String> = trees.iter().map(|(path, _)| path.clone()).collect();
+    let mut recent_paths = menu.recent_paths.lock();
+    if *recent_paths == paths {
+        return Ok(());
+    }
+    for item in menu.recent.items()? {
+        menu.recent.remove(&item)?;
+    }
+    for (path, label) in &trees {
+        let id = format!("{}{}", OPEN_PREFIX, path);
+        menu.recent
+            .append(&MenuItem::with_id(app, id, label, true, None::<&str>)?)?;
+    }
+    menu.recent.set_enabled(!trees.is_empty())?;
+    *recent_paths = paths;
+    Ok(())
+}
+
+/// Routes and labels of the most recently visited trees
+fn recent_trees(app: &AppHandle) -> Vec<(String, String)> {
+    let worker = app.state::<Arc<WorkerState>>();
+    let history = worker
+        .guest_history()
+        .unwrap_or_else(|| app.state::<Arc<HistoryStore>>().inner().clone());
+    history
+        .get_recent(50)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| entry.entry_type == "tree")
+        .take(RECENT_TREES)
+        .map(|entry| (entry.path, entry.label))
+        .collect()
+}
+
+fn handle_menu_event(app: &AppHandle, event: MenuEvent) {
+    match event.id().as_ref() {
+        "tray_pause" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                let state = app.state::<Arc<WorkerState>>();
+                let paused = !state.sync.is_paused();
+                state.set_sync_paused(paused).await;
+                refresh(&app).await;
+            });
+        }
+        "tray_evict" => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                let request = WorkerRequest::RunEviction {
+                    id: format!("tray-eviction-{}", uuid::Uuid::new_v4()),
+                };
+                let state = app.state::<Arc<WorkerState>>();
+                if let Err(e) = worker::worker_message(request, app.clone(), state).await {
+                    warn!("Eviction from tray failed: {}", e);
+                }
+            });
+        }
+        "tray_show" => show_main_window(app),
+        "tray_quit" => app.exit(0),
+        id => {
+            if let Some(path) = id.strip_prefix(OPEN_PREFIX) {
+                show_main_window(app);
+                let _ = app.emit("tray-open", serde_json::json!({ "path": path }));
+            }
+        }
+    }
+}
+
+fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
@@ -0,0 +1,280 @@
+//! Per-origin storage of child webviews
+//!
+//! Every htree:// origin opened with `create_htree_webview` gets its own
+//! localStorage, IndexedDB, caches and cookies in the webview profile. The
+//! origins are remembered in `webview_origins.json` so `list_webview_origins`
+//! can show them after a restart, and `clear_webview_data` wipes one of them
+//! without touching the rest of the profile.
+//!
+//! Webview engines have no API to clear a single origin, so the data is
+//! cleared from inside the origin: by script in an open webview of it, or in
+//! a hidden webview that loads the origin, clears it and is closed again.
+
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Runtime, WebviewBuilder, WebviewUrl};
+use tracing::{info, warn};
+
+/// File in the data dir listing the origins
+const ORIGINS_FILE: &str = "webview_origins.json";
+
+/// Path the clearing script navigates to once it's done
+const CLEARED_PATH: &str = "/.htree-cleared";
+
+/// How long a hidden webview gets to clear its origin
+const CLEAR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wipes the storage of the page's origin. `done` runs once everything is
+/// cleared, or failed to.
+fn clear_script(done: &str) -> String {
+    format!(
+        r#"
+(function() {{
+  if (window.top !== window) return;
+  const tasks = [];
+  try {{ localStorage.clear(); }} catch (e) {{}}
+  try {{ sessionStorage.clear(); }} catch (e) {{}}
+  for (const cookie of document.cookie.split(';')) {{
+    const name = cookie.split('=')[0].trim();
+    if (name) document.cookie = name + '=; expires=Thu, 01 Jan 1970 00:00:00 GMT; path=/';
+  }}
+  if (window.indexedDB && indexedDB.databases) {{
+    tasks.push(indexedDB.databases().then((dbs) => Promise.all(dbs.map((db) =>
+      new Promise((resolve) => {{
+        const req = indexedDB.deleteDatabase(db.name);
+        req.onsuccess = req.onerror = req.onblocked = resolve;
+      }})))));
+  }}
+  if (window.caches) {{
+    tasks.push(caches.keys().then((keys) => Promise.all(keys.map((key) => caches.delete(key)))));
+  }}
+  if (navigator.serviceWorker) {{
+    tasks.push(navigator.serviceWorker.getRegistrations()
+      .then((regs) => Promise.all(regs.map((reg) => reg.unregister()))));
+  }}
+  Promise.allSettled(tasks).then(() => {{ {done} }});
+}})();
+"#
+    )
+}
+
+/// An origin as listed by `list_webview_origins`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebviewOrigin {
+    pub origin: String,
+    /// Unix timestamp (ms) of the last webview created for it
+    pub last_opened: u64,
+    /// Whether a webview of it is open
+    pub open: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Origins {
+    /// Origin -> last opened (unix ms)
+    #[serde(default)]
+    origins: BTreeMap<String, u64>,
+}
+
+/// Known origins plus the labels of the webviews created for them
+struct OriginStore {
+    path: Option<PathBuf>,
+    origins: RwLock<Origins>,
+    /// Webview label -> origin
+    labels: RwLock<HashMap<String, String>>,
+}
+
+impl OriginStore {
+    fn save(&self, origins: &Origins) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec(origins)
+            .map_err(|e| e.to_string())
+            .and_then(|data| std::fs::write(path, data).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("Failed to save {:?}: {}", path, e);
+        }
+    }
+
+    /// Labels of the webviews created for `origin`
+    fn labels_of(&self, origin: &str) -> Vec<String> {
+        self.labels
+            .read()
+            .iter()
+            .filter(|(_, o)| o.as_str() == origin)
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+}
+
+static GLOBAL_ORIGINS: OnceCell<OriginStore> = OnceCell::new();
+
+/// Load `webview_origins.json` from the data dir
+pub fn init_webview_origins(data_dir: &Path) {
+    let _ = GLOBAL_ORIGINS.get_or_init(|| {
+        let path = data_dir.join(ORIGINS_FILE);
+        let origins = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        OriginStore {
+            path: Some(path),
+            origins: RwLock::new(origins),
+            labels: RwLock::new(HashMap::new()),
+        }
+    });
+}
+
+fn store() -> &'static OriginStore {
+    GLOBAL_ORIGINS.get_or_init(|| OriginStore {
+        path: None,
+        origins: RwLock::new(Origins::default()),
+        labels: RwLock::new(HashMap::new()),
+    })
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Remember that webview `label` was created for `origin`
+pub fn record_webview(label: &str, origin: &str) {
+    let store = store();
+    store
+        .labels
+        .write()
+        .insert(label.to_string(), origin.to_string());
+    let mut origins = store.origins.write();
+    origins.origins.insert(origin.to_string(), now_ms());
+    store.save(&origins);
+}
+
+/// Tauri command listing the htree:// origins that may hold webview data,
+/// most recently opened first
+#[tauri::command]
+pub fn list_webview_origins<R: Runtime>(app: AppHandle<R>) -> Vec<WebviewOrigin> {
+    let store = store();
+    let mut origins: Vec<WebviewOrigin> = store
+        .origins
+        .read()
+        .origins
+        .iter()
+        .map(|(origin, last_opened)| WebviewOrigin {
+            origin: origin.clone(),
+            last_opened: *last_opened,
+            open: store
+                .labels_of(origin)
+                .iter()
+                .any(|label| app.get_webview(label).is_some()),
+        })
+        .collect();
+    origins.sort_by(|a, b| b.last_opened.cmp(&a.last_opened));
+    origins
+}
+
+/// Tauri command wiping localStorage, IndexedDB, caches and cookies of one
+/// htree:// origin, and the NIP-07 permissions given to it
+#[tauri::command]
+pub async fn clear_webview_data<R: Runtime>(
+    app: AppHandle<R>,
+    origin: String,
+) -> Result<(), String> {
+    let url = tauri::Url::parse(&origin).map_err(|e| format!("Invalid origin: {}", e))?;
+    if url.scheme() != "htree" || url.host_str().is_none() {
+        return Err("Only htree:// origins can be cleared".to_string());
+    }
+    let store = store();
+    if !store.origins.read().origins.contains_key(&origin) {
+        return Err(format!("Unknown origin: {}", origin));
+    }
+
+    let open: Vec<_> = store
+        .labels_of(&origin)
+        .iter()
+        .filter_map(|label| app.get_webview(label))
+        .collect();
+    if open.is_empty() {
+        clear_in_hidden_webview(&app, &origin).await?;
+        let mut origins = store.origins.write();
+        origins.origins.remove(&origin);
+        store.save(&origins);
+    } else {
+        // Reload so the app starts over from empty storage
+        let script = clear_script("location.reload();");
+        for webview in open {
+            webview
+                .eval(&script)
+                .map_err(|e| format!("Failed to clear webview data: {}", e))?;
+        }
+    }
+
+    if let Some(nip07) = crate::nip07::get_nip07_state() {
+        nip07.permissions.revoke_all(&origin).await;
+    }
+    info!("Cleared webview data of {}", origin);
+    Ok(())
+}
+
+/// Load `origin` in an invisible webview that clears its storage
+async fn clear_in_hidden_webview<R: Runtime>(
+    app: &AppHandle<R>,
+    origin: &str,
+) -> Result<(), String> {
+    let window = app.get_window("main").ok_or("Main window not found")?;
+    let url = tauri::Url::parse(origin).map_err(|e| format!("Invalid origin: {}", e))?;
+    let label = format!("clear-{}", uuid::Uuid::new_v4());
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+    let done_tx = parking_lot::Mutex::new(Some(done_tx));
+
+    let script = clear_script(&format!("location.replace('{}');", CLEARED_PATH));
+    let builder =
+        crate::proxy::apply_webview(WebviewBuilder::new(&label, WebviewUrl::External(url)))
+            .initialization_script(&script)
+            .on_navigation(move |nav_url| {
+                if nav_url.path() == CLEARED_PATH {
+                    if let Some(tx) = done_tx.lock().take() {
+                        let _ = tx.send(());
+                    }
+                    return false;
+                }
+                true
+            });
+    let webview = window
+        .add_child(
+            builder,
+            tauri::LogicalPosition::new(0.0, 0.0),
+            tauri::LogicalSize::new(0.0, 0.0),
+        )
+        .map_err(|e| format!("Failed to create webview: {}", e))?;
+
+    let result = tokio::time::timeout(CLEAR_TIMEOUT, done_rx).await;
+    if let Err(e) = webview.close() {
+        warn!("Failed to close webview {}: {}", label, e);
+    }
+    match result {
+        Ok(Ok(())) => Ok(()),
+        _ => Err(format!("Timed out clearing {}", origin)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_script_runs_done_after_clearing() {
+        let script = clear_script("location.reload();");
+        assert!(script.contains("localStorage.clear()"));
+        assert!(script.contains("indexedDB.deleteDatabase"));
+        assert!(script.contains("Promise.allSettled(tasks).then(() => { location.reload(); })"));
+    }
+}
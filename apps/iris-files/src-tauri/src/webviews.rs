@@ -0,0 +1,421 @@
+//! Lifecycle of child webviews
+//!
+//! `create_nip07_webview` and `create_htree_webview` register every child
+//! webview here, so `list_webviews` can enumerate them and `close_webview`
+//! closes one and drops what was kept for its label: the NIP-07 session of
+//! its origin, its private flag. Webviews closed from the frontend directly
+//! are reaped the same way by a periodic check.
+//!
+//! Each webview is a page with its own memory, so live ones are capped by
+//! [`WebviewLimits`]: one left idle for `idleSuspendSecs` is suspended, and
+//! so is the least recently used one when more than `maxLive` are live. The
+//! foreground webview, the one last created or restored, is never suspended
+//! for these. A suspended webview is hidden and navigated to about:blank,
+//! which frees the page, and [`SUSPENDED_EVENT`] is emitted;
+//! `restore_webview` loads the URL it was on again.
+
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use tracing::{info, warn};
+
+/// File in the data dir holding the limits
+const LIMITS_FILE: &str = "webview_limits.json";
+
+/// How often idle and closed webviews are looked for
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// URL a suspended webview is parked at
+const SUSPENDED_URL: &str = "about:blank";
+
+/// Event telling the frontend a webview was suspended, with its label and
+/// the URL restoring it loads
+pub const SUSPENDED_EVENT: &str = "child-webview-suspended";
+
+/// Caps on live child webviews; 0 turns a cap off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WebviewLimits {
+    /// Suspend webviews idle for this long
+    pub idle_suspend_secs: u64,
+    /// Suspend the least recently used webviews beyond this many
+    pub max_live: usize,
+}
+
+impl Default for WebviewLimits {
+    fn default() -> Self {
+        Self {
+            idle_suspend_secs: 600,
+            max_live: 8,
+        }
+    }
+}
+
+/// A webview as listed by `list_webviews`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebviewInfo {
+    pub label: String,
+    pub origin: String,
+    /// Unix timestamps (ms)
+    pub created_at: u64,
+    pub last_active: u64,
+    pub suspended: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    origin: String,
+    created_at: u64,
+    last_active: u64,
+    /// URL to load on restore, while suspended
+    suspended_url: Option<String>,
+}
+
+/// Child webviews by label, and the limits applied to them
+struct Registry {
+    path: Option<PathBuf>,
+    limits: RwLock<WebviewLimits>,
+    entries: RwLock<HashMap<String, Entry>>,
+    /// Label of the webview in the foreground
+    foreground: RwLock<Option<String>>,
+}
+
+impl Registry {
+    fn new(path: Option<PathBuf>, limits: WebviewLimits) -> Self {
+        Self {
+            path,
+            limits: RwLock::new(limits),
+            entries: RwLock::new(HashMap::new()),
+            foreground: RwLock::new(None),
+        }
+    }
+
+    fn register(&self, label: &str, origin: &str, now: u64) {
+        *self.foreground.write() = Some(label.to_string());
+        self.entries.write().insert(
+            label.to_string(),
+            Entry {
+                origin: origin.to_string(),
+                created_at: now,
+                last_active: now,
+                suspended_url: None,
+            },
+        );
+    }
+
+    fn touch(&self, label: &str, now: u64) {
+        if let Some(entry) = self.entries.write().get_mut(label) {
+            entry.last_active = now;
+        }
+    }
+
+    fn list(&self) -> Vec<WebviewInfo> {
+        let mut list: Vec<WebviewInfo> = self
+            .entries
+            .read()
+            .iter()
+            .map(|(label, entry)| WebviewInfo {
+                label: label.clone(),
+                origin: entry.origin.clone(),
+                created_at: entry.created_at,
+                last_active: entry.last_active,
+                suspended: entry.suspended_url.is_some(),
+            })
+            .collect();
+        list.sort_by(|a, b| b.last_active.cmp(&a.last_active));
+        list
+    }
+
+    /// Live background webviews to suspend at `now`: the idle ones, then
+    /// the least recently used beyond the cap
+    fn to_suspend(&self, now: u64) -> Vec<String> {
+        let limits = *self.limits.read();
+        let entries = self.entries.read();
+        let foreground = self.foreground.read().clone();
+        let mut live: Vec<(&String, &Entry)> = entries
+            .iter()
+            .filter(|(_, entry)| entry.suspended_url.is_none())
+            .collect();
+        let foreground_live = live
+            .iter()
+            .any(|(label, _)| Some(*label) == foreground.as_ref());
+        live.retain(|(label, _)| Some(*label) != foreground.as_ref());
+        live.sort_by(|a, b| b.1.last_active.cmp(&a.1.last_active));
+
+        // The foreground webview takes one of the live slots
+        let slots = limits.max_live.saturating_sub(usize::from(foreground_live));
+        let idle_ms = limits.idle_suspend_secs.saturating_mul(1000);
+        live.iter()
+            .enumerate()
+            .filter(|(i, (_, entry))| {
+                let idle = limits.idle_suspend_secs > 0
+                    && now.saturating_sub(entry.last_active) >= idle_ms;
+                let over_cap = limits.max_live > 0 && *i >= slots;
+                idle || over_cap
+            })
+            .map(|(_, (label, _))| (*label).clone())
+            .collect()
+    }
+
+    /// Remove `label`, returning its origin if no other webview has it
+    fn forget(&self, label: &str) -> Option<String> {
+        let mut foreground = self.foreground.write();
+        if foreground.as_deref() == Some(label) {
+            *foreground = None;
+        }
+        drop(foreground);
+        let mut entries = self.entries.write();
+        let entry = entries.remove(label)?;
+        let shared = entries.values().any(|e| e.origin == entry.origin);
+        (!shared).then_some(entry.origin)
+    }
+
+    fn save_limits(&self, limits: &WebviewLimits) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            let data = serde_json::to_vec_pretty(limits)
+                .map_err(|e| format!("Failed to encode webview limits: {}", e))?;
+            crate::atomic_file::write(path, data)
+                .map_err(|e| format!("Failed to save webview limits: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+static GLOBAL_WEBVIEWS: OnceCell<Registry> = OnceCell::new();
+
+/// Load `webview_limits.json` from the data dir
+pub fn init_webviews(data_dir: &Path) {
+    let _ = GLOBAL_WEBVIEWS.get_or_init(|| {
+        let path = data_dir.join(LIMITS_FILE);
+        let limits = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Registry::new(Some(path), limits)
+    });
+}
+
+fn registry() -> &'static Registry {
+    GLOBAL_WEBVIEWS.get_or_init(|| Registry::new(None, WebviewLimits::default()))
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Register the child webview `label` created for `origin`, suspending
+/// others if it takes the live ones over the cap
+pub fn track_webview<R: Runtime>(app: &AppHandle<R>, label: &str, origin: &str) {
+    let registry = registry();
+    registry.register(label, origin, now_ms());
+    for other in registry.to_suspend(now_ms()) {
+        if other != label {
+            if let Err(e) = suspend(app, &other) {
+                warn!("Failed to suspend webview {}: {}", other, e);
+            }
+        }
+    }
+}
+
+/// Note activity in webview `label`, e.g. a navigation
+pub fn touch_webview(label: &str) {
+    registry().touch(label, now_ms());
+}
+
+/// Whether webview `label` is suspended; its navigation to the parked URL
+/// isn't the user's
+pub fn is_suspended(label: &str) -> bool {
+    registry()
+        .entries
+        .read()
+        .get(label)
+        .is_some_and(|entry| entry.suspended_url.is_some())
+}
+
+fn suspend<R: Runtime>(app: &AppHandle<R>, label: &str) -> Result<(), String> {
+    let webview = app
+        .get_webview(label)
+        .ok_or_else(|| format!("Webview {} not found", label))?;
+    let url = webview.url().map_err(|e| e.to_string())?;
+    {
+        let mut entries = registry().entries.write();
+        let entry = entries
+            .get_mut(label)
+            .ok_or_else(|| format!("Unknown webview: {}", label))?;
+        if entry.suspended_url.is_some() {
+            return Ok(());
+        }
+        entry.suspended_url = Some(url.to_string());
+    }
+    webview.hide().map_err(|e| e.to_string())?;
+    let parked = tauri::Url::parse(SUSPENDED_URL).map_err(|e| e.to_string())?;
+    webview.navigate(parked).map_err(|e| e.to_string())?;
+    info!("Suspended webview {} at {}", label, url);
+    let _ = app.emit(
+        SUSPENDED_EVENT,
+        serde_json::json!({ "label": label, "url": url.to_string() }),
+    );
+    Ok(())
+}
+
+fn restore<R: Runtime>(app: &AppHandle<R>, label: &str) -> Result<(), String> {
+    let webview = app
+        .get_webview(label)
+        .ok_or_else(|| format!("Webview {} not found", label))?;
+    let url = {
+        let mut entries = registry().entries.write();
+        let entry = entries
+            .get_mut(label)
+            .ok_or_else(|| format!("Unknown webview: {}", label))?;
+        entry.last_active = now_ms();
+        entry.suspended_url.take()
+    };
+    *registry().foreground.write() = Some(label.to_string());
+    if let Some(url) = url {
+        let url = tauri::Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
+        webview.navigate(url).map_err(|e| e.to_string())?;
+        info!("Restored webview {}", label);
+    }
+    webview.show().map_err(|e| e.to_string())
+}
+
+/// Drop what's kept for the closed webview `label`
+fn reap(label: &str) {
+    crate::history::set_private_webview(label, false);
+    crate::filter_proxy::stop(label);
+    let nip07 = crate::nip07::get_nip07_state();
+    if let Some(nip07) = &nip07 {
+        nip07.clear_policy(label);
+    }
+    if let Some(origin) = registry().forget(label) {
+        if let Some(nip07) = &nip07 {
+            nip07.clear_session(&origin);
+        }
+    }
+}
+
+/// Reap webviews closed behind our back and suspend idle ones, until the
+/// app exits
+pub fn start_reaper<R: Runtime>(app: AppHandle<R>) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let registry = registry();
+            let labels: Vec<String> = registry.entries.read().keys().cloned().collect();
+            for label in labels {
+                if app.get_webview(&label).is_none() {
+                    reap(&label);
+                }
+            }
+            for label in registry.to_suspend(now_ms()) {
+                if let Err(e) = suspend(&app, &label) {
+                    warn!("Failed to suspend webview {}: {}", label, e);
+                }
+            }
+        }
+    });
+}
+
+/// Tauri command listing the child webviews, most recently active first
+#[tauri::command]
+pub fn list_webviews() -> Vec<WebviewInfo> {
+    registry().list()
+}
+
+/// Tauri command closing a child webview
+#[tauri::command]
+pub async fn close_webview<R: Runtime>(app: AppHandle<R>, label: String) -> Result<(), String> {
+    if !registry().entries.read().contains_key(&label) {
+        return Err(format!("Unknown webview: {}", label));
+    }
+    if let Some(webview) = app.get_webview(&label) {
+        webview
+            .close()
+            .map_err(|e| format!("Failed to close webview: {}", e))?;
+    }
+    reap(&label);
+    info!("Closed webview {}", label);
+    Ok(())
+}
+
+/// Tauri command suspending a child webview
+#[tauri::command]
+pub fn suspend_webview<R: Runtime>(app: AppHandle<R>, label: String) -> Result<(), String> {
+    suspend(&app, &label)
+}
+
+/// Tauri command restoring a suspended child webview, or just marking it
+/// active if it isn't
+#[tauri::command]
+pub fn restore_webview<R: Runtime>(app: AppHandle<R>, label: String) -> Result<(), String> {
+    restore(&app, &label)
+}
+
+#[tauri::command]
+pub fn get_webview_limits() -> WebviewLimits {
+    *registry().limits.read()
+}
+
+#[tauri::command]
+pub fn set_webview_limits(limits: WebviewLimits) -> Result<(), String> {
+    let registry = registry();
+    registry.save_limits(&limits)?;
+    *registry.limits.write() = limits;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_and_over_cap_webviews_are_suspended() {
+        let registry = Registry::new(
+            None,
+            WebviewLimits {
+                idle_suspend_secs: 60,
+                max_live: 2,
+            },
+        );
+        registry.register("a", "htree://nhash1a", 0);
+        registry.register("b", "htree://nhash1b", 50_000);
+        registry.register("c", "htree://nhash1c", 55_000);
+        assert_eq!(registry.to_suspend(56_000), vec!["a".to_string()]);
+
+        // Idle past the limit; c is in the foreground and stays
+        registry.touch("a", 56_000);
+        assert_eq!(registry.to_suspend(115_500), vec!["b".to_string()]);
+        assert_eq!(
+            registry.to_suspend(200_000),
+            vec!["a".to_string(), "b".to_string()]
+        );
+
+        // Suspended ones don't count
+        registry.entries.write().get_mut("a").unwrap().suspended_url =
+            Some("htree://nhash1a".to_string());
+        assert!(registry.to_suspend(56_000).is_empty());
+        assert!(registry.list()[0].suspended);
+    }
+
+    #[test]
+    fn test_forget_returns_origin_once_unused() {
+        let registry = Registry::new(None, WebviewLimits::default());
+        registry.register("a", "htree://npub1x.public", 0);
+        registry.register("b", "htree://npub1x.public", 0);
+        assert_eq!(registry.forget("a"), None);
+        assert_eq!(
+            registry.forget("b"),
+            Some("htree://npub1x.public".to_string())
+        );
+        assert_eq!(registry.forget("b"), None);
+    }
+}
@@ -0,0 +1,238 @@
+//! Stored identities and switching between them
+//!
+//! Every signing identity set with `setIdentity` is remembered as an
+//! account, keyed by its hex pubkey, together with its Blossom servers and
+//! the names of the trees it published. Switching makes another account the active one: its
+//! keys sign Nostr events and Blossom requests, and its pubkey becomes the
+//! social graph root. Accounts are persisted in `accounts.json`, but secret
+//! keys are only held in memory; the frontend provides them with
+//! `setIdentity` as before, and an account whose key wasn't given in this
+//! session switches in read-only.
+
+use nostr_sdk::{Keys, SecretKey};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// File in the data dir holding the accounts
+const ACCOUNTS_FILE: &str = "accounts.json";
+
+/// Settings kept per account
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Account {
+    /// Empty until the account sets its own servers
+    #[serde(default)]
+    read_servers: Vec<String>,
+    #[serde(default)]
+    write_servers: Vec<String>,
+    /// Trees published by the account
+    #[serde(default)]
+    trees: BTreeSet<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Accounts {
+    #[serde(default)]
+    active: Option<String>,
+    /// Hex pubkey -> account
+    #[serde(default)]
+    accounts: BTreeMap<String, Account>,
+}
+
+/// An account as reported by `listAccounts`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInfo {
+    pub pubkey: String,
+    pub active: bool,
+    /// Whether the secret key was given in this session
+    pub can_sign: bool,
+    pub read_servers: Vec<String>,
+    pub write_servers: Vec<String>,
+    pub trees: Vec<String>,
+}
+
+/// What the worker needs to make an account the active one
+pub struct ActiveAccount {
+    pub pubkey: String,
+    /// None for a read-only account
+    pub keys: Option<Keys>,
+    pub read_servers: Vec<String>,
+    pub write_servers: Vec<String>,
+}
+
+/// Stored accounts and the active one, persisted as JSON
+pub struct AccountManager {
+    path: PathBuf,
+    accounts: RwLock<Accounts>,
+    /// Hex pubkey -> secret key, never written to disk
+    secrets: RwLock<HashMap<String, SecretKey>>,
+}
+
+impl AccountManager {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join(ACCOUNTS_FILE);
+        let accounts = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            accounts: RwLock::new(accounts),
+            secrets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn save(&self, accounts: &Accounts) -> Result<(), String> {
+        let data = serde_json::to_vec(accounts)
+            .map_err(|e| format!("Failed to encode accounts: {}", e))?;
+        std::fs::write(&self.path, data).map_err(|e| format!("Failed to save accounts: {}", e))
+    }
+
+    /// Remember `pubkey` (hex) as an account and make it the active one
+    pub fn add(&self, pubkey: &str, secret_key: Option<SecretKey>) -> Result<(), String> {
+        if let Some(secret_key) = secret_key {
+            self.secrets.write().insert(pubkey.to_string(), secret_key);
+        }
+        let mut accounts = self.accounts.write();
+        accounts.accounts.entry(pubkey.to_string()).or_default();
+        accounts.active = Some(pubkey.to_string());
+        self.save(&accounts)
+    }
+
+    /// Make a stored account the active one
+    pub fn switch(&self, pubkey: &str) -> Result<ActiveAccount, String> {
+        let mut accounts = self.accounts.write();
+        let account = accounts
+            .accounts
+            .get(pubkey)
+            .cloned()
+            .ok_or_else(|| format!("Unknown account: {}", pubkey))?;
+        accounts.active = Some(pubkey.to_string());
+        self.save(&accounts)?;
+        Ok(ActiveAccount {
+            pubkey: pubkey.to_string(),
+            keys: self.secrets.read().get(pubkey).cloned().map(Keys::new),
+            read_servers: account.read_servers,
+            write_servers: account.write_servers,
+        })
+    }
+
+    /// Remember the active account's Blossom servers
+    pub fn set_servers(
+        &self,
+        read_servers: Vec<String>,
+        write_servers: Vec<String>,
+    ) -> Result<(), String> {
+        let mut accounts = self.accounts.write();
+        let Some(active) = accounts.active.clone() else {
+            return Ok(());
+        };
+        if let Some(account) = accounts.accounts.get_mut(&active) {
+            account.read_servers = read_servers;
+            account.write_servers = write_servers;
+        }
+        self.save(&accounts)
+    }
+
+    /// Add a tree to the list of `pubkey`'s published trees
+    pub fn add_tree(&self, pubkey: &str, tree_name: &str) -> Result<(), String> {
+        let mut accounts = self.accounts.write();
+        let Some(account) = accounts.accounts.get_mut(pubkey) else {
+            return Ok(());
+        };
+        if !account.trees.insert(tree_name.to_string()) {
+            return Ok(());
+        }
+        self.save(&accounts)
+    }
+
+    /// Remove a deleted tree from the list of `pubkey`'s published trees
+    pub fn remove_tree(&self, pubkey: &str, tree_name: &str) -> Result<(), String> {
+        let mut accounts = self.accounts.write();
+        let Some(account) = accounts.accounts.get_mut(pubkey) else {
+            return Ok(());
+        };
+        if !account.trees.remove(tree_name) {
+            return Ok(());
+        }
+        self.save(&accounts)
+    }
+
+    pub fn list(&self) -> Vec<AccountInfo> {
+        let accounts = self.accounts.read();
+        let secrets = self.secrets.read();
+        accounts
+            .accounts
+            .iter()
+            .map(|(pubkey, account)| AccountInfo {
+                pubkey: pubkey.clone(),
+                active: accounts.active.as_ref() == Some(pubkey),
+                can_sign: secrets.contains_key(pubkey),
+                read_servers: account.read_servers.clone(),
+                write_servers: account.write_servers.clone(),
+                trees: account.trees.iter().cloned().collect(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_accounts_keep_separate_settings() {
+        let dir = TempDir::new().unwrap();
+        let manager = AccountManager::new(dir.path());
+        let alice = Keys::generate();
+        let alice_pk = alice.public_key().to_hex();
+        let bob_pk = Keys::generate().public_key().to_hex();
+
+        manager
+            .add(&alice_pk, Some(alice.secret_key().clone()))
+            .unwrap();
+        manager
+            .set_servers(vec!["https://a.example".into()], vec![])
+            .unwrap();
+        manager.add_tree(&alice_pk, "photos").unwrap();
+        manager.add(&bob_pk, None).unwrap();
+        manager.add_tree(&bob_pk, "notes").unwrap();
+
+        let switched = manager.switch(&alice_pk).unwrap();
+        assert_eq!(
+            switched.keys.map(|k| k.public_key().to_hex()),
+            Some(alice_pk.clone())
+        );
+        assert_eq!(switched.read_servers, vec!["https://a.example"]);
+        assert!(manager.switch(&bob_pk).unwrap().keys.is_none());
+        assert!(manager.switch("00").is_err());
+
+        let list = manager.list();
+        let bob = list.iter().find(|a| a.pubkey == bob_pk).unwrap();
+        assert!(bob.active && !bob.can_sign);
+        assert_eq!(bob.trees, vec!["notes"]);
+        assert!(bob.read_servers.is_empty());
+    }
+
+    #[test]
+    fn test_secret_keys_are_not_persisted() {
+        let dir = TempDir::new().unwrap();
+        let keys = Keys::generate();
+        let pubkey = keys.public_key().to_hex();
+        AccountManager::new(dir.path())
+            .add(&pubkey, Some(keys.secret_key().clone()))
+            .unwrap();
+
+        let saved = std::fs::read_to_string(dir.path().join(ACCOUNTS_FILE)).unwrap();
+        assert!(!saved.contains(&keys.secret_key().to_secret_hex()));
+
+        let reopened = AccountManager::new(dir.path());
+        let list = reopened.list();
+        assert!(list[0].active && !list[0].can_sign);
+        assert!(reopened.switch(&pubkey).unwrap().keys.is_none());
+    }
+}
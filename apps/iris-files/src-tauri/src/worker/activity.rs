@@ -0,0 +1,390 @@
+//! Feed of tree updates from people we follow
+//!
+//! `watch` subscribes to tree root events (kind 30078 labelled `hashtree`)
+//! by our follows, from just after the newest entry, or `BACKFILL` back on
+//! the first run. Each new root is recorded as who published which tree
+//! when. If the tree's previous root and the new one are both in the local
+//! blob store, the entry also counts the top-level entries added, removed
+//! and changed. The newest `MAX_ENTRIES` are kept in `activity.json`;
+//! `getActivityFeed` pages through them newest first.
+
+use nostr_sdk::{Event, Filter, Kind, PublicKey, RelayPoolNotification, SubscriptionId, Timestamp};
+use nostrdb::{Ndb, Transaction};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::nostr::NostrManager;
+use super::shares::tag_value;
+use super::store::BlobStore;
+use super::tree::TreeManager;
+use super::types::{WorkerCid, WorkerDirEntry};
+
+/// Subscription id of the relay watch
+const WATCH_SUB_ID: &str = "activity-watch";
+const KIND_HASHTREE: u16 = 30078;
+const TREE_LABEL: &str = "hashtree";
+/// Entries kept
+pub const MAX_ENTRIES: usize = 1000;
+/// How far back the first watch looks, in seconds
+const BACKFILL: u64 = 24 * 60 * 60;
+
+/// Top-level entries of a tree that changed between two roots
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+/// A tree root published by someone we follow
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityEntry {
+    pub event_id: String,
+    /// Author, hex
+    pub author: String,
+    pub tree_name: String,
+    pub hash: String,
+    /// Key of public trees; private roots aren't readable here
+    pub key: Option<String>,
+    pub created_at: u64,
+    /// Change from the tree's previous root, if both were cached
+    pub diff: Option<DiffSummary>,
+}
+
+impl ActivityEntry {
+    /// Entry for tree root `event`, None for other events
+    pub fn from_event(event: &Event) -> Option<Self> {
+        if event.kind != Kind::from(KIND_HASHTREE) || tag_value(event, "l")? != TREE_LABEL {
+            return None;
+        }
+        Some(Self {
+            event_id: event.id.to_hex(),
+            author: event.pubkey.to_hex(),
+            tree_name: tag_value(event, "d")?.to_string(),
+            hash: tag_value(event, "hash")?.to_string(),
+            key: tag_value(event, "key").map(str::to_string),
+            created_at: event.created_at.as_u64(),
+            diff: None,
+        })
+    }
+
+    fn cid(&self) -> WorkerCid {
+        WorkerCid {
+            hash: self.hash.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+/// Count the names added, removed and pointing elsewhere from `old` to `new`
+pub fn diff_entries(old: &[WorkerDirEntry], new: &[WorkerDirEntry]) -> DiffSummary {
+    let old: HashMap<&str, &str> = old
+        .iter()
+        .map(|entry| (entry.name.as_str(), entry.hash.as_str()))
+        .collect();
+    let mut summary = DiffSummary::default();
+    let mut seen = HashSet::new();
+    for entry in new {
+        seen.insert(entry.name.as_str());
+        match old.get(entry.name.as_str()) {
+            None => summary.added += 1,
+            Some(hash) if *hash != entry.hash => summary.changed += 1,
+            Some(_) => {}
+        }
+    }
+    summary.removed = old.keys().filter(|name| !seen.contains(*name)).count();
+    summary
+}
+
+/// Recorded tree updates and the relay watch feeding them
+pub struct ActivityFeed {
+    path: PathBuf,
+    /// Newest first
+    entries: RwLock<Vec<ActivityEntry>>,
+    watch: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl ActivityFeed {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("activity.json");
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            entries: RwLock::new(entries),
+            watch: Mutex::new(None),
+        }
+    }
+
+    /// Up to `limit` entries older than `before` (seconds), and whether
+    /// there are more
+    pub fn page(&self, before: Option<u64>, limit: usize) -> (Vec<ActivityEntry>, bool) {
+        let entries = self.entries.read();
+        let mut older = entries
+            .iter()
+            .filter(|entry| before.map_or(true, |before| entry.created_at < before));
+        let page: Vec<_> = older.by_ref().take(limit).cloned().collect();
+        let has_more = older.next().is_some();
+        (page, has_more)
+    }
+
+    /// Latest recorded root of `author`'s tree `tree_name`
+    pub fn latest(&self, author: &str, tree_name: &str) -> Option<ActivityEntry> {
+        self.entries
+            .read()
+            .iter()
+            .find(|entry| entry.author == author && entry.tree_name == tree_name)
+            .cloned()
+    }
+
+    /// Record `entry`, unless it's known or older than the tree's latest
+    /// root. Returns whether it was recorded.
+    pub fn record(&self, entry: ActivityEntry) -> Result<bool, String> {
+        let mut entries = self.entries.read().clone();
+        let known = entries.iter().any(|e| {
+            e.event_id == entry.event_id
+                || (e.author == entry.author
+                    && e.tree_name == entry.tree_name
+                    && (e.created_at >= entry.created_at || e.hash == entry.hash))
+        });
+        if known {
+            return Ok(false);
+        }
+        let at = entries.partition_point(|e| e.created_at >= entry.created_at);
+        entries.insert(at, entry);
+        entries.truncate(MAX_ENTRIES);
+
+        let data = serde_json::to_vec(&entries)
+            .map_err(|e| format!("Failed to encode activity feed: {}", e))?;
+        std::fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to save activity feed: {}", e))?;
+        *self.entries.write() = entries;
+        Ok(true)
+    }
+
+    fn newest_at(&self) -> Option<u64> {
+        self.entries.read().first().map(|entry| entry.created_at)
+    }
+
+    /// Watch the relays for tree updates by the follows of the current
+    /// identity, replacing an earlier watch. Stops when there's no identity.
+    pub async fn watch(
+        self: &Arc<Self>,
+        nostr: &NostrManager,
+        ndb: &Ndb,
+        tree: Arc<tokio::sync::RwLock<Option<TreeManager>>>,
+        store: Arc<BlobStore>,
+    ) {
+        if let Some(task) = self.watch.lock().take() {
+            task.abort();
+        }
+        let Some(client) = nostr.get_client() else {
+            return;
+        };
+        let sub_id = SubscriptionId::new(WATCH_SUB_ID);
+        let follows = nostr
+            .get_keys()
+            .map(|keys| followed(ndb, &keys.public_key()))
+            .unwrap_or_default();
+        if follows.is_empty() {
+            client.unsubscribe(sub_id).await;
+            return;
+        }
+
+        let since = match self.newest_at() {
+            Some(newest) => newest + 1,
+            None => Timestamp::now().as_u64().saturating_sub(BACKFILL),
+        };
+        let filter = Filter::new()
+            .kind(Kind::from(KIND_HASHTREE))
+            .authors(follows)
+            .since(Timestamp::from(since));
+        if let Err(e) = client
+            .subscribe_with_id(sub_id.clone(), vec![filter], None)
+            .await
+        {
+            warn!("Failed to watch for tree updates: {}", e);
+            return;
+        }
+
+        let feed = self.clone();
+        let task = tokio::spawn(async move {
+            let mut notifications = client.notifications();
+            // The same event arrives from each relay
+            let mut seen = HashSet::new();
+            loop {
+                let notification = match notifications.recv().await {
+                    Ok(notification) => notification,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let RelayPoolNotification::Event {
+                    event,
+                    subscription_id,
+                    ..
+                } = notification
+                else {
+                    continue;
+                };
+                if subscription_id != sub_id || !seen.insert(event.id) {
+                    continue;
+                }
+                let Some(mut entry) = ActivityEntry::from_event(&event) else {
+                    continue;
+                };
+                if let Some(previous) = feed.latest(&entry.author, &entry.tree_name) {
+                    if let Some(tree) = tree.read().await.as_ref() {
+                        entry.diff = summarize(tree, &store, &previous, &entry).await;
+                    }
+                }
+                match feed.record(entry) {
+                    Ok(true) => debug!("Recorded tree update {}", event.id),
+                    Ok(false) => {}
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        });
+        // A watch started meanwhile is replaced too
+        if let Some(earlier) = self.watch.lock().replace(task) {
+            earlier.abort();
+        }
+    }
+}
+
+/// Pubkeys `pubkey` follows, per nostrdb
+fn followed(ndb: &Ndb, pubkey: &PublicKey) -> Vec<PublicKey> {
+    let Ok(txn) = Transaction::new(ndb) else {
+        return Vec::new();
+    };
+    // Relays refuse filters with many more authors than this
+    nostrdb::socialgraph::get_followed(&txn, ndb, &pubkey.to_bytes(), 1000)
+        .iter()
+        .filter_map(|pk| PublicKey::from_slice(pk).ok())
+        .collect()
+}
+
+/// Diff of two roots of a tree, if both are in the local store
+async fn summarize(
+    tree: &TreeManager,
+    store: &BlobStore,
+    old: &ActivityEntry,
+    new: &ActivityEntry,
+) -> Option<DiffSummary> {
+    if !store.has(&old.hash) || !store.has(&new.hash) {
+        return None;
+    }
+    let old_entries = tree.list_dir(&old.cid()).await.ok()?;
+    let new_entries = tree.list_dir(&new.cid()).await.ok()?;
+    Some(diff_entries(&old_entries, &new_entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys, Tag, TagKind};
+    use tempfile::TempDir;
+
+    fn entry(author: &str, tree: &str, hash: &str, created_at: u64) -> ActivityEntry {
+        ActivityEntry {
+            event_id: format!("{}-{}-{}", author, tree, created_at),
+            author: author.to_string(),
+            tree_name: tree.to_string(),
+            hash: hash.to_string(),
+            key: None,
+            created_at,
+            diff: None,
+        }
+    }
+
+    fn dir_entry(name: &str, hash: &str) -> WorkerDirEntry {
+        WorkerDirEntry {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            size: 0,
+            link_type: 1,
+            key: None,
+        }
+    }
+
+    #[test]
+    fn test_entry_from_tree_event() {
+        let keys = Keys::generate();
+        let tag =
+            |name: &str, value: &str| Tag::custom(TagKind::custom(name), vec![value.to_string()]);
+        let event = EventBuilder::new(
+            Kind::from(KIND_HASHTREE),
+            "",
+            [tag("d", "photos"), tag("l", "hashtree"), tag("hash", "ab")],
+        )
+        .to_event(&keys)
+        .unwrap();
+        let entry = ActivityEntry::from_event(&event).unwrap();
+        assert_eq!(entry.author, keys.public_key().to_hex());
+        assert_eq!(entry.tree_name, "photos");
+        assert_eq!(entry.hash, "ab");
+
+        let share = EventBuilder::new(
+            Kind::from(KIND_HASHTREE),
+            "",
+            [tag("d", "x"), tag("l", "share"), tag("hash", "ab")],
+        )
+        .to_event(&keys)
+        .unwrap();
+        assert!(ActivityEntry::from_event(&share).is_none());
+    }
+
+    #[test]
+    fn test_record_and_page() {
+        let dir = TempDir::new().unwrap();
+        let feed = ActivityFeed::new(dir.path());
+        assert!(feed.record(entry("a", "photos", "h1", 10)).unwrap());
+        assert!(feed.record(entry("b", "music", "h2", 30)).unwrap());
+        assert!(feed.record(entry("a", "photos", "h3", 20)).unwrap());
+        // Older than the tree's latest root, or the same root again
+        assert!(!feed.record(entry("a", "photos", "h0", 5)).unwrap());
+        assert!(!feed.record(entry("a", "photos", "h3", 25)).unwrap());
+
+        let (page, has_more) = feed.page(None, 2);
+        let times: Vec<_> = page.iter().map(|e| e.created_at).collect();
+        assert_eq!(times, vec![30, 20]);
+        assert!(has_more);
+        let (page, has_more) = feed.page(Some(20), 2);
+        assert_eq!(page[0].hash, "h1");
+        assert!(!has_more);
+        assert_eq!(feed.latest("a", "photos").unwrap().hash, "h3");
+
+        let reopened = ActivityFeed::new(dir.path());
+        assert_eq!(reopened.page(None, 10).0.len(), 3);
+    }
+
+    #[test]
+    fn test_diff_entries() {
+        let old = [
+            dir_entry("a", "1"),
+            dir_entry("b", "2"),
+            dir_entry("c", "3"),
+        ];
+        let new = [
+            dir_entry("a", "1"),
+            dir_entry("b", "9"),
+            dir_entry("d", "4"),
+        ];
+        assert_eq!(
+            diff_entries(&old, &new),
+            DiffSummary {
+                added: 1,
+                removed: 1,
+                changed: 1,
+            }
+        );
+    }
+}
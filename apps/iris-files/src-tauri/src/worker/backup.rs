@@ -0,0 +1,507 @@
+//! Export and import of the whole local state, for moving to a new device
+//!
+//! An archive is a single file: a magic line, then entries of
+//! `[path len u16 LE][path][data len u64 LE][data]`. The first entry is
+//! `manifest.json` with the relay and Blossom settings; the rest are the
+//! blob store, nostrdb, search index, history and the JSON settings files of
+//! the data dir, under their paths relative to it.
+//!
+//! The identity's secret key is only exported when a passphrase is given,
+//! and then NIP-49 encrypted (`ncryptsec`). Without the passphrase an import
+//! restores everything but the key.
+//!
+//! The databases are open while the app runs, so an export archives
+//! consistent copies the caller makes first (`ndb_maintenance::snapshot`,
+//! `SearchIndex::copy_to`, `HistoryStore::copy_to`), never the live files.
+//! For the same reason an import only stages the files in
+//! `import-pending/`; `apply_pending_import` moves them into place on the
+//! next start, before anything is opened. Replaced files are kept in
+//! `import-previous/` until the next import.
+
+use nostr_sdk::nips::nip19::{FromBech32, ToBech32};
+use nostr_sdk::nips::nip49::{EncryptedSecretKey, KeySecurity};
+use nostr_sdk::SecretKey;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, warn};
+
+use super::progress::ProgressReporter;
+use super::types::StateSettings;
+
+/// First bytes of every archive
+const MAGIC: &[u8] = b"htree-state 1\n";
+
+/// Name of the first entry
+const MANIFEST: &str = "manifest.json";
+
+/// Staging directory for an import, applied on the next start
+const PENDING_DIR: &str = "import-pending";
+
+/// Written last into the staging directory; partial imports lack it
+const COMPLETE_MARKER: &str = ".complete";
+
+/// Where files replaced by an import are kept
+const PREVIOUS_DIR: &str = "import-previous";
+
+/// Data dir entries carried in an archive. Transcode caches are left out,
+/// they're rebuilt on demand.
+const STATE_PATHS: &[&str] = &[
+    "blobs",
+    "nostrdb",
+    "search",
+    "history",
+    "shares.json",
+    "origins.json",
+    "accounts.json",
+    "acl.json",
+    "rate_limits.json",
+];
+
+/// LMDB databases among the state paths, taken from the snapshot dir
+const DATABASE_PATHS: &[&str] = &["nostrdb", "search", "history"];
+
+/// scrypt cost of the NIP-49 key encryption (2^16 rounds)
+const KEY_LOG_N: u8 = 16;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    version: u32,
+    created_at: u64,
+    settings: StateSettings,
+    /// NIP-49 encrypted secret key, if exported with a passphrase
+    #[serde(default)]
+    ncryptsec: Option<String>,
+}
+
+/// Files and bytes written to or read from an archive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveStats {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// What an import restored
+#[derive(Debug)]
+pub struct ImportedState {
+    pub stats: ArchiveStats,
+    pub settings: StateSettings,
+    /// Decrypted secret key, when the archive had one and the passphrase
+    /// was given
+    pub secret_key: Option<SecretKey>,
+}
+
+/// LMDB lock files belong to one process, and .tmp files are in-flight writes
+fn is_skipped(name: &str) -> bool {
+    name == "lock.mdb" || name.ends_with(".tmp")
+}
+
+/// Files that go into an archive: (relative path, path, size). Databases
+/// come from `snapshots`, everything else from `data_dir`.
+fn collect_files(data_dir: &Path, snapshots: &Path) -> io::Result<Vec<(String, PathBuf, u64)>> {
+    let mut files = Vec::new();
+    let mut stack: Vec<(String, PathBuf)> = STATE_PATHS
+        .iter()
+        .map(|name| {
+            let root = if DATABASE_PATHS.contains(name) {
+                snapshots
+            } else {
+                data_dir
+            };
+            (name.to_string(), root.join(name))
+        })
+        .collect();
+    while let Some((rel, path)) = stack.pop() {
+        let metadata = match fs::metadata(&path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if metadata.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !is_skipped(&name) {
+                    stack.push((format!("{}/{}", rel, name), entry.path()));
+                }
+            }
+        } else {
+            files.push((rel, path, metadata.len()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn write_entry(out: &mut impl Write, path: &str, len: u64, data: &mut impl Read) -> io::Result<()> {
+    let path_len = u16::try_from(path.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path too long"))?;
+    out.write_all(&path_len.to_le_bytes())?;
+    out.write_all(path.as_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    let copied = io::copy(&mut data.take(len), out)?;
+    if copied != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} changed while exporting", path),
+        ));
+    }
+    Ok(())
+}
+
+/// Read an entry header; None at the end of the archive
+fn read_entry_header(input: &mut impl Read) -> io::Result<Option<(String, u64)>> {
+    let mut path_len = [0u8; 2];
+    match input.read_exact(&mut path_len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut path = vec![0u8; u16::from_le_bytes(path_len) as usize];
+    input.read_exact(&mut path)?;
+    let path = String::from_utf8(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid entry path"))?;
+    let mut len = [0u8; 8];
+    input.read_exact(&mut len)?;
+    Ok(Some((path, u64::from_le_bytes(len))))
+}
+
+/// Whether an archive path stays inside one of the state paths
+fn is_valid_entry_path(path: &str) -> bool {
+    let rel = Path::new(path);
+    rel.components().all(|c| matches!(c, Component::Normal(_)))
+        && rel
+            .components()
+            .next()
+            .is_some_and(|first| STATE_PATHS.iter().any(|p| first.as_os_str() == *p))
+}
+
+/// Write the state of `data_dir` to an archive at `out`, with the databases
+/// copied into `snapshots` beforehand. The secret key is included,
+/// encrypted, only when `identity` carries it with a passphrase.
+pub fn export_state(
+    data_dir: &Path,
+    snapshots: &Path,
+    out: &Path,
+    settings: StateSettings,
+    identity: Option<(&SecretKey, &str)>,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<ArchiveStats, String> {
+    let ncryptsec = match identity {
+        Some((secret_key, passphrase)) => {
+            let key =
+                EncryptedSecretKey::new(secret_key, passphrase, KEY_LOG_N, KeySecurity::Medium)
+                    .map_err(|e| format!("Failed to encrypt key: {}", e))?;
+            Some(
+                key.to_bech32()
+                    .map_err(|e| format!("Failed to encode key: {}", e))?,
+            )
+        }
+        None => None,
+    };
+    let manifest = serde_json::to_vec_pretty(&Manifest {
+        version: 1,
+        created_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        settings,
+        ncryptsec,
+    })
+    .map_err(|e| format!("Failed to encode manifest: {}", e))?;
+
+    let files =
+        collect_files(data_dir, snapshots).map_err(|e| format!("Failed to list state: {}", e))?;
+    if let Some(progress) = progress.as_deref_mut() {
+        let total = files.iter().map(|(_, _, size)| size).sum();
+        progress.phase("export", Some(files.len() as u64), Some(total));
+    }
+
+    let tmp_path = out.with_extension("tmp");
+    let mut write = || -> io::Result<ArchiveStats> {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_all(MAGIC)?;
+        write_entry(
+            &mut writer,
+            MANIFEST,
+            manifest.len() as u64,
+            &mut manifest.as_slice(),
+        )?;
+        let mut stats = ArchiveStats::default();
+        for (rel, path, size) in &files {
+            let mut file = File::open(path)?;
+            write_entry(&mut writer, rel, *size, &mut file)?;
+            stats.files += 1;
+            stats.bytes += size;
+            if let Some(progress) = progress.as_deref_mut() {
+                progress.advance(1, *size);
+            }
+        }
+        writer.into_inner()?.sync_all()?;
+        Ok(stats)
+    };
+    let stats = write().map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Failed to write archive: {}", e)
+    })?;
+    fs::rename(&tmp_path, out).map_err(|e| format!("Failed to save archive: {}", e))?;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    info!(
+        "Exported {} files ({} bytes) to {:?}",
+        stats.files, stats.bytes, out
+    );
+    Ok(stats)
+}
+
+/// Stage the archive at `archive` for the next start. The passphrase
+/// decrypts the secret key, if the archive has one.
+pub fn import_state(
+    data_dir: &Path,
+    archive: &Path,
+    passphrase: Option<&str>,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<ImportedState, String> {
+    let archive_len = fs::metadata(archive)
+        .map_err(|e| format!("Failed to open archive: {}", e))?
+        .len();
+    let mut input =
+        BufReader::new(File::open(archive).map_err(|e| format!("Failed to open archive: {}", e))?);
+    let mut magic = [0u8; MAGIC.len()];
+    if input.read_exact(&mut magic).is_err() || magic != MAGIC {
+        return Err("Not a hashtree state archive".to_string());
+    }
+
+    let invalid = |e: io::Error| format!("Invalid archive: {}", e);
+    let manifest: Manifest = match read_entry_header(&mut input).map_err(invalid)? {
+        Some((path, len)) if path == MANIFEST && len <= 1024 * 1024 => {
+            let mut data = vec![0u8; len as usize];
+            input.read_exact(&mut data).map_err(invalid)?;
+            serde_json::from_slice(&data).map_err(|e| format!("Invalid manifest: {}", e))?
+        }
+        _ => return Err("Archive has no manifest".to_string()),
+    };
+    // Checked before anything is staged, so a wrong passphrase changes nothing
+    let secret_key = match (&manifest.ncryptsec, passphrase) {
+        (Some(ncryptsec), Some(passphrase)) => Some(
+            EncryptedSecretKey::from_bech32(ncryptsec)
+                .map_err(|e| e.to_string())
+                .and_then(|key| key.to_secret_key(passphrase).map_err(|e| e.to_string()))
+                .map_err(|e| format!("Failed to decrypt key: {}", e))?,
+        ),
+        (Some(_), None) => {
+            warn!("Archive has an encrypted key but no passphrase was given");
+            None
+        }
+        (None, _) => None,
+    };
+
+    let pending = data_dir.join(PENDING_DIR);
+    if pending.exists() {
+        fs::remove_dir_all(&pending).map_err(|e| format!("Failed to clear staging: {}", e))?;
+    }
+    if let Some(progress) = progress.as_deref_mut() {
+        progress.phase("import", None, Some(archive_len));
+    }
+
+    let mut stats = ArchiveStats::default();
+    let mut stage = || -> Result<(), String> {
+        while let Some((path, len)) = read_entry_header(&mut input).map_err(invalid)? {
+            if !is_valid_entry_path(&path) {
+                return Err(format!("Invalid archive path: {}", path));
+            }
+            let target = pending.join(&path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Failed to stage: {}", e))?;
+            }
+            let mut file =
+                File::create(&target).map_err(|e| format!("Failed to stage {}: {}", path, e))?;
+            let copied = io::copy(&mut (&mut input).take(len), &mut file)
+                .map_err(|e| format!("Failed to stage {}: {}", path, e))?;
+            if copied != len {
+                return Err(format!("Archive truncated in {}", path));
+            }
+            stats.files += 1;
+            stats.bytes += len;
+            if let Some(progress) = progress.as_deref_mut() {
+                progress.advance(1, len);
+            }
+        }
+        fs::write(pending.join(COMPLETE_MARKER), b"").map_err(|e| format!("Failed to stage: {}", e))
+    };
+    if let Err(e) = stage() {
+        let _ = fs::remove_dir_all(&pending);
+        return Err(e);
+    }
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    info!(
+        "Staged {} files ({} bytes) for import",
+        stats.files, stats.bytes
+    );
+
+    Ok(ImportedState {
+        stats,
+        settings: manifest.settings,
+        secret_key,
+    })
+}
+
+/// Move a staged import into place. Must run before any store in the data
+/// dir is opened; returns whether an import was applied.
+pub fn apply_pending_import(data_dir: &Path) -> Result<bool, String> {
+    let pending = data_dir.join(PENDING_DIR);
+    if !pending.exists() {
+        return Ok(false);
+    }
+    if !pending.join(COMPLETE_MARKER).exists() {
+        warn!("Discarding incomplete import in {:?}", pending);
+        fs::remove_dir_all(&pending).map_err(|e| format!("Failed to discard import: {}", e))?;
+        return Ok(false);
+    }
+
+    let previous = data_dir.join(PREVIOUS_DIR);
+    if previous.exists() {
+        fs::remove_dir_all(&previous)
+            .map_err(|e| format!("Failed to clear {:?}: {}", previous, e))?;
+    }
+    let move_err = |e: io::Error| format!("Failed to apply import: {}", e);
+    for name in STATE_PATHS {
+        let staged = pending.join(name);
+        if !staged.exists() {
+            continue;
+        }
+        let target = data_dir.join(name);
+        if target.exists() {
+            fs::create_dir_all(&previous).map_err(move_err)?;
+            fs::rename(&target, previous.join(name)).map_err(move_err)?;
+        }
+        fs::rename(&staged, &target).map_err(move_err)?;
+    }
+    fs::remove_dir_all(&pending).map_err(move_err)?;
+    info!("Applied imported state to {:?}", data_dir);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn settings() -> StateSettings {
+        StateSettings {
+            pubkey: Some("a".repeat(64)),
+            relays: vec!["wss://relay.example".to_string()],
+            read_servers: vec!["https://blossom.example".to_string()],
+            write_servers: Vec::new(),
+        }
+    }
+
+    /// Fills `dir` with state and returns its snapshot dir
+    fn populate(dir: &Path) -> PathBuf {
+        fs::create_dir_all(dir.join("blobs/ab/cd")).unwrap();
+        fs::write(dir.join("blobs/ab/cd/ef"), b"blob").unwrap();
+        fs::write(dir.join("blobs/ab/cd/ef.tmp"), b"partial").unwrap();
+        // The live database is never read
+        fs::create_dir_all(dir.join("nostrdb")).unwrap();
+        fs::write(dir.join("nostrdb/data.mdb"), b"live").unwrap();
+        let snapshots = dir.join("snapshots");
+        fs::create_dir_all(snapshots.join("nostrdb")).unwrap();
+        fs::write(snapshots.join("nostrdb/data.mdb"), b"events").unwrap();
+        fs::write(snapshots.join("nostrdb/lock.mdb"), b"lock").unwrap();
+        fs::write(dir.join("shares.json"), b"{}").unwrap();
+        fs::create_dir_all(dir.join("hls")).unwrap();
+        fs::write(dir.join("hls/cache"), b"skip").unwrap();
+        snapshots
+    }
+
+    #[test]
+    fn test_export_and_import_roundtrip() {
+        let source = TempDir::new().unwrap();
+        let snapshots = populate(source.path());
+        let archive = source.path().join("state.htstate");
+        let keys = nostr_sdk::Keys::generate();
+
+        let exported = export_state(
+            source.path(),
+            &snapshots,
+            &archive,
+            settings(),
+            Some((keys.secret_key(), "correct horse")),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            exported,
+            ArchiveStats {
+                files: 3,
+                bytes: 12
+            }
+        );
+
+        let target = TempDir::new().unwrap();
+        fs::write(target.path().join("shares.json"), b"old").unwrap();
+        assert!(import_state(target.path(), &archive, Some("wrong"), None).is_err());
+        assert!(!target.path().join(PENDING_DIR).exists());
+
+        let imported = import_state(target.path(), &archive, Some("correct horse"), None).unwrap();
+        assert_eq!(imported.stats, exported);
+        assert_eq!(imported.settings, settings());
+        assert_eq!(imported.secret_key.as_ref(), Some(keys.secret_key()));
+        // Nothing changes until the next start
+        assert_eq!(fs::read(target.path().join("shares.json")).unwrap(), b"old");
+
+        assert!(apply_pending_import(target.path()).unwrap());
+        assert_eq!(
+            fs::read(target.path().join("blobs/ab/cd/ef")).unwrap(),
+            b"blob"
+        );
+        assert_eq!(
+            fs::read(target.path().join("nostrdb/data.mdb")).unwrap(),
+            b"events"
+        );
+        assert_eq!(fs::read(target.path().join("shares.json")).unwrap(), b"{}");
+        assert_eq!(
+            fs::read(target.path().join(PREVIOUS_DIR).join("shares.json")).unwrap(),
+            b"old"
+        );
+        assert!(!target.path().join("nostrdb/lock.mdb").exists());
+        assert!(!target.path().join("hls").exists());
+        assert!(!apply_pending_import(target.path()).unwrap());
+    }
+
+    #[test]
+    fn test_key_is_left_out_without_passphrase() {
+        let source = TempDir::new().unwrap();
+        let snapshots = populate(source.path());
+        let archive = source.path().join("state.htstate");
+        export_state(source.path(), &snapshots, &archive, settings(), None, None).unwrap();
+
+        let target = TempDir::new().unwrap();
+        let imported = import_state(target.path(), &archive, Some("anything"), None).unwrap();
+        assert!(imported.secret_key.is_none());
+    }
+
+    #[test]
+    fn test_rejects_paths_outside_state() {
+        assert!(is_valid_entry_path("blobs/ab/cd/ef"));
+        assert!(is_valid_entry_path("shares.json"));
+        assert!(!is_valid_entry_path("blobs/../../etc/passwd"));
+        assert!(!is_valid_entry_path("/etc/passwd"));
+        assert!(!is_valid_entry_path("hls/cache"));
+        assert!(!is_valid_entry_path(""));
+    }
+
+    #[test]
+    fn test_incomplete_import_is_discarded() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join(PENDING_DIR).join("blobs")).unwrap();
+        assert!(!apply_pending_import(dir.path()).unwrap());
+        assert!(!dir.path().join(PENDING_DIR).exists());
+        assert!(!dir.path().join("blobs").exists());
+    }
+}
@@ -7,6 +7,8 @@ use nostr_sdk::Keys;
 use parking_lot::RwLock;
 use tracing::{debug, info};
 
+use crate::proxy::{self, Component};
+
 /// Default Blossom servers
 const DEFAULT_WRITE_SERVERS: &[&str] = &[
     "https://upload.iris.to",
@@ -48,13 +50,29 @@ impl BlossomManager {
 
         let client = BlossomClient::new_empty(keys.clone())
             .with_read_servers(read_servers)
-            .with_write_servers(write_servers);
+            .with_write_servers(write_servers)
+            .with_proxy(proxy::reqwest_proxy(Component::Blossom));
 
         *self.client.write() = Some(client);
         *self.keys.write() = Some(keys);
         info!("Blossom client initialized");
     }
 
+    /// Rebuild the client with the current proxy setting
+    pub fn apply_proxy(&self) {
+        let mut client = self.client.write();
+        if let Some(current) = client.take() {
+            *client = Some(current.with_proxy(proxy::reqwest_proxy(Component::Blossom)));
+        }
+    }
+
+    /// Drop the keys and client; servers set until new keys arrive are queued
+    pub fn clear_keys(&self) {
+        self.client.write().take();
+        self.keys.write().take();
+        info!("Blossom keys cleared");
+    }
+
     /// Check if client is initialized
     pub fn is_initialized(&self) -> bool {
         self.client.read().is_some()
@@ -94,6 +112,25 @@ impl BlossomManager {
         Ok(data)
     }
 
+    /// Delete a blob from the write servers
+    /// Returns how many of them no longer have it
+    pub async fn delete(&self, hash: &str) -> Result<usize, BlossomError> {
+        let client = self
+            .client
+            .read()
+            .clone()
+            .ok_or_else(|| BlossomError::NoServers)?;
+
+        let deleted = client.delete(hash).await?;
+        debug!(
+            "Deleted {}... from {} servers",
+            &hash[..12.min(hash.len())],
+            deleted
+        );
+
+        Ok(deleted)
+    }
+
     /// Check if a blob exists on any server
     pub async fn exists(&self, hash: &str) -> Result<bool, BlossomError> {
         let client = self
@@ -136,7 +173,8 @@ impl BlossomManager {
             // Keys available - update client now
             let client = BlossomClient::new_empty(keys)
                 .with_read_servers(read_servers.clone())
-                .with_write_servers(write_servers.clone());
+                .with_write_servers(write_servers.clone())
+                .with_proxy(proxy::reqwest_proxy(Component::Blossom));
 
             *self.client.write() = Some(client);
             info!(
@@ -0,0 +1,320 @@
+//! Collaborative trees
+//!
+//! An owner lists a tree's collaborators in its root event (see
+//! `tree_roots::Commit`). A collaborator suggests a change by publishing a
+//! proposal: a kind 30079 event addressed to the owner (`p`), naming the
+//! tree's address (`a`), the proposed root (`hash`) and the root it was
+//! made on (`parent`), with the commit message as content. The proposed
+//! root's key and the message of an encrypted root are NIP-44 encrypted to
+//! the owner. Proposals
+//! are replaceable per owner and tree, so a collaborator's newer proposal
+//! supersedes their older one.
+//!
+//! The owner's client lists the proposals of current collaborators, shows
+//! what they change and merges them into the tree (`TreeManager::merge`),
+//! publishing the result with the collaborator as its `author`. Merged and
+//! dismissed proposals are kept in `proposals.json`, so they aren't listed
+//! again.
+
+use nostr_sdk::nips::nip44;
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, PublicKey, Tag};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use super::tree_roots::{TreeRoot, KIND_TREE_ROOT};
+use super::types::WorkerCid;
+
+/// Kind of root proposals
+pub const KIND_ROOT_PROPOSAL: u16 = 30079;
+
+const TAG_OWNER_ENCRYPTED_KEY: &str = "ownerEncryptedKey";
+
+/// A collaborator's proposed root for one of our trees
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Proposal {
+    pub event_id: String,
+    /// Hex pubkey of the collaborator
+    pub author: String,
+    pub tree_name: String,
+    #[serde(flatten)]
+    pub cid: WorkerCid,
+    /// Hash of the root the change was made on
+    pub base: Option<String>,
+    pub message: Option<String>,
+    pub created_at: u64,
+}
+
+/// Address of `owner`'s tree `tree_name`, as in `a` tags
+pub fn tree_address(owner: &PublicKey, tree_name: &str) -> String {
+    format!("{}:{}:{}", KIND_TREE_ROOT, owner.to_hex(), tree_name)
+}
+
+/// Proposal of `cid` as the new root of `owner`'s tree `tree_name`, made
+/// on the root `base`
+pub fn proposal_event(
+    keys: &Keys,
+    owner: &PublicKey,
+    tree_name: &str,
+    cid: &WorkerCid,
+    base: Option<&str>,
+    message: Option<&str>,
+) -> Result<Event, String> {
+    let parse = |values: &[&str]| Tag::parse(values).map_err(|e| e.to_string());
+    let mut tags = vec![
+        Tag::identifier(format!("{}:{}", owner.to_hex(), tree_name)),
+        parse(&["a", tree_address(owner, tree_name).as_str()])?,
+        Tag::public_key(*owner),
+        parse(&["l", "hashtree"])?,
+        parse(&["hash", cid.hash.as_str()])?,
+    ];
+    if let Some(base) = base {
+        tags.push(parse(&["parent", base])?);
+    }
+    let encrypt = |plaintext: &str| {
+        nip44::encrypt(keys.secret_key(), owner, plaintext, nip44::Version::V2)
+            .map_err(|e| format!("Failed to encrypt proposal: {}", e))
+    };
+    let mut content = message.unwrap_or_default().to_string();
+    if let Some(key) = &cid.key {
+        tags.push(parse(&[TAG_OWNER_ENCRYPTED_KEY, encrypt(key)?.as_str()])?);
+        if !content.is_empty() {
+            content = encrypt(&content)?;
+        }
+    }
+    EventBuilder::new(Kind::from(KIND_ROOT_PROPOSAL), content, tags)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+/// Read a proposal addressed to the owner of `keys`, None if `event` isn't
+/// one or its key can't be decrypted
+pub fn read_proposal(event: &Event, keys: &Keys) -> Option<Proposal> {
+    if event.kind.as_u16() != KIND_ROOT_PROPOSAL {
+        return None;
+    }
+    let tag = |name: &str| {
+        event.tags.iter().find_map(|tag| {
+            let values = tag.as_slice();
+            (values.len() >= 2 && values[0] == name).then(|| values[1].clone())
+        })
+    };
+    let owner = keys.public_key();
+    if tag("p")? != owner.to_hex() {
+        return None;
+    }
+    let prefix = tree_address(&owner, "");
+    let tree_name = tag("a")?.strip_prefix(&prefix)?.to_string();
+    let hash = tag("hash").filter(|hash| !hash.is_empty())?;
+    let decrypt = |ciphertext: &str| nip44::decrypt(keys.secret_key(), &event.pubkey, ciphertext);
+    let (key, message) = match tag(TAG_OWNER_ENCRYPTED_KEY) {
+        Some(ciphertext) => {
+            let key = decrypt(&ciphertext).ok()?;
+            hashtree_core::key_from_hex(&key).ok()?;
+            let message = Some(event.content.as_str())
+                .filter(|content| !content.is_empty())
+                .and_then(|content| decrypt(content).ok());
+            (Some(key), message)
+        }
+        None => (None, Some(event.content.clone())),
+    };
+
+    Some(Proposal {
+        event_id: event.id.to_hex(),
+        author: event.pubkey.to_hex(),
+        tree_name,
+        cid: WorkerCid { hash, key },
+        base: tag("parent").filter(|base| !base.is_empty()),
+        message: message.filter(|m| !m.is_empty()),
+        created_at: event.created_at.as_u64(),
+    })
+}
+
+/// The proposals among `proposals` still open, newest first: each current
+/// collaborator's newest proposal for a tree in `roots`, unless it was
+/// handled or already is the tree's root
+pub fn open_proposals(
+    proposals: Vec<Proposal>,
+    roots: &[TreeRoot],
+    handled: &ProposalRegistry,
+) -> Vec<Proposal> {
+    let root_of = |proposal: &Proposal| {
+        roots
+            .iter()
+            .find(|root| root.name == proposal.tree_name)
+            .filter(|root| root.collaborators.contains(&proposal.author))
+    };
+    let mut newest: HashMap<(String, String), Proposal> = HashMap::new();
+    for proposal in proposals {
+        if root_of(&proposal).is_none() {
+            continue;
+        }
+        let key = (proposal.author.clone(), proposal.tree_name.clone());
+        let older = newest
+            .get(&key)
+            .is_some_and(|seen| seen.created_at >= proposal.created_at);
+        if !older {
+            newest.insert(key, proposal);
+        }
+    }
+    let mut open: Vec<Proposal> = newest
+        .into_values()
+        .filter(|proposal| {
+            root_of(proposal).is_some_and(|root| root.cid.hash != proposal.cid.hash)
+                && !handled.is_handled(&proposal.event_id)
+        })
+        .collect();
+    open.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    open
+}
+
+/// Merged and dismissed proposals by event id, persisted as JSON, and the
+/// ones last listed
+pub struct ProposalRegistry {
+    path: PathBuf,
+    handled: RwLock<BTreeSet<String>>,
+    listed: RwLock<HashMap<String, Proposal>>,
+}
+
+impl ProposalRegistry {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("proposals.json");
+        let handled = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            handled: RwLock::new(handled),
+            listed: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_handled(&self, event_id: &str) -> bool {
+        self.handled.read().contains(event_id)
+    }
+
+    /// Don't list the proposal `event_id` again
+    pub fn mark_handled(&self, event_id: &str) -> Result<(), String> {
+        self.listed.write().remove(event_id);
+        let mut handled = self.handled.write();
+        if !handled.insert(event_id.to_string()) {
+            return Ok(());
+        }
+        let data = serde_json::to_vec_pretty(&*handled)
+            .map_err(|e| format!("Failed to encode proposals: {}", e))?;
+        std::fs::write(&self.path, data).map_err(|e| format!("Failed to save proposals: {}", e))
+    }
+
+    /// Remember the proposals just listed, for looking them up by id
+    pub fn set_listed(&self, proposals: &[Proposal]) {
+        let mut listed = self.listed.write();
+        for proposal in proposals {
+            listed.insert(proposal.event_id.clone(), proposal.clone());
+        }
+    }
+
+    pub fn listed(&self, event_id: &str) -> Option<Proposal> {
+        self.listed.read().get(event_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::htree::TreeVisibility;
+    use crate::worker::nostr::build_tree_commit_event;
+    use crate::worker::tree_roots::{tree_root, Commit};
+    use tempfile::TempDir;
+
+    fn cid(n: u8) -> WorkerCid {
+        WorkerCid {
+            hash: format!("{:02x}", n).repeat(32),
+            key: Some("cd".repeat(32)),
+        }
+    }
+
+    #[test]
+    fn test_proposal_round_trip() {
+        let owner = Keys::generate();
+        let collaborator = Keys::generate();
+        let other = Keys::generate();
+        let base = "01".repeat(32);
+
+        let event = proposal_event(
+            &collaborator,
+            &owner.public_key(),
+            "docs",
+            &cid(2),
+            Some(&base),
+            Some("Fix typo"),
+        )
+        .unwrap();
+        let proposal = read_proposal(&event, &owner).unwrap();
+        assert_eq!(proposal.author, collaborator.public_key().to_hex());
+        assert_eq!(proposal.tree_name, "docs");
+        assert_eq!(proposal.cid.hash, cid(2).hash);
+        assert_eq!(proposal.cid.key, cid(2).key);
+        assert_eq!(proposal.base, Some(base));
+        assert_eq!(proposal.message.as_deref(), Some("Fix typo"));
+        assert_ne!(event.content, "Fix typo");
+
+        // Only the owner reads a proposal addressed to them
+        assert!(read_proposal(&event, &other).is_none());
+        assert!(read_proposal(&event, &collaborator).is_none());
+    }
+
+    #[test]
+    fn test_open_proposals() {
+        let owner = Keys::generate();
+        let collaborator = Keys::generate();
+        let stranger = Keys::generate();
+        let dir = TempDir::new().unwrap();
+        let registry = ProposalRegistry::new(dir.path());
+
+        let root_event = build_tree_commit_event(
+            &owner,
+            "docs",
+            &cid(1),
+            &TreeVisibility::Public,
+            None,
+            &Commit {
+                collaborators: vec![collaborator.public_key().to_hex()],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let roots = vec![tree_root(&root_event, Some(&owner)).unwrap()];
+
+        let propose = |keys: &Keys, tree_name: &str, n: u8, created_at: u64| {
+            let event =
+                proposal_event(keys, &owner.public_key(), tree_name, &cid(n), None, None).unwrap();
+            Proposal {
+                created_at,
+                ..read_proposal(&event, &owner).unwrap()
+            }
+        };
+        let proposals = vec![
+            propose(&collaborator, "docs", 2, 10),
+            propose(&collaborator, "docs", 3, 20),
+            propose(&stranger, "docs", 4, 30),
+            propose(&collaborator, "music", 5, 40),
+        ];
+
+        // Only the collaborator's newest proposal for a tree of ours
+        let open = open_proposals(proposals.clone(), &roots, &registry);
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].cid.hash, cid(3).hash);
+
+        // Handling it doesn't bring back the one it superseded
+        registry.mark_handled(&open[0].event_id).unwrap();
+        let reloaded = ProposalRegistry::new(dir.path());
+        assert!(reloaded.is_handled(&open[0].event_id));
+        assert!(open_proposals(proposals, &roots, &reloaded).is_empty());
+
+        let merged = vec![propose(&collaborator, "docs", 1, 50)];
+        assert!(open_proposals(merged, &roots, &registry).is_empty());
+    }
+}
@@ -1,25 +1,227 @@
 //! Combined store that checks local filesystem first, then Blossom
 //!
 //! This allows tree operations to fetch blobs from Blossom if not cached locally.
+//! Blocks read through it are kept in a size-bounded in-memory LRU, so hot
+//! tree nodes hit on every path resolution skip the filesystem entirely.
+//! Concurrent gets of a block missing locally share one Blossom request.
+//!
+//! Blocks fetched from Blossom are written to disk straight away, except
+//! while reading a file over the admission size that isn't pinned (see
+//! [`on_probation`]): its blocks are only written once read again while
+//! their hash is still on probation, so streaming a long video once
+//! doesn't evict the whole disk cache. The admission settings are kept in
+//! `cache-admission.json`.
 
 use async_trait::async_trait;
 use hashtree_blossom::{BlossomClient, BlossomStore};
+use hashtree_core::store::slice_range;
 use hashtree_core::{to_hex, Store, StoreError};
 use hashtree_fs::FsBlobStore;
+use lru::LruCache;
 use nostr_sdk::Keys;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{OnceCell, RwLock};
 use tracing::{debug, warn};
 
+use crate::proxy::{self, Component};
+
 /// Default Blossom servers for fetching blobs
 const DEFAULT_BLOSSOM_SERVERS: &[&str] = &[
     "https://cdn.iris.to",
 ];
 
+/// Memory budget of the block cache
+const BLOCK_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Blocks larger than this are read through without being cached. Full
+/// file chunks fit, so one on probation can be read again from memory.
+const BLOCK_CACHE_MAX_ITEM_BYTES: usize = hashtree_core::DEFAULT_CHUNK_SIZE;
+
+/// File in the data dir holding the admission settings
+const ADMISSION_FILE: &str = "cache-admission.json";
+
+/// Blocks of files up to this size are written to disk on first read
+pub const DEFAULT_ADMIT_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Hashes of larger blocks remembered after their first read
+const PROBATION_ENTRIES: usize = 16 * 1024;
+
+/// Block cache counters, reported with the storage stats
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub items: u64,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+/// LRU of recently read blocks, bounded by total size. Shared with the
+/// `BlobStore` so deleting a block also drops it from memory.
+pub(crate) struct BlockCache {
+    blocks: Mutex<CachedBlocks>,
+    max_bytes: u64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct CachedBlocks {
+    lru: LruCache<[u8; 32], Arc<Vec<u8>>>,
+    bytes: u64,
+}
+
+impl BlockCache {
+    fn new(max_bytes: u64) -> Self {
+        Self {
+            blocks: Mutex::new(CachedBlocks {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_bytes,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn with_default_size() -> Self {
+        Self::new(BLOCK_CACHE_MAX_BYTES)
+    }
+
+    /// Look up a block, counting the hit or miss
+    fn get(&self, hash: &[u8; 32]) -> Option<Arc<Vec<u8>>> {
+        let found = self.blocks.lock().lru.get(hash).cloned();
+        let counter = if found.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.blocks.lock().lru.contains(hash)
+    }
+
+    fn insert(&self, hash: [u8; 32], data: &[u8]) {
+        if data.len() > BLOCK_CACHE_MAX_ITEM_BYTES {
+            return;
+        }
+        let mut blocks = self.blocks.lock();
+        if let Some(old) = blocks.lru.put(hash, Arc::new(data.to_vec())) {
+            blocks.bytes -= old.len() as u64;
+        }
+        blocks.bytes += data.len() as u64;
+        while blocks.bytes > self.max_bytes {
+            let Some((_, evicted)) = blocks.lru.pop_lru() else {
+                break;
+            };
+            blocks.bytes -= evicted.len() as u64;
+        }
+    }
+
+    pub(crate) fn remove(&self, hash: &[u8; 32]) {
+        let mut blocks = self.blocks.lock();
+        if let Some(old) = blocks.lru.pop(hash) {
+            blocks.bytes -= old.len() as u64;
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut blocks = self.blocks.lock();
+        blocks.lru.clear();
+        blocks.bytes = 0;
+    }
+
+    fn stats(&self) -> CacheStats {
+        let blocks = self.blocks.lock();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            items: blocks.lru.len() as u64,
+            bytes: blocks.bytes,
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+/// Which blocks fetched from Blossom are written to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdmissionSettings {
+    /// Disabled, every fetched block is written
+    pub enabled: bool,
+    /// Files over this size that aren't pinned have their blocks written
+    /// only on their second read
+    pub large_file_bytes: u64,
+}
+
+impl Default for AdmissionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            large_file_bytes: DEFAULT_ADMIT_FILE_BYTES,
+        }
+    }
+}
+
+impl AdmissionSettings {
+    /// Settings saved in `data_dir`, the defaults if there are none
+    pub fn load(data_dir: &Path) -> Self {
+        std::fs::read(data_dir.join(ADMISSION_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, data_dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        crate::atomic_file::write(&data_dir.join(ADMISSION_FILE), json)
+            .map_err(|e| format!("Failed to save cache admission: {}", e))
+    }
+
+    /// Whether blocks of an unpinned file of `size` go on probation
+    pub fn probation(&self, size: u64) -> bool {
+        self.enabled && size > self.large_file_bytes
+    }
+}
+
+tokio::task_local! {
+    /// Set while a task reads a file whose blocks go on probation
+    static PROBATION: ();
+}
+
+/// Run `read`, putting blocks it fetches from Blossom on probation
+/// instead of writing them to disk
+pub async fn on_probation<F: Future>(read: F) -> F::Output {
+    PROBATION.scope((), read).await
+}
+
+struct Admission {
+    settings: AdmissionSettings,
+    /// Blocks read once on probation, cached if read again while
+    /// remembered here
+    probation: LruCache<[u8; 32], ()>,
+}
+
+/// Outcome of a Blossom fetch, shared by every get waiting on it
+type InFlight = OnceCell<Result<Option<Vec<u8>>, String>>;
+
 /// Combined store that checks local filesystem first, then Blossom
 pub struct CombinedStore {
     local: Arc<FsBlobStore>,
     blossom: Arc<RwLock<BlossomStore>>,
+    cache: Arc<BlockCache>,
+    /// Blossom fetches in progress by hash
+    in_flight: Mutex<HashMap<[u8; 32], Arc<InFlight>>>,
+    admission: Mutex<Admission>,
 }
 
 impl CombinedStore {
@@ -27,41 +229,96 @@ impl CombinedStore {
         // Create default Blossom store with anonymous keys for read-only access
         let keys = Keys::generate();
         let blossom_client = BlossomClient::new_empty(keys)
-            .with_read_servers(DEFAULT_BLOSSOM_SERVERS.iter().map(|s| s.to_string()).collect());
+            .with_read_servers(DEFAULT_BLOSSOM_SERVERS.iter().map(|s| s.to_string()).collect())
+            .with_proxy(proxy::reqwest_proxy(Component::Blossom));
         let blossom_store = BlossomStore::new(blossom_client);
 
         Self {
             local,
             blossom: Arc::new(RwLock::new(blossom_store)),
+            cache: Arc::new(BlockCache::with_default_size()),
+            in_flight: Mutex::new(HashMap::new()),
+            admission: Mutex::new(Admission {
+                settings: AdmissionSettings::default(),
+                probation: LruCache::new(NonZeroUsize::new(PROBATION_ENTRIES).unwrap()),
+            }),
+        }
+    }
+
+    /// Use `cache` as the in-memory block cache, e.g. the one of the
+    /// `BlobStore` that `local` belongs to
+    pub(crate) fn with_block_cache(mut self, cache: Arc<BlockCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Hit/miss counters and size of the in-memory block cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    pub fn admission(&self) -> AdmissionSettings {
+        self.admission.lock().settings
+    }
+
+    pub fn set_admission(&self, settings: AdmissionSettings) {
+        let mut admission = self.admission.lock();
+        admission.settings = settings;
+        if !settings.enabled {
+            admission.probation.clear();
+        }
+    }
+
+    /// Whether a block fetched from Blossom goes to disk now: unless read
+    /// [`on_probation`] for the first time, in which case it's put on
+    /// probation
+    fn admit(&self, hash: &[u8; 32]) -> bool {
+        let mut admission = self.admission.lock();
+        if !admission.settings.enabled
+            || PROBATION.try_with(|_| ()).is_err()
+            || admission.probation.pop(hash).is_some()
+        {
+            return true;
         }
+        admission.probation.put(*hash, ());
+        false
     }
 
     /// Update Blossom read servers
     pub async fn set_blossom_servers(&self, read_servers: Vec<String>, keys: Option<Keys>) {
         let keys = keys.unwrap_or_else(Keys::generate);
         let blossom_client = BlossomClient::new_empty(keys)
-            .with_read_servers(read_servers);
+            .with_read_servers(read_servers)
+            .with_proxy(proxy::reqwest_proxy(Component::Blossom));
         let mut guard = self.blossom.write().await;
         *guard = BlossomStore::new(blossom_client);
     }
-}
 
-#[async_trait]
-impl Store for CombinedStore {
-    async fn get(&self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError> {
-        // Try local store first
-        if let Ok(Some(data)) = self.local.get(hash).await {
-            debug!("Found blob {} in local store ({} bytes)", &to_hex(hash)[..8], data.len());
-            return Ok(Some(data));
-        }
+    /// Rebuild the Blossom client with the current proxy setting
+    pub async fn apply_proxy(&self) {
+        let mut guard = self.blossom.write().await;
+        let blossom_client = guard
+            .client()
+            .clone()
+            .with_proxy(proxy::reqwest_proxy(Component::Blossom));
+        *guard = BlossomStore::new(blossom_client);
+    }
 
-        // Fall back to Blossom
+    async fn fetch_blossom(&self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, String> {
         let blossom = self.blossom.read().await;
         match blossom.get(hash).await {
             Ok(Some(data)) => {
                 debug!("Found blob {} in Blossom ({} bytes)", &to_hex(hash)[..8], data.len());
-                // Cache locally for future requests
+                self.cache.insert(*hash, &data);
                 drop(blossom); // Release read lock before writing
+                if !self.admit(hash) {
+                    debug!(
+                        "Keeping blob {} off disk until it's read again",
+                        &to_hex(hash)[..8]
+                    );
+                    return Ok(Some(data));
+                }
+                // Cache locally for future requests
                 match self.local.put(*hash, data.clone()).await {
                     Ok(_) => debug!("Cached blob {} locally", &to_hex(hash)[..8]),
                     Err(e) => warn!("Failed to cache blob locally: {}", e),
@@ -74,18 +331,75 @@ impl Store for CombinedStore {
             }
             Err(e) => {
                 warn!("Blossom fetch error for {}: {}", &to_hex(hash)[..8], e);
-                Err(StoreError::Other(e.to_string()))
+                Err(e.to_string())
             }
         }
     }
+}
+
+#[async_trait]
+impl Store for CombinedStore {
+    async fn get(&self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Some(data) = self.cache.get(hash) {
+            // Read again while on probation: now it's worth keeping
+            if self.admission.lock().probation.pop(hash).is_some() {
+                if let Err(e) = self.local.put(*hash, data.to_vec()).await {
+                    warn!("Failed to cache blob locally: {}", e);
+                }
+            }
+            return Ok(Some(data.to_vec()));
+        }
+
+        // Try local store first
+        if let Ok(Some(data)) = self.local.get(hash).await {
+            debug!("Found blob {} in local store ({} bytes)", &to_hex(hash)[..8], data.len());
+            self.cache.insert(*hash, &data);
+            return Ok(Some(data));
+        }
+
+        // Fall back to Blossom, joining a fetch of the same block already
+        // in progress. If its caller goes away, a waiting one takes over.
+        let flight = self.in_flight.lock().entry(*hash).or_default().clone();
+        let result = flight
+            .get_or_init(|| self.fetch_blossom(hash))
+            .await
+            .clone();
+        let mut in_flight = self.in_flight.lock();
+        if in_flight
+            .get(hash)
+            .is_some_and(|current| Arc::ptr_eq(current, &flight))
+        {
+            in_flight.remove(hash);
+        }
+        result.map_err(StoreError::Other)
+    }
+
+    /// Slices of local blobs are read in place. Anything else goes through
+    /// `get`, so a block from Blossom is verified whole and cached as usual.
+    async fn get_range(
+        &self,
+        hash: &[u8; 32],
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        if !self.cache.contains(hash) {
+            if let Ok(Some(data)) = self.local.get_range(hash, offset, len).await {
+                return Ok(Some(data));
+            }
+        }
+        Ok(self
+            .get(hash)
+            .await?
+            .map(|data| slice_range(&data, offset, len).to_vec()))
+    }
 
     async fn put(&self, hash: [u8; 32], data: Vec<u8>) -> Result<bool, StoreError> {
         self.local.put(hash, data).await
     }
 
     async fn has(&self, hash: &[u8; 32]) -> Result<bool, StoreError> {
-        // Check local first
-        if self.local.has(hash).await? {
+        // Check memory, then local
+        if self.cache.contains(hash) || self.local.has(hash).await? {
             return Ok(true);
         }
 
@@ -97,7 +411,28 @@ impl Store for CombinedStore {
             .map_err(|e| StoreError::Other(e.to_string()))
     }
 
+    async fn has_many(&self, hashes: &[[u8; 32]]) -> Result<Vec<bool>, StoreError> {
+        let mut found = self.local.has_many(hashes).await?;
+
+        // Ask Blossom about the rest in one batch
+        let missing: Vec<usize> = (0..hashes.len()).filter(|&i| !found[i]).collect();
+        if missing.is_empty() {
+            return Ok(found);
+        }
+        let missing_hashes: Vec<[u8; 32]> = missing.iter().map(|&i| hashes[i]).collect();
+        let blossom = self.blossom.read().await;
+        let remote = blossom
+            .has_many(&missing_hashes)
+            .await
+            .map_err(|e| StoreError::Other(e.to_string()))?;
+        for (i, exists) in missing.into_iter().zip(remote) {
+            found[i] = exists;
+        }
+        Ok(found)
+    }
+
     async fn delete(&self, hash: &[u8; 32]) -> Result<bool, StoreError> {
+        self.cache.remove(hash);
         self.local.delete(hash).await
     }
 }
@@ -122,6 +457,86 @@ mod tests {
         assert_eq!(data, Some(b"test data".to_vec()));
     }
 
+    #[tokio::test]
+    async fn test_repeated_reads_hit_the_block_cache() {
+        let dir = tempdir().unwrap();
+        let local = Arc::new(FsBlobStore::new(dir.path()).unwrap());
+        let store = CombinedStore::new(local.clone());
+
+        let hash = [0xbb; 32];
+        store.put(hash, b"tree node".to_vec()).await.unwrap();
+        store.get(&hash).await.unwrap();
+        // Served from memory even once the file is gone
+        std::fs::remove_dir_all(dir.path()).unwrap();
+        assert_eq!(store.get(&hash).await.unwrap(), Some(b"tree node".to_vec()));
+
+        let stats = store.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!((stats.items, stats.bytes), (1, 9));
+
+        store.delete(&hash).await.unwrap();
+        assert_eq!(store.cache_stats().items, 0);
+    }
+
+    #[tokio::test]
+    async fn test_blocks_on_probation_go_to_disk_on_second_read() {
+        let dir = tempdir().unwrap();
+        let local = Arc::new(FsBlobStore::new(dir.path()).unwrap());
+        let store = CombinedStore::new(local.clone());
+        let chunk = vec![7; hashtree_core::DEFAULT_CHUNK_SIZE];
+
+        assert!(store.admit(&[1; 32]));
+        assert!(!on_probation(async { store.admit(&[2; 32]) }).await);
+        // Read again from memory while on probation
+        store.cache.insert([2; 32], &chunk);
+        assert_eq!(store.get(&[2; 32]).await.unwrap(), Some(chunk.clone()));
+        assert!(local.has(&[2; 32]).await.unwrap());
+        // Or fetched again once evicted from memory
+        on_probation(async {
+            assert!(!store.admit(&[3; 32]));
+            assert!(store.admit(&[3; 32]));
+        })
+        .await;
+
+        store.set_admission(AdmissionSettings {
+            enabled: false,
+            ..Default::default()
+        });
+        assert!(on_probation(async { store.admit(&[4; 32]) }).await);
+    }
+
+    #[test]
+    fn test_admission_settings_are_saved() {
+        let dir = tempdir().unwrap();
+        let settings = AdmissionSettings::load(dir.path());
+        assert_eq!(settings, AdmissionSettings::default());
+        assert!(!settings.probation(DEFAULT_ADMIT_FILE_BYTES));
+        assert!(settings.probation(DEFAULT_ADMIT_FILE_BYTES + 1));
+
+        let off = AdmissionSettings {
+            enabled: false,
+            large_file_bytes: 1,
+        };
+        off.save(dir.path()).unwrap();
+        assert_eq!(AdmissionSettings::load(dir.path()), off);
+        assert!(!off.probation(u64::MAX));
+    }
+
+    #[test]
+    fn test_block_cache_evicts_to_size_limit() {
+        let cache = BlockCache::new(10);
+        cache.insert([1; 32], &[0; 4]);
+        cache.insert([2; 32], &[0; 4]);
+        // Touch the first block so the second is least recently used
+        assert!(cache.get(&[1; 32]).is_some());
+        cache.insert([3; 32], &[0; 4]);
+
+        assert!(cache.contains(&[1; 32]));
+        assert!(!cache.contains(&[2; 32]));
+        assert!(cache.contains(&[3; 32]));
+        assert_eq!(cache.stats().bytes, 8);
+    }
+
     #[tokio::test]
     async fn test_combined_store_blossom_fallback() {
         // Test fetching the known media tree root from Blossom
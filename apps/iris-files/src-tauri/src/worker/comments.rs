@@ -0,0 +1,188 @@
+//! Comment threads on tree paths (NIP-22)
+//!
+//! A comment is a kind 1111 event scoped to a file or directory of someone's
+//! tree. Its root tags reference both the tree: `A` is the address of the
+//! tree root event (`30078:<owner>:<tree>`) with `P` its owner, and the
+//! path: `I` is `htree://<npub>/<tree>/<path>`, with `K` `htree`. Relays
+//! index `I`, so a thread is subscribed to with a `#I` filter. Top-level
+//! comments have the path as parent (`i`, `k`); replies have the comment
+//! they answer (`e`, `k` 1111, `p`).
+
+use nostr_sdk::nips::nip19::ToBech32;
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, PublicKey, Tag};
+use serde::Deserialize;
+
+use super::shares::parse_pubkey;
+
+/// Kind of NIP-22 comments
+pub const KIND_COMMENT: u16 = 1111;
+
+/// Kind of tree root events
+const KIND_TREE_ROOT: u16 = 30078;
+
+/// Scope kind of htree paths
+const PATH_SCOPE: &str = "htree";
+
+/// Comment a reply answers
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommentParent {
+    /// Event id, hex
+    pub id: String,
+    /// Author, hex
+    pub pubkey: String,
+}
+
+/// Where a comment thread hangs: a path in `owner`'s tree
+pub struct Thread<'a> {
+    pub owner: PublicKey,
+    pub tree_name: &'a str,
+    pub path: &'a str,
+}
+
+impl<'a> Thread<'a> {
+    /// Thread on `path` in tree `tree_name` of `owner` (hex or npub)
+    pub fn new(owner: &str, tree_name: &'a str, path: &'a str) -> Result<Self, String> {
+        Ok(Self {
+            owner: parse_pubkey(owner)?,
+            tree_name,
+            path,
+        })
+    }
+
+    /// Identifier of the path, `htree://<npub>/<tree>/<path>`
+    pub fn identifier(&self) -> String {
+        let npub = self
+            .owner
+            .to_bech32()
+            .unwrap_or_else(|_| self.owner.to_hex());
+        let path = self.path.trim_matches('/');
+        if path.is_empty() {
+            format!("htree://{}/{}", npub, self.tree_name)
+        } else {
+            format!("htree://{}/{}/{}", npub, self.tree_name, path)
+        }
+    }
+
+    /// Address of the tree root event
+    pub fn tree_address(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            KIND_TREE_ROOT,
+            self.owner.to_hex(),
+            self.tree_name
+        )
+    }
+
+    /// Filter for the thread's comments, as the JSON `subscribe` takes
+    pub fn filter(&self) -> serde_json::Value {
+        serde_json::json!({
+            "kinds": [KIND_COMMENT],
+            "#I": [self.identifier()],
+        })
+    }
+
+    /// Tags of a comment in the thread, replying to `parent` if given
+    pub fn tags(&self, parent: Option<&CommentParent>) -> Result<Vec<Tag>, String> {
+        let identifier = self.identifier();
+        let owner = self.owner.to_hex();
+        let mut tags = vec![
+            vec!["A".to_string(), self.tree_address()],
+            vec!["I".to_string(), identifier.clone()],
+            vec!["K".to_string(), PATH_SCOPE.to_string()],
+            vec!["P".to_string(), owner],
+        ];
+        match parent {
+            Some(parent) => {
+                let author = PublicKey::from_hex(&parent.pubkey)
+                    .map_err(|e| format!("Invalid parent pubkey: {}", e))?;
+                tags.push(vec![
+                    "e".to_string(),
+                    parent.id.clone(),
+                    String::new(),
+                    author.to_hex(),
+                ]);
+                tags.push(vec!["k".to_string(), KIND_COMMENT.to_string()]);
+                tags.push(vec!["p".to_string(), author.to_hex()]);
+            }
+            None => {
+                tags.push(vec!["i".to_string(), identifier]);
+                tags.push(vec!["k".to_string(), PATH_SCOPE.to_string()]);
+            }
+        }
+        tags.iter()
+            .map(|tag| Tag::parse(tag.as_slice()).map_err(|e| format!("Invalid tag: {}", e)))
+            .collect()
+    }
+
+    /// Comment `content` in the thread, signed by `keys`
+    pub fn comment(
+        &self,
+        keys: &Keys,
+        content: &str,
+        parent: Option<&CommentParent>,
+    ) -> Result<Event, String> {
+        if content.trim().is_empty() {
+            return Err("Empty comment".to_string());
+        }
+        EventBuilder::new(Kind::from(KIND_COMMENT), content, self.tags(parent)?)
+            .to_event(keys)
+            .map_err(|e| format!("Failed to sign event: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_values(event: &Event, name: &str) -> Vec<Vec<String>> {
+        event
+            .tags
+            .iter()
+            .map(|tag| tag.as_slice().to_vec())
+            .filter(|tag| tag[0] == name)
+            .collect()
+    }
+
+    #[test]
+    fn test_comment_and_reply_tags() {
+        let owner = Keys::generate().public_key();
+        let keys = Keys::generate();
+        let thread = Thread {
+            owner,
+            tree_name: "photos",
+            path: "/2024/beach.jpg",
+        };
+        let identifier = thread.identifier();
+        assert!(identifier.starts_with("htree://npub1"));
+        assert!(identifier.ends_with("/photos/2024/beach.jpg"));
+        assert_eq!(thread.filter()["#I"][0], identifier.as_str());
+
+        let comment = thread.comment(&keys, "Nice", None).unwrap();
+        assert_eq!(comment.kind, Kind::from(KIND_COMMENT));
+        assert_eq!(
+            tag_values(&comment, "A"),
+            vec![vec![
+                "A".to_string(),
+                format!("30078:{}:photos", owner.to_hex())
+            ]]
+        );
+        assert_eq!(tag_values(&comment, "i")[0][1], identifier);
+        assert_eq!(tag_values(&comment, "k")[0][1], "htree");
+        assert!(tag_values(&comment, "e").is_empty());
+
+        let parent = CommentParent {
+            id: comment.id.to_hex(),
+            pubkey: keys.public_key().to_hex(),
+        };
+        let reply = thread
+            .comment(&Keys::generate(), "Thanks", Some(&parent))
+            .unwrap();
+        assert_eq!(tag_values(&reply, "I")[0][1], identifier);
+        assert_eq!(tag_values(&reply, "e")[0][1], parent.id);
+        assert_eq!(tag_values(&reply, "k")[0][1], "1111");
+        assert_eq!(tag_values(&reply, "p")[0][1], parent.pubkey);
+        assert!(tag_values(&reply, "i").is_empty());
+
+        assert!(thread.comment(&keys, "  ", None).is_err());
+    }
+}
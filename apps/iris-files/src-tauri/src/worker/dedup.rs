@@ -0,0 +1,167 @@
+//! Per-subscription deduplication of events sent to the frontend
+//!
+//! A subscription's events come from the nostrdb cache and from every relay
+//! it was sent to, so the same event arrives several times, and replaceable
+//! events (profiles, contact lists, tree roots) can arrive older after
+//! newer. Each subscription passes an event on once, and a replaceable one
+//! only if it's newer than the one already passed on for its author, kind
+//! and `d` tag. Ties go to the lower id, as in NIP-01.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Event ids remembered per subscription; older ones are forgotten
+const MAX_SEEN: usize = 50_000;
+
+/// Kinds of which relays keep only the newest event (NIP-01)
+pub fn is_replaceable(kind: u64) -> bool {
+    matches!(kind, 0 | 3 | 10_000..=19_999 | 30_000..=39_999)
+}
+
+/// Kinds also keyed by their `d` tag
+fn is_addressable(kind: u64) -> bool {
+    (30_000..40_000).contains(&kind)
+}
+
+/// Kind, author and `d` tag of a replaceable event
+pub type Address = (u64, String, String);
+
+/// Address of `event` (JSON), None if it isn't replaceable
+pub fn address(event: &serde_json::Value) -> Option<Address> {
+    let kind = event["kind"].as_u64().unwrap_or(1);
+    if !is_replaceable(kind) {
+        return None;
+    }
+    let d_tag = if is_addressable(kind) {
+        d_tag(event).unwrap_or_default()
+    } else {
+        String::new()
+    };
+    let author = event["pubkey"].as_str().unwrap_or_default().to_string();
+    Some((kind, author, d_tag))
+}
+
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+    /// created_at and id of the newest event passed on per address
+    latest: HashMap<Address, (u64, String)>,
+}
+
+impl Seen {
+    fn admit(&mut self, event: &serde_json::Value) -> bool {
+        let Some(id) = event["id"].as_str() else {
+            return true;
+        };
+        if self.ids.contains(id) {
+            return false;
+        }
+        if let Some(address) = address(event) {
+            let created_at = event["created_at"].as_u64().unwrap_or(0);
+            if let Some((latest_at, latest_id)) = self.latest.get(&address) {
+                let newer = created_at > *latest_at
+                    || (created_at == *latest_at && id < latest_id.as_str());
+                if !newer {
+                    return false;
+                }
+            }
+            self.latest.insert(address, (created_at, id.to_string()));
+        }
+
+        self.ids.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        if self.order.len() > MAX_SEEN {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+fn d_tag(event: &serde_json::Value) -> Option<String> {
+    event["tags"]
+        .as_array()?
+        .iter()
+        .find(|tag| tag[0].as_str() == Some("d"))
+        .and_then(|tag| tag[1].as_str())
+        .map(str::to_string)
+}
+
+/// Events passed on so far, by subscription
+#[derive(Default)]
+pub struct EventDedup {
+    subscriptions: Mutex<HashMap<String, Seen>>,
+}
+
+impl EventDedup {
+    /// Whether `event` (JSON) should go to the frontend for `sub_id`
+    pub fn admit(&self, sub_id: &str, event: &serde_json::Value) -> bool {
+        self.subscriptions
+            .lock()
+            .entry(sub_id.to_string())
+            .or_default()
+            .admit(event)
+    }
+
+    /// Drop what `sub_id` has seen, once it's closed
+    pub fn forget(&self, sub_id: &str) {
+        self.subscriptions.lock().remove(sub_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(id: &str, kind: u64, created_at: u64, d: Option<&str>) -> serde_json::Value {
+        let tags = match d {
+            Some(d) => json!([["d", d]]),
+            None => json!([]),
+        };
+        json!({
+            "id": id,
+            "pubkey": "aa",
+            "kind": kind,
+            "created_at": created_at,
+            "tags": tags,
+        })
+    }
+
+    #[test]
+    fn test_duplicates_are_dropped_per_subscription() {
+        let dedup = EventDedup::default();
+        let note = event("01", 1, 10, None);
+        assert!(dedup.admit("a", &note));
+        assert!(!dedup.admit("a", &note));
+        assert!(dedup.admit("b", &note));
+
+        dedup.forget("a");
+        assert!(dedup.admit("a", &note));
+    }
+
+    #[test]
+    fn test_replaceable_events_never_regress() {
+        let dedup = EventDedup::default();
+        assert!(dedup.admit("s", &event("02", 0, 20, None)));
+        assert!(!dedup.admit("s", &event("01", 0, 10, None)), "older");
+        assert!(
+            !dedup.admit("s", &event("03", 0, 20, None)),
+            "tie, higher id"
+        );
+        assert!(dedup.admit("s", &event("00", 0, 20, None)), "tie, lower id");
+        assert!(dedup.admit("s", &event("04", 0, 30, None)));
+
+        // Tree roots are kept apart by their d tag
+        assert!(dedup.admit("s", &event("10", 30078, 50, Some("photos"))));
+        assert!(dedup.admit("s", &event("11", 30078, 40, Some("music"))));
+        assert!(!dedup.admit("s", &event("12", 30078, 45, Some("photos"))));
+        assert!(dedup.admit("s", &event("13", 30078, 60, Some("photos"))));
+
+        // Regular events are never compared
+        assert!(dedup.admit("s", &event("20", 1, 5, None)));
+        assert!(dedup.admit("s", &event("21", 1, 1, None)));
+    }
+}
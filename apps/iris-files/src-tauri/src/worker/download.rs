@@ -0,0 +1,486 @@
+//! Saving files and directories of a tree to disk
+//!
+//! `downloadToDisk` streams each file into `<name>.part` beside its
+//! destination. Once everything is written, the `.part` files are read back
+//! and checked chunk by chunk against a second pass over the tree, and only
+//! then renamed into place; a file that doesn't match is deleted. A download
+//! that stopped half way, failed or cut off by a restart, continues from the
+//! end of its `.part` files when it's requested again. `pauseDownload` holds
+//! a running download between chunks until `resumeDownload`.
+//!
+//! Files saved from the web go through a `.part` file too, but as there's
+//! nothing to check them against, they start over each time and the `.part`
+//! file is deleted when they fail. They're capped at
+//! [`MAX_WEB_DOWNLOAD_BYTES`].
+
+use futures::StreamExt;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+
+use super::progress::ProgressReporter;
+use super::tree::TreeManager;
+use super::types::WorkerCid;
+
+/// Suffix of files still being downloaded
+const PART_SUFFIX: &str = ".part";
+
+/// Largest file saved from the web
+pub const MAX_WEB_DOWNLOAD_BYTES: u64 = 4 << 30;
+
+/// Longest wait for the next chunk of a file saved from the web
+const WEB_CHUNK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Pause switches of the running downloads, by request id
+#[derive(Default)]
+pub struct Downloads {
+    running: Mutex<HashMap<String, watch::Sender<bool>>>,
+}
+
+impl Downloads {
+    /// Register download `id` until the returned handle is dropped
+    pub fn start(self: &Arc<Self>, id: &str) -> DownloadHandle {
+        let (paused_tx, paused) = watch::channel(false);
+        self.running.lock().insert(id.to_string(), paused_tx);
+        DownloadHandle {
+            downloads: self.clone(),
+            id: id.to_string(),
+            paused,
+        }
+    }
+
+    /// Pause or resume download `id`, false if it isn't running
+    pub fn set_paused(&self, id: &str, paused: bool) -> bool {
+        match self.running.lock().get(id) {
+            Some(paused_tx) => {
+                paused_tx.send_replace(paused);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A running download, registered in `Downloads`
+pub struct DownloadHandle {
+    downloads: Arc<Downloads>,
+    id: String,
+    paused: watch::Receiver<bool>,
+}
+
+impl DownloadHandle {
+    async fn wait_if_paused(&mut self) {
+        let _ = self.paused.wait_for(|paused| !paused).await;
+    }
+}
+
+impl Drop for DownloadHandle {
+    fn drop(&mut self) {
+        self.downloads.running.lock().remove(&self.id);
+    }
+}
+
+/// Result of a finished download
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DownloadStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// A file of the tree and where it goes
+struct DownloadFile {
+    cid: WorkerCid,
+    target: PathBuf,
+    size: Option<u64>,
+}
+
+/// Write the file or directory at `cid` to `dest`, replacing what's there
+pub async fn download(
+    tree: &TreeManager,
+    cid: &WorkerCid,
+    dest: &Path,
+    handle: &mut DownloadHandle,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<DownloadStats, String> {
+    let files = if tree.is_dir(cid).await? {
+        collect_files(tree, cid, dest).await?
+    } else {
+        vec![DownloadFile {
+            cid: cid.clone(),
+            target: dest.to_path_buf(),
+            size: tree.file_size(cid).await,
+        }]
+    };
+    let bytes_total = files.iter().map(|file| file.size).sum::<Option<u64>>();
+
+    if let Some(progress) = progress.as_deref_mut() {
+        progress.phase("download", Some(files.len() as u64), bytes_total);
+    }
+    for file in &files {
+        write_part(
+            tree,
+            &file.cid,
+            &part_path(&file.target),
+            handle,
+            progress.as_deref_mut(),
+        )
+        .await?;
+    }
+
+    if let Some(progress) = progress.as_deref_mut() {
+        progress.phase("verify", Some(files.len() as u64), bytes_total);
+    }
+    let mut bytes = 0;
+    for file in &files {
+        let part = part_path(&file.target);
+        bytes += verify_part(tree, &file.cid, &part, handle, progress.as_deref_mut()).await?;
+        fs::rename(&part, &file.target)
+            .await
+            .map_err(|e| format!("Failed to move {}: {}", part.display(), e))?;
+    }
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    Ok(DownloadStats {
+        files: files.len(),
+        bytes,
+    })
+}
+
+/// Save `body`, a file from the web of `len` bytes if known, to `dest`
+pub async fn download_web<S, B>(
+    body: S,
+    len: Option<u64>,
+    dest: &Path,
+    handle: &mut DownloadHandle,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<DownloadStats, String>
+where
+    S: futures::Stream<Item = Result<B, String>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    if len.is_some_and(|len| len > MAX_WEB_DOWNLOAD_BYTES) {
+        return Err(format!(
+            "File is larger than the {} byte limit",
+            MAX_WEB_DOWNLOAD_BYTES
+        ));
+    }
+    if let Some(progress) = progress.as_deref_mut() {
+        progress.phase("download", Some(1), len);
+    }
+    let part = part_path(dest);
+    let result = write_web_part(body, &part, handle, progress.as_deref_mut()).await;
+    let bytes = match result {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let _ = fs::remove_file(&part).await;
+            return Err(e);
+        }
+    };
+    fs::rename(&part, dest)
+        .await
+        .map_err(|e| format!("Failed to move {}: {}", part.display(), e))?;
+
+    if let Some(progress) = progress {
+        progress.advance(1, 0);
+        progress.finish();
+    }
+    Ok(DownloadStats { files: 1, bytes })
+}
+
+/// Stream `body` into a new `part` file, up to [`MAX_WEB_DOWNLOAD_BYTES`]
+async fn write_web_part<S, B>(
+    mut body: S,
+    part: &Path,
+    handle: &mut DownloadHandle,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<u64, String>
+where
+    S: futures::Stream<Item = Result<B, String>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    let mut file = File::create(part)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", part.display(), e))?;
+    let mut bytes = 0u64;
+    loop {
+        handle.wait_if_paused().await;
+        let chunk = match tokio::time::timeout(WEB_CHUNK_TIMEOUT, body.next()).await {
+            Ok(Some(chunk)) => chunk?,
+            Ok(None) => break,
+            Err(_) => return Err("Download stalled".to_string()),
+        };
+        let chunk = chunk.as_ref();
+        bytes += chunk.len() as u64;
+        if bytes > MAX_WEB_DOWNLOAD_BYTES {
+            return Err(format!(
+                "File is larger than the {} byte limit",
+                MAX_WEB_DOWNLOAD_BYTES
+            ));
+        }
+        file.write_all(chunk)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", part.display(), e))?;
+        if let Some(progress) = progress.as_deref_mut() {
+            progress.advance(0, chunk.len() as u64);
+        }
+    }
+    file.sync_all()
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", part.display(), e))?;
+    Ok(bytes)
+}
+
+/// List the files under directory `root`, creating their directories
+/// below `dest` (empty ones included)
+async fn collect_files(
+    tree: &TreeManager,
+    root: &WorkerCid,
+    dest: &Path,
+) -> Result<Vec<DownloadFile>, String> {
+    let mut files = Vec::new();
+    let mut stack = vec![(root.clone(), dest.to_path_buf())];
+
+    while let Some((dir_cid, dir_path)) = stack.pop() {
+        fs::create_dir_all(&dir_path)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", dir_path.display(), e))?;
+        for entry in tree.list_dir(&dir_cid).await? {
+            check_name(&entry.name)?;
+            let cid = WorkerCid {
+                hash: entry.hash,
+                key: entry.key,
+            };
+            let path = dir_path.join(&entry.name);
+            if entry.link_type == 2 {
+                stack.push((cid, path));
+            } else {
+                files.push(DownloadFile {
+                    cid,
+                    target: path,
+                    size: Some(entry.size),
+                });
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Names come from whoever made the tree, so they must not point outside
+/// the destination
+fn check_name(name: &str) -> Result<(), String> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains(['/', '\\']) => Ok(()),
+        _ => Err(format!("Unsafe file name in tree: {:?}", name)),
+    }
+}
+
+fn part_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(PART_SUFFIX);
+    target.with_file_name(name)
+}
+
+/// Stream a file into `part`, keeping the bytes an earlier attempt wrote
+async fn write_part(
+    tree: &TreeManager,
+    cid: &WorkerCid,
+    part: &Path,
+    handle: &mut DownloadHandle,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<(), String> {
+    let resume_from = fs::metadata(part).await.map(|m| m.len()).unwrap_or(0);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(part)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", part.display(), e))?;
+
+    let mut stream = tree.read_file_stream(cid)?;
+    let mut offset = 0u64;
+    while let Some(chunk) = stream.next().await {
+        handle.wait_if_paused().await;
+        let chunk = chunk?;
+        let end = offset + chunk.len() as u64;
+        if end > resume_from {
+            let skip = resume_from.saturating_sub(offset) as usize;
+            file.write_all(&chunk[skip..])
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", part.display(), e))?;
+        }
+        offset = end;
+        if let Some(progress) = progress.as_deref_mut() {
+            progress.advance(0, chunk.len() as u64);
+        }
+    }
+    file.sync_all()
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", part.display(), e))?;
+
+    if let Some(progress) = progress {
+        progress.advance(1, 0);
+    }
+    Ok(())
+}
+
+/// Compare `part` with the file in the tree by the checksums of each chunk,
+/// deleting it if they differ. Returns its length.
+async fn verify_part(
+    tree: &TreeManager,
+    cid: &WorkerCid,
+    part: &Path,
+    handle: &mut DownloadHandle,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<u64, String> {
+    let mut file = File::open(part)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", part.display(), e))?;
+    let mut stream = tree.read_file_stream(cid)?;
+    let mut buf = Vec::new();
+    let mut len = 0u64;
+    let mut matches = true;
+
+    while let Some(chunk) = stream.next().await {
+        handle.wait_if_paused().await;
+        let chunk = chunk?;
+        buf.resize(chunk.len(), 0);
+        if file.read_exact(&mut buf).await.is_err()
+            || hashtree_core::sha256(&buf) != hashtree_core::sha256(&chunk)
+        {
+            matches = false;
+            break;
+        }
+        len += chunk.len() as u64;
+        if let Some(progress) = progress.as_deref_mut() {
+            progress.advance(0, chunk.len() as u64);
+        }
+    }
+    // Nothing may follow the tree's content
+    if matches {
+        let extra = file
+            .read(&mut [0u8; 1])
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", part.display(), e))?;
+        matches = extra == 0;
+    }
+
+    if !matches {
+        drop(file);
+        let _ = fs::remove_file(part).await;
+        return Err(format!("Checksum mismatch in {}", part.display()));
+    }
+    if let Some(progress) = progress {
+        progress.advance(1, 0);
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::store::BlobStore;
+    use tempfile::TempDir;
+
+    fn test_tree(dir: &TempDir) -> TreeManager {
+        TreeManager::new(Arc::new(BlobStore::new(dir.path().to_path_buf())))
+    }
+
+    #[tokio::test]
+    async fn test_download_directory() {
+        let dir = TempDir::new().unwrap();
+        let tree = test_tree(&dir);
+        let root = tree.create_empty_dir(true).await.unwrap();
+        let root = tree
+            .write_file(Some(&root), "a.txt", b"alpha", false)
+            .await
+            .unwrap();
+        let root = tree
+            .write_file(Some(&root), "b.txt", b"beta", false)
+            .await
+            .unwrap();
+
+        let downloads = Arc::new(Downloads::default());
+        let mut handle = downloads.start("dl");
+        let dest = dir.path().join("out");
+        let stats = download(&tree, &root, &dest, &mut handle, None)
+            .await
+            .unwrap();
+        assert_eq!(stats, DownloadStats { files: 2, bytes: 9 });
+        assert_eq!(std::fs::read(dest.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(std::fs::read(dest.join("b.txt")).unwrap(), b"beta");
+        assert!(!dest.join("a.txt.part").exists());
+
+        assert!(downloads.set_paused("dl", true));
+        drop(handle);
+        assert!(!downloads.set_paused("dl", false));
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_and_verifies_part_file() {
+        let dir = TempDir::new().unwrap();
+        let tree = test_tree(&dir);
+        let cid = tree
+            .write_file(None, "", b"hello world", false)
+            .await
+            .unwrap();
+        let downloads = Arc::new(Downloads::default());
+        let dest = dir.path().join("hello.txt");
+
+        // Picks up after the bytes already written
+        std::fs::write(part_path(&dest), b"hello").unwrap();
+        let stats = download(&tree, &cid, &dest, &mut downloads.start("a"), None)
+            .await
+            .unwrap();
+        assert_eq!(stats.bytes, 11);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"hello world");
+
+        // A part file that doesn't match the tree is thrown away
+        std::fs::write(part_path(&dest), b"jello").unwrap();
+        let result = download(&tree, &cid, &dest, &mut downloads.start("b"), None).await;
+        assert!(result.unwrap_err().contains("Checksum mismatch"));
+        assert!(!part_path(&dest).exists());
+    }
+
+    #[tokio::test]
+    async fn test_web_download_removes_part_file_on_failure() {
+        let dir = TempDir::new().unwrap();
+        let downloads = Arc::new(Downloads::default());
+        let dest = dir.path().join("page.html");
+
+        let body = futures::stream::iter(vec![Ok(b"<html>".to_vec()), Ok(b"</html>".to_vec())]);
+        let stats = download_web(body, None, &dest, &mut downloads.start("a"), None)
+            .await
+            .unwrap();
+        assert_eq!(stats.bytes, 13);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"<html></html>");
+
+        let failing = dir.path().join("cut.html");
+        let body = futures::stream::iter(vec![Ok(b"<html>".to_vec()), Err("reset".to_string())]);
+        let result = download_web(body, None, &failing, &mut downloads.start("b"), None).await;
+        assert_eq!(result.unwrap_err(), "reset");
+        assert!(!failing.exists());
+        assert!(!part_path(&failing).exists());
+
+        let body = futures::stream::iter(Vec::<Result<Vec<u8>, String>>::new());
+        let too_big = Some(MAX_WEB_DOWNLOAD_BYTES + 1);
+        let result = download_web(body, too_big, &failing, &mut downloads.start("c"), None).await;
+        assert!(result.is_err());
+        assert!(!part_path(&failing).exists());
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("photo.jpg").is_ok());
+        for name in ["", ".", "..", "a/b", "..\\x", "/etc"] {
+            assert!(check_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Following and unfollowing: editing our contact list (kind 3)
+//!
+//! A contact list replaces the previous one wholesale, so an edit starts
+//! from the newest list found on the relays or in nostrdb and only adds or
+//! drops the one `p` tag; other tags (relay hints, petnames) and the
+//! content are kept as they were.
+
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, PublicKey, Tag, Timestamp};
+
+/// Pubkeys (hex) followed in contact list `event`
+pub fn followed_pubkeys(event: &Event) -> Vec<String> {
+    event
+        .tags
+        .iter()
+        .filter_map(|tag| match tag.as_slice() {
+            [kind, pubkey, ..] if kind == "p" => PublicKey::from_hex(pubkey).ok(),
+            _ => None,
+        })
+        .map(|pubkey| pubkey.to_hex())
+        .collect()
+}
+
+/// `current` contact list with `target` followed or not, signed by `keys`.
+/// None if it already was.
+pub fn edit_contact_list(
+    keys: &Keys,
+    current: Option<&Event>,
+    target: &PublicKey,
+    follow: bool,
+) -> Result<Option<Event>, String> {
+    let target_hex = target.to_hex();
+    let is_target = |tag: &Tag| match tag.as_slice() {
+        [kind, pubkey, ..] => kind == "p" && *pubkey == target_hex,
+        _ => false,
+    };
+    let mut tags: Vec<Tag> = current.map(|event| event.tags.to_vec()).unwrap_or_default();
+    let following = tags.iter().any(is_target);
+    if following == follow {
+        return Ok(None);
+    }
+    if follow {
+        tags.push(Tag::public_key(*target));
+    } else {
+        tags.retain(|tag| !is_target(tag));
+    }
+
+    let content = current
+        .map(|event| event.content.clone())
+        .unwrap_or_default();
+    // The new list has to be newer than the one it replaces
+    let created_at = match current {
+        Some(event) if event.created_at >= Timestamp::now() => {
+            Timestamp::from(event.created_at.as_u64() + 1)
+        }
+        _ => Timestamp::now(),
+    };
+    EventBuilder::new(Kind::ContactList, content, tags)
+        .custom_created_at(created_at)
+        .to_event(keys)
+        .map(Some)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::TagKind;
+
+    #[test]
+    fn test_follow_and_unfollow_keep_other_tags() {
+        let keys = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let current = EventBuilder::new(
+            Kind::ContactList,
+            "{\"wss://relay.example\":{\"read\":true}}",
+            [
+                Tag::public_key(alice),
+                Tag::custom(TagKind::custom("t"), vec!["hashtree".to_string()]),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let followed = edit_contact_list(&keys, Some(&current), &bob, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            followed_pubkeys(&followed),
+            vec![alice.to_hex(), bob.to_hex()]
+        );
+        assert_eq!(followed.content, current.content);
+        assert_eq!(followed.tags.len(), 3);
+        assert!(followed.created_at > current.created_at);
+        assert!(edit_contact_list(&keys, Some(&followed), &bob, true)
+            .unwrap()
+            .is_none());
+
+        let unfollowed = edit_contact_list(&keys, Some(&followed), &alice, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(followed_pubkeys(&unfollowed), vec![bob.to_hex()]);
+        assert!(unfollowed.created_at > followed.created_at);
+        assert_eq!(unfollowed.tags.len(), 2);
+
+        let first = edit_contact_list(&keys, None, &alice, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(followed_pubkeys(&first), vec![alice.to_hex()]);
+        assert!(edit_contact_list(&keys, None, &alice, false)
+            .unwrap()
+            .is_none());
+    }
+}
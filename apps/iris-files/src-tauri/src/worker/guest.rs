@@ -0,0 +1,98 @@
+//! Guest mode for opening untrusted links
+//!
+//! A guest session is read-only: the worker refuses to publish, upload or
+//! export, and anything that still needs a key (relay auth, WebRTC
+//! signaling) uses freshly generated keys. Its blob cache, history and
+//! bookmarks live in a directory of its own under `guest/` in the data dir,
+//! which is deleted when the session ends. Permission decisions made during
+//! the session are discarded, and trees opened as a guest aren't indexed for
+//! search or tagged for quotas. Leftovers of sessions that were never
+//! ended, e.g. after a crash, are wiped on the next start.
+
+use nostr_sdk::Keys;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use super::store::BlobStore;
+use crate::bookmarks::BookmarkStore;
+use crate::history::HistoryStore;
+
+/// Dir in the data dir holding guest sessions
+const GUEST_DIR: &str = "guest";
+
+/// Ephemeral keys and stores of a guest session
+pub struct GuestSession {
+    dir: PathBuf,
+    pub keys: Keys,
+    pub store: Arc<BlobStore>,
+    pub history: Arc<HistoryStore>,
+    pub bookmarks: Arc<BookmarkStore>,
+}
+
+impl GuestSession {
+    pub fn new(data_dir: &Path) -> Result<Self, String> {
+        let dir = data_dir
+            .join(GUEST_DIR)
+            .join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create guest dir: {}", e))?;
+        let history = HistoryStore::new(&dir)?;
+        let bookmarks = BookmarkStore::new(&dir)?;
+        info!("Started guest session in {:?}", dir);
+        Ok(Self {
+            store: Arc::new(BlobStore::new(dir.clone())),
+            history: Arc::new(history),
+            bookmarks: Arc::new(bookmarks),
+            keys: Keys::generate(),
+            dir,
+        })
+    }
+
+    /// Where the session's blobs, history and bookmarks live
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for GuestSession {
+    fn drop(&mut self) {
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => info!("Wiped guest session {:?}", self.dir),
+            Err(e) => warn!("Failed to wipe guest session {:?}: {}", self.dir, e),
+        }
+    }
+}
+
+/// Remove sessions left behind by a previous run
+pub fn wipe_guest_sessions(data_dir: &Path) {
+    let dir = data_dir.join(GUEST_DIR);
+    if dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            warn!("Failed to wipe old guest sessions: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_session_is_wiped_on_drop() {
+        let data_dir = TempDir::new().unwrap();
+        let first = GuestSession::new(data_dir.path()).unwrap();
+        let second = GuestSession::new(data_dir.path()).unwrap();
+        assert_ne!(first.keys.public_key(), second.keys.public_key());
+        assert!(first.dir().join("history").is_dir());
+
+        let first_dir = first.dir().to_path_buf();
+        drop(first);
+        assert!(!first_dir.exists());
+
+        let second_dir = second.dir().to_path_buf();
+        std::mem::forget(second);
+        wipe_guest_sessions(data_dir.path());
+        assert!(!second_dir.exists());
+    }
+}
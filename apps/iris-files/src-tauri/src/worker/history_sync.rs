@@ -0,0 +1,225 @@
+//! Navigation history synced between devices
+//!
+//! Opt-in. Each sync reads the `entries` file of our private `history` tree,
+//! merges it into the local history (the later visit of a path wins) and
+//! publishes the merged history back if it differs from what the tree had.
+//! Entries deleted or expired here aren't merged back in, so the next
+//! publish drops them from the tree too.
+//! Besides the tree's own encryption, entries are NIP-44 encrypted to
+//! ourselves, so only our key opens them even if the tree key leaks. NIP-44
+//! plaintexts are limited to 64 KiB, so the file holds one ciphertext per
+//! line, each a JSON array of entries.
+
+use nostr_sdk::nips::nip44;
+use nostr_sdk::Keys;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use super::tree::TreeManager;
+use super::types::WorkerCid;
+use crate::history::HistoryEntry;
+
+/// Name of the tree holding the synced history
+pub const HISTORY_TREE: &str = "history";
+const ENTRIES_FILE: &str = "entries";
+/// Time between background syncs
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Plaintext size of one encrypted chunk, below the NIP-44 limit
+const CHUNK_BYTES: usize = 60_000;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Settings {
+    enabled: bool,
+}
+
+/// Whether history sync is on, persisted, and the state of its task
+pub struct HistorySync {
+    path: PathBuf,
+    enabled: AtomicBool,
+    started: AtomicBool,
+    /// Held while a sync runs, so background and requested ones don't overlap
+    pub running: tokio::sync::Mutex<()>,
+}
+
+impl HistorySync {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("history_sync.json");
+        let settings: Settings = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            enabled: AtomicBool::new(settings.enabled),
+            started: AtomicBool::new(false),
+            running: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(&Settings { enabled })
+            .map_err(|e| format!("Failed to encode history sync settings: {}", e))?;
+        std::fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to save history sync settings: {}", e))?;
+        self.enabled.store(enabled, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// True the first time only, for starting the background task once
+    pub fn begin(&self) -> bool {
+        !self.started.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// Encrypt `entries` to ourselves, one NIP-44 payload per line
+pub fn encrypt_entries(keys: &Keys, entries: &[HistoryEntry]) -> Result<String, String> {
+    let mut chunks: Vec<Vec<String>> = vec![Vec::new()];
+    let mut chunk_bytes = 0;
+    for entry in entries {
+        let json = serde_json::to_string(entry)
+            .map_err(|e| format!("Failed to encode history entry: {}", e))?;
+        if json.len() + 2 > CHUNK_BYTES {
+            continue;
+        }
+        if chunk_bytes + json.len() + 2 > CHUNK_BYTES {
+            chunks.push(Vec::new());
+            chunk_bytes = 0;
+        }
+        chunk_bytes += json.len() + 1;
+        chunks.last_mut().unwrap().push(json);
+    }
+
+    let mut lines = Vec::with_capacity(chunks.len());
+    for chunk in chunks.iter().filter(|chunk| !chunk.is_empty()) {
+        let plaintext = format!("[{}]", chunk.join(","));
+        let ciphertext = nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            plaintext,
+            nip44::Version::V2,
+        )
+        .map_err(|e| format!("NIP-44 encryption failed: {}", e))?;
+        lines.push(ciphertext);
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Entries encrypted by `encrypt_entries`
+pub fn decrypt_entries(keys: &Keys, data: &str) -> Result<Vec<HistoryEntry>, String> {
+    let mut entries = Vec::new();
+    for line in data.lines().filter(|line| !line.trim().is_empty()) {
+        let plaintext = nip44::decrypt(keys.secret_key(), &keys.public_key(), line.trim())
+            .map_err(|e| format!("NIP-44 decryption failed: {}", e))?;
+        let chunk: Vec<HistoryEntry> = serde_json::from_str(&plaintext)
+            .map_err(|e| format!("Invalid history entries: {}", e))?;
+        entries.extend(chunk);
+    }
+    Ok(entries)
+}
+
+/// Contents of the entries file in history tree `root`, None if it has none
+pub async fn read_entries(tree: &TreeManager, root: &WorkerCid) -> Result<Option<String>, String> {
+    let Some(entry) = tree
+        .list_dir(root)
+        .await?
+        .into_iter()
+        .find(|entry| entry.name == ENTRIES_FILE)
+    else {
+        return Ok(None);
+    };
+    let cid = WorkerCid {
+        hash: entry.hash,
+        key: entry.key,
+    };
+    let data = tree.read_file(&cid).await?;
+    String::from_utf8(data)
+        .map(Some)
+        .map_err(|_| "History entries aren't text".to_string())
+}
+
+/// History tree `root` (or a new one) with `data` as its entries file
+pub async fn write_entries(
+    tree: &TreeManager,
+    root: Option<&WorkerCid>,
+    data: &str,
+) -> Result<WorkerCid, String> {
+    let root = match root {
+        Some(root) => root.clone(),
+        None => tree.create_empty_dir(true).await?,
+    };
+    tree.write_file(Some(&root), ENTRIES_FILE, data.as_bytes(), true)
+        .await
+}
+
+/// Whether `a` and `b` hold the same entries, in any order
+pub fn same_entries(a: &[HistoryEntry], b: &[HistoryEntry]) -> bool {
+    let sorted = |entries: &[HistoryEntry]| {
+        let mut entries = entries.to_vec();
+        entries.sort_by(|x, y| x.path.cmp(&y.path));
+        entries
+    };
+    a.len() == b.len() && sorted(a) == sorted(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::store::BlobStore;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn entry(n: usize) -> HistoryEntry {
+        HistoryEntry {
+            path: format!("/npub1example/tree-{}", n),
+            label: format!("Tree {} {}", n, "x".repeat(200)),
+            entry_type: "tree".to_string(),
+            npub: None,
+            tree_name: Some(format!("tree-{}", n)),
+            visit_count: n as u32,
+            last_visited: n as u64,
+            first_visited: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries_roundtrip_through_tree() {
+        let dir = TempDir::new().unwrap();
+        let tree = TreeManager::new(Arc::new(BlobStore::new(dir.path().to_path_buf())));
+        let keys = Keys::generate();
+        let entries: Vec<_> = (0..1000).map(entry).collect();
+
+        let data = encrypt_entries(&keys, &entries).unwrap();
+        assert!(data.lines().count() > 1, "should be split into chunks");
+        assert!(!data.contains("tree-1"));
+
+        let root = write_entries(&tree, None, &data).await.unwrap();
+        assert!(root.key.is_some());
+        let read = read_entries(&tree, &root).await.unwrap().unwrap();
+        let decrypted = decrypt_entries(&keys, &read).unwrap();
+        assert_eq!(decrypted, entries);
+        assert!(same_entries(
+            &decrypted,
+            &entries.iter().rev().cloned().collect::<Vec<_>>()
+        ));
+
+        // Someone else's key doesn't open them
+        assert!(decrypt_entries(&Keys::generate(), &read).is_err());
+    }
+
+    #[test]
+    fn test_settings_persist() {
+        let dir = TempDir::new().unwrap();
+        let sync = HistorySync::new(dir.path());
+        assert!(!sync.is_enabled());
+        sync.set_enabled(true).unwrap();
+        assert!(HistorySync::new(dir.path()).is_enabled());
+        assert!(sync.begin());
+        assert!(!sync.begin());
+    }
+}
@@ -0,0 +1,210 @@
+//! "Add to hashtree" from the OS share sheet and clipboard
+//!
+//! Files, links and text shared with the app are collected in the `inbox`
+//! tree: files under their own name, links as `.url` shortcuts and text as
+//! `.txt` notes. A name that's already taken gets a number appended, so
+//! nothing in the inbox is ever replaced. The inbox is published as a
+//! private tree, which makes it show up on the user's other devices and
+//! nowhere else.
+
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::ingest;
+use super::tree::TreeManager;
+use super::types::{WorkerCid, WorkerDirEntry};
+
+/// Name of the tree shared items are added to
+pub const INBOX_TREE: &str = "inbox";
+
+/// Something shared with the app
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum SharedItem {
+    /// A local copy of a shared file
+    File {
+        path: String,
+    },
+    Url {
+        url: String,
+        title: Option<String>,
+    },
+    Text {
+        text: String,
+    },
+}
+
+/// Add `items` to the inbox at `root`, or to a new one. Returns the new root.
+pub async fn add_items(
+    tree: &TreeManager,
+    root: Option<&WorkerCid>,
+    items: &[SharedItem],
+) -> Result<WorkerCid, String> {
+    let mut root = match root {
+        Some(root) => root.clone(),
+        None => tree.create_empty_dir(true).await?,
+    };
+    let encrypted = root.key.is_some();
+    let mut taken: HashSet<String> = tree
+        .list_dir(&root)
+        .await?
+        .into_iter()
+        .map(|e| e.name)
+        .collect();
+
+    for item in items {
+        let mut entry = match item {
+            SharedItem::File { path } => {
+                match ingest::import_path(tree, Path::new(path), encrypted, None).await? {
+                    Some(entry) => entry,
+                    None => return Err(format!("Not a file or directory: {}", path)),
+                }
+            }
+            SharedItem::Url { url, title } => {
+                let parsed = tauri::Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+                let name = title
+                    .as_deref()
+                    .or(parsed.host_str())
+                    .map(file_stem)
+                    .filter(|stem| !stem.is_empty())
+                    .unwrap_or_else(|| "link".to_string());
+                let shortcut = format!("[InternetShortcut]\r\nURL={}\r\n", parsed);
+                put_note(
+                    tree,
+                    format!("{}.url", name),
+                    shortcut.as_bytes(),
+                    encrypted,
+                )
+                .await?
+            }
+            SharedItem::Text { text } => {
+                put_note(tree, "note.txt".to_string(), text.as_bytes(), encrypted).await?
+            }
+        };
+        entry.name = unique_name(&taken, &entry.name);
+        taken.insert(entry.name.clone());
+        root = tree.set_entry(&root, "", &entry).await?;
+    }
+    Ok(root)
+}
+
+async fn put_note(
+    tree: &TreeManager,
+    name: String,
+    data: &[u8],
+    encrypted: bool,
+) -> Result<WorkerDirEntry, String> {
+    let cid = tree.write_file(None, &name, data, encrypted).await?;
+    Ok(WorkerDirEntry {
+        name,
+        hash: cid.hash,
+        size: data.len() as u64,
+        link_type: 0,
+        key: cid.key,
+    })
+}
+
+/// `title` made usable as a file name
+fn file_stem(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .take(80)
+        .collect::<String>()
+        .trim_matches(|c: char| c == '.' || c.is_whitespace())
+        .to_string()
+}
+
+/// `name`, or `name (2)`, `name (3)`... before the extension, whichever is free
+fn unique_name(taken: &HashSet<String>, name: &str) -> String {
+    if !taken.contains(name) {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    (2..)
+        .map(|n| format!("{} ({}){}", stem, n, ext))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::store::BlobStore;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_unique_name() {
+        let taken: HashSet<String> = ["note.txt", "note (2).txt", "README"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(unique_name(&taken, "photo.jpg"), "photo.jpg");
+        assert_eq!(unique_name(&taken, "note.txt"), "note (3).txt");
+        assert_eq!(unique_name(&taken, "README"), "README (2)");
+        assert_eq!(file_stem(" a/b: c. "), "a_b_ c");
+    }
+
+    #[tokio::test]
+    async fn test_add_items_to_new_and_existing_inbox() {
+        let dir = TempDir::new().unwrap();
+        let tree = TreeManager::new(Arc::new(BlobStore::new(dir.path().to_path_buf())));
+        let photo = dir.path().join("photo.jpg");
+        std::fs::write(&photo, b"jpeg").unwrap();
+
+        let root = add_items(
+            &tree,
+            None,
+            &[
+                SharedItem::File {
+                    path: photo.to_string_lossy().into_owned(),
+                },
+                SharedItem::Url {
+                    url: "https://example.com/article".into(),
+                    title: None,
+                },
+                SharedItem::Text {
+                    text: "first".into(),
+                },
+            ],
+        )
+        .await
+        .unwrap();
+        assert!(root.key.is_some());
+
+        let root = add_items(
+            &tree,
+            Some(&root),
+            &[SharedItem::Text {
+                text: "second".into(),
+            }],
+        )
+        .await
+        .unwrap();
+
+        let entries = tree.list_dir(&root).await.unwrap();
+        let mut names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["example.com.url", "note (2).txt", "note.txt", "photo.jpg"]
+        );
+        let second = entries.iter().find(|e| e.name == "note (2).txt").unwrap();
+        let second = WorkerCid {
+            hash: second.hash.clone(),
+            key: second.key.clone(),
+        };
+        assert_eq!(tree.read_file(&second).await.unwrap(), b"second");
+    }
+}
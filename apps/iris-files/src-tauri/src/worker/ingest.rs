@@ -0,0 +1,247 @@
+//! Importing files dropped on the window
+//!
+//! `import_dropped_files` gets the native paths of dropped files and
+//! directories and stores them straight from disk, chunk by chunk, so
+//! large files never pass through the webview as base64 `writeFile`
+//! requests. Directories are imported recursively; symlinks and other
+//! special files are skipped.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::fs::File;
+use tokio::io::ReadBuf;
+
+use super::progress::ProgressReporter;
+use super::tree::TreeManager;
+use super::types::{WorkerCid, WorkerDirEntry};
+
+/// Import `paths` into directory `dir_path` of `parent_cid`, or into a new
+/// directory without a parent. Returns the new root.
+pub async fn import_paths(
+    tree: &TreeManager,
+    paths: &[PathBuf],
+    parent_cid: Option<&WorkerCid>,
+    dir_path: &str,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<WorkerCid, String> {
+    if let Some(progress) = progress.as_deref_mut() {
+        progress.phase("scan", None, None);
+    }
+    let scan_paths = paths.to_vec();
+    let (files, bytes) = blocking(move || scan(&scan_paths)).await?;
+    if let Some(progress) = progress.as_deref_mut() {
+        progress.phase("import", Some(files), Some(bytes));
+    }
+
+    let mut root = match parent_cid {
+        Some(cid) => cid.clone(),
+        None => tree.create_empty_dir(false).await?,
+    };
+    let encrypted = root.key.is_some();
+    for path in paths {
+        if let Some(entry) = import_path(tree, path, encrypted, progress.as_deref_mut()).await? {
+            root = tree.set_entry(&root, dir_path, &entry).await?;
+        }
+    }
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    Ok(root)
+}
+
+/// Count the files under `paths` and their bytes
+fn scan(paths: &[PathBuf]) -> Result<(u64, u64), String> {
+    let (mut files, mut bytes) = (0, 0);
+    let mut stack = paths.to_vec();
+    while let Some(path) = stack.pop() {
+        let metadata = std::fs::symlink_metadata(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if metadata.is_dir() {
+            stack.extend(read_dir(&path)?);
+        } else if metadata.is_file() {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+fn read_dir(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut children = std::fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    children.sort();
+    Ok(children)
+}
+
+/// Run filesystem calls that have no async version off the runtime
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("Import task failed: {}", e))?
+}
+
+/// Store a file or a directory with its contents, None for anything else
+pub async fn import_path(
+    tree: &TreeManager,
+    path: &Path,
+    encrypted: bool,
+    mut progress: Option<&mut ProgressReporter>,
+) -> Result<Option<WorkerDirEntry>, String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Unsupported file name: {}", path.display()))?
+        .to_string();
+    let metadata = tokio::fs::symlink_metadata(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    if metadata.is_dir() {
+        let dir = path.to_path_buf();
+        let mut entries = Vec::new();
+        for child in blocking(move || read_dir(&dir)).await? {
+            let entry = Box::pin(import_path(
+                tree,
+                &child,
+                encrypted,
+                progress.as_deref_mut(),
+            ))
+            .await?;
+            entries.extend(entry);
+        }
+        let cid = tree.put_dir(&entries, encrypted).await?;
+        Ok(Some(WorkerDirEntry {
+            name,
+            hash: cid.hash,
+            size: entries.iter().map(|e| e.size).sum(),
+            link_type: 2,
+            key: cid.key,
+        }))
+    } else if metadata.is_file() {
+        let file = File::open(path)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let reader = ProgressRead {
+            inner: file,
+            progress: progress.as_deref_mut(),
+        };
+        let (cid, size) = tree.put_reader(reader, encrypted).await?;
+        if let Some(progress) = progress {
+            progress.advance(1, 0);
+        }
+        Ok(Some(WorkerDirEntry {
+            name,
+            hash: cid.hash,
+            size,
+            link_type: 0,
+            key: cid.key,
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reader counting the bytes read from it as progress
+struct ProgressRead<'a, R> {
+    inner: R,
+    progress: Option<&'a mut ProgressReporter>,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> futures::io::AsyncRead for ProgressRead<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let mut read_buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+        let n = read_buf.filled().len();
+        if let Some(progress) = this.progress.as_deref_mut() {
+            progress.advance(0, n as u64);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::store::BlobStore;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_import_files_and_directories() {
+        let dir = TempDir::new().unwrap();
+        let tree = TreeManager::new(Arc::new(BlobStore::new(dir.path().to_path_buf())));
+        let dropped = dir.path().join("dropped");
+        std::fs::create_dir_all(dropped.join("album/empty")).unwrap();
+        std::fs::write(dropped.join("notes.txt"), b"notes").unwrap();
+        std::fs::write(dropped.join("album/a.jpg"), vec![7u8; 100_000]).unwrap();
+
+        let paths = vec![dropped.join("notes.txt"), dropped.join("album")];
+        assert_eq!(scan(&paths).unwrap(), (2, 100_005));
+
+        let root = import_paths(&tree, &paths, None, "", None).await.unwrap();
+
+        let mut names: Vec<_> = tree
+            .list_dir(&root)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.name, e.link_type))
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![("album".into(), 2), ("notes.txt".into(), 0)]);
+
+        let album = tree.list_dir(&root).await.unwrap();
+        let album = album.iter().find(|e| e.name == "album").unwrap();
+        let album_cid = WorkerCid {
+            hash: album.hash.clone(),
+            key: album.key.clone(),
+        };
+        let entries = tree.list_dir(&album_cid).await.unwrap();
+        let photo = entries.iter().find(|e| e.name == "a.jpg").unwrap();
+        let photo_cid = WorkerCid {
+            hash: photo.hash.clone(),
+            key: photo.key.clone(),
+        };
+        assert_eq!(
+            tree.read_file(&photo_cid).await.unwrap(),
+            vec![7u8; 100_000]
+        );
+        assert!(entries
+            .iter()
+            .any(|e| e.name == "empty" && e.link_type == 2));
+
+        // Dropped into a subdirectory of an existing tree
+        let root = import_paths(
+            &tree,
+            &[dropped.join("notes.txt")],
+            Some(&root),
+            "album",
+            None,
+        )
+        .await
+        .unwrap();
+        let album = tree.list_dir(&root).await.unwrap();
+        let album = album.iter().find(|e| e.name == "album").unwrap();
+        let album_cid = WorkerCid {
+            hash: album.hash.clone(),
+            key: album.key.clone(),
+        };
+        let entries = tree.list_dir(&album_cid).await.unwrap();
+        assert!(entries.iter().any(|e| e.name == "notes.txt"));
+    }
+}
@@ -0,0 +1,646 @@
+//! Media metadata extraction
+//!
+//! Minimal parsers for the metadata the media library views need:
+//! image dimensions and EXIF capture date (JPEG/PNG/GIF/WebP), ID3v2 tags
+//! (MP3) and MP4/MOV/M4A duration, dimensions and codec. Only file headers
+//! are read, so large files don't need to be downloaded in full.
+
+use serde::{Deserialize, Serialize};
+
+use super::tree::TreeManager;
+use super::types::WorkerCid;
+
+/// Bytes read from the start of images and audio files
+const HEADER_BYTES: u64 = 256 * 1024;
+
+/// Largest MP4 `moov` box that will be fetched and parsed
+const MAX_MOOV_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Maximum number of top-level MP4 boxes walked looking for `moov`
+const MAX_TOP_LEVEL_BOXES: usize = 64;
+
+/// Seconds between 1904-01-01 (MP4 epoch) and 1970-01-01
+const MP4_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Image,
+    Audio,
+    Video,
+}
+
+/// Metadata extracted from a media file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaMetadata {
+    pub kind: Option<MediaKind>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_ms: Option<u64>,
+    /// Capture/creation time, unix seconds
+    pub captured_at: Option<u64>,
+    pub codec: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+/// Guess media kind from a filename extension
+pub fn media_kind_for_filename(name: &str) -> Option<MediaKind> {
+    let ext = name.rsplit_once('.')?.1.to_lowercase();
+    match ext.as_str() {
+        "jpg" | "jpeg" | "png" | "gif" | "webp" => Some(MediaKind::Image),
+        "mp3" | "m4a" => Some(MediaKind::Audio),
+        "mp4" | "m4v" | "mov" => Some(MediaKind::Video),
+        _ => None,
+    }
+}
+
+/// Read the headers of a media file from the tree and extract its metadata
+pub async fn extract_from_tree(
+    tree: &TreeManager,
+    cid: &WorkerCid,
+    name: &str,
+    size: u64,
+) -> Option<MediaMetadata> {
+    let kind = media_kind_for_filename(name)?;
+    let ext = name.rsplit_once('.')?.1.to_lowercase();
+
+    if matches!(ext.as_str(), "mp4" | "m4v" | "mov" | "m4a") {
+        let moov = read_mp4_moov(tree, cid, size).await?;
+        let mut meta = parse_mp4_moov(&moov);
+        // Audio-only MP4 containers have no video track
+        if meta.kind.is_none() {
+            meta.kind = Some(kind);
+        }
+        return Some(meta);
+    }
+
+    let head = tree
+        .read_file_range(cid, 0, Some(HEADER_BYTES.min(size)))
+        .await
+        .ok()?;
+    let mut meta = extract(&head)?;
+    meta.kind.get_or_insert(kind);
+    Some(meta)
+}
+
+/// Extract metadata from the first bytes of a file, detected by magic bytes
+pub fn extract(data: &[u8]) -> Option<MediaMetadata> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        parse_jpeg(data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        parse_png(data)
+    } else if data.starts_with(b"GIF8") {
+        parse_gif(data)
+    } else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        parse_webp(data)
+    } else if data.starts_with(b"ID3") {
+        parse_id3(data)
+    } else {
+        None
+    }
+}
+
+fn be_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+fn le_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn le_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn le_u24(data: &[u8], pos: usize) -> Option<u32> {
+    let b = data.get(pos..pos + 3)?;
+    Some(b[0] as u32 | ((b[1] as u32) << 8) | ((b[2] as u32) << 16))
+}
+
+fn image(width: u32, height: u32) -> MediaMetadata {
+    MediaMetadata {
+        kind: Some(MediaKind::Image),
+        width: Some(width),
+        height: Some(height),
+        ..Default::default()
+    }
+}
+
+fn parse_png(data: &[u8]) -> Option<MediaMetadata> {
+    if data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let mut meta = image(be_u32(data, 16)?, be_u32(data, 20)?);
+    meta.codec = Some("png".to_string());
+    Some(meta)
+}
+
+fn parse_gif(data: &[u8]) -> Option<MediaMetadata> {
+    let mut meta = image(le_u16(data, 6)? as u32, le_u16(data, 8)? as u32);
+    meta.codec = Some("gif".to_string());
+    Some(meta)
+}
+
+fn parse_webp(data: &[u8]) -> Option<MediaMetadata> {
+    let (width, height) = match data.get(12..16)? {
+        b"VP8X" => (le_u24(data, 24)? + 1, le_u24(data, 27)? + 1),
+        b"VP8L" => {
+            let bits = le_u32(data, 21)?;
+            ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1)
+        }
+        b"VP8 " => (
+            (le_u16(data, 26)? & 0x3FFF) as u32,
+            (le_u16(data, 28)? & 0x3FFF) as u32,
+        ),
+        _ => return None,
+    };
+    let mut meta = image(width, height);
+    meta.codec = Some("webp".to_string());
+    Some(meta)
+}
+
+fn parse_jpeg(data: &[u8]) -> Option<MediaMetadata> {
+    let mut meta = MediaMetadata {
+        kind: Some(MediaKind::Image),
+        codec: Some("jpeg".to_string()),
+        ..Default::default()
+    };
+
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            break;
+        }
+        let marker = data[pos + 1];
+        // Fill bytes and standalone markers
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        if marker == 0xD8 || (0xD0..=0xD7).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        if marker == 0xD9 || marker == 0xDA {
+            break;
+        }
+
+        let len = be_u16(data, pos + 2)? as usize;
+        let segment = match data.get(pos + 4..pos + 2 + len) {
+            Some(s) => s,
+            None => break,
+        };
+
+        match marker {
+            0xE1 if segment.starts_with(b"Exif\0\0") => {
+                meta.captured_at = parse_exif_date(&segment[6..]);
+            }
+            // SOF markers, except DHT (C4), JPG (C8) and DAC (CC)
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                meta.height = be_u16(segment, 1).map(|h| h as u32);
+                meta.width = be_u16(segment, 3).map(|w| w as u32);
+                break;
+            }
+            _ => {}
+        }
+        pos += 2 + len;
+    }
+
+    Some(meta)
+}
+
+/// Read DateTimeOriginal (or DateTime) from a TIFF/EXIF block
+fn parse_exif_date(tiff: &[u8]) -> Option<u64> {
+    let little = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        if little {
+            le_u16(tiff, pos)
+        } else {
+            be_u16(tiff, pos)
+        }
+    };
+    let u32_at = |pos: usize| {
+        if little {
+            le_u32(tiff, pos)
+        } else {
+            be_u32(tiff, pos)
+        }
+    };
+
+    // Returns (tag -> value offset) lookup for an IFD
+    let find_tag = |ifd: usize, wanted: u16| -> Option<u32> {
+        let count = u16_at(ifd)? as usize;
+        for i in 0..count {
+            let entry = ifd + 2 + i * 12;
+            if u16_at(entry)? == wanted {
+                return u32_at(entry + 8);
+            }
+        }
+        None
+    };
+    let read_date = |offset: u32| -> Option<u64> {
+        let offset = offset as usize;
+        let text = std::str::from_utf8(tiff.get(offset..offset.checked_add(19)?)?).ok()?;
+        parse_exif_datetime(text)
+    };
+
+    let ifd0 = u32_at(4)? as usize;
+    if let Some(exif_ifd) = find_tag(ifd0, 0x8769) {
+        if let Some(ts) = find_tag(exif_ifd as usize, 0x9003).and_then(read_date) {
+            return Some(ts);
+        }
+    }
+    find_tag(ifd0, 0x0132).and_then(read_date)
+}
+
+/// Parse an EXIF "YYYY:MM:DD HH:MM:SS" timestamp into unix seconds
+fn parse_exif_datetime(text: &str) -> Option<u64> {
+    let field = |range: std::ops::Range<usize>| text.get(range)?.parse::<u64>().ok();
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    Some(days as u64 * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn syncsafe_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let b = data.get(pos..pos + 4)?;
+    Some(((b[0] as u32) << 21) | ((b[1] as u32) << 14) | ((b[2] as u32) << 7) | b[3] as u32)
+}
+
+/// Decode an ID3v2 text frame body
+fn decode_id3_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let decoded = match encoding {
+        0 => text.iter().map(|&b| b as char).collect(),
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                _ => (encoding == 2, text),
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|c| {
+                    if big_endian {
+                        u16::from_be_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_le_bytes([c[0], c[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    let trimmed = decoded.trim_end_matches('\0').trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+fn parse_id3(data: &[u8]) -> Option<MediaMetadata> {
+    let version = *data.get(3)?;
+    if !(3..=4).contains(&version) {
+        return None;
+    }
+    let tag_end = (10 + syncsafe_u32(data, 6)? as usize).min(data.len());
+
+    let mut meta = MediaMetadata {
+        kind: Some(MediaKind::Audio),
+        codec: Some("mp3".to_string()),
+        ..Default::default()
+    };
+
+    let mut pos = 10;
+    while pos + 10 <= tag_end {
+        let id = &data[pos..pos + 4];
+        if id[0] == 0 {
+            break; // padding
+        }
+        let size = if version == 4 {
+            syncsafe_u32(data, pos + 4)?
+        } else {
+            be_u32(data, pos + 4)?
+        } as usize;
+        let body = match data.get(pos + 10..pos + 10 + size) {
+            Some(b) => b,
+            None => break,
+        };
+
+        match id {
+            b"TIT2" => meta.title = decode_id3_text(body),
+            b"TPE1" => meta.artist = decode_id3_text(body),
+            b"TALB" => meta.album = decode_id3_text(body),
+            b"TLEN" => {
+                meta.duration_ms = decode_id3_text(body).and_then(|t| t.parse().ok());
+            }
+            _ => {}
+        }
+        pos += 10 + size;
+    }
+
+    Some(meta)
+}
+
+/// Iterate child boxes of an MP4 box payload as (type, payload)
+fn mp4_boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let size = be_u32(data, pos)? as usize;
+        let kind = data.get(pos + 4..pos + 8)?;
+        let (header, size) = match size {
+            0 => (8, data.len() - pos),
+            1 => (16, be_u64(data, pos + 8)? as usize),
+            n => (8, n),
+        };
+        if size < header {
+            return None;
+        }
+        let end = pos.checked_add(size)?;
+        let payload = data.get(pos + header..end)?;
+        pos = end;
+        Some((kind, payload))
+    })
+}
+
+fn mp4_child<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    mp4_boxes(data).find(|(k, _)| k == &kind).map(|(_, p)| p)
+}
+
+/// Walk top-level boxes with range reads and fetch the `moov` box payload
+async fn read_mp4_moov(tree: &TreeManager, cid: &WorkerCid, size: u64) -> Option<Vec<u8>> {
+    let mut offset = 0u64;
+    for _ in 0..MAX_TOP_LEVEL_BOXES {
+        if size.saturating_sub(offset) < 8 {
+            return None;
+        }
+        let header = tree
+            .read_file_range(cid, offset, Some(offset.saturating_add(16).min(size)))
+            .await
+            .ok()?;
+        let (header_len, box_size) = match be_u32(&header, 0)? {
+            0 => (8, size - offset),
+            1 => (16, be_u64(&header, 8)?),
+            n => (8, n as u64),
+        };
+        if box_size < header_len {
+            return None;
+        }
+        let end = offset.checked_add(box_size)?;
+        if &header[4..8] == b"moov" {
+            if box_size > MAX_MOOV_BYTES {
+                return None;
+            }
+            return tree
+                .read_file_range(cid, offset + header_len, Some(end))
+                .await
+                .ok();
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Extract duration, dimensions, codec and creation time from a `moov` payload
+pub fn parse_mp4_moov(moov: &[u8]) -> MediaMetadata {
+    let mut meta = MediaMetadata::default();
+
+    if let Some(mvhd) = mp4_child(moov, b"mvhd") {
+        let (created, timescale, duration) = if mvhd.first() == Some(&1) {
+            (be_u64(mvhd, 4), be_u32(mvhd, 20), be_u64(mvhd, 24))
+        } else {
+            (
+                be_u32(mvhd, 4).map(u64::from),
+                be_u32(mvhd, 12),
+                be_u32(mvhd, 16).map(u64::from),
+            )
+        };
+        if let (Some(timescale), Some(duration)) = (timescale, duration) {
+            if timescale > 0 {
+                meta.duration_ms = Some(duration.saturating_mul(1000) / timescale as u64);
+            }
+        }
+        meta.captured_at = created
+            .filter(|&c| c > MP4_EPOCH_OFFSET)
+            .map(|c| c - MP4_EPOCH_OFFSET);
+    }
+
+    for (kind, trak) in mp4_boxes(moov) {
+        if kind != b"trak" {
+            continue;
+        }
+        let Some(mdia) = mp4_child(trak, b"mdia") else {
+            continue;
+        };
+        let handler = mp4_child(mdia, b"hdlr").and_then(|h| h.get(8..12));
+        let codec = mp4_child(mdia, b"minf")
+            .and_then(|minf| mp4_child(minf, b"stbl"))
+            .and_then(|stbl| mp4_child(stbl, b"stsd"))
+            .and_then(|stsd| stsd.get(12..16))
+            .map(|fourcc| String::from_utf8_lossy(fourcc).trim().to_string());
+
+        match handler {
+            Some(b"vide") => {
+                meta.kind = Some(MediaKind::Video);
+                if let Some(tkhd) = mp4_child(trak, b"tkhd") {
+                    // Width and height are 16.16 fixed point at the end of tkhd
+                    let end = tkhd.len();
+                    if end >= 8 {
+                        meta.width = be_u32(tkhd, end - 8).map(|w| w >> 16);
+                        meta.height = be_u32(tkhd, end - 4).map(|h| h >> 16);
+                    }
+                }
+                meta.codec = codec;
+            }
+            Some(b"soun") => {
+                if meta.kind.is_none() {
+                    meta.kind = Some(MediaKind::Audio);
+                }
+                if meta.codec.is_none() {
+                    meta.codec = codec;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    meta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn test_media_kind_for_filename() {
+        assert_eq!(media_kind_for_filename("a.JPG"), Some(MediaKind::Image));
+        assert_eq!(media_kind_for_filename("song.mp3"), Some(MediaKind::Audio));
+        assert_eq!(media_kind_for_filename("clip.mov"), Some(MediaKind::Video));
+        assert_eq!(media_kind_for_filename("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_parse_png_dimensions() {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend_from_slice(&640u32.to_be_bytes());
+        data.extend_from_slice(&480u32.to_be_bytes());
+        let meta = extract(&data).unwrap();
+        assert_eq!(meta.kind, Some(MediaKind::Image));
+        assert_eq!((meta.width, meta.height), (Some(640), Some(480)));
+    }
+
+    #[test]
+    fn test_parse_jpeg_dimensions_and_exif_date() {
+        // TIFF (big endian) with IFD0 containing only DateTime (0x0132)
+        let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&0x0132u16.to_be_bytes());
+        tiff.extend_from_slice(&2u16.to_be_bytes()); // ASCII
+        tiff.extend_from_slice(&20u32.to_be_bytes());
+        tiff.extend_from_slice(&26u32.to_be_bytes()); // value offset
+        tiff.extend_from_slice(&0u32.to_be_bytes()); // next IFD
+        tiff.extend_from_slice(b"2021:03:04 05:06:07\0");
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(&tiff);
+
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        data.extend_from_slice(&((app1.len() + 2) as u16).to_be_bytes());
+        data.extend_from_slice(&app1);
+        // SOF0: precision, height, width, components
+        data.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x0B, 0x08]);
+        data.extend_from_slice(&1080u16.to_be_bytes());
+        data.extend_from_slice(&1920u16.to_be_bytes());
+        data.extend_from_slice(&[0x01, 0x01, 0x11, 0x00]);
+
+        let meta = extract(&data).unwrap();
+        assert_eq!((meta.width, meta.height), (Some(1920), Some(1080)));
+        assert_eq!(meta.captured_at, Some(1_614_834_367));
+    }
+
+    #[test]
+    fn test_parse_exif_datetime() {
+        assert_eq!(parse_exif_datetime("1970:01:01 00:00:00"), Some(0));
+        assert_eq!(
+            parse_exif_datetime("2000:02:29 12:00:00"),
+            Some(951_825_600)
+        );
+        assert_eq!(parse_exif_datetime("0000:00:00 00:00:00"), None);
+    }
+
+    #[test]
+    fn test_parse_id3_tags() {
+        fn frame(id: &[u8], text: &str) -> Vec<u8> {
+            let mut body = vec![3u8];
+            body.extend_from_slice(text.as_bytes());
+            let mut out = id.to_vec();
+            out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&body);
+            out
+        }
+        let mut frames = frame(b"TIT2", "Song");
+        frames.extend(frame(b"TPE1", "Artist"));
+        frames.extend(frame(b"TLEN", "215000"));
+
+        let mut data = b"ID3\x03\x00\x00".to_vec();
+        let size = frames.len() as u32;
+        data.extend_from_slice(&[
+            (size >> 21) as u8 & 0x7F,
+            (size >> 14) as u8 & 0x7F,
+            (size >> 7) as u8 & 0x7F,
+            size as u8 & 0x7F,
+        ]);
+        data.extend_from_slice(&frames);
+
+        let meta = extract(&data).unwrap();
+        assert_eq!(meta.kind, Some(MediaKind::Audio));
+        assert_eq!(meta.title.as_deref(), Some("Song"));
+        assert_eq!(meta.artist.as_deref(), Some("Artist"));
+        assert_eq!(meta.duration_ms, Some(215_000));
+    }
+
+    #[test]
+    fn test_parse_mp4_moov() {
+        // mvhd v0: version/flags, created, modified, timescale, duration
+        let mut mvhd = vec![0u8; 4];
+        mvhd.extend_from_slice(&((MP4_EPOCH_OFFSET + 1000) as u32).to_be_bytes());
+        mvhd.extend_from_slice(&0u32.to_be_bytes());
+        mvhd.extend_from_slice(&1000u32.to_be_bytes());
+        mvhd.extend_from_slice(&90_500u32.to_be_bytes());
+
+        let mut tkhd = vec![0u8; 76];
+        tkhd.extend_from_slice(&(1280u32 << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(720u32 << 16).to_be_bytes());
+
+        let mut hdlr = vec![0u8; 8];
+        hdlr.extend_from_slice(b"vide");
+        hdlr.extend_from_slice(&[0u8; 12]);
+
+        let mut stsd = vec![0u8; 4];
+        stsd.extend_from_slice(&1u32.to_be_bytes());
+        stsd.extend(mp4_box(b"avc1", &[0u8; 8]));
+
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let minf = mp4_box(b"minf", &stbl);
+        let mut mdia = mp4_box(b"hdlr", &hdlr);
+        mdia.extend(minf);
+        let mut trak = mp4_box(b"tkhd", &tkhd);
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        let mut moov = mp4_box(b"mvhd", &mvhd);
+        moov.extend(mp4_box(b"trak", &trak));
+
+        let meta = parse_mp4_moov(&moov);
+        assert_eq!(meta.kind, Some(MediaKind::Video));
+        assert_eq!(meta.duration_ms, Some(90_500));
+        assert_eq!(meta.captured_at, Some(1000));
+        assert_eq!((meta.width, meta.height), (Some(1280), Some(720)));
+        assert_eq!(meta.codec.as_deref(), Some("avc1"));
+    }
+
+    #[test]
+    fn test_mp4_boxes_with_oversized_size() {
+        // A 64-bit size after another box would wrap the payload end
+        let mut data = mp4_box(b"free", &[]);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(mp4_boxes(&data).count(), 1);
+    }
+}
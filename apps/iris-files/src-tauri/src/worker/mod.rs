@@ -1,22 +1,90 @@
+mod accounts;
+mod activity;
+mod backup;
 mod blossom;
+mod collab;
 mod combined_store;
+mod comments;
+mod dedup;
+mod download;
+mod follows;
+mod guest;
+mod history_sync;
+mod inbox;
+mod ingest;
+pub mod media;
+mod mutes;
+mod ndb_maintenance;
 mod nostr;
+mod notify;
+mod outbox;
+mod pow;
+mod profiles;
+mod progress;
+mod quota;
+mod relay_health;
+pub mod scheduler;
+pub mod search;
+mod shares;
+mod social_graph;
 pub mod store;
+mod sync;
 mod tree;
+mod tree_roots;
 mod types;
 mod webrtc;
-
+mod wot;
+
+pub use accounts::AccountInfo;
+pub use backup::apply_pending_import;
+pub use download::DownloadStats;
+pub use guest::wipe_guest_sessions;
+pub use ndb_maintenance::apply_pending_purge;
+pub use search::SearchIndex;
 pub use store::BlobStore;
+pub use sync::SyncStatus;
 pub use tree::TreeManager;
-pub use types::{PeerStatEntry, WorkerCid, WorkerDirEntry, WorkerRequest, WorkerResponse};
-
+pub use types::{
+    BlobRequest, MediaFilter, MediaItem, MediaSort, PeerStatEntry, SearchHit, StateSettings,
+    WorkerCid, WorkerDirEntry, WorkerRequest, WorkerResponse,
+};
+pub use wot::Wot;
+
+use accounts::AccountManager;
+use activity::ActivityFeed;
 use blossom::BlossomManager;
+use collab::{Proposal, ProposalRegistry};
+use combined_store::AdmissionSettings;
+use dedup::EventDedup;
+use download::Downloads;
+use guest::GuestSession;
+use history_sync::HistorySync;
+use mutes::MuteList;
+use ndb_maintenance::PurgeRule;
 use nostr::NostrManager;
+use outbox::Outbox;
+use pow::PowMiner;
+use notify::Notifier;
+use profiles::ProfileBatcher;
+use progress::ProgressReporter;
+use quota::{Origin, OriginRegistry, Quotas};
+use scheduler::{Priority, Scheduler};
+use shares::ShareRegistry;
+use social_graph::SocialGraphCache;
+use sync::SyncControl;
+use tree::{duplicate_groups, MergeOutcome};
+use tree_roots::TreeRoot;
+use types::{DuplicateGroup, FileCopy, TreeChange};
 use webrtc::WebRTCManager;
 use nostrdb::{Config, Ndb, Transaction};
 
+use crate::htree::TreeVisibility;
+use hashtree_gateway::manifest::MANIFEST_FILENAME;
+
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter};
 use tokio::sync::RwLock;
@@ -93,9 +161,28 @@ fn json_to_ndb_filter(filter_json: &serde_json::Value) -> Option<nostrdb::Filter
     Some(builder.build())
 }
 
+/// Whether `event` has the tags `filter_json` asks for (`"#e": [...]` and
+/// the like), which `json_to_ndb_filter` leaves out
+fn matches_tag_filters(filter_json: &serde_json::Value, event: &serde_json::Value) -> bool {
+    let Some(filter) = filter_json.as_object() else {
+        return true;
+    };
+    filter
+        .iter()
+        .filter_map(|(key, values)| Some((key.strip_prefix('#')?, values.as_array()?)))
+        .all(|(name, values)| {
+            event["tags"].as_array().is_some_and(|tags| {
+                tags.iter()
+                    .any(|tag| tag[0].as_str() == Some(name) && values.contains(&tag[1]))
+            })
+        })
+}
+
 /// Query ndb cache and emit cached events to frontend
 fn query_ndb_cache(
     ndb: &Ndb,
+    wot: &Wot,
+    dedup: &EventDedup,
     filters_json: &[serde_json::Value],
     sub_id: &str,
     app_handle: &AppHandle,
@@ -123,14 +210,19 @@ fn query_ndb_cache(
                                     if let Ok(note) = ndb.get_note_by_key(&txn, note_key) {
                                         if let Ok(event_json) = note.json() {
                                             if let Ok(event) = serde_json::from_str::<serde_json::Value>(&event_json) {
+                                                found_ids.push(id_arr);
+                                                if !dedup.admit(sub_id, &event) {
+                                                    continue;
+                                                }
                                                 let _ = app_handle.emit(
                                                     "worker_response",
                                                     &WorkerResponse::Event {
                                                         sub_id: sub_id.to_string(),
                                                         event,
+                                                        untrusted: wot
+                                                            .flags(&hex::encode(note.pubkey())),
                                                     },
                                                 );
-                                                found_ids.push(id_arr);
                                             }
                                         }
                                     }
@@ -147,15 +239,22 @@ fn query_ndb_cache(
                     for result in results.iter() {
                         if let Ok(event_json) = result.note.json() {
                             if let Ok(event) = serde_json::from_str::<serde_json::Value>(&event_json) {
+                                if !matches_tag_filters(filter_json, &event) {
+                                    continue;
+                                }
                                 // Track found ID
                                 let id_bytes = result.note.id();
                                 found_ids.push(*id_bytes);
+                                if !dedup.admit(sub_id, &event) {
+                                    continue;
+                                }
 
                                 let _ = app_handle.emit(
                                     "worker_response",
                                     &WorkerResponse::Event {
                                         sub_id: sub_id.to_string(),
                                         event,
+                                        untrusted: wot.flags(&hex::encode(result.note.pubkey())),
                                     },
                                 );
                             }
@@ -178,8 +277,46 @@ pub struct WorkerState {
     pub ndb: Arc<Ndb>,
     pub blossom: Arc<BlossomManager>,
     pub webrtc: Arc<WebRTCManager>,
+    /// Index of filenames and small text files in synced/imported trees
+    pub search: Arc<SearchIndex>,
+    /// Recipients of our shared private trees
+    pub shares: Arc<ShareRegistry>,
+    /// Merged and dismissed proposals of collaborators
+    pub proposals: Arc<ProposalRegistry>,
+    /// Trees tagged as our own or other people's, with per-origin quotas
+    pub origins: Arc<OriginRegistry>,
+    /// Identities set with setIdentity, one of them active
+    pub accounts: Arc<AccountManager>,
     /// Our pubkey for WoT calculations (hex, 64 chars)
     pub our_pubkey: Arc<parking_lot::RwLock<Option<String>>>,
+    /// Run slots for `worker_message`, by priority class
+    pub scheduler: Scheduler,
+    /// App data dir holding every store above
+    pub data_dir: PathBuf,
+    /// Ephemeral keys and stores while guest mode is on
+    pub guest: Arc<parking_lot::RwLock<Option<Arc<GuestSession>>>>,
+    /// Running downloadToDisk requests, for pausing them
+    pub downloads: Arc<Downloads>,
+    /// Pause switch for WebRTC and pushing/republishing
+    pub sync: Arc<SyncControl>,
+    /// OS notifications for mentions, shares and finished pushes
+    pub notifier: Arc<Notifier>,
+    /// Opt-in history sync through a private tree
+    pub history_sync: Arc<HistorySync>,
+    /// Social-graph policy for prefetching, serving and flagging content
+    pub wot: Arc<Wot>,
+    /// Follow-distance levels from our pubkey, kept between requests
+    pub social_graph: Arc<SocialGraphCache>,
+    /// Pubkeys whose trees and events are refused
+    pub mutes: Arc<MuteList>,
+    /// getProfile misses waiting for the next relay query
+    pub profiles: Arc<ProfileBatcher>,
+    /// Tree updates from people we follow
+    pub activity: Arc<ActivityFeed>,
+    /// Published events waiting for a relay to accept them
+    pub outbox: Arc<Outbox>,
+    /// NIP-13 proof of work for published events
+    pub pow: Arc<PowMiner>,
 }
 
 impl WorkerState {
@@ -191,20 +328,172 @@ impl WorkerState {
         std::fs::create_dir_all(&ndb_dir).map_err(|e| format!("Failed to create nostrdb dir: {}", e))?;
         let config = Config::new()
             .set_ingester_threads(2);  // Limit threads to avoid exhausting LMDB readers
-        let ndb = Ndb::new(ndb_dir.to_str().unwrap(), &config)
+        let ndb_path = ndb_dir
+            .to_str()
+            .ok_or_else(|| format!("Non-UTF-8 nostrdb path {:?}", ndb_dir))?;
+        let ndb = Ndb::new(ndb_path, &config)
             .map_err(|e| format!("Failed to initialize nostrdb: {:?}", e))?;
         info!("Initialized nostrdb at {:?}", ndb_dir);
 
+        let search = SearchIndex::new(&data_dir)?;
+        let ndb = Arc::new(ndb);
+        let mutes = Arc::new(MuteList::new(&data_dir));
+        let wot = Arc::new(Wot::new(&data_dir, ndb.clone(), mutes.clone()));
+        let social_graph = Arc::new(SocialGraphCache::default());
+        let outbox = Arc::new(Outbox::new(&data_dir));
+        let pow = Arc::new(PowMiner::new(&data_dir));
+        let tree = TreeManager::new(store.clone());
+        tree.set_pack_threshold(load_pack_threshold(&data_dir));
+        tree.set_cache_admission(AdmissionSettings::load(&data_dir));
+
         Ok(Self {
-            store: store.clone(),
-            tree: Arc::new(RwLock::new(Some(TreeManager::new(store)))),
-            nostr: Arc::new(NostrManager::new()),
-            ndb: Arc::new(ndb),
+            store,
+            tree: Arc::new(RwLock::new(Some(tree))),
+            nostr: Arc::new(
+                NostrManager::new()
+                    .with_wot(wot.clone())
+                    .with_social_graph(social_graph.clone())
+                    .with_outbox(outbox.clone())
+                    .with_pow(pow.clone()),
+            ),
+            ndb,
             blossom: Arc::new(BlossomManager::new()),
-            webrtc: Arc::new(WebRTCManager::new()),
+            webrtc: Arc::new(WebRTCManager::new().with_wot(wot.clone())),
+            search: Arc::new(search),
+            shares: Arc::new(ShareRegistry::new(&data_dir)),
+            proposals: Arc::new(ProposalRegistry::new(&data_dir)),
+            origins: Arc::new(OriginRegistry::new(&data_dir)),
+            accounts: Arc::new(AccountManager::new(&data_dir)),
+            notifier: Arc::new(Notifier::new(&data_dir)),
+            history_sync: Arc::new(HistorySync::new(&data_dir)),
+            wot,
+            social_graph,
+            mutes,
+            profiles: Arc::new(ProfileBatcher::default()),
+            activity: Arc::new(ActivityFeed::new(&data_dir)),
+            outbox,
+            pow,
             our_pubkey: Arc::new(parking_lot::RwLock::new(None)),
+            scheduler: Scheduler::default(),
+            data_dir,
+            guest: Arc::new(parking_lot::RwLock::new(None)),
+            downloads: Arc::new(Downloads::default()),
+            sync: Arc::new(SyncControl::default()),
         })
     }
+
+    /// Blob store of the guest session if one is on, the persistent one otherwise
+    pub fn blob_store(&self) -> Arc<BlobStore> {
+        match self.guest.read().as_ref() {
+            Some(guest) => guest.store.clone(),
+            None => self.store.clone(),
+        }
+    }
+
+    /// History of the guest session, if one is on
+    pub fn guest_history(&self) -> Option<Arc<crate::history::HistoryStore>> {
+        self.guest
+            .read()
+            .as_ref()
+            .map(|guest| guest.history.clone())
+    }
+
+    /// Bookmarks of the guest session, if one is on
+    pub fn guest_bookmarks(&self) -> Option<Arc<crate::bookmarks::BookmarkStore>> {
+        self.guest
+            .read()
+            .as_ref()
+            .map(|guest| guest.bookmarks.clone())
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.guest.read().is_some()
+    }
+
+    /// Save the file or directory at `cid` to `dest` as `downloadToDisk`
+    /// does, reporting progress under `id`
+    pub async fn download_to_disk(
+        &self,
+        app_handle: &AppHandle,
+        id: &str,
+        cid: &WorkerCid,
+        dest: &Path,
+    ) -> Result<DownloadStats, String> {
+        // A download can take hours, so it gets its own manager rather
+        // than keeping the tree locked
+        let tree = self
+            .tree
+            .read()
+            .await
+            .as_ref()
+            .map(TreeManager::detached)
+            .ok_or("Tree not initialized")?;
+        let mut handle = self.downloads.start(id);
+        let mut progress = ProgressReporter::new(app_handle, id);
+        download::download(&tree, cid, dest, &mut handle, Some(&mut progress)).await
+    }
+
+    /// Save the body of `response` to `dest`, as a download reporting
+    /// progress under `id`
+    pub async fn download_web_to_disk(
+        &self,
+        app_handle: &AppHandle,
+        id: &str,
+        response: reqwest::Response,
+        dest: &Path,
+    ) -> Result<DownloadStats, String> {
+        let len = response.content_length();
+        let body = futures::stream::unfold(response, |mut response| async move {
+            match response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), response)),
+                Ok(None) => None,
+                Err(e) => Some((Err(format!("Failed to fetch: {}", e)), response)),
+            }
+        });
+        let mut handle = self.downloads.start(id);
+        let mut progress = ProgressReporter::new(app_handle, id);
+        download::download_web(Box::pin(body), len, dest, &mut handle, Some(&mut progress)).await
+    }
+
+    pub async fn sync_status(&self) -> SyncStatus {
+        SyncStatus::new(
+            self.sync.is_paused(),
+            self.webrtc.peer_count().await,
+            &self.nostr.get_relay_stats().await,
+            &self.scheduler.jobs(),
+        )
+    }
+
+    /// Pause or resume sync, disconnecting or reconnecting WebRTC peers
+    pub async fn set_sync_paused(&self, paused: bool) {
+        if !self.sync.set_paused(paused) {
+            return;
+        }
+        if paused {
+            info!("Sync paused");
+            self.webrtc.shutdown().await;
+            return;
+        }
+        info!("Sync resumed");
+        self.start_webrtc();
+    }
+
+    /// Start WebRTC signaling in the background, unless it's running or a
+    /// proxy is set
+    fn start_webrtc(&self) {
+        if let Some(client) = self.nostr.get_client() {
+            let keys = self
+                .nostr
+                .get_keys()
+                .unwrap_or_else(nostr_sdk::Keys::generate);
+            let webrtc = self.webrtc.clone();
+            tokio::spawn(async move {
+                if let Err(e) = webrtc.init(client, keys).await {
+                    warn!("Failed to initialize WebRTC: {}", e);
+                }
+            });
+        }
+    }
 }
 
 /// Handle worker messages from frontend
@@ -214,6 +503,32 @@ pub async fn worker_message(
     app_handle: AppHandle,
     state: tauri::State<'_, std::sync::Arc<WorkerState>>,
 ) -> Result<(), String> {
+    let response = handle_request(message, app_handle.clone(), state).await?;
+    app_handle
+        .emit("worker_response", &response)
+        .map_err(|e| format!("Failed to emit response: {}", e))
+}
+
+/// Run a worker request to its response, for `worker_message` and the
+/// remote API of paired devices (see [`crate::remote`])
+pub async fn handle_request(
+    message: WorkerRequest,
+    app_handle: AppHandle,
+    state: tauri::State<'_, std::sync::Arc<WorkerState>>,
+) -> Result<WorkerResponse, String> {
+    let kind = message.kind();
+
+    // Pushes wait for a paused sync to resume before queueing
+    if sync::SYNC_KINDS.contains(&kind) {
+        state.sync.wait_resumed().await;
+    }
+
+    // Held until the response is emitted
+    let _permit = match message.priority() {
+        Some(priority) => Some(state.scheduler.acquire(message.id(), kind, priority).await),
+        None => None,
+    };
+
     let response = match message {
         // Lifecycle
         WorkerRequest::Init { id } => {
@@ -222,74 +537,223 @@ pub async fn worker_message(
             if let Err(e) = state.nostr.ensure_client(Some(app_handle.clone()), Some(state.ndb.clone())).await {
                 tracing::warn!("Failed to initialize Nostr client during init: {}", e);
             }
+            state.notifier.set_app_handle(app_handle.clone());
+            state.notifier.watch(&state.nostr, state.ndb.clone()).await;
+            watch_activity(&state).await;
+            if state.history_sync.begin() {
+                start_history_sync(state.inner().clone(), app_handle.clone());
+            }
             WorkerResponse::Ready { id }
         }
         WorkerRequest::Ping { id } => WorkerResponse::Pong { id },
+        WorkerRequest::GetJobs { id } => WorkerResponse::Jobs {
+            id,
+            jobs: state.scheduler.jobs(),
+        },
+        WorkerRequest::GetSyncStatus { id } => WorkerResponse::SyncStatus {
+            id,
+            status: state.sync_status().await,
+        },
+        WorkerRequest::SetSyncPaused { id, paused } => {
+            state.set_sync_paused(paused).await;
+            WorkerResponse::SyncStatus {
+                id,
+                status: state.sync_status().await,
+            }
+        }
+        WorkerRequest::GetNotifyRules { id } => WorkerResponse::NotifyRules {
+            id,
+            rules: state.notifier.rules(),
+        },
+        WorkerRequest::SetNotifyRules { id, rules } => match state.notifier.set_rules(rules) {
+            Ok(()) => WorkerResponse::NotifyRules {
+                id,
+                rules: state.notifier.rules(),
+            },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+        WorkerRequest::GetWotPolicy { id } => WorkerResponse::WotPolicy {
+            id,
+            policy: state.wot.policy(),
+        },
+        WorkerRequest::SetWotPolicy { id, policy } => match state.wot.set_policy(policy) {
+            Ok(()) => WorkerResponse::WotPolicy {
+                id,
+                policy: state.wot.policy(),
+            },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+        WorkerRequest::GetPowPolicy { id } => WorkerResponse::PowPolicy {
+            id,
+            policy: state.pow.policy(),
+        },
+        WorkerRequest::SetPowPolicy { id, policy } => match state.pow.set_policy(policy) {
+            Ok(()) => WorkerResponse::PowPolicy {
+                id,
+                policy: state.pow.policy(),
+            },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+        WorkerRequest::CancelPow { id } => WorkerResponse::Bool {
+            id,
+            value: state.pow.cancel(),
+        },
+        WorkerRequest::GetHistorySync { id } => WorkerResponse::Bool {
+            id,
+            value: state.history_sync.is_enabled(),
+        },
+        WorkerRequest::SetHistorySync { id, enabled } => {
+            match state.history_sync.set_enabled(enabled) {
+                Ok(()) => {
+                    // Pick up the other devices' history without waiting a round
+                    if enabled && !state.is_guest() {
+                        let state = state.inner().clone();
+                        let app_handle = app_handle.clone();
+                        tokio::spawn(async move {
+                            background_history_sync(&state, &app_handle).await;
+                        });
+                    }
+                    WorkerResponse::Bool { id, value: enabled }
+                }
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+        WorkerRequest::SyncHistory { id } => match sync_history(&state, &app_handle).await {
+            Ok(merged) => WorkerResponse::HistorySynced { id, merged },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+        WorkerRequest::PauseDownload { id, download_id } => WorkerResponse::Bool {
+            id,
+            value: state.downloads.set_paused(&download_id, true),
+        },
+        WorkerRequest::ResumeDownload { id, download_id } => WorkerResponse::Bool {
+            id,
+            value: state.downloads.set_paused(&download_id, false),
+        },
+
+        // Guests only read: nothing is published, uploaded or exported
+        WorkerRequest::Publish { id, .. }
+        | WorkerRequest::BlossomUpload { id, .. }
+        | WorkerRequest::PushToBlossom { id, .. }
+        | WorkerRequest::PublishTree { id, .. }
+        | WorkerRequest::AddToInbox { id, .. }
+        | WorkerRequest::SyncHistory { id, .. }
+        | WorkerRequest::PostComment { id, .. }
+        | WorkerRequest::Follow { id, .. }
+        | WorkerRequest::Unfollow { id, .. }
+        | WorkerRequest::MutePubkey { id, .. }
+        | WorkerRequest::UnmutePubkey { id, .. }
+        | WorkerRequest::FetchMutes { id, .. }
+        | WorkerRequest::PublishMutes { id, .. }
+        | WorkerRequest::RetryOutbox { id, .. }
+        | WorkerRequest::GrantAccess { id, .. }
+        | WorkerRequest::RevokeAccess { id, .. }
+        | WorkerRequest::SetCollaborators { id, .. }
+        | WorkerRequest::ProposeRoot { id, .. }
+        | WorkerRequest::MergeProposal { id, .. }
+        | WorkerRequest::DismissProposal { id, .. }
+        | WorkerRequest::RotateTreeKey { id, .. }
+        | WorkerRequest::RepublishTree { id, .. }
+        | WorkerRequest::RepublishTrees { id, .. }
+        | WorkerRequest::DeleteTree { id, .. }
+        | WorkerRequest::SwitchAccount { id, .. }
+        | WorkerRequest::SetIdentity { id, .. }
+        | WorkerRequest::ExportState { id, .. }
+        | WorkerRequest::ImportState { id, .. }
+            if state.is_guest() =>
+        {
+            WorkerResponse::Error {
+                id,
+                error: "Not available in guest mode".to_string(),
+            }
+        }
 
         // Store operations
         WorkerRequest::Get { id, hash } => {
-            // Use CombinedStore (with Blossom fallback) via TreeManager if available
-            let tree_guard = state.tree.read().await;
-            let data = if let Some(tree) = tree_guard.as_ref() {
-                tree.get_blob(&hash).await.map(|d| BASE64.encode(&d))
-            } else {
-                state.store.get(&hash).await.map(|d| BASE64.encode(&d))
-            };
-            WorkerResponse::Result { id, data }
+            match read_blob(&state, BlobRequest::Get { hash }).await {
+                Ok(data) => WorkerResponse::Result {
+                    id,
+                    data: data.map(|d| BASE64.encode(&d)),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
         }
 
         WorkerRequest::Put { id, hash, data } => {
             let bytes = BASE64
                 .decode(&data)
                 .map_err(|e| format!("Invalid base64: {}", e))?;
-            let ok = state.store.put(&hash, &bytes).await.unwrap_or(false);
+            let ok = state.blob_store().put(&hash, &bytes).await.unwrap_or(false);
             WorkerResponse::Bool { id, value: ok }
         }
 
         WorkerRequest::Has { id, hash } => WorkerResponse::Bool {
             id,
-            value: state.store.has(&hash),
+            value: state.blob_store().has(&hash),
         },
 
         WorkerRequest::Delete { id, hash } => {
-            let ok = state.store.delete(&hash).await;
+            let ok = state.blob_store().delete(&hash).await;
             WorkerResponse::Bool { id, value: ok }
         }
 
+        WorkerRequest::GetMany { id, hashes } => {
+            let tree_guard = state.tree.read().await;
+            let found = match tree_guard.as_ref() {
+                Some(tree) => tree.get_blobs(&hashes).await,
+                None => Ok(state.blob_store().get_many(&hashes).await),
+            };
+            match found {
+                Ok(found) => WorkerResponse::Results {
+                    id,
+                    data: found
+                        .into_iter()
+                        .map(|d| d.map(|d| BASE64.encode(&d)))
+                        .collect(),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::PutMany { id, items } => {
+            let items = items
+                .into_iter()
+                .map(|item| {
+                    let bytes = BASE64
+                        .decode(&item.data)
+                        .map_err(|e| format!("Invalid base64: {}", e))?;
+                    Ok((item.hash, bytes))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            match state.blob_store().put_many(items).await {
+                Ok(values) => WorkerResponse::Bools { id, values },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::HasMany { id, hashes } => WorkerResponse::Bools {
+            id,
+            values: state.blob_store().has_many(&hashes).await,
+        },
+
         // Tree operations
         WorkerRequest::ReadFile { id, cid } => {
-            let tree_guard = state.tree.read().await;
-            if let Some(tree) = tree_guard.as_ref() {
-                match tree.read_file(&cid).await {
-                    Ok(data) => WorkerResponse::Result {
-                        id,
-                        data: Some(BASE64.encode(&data)),
-                    },
-                    Err(e) => WorkerResponse::Error { id, error: e },
-                }
-            } else {
-                WorkerResponse::Error {
+            match read_blob(&state, BlobRequest::ReadFile { cid }).await {
+                Ok(data) => WorkerResponse::Result {
                     id,
-                    error: "Tree not initialized".to_string(),
-                }
+                    data: data.map(|d| BASE64.encode(&d)),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
             }
         }
 
         WorkerRequest::ReadFileRange { id, cid, start, end } => {
-            let tree_guard = state.tree.read().await;
-            if let Some(tree) = tree_guard.as_ref() {
-                match tree.read_file_range(&cid, start, end).await {
-                    Ok(data) => WorkerResponse::Result {
-                        id,
-                        data: Some(BASE64.encode(&data)),
-                    },
-                    Err(e) => WorkerResponse::Error { id, error: e },
-                }
-            } else {
-                WorkerResponse::Error {
+            match read_blob(&state, BlobRequest::ReadFileRange { cid, start, end }).await {
+                Ok(data) => WorkerResponse::Result {
                     id,
-                    error: "Tree not initialized".to_string(),
-                }
+                    data: data.map(|d| BASE64.encode(&d)),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
             }
         }
 
@@ -298,6 +762,7 @@ pub async fn worker_message(
             parent_cid,
             path,
             data,
+            encrypted,
         } => {
             let bytes = BASE64
                 .decode(&data)
@@ -305,7 +770,30 @@ pub async fn worker_message(
 
             let tree_guard = state.tree.read().await;
             if let Some(tree) = tree_guard.as_ref() {
-                match tree.write_file(parent_cid.as_ref(), &path, &bytes).await {
+                let mut progress = ProgressReporter::new(&app_handle, &id);
+                progress.phase("import", Some(1), Some(bytes.len() as u64));
+                match tree
+                    .write_file(parent_cid.as_ref(), &path, &bytes, encrypted)
+                    .await
+                {
+                    Ok(cid) => {
+                        progress.advance(1, bytes.len() as u64);
+                        WorkerResponse::Cid { id, cid: Some(cid) }
+                    }
+                    Err(e) => WorkerResponse::Error { id, error: e },
+                }
+            } else {
+                WorkerResponse::Error {
+                    id,
+                    error: "Tree not initialized".to_string(),
+                }
+            }
+        }
+
+        WorkerRequest::CreateDir { id, encrypted } => {
+            let tree_guard = state.tree.read().await;
+            if let Some(tree) = tree_guard.as_ref() {
+                match tree.create_empty_dir(encrypted).await {
                     Ok(cid) => WorkerResponse::Cid { id, cid: Some(cid) },
                     Err(e) => WorkerResponse::Error { id, error: e },
                 }
@@ -361,181 +849,251 @@ pub async fn worker_message(
             }
         }
 
+        WorkerRequest::TreeStats { id, cid, largest } => {
+            let tree_guard = state.tree.read().await;
+            if let Some(tree) = tree_guard.as_ref() {
+                match tree.tree_stats(&cid, largest.unwrap_or(10)).await {
+                    Ok(stats) => WorkerResponse::TreeStats { id, stats },
+                    Err(e) => WorkerResponse::Error { id, error: e },
+                }
+            } else {
+                WorkerResponse::Error {
+                    id,
+                    error: "Tree not initialized".to_string(),
+                }
+            }
+        }
+
+        WorkerRequest::FindDuplicates { id, root } => {
+            match find_duplicates(&state, root.as_ref()).await {
+                Ok(groups) => WorkerResponse::Duplicates {
+                    id,
+                    savings: groups.iter().map(|group| group.savings).sum(),
+                    groups,
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::AppendLog {
+            id,
+            cid,
+            entries,
+            encrypted,
+        } => {
+            let entries = entries
+                .iter()
+                .map(|entry| BASE64.decode(entry))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Invalid base64: {}", e))?;
+
+            let tree_guard = state.tree.read().await;
+            if let Some(tree) = tree_guard.as_ref() {
+                match tree.log_append(cid.as_ref(), &entries, encrypted).await {
+                    Ok((cid, length)) => WorkerResponse::LogAppended { id, cid, length },
+                    Err(e) => WorkerResponse::Error { id, error: e },
+                }
+            } else {
+                WorkerResponse::Error {
+                    id,
+                    error: "Tree not initialized".to_string(),
+                }
+            }
+        }
+
+        WorkerRequest::ReadLog {
+            id,
+            cid,
+            start,
+            end,
+        } => {
+            let tree_guard = state.tree.read().await;
+            if let Some(tree) = tree_guard.as_ref() {
+                match tree.log_read(&cid, start, end).await {
+                    Ok((entries, length)) => WorkerResponse::LogEntries {
+                        id,
+                        entries: entries.iter().map(|entry| BASE64.encode(entry)).collect(),
+                        length,
+                    },
+                    Err(e) => WorkerResponse::Error { id, error: e },
+                }
+            } else {
+                WorkerResponse::Error {
+                    id,
+                    error: "Tree not initialized".to_string(),
+                }
+            }
+        }
+
         WorkerRequest::ResolveRoot { id, npub, path } => {
             // Parse npub to get pubkey (supports npub1... or hex)
             let public_key = if npub.starts_with("npub1") {
                 match nostr_sdk::PublicKey::parse(&npub) {
                     Ok(pk) => pk,
-                    Err(e) => {
-                        return app_handle
-                            .emit("worker_response", &WorkerResponse::Cid { id, cid: None })
-                            .map_err(|_| format!("Invalid npub: {}", e));
+                    Err(_) => {
+                        return Ok(WorkerResponse::Cid { id, cid: None });
                     }
                 }
             } else {
                 match nostr_sdk::PublicKey::from_hex(&npub) {
                     Ok(pk) => pk,
-                    Err(e) => {
-                        return app_handle
-                            .emit("worker_response", &WorkerResponse::Cid { id, cid: None })
-                            .map_err(|_| format!("Invalid pubkey: {}", e));
+                    Err(_) => {
+                        return Ok(WorkerResponse::Cid { id, cid: None });
                     }
                 }
             };
             let pk_bytes = public_key.to_bytes();
 
+            // Trees of muted pubkeys don't resolve
+            if state.mutes.is_muted(&public_key.to_hex()) {
+                return Ok(WorkerResponse::Cid { id, cid: None });
+            }
+
             // Parse path to get tree name (first segment, default 'public')
             let tree_name = path
                 .as_ref()
                 .and_then(|p| p.split('/').filter(|s| !s.is_empty()).next())
                 .unwrap_or("public");
 
-            // Helper to extract CID from nostrdb query results
-            fn extract_cid_from_ndb_results(
-                ndb: &Ndb,
-                txn: &Transaction,
-                pk_bytes: &[u8; 32],
-                tree_name: &str,
-            ) -> Option<WorkerCid> {
-                let filter = nostrdb::Filter::new()
-                    .kinds(vec![30078])
-                    .authors(vec![pk_bytes])
-                    .build();
-
-                let results = match ndb.query(txn, &[filter], 100) {
-                    Ok(r) => r,
-                    Err(_) => return None,
-                };
-
-                for result in results.iter() {
-                    let mut has_d_tag = false;
-                    let mut has_l_tag = false;
-                    let mut hash_value: Option<String> = None;
-                    let mut key_value: Option<String> = None;
-
-                    for tag in result.note.tags() {
-                        if let Some(tag_str) = tag.get_unchecked(0).str() {
-                            if tag_str == "d" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    has_d_tag = val == tree_name;
-                                }
-                            } else if tag_str == "l" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    has_l_tag = val == "hashtree";
-                                }
-                            } else if tag_str == "hash" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    if !val.is_empty() {
-                                        hash_value = Some(val.to_string());
-                                    }
-                                }
-                            } else if tag_str == "key" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    if !val.is_empty() {
-                                        key_value = Some(val.to_string());
-                                    }
-                                }
-                            }
-                        }
-                    }
+            let own_keys = state.nostr.get_keys();
 
-                    if has_d_tag && has_l_tag {
-                        if let Some(hash) = hash_value {
-                            return Some(WorkerCid { hash, key: key_value });
-                        }
+            // 1. Query nostrdb cache first (fast path), then the relays
+            let cached = tree_roots::cached_tree_root(&state.ndb, &pk_bytes, tree_name, own_keys.as_ref());
+            let found = match cached {
+                Some(root) => Some(root),
+                None => match fetch_tree_roots(&state, &app_handle, &public_key, Some(tree_name)).await {
+                    Ok(roots) => roots.into_iter().find(|root| root.name == tree_name),
+                    Err(e) => {
+                        debug!("Failed to fetch {}/{} from relays: {}", npub, tree_name, e);
+                        None
                     }
-                }
-                None
-            }
+                },
+            };
 
-            // 1. Query nostrdb cache first (fast path)
-            let cached_cid: Option<WorkerCid> = {
-                if let Ok(txn) = Transaction::new(&state.ndb) {
-                    extract_cid_from_ndb_results(&state.ndb, &txn, &pk_bytes, tree_name)
-                } else {
-                    None
+            let found_cid = match found {
+                Some(root) if root.locked => {
+                    Some(unlock_shared_root(&state, &app_handle, &public_key, tree_name, root.cid).await)
                 }
+                other => other.map(|root| root.cid),
             };
 
-            if cached_cid.is_some() {
-                return app_handle
-                    .emit("worker_response", &WorkerResponse::Cid { id, cid: cached_cid })
-                    .map_err(|e| format!("Failed to emit: {}", e));
-            }
+            tracing::info!("ResolveRoot {}/{} -> {:?}", npub, tree_name, found_cid);
+            WorkerResponse::Cid { id, cid: found_cid }
+        }
 
-            // 2. Not in cache - query relays with timeout
-            if let Err(e) = state.nostr.ensure_client(Some(app_handle.clone()), Some(state.ndb.clone())).await {
-                debug!("Failed to init nostr client for ResolveRoot: {}", e);
-                return app_handle
-                    .emit("worker_response", &WorkerResponse::Cid { id, cid: None })
-                    .map_err(|e| format!("Failed to emit: {}", e));
-            }
-
-            // Build filter for kind 30078 with d tag and l=hashtree
-            let relay_filter = nostr_sdk::Filter::new()
-                .kind(nostr_sdk::Kind::from(30078u16))
-                .author(public_key)
-                .custom_tag(nostr_sdk::SingleLetterTag::from_char('d').unwrap(), vec![tree_name.to_string()])
-                .custom_tag(nostr_sdk::SingleLetterTag::from_char('l').unwrap(), vec!["hashtree".to_string()])
-                .limit(1);
-
-            // One-shot fetch with 3 second timeout
-            let fetch_result = tokio::time::timeout(
-                std::time::Duration::from_secs(3),
-                state.nostr.fetch_events(vec![relay_filter])
-            ).await;
-
-            let found_cid = match fetch_result {
-                Ok(Ok(events)) => {
-                    // Process events - store in ndb and extract CID
-                    for event in &events {
-                        let event_json = serde_json::to_string(&event).unwrap_or_default();
-                        let relay_msg = format!(r#"["EVENT","resolve-root",{}]"#, event_json);
-                        let _ = state.ndb.process_event(&relay_msg);
-                    }
-
-                    // Now query ndb again for the result
-                    if let Ok(txn) = Transaction::new(&state.ndb) {
-                        extract_cid_from_ndb_results(&state.ndb, &txn, &pk_bytes, tree_name)
-                    } else {
-                        None
-                    }
-                }
-                Ok(Err(e)) => {
-                    debug!("Relay fetch error: {}", e);
-                    None
+        WorkerRequest::ListTrees { id, pubkey } => {
+            let public_key = match nostr_sdk::PublicKey::parse(&pubkey) {
+                Ok(pk) => pk,
+                Err(e) => {
+                    return Ok(WorkerResponse::Error {
+                        id,
+                        error: format!("Invalid pubkey: {}", e),
+                    });
                 }
-                Err(_) => {
-                    debug!("Relay fetch timeout for {}/{}", npub, tree_name);
-                    None
+            };
+            let trees = if state.mutes.is_muted(&public_key.to_hex()) {
+                Vec::new()
+            } else {
+                let own_keys = state.nostr.get_keys();
+                let cached =
+                    tree_roots::cached_tree_roots(&state.ndb, &public_key.to_bytes(), own_keys.as_ref())
+                        .unwrap_or_else(|e| {
+                            debug!("Failed to read cached tree roots: {}", e);
+                            Vec::new()
+                        });
+                if cached.is_empty() {
+                    fetch_tree_roots(&state, &app_handle, &public_key, None)
+                        .await
+                        .unwrap_or_else(|e| {
+                            debug!("Failed to fetch tree roots of {}: {}", pubkey, e);
+                            Vec::new()
+                        })
+                } else {
+                    cached
                 }
             };
+            WorkerResponse::Trees { id, trees }
+        }
 
-            tracing::info!("ResolveRoot {}/{} -> {:?}", npub, tree_name, found_cid);
-            WorkerResponse::Cid { id, cid: found_cid }
+        WorkerRequest::GetTreeLog {
+            id,
+            pubkey,
+            tree_name,
+            limit,
+        } => {
+            let public_key = match nostr_sdk::PublicKey::parse(&pubkey) {
+                Ok(pk) => pk,
+                Err(e) => {
+                    return Ok(WorkerResponse::Error {
+                        id,
+                        error: format!("Invalid pubkey: {}", e),
+                    });
+                }
+            };
+            let limit = limit.unwrap_or(tree_roots::DEFAULT_LOG_LIMIT);
+            let commits = if state.mutes.is_muted(&public_key.to_hex()) {
+                Vec::new()
+            } else {
+                let own_keys = state.nostr.get_keys();
+                let cached_log = || {
+                    tree_roots::cached_tree_log(
+                        &state.ndb,
+                        &public_key.to_bytes(),
+                        &tree_name,
+                        own_keys.as_ref(),
+                        limit,
+                    )
+                    .unwrap_or_else(|e| {
+                        debug!("Failed to read cached tree roots: {}", e);
+                        Vec::new()
+                    })
+                };
+                let cached = cached_log();
+                if cached.is_empty() {
+                    // Caches the newest root; older ones are only known if seen before
+                    if let Err(e) =
+                        fetch_tree_roots(&state, &app_handle, &public_key, Some(&tree_name)).await
+                    {
+                        debug!("Failed to fetch tree roots of {}: {}", pubkey, e);
+                    }
+                    cached_log()
+                } else {
+                    cached
+                }
+            };
+            WorkerResponse::TreeLog { id, commits }
         }
 
         // Nostr operations
-        WorkerRequest::Subscribe { id, filters } => {
+        WorkerRequest::Subscribe {
+            id,
+            filters,
+            relays,
+        } => {
             // Ensure client is initialized with ndb for event storage
             if let Err(e) = state.nostr.ensure_client(Some(app_handle.clone()), Some(state.ndb.clone())).await {
-                return app_handle
-                    .emit(
-                        "worker_response",
-                        &WorkerResponse::Error {
-                            id,
-                            error: format!("Failed to initialize Nostr client: {}", e),
-                        },
-                    )
-                    .map_err(|e| format!("Failed to emit response: {}", e));
+                return Ok(WorkerResponse::Error {
+                    id,
+                    error: format!("Failed to initialize Nostr client: {}", e),
+                });
             }
 
             // Query ndb cache first - emit cached events immediately
-            let _found_ids = query_ndb_cache(&state.ndb, &filters, &id, &app_handle);
+            let _found_ids = query_ndb_cache(
+                &state.ndb,
+                &state.wot,
+                state.nostr.dedup(),
+                &filters,
+                &id,
+                &app_handle,
+            );
 
             // Parse filters and subscribe to relays for more/missing events
             match nostr::parse_filters(filters) {
-                Ok(parsed_filters) => match state.nostr.subscribe(id.clone(), parsed_filters).await
+                Ok(parsed_filters) => match state
+                    .nostr
+                    .subscribe(id.clone(), parsed_filters, relays)
+                    .await
                 {
                     Ok(()) => WorkerResponse::Void { id },
                     Err(e) => WorkerResponse::Error { id, error: e },
@@ -557,15 +1115,10 @@ pub async fn worker_message(
         WorkerRequest::Publish { id, event } => {
             // Ensure client is initialized with ndb for event storage
             if let Err(e) = state.nostr.ensure_client(Some(app_handle.clone()), Some(state.ndb.clone())).await {
-                return app_handle
-                    .emit(
-                        "worker_response",
-                        &WorkerResponse::Error {
-                            id,
-                            error: format!("Failed to initialize Nostr client: {}", e),
-                        },
-                    )
-                    .map_err(|e| format!("Failed to emit response: {}", e));
+                return Ok(WorkerResponse::Error {
+                    id,
+                    error: format!("Failed to initialize Nostr client: {}", e),
+                });
             }
 
             match state.nostr.publish(event).await {
@@ -580,19 +1133,26 @@ pub async fn worker_message(
         WorkerRequest::SetIdentity { id, pubkey, nsec } => {
             // Set identity for Nostr
             if let Err(e) = state.nostr.set_identity(&pubkey, nsec.as_deref()) {
-                return app_handle
-                    .emit(
-                        "worker_response",
-                        &WorkerResponse::Error {
-                            id,
-                            error: e.clone(),
-                        },
-                    )
-                    .map_err(|e| format!("Failed to emit response: {}", e));
+                return Ok(WorkerResponse::Error {
+                    id,
+                    error: e.clone(),
+                });
+            }
+
+            // Remember signing identities so they can be switched back to.
+            // Read-only ones are also used just to move the social graph root.
+            if nsec.is_some() {
+                if let Some(keys) = state.nostr.get_keys() {
+                    let pubkey = keys.public_key().to_hex();
+                    if let Err(e) = state.accounts.add(&pubkey, Some(keys.secret_key().clone())) {
+                        warn!("Failed to remember account: {}", e);
+                    }
+                }
             }
 
             // Set pubkey for social graph WoT calculations
             *state.our_pubkey.write() = Some(pubkey.clone());
+            state.mutes.set_account(Some(&pubkey));
             if let Ok(pk_bytes) = hex_to_pubkey(&pubkey) {
                 nostrdb::socialgraph::set_root(&state.ndb, &pk_bytes);
                 info!("Set social graph root to {}", &pubkey[..8]);
@@ -621,9 +1181,55 @@ pub async fn worker_message(
                 }
             }
 
+            // Pick up blocks made on other devices
+            if nsec.is_some() && !state.is_guest() {
+                let state = state.inner().clone();
+                tokio::spawn(async move {
+                    if let Err(e) = fetch_mutes(&state).await {
+                        debug!("Mute list not fetched: {}", e);
+                    }
+                });
+            }
+
             WorkerResponse::Void { id }
         }
 
+        WorkerRequest::ListAccounts { id } => WorkerResponse::Accounts {
+            id,
+            accounts: state.accounts.list(),
+        },
+
+        WorkerRequest::SwitchAccount { id, pubkey } => {
+            let result = match shares::parse_pubkey(&pubkey) {
+                Ok(pk) => switch_account(&state, &pk.to_hex()).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => WorkerResponse::Void { id },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::SetGuestMode { id, enabled } => {
+            let result = if enabled {
+                enter_guest_mode(&state).await
+            } else {
+                leave_guest_mode(&state).await
+            };
+            match result {
+                Ok(()) => WorkerResponse::GuestMode {
+                    id,
+                    enabled,
+                    pubkey: state
+                        .guest
+                        .read()
+                        .as_ref()
+                        .map(|guest| guest.keys.public_key().to_hex()),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
         // Relay management
         WorkerRequest::SetRelays { id, relays } => {
             match state.nostr.set_relays(relays).await {
@@ -650,12 +1256,10 @@ pub async fn worker_message(
                     let txn = match nostrdb::Transaction::new(&state.ndb) {
                         Ok(t) => t,
                         Err(e) => {
-                            return app_handle
-                                .emit("worker_response", &WorkerResponse::Error {
-                                    id,
-                                    error: format!("Transaction error: {:?}", e),
-                                })
-                                .map_err(|e| format!("Failed to emit: {}", e));
+                            return Ok(WorkerResponse::Error {
+                                id,
+                                error: format!("Transaction error: {:?}", e),
+                            });
                         }
                     };
                     let follows = nostrdb::socialgraph::get_followed(&txn, &state.ndb, &pk_bytes, 10000);
@@ -672,12 +1276,10 @@ pub async fn worker_message(
                     let txn = match nostrdb::Transaction::new(&state.ndb) {
                         Ok(t) => t,
                         Err(e) => {
-                            return app_handle
-                                .emit("worker_response", &WorkerResponse::Error {
-                                    id,
-                                    error: format!("Transaction error: {:?}", e),
-                                })
-                                .map_err(|e| format!("Failed to emit: {}", e));
+                            return Ok(WorkerResponse::Error {
+                                id,
+                                error: format!("Transaction error: {:?}", e),
+                            });
                         }
                     };
                     let followers = nostrdb::socialgraph::get_followers(&txn, &state.ndb, &pk_bytes, 10000);
@@ -694,12 +1296,10 @@ pub async fn worker_message(
                     let txn = match nostrdb::Transaction::new(&state.ndb) {
                         Ok(t) => t,
                         Err(e) => {
-                            return app_handle
-                                .emit("worker_response", &WorkerResponse::Error {
-                                    id,
-                                    error: format!("Transaction error: {:?}", e),
-                                })
-                                .map_err(|e| format!("Failed to emit: {}", e));
+                            return Ok(WorkerResponse::Error {
+                                id,
+                                error: format!("Transaction error: {:?}", e),
+                            });
                         }
                     };
                     let dist = nostrdb::socialgraph::get_follow_distance(&txn, &state.ndb, &pk_bytes);
@@ -712,63 +1312,158 @@ pub async fn worker_message(
         }
 
         WorkerRequest::GetUsersWithinDistance { id, max_distance } => {
-            // Get users at each distance level up to max_distance
+            // Walk out from our pubkey, sending each level as it completes
             let mut users: Vec<(String, usize)> = Vec::new();
-            let txn = match nostrdb::Transaction::new(&state.ndb) {
-                Ok(t) => t,
-                Err(e) => {
-                    return app_handle
-                        .emit("worker_response", &WorkerResponse::Error {
-                            id,
-                            error: format!("Transaction error: {:?}", e),
-                        })
-                        .map_err(|e| format!("Failed to emit: {}", e));
+            let root = state
+                .our_pubkey
+                .read()
+                .as_ref()
+                .and_then(|pk| hex_to_pubkey(pk).ok());
+            if let Some(root) = root {
+                let walked = state.social_graph.users_within(
+                    &state.ndb,
+                    root,
+                    max_distance,
+                    |distance, level| {
+                        let level: Vec<String> = level.iter().map(pubkey_to_hex).collect();
+                        users.extend(level.iter().map(|pk| (pk.clone(), distance)));
+                        let _ = app_handle.emit(
+                            "worker_response",
+                            &WorkerResponse::UsersAtDistance {
+                                id: id.clone(),
+                                distance,
+                                users: level,
+                            },
+                        );
+                    },
+                );
+                if let Err(e) = walked {
+                    return Ok(WorkerResponse::Error { id, error: e });
                 }
-            };
+            }
+            WorkerResponse::UsersWithDistance { id, users }
+        }
 
-            // Start from our pubkey and BFS
-            if let Some(our_pk) = state.our_pubkey.read().as_ref() {
-                if let Ok(root_bytes) = hex_to_pubkey(our_pk) {
-                    let mut visited = std::collections::HashSet::new();
-                    let mut current_level = vec![root_bytes];
-                    visited.insert(root_bytes);
-
-                    for distance in 1..=max_distance {
-                        let mut next_level = Vec::new();
-                        for pk in &current_level {
-                            let follows = nostrdb::socialgraph::get_followed(&txn, &state.ndb, pk, 10000);
-                            for followed in follows {
-                                if !visited.contains(&followed) {
-                                    visited.insert(followed);
-                                    users.push((pubkey_to_hex(&followed), distance));
-                                    next_level.push(followed);
-                                }
-                            }
-                        }
-                        current_level = next_level;
-                        if current_level.is_empty() {
-                            break;
-                        }
-                    }
+        // Activity of follows
+        WorkerRequest::GetActivityFeed { id, before, limit } => {
+            let (entries, has_more) = state.activity.page(before, limit.unwrap_or(50));
+            WorkerResponse::ActivityFeed {
+                id,
+                entries,
+                has_more,
+            }
+        }
+
+        // Profiles
+        WorkerRequest::GetProfile { id, pubkey } => {
+            match get_profile(state.inner().clone(), &app_handle, &pubkey).await {
+                Ok(profile) => WorkerResponse::Profile { id, profile },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        // Comments on tree paths
+        WorkerRequest::SubscribeComments {
+            id,
+            npub,
+            tree_name,
+            path,
+        } => match subscribe_comments(&state, &app_handle, &id, &npub, &tree_name, &path).await {
+            Ok(()) => WorkerResponse::Void { id },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+        WorkerRequest::PostComment {
+            id,
+            npub,
+            tree_name,
+            path,
+            content,
+            reply_to,
+        } => {
+            let thread = comments::Thread::new(&npub, &tree_name, &path);
+            let result = match thread {
+                Ok(thread) => {
+                    post_comment(&state, &app_handle, &thread, &content, reply_to.as_ref()).await
                 }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(event_id) => WorkerResponse::Result {
+                    id,
+                    data: Some(event_id),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        // Following
+        WorkerRequest::Follow { id, pubkey } | WorkerRequest::Unfollow { id, pubkey } => {
+            let follow = kind == "follow";
+            match set_following(&state, &app_handle, &pubkey, follow).await {
+                Ok(pubkeys) => WorkerResponse::Follows { id, pubkeys },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        // Mute list
+        WorkerRequest::GetMutes { id } => WorkerResponse::Mutes {
+            id,
+            pubkeys: state.mutes.pubkeys(),
+        },
+        WorkerRequest::MutePubkey { id, pubkey } => {
+            let _edit = state.mutes.edit().await;
+            match state.mutes.set_muted(&pubkey, true) {
+                Ok(_) => WorkerResponse::Mutes {
+                    id,
+                    pubkeys: state.mutes.pubkeys(),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+        WorkerRequest::UnmutePubkey { id, pubkey } => {
+            let _edit = state.mutes.edit().await;
+            match state.mutes.set_muted(&pubkey, false) {
+                Ok(_) => WorkerResponse::Mutes {
+                    id,
+                    pubkeys: state.mutes.pubkeys(),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
             }
-            WorkerResponse::UsersWithDistance { id, users }
         }
+        WorkerRequest::FetchMutes { id } => {
+            let fetched = match state
+                .nostr
+                .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+                .await
+            {
+                Ok(()) => fetch_mutes(&state).await,
+                Err(e) => Err(e),
+            };
+            match fetched {
+                Ok(_) => WorkerResponse::Mutes {
+                    id,
+                    pubkeys: state.mutes.pubkeys(),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+        WorkerRequest::PublishMutes { id } => match publish_mutes(&state, &app_handle).await {
+            Ok(event_id) => WorkerResponse::Result {
+                id,
+                data: Some(event_id),
+            },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
 
         // Blossom operations (Phase 6)
         WorkerRequest::BlossomUpload { id, data } => {
             let bytes = match BASE64.decode(&data) {
                 Ok(b) => b,
                 Err(e) => {
-                    return app_handle
-                        .emit(
-                            "worker_response",
-                            &WorkerResponse::Error {
-                                id,
-                                error: format!("Invalid base64: {}", e),
-                            },
-                        )
-                        .map_err(|e| format!("Failed to emit response: {}", e));
+                    return Ok(WorkerResponse::Error {
+                        id,
+                        error: format!("Invalid base64: {}", e),
+                    });
                 }
             };
 
@@ -809,14 +1504,43 @@ pub async fn worker_message(
 
         // Stats operations
         WorkerRequest::GetStorageStats { id } => {
-            let stats = state.store.stats();
+            let store = state.blob_store();
+            let stats = store.stats();
+            let cache = state
+                .tree
+                .read()
+                .await
+                .as_ref()
+                .map(|tree| tree.cache_stats())
+                .unwrap_or_default();
+            let usage = state.origins.usage();
+            let quotas = state.origins.quotas();
+            let logical_bytes = state.origins.logical_bytes();
+            let stored_bytes = usage.own_bytes + usage.others_bytes;
             WorkerResponse::StorageStats {
                 id,
                 items: stats.items,
                 bytes: stats.bytes,
                 pinned_items: stats.pinned_items,
                 pinned_bytes: stats.pinned_bytes,
-                max_bytes: state.store.max_bytes(),
+                max_bytes: store.max_bytes(),
+                cache_hits: cache.hits,
+                cache_misses: cache.misses,
+                cache_items: cache.items,
+                cache_bytes: cache.bytes,
+                cache_max_bytes: cache.max_bytes,
+                own_bytes: usage.own_bytes,
+                own_trees: usage.own_trees,
+                own_quota_bytes: quotas.own_bytes,
+                others_bytes: usage.others_bytes,
+                others_trees: usage.others_trees,
+                others_quota_bytes: quotas.others_bytes,
+                logical_bytes,
+                dedup_ratio: if stored_bytes > 0 {
+                    logical_bytes as f64 / stored_bytes as f64
+                } else {
+                    1.0
+                },
             }
         }
 
@@ -844,17 +1568,308 @@ pub async fn worker_message(
             WorkerResponse::Void { id }
         }
 
+        WorkerRequest::SetCacheAdmission {
+            id,
+            enabled,
+            large_file_bytes,
+        } => {
+            let settings = AdmissionSettings {
+                enabled,
+                large_file_bytes,
+            };
+            match settings.save(&state.data_dir) {
+                Ok(()) => {
+                    if let Some(tree) = state.tree.read().await.as_ref() {
+                        tree.set_cache_admission(settings);
+                    }
+                    WorkerResponse::Void { id }
+                }
+                Err(error) => WorkerResponse::Error { id, error },
+            }
+        }
+
+        WorkerRequest::SetPacking { id, threshold } => {
+            let path = state.data_dir.join(PACKING_FILE);
+            match crate::atomic_file::write(&path, threshold.to_string()) {
+                Ok(()) => {
+                    if let Some(tree) = state.tree.read().await.as_ref() {
+                        tree.set_pack_threshold(threshold as usize);
+                    }
+                    WorkerResponse::Void { id }
+                }
+                Err(e) => WorkerResponse::Error {
+                    id,
+                    error: format!("Failed to save packing threshold: {}", e),
+                },
+            }
+        }
+
         WorkerRequest::RunEviction { id } => {
-            let bytes_freed = state.store.evict_if_needed().await;
+            let mut progress = ProgressReporter::new(&app_handle, &id);
+            progress.phase("evict", None, None);
+            let mut bytes_freed = 0;
+            match state.origins.trim_to_quotas() {
+                Ok(blocks) => {
+                    for (hash, size) in blocks {
+                        if !state.store.is_pinned(&hash) && state.store.delete(&hash).await {
+                            bytes_freed += size;
+                            progress.advance(1, size);
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to apply storage quotas: {}", e),
+            }
+            let lru_freed = state.blob_store().evict_if_needed().await;
+            bytes_freed += lru_freed;
+            progress.advance(0, lru_freed);
+            progress.finish();
             WorkerResponse::EvictionResult { id, bytes_freed }
         }
 
+        WorkerRequest::RunScrub { id } => {
+            let stats = state.store.stats();
+            let mut progress = ProgressReporter::new(&app_handle, &id);
+            progress.phase("verify", Some(stats.items), Some(stats.bytes));
+            let store = state.store.clone();
+            let scrubbed = tokio::task::spawn_blocking(move || {
+                let report = store.scrub(|size| progress.advance(1, size));
+                (report, progress)
+            })
+            .await;
+
+            match scrubbed {
+                Ok((Ok(report), mut progress)) => {
+                    if !report.corrupted.is_empty() {
+                        warn!("Scrub found {} corrupted blobs", report.corrupted.len());
+                    }
+                    if !report.unreadable.is_empty() {
+                        warn!("Scrub could not read {} blobs", report.unreadable.len());
+                    }
+                    progress.phase("refetch", Some(report.corrupted.len() as u64), None);
+                    let mut refetched = 0;
+                    if let Some(tree) = state.tree.read().await.as_ref() {
+                        for hash in &report.corrupted {
+                            if let Some(data) = tree.refetch_blob(hash).await {
+                                refetched += 1;
+                                progress.advance(1, data.len() as u64);
+                            } else {
+                                progress.advance(1, 0);
+                            }
+                        }
+                    }
+                    progress.finish();
+                    WorkerResponse::ScrubResult {
+                        id,
+                        checked: report.checked as u64,
+                        bytes: report.bytes,
+                        corrupted: report.corrupted.iter().map(hex::encode).collect(),
+                        refetched,
+                    }
+                }
+                Ok((Err(e), _)) => WorkerResponse::Error { id, error: e },
+                Err(e) => WorkerResponse::Error {
+                    id,
+                    error: format!("Scrub task failed: {}", e),
+                },
+            }
+        }
+
+        WorkerRequest::ExportState {
+            id,
+            path,
+            passphrase,
+        } => {
+            let settings = StateSettings {
+                pubkey: state.nostr.get_pubkey(),
+                relays: state.nostr.get_relays().await,
+                read_servers: state.blossom.read_servers(),
+                write_servers: state.blossom.write_servers(),
+            };
+            let secret_key = state.nostr.get_keys().map(|keys| keys.secret_key().clone());
+            if passphrase.is_some() && secret_key.is_none() {
+                warn!("No signing identity set, exporting state without a key");
+            }
+            let data_dir = state.data_dir.clone();
+            let (ndb, search) = (state.ndb.clone(), state.search.clone());
+            let history = {
+                use tauri::Manager;
+                app_handle
+                    .try_state::<Arc<crate::history::HistoryStore>>()
+                    .map(|history| history.inner().clone())
+            };
+            let mut progress = ProgressReporter::new(&app_handle, &id);
+            let exported = tokio::task::spawn_blocking(move || {
+                // Consistent copies of the open databases, archived in their place
+                let snapshots = data_dir.join("export-snapshot");
+                let _ = std::fs::remove_dir_all(&snapshots);
+                let export = || {
+                    progress.phase("snapshot", None, None);
+                    ndb_maintenance::snapshot(&ndb, &snapshots.join(ndb_maintenance::NDB_DIR))?;
+                    search.copy_to(&snapshots.join("search"))?;
+                    if let Some(history) = &history {
+                        history.copy_to(&snapshots.join("history"))?;
+                    }
+                    let identity = secret_key.as_ref().zip(passphrase.as_deref());
+                    backup::export_state(
+                        &data_dir,
+                        &snapshots,
+                        std::path::Path::new(&path),
+                        settings,
+                        identity,
+                        Some(&mut progress),
+                    )
+                };
+                let exported = export();
+                let _ = std::fs::remove_dir_all(&snapshots);
+                exported
+            })
+            .await;
+            match exported {
+                Ok(Ok(stats)) => WorkerResponse::StateExported {
+                    id,
+                    files: stats.files,
+                    bytes: stats.bytes,
+                },
+                Ok(Err(e)) => WorkerResponse::Error { id, error: e },
+                Err(e) => WorkerResponse::Error {
+                    id,
+                    error: format!("Export task failed: {}", e),
+                },
+            }
+        }
+
+        WorkerRequest::ImportState {
+            id,
+            path,
+            passphrase,
+        } => {
+            let data_dir = state.data_dir.clone();
+            let mut progress = ProgressReporter::new(&app_handle, &id);
+            let imported = tokio::task::spawn_blocking(move || {
+                backup::import_state(
+                    &data_dir,
+                    std::path::Path::new(&path),
+                    passphrase.as_deref(),
+                    Some(&mut progress),
+                )
+            })
+            .await;
+            match imported {
+                Ok(Ok(imported)) => {
+                    // Connections switch now; the files take over on restart
+                    let settings = imported.settings;
+                    if !settings.relays.is_empty() {
+                        if let Err(e) = state.nostr.set_relays(settings.relays.clone()).await {
+                            warn!("Failed to apply imported relays: {}", e);
+                        }
+                    }
+                    if let Err(e) = state.blossom.set_servers(
+                        settings.read_servers.clone(),
+                        settings.write_servers.clone(),
+                    ) {
+                        warn!("Failed to apply imported Blossom servers: {}", e);
+                    }
+                    WorkerResponse::StateImported {
+                        id,
+                        files: imported.stats.files,
+                        bytes: imported.stats.bytes,
+                        settings,
+                        nsec: imported.secret_key.map(|key| key.to_secret_hex()),
+                        restart_required: true,
+                    }
+                }
+                Ok(Err(e)) => WorkerResponse::Error { id, error: e },
+                Err(e) => WorkerResponse::Error {
+                    id,
+                    error: format!("Import task failed: {}", e),
+                },
+            }
+        }
+
+        WorkerRequest::SetQuota {
+            id,
+            own_bytes,
+            others_bytes,
+        } => match state.origins.set_quotas(Quotas {
+            own_bytes,
+            others_bytes,
+        }) {
+            Ok(()) => WorkerResponse::Void { id },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
+        // nostrdb maintenance
+        WorkerRequest::GetNdbStats { id } => {
+            let pending_purge = ndb_maintenance::pending_purge(&state.data_dir);
+            let rule = pending_purge.clone();
+            match ndb_usage(&state, rule).await {
+                Ok(usage) => WorkerResponse::NdbStats {
+                    id,
+                    bytes: usage.bytes,
+                    events: usage.events,
+                    kinds: usage.kinds,
+                    pending_purge,
+                    purgeable: usage.purgeable,
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::PurgeNdb {
+            id,
+            older_than,
+            kinds,
+        } => {
+            // Our own events, of every identity, are never purged
+            let rule = PurgeRule {
+                older_than,
+                kinds: kinds.unwrap_or_default(),
+                keep_authors: state
+                    .accounts
+                    .list()
+                    .into_iter()
+                    .map(|account| account.pubkey)
+                    .chain(state.nostr.get_pubkey())
+                    .collect(),
+            };
+            let scheduled = match ndb_maintenance::schedule_purge(&state.data_dir, &rule) {
+                Ok(()) => ndb_usage(&state, Some(rule)).await,
+                Err(e) => Err(e),
+            };
+            match scheduled {
+                Ok(usage) => WorkerResponse::NdbPurgeScheduled {
+                    id,
+                    events: usage.purgeable,
+                    restart_required: true,
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
         // Relay statistics
         WorkerRequest::GetRelayStats { id } => {
             let relays = state.nostr.get_relay_stats().await;
             WorkerResponse::RelayStats { id, relays }
         }
 
+        // Outbox of published events
+        WorkerRequest::GetOutbox { id } => WorkerResponse::Outbox {
+            id,
+            pending: state.outbox.pending(),
+            entries: state.outbox.entries(),
+        },
+        WorkerRequest::RetryOutbox { id, event_ids } => {
+            let now = nostr_sdk::Timestamp::now().as_u64();
+            match state.outbox.retry(event_ids.as_deref(), now) {
+                Ok(_) => WorkerResponse::Outbox {
+                    id,
+                    pending: state.outbox.pending(),
+                    entries: state.outbox.entries(),
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
         // Blossom server configuration
         WorkerRequest::SetBlossomServers {
             id,
@@ -862,10 +1877,18 @@ pub async fn worker_message(
             write_servers,
         } => {
             // Update blossom manager
-            let result = state.blossom.set_servers(read_servers.clone(), write_servers);
+            let result = state
+                .blossom
+                .set_servers(read_servers.clone(), write_servers.clone());
 
             // Also update tree's combined store for remote blob fetching
             if result.is_ok() {
+                if let Err(e) = state
+                    .accounts
+                    .set_servers(read_servers.clone(), write_servers.clone())
+                {
+                    warn!("Failed to remember account servers: {}", e);
+                }
                 if let Some(tree) = state.tree.read().await.as_ref() {
                     tree.set_blossom_servers(read_servers).await;
                 }
@@ -883,34 +1906,50 @@ pub async fn worker_message(
             write_servers: state.blossom.write_servers(),
         },
 
-        // Tree push to Blossom
+        // Network proxy
+        WorkerRequest::SetProxy { id, proxy } => match crate::proxy::set_proxy(proxy) {
+            Ok(()) => {
+                state.blossom.apply_proxy();
+                if let Some(tree) = state.tree.read().await.as_ref() {
+                    tree.apply_proxy().await;
+                }
+                state.nostr.reconnect_relays().await;
+                crate::htree::apply_proxy().await;
+                if crate::proxy::is_proxied() {
+                    state.webrtc.shutdown().await;
+                } else if !state.sync.is_paused() {
+                    state.start_webrtc();
+                }
+                WorkerResponse::Void { id }
+            }
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
+        WorkerRequest::GetProxy { id } => WorkerResponse::Proxy {
+            id,
+            proxy: crate::proxy::proxy_config(),
+        },
+
+        // Tree push to Blossom
         WorkerRequest::PushToBlossom { id, cid, tree_name } => {
             let tree_guard = state.tree.read().await;
             let tree = match tree_guard.as_ref() {
                 Some(t) => t,
                 None => {
-                    return app_handle
-                        .emit(
-                            "worker_response",
-                            &WorkerResponse::Error {
-                                id,
-                                error: "Tree not initialized".to_string(),
-                            },
-                        )
-                        .map_err(|e| format!("Failed to emit response: {}", e));
+                    return Ok(WorkerResponse::Error {
+                        id,
+                        error: "Tree not initialized".to_string(),
+                    });
                 }
             };
 
             // Walk all blocks in the tree
+            let mut progress = ProgressReporter::new(&app_handle, &id);
+            progress.phase("walk", None, None);
             let blocks = match tree.walk_blocks(&cid).await {
                 Ok(b) => b,
                 Err(e) => {
-                    return app_handle
-                        .emit(
-                            "worker_response",
-                            &WorkerResponse::Error { id, error: e },
-                        )
-                        .map_err(|e| format!("Failed to emit response: {}", e));
+                    return Ok(WorkerResponse::Error { id, error: e });
                 }
             };
 
@@ -920,6 +1959,8 @@ pub async fn worker_message(
             let mut skipped: u32 = 0;
             let mut failed: u32 = 0;
             let mut errors: Vec<String> = Vec::new();
+            let total_bytes: u64 = blocks.iter().map(|b| b.data.len() as u64).sum();
+            progress.phase("upload", Some(blocks.len() as u64), Some(total_bytes));
 
             for (idx, block) in blocks.iter().enumerate() {
                 // Emit progress
@@ -957,7 +1998,9 @@ pub async fn worker_message(
                         }
                     }
                 }
+                progress.advance(1, block.data.len() as u64);
             }
+            progress.finish();
 
             WorkerResponse::PushResult {
                 id,
@@ -968,99 +2011,269 @@ pub async fn worker_message(
             }
         }
 
-        // Republish tree event to Nostr
-        WorkerRequest::RepublishTree { id, pubkey, tree_name } => {
-            let pk_bytes = match hex_to_pubkey(&pubkey) {
-                Ok(b) => b,
-                Err(e) => {
-                    return app_handle
-                        .emit("worker_response", &WorkerResponse::Bool { id, value: false })
-                        .map_err(|_| e);
-                }
-            };
-
-            // Query nostrdb synchronously, extract event JSON before any await
-            let found_event: Option<String> = {
-                let txn = match Transaction::new(&state.ndb) {
-                    Ok(t) => t,
+        // Sign and publish a tree root event
+        WorkerRequest::PublishTree {
+            id,
+            tree_name,
+            cid,
+            visibility,
+            link_secret,
+            sign,
+            message,
+            parent,
+        } => {
+            let link_secret = match (&visibility, link_secret) {
+                (TreeVisibility::LinkVisible, Some(hex)) => match hashtree_core::key_from_hex(&hex) {
+                    Ok(secret) => Some(secret),
                     Err(e) => {
-                        return app_handle
-                            .emit("worker_response", &WorkerResponse::Error {
-                                id,
-                                error: format!("Transaction error: {:?}", e),
-                            })
-                            .map_err(|e| format!("Failed to emit: {}", e));
+                        return Ok(WorkerResponse::Error {
+                            id,
+                            error: format!("Invalid link secret: {}", e),
+                        });
                     }
-                };
+                },
+                (TreeVisibility::LinkVisible, None) => Some(hashtree_core::generate_key()),
+                _ => None,
+            };
 
-                let filter = nostrdb::Filter::new()
-                    .kinds(vec![30078])
-                    .authors(vec![&pk_bytes])
-                    .build();
+            if let Err(e) = state
+                .nostr
+                .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+                .await
+            {
+                return Ok(WorkerResponse::Error { id, error: e });
+            }
 
-                let results = match state.ndb.query(&txn, &[filter], 100) {
-                    Ok(r) => r,
-                    Err(_) => {
-                        return app_handle
-                            .emit("worker_response", &WorkerResponse::Bool { id, value: false })
-                            .map_err(|e| format!("Failed to emit: {}", e));
+            let cid = if sign {
+                match sign_tree(&state, &cid).await {
+                    Ok(signed) => signed,
+                    Err(e) => {
+                        return Ok(WorkerResponse::Error { id, error: e });
                     }
-                };
+                }
+            } else {
+                cid
+            };
 
-                let mut found: Option<String> = None;
-                for result in results.iter() {
-                    let mut has_d_tag = false;
-                    let mut has_l_tag = false;
-                    let mut has_hash = false;
+            let previous = state.nostr.get_keys().and_then(|keys| {
+                tree_roots::cached_tree_root(
+                    &state.ndb,
+                    &keys.public_key().to_bytes(),
+                    &tree_name,
+                    Some(&keys),
+                )
+            });
+            let commit = match parent {
+                Some(parent) => tree_roots::Commit {
+                    message,
+                    parent: Some(parent).filter(|parent| *parent != cid.hash),
+                    author: None,
+                    collaborators: previous.map(|root| root.collaborators).unwrap_or_default(),
+                },
+                None => tree_roots::Commit::after(previous.as_ref(), &cid, message),
+            };
 
-                    for tag in result.note.tags() {
-                        if let Some(tag_str) = tag.get_unchecked(0).str() {
-                            if tag_str == "d" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    has_d_tag = val == tree_name;
-                                }
-                            } else if tag_str == "l" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    has_l_tag = val == "hashtree";
-                                }
-                            } else if tag_str == "hash" {
-                                if let Some(val) = tag.get_unchecked(1).str() {
-                                    has_hash = !val.is_empty();
-                                }
-                            }
+            match state
+                .nostr
+                .publish_tree_root(&tree_name, &cid, &visibility, link_secret.as_ref(), &commit)
+                .await
+            {
+                Ok(event_id) => {
+                    info!("Published tree root: {} ({:?})", tree_name, visibility);
+                    if visibility == TreeVisibility::Private {
+                        rewrap_shares(&state, &tree_name, &cid).await;
+                    }
+                    if let Some(owner) = state.nostr.get_pubkey() {
+                        tag_tree_origin(&state, &owner, &tree_name, Origin::Own, &cid).await;
+                        if let Err(e) = state.accounts.add_tree(&owner, &tree_name) {
+                            warn!("Failed to add {} to the account's trees: {}", tree_name, e);
                         }
                     }
+                    WorkerResponse::Published {
+                        id,
+                        cid,
+                        event_id: event_id.to_hex(),
+                        link_secret: link_secret.map(|s| hashtree_core::to_hex(&s)),
+                    }
+                }
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
 
-                    if has_d_tag && has_l_tag && has_hash {
-                        if let Ok(json) = result.note.json() {
-                            if found.is_none() {
-                                found = Some(json);
-                            }
-                        }
+        WorkerRequest::AddToInbox { id, items } => {
+            match add_to_inbox(&state, &app_handle, &items).await {
+                Ok((cid, event_id)) => {
+                    info!("Added {} shared items to the inbox", items.len());
+                    WorkerResponse::Published {
+                        id,
+                        cid,
+                        event_id,
+                        link_secret: None,
                     }
                 }
-                found
-                // txn, results, filter all dropped here
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::GrantAccess {
+            id,
+            tree_name,
+            cid,
+            recipient,
+        } => match share_access(&state, &app_handle, &tree_name, &recipient, Some(&cid)).await {
+            Ok(()) => WorkerResponse::Bool { id, value: true },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
+        WorkerRequest::RevokeAccess {
+            id,
+            tree_name,
+            recipient,
+        } => match share_access(&state, &app_handle, &tree_name, &recipient, None).await {
+            Ok(()) => WorkerResponse::Bool { id, value: true },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
+        WorkerRequest::ListGrants { id, tree_name } => {
+            let recipients = match state.nostr.get_pubkey() {
+                Some(owner) => state.shares.recipients(&owner, &tree_name),
+                None => Vec::new(),
             };
+            WorkerResponse::Grants { id, recipients }
+        }
 
-            match found_event {
-                Some(event_json) => {
-                    match serde_json::from_str::<serde_json::Value>(&event_json) {
-                        Ok(event_value) => {
-                            match state.nostr.publish(event_value).await {
-                                Ok(_) => {
-                                    info!("Republished tree event: {}", tree_name);
-                                    WorkerResponse::Bool { id, value: true }
-                                }
-                                Err(e) => {
-                                    debug!("Failed to republish: {}", e);
-                                    WorkerResponse::Bool { id, value: false }
-                                }
-                            }
-                        }
-                        Err(_) => WorkerResponse::Bool { id, value: false }
+        WorkerRequest::RotateTreeKey { id, npub, tree } => {
+            match rotate_tree_key(&state, &app_handle, &npub, &tree).await {
+                Ok((cid, event_id, link_secret)) => {
+                    info!("Rotated key of tree {}", tree);
+                    WorkerResponse::Rotated {
+                        id,
+                        cid,
+                        event_id,
+                        link_secret,
+                    }
+                }
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::SetCollaborators {
+            id,
+            tree_name,
+            collaborators,
+        } => match set_collaborators(&state, &app_handle, &tree_name, &collaborators).await {
+            Ok((cid, event_id, link_secret)) => WorkerResponse::Published {
+                id,
+                cid,
+                event_id,
+                link_secret,
+            },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
+        WorkerRequest::ProposeRoot {
+            id,
+            owner,
+            tree_name,
+            cid,
+            base,
+            message,
+        } => {
+            match propose_root(
+                &state,
+                &app_handle,
+                &owner,
+                &tree_name,
+                &cid,
+                base,
+                message.as_deref(),
+            )
+            .await
+            {
+                Ok(event_id) => {
+                    info!("Proposed a new root for {}", tree_name);
+                    WorkerResponse::Published {
+                        id,
+                        cid,
+                        event_id,
+                        link_secret: None,
                     }
                 }
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::ListProposals { id, tree_name } => {
+            match list_proposals(&state, &app_handle, tree_name.as_deref()).await {
+                Ok(proposals) => WorkerResponse::Proposals { id, proposals },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::DiffProposal { id, event_id } => {
+            match diff_proposal(&state, &app_handle, &event_id).await {
+                Ok(changes) => WorkerResponse::TreeDiff { id, changes },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::MergeProposal { id, event_id } => {
+            match merge_proposal(&state, &app_handle, &event_id).await {
+                Ok((cid, event_id, link_secret)) => WorkerResponse::Published {
+                    id,
+                    cid,
+                    event_id,
+                    link_secret,
+                },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::DismissProposal { id, event_id } => {
+            match state.proposals.mark_handled(&event_id) {
+                Ok(()) => WorkerResponse::Bool { id, value: true },
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
+        WorkerRequest::CreateShareLink { id, cid, path } => {
+            let tree_guard = state.tree.read().await;
+            if let Some(tree) = tree_guard.as_ref() {
+                match tree.share_link(&cid, path.as_deref()).await {
+                    Ok((nhash, url)) => WorkerResponse::ShareLink { id, nhash, url },
+                    Err(e) => WorkerResponse::Error { id, error: e },
+                }
+            } else {
+                WorkerResponse::Error {
+                    id,
+                    error: "Tree not initialized".to_string(),
+                }
+            }
+        }
+
+        // Republish tree event to Nostr
+        WorkerRequest::RepublishTree { id, pubkey, tree_name } => {
+            let pk_bytes = match hex_to_pubkey(&pubkey) {
+                Ok(b) => b,
+                Err(_) => {
+                    return Ok(WorkerResponse::Bool { id, value: false });
+                }
+            };
+
+            match tree_roots::cached_tree_root(&state.ndb, &pk_bytes, &tree_name, None) {
+                Some(root) => match serde_json::to_value(&root.event) {
+                    Ok(event_value) => match state.nostr.publish(event_value).await {
+                        Ok(_) => {
+                            info!("Republished tree event: {}", tree_name);
+                            WorkerResponse::Bool { id, value: true }
+                        }
+                        Err(e) => {
+                            debug!("Failed to republish: {}", e);
+                            WorkerResponse::Bool { id, value: false }
+                        }
+                    },
+                    Err(_) => WorkerResponse::Bool { id, value: false },
+                },
                 None => {
                     debug!("No cached event found for tree: {}", tree_name);
                     WorkerResponse::Bool { id, value: false }
@@ -1075,12 +2288,10 @@ pub async fn worker_message(
                 let txn = match Transaction::new(&state.ndb) {
                     Ok(t) => t,
                     Err(e) => {
-                        return app_handle
-                            .emit("worker_response", &WorkerResponse::Error {
-                                id,
-                                error: format!("Transaction error: {:?}", e),
-                            })
-                            .map_err(|e| format!("Failed to emit: {}", e));
+                        return Ok(WorkerResponse::Error {
+                            id,
+                            error: format!("Transaction error: {:?}", e),
+                        });
                     }
                 };
 
@@ -1091,13 +2302,11 @@ pub async fn worker_message(
                 let results = match state.ndb.query(&txn, &[filter], 1000) {
                     Ok(r) => r,
                     Err(_) => {
-                        return app_handle
-                            .emit("worker_response", &WorkerResponse::RepublishResult {
-                                id,
-                                count: 0,
-                                encryption_errors: None,
-                            })
-                            .map_err(|e| format!("Failed to emit: {}", e));
+                        return Ok(WorkerResponse::RepublishResult {
+                            id,
+                            count: 0,
+                            encryption_errors: None,
+                        });
                     }
                 };
 
@@ -1141,6 +2350,13 @@ pub async fn worker_message(
             };
 
             // Republish all found events
+            let mut progress = ProgressReporter::new(&app_handle, &id);
+            let total_bytes: u64 = events_to_republish.iter().map(|e| e.len() as u64).sum();
+            progress.phase(
+                "republish",
+                Some(events_to_republish.len() as u64),
+                Some(total_bytes),
+            );
             let mut count = 0u32;
             for event_json in events_to_republish {
                 if let Ok(event_value) = serde_json::from_str::<serde_json::Value>(&event_json) {
@@ -1148,7 +2364,9 @@ pub async fn worker_message(
                         count += 1;
                     }
                 }
+                progress.advance(1, event_json.len() as u64);
             }
+            progress.finish();
 
             info!("Republished {} tree events", count);
             WorkerResponse::RepublishResult {
@@ -1158,16 +2376,39 @@ pub async fn worker_message(
             }
         }
 
+        WorkerRequest::DeleteTree {
+            id,
+            tree_name,
+            delete_blobs,
+        } => match delete_tree(&state, &app_handle, &tree_name, delete_blobs).await {
+            Ok((event_id, released, blobs_deleted)) => {
+                info!(
+                    "Deleted tree {}: {} blocks released, {} deleted from Blossom",
+                    tree_name, released, blobs_deleted
+                );
+                WorkerResponse::TreeDeleted {
+                    id,
+                    event_id,
+                    released,
+                    blobs_deleted,
+                }
+            }
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
         // Streaming file read
         WorkerRequest::ReadFileStream { id, cid } => {
             let tree_guard = state.tree.read().await;
             if let Some(tree) = tree_guard.as_ref() {
                 // Read file in chunks and emit
+                let mut progress = ProgressReporter::new(&app_handle, &id);
+                progress.phase("read", None, None);
                 match tree.read_file(&cid).await {
                     Ok(data) => {
                         const CHUNK_SIZE: usize = 256 * 1024; // 256KB chunks
                         let chunks: Vec<&[u8]> = data.chunks(CHUNK_SIZE).collect();
                         let total = chunks.len();
+                        progress.phase("export", Some(total as u64), Some(data.len() as u64));
 
                         for (i, chunk) in chunks.into_iter().enumerate() {
                             let is_last = i == total - 1;
@@ -1179,6 +2420,7 @@ pub async fn worker_message(
                                     done: is_last,
                                 },
                             );
+                            progress.advance(1, chunk.len() as u64);
                         }
 
                         // Return void since we already emitted chunks
@@ -1197,6 +2439,24 @@ pub async fn worker_message(
             }
         }
 
+        WorkerRequest::DownloadToDisk { id, cid, dest_path } => {
+            let dest = PathBuf::from(&dest_path);
+            match state.download_to_disk(&app_handle, &id, &cid, &dest).await {
+                Ok(stats) => {
+                    info!(
+                        "Downloaded {} files ({} bytes) to {}",
+                        stats.files, stats.bytes, dest_path
+                    );
+                    WorkerResponse::Downloaded {
+                        id,
+                        files: stats.files as u64,
+                        bytes: stats.bytes,
+                    }
+                }
+                Err(e) => WorkerResponse::Error { id, error: e },
+            }
+        }
+
         // WebRTC operations
         WorkerRequest::GetPeerStats { id } => {
             let stats = state.webrtc.get_peer_stats().await;
@@ -1234,11 +2494,1438 @@ pub async fn worker_message(
             ).await;
             WorkerResponse::Void { id }
         }
+
+        // Tree content search
+        WorkerRequest::IndexTree {
+            id,
+            npub,
+            tree_name,
+            cid,
+        } => {
+            let owner = shares::parse_pubkey(&npub)
+                .map(|pk| pk.to_hex())
+                .unwrap_or_else(|_| npub.clone());
+            let origin = if state.nostr.get_pubkey().as_deref() == Some(owner.as_str()) {
+                Origin::Own
+            } else {
+                Origin::Others
+            };
+            // Tagging and indexing fetch the whole tree, which the WoT
+            // policy may not allow for this owner
+            let prefetch = origin == Origin::Own || state.wot.may_prefetch(&owner);
+            if prefetch {
+                tag_tree_origin(&state, &owner, &tree_name, origin, &cid).await;
+            }
+
+            let tree_guard = state.tree.read().await;
+            if state.is_guest() || !prefetch {
+                // Trees opened as a guest leave nothing in the search index,
+                // nor do trees the WoT policy doesn't prefetch
+                WorkerResponse::IndexResult { id, count: 0 }
+            } else if let Some(tree) = tree_guard.as_ref() {
+                let mut progress = ProgressReporter::new(&app_handle, &id);
+                match state
+                    .search
+                    .index_tree(tree, &npub, &tree_name, &cid, Some(&mut progress))
+                    .await
+                {
+                    Ok(count) => {
+                        debug!("Indexed {} entries of {}/{}", count, npub, tree_name);
+                        WorkerResponse::IndexResult {
+                            id,
+                            count: count as u32,
+                        }
+                    }
+                    Err(e) => WorkerResponse::Error { id, error: e },
+                }
+            } else {
+                WorkerResponse::Error {
+                    id,
+                    error: "Tree not initialized".to_string(),
+                }
+            }
+        }
+
+        WorkerRequest::SearchTrees {
+            id,
+            query,
+            npub,
+            limit,
+        } => match state.search.search(&query, npub.as_deref(), limit.unwrap_or(50)) {
+            Ok(hits) => WorkerResponse::SearchResults { id, hits },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
+
+        WorkerRequest::QueryMedia {
+            id,
+            filter,
+            sort,
+            limit,
+        } => match state.search.query_media(&filter, sort, limit.unwrap_or(500)) {
+            Ok(items) => WorkerResponse::MediaItems { id, items },
+            Err(e) => WorkerResponse::Error { id, error: e },
+        },
     };
 
-    app_handle
-        .emit("worker_response", &response)
-        .map_err(|e| format!("Failed to emit response: {}", e))
+    if let Some(event) = notify::sync_event(kind, &response) {
+        state.notifier.notify(&event);
+    }
+
+    Ok(response)
+}
+
+/// Error returned by `worker_blob` when a `get` finds no blob
+pub const BLOB_NOT_FOUND: &str = "Blob not found";
+
+/// Handle blob reads over the binary IPC channel
+///
+/// The bytes reach the frontend as an `ArrayBuffer` instead of a base64
+/// string in a `worker_response` event, saving the encoding overhead and the
+/// garbage it leaves behind. Metadata requests stay on `worker_message`.
+#[tauri::command]
+pub async fn worker_blob(
+    request: BlobRequest,
+    state: tauri::State<'_, std::sync::Arc<WorkerState>>,
+) -> Result<tauri::ipc::Response, String> {
+    read_blob(&state, request)
+        .await?
+        .map(tauri::ipc::Response::new)
+        .ok_or_else(|| BLOB_NOT_FOUND.to_string())
+}
+
+/// Import files dropped on the window from their native paths into
+/// directory `dir_path` of `parent_cid` (a new directory without one),
+/// returning the new root
+///
+/// The files are read from disk in chunks instead of being sent as base64
+/// `writeFile` requests. It runs in the background class like other bulk
+/// work, and progress is reported under `id`.
+#[tauri::command]
+pub async fn import_dropped_files(
+    id: String,
+    paths: Vec<String>,
+    parent_cid: Option<WorkerCid>,
+    dir_path: Option<String>,
+    app_handle: AppHandle,
+    state: tauri::State<'_, std::sync::Arc<WorkerState>>,
+) -> Result<WorkerCid, String> {
+    let _permit = state
+        .scheduler
+        .acquire(&id, "importDroppedFiles", Priority::Background)
+        .await;
+    let tree = state
+        .tree
+        .read()
+        .await
+        .as_ref()
+        .map(TreeManager::detached)
+        .ok_or("Tree not initialized")?;
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    let mut progress = ProgressReporter::new(&app_handle, &id);
+    let root = ingest::import_paths(
+        &tree,
+        &paths,
+        parent_cid.as_ref(),
+        dir_path.as_deref().unwrap_or(""),
+        Some(&mut progress),
+    )
+    .await?;
+    info!("Imported {} dropped paths", paths.len());
+    Ok(root)
+}
+
+/// Read blob or file bytes; `None` only for a missing `get`
+async fn read_blob(state: &WorkerState, request: BlobRequest) -> Result<Option<Vec<u8>>, String> {
+    let tree_guard = state.tree.read().await;
+    match (request, tree_guard.as_ref()) {
+        // Use CombinedStore (with Blossom fallback) via TreeManager if available
+        (BlobRequest::Get { hash }, Some(tree)) => Ok(tree.get_blob(&hash).await),
+        (BlobRequest::Get { hash }, None) => Ok(state.blob_store().get(&hash).await),
+        (BlobRequest::ReadFile { cid }, Some(tree)) => {
+            admitted_read(state, tree, &cid, tree.read_file(&cid))
+                .await
+                .map(Some)
+        }
+        (BlobRequest::ReadFileRange { cid, start, end }, Some(tree)) => {
+            admitted_read(state, tree, &cid, tree.read_file_range(&cid, start, end))
+                .await
+                .map(Some)
+        }
+        (_, None) => Err("Tree not initialized".to_string()),
+    }
+}
+
+/// Run `read` of file `cid`, through cache admission unless the file belongs
+/// to a tree we keep, tagged or pinned, whose blocks always go to disk
+async fn admitted_read<F: Future>(
+    state: &WorkerState,
+    tree: &TreeManager,
+    cid: &WorkerCid,
+    read: F,
+) -> F::Output {
+    if state.origins.is_referenced(&cid.hash) || state.blob_store().is_pinned(&cid.hash) {
+        read.await
+    } else {
+        tree.admit_read(cid, read).await
+    }
+}
+
+/// Publish a share (or revocation, when `cid` is None) of one of our private
+/// trees to `recipient` and remember the grant for later republishes
+async fn share_access(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    tree_name: &str,
+    recipient: &str,
+    cid: Option<&WorkerCid>,
+) -> Result<(), String> {
+    let owner = state.nostr.get_pubkey().ok_or("No signing identity set")?;
+    let recipient = shares::parse_pubkey(recipient)?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+    shares::publish_share(&state.nostr, tree_name, &recipient, cid).await?;
+
+    let recipient_hex = recipient.to_hex();
+    if cid.is_some() {
+        state.shares.grant(&owner, tree_name, &recipient_hex)
+    } else {
+        state.shares.revoke(&owner, tree_name, &recipient_hex)
+    }
+}
+
+/// Re-wrap a freshly published private root for everyone it's shared with
+async fn rewrap_shares(state: &WorkerState, tree_name: &str, cid: &WorkerCid) {
+    let Some(owner) = state.nostr.get_pubkey() else {
+        return;
+    };
+    for recipient in state.shares.recipients(&owner, tree_name) {
+        let result = match shares::parse_pubkey(&recipient) {
+            Ok(pk) => shares::publish_share(&state.nostr, tree_name, &pk, Some(cid)).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Failed to re-share {} with {}: {}", tree_name, recipient, e);
+        }
+    }
+}
+
+/// Make a remembered account the active identity: its keys sign Nostr
+/// events, Blossom requests and WebRTC signaling, and its pubkey becomes the
+/// social graph root
+async fn switch_account(state: &WorkerState, pubkey: &str) -> Result<(), String> {
+    let account = state.accounts.switch(pubkey)?;
+    set_signing_keys(state, account.keys).await?;
+    // Accounts without their own servers use the defaults set_keys picked
+    if !account.read_servers.is_empty() || !account.write_servers.is_empty() {
+        state
+            .blossom
+            .set_servers(account.read_servers.clone(), account.write_servers.clone())?;
+        if let Some(tree) = state.tree.read().await.as_ref() {
+            tree.set_blossom_servers(account.read_servers.clone()).await;
+        }
+    }
+
+    *state.our_pubkey.write() = Some(account.pubkey.clone());
+    state.mutes.set_account(Some(&account.pubkey));
+    let pk_bytes = hex_to_pubkey(&account.pubkey)?;
+    nostrdb::socialgraph::set_root(&state.ndb, &pk_bytes);
+    info!("Switched to account {}", &account.pubkey[..8]);
+    Ok(())
+}
+
+/// Sign Nostr events, Blossom requests and WebRTC signaling with `keys`, or
+/// stop signing when None
+async fn set_signing_keys(
+    state: &WorkerState,
+    keys: Option<nostr_sdk::Keys>,
+) -> Result<(), String> {
+    match &keys {
+        Some(keys) => {
+            let secret = keys.secret_key().to_secret_hex();
+            state
+                .nostr
+                .set_identity(&keys.public_key().to_hex(), Some(&secret))?;
+            state.blossom.set_keys(keys.clone());
+        }
+        None => {
+            state.nostr.clear_identity();
+            state.blossom.clear_keys();
+        }
+    }
+
+    state.notifier.watch(&state.nostr, state.ndb.clone()).await;
+    watch_activity(state).await;
+
+    // Signaling is bound to the keys it started with; while sync is paused
+    // it stays down and resuming starts it with the new ones
+    state.webrtc.shutdown().await;
+    if state.sync.is_paused() {
+        return Ok(());
+    }
+    if let (Some(client), Some(keys)) = (state.nostr.get_client(), keys) {
+        let webrtc = state.webrtc.clone();
+        tokio::spawn(async move {
+            if let Err(e) = webrtc.init(client, keys).await {
+                warn!("Failed to initialize WebRTC: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Packing threshold set with setPacking, kept across restarts
+const PACKING_FILE: &str = "packing.json";
+
+/// The saved packing threshold, 0 (off) if none was set
+fn load_pack_threshold(data_dir: &Path) -> usize {
+    std::fs::read(data_dir.join(PACKING_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or(0)
+}
+
+/// Point blob reads and writes at `store`, keeping the Blossom servers, the
+/// packing threshold and the cache admission settings
+async fn use_blob_store(state: &WorkerState, store: Arc<BlobStore>) {
+    let tree = TreeManager::new(store);
+    tree.set_pack_threshold(load_pack_threshold(&state.data_dir));
+    tree.set_cache_admission(AdmissionSettings::load(&state.data_dir));
+    let read_servers = state.blossom.read_servers();
+    if !read_servers.is_empty() {
+        tree.set_blossom_servers(read_servers).await;
+    }
+    *state.tree.write().await = Some(tree);
+}
+
+/// Start a guest session: sign with ephemeral keys and keep blobs, history
+/// and permission decisions out of the persistent stores
+async fn enter_guest_mode(state: &WorkerState) -> Result<(), String> {
+    if state.is_guest() {
+        return Ok(());
+    }
+    let session = Arc::new(GuestSession::new(&state.data_dir)?);
+    let servers = (state.blossom.read_servers(), state.blossom.write_servers());
+    *state.guest.write() = Some(session.clone());
+
+    set_signing_keys(state, Some(session.keys.clone())).await?;
+    if !servers.0.is_empty() || !servers.1.is_empty() {
+        state.blossom.set_servers(servers.0, servers.1)?;
+    }
+    use_blob_store(state, session.store.clone()).await;
+    crate::htree::set_guest_dir(Some(session.dir()));
+    if let Some(nip07) = crate::nip07::get_nip07_state() {
+        nip07.permissions.begin_guest().await;
+    }
+    info!("Guest mode on");
+    Ok(())
+}
+
+/// End the guest session, wipe its data and go back to the active account
+async fn leave_guest_mode(state: &WorkerState) -> Result<(), String> {
+    let Some(session) = state.guest.write().take() else {
+        return Ok(());
+    };
+    let servers = (state.blossom.read_servers(), state.blossom.write_servers());
+    let active = state
+        .accounts
+        .list()
+        .into_iter()
+        .find(|account| account.active);
+    let keys = match active {
+        Some(account) => state.accounts.switch(&account.pubkey)?.keys,
+        None => None,
+    };
+    set_signing_keys(state, keys).await?;
+    if !servers.0.is_empty() || !servers.1.is_empty() {
+        state.blossom.set_servers(servers.0, servers.1)?;
+    }
+    use_blob_store(state, state.store.clone()).await;
+    crate::htree::set_guest_dir(None);
+    if let Some(nip07) = crate::nip07::get_nip07_state() {
+        nip07.permissions.end_guest().await;
+    }
+    // The session's dir goes with its last reference
+    drop(session);
+    info!("Guest mode off");
+    Ok(())
+}
+
+/// Tag the blocks of a tree root with their origin, for per-origin quotas
+async fn tag_tree_origin(
+    state: &WorkerState,
+    owner: &str,
+    tree_name: &str,
+    origin: Origin,
+    cid: &WorkerCid,
+) {
+    // Guest blobs are wiped with the session, so aren't counted
+    if state.is_guest() || state.origins.is_tagged(owner, tree_name, &cid.hash) {
+        return;
+    }
+    let blocks = {
+        let tree = state.tree.read().await;
+        let Some(tree) = tree.as_ref() else {
+            return;
+        };
+        match tree.walk_blocks(cid).await {
+            Ok(blocks) => blocks,
+            Err(e) => {
+                warn!("Failed to walk {}/{} for tagging: {}", owner, tree_name, e);
+                return;
+            }
+        }
+    };
+    let blocks = blocks
+        .into_iter()
+        .map(|block| (hex::encode(block.hash), block.data.len() as u64));
+    if let Err(e) = state
+        .origins
+        .tag_tree(owner, tree_name, origin, &cid.hash, blocks)
+    {
+        warn!("Failed to tag {}/{}: {}", owner, tree_name, e);
+    }
+}
+
+/// Embed a manifest signed with our key into the tree rooted at `cid`
+async fn sign_tree(state: &WorkerState, cid: &WorkerCid) -> Result<WorkerCid, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let tree = state.tree.read().await;
+    tree.as_ref()
+        .ok_or("Tree not initialized")?
+        .embed_manifest(cid, &keys)
+        .await
+}
+
+/// Latest root we published for one of our trees, with its visibility and
+/// link secret (see `nostr::read_own_tree_root`). None if the relays have
+/// no root for it.
+async fn own_tree_root(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    keys: &nostr_sdk::Keys,
+    tree_name: &str,
+) -> Result<Option<(WorkerCid, TreeVisibility, Option<[u8; 32]>)>, String> {
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+
+    let filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::from(30078u16))
+        .author(keys.public_key())
+        .custom_tag(nostr_sdk::SingleLetterTag::from_char('d').unwrap(), vec![tree_name.to_string()])
+        .custom_tag(nostr_sdk::SingleLetterTag::from_char('l').unwrap(), vec!["hashtree".to_string()]);
+    let events = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        state.nostr.fetch_events(vec![filter]),
+    )
+    .await
+    .map_err(|_| format!("Timed out resolving {}", tree_name))??;
+    let Some(latest) = events.iter().max_by_key(|e| e.created_at) else {
+        return Ok(None);
+    };
+    nostr::read_own_tree_root(keys, latest)
+        .map(Some)
+        .ok_or_else(|| "Can't recover the tree's current key".to_string())
+}
+
+/// Fetch `author`'s tree roots from the relays, only `tree_name`'s if
+/// given, and cache them in nostrdb. Returns the newest root of each tree.
+async fn fetch_tree_roots(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    author: &nostr_sdk::PublicKey,
+    tree_name: Option<&str>,
+) -> Result<Vec<TreeRoot>, String> {
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+
+    let mut filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::from(tree_roots::KIND_TREE_ROOT))
+        .author(*author)
+        .custom_tag(
+            nostr_sdk::SingleLetterTag::from_char('l').unwrap(),
+            vec!["hashtree".to_string()],
+        );
+    if let Some(tree_name) = tree_name {
+        filter = filter.custom_tag(
+            nostr_sdk::SingleLetterTag::from_char('d').unwrap(),
+            vec![tree_name.to_string()],
+        );
+    }
+    let events = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        state.nostr.fetch_events(vec![filter]),
+    )
+    .await
+    .map_err(|_| "Timed out fetching tree roots".to_string())??;
+
+    for event in &events {
+        let event_json = serde_json::to_string(event).unwrap_or_default();
+        let relay_msg = format!(r#"["EVENT","tree-roots",{}]"#, event_json);
+        let _ = state.ndb.process_event(&relay_msg);
+    }
+    Ok(tree_roots::newest_roots(&events, state.nostr.get_keys().as_ref()))
+}
+
+/// Unpublish our tree `tree_name` and release the blocks no other tree of
+/// ours uses, deleting them from Blossom too if `delete_blobs`. Returns the
+/// deletion's id and how many blocks were released and deleted.
+async fn delete_tree(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    tree_name: &str,
+    delete_blobs: bool,
+) -> Result<(String, usize, usize), String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let owner = keys.public_key();
+    let cached =
+        tree_roots::cached_tree_root(&state.ndb, &owner.to_bytes(), tree_name, Some(&keys));
+    let root = match cached {
+        Some(root) => root,
+        None => fetch_tree_roots(state, app_handle, &owner, Some(tree_name))
+            .await?
+            .into_iter()
+            .find(|root| root.name == tree_name)
+            .ok_or_else(|| format!("No tree named {}", tree_name))?,
+    };
+
+    // Walks take a while on large trees, so they don't hold the tree lock
+    let tree = state
+        .tree
+        .read()
+        .await
+        .as_ref()
+        .map(TreeManager::detached)
+        .ok_or("Tree not initialized")?;
+    // The tree's blocks are walked while it's still readable, and the
+    // blocks our other trees use are kept
+    let walked = tree.walk_blocks(&root.cid).await.unwrap_or_else(|e| {
+        warn!("Failed to walk {} for deletion: {}", tree_name, e);
+        Vec::new()
+    });
+    let live = match live_blocks(state, &tree, &keys, tree_name).await {
+        Ok(live) => Some(live),
+        Err(e) => {
+            warn!("Keeping the blocks of {}: {}", tree_name, e);
+            None
+        }
+    };
+    let event_id = state
+        .nostr
+        .unpublish_tree(tree_name, Some(root.event.id))
+        .await?;
+
+    let owner = owner.to_hex();
+    if let Err(e) = state.accounts.remove_tree(&owner, tree_name) {
+        warn!("Failed to remove {} from the account's trees: {}", tree_name, e);
+    }
+    let untagged = state.origins.untag_tree(&owner, tree_name)?;
+    // Without knowing what the other trees use, nothing is released
+    let Some(live) = live else {
+        return Ok((event_id.to_hex(), 0, 0));
+    };
+    let mut released: BTreeSet<String> = untagged.into_iter().map(|(hash, _)| hash).collect();
+    released.extend(walked.into_iter().map(|block| hex::encode(block.hash)));
+    released.retain(|hash| !live.contains(hash) && !state.origins.is_referenced(hash));
+
+    for hash in &released {
+        if state.store.is_pinned(hash) {
+            if let Err(e) = state.store.unpin(hash).await {
+                debug!("Failed to unpin {}: {}", hash, e);
+            }
+        }
+    }
+    let mut blobs_deleted = 0;
+    if delete_blobs {
+        for hash in &released {
+            match state.blossom.delete(hash).await {
+                Ok(_) => blobs_deleted += 1,
+                Err(e) => debug!("Failed to delete {} from Blossom: {}", hash, e),
+            }
+        }
+    }
+    Ok((event_id.to_hex(), released.len(), blobs_deleted))
+}
+
+/// Blocks (hex) of our trees other than `tree_name`, found by walking each
+/// one's newest cached root
+async fn live_blocks(
+    state: &WorkerState,
+    tree: &TreeManager,
+    keys: &nostr_sdk::Keys,
+    tree_name: &str,
+) -> Result<HashSet<String>, String> {
+    let roots =
+        tree_roots::cached_tree_roots(&state.ndb, &keys.public_key().to_bytes(), Some(keys))?;
+    let mut live = HashSet::new();
+    for root in roots.iter().filter(|root| root.name != tree_name) {
+        let blocks = tree
+            .walk_blocks(&root.cid)
+            .await
+            .map_err(|e| format!("Failed to walk {}: {}", root.name, e))?;
+        live.extend(blocks.into_iter().map(|block| hex::encode(block.hash)));
+    }
+    Ok(live)
+}
+
+/// Add shared items to our inbox tree and publish its new root. Returns
+/// the root and the event id.
+async fn add_to_inbox(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    items: &[inbox::SharedItem],
+) -> Result<(WorkerCid, String), String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    // Fails rather than starting a new inbox when the relays can't be asked
+    let current = own_tree_root(state, app_handle, &keys, inbox::INBOX_TREE).await?;
+    let root = {
+        let tree = state.tree.read().await;
+        let tree = tree.as_ref().ok_or("Tree not initialized")?;
+        inbox::add_items(tree, current.as_ref().map(|(cid, _, _)| cid), items).await?
+    };
+
+    let commit = tree_roots::Commit {
+        message: Some(format!("Added {} shared items", items.len())),
+        parent: current.as_ref().map(|(cid, _, _)| cid.hash.clone()),
+        ..Default::default()
+    };
+    let (visibility, link_secret) = match current {
+        Some((_, visibility, link_secret)) => (visibility, link_secret),
+        None => (TreeVisibility::Private, None),
+    };
+    let event_id = state
+        .nostr
+        .publish_tree_root(
+            inbox::INBOX_TREE,
+            &root,
+            &visibility,
+            link_secret.as_ref(),
+            &commit,
+        )
+        .await?;
+    if visibility == TreeVisibility::Private {
+        rewrap_shares(state, inbox::INBOX_TREE, &root).await;
+    }
+    let owner = keys.public_key().to_hex();
+    tag_tree_origin(state, &owner, inbox::INBOX_TREE, Origin::Own, &root).await;
+    if let Err(e) = state.accounts.add_tree(&owner, inbox::INBOX_TREE) {
+        warn!("Failed to add the inbox to the account's trees: {}", e);
+    }
+    Ok((root, event_id.to_hex()))
+}
+
+/// Merge the history our other devices synced into the local history, then
+/// publish the merged history if the tree doesn't have it yet. Returns how
+/// many local entries changed.
+async fn sync_history(state: &WorkerState, app_handle: &AppHandle) -> Result<usize, String> {
+    use tauri::Manager;
+
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let _running = state.history_sync.running.lock().await;
+    let history = app_handle
+        .state::<Arc<crate::history::HistoryStore>>()
+        .inner()
+        .clone();
+    let current = own_tree_root(state, app_handle, &keys, history_sync::HISTORY_TREE).await?;
+
+    let remote = match &current {
+        Some((root, _, _)) => {
+            let tree = state.tree.read().await;
+            let tree = tree.as_ref().ok_or("Tree not initialized")?;
+            match history_sync::read_entries(tree, root).await? {
+                Some(data) => history_sync::decrypt_entries(&keys, &data)?,
+                None => Vec::new(),
+            }
+        }
+        None => Vec::new(),
+    };
+    let merged = history.merge(remote.clone())?;
+    let local = history.entries()?;
+    if history_sync::same_entries(&local, &remote) {
+        return Ok(merged);
+    }
+
+    let data = history_sync::encrypt_entries(&keys, &local)?;
+    let (root, blocks) = {
+        let tree = state.tree.read().await;
+        let tree = tree.as_ref().ok_or("Tree not initialized")?;
+        let root =
+            history_sync::write_entries(tree, current.as_ref().map(|(cid, _, _)| cid), &data)
+                .await?;
+        let blocks = tree.walk_blocks(&root).await?;
+        (root, blocks)
+    };
+
+    // Other devices read the tree from Blossom
+    for block in &blocks {
+        if let Err(e) = state.blossom.upload(&block.data).await {
+            let e = e.to_string();
+            if !e.contains("409") && !e.to_lowercase().contains("exists") {
+                return Err(format!("Failed to upload history: {}", e));
+            }
+        }
+    }
+
+    let commit = tree_roots::Commit {
+        parent: current.as_ref().map(|(cid, _, _)| cid.hash.clone()),
+        ..Default::default()
+    };
+    let (visibility, link_secret) = match current {
+        Some((_, visibility, link_secret)) => (visibility, link_secret),
+        None => (TreeVisibility::Private, None),
+    };
+    state
+        .nostr
+        .publish_tree_root(
+            history_sync::HISTORY_TREE,
+            &root,
+            &visibility,
+            link_secret.as_ref(),
+            &commit,
+        )
+        .await?;
+    let owner = keys.public_key().to_hex();
+    tag_tree_origin(
+        state,
+        &owner,
+        history_sync::HISTORY_TREE,
+        Origin::Own,
+        &root,
+    )
+    .await;
+    info!(
+        "Synced {} history entries, {} changed here",
+        local.len(),
+        merged
+    );
+    Ok(merged)
+}
+
+/// A history sync outside of a request, held while sync is paused
+async fn background_history_sync(state: &WorkerState, app_handle: &AppHandle) {
+    state.sync.wait_resumed().await;
+    let job_id = format!("history-sync-{}", uuid::Uuid::new_v4());
+    let _permit = state
+        .scheduler
+        .acquire(&job_id, "syncHistory", Priority::Background)
+        .await;
+    if let Err(e) = sync_history(state, app_handle).await {
+        warn!("History sync failed: {}", e);
+    }
+}
+
+/// Sync history every `SYNC_INTERVAL` while it's enabled and we have an
+/// identity of our own
+fn start_history_sync(state: Arc<WorkerState>, app_handle: AppHandle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(history_sync::SYNC_INTERVAL);
+        loop {
+            interval.tick().await;
+            if state.history_sync.is_enabled()
+                && !state.is_guest()
+                && state.nostr.get_keys().is_some()
+            {
+                background_history_sync(&state, &app_handle).await;
+            }
+        }
+    });
+}
+
+/// Merge our mute list from the relays if it's newer than the one we last
+/// saw. Returns whether it was.
+async fn fetch_mutes(state: &WorkerState) -> Result<bool, String> {
+    let _edit = state.mutes.edit().await;
+    merge_remote_mutes(state).await
+}
+
+/// Merge the latest mute list the relays have, failing unless one answered.
+/// Callers hold the mute list's edit lock.
+async fn merge_remote_mutes(state: &WorkerState) -> Result<bool, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::from(mutes::KIND_MUTE_LIST))
+        .author(keys.public_key())
+        .limit(1);
+    let events = state
+        .nostr
+        .fetch_events_answered(vec![filter], std::time::Duration::from_secs(3))
+        .await
+        .map_err(|e| format!("Failed to fetch the mute list: {}", e))?;
+    match events.iter().max_by_key(|e| e.created_at) {
+        Some(latest) => state.mutes.merge_event(&keys, latest),
+        None => Ok(false),
+    }
+}
+
+/// Publish our mute list merged with the one on the relays, so entries
+/// added elsewhere aren't dropped, returning the event id
+async fn publish_mutes(state: &WorkerState, app_handle: &AppHandle) -> Result<String, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+    let _edit = state.mutes.edit().await;
+    merge_remote_mutes(state).await?;
+    let event = state.mutes.build_event(&keys)?;
+    let event_json =
+        serde_json::to_value(&event).map_err(|e| format!("Failed to encode event: {}", e))?;
+    let event_id = state.nostr.publish(event_json).await?;
+    state.mutes.published(&event)?;
+    Ok(event_id.to_hex())
+}
+
+/// Profile of `pubkey` (hex or npub) from nostrdb, or fetched in the next
+/// batch if it isn't there
+pub async fn get_profile(
+    state: Arc<WorkerState>,
+    app_handle: &AppHandle,
+    pubkey: &str,
+) -> Result<Option<serde_json::Value>, String> {
+    let pubkey = shares::parse_pubkey(pubkey)?;
+    if let Some(profile) = profiles::cached_profile(&state.ndb, &pubkey) {
+        return Ok(Some(profile));
+    }
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+
+    let (profile, opened) = state.profiles.join(&pubkey.to_hex());
+    if opened {
+        let state = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(profiles::BATCH_DELAY).await;
+            fetch_profiles(&state).await;
+        });
+    }
+    Ok(profile.await.ok().flatten())
+}
+
+/// Fetch the open batch of profiles, storing them in nostrdb
+async fn fetch_profiles(state: &WorkerState) {
+    let waiters = state.profiles.take();
+    let filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::Metadata)
+        .authors(profiles::batch_authors(&waiters));
+    let events = match state.nostr.fetch_events(vec![filter]).await {
+        Ok(events) => events,
+        Err(e) => {
+            warn!("Failed to fetch {} profiles: {}", waiters.len(), e);
+            Vec::new()
+        }
+    };
+    for event in &events {
+        let Ok(event_json) = serde_json::to_string(event) else {
+            continue;
+        };
+        let relay_msg = format!(r#"["EVENT","profiles",{}]"#, event_json);
+        if let Err(e) = state.ndb.process_event(&relay_msg) {
+            debug!("nostrdb process_event error for profile: {:?}", e);
+        }
+    }
+    profiles::deliver(waiters, &events);
+}
+
+/// Subscribe `sub_id` to the comments on a tree path, cached ones first
+async fn subscribe_comments(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    sub_id: &str,
+    npub: &str,
+    tree_name: &str,
+    path: &str,
+) -> Result<(), String> {
+    let thread = comments::Thread::new(npub, tree_name, path)?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+    let filters = vec![thread.filter()];
+    query_ndb_cache(
+        &state.ndb,
+        &state.wot,
+        state.nostr.dedup(),
+        &filters,
+        sub_id,
+        app_handle,
+    );
+    let filters = nostr::parse_filters(filters)?;
+    state
+        .nostr
+        .subscribe(sub_id.to_string(), filters, None)
+        .await
+}
+
+/// Publish a comment in `thread`, returning its event id
+async fn post_comment(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    thread: &comments::Thread<'_>,
+    content: &str,
+    reply_to: Option<&comments::CommentParent>,
+) -> Result<String, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let event = thread.comment(&keys, content, reply_to)?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+    let event_json =
+        serde_json::to_value(&event).map_err(|e| format!("Failed to encode event: {}", e))?;
+    let event_id = state.nostr.publish(event_json).await?;
+    Ok(event_id.to_hex())
+}
+
+/// Follow or unfollow `target` (hex or npub) by publishing an edited contact
+/// list, returning the pubkeys we follow afterwards
+async fn set_following(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    target: &str,
+    follow: bool,
+) -> Result<Vec<String>, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let target = shares::parse_pubkey(target)?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+
+    // A fetch no relay answered is an error rather than an empty list:
+    // publishing from nothing would drop everyone else we follow
+    let filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::ContactList)
+        .author(keys.public_key())
+        .limit(1);
+    let mut lists = state
+        .nostr
+        .fetch_events_answered(vec![filter], std::time::Duration::from_secs(3))
+        .await
+        .map_err(|e| format!("Failed to fetch the contact list: {}", e))?;
+
+    // The relays may be behind what we published or received earlier
+    let pk_bytes = keys.public_key().to_bytes();
+    if let Ok(txn) = nostrdb::Transaction::new(&state.ndb) {
+        let filter = nostrdb::Filter::new()
+            .kinds(vec![3])
+            .authors(vec![&pk_bytes])
+            .build();
+        if let Ok(results) = state.ndb.query(&txn, &[filter], 10) {
+            lists.extend(results.iter().filter_map(|result| {
+                let json = result.note.json().ok()?;
+                serde_json::from_str::<nostr_sdk::Event>(&json).ok()
+            }));
+        }
+    }
+    let current = lists.into_iter().max_by_key(|e| e.created_at);
+
+    let list = match follows::edit_contact_list(&keys, current.as_ref(), &target, follow)? {
+        Some(event) => {
+            let event_json = serde_json::to_value(&event)
+                .map_err(|e| format!("Failed to encode event: {}", e))?;
+            state.nostr.publish(event_json).await?;
+            event
+        }
+        None => match current {
+            Some(event) => event,
+            None => return Ok(Vec::new()),
+        },
+    };
+    let pubkeys = follows::followed_pubkeys(&list);
+    state.webrtc.update_follows(pubkeys.clone()).await;
+    watch_activity(state).await;
+    Ok(pubkeys)
+}
+
+/// Size and event counts of nostrdb, counting what `rule` would purge. The
+/// scan reads every event, so it runs off the async threads.
+async fn ndb_usage(
+    state: &WorkerState,
+    rule: Option<PurgeRule>,
+) -> Result<ndb_maintenance::NdbUsage, String> {
+    let ndb = state.ndb.clone();
+    let data_dir = state.data_dir.clone();
+    tokio::task::spawn_blocking(move || ndb_maintenance::usage(&ndb, &data_dir, rule.as_ref()))
+        .await
+        .map_err(|e| format!("nostrdb scan failed: {}", e))?
+}
+
+/// (Re)start the activity feed's watch on the follows of the current identity
+async fn watch_activity(state: &WorkerState) {
+    state
+        .activity
+        .watch(
+            &state.nostr,
+            &state.ndb,
+            state.tree.clone(),
+            state.store.clone(),
+        )
+        .await;
+}
+
+/// Re-encrypt one of our trees under a new key and republish it. Private
+/// trees are re-wrapped for their recipients; link-visible trees get a new
+/// link secret, so old share links stop resolving new versions.
+async fn rotate_tree_key(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    npub: &str,
+    tree_name: &str,
+) -> Result<(WorkerCid, String, Option<String>), String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    if shares::parse_pubkey(npub)? != keys.public_key() {
+        return Err("Can only rotate the keys of your own trees".to_string());
+    }
+    let (cid, visibility, _) = own_tree_root(state, app_handle, &keys, tree_name)
+        .await?
+        .ok_or_else(|| format!("Tree not found: {}", tree_name))?;
+    if visibility == TreeVisibility::Public {
+        return Err("Public trees have no key to rotate".to_string());
+    }
+
+    let (rotated, blocks) = {
+        let tree = state.tree.read().await;
+        let tree = tree.as_ref().ok_or("Tree not initialized")?;
+        let rotated = tree.rotate_key(&cid).await?;
+        // Rotation changes the root's entries, so a signed tree needs a fresh manifest
+        let signed = tree
+            .list_dir(&rotated)
+            .await?
+            .iter()
+            .any(|e| e.name == MANIFEST_FILENAME);
+        let rotated = if signed {
+            tree.embed_manifest(&rotated, &keys).await?
+        } else {
+            rotated
+        };
+        let blocks = tree.walk_blocks(&rotated).await?;
+        (rotated, blocks)
+    };
+
+    // As `htree rotate-key` does: upload the new blocks and re-wrap the key
+    // for recipients before publishing the root, so nobody gets a root they
+    // can't open
+    for block in &blocks {
+        if let Err(e) = state.blossom.upload(&block.data).await {
+            let e = e.to_string();
+            if !e.contains("409") && !e.to_lowercase().contains("exists") {
+                return Err(format!("Failed to upload the re-encrypted tree: {}", e));
+            }
+        }
+    }
+    if visibility == TreeVisibility::Private {
+        rewrap_shares(state, tree_name, &rotated).await;
+    }
+
+    let link_secret = (visibility == TreeVisibility::LinkVisible).then(hashtree_core::generate_key);
+    let collaborators = tree_roots::cached_tree_root(
+        &state.ndb,
+        &keys.public_key().to_bytes(),
+        tree_name,
+        Some(&keys),
+    )
+    .map(|root| root.collaborators)
+    .unwrap_or_default();
+    let commit = tree_roots::Commit {
+        message: Some("Rotated key".to_string()),
+        parent: Some(cid.hash.clone()),
+        author: None,
+        collaborators,
+    };
+    let event_id = state
+        .nostr
+        .publish_tree_root(
+            tree_name,
+            &rotated,
+            &visibility,
+            link_secret.as_ref(),
+            &commit,
+        )
+        .await?;
+
+    Ok((
+        rotated,
+        event_id.to_hex(),
+        link_secret.map(|s| hashtree_core::to_hex(&s)),
+    ))
+}
+
+/// Duplicate files within the tree at `root`, or across all of our trees
+/// whose roots are cached. Trees that can't be read are skipped.
+async fn find_duplicates(
+    state: &WorkerState,
+    root: Option<&WorkerCid>,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let roots: Vec<(Option<String>, WorkerCid)> = match root {
+        Some(root) => vec![(None, root.clone())],
+        None => {
+            let keys = state.nostr.get_keys().ok_or("Not logged in")?;
+            tree_roots::cached_tree_roots(&state.ndb, &keys.public_key().to_bytes(), Some(&keys))?
+                .into_iter()
+                .map(|root| (Some(root.name), root.cid))
+                .collect()
+        }
+    };
+
+    let tree = state.tree.read().await;
+    let tree = tree.as_ref().ok_or("Tree not initialized")?;
+    let mut files = Vec::new();
+    for (tree_name, cid) in roots {
+        match tree.files(&cid).await {
+            Ok(found) => files.extend(found.into_iter().map(|(path, hash, size)| {
+                let copy = FileCopy {
+                    tree: tree_name.clone(),
+                    path,
+                };
+                (copy, hash, size)
+            })),
+            Err(e) if tree_name.is_some() => {
+                warn!("Skipping {:?} in duplicate search: {}", tree_name, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(duplicate_groups(files))
+}
+
+/// Newest root of our tree `tree_name`, fetched first so a root published
+/// from another device isn't missed
+async fn current_own_root(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    keys: &nostr_sdk::Keys,
+    tree_name: &str,
+) -> Result<TreeRoot, String> {
+    if let Err(e) = fetch_tree_roots(state, app_handle, &keys.public_key(), Some(tree_name)).await {
+        debug!("Failed to fetch the root of {}: {}", tree_name, e);
+    }
+    tree_roots::cached_tree_root(
+        &state.ndb,
+        &keys.public_key().to_bytes(),
+        tree_name,
+        Some(keys),
+    )
+    .ok_or_else(|| format!("Tree not found: {}", tree_name))
+}
+
+/// Republish the current root of our tree `tree_name` listing
+/// `collaborators` (npubs or hex). Returns the root, the event id and the
+/// link secret.
+async fn set_collaborators(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    tree_name: &str,
+    collaborators: &[String],
+) -> Result<(WorkerCid, String, Option<String>), String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let mut collaborators = collaborators
+        .iter()
+        .map(|pubkey| shares::parse_pubkey(pubkey).map(|pk| pk.to_hex()))
+        .collect::<Result<Vec<_>, _>>()?;
+    collaborators.sort();
+    collaborators.dedup();
+    collaborators.retain(|pubkey| *pubkey != keys.public_key().to_hex());
+
+    let root = current_own_root(state, app_handle, &keys, tree_name).await?;
+    let commit = tree_roots::Commit {
+        collaborators,
+        ..tree_roots::Commit::after(Some(&root), &root.cid, None)
+    };
+    let event_id = state
+        .nostr
+        .publish_tree_root(
+            tree_name,
+            &root.cid,
+            &root.visibility,
+            root.link_secret.as_ref(),
+            &commit,
+        )
+        .await?;
+
+    Ok((
+        root.cid,
+        event_id.to_hex(),
+        root.link_secret.map(|s| hashtree_core::to_hex(&s)),
+    ))
+}
+
+/// Propose `cid` as the new root of `owner`'s tree `tree_name`, made on
+/// `base` or else the tree's current root. Returns the proposal's event id.
+async fn propose_root(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    owner: &str,
+    tree_name: &str,
+    cid: &WorkerCid,
+    base: Option<String>,
+    message: Option<&str>,
+) -> Result<String, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let owner = shares::parse_pubkey(owner)?;
+    if owner == keys.public_key() {
+        return Err("Publish your own trees instead of proposing to them".to_string());
+    }
+    if let Err(e) = fetch_tree_roots(state, app_handle, &owner, Some(tree_name)).await {
+        debug!("Failed to fetch the root of {}: {}", tree_name, e);
+    }
+    let root = tree_roots::cached_tree_root(&state.ndb, &owner.to_bytes(), tree_name, Some(&keys))
+        .ok_or_else(|| format!("Tree not found: {}", tree_name))?;
+    if !root.collaborators.contains(&keys.public_key().to_hex()) {
+        return Err(format!("Not a collaborator of {}", tree_name));
+    }
+
+    let base = base.unwrap_or(root.cid.hash);
+    let event = collab::proposal_event(&keys, &owner, tree_name, cid, Some(&base), message)?;
+    let event_json =
+        serde_json::to_value(&event).map_err(|e| format!("Failed to encode event: {}", e))?;
+    state
+        .nostr
+        .publish(event_json)
+        .await
+        .map(|event_id| event_id.to_hex())
+}
+
+/// Open proposals of collaborators for our trees, only `tree_name`'s if given
+async fn list_proposals(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    tree_name: Option<&str>,
+) -> Result<Vec<Proposal>, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+
+    let mut filter = nostr_sdk::Filter::new()
+        .kind(nostr_sdk::Kind::from(collab::KIND_ROOT_PROPOSAL))
+        .custom_tag(
+            nostr_sdk::SingleLetterTag::from_char('p').unwrap(),
+            vec![keys.public_key().to_hex()],
+        );
+    if let Some(tree_name) = tree_name {
+        filter = filter.custom_tag(
+            nostr_sdk::SingleLetterTag::from_char('a').unwrap(),
+            vec![collab::tree_address(&keys.public_key(), tree_name)],
+        );
+    }
+    let events = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        state.nostr.fetch_events(vec![filter]),
+    )
+    .await
+    .map_err(|_| "Timed out fetching proposals".to_string())??;
+
+    let proposals = events
+        .iter()
+        .filter(|event| !state.mutes.is_muted(&event.pubkey.to_hex()))
+        .filter_map(|event| collab::read_proposal(event, &keys))
+        .collect();
+    let roots =
+        tree_roots::cached_tree_roots(&state.ndb, &keys.public_key().to_bytes(), Some(&keys))?;
+    let open = collab::open_proposals(proposals, &roots, &state.proposals);
+    state.proposals.set_listed(&open);
+    Ok(open)
+}
+
+/// A proposal for one of our trees by event id, from the last listing or
+/// else the relays
+async fn find_proposal(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    keys: &nostr_sdk::Keys,
+    event_id: &str,
+) -> Result<Proposal, String> {
+    if let Some(proposal) = state.proposals.listed(event_id) {
+        return Ok(proposal);
+    }
+    let id =
+        nostr_sdk::EventId::from_hex(event_id).map_err(|e| format!("Invalid event id: {}", e))?;
+    state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await?;
+    let events = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        state
+            .nostr
+            .fetch_events(vec![nostr_sdk::Filter::new().id(id)]),
+    )
+    .await
+    .map_err(|_| "Timed out fetching the proposal".to_string())??;
+    events
+        .iter()
+        .find_map(|event| collab::read_proposal(event, keys))
+        .ok_or_else(|| format!("Proposal not found: {}", event_id))
+}
+
+/// The root `proposal` was made on, if we know it: one of the tree's cached
+/// roots, or just its hash for a tree whose roots have no keys. Diffs and
+/// merges of a proposal on an unknown root are against an empty tree.
+fn proposal_base(
+    state: &WorkerState,
+    keys: &nostr_sdk::Keys,
+    root: &TreeRoot,
+    proposal: &Proposal,
+) -> Option<WorkerCid> {
+    let base = proposal.base.as_ref()?;
+    tree_roots::cached_tree_log(
+        &state.ndb,
+        &keys.public_key().to_bytes(),
+        &root.name,
+        Some(keys),
+        tree_roots::DEFAULT_LOG_LIMIT,
+    )
+    .ok()?
+    .into_iter()
+    .find(|commit| commit.cid.hash == *base)
+    .map(|commit| commit.cid)
+    .or_else(|| {
+        root.cid.key.is_none().then(|| WorkerCid {
+            hash: base.clone(),
+            key: None,
+        })
+    })
+}
+
+/// What a proposal changes compared to the root it was made on, the same
+/// base its merge would use
+async fn diff_proposal(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    event_id: &str,
+) -> Result<Vec<TreeChange>, String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let proposal = find_proposal(state, app_handle, &keys, event_id).await?;
+    let root = current_own_root(state, app_handle, &keys, &proposal.tree_name).await?;
+    let base = proposal_base(state, &keys, &root, &proposal);
+
+    // Reading the proposal may fetch the collaborator's blocks
+    let tree = state
+        .tree
+        .read()
+        .await
+        .as_ref()
+        .map(TreeManager::detached)
+        .ok_or("Tree not initialized")?;
+    tree.diff(base.as_ref(), &proposal.cid).await
+}
+
+/// Merge a collaborator's proposal into our tree and publish the result
+/// with them as its author. Returns the root, the event id and the link
+/// secret.
+async fn merge_proposal(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    event_id: &str,
+) -> Result<(WorkerCid, String, Option<String>), String> {
+    let keys = state.nostr.get_keys().ok_or("No signing identity set")?;
+    let proposal = find_proposal(state, app_handle, &keys, event_id).await?;
+    let root = current_own_root(state, app_handle, &keys, &proposal.tree_name).await?;
+    if !root.collaborators.contains(&proposal.author) {
+        return Err("The proposal's author isn't a collaborator of the tree".to_string());
+    }
+    if root.cid.key.is_some() && proposal.cid.key.is_none() {
+        return Err("Proposal of an encrypted tree has no key".to_string());
+    }
+    let base = proposal_base(state, &keys, &root, &proposal);
+
+    let merged = {
+        // Reading the proposal may fetch the collaborator's blocks
+        let tree = state
+            .tree
+            .read()
+            .await
+            .as_ref()
+            .map(TreeManager::detached)
+            .ok_or("Tree not initialized")?;
+        let merged = match tree.merge(base.as_ref(), &root.cid, &proposal.cid).await? {
+            MergeOutcome::Merged(merged) => merged,
+            MergeOutcome::Conflicts(paths) => {
+                return Err(format!("Conflicting changes: {}", paths.join(", ")));
+            }
+        };
+        // The merge keeps our manifest, which no longer matches the entries
+        let signed = tree
+            .list_dir(&root.cid)
+            .await?
+            .iter()
+            .any(|e| e.name == MANIFEST_FILENAME);
+        if signed {
+            tree.embed_manifest(&merged, &keys).await?
+        } else {
+            merged
+        }
+    };
+
+    let commit = tree_roots::Commit {
+        message: proposal.message.clone(),
+        author: Some(proposal.author.clone()),
+        ..tree_roots::Commit::after(Some(&root), &merged, None)
+    };
+    let event_id = state
+        .nostr
+        .publish_tree_root(
+            &proposal.tree_name,
+            &merged,
+            &root.visibility,
+            root.link_secret.as_ref(),
+            &commit,
+        )
+        .await?;
+    info!(
+        "Merged proposal {} into {}",
+        proposal.event_id, proposal.tree_name
+    );
+    if root.visibility == TreeVisibility::Private {
+        rewrap_shares(state, &proposal.tree_name, &merged).await;
+    }
+    state.proposals.mark_handled(&proposal.event_id)?;
+
+    Ok((
+        merged,
+        event_id.to_hex(),
+        root.link_secret.map(|s| hashtree_core::to_hex(&s)),
+    ))
+}
+
+/// Try to open a private root someone else published via the share they
+/// wrapped for us. Falls back to the locked cid when there is none.
+async fn unlock_shared_root(
+    state: &WorkerState,
+    app_handle: &AppHandle,
+    author: &nostr_sdk::PublicKey,
+    tree_name: &str,
+    locked: WorkerCid,
+) -> WorkerCid {
+    if let Err(e) = state
+        .nostr
+        .ensure_client(Some(app_handle.clone()), Some(state.ndb.clone()))
+        .await
+    {
+        debug!("Failed to init nostr client for share lookup: {}", e);
+        return locked;
+    }
+    let fetch = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        shares::fetch_share(&state.nostr, author, tree_name),
+    )
+    .await;
+    match fetch {
+        Ok(Some(cid)) => {
+            if cid.hash != locked.hash {
+                debug!("Share for {} lags behind the published root", tree_name);
+            }
+            cid
+        }
+        _ => locked,
+    }
 }
 
 #[cfg(test)]
@@ -1283,7 +3970,7 @@ mod tests {
 
         // Write file
         let data = b"Hello, Tree!";
-        let cid = tree.write_file(None, "test.txt", data).await.unwrap();
+        let cid = tree.write_file(None, "test.txt", data, false).await.unwrap();
 
         // Read file
         let result = tree.read_file(&cid).await.unwrap();
@@ -1298,11 +3985,11 @@ mod tests {
         let tree = tree_guard.as_ref().unwrap();
 
         // Create empty dir
-        let dir_cid = tree.create_empty_dir().await.unwrap();
+        let dir_cid = tree.create_empty_dir(false).await.unwrap();
 
         // Add file
         let new_cid = tree
-            .write_file(Some(&dir_cid), "file.txt", b"content")
+            .write_file(Some(&dir_cid), "file.txt", b"content", false)
             .await
             .unwrap();
 
@@ -1343,6 +4030,24 @@ mod tests {
         assert!(filter.is_some());
     }
 
+    #[test]
+    fn test_matches_tag_filters() {
+        let event = serde_json::json!({
+            "kind": 1111,
+            "tags": [["I", "htree://npub1a/photos/a.jpg"], ["k", "htree"]]
+        });
+        let thread = |path: &str| {
+            let identifier = format!("htree://npub1a/photos/{}", path);
+            serde_json::json!({ "kinds": [1111], "#I": [identifier] })
+        };
+        assert!(matches_tag_filters(&thread("a.jpg"), &event));
+        assert!(!matches_tag_filters(&thread("b.jpg"), &event));
+        let other_tag = serde_json::json!({ "#e": ["00"] });
+        assert!(!matches_tag_filters(&other_tag, &event));
+        let no_tags = serde_json::json!({ "kinds": [1111] });
+        assert!(matches_tag_filters(&no_tags, &event));
+    }
+
     #[test]
     fn test_hex_to_pubkey_valid() {
         let hex = "a".repeat(64);
@@ -0,0 +1,384 @@
+//! NIP-51 mute list, honored when resolving, serving and fetching
+//!
+//! Muted pubkeys are kept per account in `mutes.json` and merged with the
+//! account's kind 10000 list from the relays: its public `p` tags and the
+//! NIP-44 encrypted private items in its content. A list newer than the one
+//! we last saw replaces the local one, with the mutes and unmutes made here
+//! since then applied on top. Published lists carry every muted pubkey as a
+//! private item and keep the list's other entries (hashtags, words, threads)
+//! as they were. Edits that fetch, merge and publish hold [`MuteList::edit`]
+//! so they don't interleave.
+//!
+//! Trees of muted pubkeys aren't resolved, served or prefetched, their
+//! events don't pass the relay proxy, and their WebRTC signaling is
+//! ignored, which keeps them out of the peer pools.
+
+use nostr_sdk::nips::nip44;
+use nostr_sdk::{Event, EventBuilder, Keys, Kind, Tag};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::shares::parse_pubkey;
+
+/// Kind of NIP-51 mute lists
+pub const KIND_MUTE_LIST: u16 = 10000;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Saved {
+    /// Muted pubkeys, hex
+    pubkeys: BTreeSet<String>,
+    /// Time (seconds) of the last list we took over or published
+    updated_at: u64,
+    /// Pubkeys muted (true) or unmuted here since, to apply to newer lists
+    pending: BTreeMap<String, bool>,
+    /// Public tags of the list other than `p`
+    public_tags: Vec<Vec<String>>,
+    /// Private items of the list other than `p`
+    private_tags: Vec<Vec<String>>,
+}
+
+/// Muted pubkeys of each of our accounts
+pub struct MuteList {
+    path: PathBuf,
+    /// Lists by account pubkey, hex
+    lists: RwLock<BTreeMap<String, Saved>>,
+    /// Account whose list is honored
+    account: RwLock<Option<String>>,
+    edit: Mutex<()>,
+}
+
+impl MuteList {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("mutes.json");
+        let lists = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            lists: RwLock::new(lists),
+            account: RwLock::new(None),
+            edit: Mutex::new(()),
+        }
+    }
+
+    /// Honor the list of `account` (hex or npub), or none
+    pub fn set_account(&self, account: Option<&str>) {
+        *self.account.write() = account
+            .and_then(|account| parse_pubkey(account).ok())
+            .map(|account| account.to_hex());
+    }
+
+    /// Hold while fetching, merging and publishing the list
+    pub async fn edit(&self) -> MutexGuard<'_, ()> {
+        self.edit.lock().await
+    }
+
+    pub fn pubkeys(&self) -> Vec<String> {
+        let Some(account) = self.account.read().clone() else {
+            return Vec::new();
+        };
+        self.lists
+            .read()
+            .get(&account)
+            .map(|saved| saved.pubkeys.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether `pubkey` (hex or npub) is muted
+    pub fn is_muted(&self, pubkey: &str) -> bool {
+        let Some(account) = self.account.read().clone() else {
+            return false;
+        };
+        let lists = self.lists.read();
+        let Some(saved) = lists.get(&account) else {
+            return false;
+        };
+        if saved.pubkeys.is_empty() {
+            return false;
+        }
+        saved.pubkeys.contains(pubkey)
+            || parse_pubkey(pubkey).is_ok_and(|pk| saved.pubkeys.contains(&pk.to_hex()))
+    }
+
+    /// Mute or unmute `pubkey`, false if it already was
+    pub fn set_muted(&self, pubkey: &str, muted: bool) -> Result<bool, String> {
+        let pubkey = parse_pubkey(pubkey)?.to_hex();
+        let account = self.account.read().clone().ok_or("No account set")?;
+        let mut lists = self.lists.write();
+        let mut saved = lists.get(&account).cloned().unwrap_or_default();
+        let changed = if muted {
+            saved.pubkeys.insert(pubkey.clone())
+        } else {
+            saved.pubkeys.remove(&pubkey)
+        };
+        if changed {
+            saved.pending.insert(pubkey, muted);
+            self.save(&mut lists, account, saved)?;
+        }
+        Ok(changed)
+    }
+
+    /// Merge our mute list `event` if it's newer than the last one we saw:
+    /// it replaces the local list, with our pending changes applied on top.
+    /// Returns whether it was newer.
+    pub fn merge_event(&self, keys: &Keys, event: &Event) -> Result<bool, String> {
+        let account = keys.public_key().to_hex();
+        if event.pubkey != keys.public_key() || event.kind != Kind::from(KIND_MUTE_LIST) {
+            return Err("Not our mute list".to_string());
+        }
+        let mut lists = self.lists.write();
+        let local = lists.get(&account).cloned().unwrap_or_default();
+        if event.created_at.as_u64() <= local.updated_at {
+            return Ok(false);
+        }
+
+        let mut saved = Saved {
+            updated_at: event.created_at.as_u64(),
+            ..Saved::default()
+        };
+        let public = event.tags.iter().map(|tag| tag.as_slice().to_vec());
+        split_items(public, &mut saved.pubkeys, &mut saved.public_tags);
+        if !event.content.is_empty() {
+            let plaintext = nip44::decrypt(keys.secret_key(), &keys.public_key(), &event.content)
+                .map_err(|e| format!("NIP-44 decryption failed: {}", e))?;
+            let private: Vec<Vec<String>> = serde_json::from_str(&plaintext)
+                .map_err(|e| format!("Invalid mute list items: {}", e))?;
+            split_items(private, &mut saved.pubkeys, &mut saved.private_tags);
+        }
+        for (pubkey, muted) in &local.pending {
+            if *muted {
+                saved.pubkeys.insert(pubkey.clone());
+            } else {
+                saved.pubkeys.remove(pubkey);
+            }
+        }
+        saved.pending = local.pending;
+        self.save(&mut lists, account, saved)?;
+        Ok(true)
+    }
+
+    /// Our mute list as a signed kind 10000 event
+    pub fn build_event(&self, keys: &Keys) -> Result<Event, String> {
+        let account = keys.public_key().to_hex();
+        let saved = self.lists.read().get(&account).cloned().unwrap_or_default();
+        let items: Vec<Vec<String>> = saved
+            .pubkeys
+            .iter()
+            .map(|pubkey| vec!["p".to_string(), pubkey.clone()])
+            .chain(saved.private_tags.iter().cloned())
+            .collect();
+        let plaintext = serde_json::to_string(&items)
+            .map_err(|e| format!("Failed to encode mute list: {}", e))?;
+        let content = nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            plaintext,
+            nip44::Version::V2,
+        )
+        .map_err(|e| format!("NIP-44 encryption failed: {}", e))?;
+        let tags = saved
+            .public_tags
+            .iter()
+            .filter_map(|tag| Tag::parse(tag.as_slice()).ok())
+            .collect::<Vec<_>>();
+        EventBuilder::new(Kind::from(KIND_MUTE_LIST), content, tags)
+            .to_event(keys)
+            .map_err(|e| format!("Failed to sign event: {}", e))
+    }
+
+    /// Note that `event`, built from this list under [`Self::edit`], was
+    /// published, so the changes it carries are no longer pending
+    pub fn published(&self, event: &Event) -> Result<(), String> {
+        let account = event.pubkey.to_hex();
+        let mut lists = self.lists.write();
+        let mut saved = lists.get(&account).cloned().unwrap_or_default();
+        saved.updated_at = saved.updated_at.max(event.created_at.as_u64());
+        saved.pending.clear();
+        self.save(&mut lists, account, saved)
+    }
+
+    fn save(
+        &self,
+        lists: &mut BTreeMap<String, Saved>,
+        account: String,
+        saved: Saved,
+    ) -> Result<(), String> {
+        let mut updated = lists.clone();
+        updated.insert(account, saved);
+        let data = serde_json::to_vec_pretty(&updated)
+            .map_err(|e| format!("Failed to encode mute list: {}", e))?;
+        crate::atomic_file::write(&self.path, data)
+            .map_err(|e| format!("Failed to save mute list: {}", e))?;
+        *lists = updated;
+        Ok(())
+    }
+}
+
+/// Sort list items into muted pubkeys and everything else
+fn split_items(
+    items: impl IntoIterator<Item = Vec<String>>,
+    pubkeys: &mut BTreeSet<String>,
+    others: &mut Vec<Vec<String>>,
+) {
+    for item in items {
+        if item.first().map(String::as_str) == Some("p") {
+            if let Some(Ok(pubkey)) = item.get(1).map(|pubkey| parse_pubkey(pubkey)) {
+                pubkeys.insert(pubkey.to_hex());
+            }
+        } else if !item.is_empty() {
+            others.push(item);
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::nips::nip19::ToBech32;
+    use nostr_sdk::{TagKind, Timestamp};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_mute_and_persist() {
+        let dir = TempDir::new().unwrap();
+        let account = Keys::generate().public_key().to_hex();
+        let mutes = MuteList::new(dir.path());
+        let muted = Keys::generate().public_key();
+        let npub = muted.to_bech32().unwrap();
+
+        assert!(mutes.set_muted(&npub, true).is_err());
+        mutes.set_account(Some(&account));
+        assert!(mutes.set_muted(&npub, true).unwrap());
+        assert!(!mutes.set_muted(&muted.to_hex(), true).unwrap());
+        assert!(mutes.is_muted(&npub));
+        assert!(mutes.is_muted(&muted.to_hex()));
+        assert!(!mutes.is_muted(&Keys::generate().public_key().to_hex()));
+        assert!(mutes.set_muted("not a pubkey", true).is_err());
+
+        let reopened = MuteList::new(dir.path());
+        assert!(reopened.pubkeys().is_empty());
+        reopened.set_account(Some(&account));
+        assert_eq!(reopened.pubkeys(), vec![muted.to_hex()]);
+        assert!(reopened.set_muted(&npub, false).unwrap());
+        assert!(!reopened.is_muted(&npub));
+    }
+
+    #[test]
+    fn test_event_roundtrip_keeps_other_entries() {
+        let dir = TempDir::new().unwrap();
+        let keys = Keys::generate();
+        let public_muted = Keys::generate().public_key();
+        let private_muted = Keys::generate().public_key();
+        let private = serde_json::to_string(&vec![
+            vec!["p".to_string(), private_muted.to_hex()],
+            vec!["word".to_string(), "spoiler".to_string()],
+        ])
+        .unwrap();
+        let content = nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            private,
+            nip44::Version::V2,
+        )
+        .unwrap();
+        let event = EventBuilder::new(
+            Kind::from(KIND_MUTE_LIST),
+            content,
+            [
+                Tag::public_key(public_muted),
+                Tag::custom(TagKind::custom("t"), vec!["nsfw".to_string()]),
+            ],
+        )
+        .custom_created_at(Timestamp::from(now_secs() + 10))
+        .to_event(&keys)
+        .unwrap();
+
+        let mutes = MuteList::new(dir.path());
+        mutes.set_account(Some(&keys.public_key().to_hex()));
+        assert!(mutes.merge_event(&keys, &event).unwrap());
+        assert!(mutes.is_muted(&public_muted.to_hex()));
+        assert!(mutes.is_muted(&private_muted.to_hex()));
+        // Not newer than what we have
+        assert!(!mutes.merge_event(&keys, &event).unwrap());
+        assert!(mutes.merge_event(&Keys::generate(), &event).is_err());
+
+        let built = mutes.build_event(&keys).unwrap();
+        assert_eq!(built.tags.len(), 1);
+        let items: Vec<Vec<String>> = serde_json::from_str(
+            &nip44::decrypt(keys.secret_key(), &keys.public_key(), &built.content).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(items.contains(&vec!["word".to_string(), "spoiler".to_string()]));
+    }
+
+    #[test]
+    fn test_newer_lists_keep_local_changes() {
+        let dir = TempDir::new().unwrap();
+        let keys = Keys::generate();
+        let [remote, local, unmuted, other] =
+            [(); 4].map(|_| Keys::generate().public_key().to_hex());
+        let mutes = MuteList::new(dir.path());
+        mutes.set_account(Some(&keys.public_key().to_hex()));
+        mutes.set_muted(&unmuted, true).unwrap();
+        let published = mutes.build_event(&keys).unwrap();
+        mutes.published(&published).unwrap();
+
+        // Muted and unmuted here while another device muted `remote`
+        mutes.set_muted(&local, true).unwrap();
+        mutes.set_muted(&unmuted, false).unwrap();
+        let items = serde_json::to_string(&vec![
+            vec!["p".to_string(), remote.clone()],
+            vec!["p".to_string(), unmuted.clone()],
+        ])
+        .unwrap();
+        let content = nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            items,
+            nip44::Version::V2,
+        )
+        .unwrap();
+        let newer = EventBuilder::new(Kind::from(KIND_MUTE_LIST), content, [])
+            .custom_created_at(Timestamp::from(published.created_at.as_u64() + 1))
+            .to_event(&keys)
+            .unwrap();
+        assert!(mutes.merge_event(&keys, &newer).unwrap());
+        let mut expected = vec![remote.clone(), local.clone()];
+        expected.sort();
+        assert_eq!(mutes.pubkeys(), expected);
+
+        // Once published, a newer list is taken as it is
+        let merged = mutes.build_event(&keys).unwrap();
+        mutes.published(&merged).unwrap();
+        let later = EventBuilder::new(
+            Kind::from(KIND_MUTE_LIST),
+            "",
+            [Tag::public_key(parse_pubkey(&remote).unwrap())],
+        )
+        .custom_created_at(Timestamp::from(merged.created_at.as_u64() + 1))
+        .to_event(&keys)
+        .unwrap();
+        assert!(mutes.merge_event(&keys, &later).unwrap());
+        assert_eq!(mutes.pubkeys(), vec![remote.clone()]);
+
+        // Other accounts have lists of their own
+        mutes.set_account(Some(&Keys::generate().public_key().to_hex()));
+        assert!(!mutes.is_muted(&remote));
+        mutes.set_muted(&other, true).unwrap();
+        mutes.set_account(Some(&keys.public_key().to_hex()));
+        assert!(!mutes.is_muted(&other));
+    }
+}
@@ -0,0 +1,377 @@
+//! Size stats and purging of the nostrdb event cache
+//!
+//! nostrdb can't delete events, and LMDB never gives freed pages back to
+//! the filesystem anyway, so a purge rewrites the database: `PurgeNdb`
+//! stages a rule in `nostrdb-purge.json`, and `apply_pending_purge` copies
+//! every event the rule keeps into a fresh database on the next start,
+//! before nostrdb is opened, then swaps it in. A rule that purges nothing
+//! just compacts.
+
+use nostrdb::{Config, Ndb, Note, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use tracing::{error, info, warn};
+
+use super::dedup::is_replaceable;
+
+/// Database directory in the data dir
+pub const NDB_DIR: &str = "nostrdb";
+
+/// Staged purge rule, applied on the next start
+const PENDING_FILE: &str = "nostrdb-purge.json";
+
+/// Where the rewritten database is built
+const REBUILD_DIR: &str = "nostrdb-rebuild";
+
+/// Where the old database goes while the new one is moved into place
+const PREVIOUS_DIR: &str = "nostrdb-previous";
+
+/// Events per query while scanning the database
+const PAGE: i32 = 10_000;
+
+/// Which events a purge removes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeRule {
+    /// Remove events created before this (unix seconds). Replaceable
+    /// events, the current profiles, follow lists and tree roots, are only
+    /// removed if their kind is listed in `kinds`.
+    #[serde(default)]
+    pub older_than: Option<u64>,
+    /// Only remove events of these kinds (empty = any kind)
+    #[serde(default)]
+    pub kinds: Vec<u32>,
+    /// Authors (hex) whose events are always kept, i.e. ours
+    #[serde(default)]
+    pub keep_authors: Vec<String>,
+}
+
+impl PurgeRule {
+    /// Whether the rule removes anything at all
+    pub fn is_compaction(&self) -> bool {
+        self.older_than.is_none() && self.kinds.is_empty()
+    }
+
+    /// Whether an event of `kind` by `author` (hex) created at `created_at`
+    /// is removed
+    pub fn purges(&self, kind: u32, created_at: u64, author: &str) -> bool {
+        if self.is_compaction() || self.keep_authors.iter().any(|a| a == author) {
+            return false;
+        }
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            return false;
+        }
+        match self.older_than {
+            Some(before) => {
+                created_at < before && (!self.kinds.is_empty() || !is_replaceable(kind as u64))
+            }
+            None => true,
+        }
+    }
+
+    fn purges_note(&self, note: &Note) -> bool {
+        self.purges(note.kind(), note.created_at(), &hex::encode(note.pubkey()))
+    }
+}
+
+/// Events stored of one kind
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KindCount {
+    pub kind: u32,
+    pub count: u64,
+}
+
+/// Size and contents of the database
+#[derive(Debug, Clone, Default)]
+pub struct NdbUsage {
+    /// Bytes of the database files on disk
+    pub bytes: u64,
+    pub events: u64,
+    /// Most common kinds first
+    pub kinds: Vec<KindCount>,
+    /// Events the given rule would remove
+    pub purgeable: u64,
+}
+
+/// Bytes of the files in `dir`
+fn dir_bytes(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(|meta| meta.is_file())
+                .map(|meta| meta.len())
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// Call `f` with every event in `ndb`, newest first, by `(created_at, id)`.
+/// nostrdb can't filter by id, so queries are paged by `until` and a page
+/// only yields the seconds it holds whole: the one it's cut off in is read
+/// again by the next page, or on its own, however many events it has.
+pub fn for_each_note(ndb: &Ndb, mut f: impl FnMut(&Note)) -> Result<(), String> {
+    let txn = Transaction::new(ndb).map_err(|e| format!("Failed to open transaction: {:?}", e))?;
+    let query = |since: Option<u64>, until: Option<u64>, limit: i32| {
+        let mut filter = nostrdb::Filter::new().limit(limit as u64);
+        if let Some(since) = since {
+            filter = filter.since(since);
+        }
+        if let Some(until) = until {
+            filter = filter.until(until);
+        }
+        ndb.query(&txn, &[filter.build()], limit)
+            .map_err(|e| format!("Failed to query nostrdb: {:?}", e))
+    };
+    let mut until: Option<u64> = None;
+    loop {
+        let mut results = query(None, until, PAGE)?;
+        let Some(oldest) = results.iter().map(|r| r.note.created_at()).min() else {
+            return Ok(());
+        };
+        let complete = results.len() < PAGE as usize;
+        if !complete && results.iter().all(|r| r.note.created_at() == oldest) {
+            // More than a page of events in one second: read all of it
+            let mut limit = PAGE;
+            while results.len() >= limit as usize && limit < i32::MAX {
+                limit = limit.saturating_mul(2);
+                results = query(Some(oldest), Some(oldest), limit)?;
+            }
+        } else if !complete {
+            // Read again whole by the next page
+            results.retain(|r| r.note.created_at() > oldest);
+        }
+
+        results.sort_by(|a, b| {
+            (b.note.created_at(), b.note.id()).cmp(&(a.note.created_at(), a.note.id()))
+        });
+        for result in results.iter() {
+            f(&result.note);
+        }
+
+        if complete {
+            return Ok(());
+        }
+        if results.iter().all(|r| r.note.created_at() > oldest) {
+            until = Some(oldest);
+        } else if oldest > 0 {
+            until = Some(oldest - 1);
+        } else {
+            return Ok(());
+        }
+    }
+}
+
+/// Size of the database in `data_dir` and its events by kind, counting the
+/// ones `rule` would purge
+pub fn usage(ndb: &Ndb, data_dir: &Path, rule: Option<&PurgeRule>) -> Result<NdbUsage, String> {
+    let mut kinds: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut usage = NdbUsage {
+        bytes: dir_bytes(&data_dir.join(NDB_DIR)),
+        ..Default::default()
+    };
+    for_each_note(ndb, |note| {
+        usage.events += 1;
+        *kinds.entry(note.kind()).or_default() += 1;
+        if rule.is_some_and(|rule| rule.purges_note(note)) {
+            usage.purgeable += 1;
+        }
+    })?;
+    usage.kinds = kinds
+        .into_iter()
+        .map(|(kind, count)| KindCount { kind, count })
+        .collect();
+    usage.kinds.sort_by(|a, b| b.count.cmp(&a.count));
+    Ok(usage)
+}
+
+/// The purge rule staged for the next start, if any
+pub fn pending_purge(data_dir: &Path) -> Option<PurgeRule> {
+    let data = fs::read(data_dir.join(PENDING_FILE)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+/// Stage `rule`, replacing any staged before
+pub fn schedule_purge(data_dir: &Path, rule: &PurgeRule) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(rule).map_err(|e| e.to_string())?;
+    fs::write(data_dir.join(PENDING_FILE), json)
+        .map_err(|e| format!("Failed to stage nostrdb purge: {}", e))
+}
+
+fn open_ndb(dir: &Path) -> Result<Ndb, String> {
+    let path = dir
+        .to_str()
+        .ok_or_else(|| format!("Non-UTF-8 nostrdb path {:?}", dir))?;
+    Ndb::new(path, &Config::new().set_ingester_threads(2))
+        .map_err(|e| format!("Failed to open nostrdb at {:?}: {:?}", dir, e))
+}
+
+/// Copy the events of `from` that `keep` accepts into a fresh database at
+/// `dir`. Returns the events copied and left out.
+fn copy_notes(from: &Ndb, dir: &Path, keep: impl Fn(&Note) -> bool) -> Result<(u64, u64), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    let to = open_ndb(dir)?;
+    let (mut kept, mut skipped) = (0, 0);
+    for_each_note(from, |note| {
+        if !keep(note) {
+            skipped += 1;
+            return;
+        }
+        match note.json() {
+            Ok(json) => {
+                let relay_msg = format!(r#"["EVENT","copy",{}]"#, json);
+                if let Err(e) = to.process_event(&relay_msg) {
+                    warn!("Failed to copy event: {:?}", e);
+                }
+                kept += 1;
+            }
+            Err(e) => warn!("Failed to serialize event: {:?}", e),
+        }
+    })?;
+    // Dropping the new database waits for its ingester to finish
+    drop(to);
+    Ok((kept, skipped))
+}
+
+/// Copy every event of `ndb` into a fresh database at `dir`, e.g. for an
+/// export. Events are read in one transaction, so the copy is consistent
+/// while `ndb` is in use. Returns the events copied.
+pub fn snapshot(ndb: &Ndb, dir: &Path) -> Result<u64, String> {
+    copy_notes(ndb, dir, |_| true).map(|(kept, _)| kept)
+}
+
+/// Rewrite the database without the events of a staged purge. Must run
+/// before nostrdb is opened; returns the number of events removed, None if
+/// nothing was staged.
+pub fn apply_pending_purge(data_dir: &Path) -> Result<Option<u64>, String> {
+    let ndb_dir = data_dir.join(NDB_DIR);
+    let previous = data_dir.join(PREVIOUS_DIR);
+    if !ndb_dir.exists() && previous.exists() {
+        // A swap was cut short after moving the old database away
+        warn!("Restoring nostrdb from {:?}", previous);
+        fs::rename(&previous, &ndb_dir)
+            .map_err(|e| format!("Failed to restore {:?}: {}", previous, e))?;
+    }
+    let rebuild_dir = data_dir.join(REBUILD_DIR);
+    if rebuild_dir.exists() {
+        // Left over from a rewrite that didn't finish
+        fs::remove_dir_all(&rebuild_dir)
+            .map_err(|e| format!("Failed to clear {:?}: {}", rebuild_dir, e))?;
+    }
+    let Some(rule) = pending_purge(data_dir) else {
+        return Ok(None);
+    };
+    // A purge that fails isn't retried on every start
+    fs::remove_file(data_dir.join(PENDING_FILE))
+        .map_err(|e| format!("Failed to remove staged purge: {}", e))?;
+
+    if !ndb_dir.exists() {
+        return Ok(Some(0));
+    }
+    let (kept, removed) = {
+        let old = open_ndb(&ndb_dir)?;
+        copy_notes(&old, &rebuild_dir, |note| !rule.purges_note(note))?
+    };
+
+    if previous.exists() {
+        fs::remove_dir_all(&previous)
+            .map_err(|e| format!("Failed to clear {:?}: {}", previous, e))?;
+    }
+    let move_err = |e: std::io::Error| format!("Failed to swap in rewritten nostrdb: {}", e);
+    let bytes_before = dir_bytes(&ndb_dir);
+    fs::rename(&ndb_dir, &previous).map_err(move_err)?;
+    if let Err(e) = fs::rename(&rebuild_dir, &ndb_dir) {
+        // Put the old database back; failing that, the next start does
+        if let Err(e) = fs::rename(&previous, &ndb_dir) {
+            error!("Failed to restore nostrdb from {:?}: {}", previous, e);
+        }
+        return Err(move_err(e));
+    }
+    if let Err(e) = fs::remove_dir_all(&previous) {
+        warn!("Failed to remove {:?}: {}", previous, e);
+    }
+    info!(
+        "Rewrote nostrdb: {} events kept, {} removed, {} -> {} bytes",
+        kept,
+        removed,
+        bytes_before,
+        dir_bytes(&ndb_dir)
+    );
+    Ok(Some(removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_purge_rule() {
+        let me = "a".repeat(64);
+        let other = "b".repeat(64);
+        let by_age = PurgeRule {
+            older_than: Some(1000),
+            keep_authors: vec![me.clone()],
+            ..Default::default()
+        };
+        assert!(by_age.purges(1, 999, &other));
+        assert!(!by_age.purges(1, 1000, &other));
+        assert!(!by_age.purges(1, 999, &me));
+        // Replaceable events stay unless their kind is named
+        assert!(!by_age.purges(0, 1, &other));
+        assert!(!by_age.purges(30078, 1, &other));
+
+        let by_kind = PurgeRule {
+            kinds: vec![7, 0],
+            ..Default::default()
+        };
+        assert!(by_kind.purges(7, 5000, &other));
+        assert!(by_kind.purges(0, 5000, &other));
+        assert!(!by_kind.purges(1, 5000, &other));
+
+        let both = PurgeRule {
+            older_than: Some(1000),
+            kinds: vec![0],
+            ..Default::default()
+        };
+        assert!(both.purges(0, 999, &other));
+        assert!(!both.purges(0, 1000, &other));
+
+        let compaction = PurgeRule::default();
+        assert!(compaction.is_compaction());
+        assert!(!compaction.purges(1, 0, &other));
+    }
+
+    #[test]
+    fn test_staged_purge_is_applied_once() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(pending_purge(dir.path()), None);
+        assert_eq!(apply_pending_purge(dir.path()).unwrap(), None);
+
+        let rule = PurgeRule {
+            kinds: vec![7],
+            ..Default::default()
+        };
+        schedule_purge(dir.path(), &rule).unwrap();
+        assert_eq!(pending_purge(dir.path()), Some(rule));
+        // No database yet, nothing to rewrite
+        assert_eq!(apply_pending_purge(dir.path()).unwrap(), Some(0));
+        assert_eq!(pending_purge(dir.path()), None);
+    }
+
+    #[test]
+    fn test_interrupted_swap_is_undone() {
+        let dir = TempDir::new().unwrap();
+        let previous = dir.path().join(PREVIOUS_DIR);
+        fs::create_dir(&previous).unwrap();
+        fs::write(previous.join("data.mdb"), "events").unwrap();
+
+        assert_eq!(apply_pending_purge(dir.path()).unwrap(), None);
+        let restored = dir.path().join(NDB_DIR).join("data.mdb");
+        assert_eq!(fs::read_to_string(restored).unwrap(), "events");
+        assert!(!previous.exists());
+    }
+}
@@ -2,19 +2,30 @@
 //!
 //! Handles subscription and publishing to Nostr relays.
 
+use nostr_sdk::nips::nip44;
 use nostr_sdk::{
-    Client, EventId, Filter, Keys, Kind, NostrSigner, PublicKey, RelayPoolNotification, SecretKey,
-    SubscriptionId,
+    Client, Event, EventId, Filter, Keys, Kind, NostrSigner, PublicKey, RelayOptions,
+    RelayPoolNotification, RelayStatus, SecretKey, SubscriptionId,
 };
 use nostrdb::Ndb;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::{AppHandle, Emitter};
 use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 
-use super::types::{RelayStatEntry, WorkerResponse};
+use super::dedup::EventDedup;
+use super::outbox::{Outbox, SendResult};
+use super::pow::PowMiner;
+use super::relay_health::RelayHealth;
+use super::social_graph::SocialGraphCache;
+use super::tree_roots::{self, Commit};
+use super::types::{RelayStatEntry, WorkerCid, WorkerResponse};
+use super::wot::Wot;
+use crate::htree::TreeVisibility;
+use crate::proxy;
 
 /// Default relays for the worker - matches web app defaults in settings.ts
 const DEFAULT_RELAYS: &[&str] = &[
@@ -26,6 +37,24 @@ const DEFAULT_RELAYS: &[&str] = &[
     "wss://temp.iris.to",
 ];
 
+/// How often relay connections are checked for reconnects
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time a reconnect attempt gets before it counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Relays are reconnected by `NostrManager`, with backoff, not by nostr-sdk,
+/// and connect through the relay proxy if one is set
+fn relay_opts() -> RelayOptions {
+    proxy::relay_options(RelayOptions::new().reconnect(false))
+}
+
+/// Hint relays come from other people's events, so they're only read from:
+/// our events aren't published to relays someone else picked
+fn hint_relay_opts() -> RelayOptions {
+    relay_opts().write(false)
+}
+
 /// Get relays to use - checks IRIS_TEST_RELAY env var first, then falls back to defaults
 fn get_initial_relays() -> Vec<String> {
     // Check for test relay override (e.g., "ws://localhost:4736")
@@ -38,11 +67,56 @@ fn get_initial_relays() -> Vec<String> {
 
 use std::collections::HashSet;
 
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Relay URL as the pool keys it, e.g. with the trailing slash
+fn normalize_relay_url(url: &str) -> Result<String, String> {
+    nostr_sdk::Url::parse(url.trim())
+        .map(|url| url.to_string())
+        .map_err(|e| format!("Invalid relay URL {}: {}", url, e))
+}
+
 /// Active subscription with its filters and relay state
 struct ActiveSubscription {
     filters: Vec<Filter>,
     sdk_id: Option<SubscriptionId>,
     sent_to: HashSet<String>, // relay URLs that have received this sub
+    /// Only these relays get the subscription; None for the configured ones
+    relays: Option<HashSet<String>>,
+}
+
+impl ActiveSubscription {
+    /// Relays of `pool` the subscription goes to. Hint relays, added only
+    /// for targeted subscriptions, don't get the others.
+    fn eligible(&self, pool: &HashSet<String>, hint_relays: &HashSet<String>) -> HashSet<String> {
+        pool.iter()
+            .filter(|url| match &self.relays {
+                Some(targets) => targets.contains(*url),
+                None => !hint_relays.contains(*url),
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Relays of `connected` the subscription still has to be sent to.
+    /// Relays no longer connected are dropped from `sent_to`: they lose
+    /// their subscriptions with the connection.
+    fn missing_relays(
+        &mut self,
+        connected: &HashSet<String>,
+        hint_relays: &HashSet<String>,
+    ) -> Vec<String> {
+        self.sent_to.retain(|url| connected.contains(url));
+        self.eligible(connected, hint_relays)
+            .into_iter()
+            .filter(|url| !self.sent_to.contains(url))
+            .collect()
+    }
 }
 
 /// Manages Nostr connections and subscriptions
@@ -52,6 +126,20 @@ pub struct NostrManager {
     identity: Arc<RwLock<Option<Keys>>>,
     shutdown_tx: Arc<RwLock<Option<mpsc::Sender<()>>>>,
     ndb: Arc<RwLock<Option<Arc<Ndb>>>>,
+    /// Policy flagging events from untrusted authors
+    wot: Option<Arc<Wot>>,
+    /// Follow-distance cache, invalidated by contact lists
+    social_graph: Option<Arc<SocialGraphCache>>,
+    /// Reconnect backoff and health score of each relay
+    health: Arc<RelayHealth>,
+    /// Relays in the pool only because a targeted subscription asked for them
+    hint_relays: Arc<RwLock<HashSet<String>>>,
+    /// Events already sent to the frontend, by subscription
+    dedup: Arc<EventDedup>,
+    /// Published events kept until a relay accepts them
+    outbox: Option<Arc<Outbox>>,
+    /// Mines proof of work into published events that need it
+    pow: Option<Arc<PowMiner>>,
 }
 
 impl NostrManager {
@@ -67,6 +155,12 @@ impl NostrManager {
     pub fn get_keys(&self) -> Option<Keys> {
         self.identity.read().clone()
     }
+
+    /// Events already sent to the frontend, for cached events to go through
+    /// the same check as relay ones
+    pub fn dedup(&self) -> &EventDedup {
+        &self.dedup
+    }
 }
 
 impl NostrManager {
@@ -77,9 +171,41 @@ impl NostrManager {
             identity: Arc::new(RwLock::new(None)),
             shutdown_tx: Arc::new(RwLock::new(None)),
             ndb: Arc::new(RwLock::new(None)),
+            wot: None,
+            social_graph: None,
+            health: Arc::new(RelayHealth::default()),
+            hint_relays: Arc::new(RwLock::new(HashSet::new())),
+            dedup: Arc::new(EventDedup::default()),
+            outbox: None,
+            pow: None,
         }
     }
 
+    /// Flag events forwarded to the frontend by `wot`
+    pub fn with_wot(mut self, wot: Arc<Wot>) -> Self {
+        self.wot = Some(wot);
+        self
+    }
+
+    /// Invalidate `social_graph` whenever a contact list comes in
+    pub fn with_social_graph(mut self, social_graph: Arc<SocialGraphCache>) -> Self {
+        self.social_graph = Some(social_graph);
+        self
+    }
+
+    /// Queue published events in `outbox`, so they're sent again until a
+    /// relay accepts them
+    pub fn with_outbox(mut self, outbox: Arc<Outbox>) -> Self {
+        self.outbox = Some(outbox);
+        self
+    }
+
+    /// Mine published events of ours to the difficulty `pow` sets
+    pub fn with_pow(mut self, pow: Arc<PowMiner>) -> Self {
+        self.pow = Some(pow);
+        self
+    }
+
     /// Initialize the Nostr client and connect to relays
     pub async fn ensure_client(&self, app_handle: Option<AppHandle>, ndb: Option<Arc<Ndb>>) -> Result<(), String> {
         {
@@ -110,7 +236,10 @@ impl NostrManager {
         // Add relays (uses IRIS_TEST_RELAY env var if set, otherwise defaults)
         let relays = get_initial_relays();
         for relay in &relays {
-            if let Err(e) = client.add_relay(relay.as_str()).await {
+            if let Err(e) = client
+                .add_relay_with_opts(relay.as_str(), relay_opts())
+                .await
+            {
                 warn!("Failed to add relay {}: {}", relay, e);
             }
         }
@@ -129,6 +258,7 @@ impl NostrManager {
             }
         });
         info!("Connecting to Nostr relays in background...");
+        self.start_relay_supervisor(client.clone());
 
         // Store ndb reference for publish
         if let Some(ref ndb_ref) = ndb {
@@ -144,51 +274,180 @@ impl NostrManager {
         Ok(())
     }
 
-    /// Retry sending subscriptions that haven't reached any relay yet
-    async fn retry_pending_subscriptions(client: &Client, subscriptions: &Arc<RwLock<HashMap<String, ActiveSubscription>>>) {
-        let pending: Vec<(String, Vec<Filter>, SubscriptionId)> = {
-            let subs = subscriptions.read();
-            subs.iter()
-                .filter(|(_, active)| active.sent_to.is_empty())
-                .map(|(id, active)| {
+    /// Reconnect relays that dropped or failed to connect, as their backoff
+    /// in `health` allows, and send the outbox's due events while any relay
+    /// is up. A relay (re)connecting makes every pending event due.
+    fn start_relay_supervisor(&self, client: Client) {
+        let health = self.health.clone();
+        let outbox = self.outbox.clone();
+        tokio::spawn(async move {
+            let mut connected = HashSet::new();
+            loop {
+                tokio::time::sleep(SUPERVISE_INTERVAL).await;
+                let now = Instant::now();
+                let was_connected = std::mem::take(&mut connected);
+                for (url, relay) in client.relays().await {
+                    let url = url.to_string();
+                    match relay.status().await {
+                        RelayStatus::Connected => {
+                            health.connected(&url, now);
+                            connected.insert(url);
+                        }
+                        RelayStatus::Disconnected | RelayStatus::Terminated => {
+                            health.disconnected(&url, now);
+                            if !health.begin_attempt(&url, now) {
+                                continue;
+                            }
+                            debug!("Reconnecting to relay {}", url);
+                            let health = health.clone();
+                            tokio::spawn(async move {
+                                relay.connect(Some(CONNECT_TIMEOUT)).await;
+                                match relay.status().await {
+                                    RelayStatus::Connected => {
+                                        health.connected(&url, Instant::now())
+                                    }
+                                    status => {
+                                        let error = match status {
+                                            RelayStatus::Pending | RelayStatus::Connecting => {
+                                                "Connection timed out"
+                                            }
+                                            _ => "Connection failed",
+                                        };
+                                        debug!("Relay {}: {}", url, error);
+                                        health.failed(&url, error.to_string(), Instant::now());
+                                    }
+                                }
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+
+                let Some(outbox) = &outbox else {
+                    continue;
+                };
+                if connected.iter().any(|url| !was_connected.contains(url)) {
+                    if let Err(e) = outbox.bring_forward(now_secs()) {
+                        warn!("Failed to reschedule outbox: {}", e);
+                    }
+                }
+                if connected.is_empty() {
+                    continue;
+                }
+                for (event_id, event_json) in outbox.due(now_secs()) {
+                    let client = client.clone();
+                    let outbox = outbox.clone();
+                    tokio::spawn(async move {
+                        Self::send_from_outbox(&client, &outbox, &event_id, event_json).await;
+                    });
+                }
+            }
+        });
+    }
+
+    /// Send an event of `outbox` once and record how it went. Returns
+    /// whether it's delivered.
+    async fn send_from_outbox(
+        client: &Client,
+        outbox: &Outbox,
+        event_id: &str,
+        event_json: serde_json::Value,
+    ) -> bool {
+        let result = match serde_json::from_value::<Event>(event_json) {
+            Ok(event) => match client.send_event(event).await {
+                Ok(output) => SendResult {
+                    accepted: output.success.iter().map(|url| url.to_string()).collect(),
+                    rejected: output
+                        .failed
+                        .iter()
+                        .map(|(url, error)| {
+                            let error = error.clone().unwrap_or_else(|| "Not sent".to_string());
+                            (url.to_string(), error)
+                        })
+                        .collect(),
+                    error: None,
+                },
+                Err(e) => SendResult {
+                    error: Some(format!("Publish error: {}", e)),
+                    ..Default::default()
+                },
+            },
+            Err(e) => SendResult {
+                error: Some(format!("Invalid event JSON: {}", e)),
+                ..Default::default()
+            },
+        };
+        match outbox.record(event_id, result, now_secs()) {
+            Ok(true) => {
+                info!("Published event: {}", event_id);
+                true
+            }
+            Ok(false) => {
+                debug!("Event {} not accepted yet, kept in outbox", event_id);
+                false
+            }
+            Err(e) => {
+                warn!("Failed to record outbox delivery: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Send each active subscription to the connected relays that don't
+    /// have it yet: ones added by `set_relays` after it was made, ones that
+    /// connected late and ones that reconnected
+    async fn replay_subscriptions(
+        client: &Client,
+        subscriptions: &Arc<RwLock<HashMap<String, ActiveSubscription>>>,
+        hint_relays: &RwLock<HashSet<String>>,
+    ) {
+        let mut connected = HashSet::new();
+        for (url, relay) in client.relays().await {
+            if relay.status().await == RelayStatus::Connected {
+                connected.insert(url.to_string());
+            }
+        }
+
+        let missing: Vec<(String, Vec<Filter>, SubscriptionId, Vec<String>)> = {
+            let hint_relays = hint_relays.read();
+            let mut subs = subscriptions.write();
+            subs.iter_mut()
+                .filter_map(|(id, active)| {
+                    let urls = active.missing_relays(&connected, &hint_relays);
+                    if urls.is_empty() {
+                        return None;
+                    }
                     let sdk_id = active
                         .sdk_id
                         .clone()
                         .unwrap_or_else(|| SubscriptionId::new(id.clone()));
-                    (id.clone(), active.filters.clone(), sdk_id)
+                    Some((id.clone(), active.filters.clone(), sdk_id, urls))
                 })
                 .collect()
         };
 
-        if pending.is_empty() {
-            return;
-        }
-
-        debug!("Retrying {} pending subscriptions", pending.len());
-
-        for (sub_id, filters, sdk_id) in pending {
-            match client.subscribe_with_id(sdk_id.clone(), filters, None).await {
+        for (sub_id, filters, sdk_id, urls) in missing {
+            match client
+                .subscribe_with_id_to(urls.clone(), sdk_id.clone(), filters, None)
+                .await
+            {
                 Ok(output) => {
                     let mut subs = subscriptions.write();
                     if let Some(active) = subs.get_mut(&sub_id) {
                         active.sdk_id = Some(sdk_id);
-                        active.sent_to.clear();
                         for url in output.success.iter() {
                             active.sent_to.insert(url.to_string());
                         }
-                        if active.sent_to.is_empty() {
-                            debug!("Subscription {} retry queued (no relays connected)", sub_id);
-                        } else {
-                            info!(
-                                "Subscription {} retry succeeded, sent to {} relays",
-                                sub_id,
-                                active.sent_to.len()
-                            );
-                        }
+                        debug!(
+                            "Subscription {} replayed to {} of {} relays",
+                            sub_id,
+                            output.success.len(),
+                            urls.len()
+                        );
                     }
                 }
                 Err(e) => {
-                    debug!("Subscription {} retry failed: {}", sub_id, e);
+                    debug!("Subscription {} replay failed: {}", sub_id, e);
                 }
             }
         }
@@ -197,17 +456,23 @@ impl NostrManager {
     /// Start listening for relay events and forward to frontend
     async fn start_event_listener(&self, client: Client, app_handle: AppHandle, ndb: Option<Arc<Ndb>>) {
         let subscriptions = self.subscriptions.clone();
+        let wot = self.wot.clone();
+        let social_graph = self.social_graph.clone();
+        let health = self.health.clone();
+        let dedup = self.dedup.clone();
         let (tx, mut rx) = mpsc::channel::<()>(1);
         *self.shutdown_tx.write() = Some(tx);
 
         let client_for_retry = client.clone();
         let subs_for_retry = subscriptions.clone();
+        let hints_for_retry = self.hint_relays.clone();
 
         tokio::spawn(async move {
             tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
             loop {
-                Self::retry_pending_subscriptions(&client_for_retry, &subs_for_retry).await;
+                Self::replay_subscriptions(&client_for_retry, &subs_for_retry, &hints_for_retry)
+                    .await;
                 tokio::time::sleep(std::time::Duration::from_secs(2)).await;
             }
         });
@@ -236,6 +501,16 @@ impl NostrManager {
                                             }
                                         }
 
+                                        if event.kind == Kind::ContactList {
+                                            if let Some(ref social_graph) = social_graph {
+                                                let version = social_graph.invalidate();
+                                                let _ = app_handle.emit(
+                                                    "worker_response",
+                                                    &WorkerResponse::SocialGraphVersion { version },
+                                                );
+                                            }
+                                        }
+
                                         // Find the worker subscription ID from our mapping
                                         let sub_id = {
                                             let subs = subscriptions.read();
@@ -249,18 +524,26 @@ impl NostrManager {
                                             }
                                         };
 
+                                        let event_value = serde_json::to_value(&*event).unwrap_or_default();
+                                        let sub_id = sub_id.filter(|sub_id| dedup.admit(sub_id, &event_value));
                                         if let Some(sub_id) = sub_id {
                                             debug!("Received event for subscription {}", sub_id);
+                                            let untrusted = wot
+                                                .as_ref()
+                                                .is_some_and(|wot| wot.flags(&event.pubkey.to_hex()));
                                             let response = WorkerResponse::Event {
                                                 sub_id,
-                                                event: serde_json::to_value(&*event).unwrap_or_default(),
+                                                event: event_value,
+                                                untrusted,
                                             };
                                             if let Err(e) = app_handle.emit("worker_response", &response) {
                                                 error!("Failed to emit event: {}", e);
                                             }
                                         }
                                     }
-                                    RelayPoolNotification::Message { message, .. } => {
+                                    RelayPoolNotification::Message { relay_url, message } => {
+                                        health.message(&relay_url.to_string(), &message, Instant::now());
+
                                         // Handle EOSE
                                         if let nostr_sdk::RelayMessage::EndOfStoredEvents(sdk_sub_id) = message {
                                             let worker_sub_id = {
@@ -300,8 +583,26 @@ impl NostrManager {
 
     /// Subscribe to events with filters
     /// Stores subscription even if relays aren't connected - will be sent when they connect
-    pub async fn subscribe(&self, sub_id: String, filters: Vec<Filter>) -> Result<(), String> {
+    ///
+    /// With `relays`, the subscription goes to those relays only, which are
+    /// added to the pool if they aren't in it; otherwise to every configured
+    /// relay.
+    pub async fn subscribe(
+        &self,
+        sub_id: String,
+        filters: Vec<Filter>,
+        relays: Option<Vec<String>>,
+    ) -> Result<(), String> {
         debug!("Creating subscription {} with {} filters", sub_id, filters.len());
+        let targets = match relays {
+            Some(relays) if !relays.is_empty() => Some(
+                relays
+                    .iter()
+                    .map(|url| normalize_relay_url(url))
+                    .collect::<Result<HashSet<_>, _>>()?,
+            ),
+            _ => None,
+        };
 
         // Store the subscription with its filters
         let sdk_id = SubscriptionId::new(sub_id.clone());
@@ -309,12 +610,26 @@ impl NostrManager {
             filters: filters.clone(),
             sdk_id: Some(sdk_id.clone()),
             sent_to: HashSet::new(),
+            relays: targets,
         };
 
         // Try to send to connected relays
         let client = { self.client.read().clone() };
         if let Some(client) = client {
-            match client.subscribe_with_id(sdk_id, filters, None).await {
+            if let Some(targets) = &active_sub.relays {
+                self.add_hint_relays(&client, targets).await;
+            }
+            let pool: HashSet<String> = client
+                .relays()
+                .await
+                .keys()
+                .map(|url| url.to_string())
+                .collect();
+            let urls = active_sub.eligible(&pool, &self.hint_relays.read());
+            match client
+                .subscribe_with_id_to(urls, sdk_id, filters, None)
+                .await
+            {
                 Ok(output) => {
                     // Track which relays received it
                     for url in output.success.iter() {
@@ -331,6 +646,7 @@ impl NostrManager {
             debug!("Subscription {} queued (client not initialized)", sub_id);
         }
 
+        self.health.subscribed(&sub_id, Instant::now());
         self.subscriptions.write().insert(sub_id.clone(), active_sub);
         Ok(())
     }
@@ -342,30 +658,95 @@ impl NostrManager {
             subs.remove(sub_id)
         };
 
+        self.health.unsubscribed(sub_id);
+        self.dedup.forget(sub_id);
         if let Some(active_sub) = active_sub {
+            let client = { self.client.read().clone() };
             if let Some(sdk_id) = active_sub.sdk_id {
-                let client = { self.client.read().clone() };
-                if let Some(client) = client {
+                if let Some(client) = &client {
                     client.unsubscribe(sdk_id).await;
                 }
             }
+            if let (Some(client), Some(targets)) = (&client, &active_sub.relays) {
+                self.release_hint_relays(client, targets).await;
+            }
             info!("Unsubscribed: {}", sub_id);
         }
 
         Ok(())
     }
 
-    /// Publish an event
-    pub async fn publish(&self, event_json: serde_json::Value) -> Result<EventId, String> {
-        let client = {
-            let guard = self.client.read();
-            guard.clone().ok_or("Nostr client not initialized")?
+    /// Add the relays of `targets` that aren't in the pool, as read-only
+    /// hint relays
+    async fn add_hint_relays(&self, client: &Client, targets: &HashSet<String>) {
+        let pool = client.relays().await;
+        for url in targets {
+            if pool.keys().any(|known| known.as_str() == url) {
+                continue;
+            }
+            if let Err(e) = client
+                .add_relay_with_opts(url.as_str(), hint_relay_opts())
+                .await
+            {
+                warn!("Failed to add hint relay {}: {}", url, e);
+                continue;
+            }
+            self.hint_relays.write().insert(url.clone());
+            if let Err(e) = client.connect_relay(url.as_str()).await {
+                debug!("Failed to connect hint relay {}: {}", url, e);
+            }
+        }
+    }
+
+    /// Remove the hint relays of `targets` no active subscription targets
+    async fn release_hint_relays(&self, client: &Client, targets: &HashSet<String>) {
+        let unused: Vec<String> = {
+            let subs = self.subscriptions.read();
+            let mut hint_relays = self.hint_relays.write();
+            let unused: Vec<String> = targets
+                .iter()
+                .filter(|url| hint_relays.contains(*url))
+                .filter(|url| {
+                    !subs.values().any(|active| {
+                        active
+                            .relays
+                            .as_ref()
+                            .is_some_and(|relays| relays.contains(*url))
+                    })
+                })
+                .cloned()
+                .collect();
+            for url in &unused {
+                hint_relays.remove(url);
+            }
+            unused
         };
+        for url in unused {
+            if let Err(e) = client.remove_relay(url.as_str()).await {
+                warn!("Failed to remove hint relay {}: {}", url, e);
+            }
+            self.health.forget(&url);
+        }
+    }
+
+    /// Publish an event
+    pub async fn publish(&self, mut event_json: serde_json::Value) -> Result<EventId, String> {
+        let client = { self.client.read().clone() };
 
         // Parse the event JSON - this should be a signed event or event builder
-        let event: nostr_sdk::Event =
+        let mut event: nostr_sdk::Event =
             serde_json::from_value(event_json.clone()).map_err(|e| format!("Invalid event JSON: {}", e))?;
 
+        // Mining changes the id, so it comes before the event is stored
+        if let (Some(pow), Some(keys)) = (&self.pow, self.get_keys()) {
+            let mined = pow.mine(event.clone(), &keys).await?;
+            if mined.id != event.id {
+                event_json = serde_json::to_value(&mined)
+                    .map_err(|e| format!("Failed to encode event: {}", e))?;
+                event = mined;
+            }
+        }
+
         // Store in nostrdb before sending (so republishTree can find it)
         if let Some(ndb) = self.ndb.read().as_ref() {
             let event_str = serde_json::to_string(&event_json).unwrap_or_default();
@@ -374,7 +755,36 @@ impl NostrManager {
                 debug!("nostrdb process_event error on publish: {:?}", e);
             }
         }
+        if event.kind == Kind::ContactList {
+            if let Some(social_graph) = &self.social_graph {
+                social_graph.invalidate();
+            }
+        }
+
+        // With an outbox the event is kept until a relay accepts it, so
+        // one published while offline is still sent later, but publishing
+        // only succeeds once a relay has it
+        if let Some(outbox) = &self.outbox {
+            let event_id = event.id;
+            let id = event_id.to_hex();
+            outbox.queue(&event_json, now_secs())?;
+            let delivered = match client {
+                Some(client) if outbox.begin_send(&id) => {
+                    Self::send_from_outbox(&client, outbox, &id, event_json).await
+                }
+                _ => outbox.entry(&id).is_some_and(|entry| entry.delivered),
+            };
+            if !delivered {
+                let reason = outbox
+                    .entry(&id)
+                    .and_then(|entry| entry.last_error)
+                    .unwrap_or_else(|| "No relay accepted it yet".to_string());
+                return Err(format!("Not delivered yet, kept in the outbox: {}", reason));
+            }
+            return Ok(event_id);
+        }
 
+        let client = client.ok_or("Nostr client not initialized")?;
         let output = client
             .send_event(event)
             .await
@@ -385,6 +795,41 @@ impl NostrManager {
         Ok(event_id)
     }
 
+    /// Sign a tree root event with the current identity and publish it
+    pub async fn publish_tree_root(
+        &self,
+        tree_name: &str,
+        cid: &WorkerCid,
+        visibility: &TreeVisibility,
+        link_secret: Option<&[u8; 32]>,
+        commit: &Commit,
+    ) -> Result<EventId, String> {
+        let keys = self.get_keys().ok_or("No signing identity set")?;
+        let event =
+            build_tree_commit_event(&keys, tree_name, cid, visibility, link_secret, commit)?;
+        let event_json =
+            serde_json::to_value(&event).map_err(|e| format!("Failed to encode event: {}", e))?;
+        self.publish(event_json).await
+    }
+
+    /// Unpublish one of our trees: publish its tombstone, then a NIP-09
+    /// deletion of its root events, naming `root_event` by id. Returns the
+    /// deletion's id.
+    pub async fn unpublish_tree(
+        &self,
+        tree_name: &str,
+        root_event: Option<EventId>,
+    ) -> Result<EventId, String> {
+        let keys = self.get_keys().ok_or("No signing identity set")?;
+        let tombstone = tree_roots::tombstone_event(&keys, tree_name)?;
+        let deletion = tree_roots::deletion_event(&keys, tree_name, root_event)?;
+        let encode = |event: &Event| {
+            serde_json::to_value(event).map_err(|e| format!("Failed to encode event: {}", e))
+        };
+        self.publish(encode(&tombstone)?).await?;
+        self.publish(encode(&deletion)?).await
+    }
+
     /// Fetch events matching filters (one-shot query, not subscription)
     pub async fn fetch_events(&self, filters: Vec<Filter>) -> Result<Vec<nostr_sdk::Event>, String> {
         let client = {
@@ -400,6 +845,50 @@ impl NostrManager {
         Ok(events)
     }
 
+    /// Fetch events matching filters from each connected relay, failing
+    /// unless at least one of them answered. For the current version of a
+    /// replaceable list that's about to be edited, where finding nothing
+    /// must mean there is none.
+    pub async fn fetch_events_answered(
+        &self,
+        filters: Vec<Filter>,
+        timeout: Duration,
+    ) -> Result<Vec<Event>, String> {
+        let client = {
+            let guard = self.client.read();
+            guard.clone().ok_or("Nostr client not initialized")?
+        };
+
+        let mut queries = Vec::new();
+        for (url, relay) in client.relays().await {
+            if relay.status().await != RelayStatus::Connected {
+                continue;
+            }
+            let filters = filters.clone();
+            queries.push(async move {
+                let result = relay
+                    .get_events_of(filters, timeout, nostr_sdk::FilterOptions::ExitOnEOSE)
+                    .await;
+                if let Err(e) = &result {
+                    debug!("Relay {} didn't answer: {}", url, e);
+                }
+                result.ok()
+            });
+        }
+
+        let mut answered = false;
+        let mut events = Vec::new();
+        let results = futures::future::join_all(queries).await;
+        for result in results.into_iter().flatten() {
+            answered = true;
+            events.extend(result);
+        }
+        if !answered {
+            return Err("No relay answered".to_string());
+        }
+        Ok(events)
+    }
+
     /// Set identity for signing events
     pub fn set_identity(&self, pubkey: &str, nsec: Option<&str>) -> Result<(), String> {
         // Validate pubkey format
@@ -426,6 +915,11 @@ impl NostrManager {
         Ok(())
     }
 
+    /// Drop the signing keys, e.g. when switching to a read-only account
+    pub fn clear_identity(&self) {
+        *self.identity.write() = None;
+    }
+
     /// Get the current public key
     pub fn get_pubkey(&self) -> Option<String> {
         let identity = self.identity.read();
@@ -446,17 +940,34 @@ impl NostrManager {
         };
 
         if let Some(client) = client {
-            // Remove all existing relays
+            // Remove all existing relays, except those of targeted
+            // subscriptions; any of them configured now stop being hints
+            let hint_relays = {
+                let mut hint_relays = self.hint_relays.write();
+                for relay in &relays {
+                    if let Ok(url) = normalize_relay_url(relay) {
+                        hint_relays.remove(&url);
+                    }
+                }
+                hint_relays.clone()
+            };
             let existing = client.relays().await;
             for url in existing.keys() {
+                if hint_relays.contains(url.as_str()) {
+                    continue;
+                }
                 if let Err(e) = client.remove_relay(url.as_str()).await {
                     warn!("Failed to remove relay {}: {}", url, e);
                 }
+                self.health.forget(url.as_str());
             }
 
             // Add new relays
             for relay in &relays {
-                if let Err(e) = client.add_relay(relay.as_str()).await {
+                if let Err(e) = client
+                    .add_relay_with_opts(relay.as_str(), relay_opts())
+                    .await
+                {
                     warn!("Failed to add relay {}: {}", relay, e);
                 }
             }
@@ -475,6 +986,38 @@ impl NostrManager {
         Ok(())
     }
 
+    /// Reconnect every relay, hint relays too, with the current proxy setting
+    pub async fn reconnect_relays(&self) {
+        let client = {
+            let guard = self.client.read();
+            guard.clone()
+        };
+        let Some(client) = client else {
+            return;
+        };
+
+        let existing = client.relays().await;
+        let hint_relays = self.hint_relays.read().clone();
+        for url in existing.keys() {
+            if let Err(e) = client.remove_relay(url.as_str()).await {
+                warn!("Failed to remove relay {}: {}", url, e);
+                continue;
+            }
+            let opts = if hint_relays.contains(url.as_str()) {
+                hint_relay_opts()
+            } else {
+                relay_opts()
+            };
+            if let Err(e) = client.add_relay_with_opts(url.as_str(), opts).await {
+                warn!("Failed to add relay {}: {}", url, e);
+            }
+        }
+        tokio::spawn(async move {
+            client.connect().await;
+        });
+        info!("Reconnecting {} relays", existing.len());
+    }
+
     /// Get current relay URLs
     pub async fn get_relays(&self) -> Vec<String> {
         let client = {
@@ -483,11 +1026,13 @@ impl NostrManager {
         };
 
         if let Some(client) = client {
+            let hint_relays = self.hint_relays.read().clone();
             client
                 .relays()
                 .await
                 .keys()
                 .map(|u| u.to_string())
+                .filter(|url| !hint_relays.contains(url))
                 .collect()
         } else {
             DEFAULT_RELAYS.iter().map(|s| s.to_string()).collect()
@@ -506,16 +1051,21 @@ impl NostrManager {
             match tokio::time::timeout(std::time::Duration::from_millis(500), client.relays()).await {
                 Ok(relays) => {
                     let mut stats = Vec::new();
+                    let now = Instant::now();
                     for (url, relay) in relays.iter() {
                         let status = relay.status().await;
                         let connected = status == nostr_sdk::RelayStatus::Connected;
                         let connecting = status == nostr_sdk::RelayStatus::Connecting
                             || status == nostr_sdk::RelayStatus::Pending;
-                        stats.push(RelayStatEntry {
-                            url: url.to_string(),
+                        let traffic = relay.stats();
+                        stats.push(self.health.entry(
+                            url.as_str(),
                             connected,
                             connecting,
-                        });
+                            traffic.bytes_sent() as u64,
+                            traffic.bytes_received() as u64,
+                            now,
+                        ));
                     }
                     return stats;
                 }
@@ -526,13 +1076,10 @@ impl NostrManager {
         }
 
         // Return default relays as disconnected (either no client or timeout)
+        let now = Instant::now();
         DEFAULT_RELAYS
             .iter()
-            .map(|url| RelayStatEntry {
-                url: url.to_string(),
-                connected: false,
-                connecting: false,
-            })
+            .map(|url| self.health.entry(url, false, false, 0, 0, now))
             .collect()
     }
 
@@ -558,6 +1105,85 @@ impl Default for NostrManager {
     }
 }
 
+/// Build a signed hashtree root event for `tree_name`.
+///
+/// Public trees carry the CHK key in a plain `key` tag. Private trees carry it
+/// only NIP-44 encrypted to the author (`selfEncryptedKey`), so relays and
+/// other readers see nothing but the root hash. Link-visible trees carry the
+/// key XOR-masked with `link_secret` (`encryptedKey`); the secret travels only
+/// in share URLs, plus a NIP-44 copy for the author (`selfEncryptedLinkKey`).
+/// The format is `hashtree_resolver`'s, which the CLI publishes with too.
+pub fn build_tree_root_event(
+    keys: &Keys,
+    tree_name: &str,
+    cid: &WorkerCid,
+    visibility: &TreeVisibility,
+    link_secret: Option<&[u8; 32]>,
+) -> Result<Event, String> {
+    build_tree_commit_event(
+        keys,
+        tree_name,
+        cid,
+        visibility,
+        link_secret,
+        &Commit::default(),
+    )
+}
+
+/// A tree root event carrying `commit`'s message and parent root
+pub fn build_tree_commit_event(
+    keys: &Keys,
+    tree_name: &str,
+    cid: &WorkerCid,
+    visibility: &TreeVisibility,
+    link_secret: Option<&[u8; 32]>,
+    commit: &Commit,
+) -> Result<Event, String> {
+    let commit_tags = commit.tags(keys, visibility)?;
+    let cid = hashtree_core::Cid {
+        hash: hashtree_core::from_hex(&cid.hash).map_err(|e| format!("Invalid hash: {}", e))?,
+        key: cid
+            .key
+            .as_deref()
+            .map(hashtree_core::key_from_hex)
+            .transpose()
+            .map_err(|e| format!("Invalid key: {}", e))?,
+    };
+    let visibility = match visibility {
+        TreeVisibility::Public => hashtree_core::TreeVisibility::Public,
+        TreeVisibility::LinkVisible => hashtree_core::TreeVisibility::LinkVisible,
+        TreeVisibility::Private => hashtree_core::TreeVisibility::Private,
+    };
+    hashtree_resolver::nostr::tree_root_event(keys, tree_name, &cid, visibility, link_secret)
+        .map_err(|e| e.to_string())?
+        .add_tags(commit_tags)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+/// Decrypt a `selfEncryptedKey` tag value. Only the author's keys can.
+pub fn decrypt_self_encrypted_key(keys: &Keys, ciphertext: &str) -> Option<String> {
+    let key_hex = nip44::decrypt(keys.secret_key(), &keys.public_key(), ciphertext).ok()?;
+    (key_hex.len() == 64 && key_hex.chars().all(|c| c.is_ascii_hexdigit())).then_some(key_hex)
+}
+
+/// Read one of our own tree root events back: the root cid with its key,
+/// the tree's visibility and, for link-visible trees, the link secret.
+/// None if the event isn't ours or its key can't be recovered.
+pub fn read_own_tree_root(
+    keys: &Keys,
+    event: &Event,
+) -> Option<(WorkerCid, TreeVisibility, Option<[u8; 32]>)> {
+    if event.pubkey != keys.public_key() {
+        return None;
+    }
+    let root = tree_roots::tree_root(event, Some(keys))?;
+    if root.visibility != TreeVisibility::Public && root.cid.key.is_none() {
+        return None;
+    }
+    Some((root.cid, root.visibility, root.link_secret))
+}
+
 /// Helper to convert serde_json::Value filters to nostr-sdk Filters
 pub fn parse_filters(filters_json: Vec<serde_json::Value>) -> Result<Vec<Filter>, String> {
     filters_json
@@ -681,6 +1307,68 @@ mod tests {
         assert!(manager.get_pubkey().is_none());
     }
 
+    #[test]
+    fn test_missing_relays_replays_to_new_and_reconnected_relays() {
+        let relays = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect();
+        let no_hints = HashSet::new();
+        let mut active = ActiveSubscription {
+            filters: Vec::new(),
+            sdk_id: None,
+            sent_to: relays(&["wss://a/", "wss://b/"]),
+            relays: None,
+        };
+
+        // Added by set_relays after the subscription was sent
+        let connected = relays(&["wss://a/", "wss://b/", "wss://c/"]);
+        assert_eq!(
+            active.missing_relays(&connected, &no_hints),
+            vec!["wss://c/"]
+        );
+        active.sent_to.insert("wss://c/".to_string());
+        assert!(active.missing_relays(&connected, &no_hints).is_empty());
+
+        // b drops, then comes back
+        let without_b = relays(&["wss://a/", "wss://c/"]);
+        assert!(active.missing_relays(&without_b, &no_hints).is_empty());
+        assert_eq!(active.sent_to, without_b);
+        assert_eq!(
+            active.missing_relays(&connected, &no_hints),
+            vec!["wss://b/"]
+        );
+    }
+
+    #[test]
+    fn test_targeted_subscriptions_keep_to_their_relays() {
+        let relays = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect();
+        let pool = relays(&["wss://a/", "wss://b/", "wss://hint/"]);
+        let hints = relays(&["wss://hint/"]);
+        let mut targeted = ActiveSubscription {
+            filters: Vec::new(),
+            sdk_id: None,
+            sent_to: HashSet::new(),
+            relays: Some(relays(&["wss://b/", "wss://hint/"])),
+        };
+        let mut untargeted = ActiveSubscription {
+            filters: Vec::new(),
+            sdk_id: None,
+            sent_to: HashSet::new(),
+            relays: None,
+        };
+
+        let mut sent = targeted.missing_relays(&pool, &hints);
+        sent.sort();
+        assert_eq!(sent, vec!["wss://b/", "wss://hint/"]);
+        let mut sent = untargeted.missing_relays(&pool, &hints);
+        sent.sort();
+        assert_eq!(sent, vec!["wss://a/", "wss://b/"]);
+
+        assert_eq!(
+            normalize_relay_url(" wss://relay.example ").unwrap(),
+            "wss://relay.example/"
+        );
+        assert!(normalize_relay_url("not a url").is_err());
+    }
+
     #[test]
     fn test_set_identity_with_npub() {
         let manager = NostrManager::new();
@@ -718,6 +1406,127 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    fn tag_value(event: &Event, name: &str) -> Option<String> {
+        event.tags.iter().find_map(|tag| {
+            let values = tag.as_slice();
+            (values.first().map(String::as_str) == Some(name)).then(|| values[1].clone())
+        })
+    }
+
+    #[test]
+    fn test_build_private_tree_root_event() {
+        let keys = Keys::generate();
+        let cid = WorkerCid {
+            hash: "a".repeat(64),
+            key: Some("b".repeat(64)),
+        };
+
+        let event =
+            build_tree_root_event(&keys, "docs/private", &cid, &TreeVisibility::Private, None)
+                .unwrap();
+        assert!(event.verify().is_ok());
+        assert_eq!(tag_value(&event, "hash"), Some(cid.hash.clone()));
+        assert_eq!(tag_value(&event, "key"), None);
+
+        // The key appears only inside the NIP-44 ciphertext
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(!json.contains(&"b".repeat(64)));
+        let ciphertext = tag_value(&event, "selfEncryptedKey").unwrap();
+        assert_eq!(
+            decrypt_self_encrypted_key(&keys, &ciphertext),
+            cid.key.clone()
+        );
+        assert_eq!(
+            decrypt_self_encrypted_key(&Keys::generate(), &ciphertext),
+            None
+        );
+
+        let keyless = WorkerCid {
+            hash: cid.hash.clone(),
+            key: None,
+        };
+        assert!(
+            build_tree_root_event(&keys, "docs", &keyless, &TreeVisibility::Private, None).is_err()
+        );
+    }
+
+    #[test]
+    fn test_build_public_tree_root_event() {
+        let keys = Keys::generate();
+        let cid = WorkerCid {
+            hash: "c".repeat(64),
+            key: Some("d".repeat(64)),
+        };
+        let event =
+            build_tree_root_event(&keys, "public", &cid, &TreeVisibility::Public, None).unwrap();
+        assert_eq!(tag_value(&event, "key"), cid.key);
+        assert_eq!(tag_value(&event, "d").as_deref(), Some("public"));
+        assert_eq!(tag_value(&event, "selfEncryptedKey"), None);
+    }
+
+    #[test]
+    fn test_build_link_visible_tree_root_event() {
+        let keys = Keys::generate();
+        let key = [0x11u8; 32];
+        let secret = [0x22u8; 32];
+        let cid = WorkerCid {
+            hash: "e".repeat(64),
+            key: Some(hashtree_core::to_hex(&key)),
+        };
+
+        let event = build_tree_root_event(
+            &keys,
+            "shared",
+            &cid,
+            &TreeVisibility::LinkVisible,
+            Some(&secret),
+        )
+        .unwrap();
+        assert_eq!(tag_value(&event, "key"), None);
+        let masked =
+            hashtree_core::key_from_hex(&tag_value(&event, "encryptedKey").unwrap()).unwrap();
+        assert_ne!(masked, key);
+        assert_eq!(hashtree_core::xor_keys(&masked, &secret), key);
+
+        assert!(
+            build_tree_root_event(&keys, "shared", &cid, &TreeVisibility::LinkVisible, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_read_own_tree_root() {
+        let keys = Keys::generate();
+        let secret = [0x33u8; 32];
+        let cid = WorkerCid {
+            hash: "f".repeat(64),
+            key: Some("1".repeat(64)),
+        };
+
+        let private =
+            build_tree_root_event(&keys, "notes", &cid, &TreeVisibility::Private, None).unwrap();
+        let (read, visibility, link_secret) = read_own_tree_root(&keys, &private).unwrap();
+        assert_eq!((read.hash, read.key), (cid.hash.clone(), cid.key.clone()));
+        assert_eq!(visibility, TreeVisibility::Private);
+        assert_eq!(link_secret, None);
+
+        let shared = build_tree_root_event(
+            &keys,
+            "notes",
+            &cid,
+            &TreeVisibility::LinkVisible,
+            Some(&secret),
+        )
+        .unwrap();
+        let (read, visibility, link_secret) = read_own_tree_root(&keys, &shared).unwrap();
+        assert_eq!(read.key, cid.key);
+        assert_eq!(visibility, TreeVisibility::LinkVisible);
+        assert_eq!(link_secret, Some(secret));
+
+        // Someone else's event, or one we can't decrypt
+        assert!(read_own_tree_root(&Keys::generate(), &private).is_none());
+    }
+
     #[test]
     fn test_set_identity_invalid() {
         let manager = NostrManager::new();
@@ -0,0 +1,469 @@
+//! Native notifications for share and sync events
+//!
+//! Events are turned into OS notifications by `NotifyRules`, kept in
+//! `notify_rules.json`: a tree root from someone within `maxDistance`
+//! follows that mentions us in a `p` tag, a share event granting us a tree,
+//! and the end of a push or republish of all trees. The relay watch only
+//! asks for events from the moment it started, so reconnecting doesn't
+//! repeat old notifications.
+
+use nostr_sdk::nips::nip19::ToBech32;
+use nostr_sdk::{
+    Event, Filter, Keys, Kind, PublicKey, RelayPoolNotification, SubscriptionId, Timestamp,
+};
+use nostrdb::{Ndb, Transaction};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::nostr::NostrManager;
+use super::shares::{self, SHARE_LABEL};
+use super::types::WorkerResponse;
+
+/// Subscription id of the relay watch
+const WATCH_SUB_ID: &str = "notify-watch";
+/// Label of tree root events
+const TREE_LABEL: &str = "hashtree";
+const KIND_HASHTREE: u16 = 30078;
+
+/// Which events show a notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotifyRules {
+    pub enabled: bool,
+    /// Trees that mention us
+    pub mentions: bool,
+    /// Follow distance a mention must come from; 1 is people we follow
+    pub max_distance: usize,
+    /// Trees shared with us
+    pub shares: bool,
+    pub sync_completed: bool,
+    pub sync_failed: bool,
+}
+
+impl Default for NotifyRules {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mentions: true,
+            max_distance: 2,
+            shares: true,
+            sync_completed: true,
+            sync_failed: true,
+        }
+    }
+}
+
+/// Something that may be worth a notification
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotifyEvent {
+    Mention {
+        author: String,
+        tree: String,
+        /// Follow distance of the author, None if not connected
+        distance: Option<usize>,
+    },
+    ShareReceived {
+        author: String,
+        tree: String,
+    },
+    SyncFinished {
+        summary: String,
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+impl NotifyRules {
+    /// The notification `event` shows, if any
+    pub fn notification(&self, event: &NotifyEvent) -> Option<Notification> {
+        if !self.enabled {
+            return None;
+        }
+        let (title, body) = match event {
+            NotifyEvent::Mention {
+                author,
+                tree,
+                distance,
+            } => {
+                if !self.mentions || !distance.is_some_and(|d| d <= self.max_distance) {
+                    return None;
+                }
+                (
+                    "Mentioned in a tree",
+                    format!("{} mentioned you in {}", author, tree),
+                )
+            }
+            NotifyEvent::ShareReceived { author, tree } => {
+                if !self.shares {
+                    return None;
+                }
+                (
+                    "Tree shared with you",
+                    format!("{} shared {}", author, tree),
+                )
+            }
+            NotifyEvent::SyncFinished {
+                summary,
+                error: None,
+            } => {
+                if !self.sync_completed {
+                    return None;
+                }
+                ("Sync complete", summary.clone())
+            }
+            NotifyEvent::SyncFinished {
+                summary,
+                error: Some(error),
+            } => {
+                if !self.sync_failed {
+                    return None;
+                }
+                ("Sync failed", format!("{}: {}", summary, error))
+            }
+        };
+        Some(Notification {
+            title: title.to_string(),
+            body,
+        })
+    }
+}
+
+/// Mention or share addressed to `keys` in `event`, None for anything else
+pub fn classify(
+    keys: &Keys,
+    event: &Event,
+    distance: impl FnOnce(&PublicKey) -> Option<usize>,
+) -> Option<NotifyEvent> {
+    if event.pubkey == keys.public_key() || event.kind != Kind::from(KIND_HASHTREE) {
+        return None;
+    }
+    let author = short_npub(&event.pubkey);
+    match shares::tag_value(event, "l")? {
+        SHARE_LABEL => {
+            shares::unwrap_share_event(keys, event)?;
+            Some(NotifyEvent::ShareReceived {
+                author,
+                tree: shares::tag_value(event, "tree")?.to_string(),
+            })
+        }
+        TREE_LABEL => {
+            let ours = keys.public_key().to_hex();
+            let mentioned = event.tags.iter().any(|tag| {
+                let values = tag.as_slice();
+                values.len() >= 2 && values[0] == "p" && values[1] == ours
+            });
+            mentioned.then(|| NotifyEvent::Mention {
+                author,
+                tree: shares::tag_value(event, "d")
+                    .unwrap_or_default()
+                    .to_string(),
+                distance: distance(&event.pubkey),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Outcome of a push or republish of all trees, None for other requests
+pub fn sync_event(kind: &str, response: &WorkerResponse) -> Option<NotifyEvent> {
+    let (summary, error) = match (kind, response) {
+        (
+            "pushToBlossom",
+            WorkerResponse::PushResult {
+                pushed,
+                skipped,
+                failed,
+                ..
+            },
+        ) => (
+            format!("Pushed {} blocks, {} already stored", pushed, skipped),
+            (*failed > 0).then(|| format!("{} blocks failed", failed)),
+        ),
+        (
+            "republishTrees",
+            WorkerResponse::RepublishResult {
+                count,
+                encryption_errors,
+                ..
+            },
+        ) => (
+            format!("Republished {} trees", count),
+            encryption_errors
+                .as_ref()
+                .filter(|errors| !errors.is_empty())
+                .map(|errors| format!("{} trees failed", errors.len())),
+        ),
+        ("pushToBlossom", WorkerResponse::Error { error, .. }) => {
+            ("Push to Blossom".to_string(), Some(error.clone()))
+        }
+        ("republishTrees", WorkerResponse::Error { error, .. }) => {
+            ("Republishing trees".to_string(), Some(error.clone()))
+        }
+        _ => return None,
+    };
+    Some(NotifyEvent::SyncFinished { summary, error })
+}
+
+fn short_npub(pubkey: &PublicKey) -> String {
+    match pubkey.to_bech32() {
+        Ok(npub) => format!("{}…", &npub[..12]),
+        Err(_) => pubkey.to_hex()[..8].to_string(),
+    }
+}
+
+/// Notification rules and the relay watch feeding them
+pub struct Notifier {
+    path: PathBuf,
+    rules: RwLock<NotifyRules>,
+    app_handle: OnceLock<AppHandle>,
+    watch: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Notifier {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("notify_rules.json");
+        let rules = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            rules: RwLock::new(rules),
+            app_handle: OnceLock::new(),
+            watch: Mutex::new(None),
+        }
+    }
+
+    pub fn rules(&self) -> NotifyRules {
+        self.rules.read().clone()
+    }
+
+    pub fn set_rules(&self, rules: NotifyRules) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(&rules)
+            .map_err(|e| format!("Failed to encode notification rules: {}", e))?;
+        std::fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to save notification rules: {}", e))?;
+        *self.rules.write() = rules;
+        Ok(())
+    }
+
+    /// Show notifications through `app_handle` from now on
+    pub fn set_app_handle(&self, app_handle: AppHandle) {
+        let _ = self.app_handle.set(app_handle);
+    }
+
+    pub fn notify(&self, event: &NotifyEvent) {
+        let Some(app_handle) = self.app_handle.get() else {
+            return;
+        };
+        let Some(notification) = self.rules.read().notification(event) else {
+            return;
+        };
+        if let Err(e) = app_handle
+            .notification()
+            .builder()
+            .title(notification.title)
+            .body(notification.body)
+            .show()
+        {
+            warn!("Failed to show notification: {}", e);
+        }
+    }
+
+    /// Watch the relays for mentions and shares addressed to the current
+    /// identity, replacing an earlier watch. Stops when there's no identity.
+    pub async fn watch(self: &Arc<Self>, nostr: &NostrManager, ndb: Arc<Ndb>) {
+        if let Some(task) = self.watch.lock().take() {
+            task.abort();
+        }
+        let Some(client) = nostr.get_client() else {
+            return;
+        };
+        let sub_id = SubscriptionId::new(WATCH_SUB_ID);
+        let keys = match nostr.get_keys() {
+            Some(keys) if self.app_handle.get().is_some() => keys,
+            _ => {
+                client.unsubscribe(sub_id).await;
+                return;
+            }
+        };
+
+        let filter = Filter::new()
+            .kind(Kind::from(KIND_HASHTREE))
+            .pubkey(keys.public_key())
+            .since(Timestamp::now());
+        if let Err(e) = client
+            .subscribe_with_id(sub_id.clone(), vec![filter], None)
+            .await
+        {
+            warn!("Failed to watch for mentions and shares: {}", e);
+            return;
+        }
+
+        let notifier = self.clone();
+        let task = tokio::spawn(async move {
+            let mut notifications = client.notifications();
+            // The same event arrives from each relay
+            let mut seen = HashSet::new();
+            loop {
+                let notification = match notifications.recv().await {
+                    Ok(notification) => notification,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                let RelayPoolNotification::Event {
+                    event,
+                    subscription_id,
+                    ..
+                } = notification
+                else {
+                    continue;
+                };
+                if subscription_id != sub_id || !seen.insert(event.id) {
+                    continue;
+                }
+                let distance = |pubkey: &PublicKey| follow_distance(&ndb, pubkey);
+                if let Some(event) = classify(&keys, &event, distance) {
+                    debug!("Notify event: {:?}", event);
+                    notifier.notify(&event);
+                }
+            }
+        });
+        // A watch started meanwhile is replaced too
+        if let Some(earlier) = self.watch.lock().replace(task) {
+            earlier.abort();
+        }
+    }
+}
+
+pub fn follow_distance(ndb: &Ndb, pubkey: &PublicKey) -> Option<usize> {
+    let txn = Transaction::new(ndb).ok()?;
+    let distance = nostrdb::socialgraph::get_follow_distance(&txn, ndb, &pubkey.to_bytes());
+    // nostrdb returns 1000 for "not connected"
+    (distance < 1000).then_some(distance as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::types::WorkerCid;
+    use nostr_sdk::{EventBuilder, Tag, TagKind};
+    use tempfile::TempDir;
+
+    fn tree_event(author: &Keys, tree: &str, mentions: &PublicKey) -> Event {
+        let tags = vec![
+            Tag::custom(TagKind::custom("d"), vec![tree.to_string()]),
+            Tag::custom(TagKind::custom("l"), vec![TREE_LABEL.to_string()]),
+            Tag::custom(TagKind::custom("p"), vec![mentions.to_hex()]),
+        ];
+        EventBuilder::new(Kind::from(KIND_HASHTREE), "", tags)
+            .to_event(author)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_classify_mentions_and_shares() {
+        let us = Keys::generate();
+        let author = Keys::generate();
+
+        let mention = tree_event(&author, "notes", &us.public_key());
+        let event = classify(&us, &mention, |_| Some(2)).unwrap();
+        assert_eq!(
+            event,
+            NotifyEvent::Mention {
+                author: short_npub(&author.public_key()),
+                tree: "notes".into(),
+                distance: Some(2),
+            }
+        );
+        // Our own trees and other people's mentions don't count
+        assert!(
+            classify(&us, &tree_event(&us, "notes", &us.public_key()), |_| Some(
+                0
+            ))
+            .is_none()
+        );
+        let other = tree_event(&author, "notes", &Keys::generate().public_key());
+        assert!(classify(&us, &other, |_| Some(1)).is_none());
+
+        let cid = WorkerCid {
+            hash: "a".repeat(64),
+            key: Some("b".repeat(64)),
+        };
+        let share =
+            shares::build_share_event(&author, "photos", &us.public_key(), Some(&cid)).unwrap();
+        assert!(matches!(
+            classify(&us, &share, |_| None),
+            Some(NotifyEvent::ShareReceived { tree, .. }) if tree == "photos"
+        ));
+        let revoked = shares::build_share_event(&author, "photos", &us.public_key(), None).unwrap();
+        assert!(classify(&us, &revoked, |_| None).is_none());
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut rules = NotifyRules::default();
+        let mention = |distance| NotifyEvent::Mention {
+            author: "npub1abc…".into(),
+            tree: "notes".into(),
+            distance,
+        };
+        assert!(rules.notification(&mention(Some(2))).is_some());
+        assert!(rules.notification(&mention(Some(3))).is_none());
+        assert!(rules.notification(&mention(None)).is_none());
+
+        let failed = sync_event(
+            "pushToBlossom",
+            &WorkerResponse::PushResult {
+                id: "p".into(),
+                pushed: 3,
+                skipped: 1,
+                failed: 2,
+                errors: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            rules.notification(&failed),
+            Some(Notification {
+                title: "Sync failed".into(),
+                body: "Pushed 3 blocks, 1 already stored: 2 blocks failed".into(),
+            })
+        );
+        let error = WorkerResponse::Error {
+            id: "r".into(),
+            error: "No signing identity set".into(),
+        };
+        assert!(sync_event("republishTrees", &error).is_some());
+        assert!(sync_event("writeFile", &error).is_none());
+        rules.sync_failed = false;
+        assert!(rules.notification(&failed).is_none());
+        rules.enabled = false;
+        assert!(rules.notification(&mention(Some(1))).is_none());
+    }
+
+    #[test]
+    fn test_rules_persist() {
+        let dir = TempDir::new().unwrap();
+        let rules = NotifyRules {
+            max_distance: 1,
+            sync_completed: false,
+            ..Default::default()
+        };
+        Notifier::new(dir.path()).set_rules(rules.clone()).unwrap();
+        assert_eq!(Notifier::new(dir.path()).rules(), rules);
+    }
+}
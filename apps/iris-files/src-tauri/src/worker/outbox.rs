@@ -0,0 +1,387 @@
+//! Durable outbox of published events
+//!
+//! Every event `NostrManager::publish` sends is first queued in
+//! `outbox.json`, so one published while no relay is connected, or that no
+//! relay accepted, isn't lost: it's sent again with backoff, and right away
+//! when a relay (re)connects, until `MAX_ATTEMPTS` sends failed. Each entry
+//! records which relays accepted the event and why others didn't. Delivered
+//! and given up entries are kept for their status until `MAX_SETTLED` newer
+//! ones push them out; a pending replaceable event is dropped when a newer
+//! one of its address is queued. An outbox file that can't be read is moved
+//! aside rather than overwritten.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use super::dedup;
+use super::relay_health::backoff;
+
+/// Delivered and given up entries kept for their status
+const MAX_SETTLED: usize = 200;
+
+/// Failed sends after which an event is given up on, until retried by hand
+const MAX_ATTEMPTS: u32 = 20;
+
+/// An event in the outbox and how its delivery went
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OutboxEntry {
+    pub event_id: String,
+    pub kind: u64,
+    /// The signed event
+    pub event: serde_json::Value,
+    /// Unix seconds
+    pub queued_at: u64,
+    pub attempts: u32,
+    pub last_attempt_at: Option<u64>,
+    /// When it's sent next; None once delivered or given up
+    pub next_attempt_at: Option<u64>,
+    /// Accepted by at least one relay
+    pub delivered: bool,
+    /// Relays that accepted it
+    pub accepted: BTreeSet<String>,
+    /// Relays that didn't, with their reason
+    pub rejected: BTreeMap<String, String>,
+    pub last_error: Option<String>,
+}
+
+/// Outcome of sending an event once
+#[derive(Debug, Clone, Default)]
+pub struct SendResult {
+    pub accepted: Vec<String>,
+    pub rejected: Vec<(String, String)>,
+    /// Why the event couldn't be sent at all, e.g. no relays
+    pub error: Option<String>,
+}
+
+/// Events to publish, by the order they were queued
+pub struct Outbox {
+    path: PathBuf,
+    /// Locked while changed and saved, so concurrent sends don't lose
+    /// each other's results
+    entries: Mutex<Vec<OutboxEntry>>,
+    /// Ids of events being sent right now
+    sending: Mutex<HashSet<String>>,
+}
+
+impl OutboxEntry {
+    /// Whether it's still to be sent
+    fn is_pending(&self) -> bool {
+        !self.delivered && self.next_attempt_at.is_some()
+    }
+}
+
+impl Outbox {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("outbox.json");
+        let entries = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                let aside = path.with_extension("json.corrupt");
+                warn!(
+                    "Outbox unreadable ({}), moving it to {}",
+                    e,
+                    aside.display()
+                );
+                if let Err(e) = std::fs::rename(&path, &aside) {
+                    warn!("Failed to move the outbox aside: {}", e);
+                }
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                warn!("Failed to read the outbox: {}", e);
+                Vec::new()
+            }
+        };
+        Self {
+            path,
+            entries: Mutex::new(entries),
+            sending: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// All entries, newest first
+    pub fn entries(&self) -> Vec<OutboxEntry> {
+        self.entries.lock().iter().rev().cloned().collect()
+    }
+
+    /// Entries still to be sent
+    pub fn pending(&self) -> usize {
+        self.entries
+            .lock()
+            .iter()
+            .filter(|e| e.is_pending())
+            .count()
+    }
+
+    /// The entry of `event_id`, if it's in the outbox
+    pub fn entry(&self, event_id: &str) -> Option<OutboxEntry> {
+        self.entries
+            .lock()
+            .iter()
+            .find(|e| e.event_id == event_id)
+            .cloned()
+    }
+
+    /// Queue signed `event` (JSON) to be sent now. Queuing an event again
+    /// keeps its entry as it is.
+    pub fn queue(&self, event: &serde_json::Value, now: u64) -> Result<(), String> {
+        let event_id = event["id"].as_str().ok_or("Event has no id")?.to_string();
+        let mut entries = self.entries.lock();
+        if entries.iter().any(|e| e.event_id == event_id) {
+            return Ok(());
+        }
+        if let Some(address) = dedup::address(event) {
+            let created_at = event["created_at"].as_u64().unwrap_or(0);
+            entries.retain(|e| {
+                e.delivered
+                    || dedup::address(&e.event).as_ref() != Some(&address)
+                    || e.event["created_at"].as_u64().unwrap_or(0) > created_at
+            });
+        }
+        entries.push(OutboxEntry {
+            event_id,
+            kind: event["kind"].as_u64().unwrap_or(0),
+            event: event.clone(),
+            queued_at: now,
+            next_attempt_at: Some(now),
+            ..Default::default()
+        });
+        self.save(&entries)
+    }
+
+    /// Mark `event_id` as being sent, false if it already is
+    pub fn begin_send(&self, event_id: &str) -> bool {
+        self.sending.lock().insert(event_id.to_string())
+    }
+
+    /// Events due to be sent at `now`, as (id, event). They count as being
+    /// sent until their `record`.
+    pub fn due(&self, now: u64) -> Vec<(String, serde_json::Value)> {
+        let entries = self.entries.lock();
+        let mut sending = self.sending.lock();
+        entries
+            .iter()
+            .filter(|e| e.next_attempt_at.is_some_and(|at| at <= now))
+            .filter(|e| sending.insert(e.event_id.clone()))
+            .map(|e| (e.event_id.clone(), e.event.clone()))
+            .collect()
+    }
+
+    /// Record sending `event_id` once. Returns whether it's delivered.
+    pub fn record(&self, event_id: &str, result: SendResult, now: u64) -> Result<bool, String> {
+        self.sending.lock().remove(event_id);
+        let mut entries = self.entries.lock();
+        let Some(entry) = entries.iter_mut().find(|e| e.event_id == event_id) else {
+            // Superseded while it was being sent
+            return Ok(false);
+        };
+        entry.attempts += 1;
+        entry.last_attempt_at = Some(now);
+        for (url, reason) in result.rejected {
+            if !entry.accepted.contains(&url) {
+                entry.rejected.insert(url, reason);
+            }
+        }
+        for url in result.accepted {
+            entry.rejected.remove(&url);
+            entry.accepted.insert(url);
+        }
+        entry.delivered = !entry.accepted.is_empty();
+        let delivered = entry.delivered;
+        if delivered {
+            entry.next_attempt_at = None;
+            entry.last_error = None;
+        } else {
+            let error = result
+                .error
+                .unwrap_or_else(|| "No relay accepted the event".to_string());
+            if entry.attempts >= MAX_ATTEMPTS {
+                entry.next_attempt_at = None;
+                entry.last_error = Some(format!(
+                    "Gave up after {} attempts: {}",
+                    entry.attempts, error
+                ));
+            } else {
+                entry.next_attempt_at = Some(now + backoff(entry.attempts).as_secs());
+                entry.last_error = Some(error);
+            }
+        }
+
+        let settled = entries.iter().filter(|e| !e.is_pending()).count();
+        let mut excess = settled.saturating_sub(MAX_SETTLED);
+        entries.retain(|e| {
+            if excess > 0 && !e.is_pending() {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+        self.save(&entries)?;
+        Ok(delivered)
+    }
+
+    /// Send the given events, or all undelivered ones, at the next chance.
+    /// Naming a delivered event sends it again, to reach more relays; one
+    /// given up on gets one more attempt. Returns how many were rescheduled.
+    pub fn retry(&self, event_ids: Option<&[String]>, now: u64) -> Result<usize, String> {
+        self.reschedule(now, |entry| match event_ids {
+            Some(ids) => ids.contains(&entry.event_id),
+            None => !entry.delivered,
+        })
+    }
+
+    /// Send the pending events at the next chance, e.g. once a relay
+    /// connects. Returns how many were rescheduled.
+    pub fn bring_forward(&self, now: u64) -> Result<usize, String> {
+        self.reschedule(now, OutboxEntry::is_pending)
+    }
+
+    fn reschedule(
+        &self,
+        now: u64,
+        retried: impl Fn(&OutboxEntry) -> bool,
+    ) -> Result<usize, String> {
+        let mut entries = self.entries.lock();
+        let mut count = 0;
+        for entry in entries.iter_mut() {
+            if retried(entry) {
+                entry.next_attempt_at = Some(now);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.save(&entries)?;
+        }
+        Ok(count)
+    }
+
+    fn save(&self, entries: &[OutboxEntry]) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(entries)
+            .map_err(|e| format!("Failed to encode outbox: {}", e))?;
+        crate::atomic_file::write(&self.path, data)
+            .map_err(|e| format!("Failed to save outbox: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn event(id: &str, kind: u64, created_at: u64) -> serde_json::Value {
+        json!({
+            "id": id,
+            "pubkey": "aa",
+            "kind": kind,
+            "created_at": created_at,
+            "tags": [],
+        })
+    }
+
+    fn rejected() -> SendResult {
+        SendResult {
+            rejected: vec![("wss://a/".to_string(), "blocked".to_string())],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_queued_events_retry_until_accepted() {
+        let dir = TempDir::new().unwrap();
+        let outbox = Outbox::new(dir.path());
+        outbox.queue(&event("01", 1, 10), 100).unwrap();
+
+        let due = outbox.due(100);
+        assert_eq!(due.len(), 1);
+        assert!(outbox.due(100).is_empty(), "already being sent");
+
+        let offline = SendResult {
+            error: Some("No relays connected".to_string()),
+            ..Default::default()
+        };
+        assert!(!outbox.record("01", offline, 100).unwrap());
+        let retry_at = 100 + backoff(1).as_secs();
+        assert!(outbox.due(retry_at - 1).is_empty());
+        assert_eq!(outbox.due(retry_at).len(), 1);
+        assert!(!outbox.record("01", rejected(), retry_at).unwrap());
+
+        // A reconnect brings it forward
+        assert_eq!(outbox.retry(None, retry_at + 1).unwrap(), 1);
+        outbox.due(retry_at + 1);
+        let accepted = SendResult {
+            accepted: vec!["wss://b/".to_string()],
+            ..Default::default()
+        };
+        assert!(outbox.record("01", accepted, retry_at + 1).unwrap());
+
+        // Kept across restarts
+        let outbox = Outbox::new(dir.path());
+        let entry = &outbox.entries()[0];
+        assert!(entry.delivered);
+        assert_eq!(entry.attempts, 3);
+        assert_eq!(entry.next_attempt_at, None);
+        assert!(entry.accepted.contains("wss://b/"));
+        assert_eq!(entry.rejected["wss://a/"], "blocked");
+        assert_eq!(outbox.pending(), 0);
+        assert_eq!(outbox.retry(None, 200).unwrap(), 0);
+        assert_eq!(outbox.retry(Some(&["01".to_string()]), 200).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_newer_replaceable_supersedes_pending_one() {
+        let dir = TempDir::new().unwrap();
+        let outbox = Outbox::new(dir.path());
+        outbox.queue(&event("01", 0, 10), 100).unwrap();
+        outbox.queue(&event("02", 1, 10), 100).unwrap();
+        outbox.queue(&event("03", 0, 20), 100).unwrap();
+        let ids: Vec<String> = outbox.entries().into_iter().map(|e| e.event_id).collect();
+        assert_eq!(ids, ["03", "02"]);
+
+        // An older one doesn't replace it
+        outbox.queue(&event("04", 0, 15), 100).unwrap();
+        assert_eq!(outbox.entries().len(), 3);
+    }
+
+    #[test]
+    fn test_sending_gives_up_after_max_attempts() {
+        let dir = TempDir::new().unwrap();
+        let outbox = Outbox::new(dir.path());
+        outbox.queue(&event("01", 1, 10), 100).unwrap();
+        for attempt in 1..=MAX_ATTEMPTS {
+            outbox.retry(None, 100).unwrap();
+            assert_eq!(outbox.due(100).len(), 1, "attempt {}", attempt);
+            assert!(!outbox.record("01", rejected(), 100).unwrap());
+        }
+        let entry = outbox.entry("01").unwrap();
+        assert_eq!(entry.next_attempt_at, None);
+        assert!(entry.last_error.unwrap().starts_with("Gave up"));
+        assert_eq!(outbox.pending(), 0);
+
+        // Reconnecting doesn't bring it back, retrying by hand does
+        assert_eq!(outbox.bring_forward(200).unwrap(), 0);
+        assert!(outbox.due(200).is_empty());
+        assert_eq!(outbox.retry(None, 200).unwrap(), 1);
+        assert_eq!(outbox.due(200).len(), 1);
+    }
+
+    #[test]
+    fn test_unreadable_outbox_is_moved_aside() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("outbox.json");
+        std::fs::write(&path, "[{").unwrap();
+        let outbox = Outbox::new(dir.path());
+        assert!(outbox.entries().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("outbox.json.corrupt")).unwrap(),
+            "[{"
+        );
+
+        outbox.queue(&event("01", 1, 10), 100).unwrap();
+        assert_eq!(Outbox::new(dir.path()).entries().len(), 1);
+    }
+}
@@ -0,0 +1,344 @@
+//! NIP-13 proof of work for published events
+//!
+//! Relays that demand PoW reject events whose id has too few leading zero
+//! bits. `PowPolicy`, kept in `pow_policy.json`, sets the difficulty per
+//! kind (or a default); `NostrManager::publish` has events of ours that
+//! fall short mined by `PowMiner` before they're sent: threads search
+//! disjoint nonces for the `nonce` tag, and the event is re-signed with the
+//! first one that reaches the difficulty. Mining gives up after
+//! `maxSeconds`, or when `cancel` is called, and the publish fails.
+
+use hashtree_core::sha256;
+use nostr_sdk::{Event, EventBuilder, Keys, Tag, TagKind};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Nonces tried between checks for cancellation and timeout
+const CHECK_EVERY: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PowPolicy {
+    pub enabled: bool,
+    /// Leading zero bits of kinds not in `kinds`; 0 mines nothing
+    pub default_difficulty: u8,
+    /// Difficulty by kind
+    pub kinds: BTreeMap<u16, u8>,
+    /// Mining longer than this fails the publish
+    pub max_seconds: u64,
+    /// Mining threads; 0 uses all cores but one
+    pub threads: usize,
+}
+
+impl Default for PowPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_difficulty: 0,
+            kinds: BTreeMap::new(),
+            max_seconds: 60,
+            threads: 0,
+        }
+    }
+}
+
+impl PowPolicy {
+    /// Difficulty events of `kind` are mined to
+    pub fn difficulty(&self, kind: u16) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        self.kinds
+            .get(&kind)
+            .copied()
+            .unwrap_or(self.default_difficulty)
+    }
+}
+
+/// Leading zero bits of `id`, its NIP-13 difficulty
+pub fn leading_zero_bits(id: &[u8]) -> u8 {
+    let mut bits = 0u32;
+    for byte in id {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits.min(u8::MAX as u32) as u8
+}
+
+/// Search nonces for one that gives `event`, with a `nonce` tag in place
+/// of any it has, an id of `difficulty` leading zero bits. Stops with None
+/// once `stop` returns true.
+fn search(
+    event: &Event,
+    difficulty: u8,
+    threads: usize,
+    stop: &(dyn Fn() -> bool + Sync),
+) -> Option<u64> {
+    // The id is the hash of [0, pubkey, created_at, kind, tags, content];
+    // only the nonce tag, last, changes between attempts
+    let tags: Vec<String> = event
+        .tags
+        .iter()
+        .filter(|tag| tag.kind() != TagKind::Nonce)
+        .map(|tag| serde_json::to_string(tag.as_slice()).unwrap_or_default())
+        .collect();
+    let mut prefix = format!(
+        "[0,\"{}\",{},{},[{}",
+        event.pubkey.to_hex(),
+        event.created_at.as_u64(),
+        event.kind.as_u16(),
+        tags.join(",")
+    );
+    if !tags.is_empty() {
+        prefix.push(',');
+    }
+    let suffix = format!(
+        "],{}]",
+        serde_json::to_string(&event.content).unwrap_or_default()
+    );
+
+    let found = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for start in 0..threads as u64 {
+            let (prefix, suffix, found, done) = (&prefix, &suffix, &found, &done);
+            scope.spawn(move || {
+                let mut nonce = start;
+                let mut tried = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let serialized = format!(
+                        "{}[\"nonce\",\"{}\",\"{}\"]{}",
+                        prefix, nonce, difficulty, suffix
+                    );
+                    if leading_zero_bits(&sha256(serialized.as_bytes())) >= difficulty {
+                        if !done.swap(true, Ordering::Relaxed) {
+                            found.store(nonce, Ordering::Relaxed);
+                        }
+                        return;
+                    }
+                    nonce += threads as u64;
+                    tried += 1;
+                    if tried % CHECK_EVERY == 0 && stop() {
+                        done.store(true, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    // `done` is also set by a stop, which leaves no nonce
+    let nonce = found.load(Ordering::Relaxed);
+    let serialized = format!(
+        "{}[\"nonce\",\"{}\",\"{}\"]{}",
+        prefix, nonce, difficulty, suffix
+    );
+    (leading_zero_bits(&sha256(serialized.as_bytes())) >= difficulty).then_some(nonce)
+}
+
+/// `event` re-signed by `keys` with `nonce` as its nonce tag
+fn with_nonce(event: &Event, keys: &Keys, nonce: u64, difficulty: u8) -> Result<Event, String> {
+    let mut tags: Vec<Tag> = event
+        .tags
+        .iter()
+        .filter(|tag| tag.kind() != TagKind::Nonce)
+        .cloned()
+        .collect();
+    tags.push(
+        Tag::parse(&[
+            "nonce".to_string(),
+            nonce.to_string(),
+            difficulty.to_string(),
+        ])
+        .map_err(|e| format!("Invalid nonce tag: {}", e))?,
+    );
+    EventBuilder::new(event.kind, event.content.clone(), tags)
+        .custom_created_at(event.created_at)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+/// Mines events to the difficulty of the policy
+pub struct PowMiner {
+    path: PathBuf,
+    policy: RwLock<PowPolicy>,
+    /// Bumped by `cancel`; mining started before gives up
+    generation: AtomicU64,
+    /// Events being mined
+    active: AtomicUsize,
+}
+
+impl PowMiner {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("pow_policy.json");
+        let policy = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            policy: RwLock::new(policy),
+            generation: AtomicU64::new(0),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> PowPolicy {
+        self.policy.read().clone()
+    }
+
+    pub fn set_policy(&self, policy: PowPolicy) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(&policy)
+            .map_err(|e| format!("Failed to encode PoW policy: {}", e))?;
+        std::fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to save PoW policy: {}", e))?;
+        *self.policy.write() = policy;
+        Ok(())
+    }
+
+    /// Stop all mining in progress, whose publishes fail. Returns whether
+    /// there was any.
+    pub fn cancel(&self) -> bool {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.active.load(Ordering::Relaxed) > 0
+    }
+
+    /// `event` mined to the policy's difficulty for its kind, re-signed by
+    /// `keys`. Events that already meet it, or aren't by `keys` and so
+    /// can't be re-signed, come back as they are.
+    pub async fn mine(self: &Arc<Self>, event: Event, keys: &Keys) -> Result<Event, String> {
+        let policy = self.policy();
+        let difficulty = policy.difficulty(event.kind.as_u16());
+        if difficulty == 0 || leading_zero_bits(event.id.as_bytes()) >= difficulty {
+            return Ok(event);
+        }
+        if event.pubkey != keys.public_key() {
+            warn!("Not mining event {} by another key", event.id);
+            return Ok(event);
+        }
+        let threads = match policy.threads {
+            0 => std::thread::available_parallelism()
+                .map(|n| n.get().saturating_sub(1).max(1))
+                .unwrap_or(1),
+            n => n,
+        };
+        let deadline = Instant::now() + Duration::from_secs(policy.max_seconds);
+        let generation = self.generation.load(Ordering::Relaxed);
+
+        self.active.fetch_add(1, Ordering::Relaxed);
+        let miner = self.clone();
+        let event_for_search = event.clone();
+        let started = Instant::now();
+        let nonce = tokio::task::spawn_blocking(move || {
+            let stop = || {
+                miner.generation.load(Ordering::Relaxed) != generation || Instant::now() >= deadline
+            };
+            search(&event_for_search, difficulty, threads, &stop)
+        })
+        .await;
+        self.active.fetch_sub(1, Ordering::Relaxed);
+
+        let nonce = nonce.map_err(|e| format!("Mining failed: {}", e))?;
+        let Some(nonce) = nonce else {
+            if self.generation.load(Ordering::Relaxed) != generation {
+                return Err("Proof-of-work cancelled".to_string());
+            }
+            return Err(format!(
+                "Proof-of-work of difficulty {} not found in {}s",
+                difficulty, policy.max_seconds
+            ));
+        };
+        let mined = with_nonce(&event, keys, nonce, difficulty)?;
+        info!(
+            "Mined event {} to difficulty {} in {:?}",
+            mined.id,
+            difficulty,
+            started.elapsed()
+        );
+        Ok(mined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::Kind;
+    use tempfile::TempDir;
+
+    fn miner(policy: PowPolicy) -> (TempDir, Arc<PowMiner>) {
+        let dir = TempDir::new().unwrap();
+        let miner = Arc::new(PowMiner::new(dir.path()));
+        miner.set_policy(policy).unwrap();
+        (dir, miner)
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0xff]), 0);
+        assert_eq!(leading_zero_bits(&[0x00, 0x0f]), 12);
+        assert_eq!(leading_zero_bits(&[0x00, 0x00, 0x80]), 16);
+        assert_eq!(leading_zero_bits(&[0x00; 32]), 255);
+    }
+
+    #[tokio::test]
+    async fn test_mined_event_meets_difficulty() {
+        let (_dir, miner) = miner(PowPolicy {
+            enabled: true,
+            kinds: BTreeMap::from([(1, 10)]),
+            threads: 2,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+        let tags = vec![Tag::parse(&["t", "hashtree"]).unwrap()];
+        let event = EventBuilder::new(Kind::TextNote, "hi \"there\"", tags)
+            .to_event(&keys)
+            .unwrap();
+
+        let mined = miner.mine(event.clone(), &keys).await.unwrap();
+        assert!(leading_zero_bits(mined.id.as_bytes()) >= 10);
+        assert!(mined.verify().is_ok());
+        assert_eq!(mined.content, event.content);
+        assert_eq!(mined.created_at, event.created_at);
+        let nonce = mined.tags.iter().find(|tag| tag.kind() == TagKind::Nonce);
+        assert_eq!(nonce.unwrap().as_slice()[2], "10");
+
+        // Other kinds have the default, none
+        let reaction = EventBuilder::new(Kind::Reaction, "+", [])
+            .to_event(&keys)
+            .unwrap();
+        let id = reaction.id;
+        assert_eq!(miner.mine(reaction, &keys).await.unwrap().id, id);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_mining() {
+        let (_dir, miner) = miner(PowPolicy {
+            enabled: true,
+            default_difficulty: 64,
+            threads: 1,
+            ..Default::default()
+        });
+        let keys = Keys::generate();
+        let event = EventBuilder::new(Kind::TextNote, "slow", [])
+            .to_event(&keys)
+            .unwrap();
+
+        let mining = {
+            let miner = miner.clone();
+            let keys = keys.clone();
+            tokio::spawn(async move { miner.mine(event, &keys).await })
+        };
+        while miner.active.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(miner.cancel());
+        let result = mining.await.unwrap();
+        assert_eq!(result.unwrap_err(), "Proof-of-work cancelled");
+    }
+}
@@ -0,0 +1,125 @@
+//! Profile metadata (kind 0) from nostrdb, fetched from relays on a miss
+//!
+//! Views ask for profiles one at a time, often dozens at once for a
+//! directory of npubs. Misses wait `BATCH_DELAY` for others to join, then
+//! one relay query fetches the whole batch; asking again for a pubkey that's
+//! already waiting joins the same request. Fetched profiles go into nostrdb,
+//! so later lookups are served from there.
+
+use nostr_sdk::{Event, PublicKey};
+use nostrdb::{Ndb, Transaction};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Time a batch stays open for more pubkeys
+pub const BATCH_DELAY: Duration = Duration::from_millis(100);
+
+/// Profile of `pubkey` from nostrdb, the newest one if there are several
+pub fn cached_profile(ndb: &Ndb, pubkey: &PublicKey) -> Option<serde_json::Value> {
+    let txn = Transaction::new(ndb).ok()?;
+    let pk_bytes = pubkey.to_bytes();
+    let filter = nostrdb::Filter::new()
+        .kinds(vec![0])
+        .authors(vec![&pk_bytes])
+        .build();
+    let results = ndb.query(&txn, &[filter], 10).ok()?;
+    let newest = results
+        .iter()
+        .max_by_key(|result| result.note.created_at())?;
+    serde_json::from_str(newest.note.content()).ok()
+}
+
+type Waiters = HashMap<String, Vec<oneshot::Sender<Option<serde_json::Value>>>>;
+
+/// Profile lookups waiting for the next relay query
+#[derive(Default)]
+pub struct ProfileBatcher {
+    waiting: Mutex<Waiters>,
+}
+
+impl ProfileBatcher {
+    /// Wait for `pubkey` (hex) in the next batch. True if this opened the
+    /// batch, in which case the caller fetches it after `BATCH_DELAY`.
+    pub fn join(&self, pubkey: &str) -> (oneshot::Receiver<Option<serde_json::Value>>, bool) {
+        let (tx, rx) = oneshot::channel();
+        let mut waiting = self.waiting.lock();
+        let opened = waiting.is_empty();
+        waiting.entry(pubkey.to_string()).or_default().push(tx);
+        (rx, opened)
+    }
+
+    /// Close the batch, returning its waiters
+    pub fn take(&self) -> Waiters {
+        std::mem::take(&mut *self.waiting.lock())
+    }
+}
+
+/// Pubkeys of a closed batch, for the relay filter
+pub fn batch_authors(waiters: &Waiters) -> Vec<PublicKey> {
+    waiters
+        .keys()
+        .filter_map(|pubkey| PublicKey::from_hex(pubkey).ok())
+        .collect()
+}
+
+/// Answer the waiters of a batch with the newest profile of each among
+/// `events`, None for pubkeys without one
+pub fn deliver(waiters: Waiters, events: &[Event]) {
+    let mut newest: HashMap<String, &Event> = HashMap::new();
+    for event in events {
+        let pubkey = event.pubkey.to_hex();
+        if newest
+            .get(&pubkey)
+            .map_or(true, |known| event.created_at > known.created_at)
+        {
+            newest.insert(pubkey, event);
+        }
+    }
+    for (pubkey, senders) in waiters {
+        let profile: Option<serde_json::Value> = newest
+            .get(&pubkey)
+            .and_then(|event| serde_json::from_str(&event.content).ok());
+        for sender in senders {
+            let _ = sender.send(profile.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys, Kind, Timestamp};
+
+    #[tokio::test]
+    async fn test_batches_coalesce_and_take_newest() {
+        let alice = Keys::generate();
+        let bob = Keys::generate().public_key().to_hex();
+        let batcher = ProfileBatcher::default();
+
+        let (first, opened) = batcher.join(&alice.public_key().to_hex());
+        assert!(opened);
+        let (second, opened) = batcher.join(&alice.public_key().to_hex());
+        assert!(!opened);
+        let (missing, _) = batcher.join(&bob);
+
+        let waiters = batcher.take();
+        assert_eq!(waiters.len(), 2);
+        assert_eq!(batch_authors(&waiters).len(), 2);
+        assert!(batcher.join(&bob).1, "taking closes the batch");
+
+        let profile = |name: &str, at: u64| {
+            EventBuilder::new(Kind::Metadata, format!("{{\"name\":\"{}\"}}", name), [])
+                .custom_created_at(Timestamp::from(at))
+                .to_event(&alice)
+                .unwrap()
+        };
+        deliver(waiters, &[profile("new", 2), profile("old", 1)]);
+
+        let expected = serde_json::json!({"name": "new"});
+        assert_eq!(first.await.unwrap(), Some(expected.clone()));
+        assert_eq!(second.await.unwrap(), Some(expected));
+        assert_eq!(missing.await.unwrap(), None);
+    }
+}
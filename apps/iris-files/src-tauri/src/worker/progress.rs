@@ -0,0 +1,174 @@
+//! Progress events for long-running worker operations
+//!
+//! Bulk operations report `WorkerResponse::Progress` keyed by their request
+//! id, with item and byte counts per phase. Events are emitted when the
+//! integer percentage changes (or every few items while the total is
+//! unknown), so large operations don't flood the event channel.
+
+use tauri::{AppHandle, Emitter};
+
+use super::types::WorkerResponse;
+
+/// Items between events while the total isn't known
+const UNKNOWN_TOTAL_INTERVAL: u64 = 16;
+
+type Sink = Box<dyn Fn(WorkerResponse) + Send + Sync>;
+
+/// Tracks one operation's progress and emits it to the frontend
+pub struct ProgressReporter {
+    op_id: String,
+    phase: &'static str,
+    bytes_done: u64,
+    bytes_total: Option<u64>,
+    items_done: u64,
+    items_total: Option<u64>,
+    last_percent: Option<u64>,
+    sink: Sink,
+}
+
+impl ProgressReporter {
+    /// Reporter emitting `worker_response` events for request `op_id`
+    pub fn new(app_handle: &AppHandle, op_id: &str) -> Self {
+        let app_handle = app_handle.clone();
+        Self::with_sink(
+            op_id,
+            Box::new(move |response| {
+                let _ = app_handle.emit("worker_response", &response);
+            }),
+        )
+    }
+
+    fn with_sink(op_id: &str, sink: Sink) -> Self {
+        Self {
+            op_id: op_id.to_string(),
+            phase: "",
+            bytes_done: 0,
+            bytes_total: None,
+            items_done: 0,
+            items_total: None,
+            last_percent: None,
+            sink,
+        }
+    }
+
+    /// Start a new phase with its totals, if known, and report it
+    pub fn phase(
+        &mut self,
+        phase: &'static str,
+        items_total: Option<u64>,
+        bytes_total: Option<u64>,
+    ) {
+        self.phase = phase;
+        self.items_done = 0;
+        self.items_total = items_total;
+        self.bytes_done = 0;
+        self.bytes_total = bytes_total;
+        self.emit();
+    }
+
+    /// Count finished items and bytes, reporting if the change is visible
+    pub fn advance(&mut self, items: u64, bytes: u64) {
+        self.items_done += items;
+        self.bytes_done += bytes;
+        let due = match self.percent() {
+            Some(percent) => self.last_percent != Some(percent),
+            None => items > 0 && self.items_done % UNKNOWN_TOTAL_INTERVAL == 0,
+        };
+        if due {
+            self.emit();
+        }
+    }
+
+    /// Report the final counts of the current phase
+    pub fn finish(&mut self) {
+        self.emit();
+    }
+
+    /// Completion of the current phase, by bytes when their total is known
+    fn percent(&self) -> Option<u64> {
+        let (done, total) = match (self.bytes_total, self.items_total) {
+            (Some(total), _) if total > 0 => (self.bytes_done, total),
+            (_, Some(total)) if total > 0 => (self.items_done, total),
+            _ => return None,
+        };
+        Some(done.min(total) * 100 / total)
+    }
+
+    fn emit(&mut self) {
+        self.last_percent = self.percent();
+        (self.sink)(WorkerResponse::Progress {
+            op_id: self.op_id.clone(),
+            phase: self.phase.to_string(),
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            items_done: self.items_done,
+            items_total: self.items_total,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn recording(op_id: &str) -> (ProgressReporter, Arc<Mutex<Vec<(u64, u64)>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+        let reporter = ProgressReporter::with_sink(
+            op_id,
+            Box::new(move |response| {
+                if let WorkerResponse::Progress {
+                    items_done,
+                    bytes_done,
+                    ..
+                } = response
+                {
+                    sink_events.lock().unwrap().push((items_done, bytes_done));
+                }
+            }),
+        );
+        (reporter, events)
+    }
+
+    #[test]
+    fn test_emits_on_percent_change() {
+        let (mut progress, events) = recording("op");
+        progress.phase("upload", Some(1000), Some(1000));
+        for _ in 0..1000 {
+            progress.advance(1, 1);
+        }
+        progress.finish();
+
+        let events = events.lock().unwrap();
+        // Phase start, one per percent, and the final report
+        assert_eq!(events.len(), 1 + 100 + 1);
+        assert_eq!(events[0], (0, 0));
+        assert_eq!(*events.last().unwrap(), (1000, 1000));
+    }
+
+    #[test]
+    fn test_unknown_total_reports_every_interval() {
+        let (mut progress, events) = recording("op");
+        progress.phase("index", None, None);
+        for _ in 0..40 {
+            progress.advance(1, 0);
+        }
+        assert_eq!(
+            events.lock().unwrap().as_slice(),
+            &[(0, 0), (16, 0), (32, 0)]
+        );
+    }
+
+    #[test]
+    fn test_phase_resets_counts() {
+        let (mut progress, events) = recording("op");
+        progress.phase("walk", None, None);
+        progress.advance(3, 300);
+        progress.phase("upload", Some(3), Some(300));
+        progress.advance(1, 100);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.as_slice(), &[(0, 0), (0, 0), (1, 100)]);
+    }
+}
@@ -0,0 +1,381 @@
+//! Per-origin storage accounting and quotas
+//!
+//! Blocks are tagged with the tree roots that reference them: publishing a
+//! tree tags its blocks as our own, indexing a synced tree tags them as ours
+//! or other people's depending on the tree's owner. A block referenced by
+//! any of our trees counts as ours. Each origin has its own quota; when one
+//! is exceeded, that origin's least recently tagged trees are dropped and
+//! the blocks no remaining tree references become evictable. The global
+//! `max_bytes` LRU cap still applies on top. Tags and quotas are persisted
+//! in `origins.json`.
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// File in the data dir holding tags and quotas
+const ORIGINS_FILE: &str = "origins.json";
+
+/// Whose content a tree is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Origin {
+    /// Trees we published or that are owned by our pubkey
+    Own,
+    /// Cached content of other people's trees
+    Others,
+}
+
+/// Byte caps per origin (0 = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quotas {
+    #[serde(default)]
+    pub own_bytes: u64,
+    #[serde(default)]
+    pub others_bytes: u64,
+}
+
+impl Quotas {
+    fn for_origin(&self, origin: Origin) -> u64 {
+        match origin {
+            Origin::Own => self.own_bytes,
+            Origin::Others => self.others_bytes,
+        }
+    }
+}
+
+/// Bytes and trees accounted to each origin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OriginUsage {
+    pub own_bytes: u64,
+    pub own_trees: u64,
+    pub others_bytes: u64,
+    pub others_trees: u64,
+}
+
+impl OriginUsage {
+    fn bytes(&self, origin: Origin) -> u64 {
+        match origin {
+            Origin::Own => self.own_bytes,
+            Origin::Others => self.others_bytes,
+        }
+    }
+}
+
+/// Blocks of one tree root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TreeTag {
+    origin: Origin,
+    root: String,
+    /// Block hash (hex) -> size in bytes
+    blocks: BTreeMap<String, u64>,
+    /// Order of tagging, oldest first
+    seq: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Registry {
+    #[serde(default)]
+    quotas: Quotas,
+    #[serde(default)]
+    next_seq: u64,
+    /// "owner/tree_name" -> latest tagged root
+    #[serde(default)]
+    trees: BTreeMap<String, TreeTag>,
+}
+
+impl Registry {
+    fn usage(&self) -> OriginUsage {
+        let mut blocks: HashMap<&str, (u64, Origin)> = HashMap::new();
+        let mut usage = OriginUsage::default();
+        for tag in self.trees.values() {
+            match tag.origin {
+                Origin::Own => usage.own_trees += 1,
+                Origin::Others => usage.others_trees += 1,
+            }
+            for (hash, size) in &tag.blocks {
+                let entry = blocks.entry(hash).or_insert((*size, tag.origin));
+                if tag.origin == Origin::Own {
+                    entry.1 = Origin::Own;
+                }
+            }
+        }
+        for (size, origin) in blocks.into_values() {
+            match origin {
+                Origin::Own => usage.own_bytes += size,
+                Origin::Others => usage.others_bytes += size,
+            }
+        }
+        usage
+    }
+
+    /// Oldest tagged tree of `origin`
+    fn oldest(&self, origin: Origin) -> Option<String> {
+        self.trees
+            .iter()
+            .filter(|(_, tag)| tag.origin == origin)
+            .min_by_key(|(_, tag)| tag.seq)
+            .map(|(key, _)| key.clone())
+    }
+
+    fn is_referenced(&self, hash: &str) -> bool {
+        self.trees.values().any(|tag| tag.blocks.contains_key(hash))
+    }
+}
+
+/// Tree tags and quotas, persisted as JSON
+pub struct OriginRegistry {
+    path: PathBuf,
+    registry: RwLock<Registry>,
+}
+
+impl OriginRegistry {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join(ORIGINS_FILE);
+        let registry = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            registry: RwLock::new(registry),
+        }
+    }
+
+    fn key(owner: &str, tree_name: &str) -> String {
+        format!("{}/{}", owner, tree_name)
+    }
+
+    fn save(&self, registry: &Registry) -> Result<(), String> {
+        let data =
+            serde_json::to_vec(registry).map_err(|e| format!("Failed to encode origins: {}", e))?;
+        std::fs::write(&self.path, data).map_err(|e| format!("Failed to save origins: {}", e))
+    }
+
+    /// Whether `root` is already the tagged root of the tree
+    pub fn is_tagged(&self, owner: &str, tree_name: &str, root: &str) -> bool {
+        self.registry
+            .read()
+            .trees
+            .get(&Self::key(owner, tree_name))
+            .is_some_and(|tag| tag.root == root)
+    }
+
+    /// Tag the blocks of a tree's current root, replacing its previous root
+    pub fn tag_tree(
+        &self,
+        owner: &str,
+        tree_name: &str,
+        origin: Origin,
+        root: &str,
+        blocks: impl IntoIterator<Item = (String, u64)>,
+    ) -> Result<(), String> {
+        let mut registry = self.registry.write();
+        let seq = registry.next_seq;
+        registry.next_seq += 1;
+        registry.trees.insert(
+            Self::key(owner, tree_name),
+            TreeTag {
+                origin,
+                root: root.to_string(),
+                blocks: blocks.into_iter().collect(),
+                seq,
+            },
+        );
+        self.save(&registry)
+    }
+
+    /// Stop counting a tree, e.g. once it's deleted. Returns the blocks
+    /// (hex hash, size) no remaining tree references.
+    pub fn untag_tree(&self, owner: &str, tree_name: &str) -> Result<Vec<(String, u64)>, String> {
+        let mut registry = self.registry.write();
+        let Some(tag) = registry.trees.remove(&Self::key(owner, tree_name)) else {
+            return Ok(Vec::new());
+        };
+        self.save(&registry)?;
+        Ok(tag
+            .blocks
+            .into_iter()
+            .filter(|(hash, _)| !registry.is_referenced(hash))
+            .collect())
+    }
+
+    /// Whether any tagged tree references block `hash` (hex)
+    pub fn is_referenced(&self, hash: &str) -> bool {
+        self.registry.read().is_referenced(hash)
+    }
+
+    pub fn quotas(&self) -> Quotas {
+        self.registry.read().quotas
+    }
+
+    pub fn set_quotas(&self, quotas: Quotas) -> Result<(), String> {
+        let mut registry = self.registry.write();
+        registry.quotas = quotas;
+        self.save(&registry)
+    }
+
+    pub fn usage(&self) -> OriginUsage {
+        self.registry.read().usage()
+    }
+
+    /// Bytes the tagged trees would take if none shared blocks; over the
+    /// bytes of `usage`, how much content addressing saves
+    pub fn logical_bytes(&self) -> u64 {
+        self.registry
+            .read()
+            .trees
+            .values()
+            .flat_map(|tag| tag.blocks.values())
+            .sum()
+    }
+
+    /// Drop the oldest trees of each origin over its quota. Returns the
+    /// blocks (hex hash, size) no remaining tree references, to be deleted.
+    pub fn trim_to_quotas(&self) -> Result<Vec<(String, u64)>, String> {
+        let mut registry = self.registry.write();
+        let mut dropped: BTreeMap<String, u64> = BTreeMap::new();
+        for origin in [Origin::Others, Origin::Own] {
+            let quota = registry.quotas.for_origin(origin);
+            if quota == 0 {
+                continue;
+            }
+            while registry.usage().bytes(origin) > quota {
+                let Some(key) = registry.oldest(origin) else {
+                    break;
+                };
+                if let Some(tag) = registry.trees.remove(&key) {
+                    dropped.extend(tag.blocks);
+                }
+            }
+        }
+        if dropped.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.save(&registry)?;
+        Ok(dropped
+            .into_iter()
+            .filter(|(hash, _)| !registry.is_referenced(hash))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn blocks(hashes: &[(&str, u64)]) -> Vec<(String, u64)> {
+        hashes.iter().map(|(h, s)| (h.to_string(), *s)).collect()
+    }
+
+    #[test]
+    fn test_usage_counts_shared_blocks_as_own() {
+        let dir = TempDir::new().unwrap();
+        let registry = OriginRegistry::new(dir.path());
+        registry
+            .tag_tree(
+                "me",
+                "docs",
+                Origin::Own,
+                "r1",
+                blocks(&[("a", 10), ("b", 20)]),
+            )
+            .unwrap();
+        registry
+            .tag_tree(
+                "bob",
+                "videos",
+                Origin::Others,
+                "r2",
+                blocks(&[("b", 20), ("c", 5)]),
+            )
+            .unwrap();
+
+        assert_eq!(
+            registry.usage(),
+            OriginUsage {
+                own_bytes: 30,
+                own_trees: 1,
+                others_bytes: 5,
+                others_trees: 1,
+            }
+        );
+
+        // Tagging a new root replaces the tree's old blocks
+        registry
+            .tag_tree("me", "docs", Origin::Own, "r3", blocks(&[("a", 10)]))
+            .unwrap();
+        let reopened = OriginRegistry::new(dir.path());
+        assert!(reopened.is_tagged("me", "docs", "r3"));
+        assert!(!reopened.is_tagged("me", "docs", "r1"));
+        let usage = reopened.usage();
+        assert_eq!((usage.own_bytes, usage.others_bytes), (10, 25));
+        assert_eq!(reopened.logical_bytes(), 35);
+    }
+
+    #[test]
+    fn test_trim_drops_oldest_trees_over_quota() {
+        let dir = TempDir::new().unwrap();
+        let registry = OriginRegistry::new(dir.path());
+        registry
+            .tag_tree("me", "docs", Origin::Own, "r0", blocks(&[("shared", 50)]))
+            .unwrap();
+        registry
+            .tag_tree(
+                "bob",
+                "old",
+                Origin::Others,
+                "r1",
+                blocks(&[("x", 40), ("shared", 50)]),
+            )
+            .unwrap();
+        registry
+            .tag_tree("carol", "new", Origin::Others, "r2", blocks(&[("y", 30)]))
+            .unwrap();
+        registry
+            .set_quotas(Quotas {
+                own_bytes: 0,
+                others_bytes: 50,
+            })
+            .unwrap();
+
+        // Only bob's tree goes, and our own block stays
+        assert_eq!(registry.trim_to_quotas().unwrap(), blocks(&[("x", 40)]));
+        let usage = registry.usage();
+        assert_eq!((usage.own_bytes, usage.others_bytes), (50, 30));
+        assert!(registry.trim_to_quotas().unwrap().is_empty());
+
+        let reopened = OriginRegistry::new(dir.path());
+        assert_eq!(reopened.quotas().others_bytes, 50);
+        assert_eq!(reopened.usage().others_trees, 1);
+    }
+
+    #[test]
+    fn test_untag_releases_unshared_blocks() {
+        let dir = TempDir::new().unwrap();
+        let registry = OriginRegistry::new(dir.path());
+        registry
+            .tag_tree(
+                "me",
+                "docs",
+                Origin::Own,
+                "r1",
+                blocks(&[("a", 10), ("b", 20)]),
+            )
+            .unwrap();
+        registry
+            .tag_tree("me", "music", Origin::Own, "r2", blocks(&[("b", 20)]))
+            .unwrap();
+
+        assert_eq!(
+            registry.untag_tree("me", "docs").unwrap(),
+            blocks(&[("a", 10)])
+        );
+        assert!(registry.is_referenced("b"));
+        assert!(!OriginRegistry::new(dir.path()).is_tagged("me", "docs", "r1"));
+        assert!(registry.untag_tree("me", "docs").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,339 @@
+//! Relay health: reconnect backoff, scoring and demotion
+//!
+//! nostr-sdk's own reconnect retries every relay at a fixed interval
+//! forever, so relays are added with it off and `NostrManager` reconnects
+//! them itself, asking `RelayHealth` when each is due. Failed attempts back
+//! off exponentially from `BASE_BACKOFF` to `MAX_BACKOFF`; after
+//! `DEMOTE_AFTER` failures in a row a relay is demoted and only probed
+//! every `DEMOTED_RETRY` until it connects again. The messages a relay
+//! sends feed its 0-100 score: connection failure rate, EOSE latency and
+//! notices.
+
+use nostr_sdk::RelayMessage;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use super::types::RelayStatEntry;
+
+/// Wait after the first failed attempt, doubled after each further one
+pub const BASE_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Longest wait between attempts of a relay that isn't demoted
+pub const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Failed attempts in a row after which a relay is demoted
+pub const DEMOTE_AFTER: u32 = 8;
+
+/// Wait between attempts of a demoted relay
+pub const DEMOTED_RETRY: Duration = Duration::from_secs(30 * 60);
+
+/// Period messages per second are averaged over
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Weight of the newest sample in the EOSE latency average
+const LATENCY_WEIGHT: f64 = 0.3;
+
+#[derive(Default)]
+struct RelayState {
+    attempts: u64,
+    failed_attempts: u64,
+    consecutive_failures: u32,
+    attempting: bool,
+    next_attempt: Option<Instant>,
+    connected_at: Option<Instant>,
+    last_error: Option<String>,
+    /// Notices since the relay last connected
+    notices: u64,
+    window_start: Option<Instant>,
+    window_messages: u64,
+    messages_per_sec: f64,
+    /// Average time from REQ to EOSE
+    eose_latency: Option<Duration>,
+    /// Subscriptions that got their EOSE since the relay last connected
+    eose_seen: HashSet<String>,
+}
+
+impl RelayState {
+    fn demoted(&self) -> bool {
+        self.consecutive_failures >= DEMOTE_AFTER
+    }
+
+    fn score(&self) -> u8 {
+        if self.demoted() {
+            return 0;
+        }
+        let mut score = 100.0;
+        if self.attempts > 0 {
+            score -= 50.0 * self.failed_attempts as f64 / self.attempts as f64;
+        }
+        if let Some(latency) = self.eose_latency {
+            // Up to 30 points, lost at 3 s
+            score -= (latency.as_millis() as f64 / 100.0).min(30.0);
+        }
+        score -= (self.notices as f64 * 5.0).min(20.0);
+        score.clamp(0.0, 100.0) as u8
+    }
+}
+
+/// Wait before the next attempt after `failures` failed ones in a row
+pub fn backoff(failures: u32) -> Duration {
+    if failures >= DEMOTE_AFTER {
+        return DEMOTED_RETRY;
+    }
+    let exponent = failures.saturating_sub(1).min(16);
+    (BASE_BACKOFF * 2u32.pow(exponent)).min(MAX_BACKOFF)
+}
+
+/// Connection and message health of every relay, by URL
+#[derive(Default)]
+pub struct RelayHealth {
+    relays: Mutex<HashMap<String, RelayState>>,
+    /// When each subscription was last sent
+    sent: Mutex<HashMap<String, Instant>>,
+}
+
+impl RelayHealth {
+    /// Whether `url` is due for a connection attempt. If it is, the attempt
+    /// counts as started; it ends with `connected` or `failed`.
+    pub fn begin_attempt(&self, url: &str, now: Instant) -> bool {
+        let mut relays = self.relays.lock();
+        let state = relays.entry(url.to_string()).or_default();
+        if state.attempting || state.next_attempt.is_some_and(|at| now < at) {
+            return false;
+        }
+        state.attempting = true;
+        state.attempts += 1;
+        true
+    }
+
+    /// `url` is connected
+    pub fn connected(&self, url: &str, now: Instant) {
+        let mut relays = self.relays.lock();
+        let state = relays.entry(url.to_string()).or_default();
+        state.attempting = false;
+        if state.connected_at.is_none() {
+            state.connected_at = Some(now);
+            state.consecutive_failures = 0;
+            state.next_attempt = None;
+            state.notices = 0;
+            state.eose_seen.clear();
+        }
+    }
+
+    /// A connection attempt to `url` failed
+    pub fn failed(&self, url: &str, error: String, now: Instant) {
+        let mut relays = self.relays.lock();
+        let state = relays.entry(url.to_string()).or_default();
+        state.attempting = false;
+        state.connected_at = None;
+        state.failed_attempts += 1;
+        state.consecutive_failures += 1;
+        state.last_error = Some(error);
+        state.next_attempt = Some(now + backoff(state.consecutive_failures));
+    }
+
+    /// `url` is seen disconnected. A relay that was up gets a quick retry.
+    pub fn disconnected(&self, url: &str, now: Instant) {
+        let mut relays = self.relays.lock();
+        let Some(state) = relays.get_mut(url) else {
+            return;
+        };
+        if state.connected_at.take().is_some() {
+            state.next_attempt = Some(now + BASE_BACKOFF);
+        }
+    }
+
+    /// Stop tracking `url`, e.g. when it's removed from the relay list
+    pub fn forget(&self, url: &str) {
+        self.relays.lock().remove(url);
+    }
+
+    /// Subscription `sub_id` was sent to the relays
+    pub fn subscribed(&self, sub_id: &str, now: Instant) {
+        self.sent.lock().insert(sub_id.to_string(), now);
+        for state in self.relays.lock().values_mut() {
+            state.eose_seen.remove(sub_id);
+        }
+    }
+
+    pub fn unsubscribed(&self, sub_id: &str) {
+        self.sent.lock().remove(sub_id);
+    }
+
+    /// `url` sent `message`
+    pub fn message(&self, url: &str, message: &RelayMessage, now: Instant) {
+        let sent = match message {
+            RelayMessage::EndOfStoredEvents(sub_id) => {
+                self.sent.lock().get(&sub_id.to_string()).copied()
+            }
+            _ => None,
+        };
+        let mut relays = self.relays.lock();
+        let state = relays.entry(url.to_string()).or_default();
+
+        let window_start = *state.window_start.get_or_insert(now);
+        state.window_messages += 1;
+        let elapsed = now.duration_since(window_start);
+        if elapsed >= RATE_WINDOW {
+            state.messages_per_sec = state.window_messages as f64 / elapsed.as_secs_f64();
+            state.window_start = Some(now);
+            state.window_messages = 0;
+        }
+
+        match message {
+            RelayMessage::Notice { message } => {
+                state.notices += 1;
+                state.last_error = Some(format!("Notice: {}", message));
+            }
+            RelayMessage::Closed {
+                subscription_id,
+                message,
+            } => {
+                state.last_error = Some(format!("Closed {}: {}", subscription_id, message));
+            }
+            RelayMessage::Ok {
+                status: false,
+                message,
+                ..
+            } => {
+                state.last_error = Some(format!("Rejected event: {}", message));
+            }
+            RelayMessage::EndOfStoredEvents(sub_id) => {
+                // Subscriptions are resent on reconnect, so latency counts
+                // from whichever came last
+                let Some(sent) = sent else {
+                    return;
+                };
+                if !state.eose_seen.insert(sub_id.to_string()) {
+                    return;
+                }
+                let since = state.connected_at.map_or(sent, |at| at.max(sent));
+                let sample = now.duration_since(since);
+                state.eose_latency = Some(match state.eose_latency {
+                    Some(average) => {
+                        average.mul_f64(1.0 - LATENCY_WEIGHT) + sample.mul_f64(LATENCY_WEIGHT)
+                    }
+                    None => sample,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Stats entry of `url`
+    pub fn entry(
+        &self,
+        url: &str,
+        connected: bool,
+        connecting: bool,
+        bytes_sent: u64,
+        bytes_received: u64,
+        now: Instant,
+    ) -> RelayStatEntry {
+        let relays = self.relays.lock();
+        let default = RelayState::default();
+        let state = relays.get(url).unwrap_or(&default);
+        RelayStatEntry {
+            url: url.to_string(),
+            connected,
+            connecting,
+            score: state.score(),
+            demoted: state.demoted(),
+            failures: state.consecutive_failures,
+            next_retry_secs: state
+                .next_attempt
+                .filter(|_| !connected)
+                .map(|at| at.saturating_duration_since(now).as_secs()),
+            last_error: state.last_error.clone(),
+            messages_per_sec: state.messages_per_sec,
+            eose_latency_ms: state.eose_latency.map(|latency| latency.as_millis() as u64),
+            notices: state.notices,
+            bytes_sent,
+            bytes_received,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::SubscriptionId;
+
+    const URL: &str = "wss://relay.example/";
+
+    #[test]
+    fn test_backoff_doubles_then_demotes() {
+        assert_eq!(backoff(1), BASE_BACKOFF);
+        assert_eq!(backoff(2), BASE_BACKOFF * 2);
+        assert_eq!(backoff(3), BASE_BACKOFF * 4);
+        assert_eq!(backoff(DEMOTE_AFTER - 1), MAX_BACKOFF);
+        assert_eq!(backoff(DEMOTE_AFTER), DEMOTED_RETRY);
+    }
+
+    #[test]
+    fn test_attempts_wait_for_backoff_and_demote() {
+        let health = RelayHealth::default();
+        let start = Instant::now();
+        assert!(health.begin_attempt(URL, start));
+        assert!(!health.begin_attempt(URL, start), "already attempting");
+
+        health.failed(URL, "Connection failed".to_string(), start);
+        assert!(!health.begin_attempt(URL, start));
+        assert!(health.begin_attempt(URL, start + BASE_BACKOFF));
+
+        let mut now = start + BASE_BACKOFF;
+        for _ in 2..DEMOTE_AFTER {
+            health.failed(URL, "Connection failed".to_string(), now);
+            now += DEMOTED_RETRY;
+            assert!(health.begin_attempt(URL, now));
+        }
+        let entry = health.entry(URL, false, true, 0, 0, now);
+        assert!(!entry.demoted);
+        health.failed(URL, "Connection failed".to_string(), now);
+        let entry = health.entry(URL, false, false, 0, 0, now);
+        assert!(entry.demoted);
+        assert_eq!(entry.score, 0);
+        assert_eq!(entry.next_retry_secs, Some(DEMOTED_RETRY.as_secs()));
+        assert_eq!(entry.last_error.as_deref(), Some("Connection failed"));
+
+        health.connected(URL, now);
+        let entry = health.entry(URL, true, false, 0, 0, now);
+        assert!(!entry.demoted);
+        assert_eq!(entry.failures, 0);
+        assert_eq!(entry.next_retry_secs, None);
+
+        // A drop after being up is retried quickly
+        health.disconnected(URL, now);
+        assert!(!health.begin_attempt(URL, now));
+        assert!(health.begin_attempt(URL, now + BASE_BACKOFF));
+    }
+
+    #[test]
+    fn test_messages_feed_score() {
+        let health = RelayHealth::default();
+        let start = Instant::now();
+        health.begin_attempt(URL, start);
+        health.connected(URL, start);
+        health.subscribed("feed", start);
+        assert_eq!(health.entry(URL, true, false, 0, 0, start).score, 100);
+
+        let eose = RelayMessage::EndOfStoredEvents(SubscriptionId::new("feed"));
+        health.message(URL, &eose, start + Duration::from_millis(500));
+        // A repeated EOSE doesn't count again
+        health.message(URL, &eose, start + Duration::from_secs(60));
+        let entry = health.entry(URL, true, false, 0, 0, start);
+        assert_eq!(entry.eose_latency_ms, Some(500));
+        assert_eq!(entry.score, 95);
+
+        let notice = RelayMessage::Notice {
+            message: "slow down".to_string(),
+        };
+        health.message(URL, &notice, start + Duration::from_secs(61));
+        let entry = health.entry(URL, true, false, 0, 0, start);
+        assert_eq!(entry.notices, 1);
+        assert_eq!(entry.score, 90);
+        assert_eq!(entry.last_error.as_deref(), Some("Notice: slow down"));
+        assert!(entry.messages_per_sec > 0.0);
+    }
+}
@@ -0,0 +1,317 @@
+//! Priority scheduling of worker requests
+//!
+//! Every `worker_message` waits for a slot before it runs. Slots go to
+//! interactive reads first, then metadata requests, then background work
+//! (imports, pushes, republishing, indexing, eviction), and each class is
+//! capped so a big import or push can't take every slot from the UI.
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+/// Scheduling class of a request, most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Priority {
+    /// Reads the UI is waiting on
+    Interactive,
+    /// Small lookups, settings and publishes
+    Metadata,
+    /// Bulk work nobody is watching block by block
+    Background,
+}
+
+const PRIORITIES: [Priority; 3] = [
+    Priority::Interactive,
+    Priority::Metadata,
+    Priority::Background,
+];
+
+/// Concurrency limits, overall and per class
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub total: usize,
+    pub interactive: usize,
+    pub metadata: usize,
+    pub background: usize,
+}
+
+impl Limits {
+    fn for_priority(&self, priority: Priority) -> usize {
+        match priority {
+            Priority::Interactive => self.interactive,
+            Priority::Metadata => self.metadata,
+            Priority::Background => self.background,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            total: 16,
+            interactive: 16,
+            metadata: 8,
+            background: 2,
+        }
+    }
+}
+
+/// A queued or running request, as reported by `getJobs`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: String,
+    pub priority: Priority,
+    pub running: bool,
+    /// Time since the request arrived
+    pub age_ms: u64,
+}
+
+struct Job {
+    id: String,
+    kind: &'static str,
+    priority: Priority,
+    queued_at: Instant,
+}
+
+#[derive(Default)]
+struct Queue {
+    next_seq: u64,
+    running: HashMap<u64, Job>,
+    waiting: HashMap<Priority, VecDeque<(u64, Job, oneshot::Sender<()>)>>,
+}
+
+impl Queue {
+    fn can_start(&self, priority: Priority, limits: &Limits) -> bool {
+        let running_in_class = self
+            .running
+            .values()
+            .filter(|job| job.priority == priority)
+            .count();
+        self.running.len() < limits.total && running_in_class < limits.for_priority(priority)
+    }
+
+    /// Start waiting jobs, most urgent class first, while slots are free
+    fn schedule(&mut self, limits: &Limits) {
+        for priority in PRIORITIES {
+            while self.can_start(priority, limits) {
+                let Some((seq, job, start)) = self
+                    .waiting
+                    .get_mut(&priority)
+                    .and_then(|queue| queue.pop_front())
+                else {
+                    break;
+                };
+                // A closed receiver means the request was dropped while queued
+                if start.send(()).is_ok() {
+                    self.running.insert(seq, job);
+                }
+            }
+        }
+    }
+}
+
+/// Hands out run slots to worker requests
+#[derive(Clone)]
+pub struct Scheduler {
+    queue: Arc<Mutex<Queue>>,
+    limits: Limits,
+}
+
+impl Scheduler {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(Queue::default())),
+            limits,
+        }
+    }
+
+    /// Wait for a slot for request `id`; it's held until the permit drops
+    pub async fn acquire(&self, id: &str, kind: &'static str, priority: Priority) -> JobPermit {
+        let job = Job {
+            id: id.to_string(),
+            kind,
+            priority,
+            queued_at: Instant::now(),
+        };
+        let (seq, started) = {
+            let mut queue = self.queue.lock();
+            let seq = queue.next_seq;
+            queue.next_seq += 1;
+            if queue.can_start(priority, &self.limits) {
+                queue.running.insert(seq, job);
+                (seq, None)
+            } else {
+                let (tx, rx) = oneshot::channel();
+                queue
+                    .waiting
+                    .entry(priority)
+                    .or_default()
+                    .push_back((seq, job, tx));
+                (seq, Some(rx))
+            }
+        };
+        // Created before waiting so a cancelled request leaves the queue
+        let permit = JobPermit {
+            scheduler: self.clone(),
+            seq,
+        };
+        if let Some(started) = started {
+            // The sender lives in the queue until the job is started
+            let _ = started.await;
+        }
+        permit
+    }
+
+    /// Running jobs, then queued jobs in the order they'll start
+    pub fn jobs(&self) -> Vec<JobInfo> {
+        let queue = self.queue.lock();
+        let now = Instant::now();
+        let info = |job: &Job, running: bool| JobInfo {
+            id: job.id.clone(),
+            kind: job.kind.to_string(),
+            priority: job.priority,
+            running,
+            age_ms: now.duration_since(job.queued_at).as_millis() as u64,
+        };
+
+        let mut running: Vec<&Job> = queue.running.values().collect();
+        running.sort_by_key(|job| (job.priority, job.queued_at));
+        let mut jobs: Vec<JobInfo> = running.into_iter().map(|job| info(job, true)).collect();
+        for priority in PRIORITIES {
+            if let Some(waiting) = queue.waiting.get(&priority) {
+                jobs.extend(waiting.iter().map(|(_, job, _)| info(job, false)));
+            }
+        }
+        jobs
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+/// A running job's slot, released on drop
+pub struct JobPermit {
+    scheduler: Scheduler,
+    seq: u64,
+}
+
+impl Drop for JobPermit {
+    fn drop(&mut self) {
+        let mut queue = self.scheduler.queue.lock();
+        if queue.running.remove(&self.seq).is_none() {
+            for waiting in queue.waiting.values_mut() {
+                waiting.retain(|(seq, _, _)| *seq != self.seq);
+            }
+        }
+        queue.schedule(&self.scheduler.limits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            total: 2,
+            interactive: 2,
+            metadata: 2,
+            background: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_background_is_capped() {
+        let scheduler = Scheduler::new(limits());
+        let _push = scheduler
+            .acquire("1", "pushToBlossom", Priority::Background)
+            .await;
+
+        let queued = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                scheduler
+                    .acquire("2", "indexTree", Priority::Background)
+                    .await
+            })
+        };
+        tokio::task::yield_now().await;
+        // The second background job waits, but a read still gets the free slot
+        let _read = scheduler
+            .acquire("3", "readFile", Priority::Interactive)
+            .await;
+
+        let jobs = scheduler.jobs();
+        assert_eq!(
+            jobs.iter()
+                .map(|j| (j.id.as_str(), j.running))
+                .collect::<Vec<_>>(),
+            vec![("3", true), ("1", true), ("2", false)]
+        );
+        assert!(!queued.is_finished());
+    }
+
+    #[tokio::test]
+    async fn test_freed_slots_go_to_most_urgent() {
+        let scheduler = Scheduler::new(limits());
+        let first = scheduler.acquire("1", "get", Priority::Interactive).await;
+        let second = scheduler.acquire("2", "get", Priority::Interactive).await;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for (id, priority) in [
+            ("bg", Priority::Background),
+            ("read", Priority::Interactive),
+        ] {
+            let scheduler = scheduler.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.acquire(id, "job", priority).await;
+                tx.send(id).unwrap();
+                std::future::pending::<()>().await;
+            });
+        }
+        while scheduler.jobs().len() < 4 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(first);
+        assert_eq!(rx.recv().await, Some("read"));
+        drop(second);
+        assert_eq!(rx.recv().await, Some("bg"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiters_leave_the_queue() {
+        let scheduler = Scheduler::new(Limits {
+            total: 1,
+            ..limits()
+        });
+        let permit = scheduler.acquire("1", "get", Priority::Interactive).await;
+
+        let waiting = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                scheduler.acquire("2", "get", Priority::Interactive).await;
+            })
+        };
+        while scheduler.jobs().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        waiting.abort();
+        let _ = waiting.await;
+        assert_eq!(scheduler.jobs().len(), 1);
+
+        drop(permit);
+        assert!(scheduler.jobs().is_empty());
+        let _next = scheduler.acquire("3", "get", Priority::Interactive).await;
+    }
+}
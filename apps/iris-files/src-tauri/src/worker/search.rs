@@ -0,0 +1,600 @@
+//! Search index over tree contents using heed (LMDB)
+//!
+//! Records filenames (and the text of small text files) of synced/imported
+//! trees, keyed by (npub, tree, path), so trees can be searched without
+//! walking them again. Media files also get their metadata recorded for the
+//! photo timeline and music library views.
+
+use heed::types::{Bytes, Str};
+use heed::{CompactionOption, Database, Env, EnvOpenOptions};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::debug;
+
+use super::media::{self, MediaMetadata};
+use super::progress::ProgressReporter;
+use super::tree::TreeManager;
+use super::types::{MediaFilter, MediaItem, MediaSort, SearchHit, WorkerCid};
+use crate::history::fuzzy_match_string;
+
+/// Only files up to this size have their text indexed
+const MAX_TEXT_BYTES: u64 = 64 * 1024;
+
+/// Maximum number of entries indexed per tree
+const MAX_ENTRIES_PER_TREE: usize = 10_000;
+
+/// Maximum directory depth walked while indexing
+const MAX_DEPTH: usize = 32;
+
+/// Characters of context shown on each side of a text match
+const SNIPPET_CONTEXT: usize = 40;
+
+/// File extensions whose content is indexed as text
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "json", "csv", "html", "htm", "xml", "yaml", "yml", "toml", "js",
+    "ts", "css", "rs", "py", "sh", "log",
+];
+
+/// Indexed entry stored in the database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchEntry {
+    pub npub: String,
+    pub tree_name: String,
+    pub path: String,
+    pub name: String,
+    pub hash: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub text: Option<String>,
+    pub media: Option<MediaMetadata>,
+}
+
+/// Search index using heed/LMDB
+pub struct SearchIndex {
+    env: Env,
+    db: Database<Str, Bytes>,
+}
+
+/// Build the database key for an entry. Tree names may contain '/', so
+/// the components are separated with NUL.
+fn entry_key(npub: &str, tree_name: &str, path: &str) -> String {
+    format!("{}\0{}\0{}", npub, tree_name, path)
+}
+
+fn tree_prefix(npub: &str, tree_name: &str) -> String {
+    format!("{}\0{}\0", npub, tree_name)
+}
+
+/// Whether a file's content should be indexed as text
+fn is_text_filename(name: &str) -> bool {
+    name.rsplit_once('.')
+        .map(|(_, ext)| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+impl SearchIndex {
+    /// Open or create the search index
+    pub fn new(data_dir: &Path) -> Result<Self, String> {
+        let search_dir = data_dir.join("search");
+        std::fs::create_dir_all(&search_dir)
+            .map_err(|e| format!("Failed to create search dir: {}", e))?;
+
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(512 * 1024 * 1024) // 512MB, text of small files adds up
+                .max_dbs(1)
+                .open(&search_dir)
+                .map_err(|e| format!("Failed to open search db: {}", e))?
+        };
+        if let Ok(cleared) = env.clear_stale_readers() {
+            if cleared > 0 {
+                debug!("Cleared {} stale LMDB readers for search index", cleared);
+            }
+        }
+
+        let mut wtxn = env
+            .write_txn()
+            .map_err(|e| format!("Failed to start txn: {}", e))?;
+        let db = env
+            .create_database(&mut wtxn, Some("search"))
+            .map_err(|e| format!("Failed to create db: {}", e))?;
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+
+        Ok(Self { env, db })
+    }
+
+    /// Write a consistent, compacted copy of the index into `dir`, for export
+    pub fn copy_to(&self, dir: &Path) -> Result<(), String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+        self.env
+            .copy_to_file(dir.join("data.mdb"), CompactionOption::Enabled)
+            .map_err(|e| format!("Failed to copy search db: {}", e))?;
+        Ok(())
+    }
+
+    /// Replace all indexed entries of a tree
+    pub fn replace_tree(
+        &self,
+        npub: &str,
+        tree_name: &str,
+        entries: Vec<SearchEntry>,
+    ) -> Result<(), String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+
+        self.remove_tree_in_txn(&mut wtxn, npub, tree_name)?;
+
+        for entry in &entries {
+            let bytes =
+                bincode::serialize(entry).map_err(|e| format!("Failed to serialize: {}", e))?;
+            self.db
+                .put(&mut wtxn, &entry_key(npub, tree_name, &entry.path), &bytes)
+                .map_err(|e| format!("Failed to put: {}", e))?;
+        }
+
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))?;
+
+        debug!("Indexed {} entries for {}/{}", entries.len(), npub, tree_name);
+        Ok(())
+    }
+
+    /// Remove all indexed entries of a tree
+    pub fn remove_tree(&self, npub: &str, tree_name: &str) -> Result<(), String> {
+        let mut wtxn = self
+            .env
+            .write_txn()
+            .map_err(|e| format!("Failed to start write txn: {}", e))?;
+        self.remove_tree_in_txn(&mut wtxn, npub, tree_name)?;
+        wtxn.commit()
+            .map_err(|e| format!("Failed to commit: {}", e))
+    }
+
+    fn remove_tree_in_txn(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        npub: &str,
+        tree_name: &str,
+    ) -> Result<(), String> {
+        let prefix = tree_prefix(npub, tree_name);
+        let mut keys: Vec<String> = Vec::new();
+        {
+            let iter = self
+                .db
+                .iter(wtxn)
+                .map_err(|e| format!("Failed to iterate: {}", e))?;
+            for item in iter {
+                let (key, _) = item.map_err(|e| format!("Iter error: {}", e))?;
+                if key.starts_with(&prefix) {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+        for key in keys {
+            self.db
+                .delete(wtxn, &key)
+                .map_err(|e| format!("Failed to delete: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Search indexed entries, optionally restricted to one npub.
+    /// Filename matches rank above text matches.
+    pub fn search(
+        &self,
+        query: &str,
+        npub: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, String> {
+        let query_lower = query.trim().to_lowercase();
+        if query_lower.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let rtxn = self
+            .env
+            .read_txn()
+            .map_err(|e| format!("Failed to start read txn: {}", e))?;
+
+        let mut hits: Vec<SearchHit> = Vec::new();
+        let iter = self
+            .db
+            .iter(&rtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?;
+
+        for item in iter {
+            let (_key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
+            let entry = match bincode::deserialize::<SearchEntry>(value) {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            if let Some(npub) = npub {
+                if entry.npub != npub {
+                    continue;
+                }
+            }
+            if let Some(hit) = score_entry(&query_lower, entry) {
+                hits.push(hit);
+            }
+        }
+
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// List indexed media files matching a filter
+    pub fn query_media(
+        &self,
+        filter: &MediaFilter,
+        sort: MediaSort,
+        limit: usize,
+    ) -> Result<Vec<MediaItem>, String> {
+        let rtxn = self
+            .env
+            .read_txn()
+            .map_err(|e| format!("Failed to start read txn: {}", e))?;
+
+        let mut items: Vec<MediaItem> = Vec::new();
+        let iter = self
+            .db
+            .iter(&rtxn)
+            .map_err(|e| format!("Failed to iterate: {}", e))?;
+
+        for item in iter {
+            let (_key, value) = item.map_err(|e| format!("Iter error: {}", e))?;
+            let entry = match bincode::deserialize::<SearchEntry>(value) {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+            let Some(media) = entry.media else {
+                continue;
+            };
+            if !filter.matches(&entry.npub, &entry.tree_name, &media) {
+                continue;
+            }
+            items.push(MediaItem {
+                npub: entry.npub,
+                tree_name: entry.tree_name,
+                path: entry.path,
+                name: entry.name,
+                hash: entry.hash,
+                size: entry.size,
+                media,
+            });
+        }
+
+        match sort {
+            MediaSort::DateDesc => {
+                items.sort_by(|a, b| b.media.captured_at.cmp(&a.media.captured_at))
+            }
+            MediaSort::DateAsc => {
+                items.sort_by(|a, b| a.media.captured_at.cmp(&b.media.captured_at))
+            }
+            MediaSort::Name => {
+                items.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+            }
+            MediaSort::Duration => {
+                items.sort_by(|a, b| b.media.duration_ms.cmp(&a.media.duration_ms))
+            }
+            MediaSort::Album => items.sort_by(|a, b| {
+                a.media
+                    .album
+                    .cmp(&b.media.album)
+                    .then_with(|| a.media.title.cmp(&b.media.title))
+            }),
+        }
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    /// Walk a tree and index its filenames and small text files, reporting
+    /// each entry (and its size) to `progress`
+    pub async fn index_tree(
+        &self,
+        tree: &TreeManager,
+        npub: &str,
+        tree_name: &str,
+        root: &WorkerCid,
+        mut progress: Option<&mut ProgressReporter>,
+    ) -> Result<usize, String> {
+        if let Some(progress) = progress.as_deref_mut() {
+            progress.phase("index", None, None);
+        }
+        let mut entries: Vec<SearchEntry> = Vec::new();
+        let mut stack: Vec<(WorkerCid, String, usize)> = vec![(root.clone(), String::new(), 0)];
+
+        while let Some((dir_cid, dir_path, depth)) = stack.pop() {
+            let listing = tree.list_dir(&dir_cid).await?;
+            for child in listing {
+                if entries.len() >= MAX_ENTRIES_PER_TREE {
+                    break;
+                }
+                let path = if dir_path.is_empty() {
+                    child.name.clone()
+                } else {
+                    format!("{}/{}", dir_path, child.name)
+                };
+                let child_cid = WorkerCid {
+                    hash: child.hash.clone(),
+                    key: child.key.clone(),
+                };
+                let is_dir = child.link_type == 2;
+
+                let text = if !is_dir && child.size <= MAX_TEXT_BYTES && is_text_filename(&child.name)
+                {
+                    tree.read_file(&child_cid)
+                        .await
+                        .ok()
+                        .and_then(|data| String::from_utf8(data).ok())
+                } else {
+                    None
+                };
+
+                let media = if is_dir {
+                    None
+                } else {
+                    media::extract_from_tree(tree, &child_cid, &child.name, child.size).await
+                };
+
+                if is_dir && depth < MAX_DEPTH {
+                    stack.push((child_cid, path.clone(), depth + 1));
+                }
+
+                if let Some(progress) = progress.as_deref_mut() {
+                    progress.advance(1, child.size);
+                }
+                entries.push(SearchEntry {
+                    npub: npub.to_string(),
+                    tree_name: tree_name.to_string(),
+                    path,
+                    name: child.name,
+                    hash: child.hash,
+                    size: child.size,
+                    is_dir,
+                    text,
+                    media,
+                });
+            }
+        }
+
+        let count = entries.len();
+        self.replace_tree(npub, tree_name, entries)?;
+        if let Some(progress) = progress {
+            progress.finish();
+        }
+        Ok(count)
+    }
+}
+
+/// Score an entry against a lowercased query. Returns None for no match.
+fn score_entry(query: &str, entry: SearchEntry) -> Option<SearchHit> {
+    let name_score = fuzzy_match_string(query, &entry.name.to_lowercase());
+    let path_score = fuzzy_match_string(query, &entry.path.to_lowercase()) * 0.8;
+    let mut score = name_score.max(path_score);
+
+    let mut snippet = None;
+    if let Some(ref text) = entry.text {
+        let text_lower = text.to_lowercase();
+        if let Some(pos) = text_lower.find(query) {
+            let occurrences = text_lower.matches(query).count() as f64;
+            score = score.max(3.0 + occurrences.ln_1p() * 0.5);
+            snippet = Some(make_snippet(&text_lower, text, pos, query.len()));
+        }
+    }
+
+    if score <= 0.0 {
+        return None;
+    }
+
+    Some(SearchHit {
+        npub: entry.npub,
+        tree_name: entry.tree_name,
+        path: entry.path,
+        name: entry.name,
+        hash: entry.hash,
+        size: entry.size,
+        is_dir: entry.is_dir,
+        snippet,
+        score,
+    })
+}
+
+/// Cut a snippet of original text around a match found in its lowercased copy
+fn make_snippet(text_lower: &str, text: &str, pos: usize, len: usize) -> String {
+    // Lowercasing can change byte lengths; fall back to the lowercased text then
+    let source = if text_lower.len() == text.len() { text } else { text_lower };
+
+    let mut start = pos.saturating_sub(SNIPPET_CONTEXT);
+    while !source.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (pos + len + SNIPPET_CONTEXT).min(source.len());
+    while !source.is_char_boundary(end) {
+        end += 1;
+    }
+
+    source[start..end].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::BlobStore;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn entry(npub: &str, path: &str, text: Option<&str>) -> SearchEntry {
+        SearchEntry {
+            npub: npub.to_string(),
+            tree_name: "public".to_string(),
+            path: path.to_string(),
+            name: path.rsplit('/').next().unwrap().to_string(),
+            hash: "a".repeat(64),
+            size: 10,
+            is_dir: false,
+            text: text.map(|t| t.to_string()),
+            media: None,
+        }
+    }
+
+    #[test]
+    fn test_is_text_filename() {
+        assert!(is_text_filename("README.md"));
+        assert!(is_text_filename("notes.TXT"));
+        assert!(!is_text_filename("video.mp4"));
+        assert!(!is_text_filename("Makefile"));
+    }
+
+    #[test]
+    fn test_search_ranks_filename_above_text() {
+        let dir = tempdir().unwrap();
+        let index = SearchIndex::new(dir.path()).unwrap();
+
+        index
+            .replace_tree(
+                "npub1a",
+                "public",
+                vec![
+                    entry("npub1a", "docs/bitcoin.pdf", None),
+                    entry("npub1a", "notes.txt", Some("I read the bitcoin whitepaper")),
+                    entry("npub1a", "cat.jpg", None),
+                ],
+            )
+            .unwrap();
+
+        let hits = index.search("bitcoin", None, 10).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].path, "docs/bitcoin.pdf");
+        assert_eq!(hits[1].path, "notes.txt");
+        assert!(hits[1].snippet.as_deref().unwrap().contains("bitcoin"));
+    }
+
+    #[test]
+    fn test_search_filters_by_npub() {
+        let dir = tempdir().unwrap();
+        let index = SearchIndex::new(dir.path()).unwrap();
+
+        index
+            .replace_tree("npub1a", "public", vec![entry("npub1a", "a.txt", None)])
+            .unwrap();
+        index
+            .replace_tree("npub1b", "public", vec![entry("npub1b", "a.txt", None)])
+            .unwrap();
+
+        assert_eq!(index.search("a.txt", None, 10).unwrap().len(), 2);
+        let hits = index.search("a.txt", Some("npub1b"), 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].npub, "npub1b");
+    }
+
+    #[test]
+    fn test_replace_tree_drops_old_entries() {
+        let dir = tempdir().unwrap();
+        let index = SearchIndex::new(dir.path()).unwrap();
+
+        index
+            .replace_tree("npub1a", "public", vec![entry("npub1a", "old.txt", None)])
+            .unwrap();
+        index
+            .replace_tree("npub1a", "public", vec![entry("npub1a", "new.txt", None)])
+            .unwrap();
+
+        assert!(index.search("old", None, 10).unwrap().is_empty());
+        assert_eq!(index.search("new", None, 10).unwrap().len(), 1);
+
+        index.remove_tree("npub1a", "public").unwrap();
+        assert!(index.search("new", None, 10).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_index_tree_indexes_names_and_text() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(BlobStore::new(dir.path().to_path_buf()));
+        let tree = TreeManager::new(store);
+        let index = SearchIndex::new(dir.path()).unwrap();
+
+        let root = tree.create_empty_dir(false).await.unwrap();
+        let root = tree
+            .write_file(Some(&root), "readme.md", b"hello hashtree", false)
+            .await
+            .unwrap();
+        let root = tree
+            .write_file(Some(&root), "photo.jpg", b"not really a jpeg", false)
+            .await
+            .unwrap();
+
+        let count = index.index_tree(&tree, "npub1a", "public", &root, None).await.unwrap();
+        assert_eq!(count, 2);
+
+        let hits = index.search("hashtree", None, 10).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, "readme.md");
+
+        // Binary-ish files are indexed by name only
+        assert!(index.search("jpeg", None, 10).unwrap().is_empty());
+        assert_eq!(index.search("photo", None, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_query_media_filters_and_sorts() {
+        use super::media::MediaKind;
+
+        let dir = tempdir().unwrap();
+        let index = SearchIndex::new(dir.path()).unwrap();
+
+        let with_media = |path: &str, kind: MediaKind, captured_at: u64| {
+            let mut e = entry("npub1a", path, None);
+            e.media = Some(MediaMetadata {
+                kind: Some(kind),
+                captured_at: Some(captured_at),
+                ..Default::default()
+            });
+            e
+        };
+
+        index
+            .replace_tree(
+                "npub1a",
+                "public",
+                vec![
+                    with_media("old.jpg", MediaKind::Image, 100),
+                    with_media("new.jpg", MediaKind::Image, 300),
+                    with_media("song.mp3", MediaKind::Audio, 200),
+                    entry("npub1a", "notes.txt", None),
+                ],
+            )
+            .unwrap();
+
+        let all = index
+            .query_media(&MediaFilter::default(), MediaSort::DateDesc, 10)
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].name, "new.jpg");
+
+        let filter = MediaFilter {
+            kind: Some(MediaKind::Image),
+            ..Default::default()
+        };
+        let images = index.query_media(&filter, MediaSort::DateAsc, 10).unwrap();
+        let names: Vec<_> = images.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["old.jpg", "new.jpg"]);
+
+        let filter = MediaFilter {
+            since: Some(150),
+            until: Some(250),
+            ..Default::default()
+        };
+        let ranged = index.query_media(&filter, MediaSort::DateAsc, 10).unwrap();
+        assert_eq!(ranged.len(), 1);
+        assert_eq!(ranged[0].name, "song.mp3");
+    }
+}
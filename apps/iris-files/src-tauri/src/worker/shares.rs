@@ -0,0 +1,258 @@
+//! Per-recipient sharing of private trees
+//!
+//! A private tree's root key is wrapped (NIP-44) to each recipient and
+//! published as a share event: kind 30078, `d` = `hashtree-share/<recipient>/<tree>`,
+//! labelled `hashtree-share` so tree listings ignore it. The event carries the
+//! root hash and the wrapped key, so a recipient can open the tree without
+//! the author's root event. Revoking replaces the share event with one that
+//! has no key; it doesn't un-share content the recipient already fetched
+//! (rotate the tree key for that).
+//!
+//! Granted recipients are remembered locally so the worker can re-wrap the
+//! key whenever the owner publishes a new root.
+
+use nostr_sdk::nips::nip44;
+use nostr_sdk::{Event, Filter, Keys, Kind, PublicKey, SingleLetterTag};
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use super::nostr::NostrManager;
+use super::types::WorkerCid;
+
+pub use hashtree_resolver::nostr::{share_d_tag, SHARE_LABEL};
+
+const KIND_SHARE: u16 = 30078;
+
+/// Parse a recipient given as npub or hex
+pub fn parse_pubkey(pubkey: &str) -> Result<PublicKey, String> {
+    if pubkey.starts_with("npub1") {
+        PublicKey::parse(pubkey).map_err(|e| format!("Invalid npub: {}", e))
+    } else {
+        PublicKey::from_hex(pubkey).map_err(|e| format!("Invalid pubkey: {}", e))
+    }
+}
+
+/// Build a share event granting `recipient` access to `cid`, or revoking
+/// access when `cid` is None. The format is shared with the CLI (see
+/// [`hashtree_resolver::nostr::share_event`]).
+pub fn build_share_event(
+    keys: &Keys,
+    tree_name: &str,
+    recipient: &PublicKey,
+    cid: Option<&WorkerCid>,
+) -> Result<Event, String> {
+    let cid = cid
+        .map(|cid| -> Result<hashtree_core::Cid, String> {
+            let key = cid
+                .key
+                .as_deref()
+                .ok_or("Only encrypted trees can be shared")?;
+            Ok(hashtree_core::Cid {
+                hash: hashtree_core::from_hex(&cid.hash)
+                    .map_err(|e| format!("Invalid hash: {}", e))?,
+                key: Some(
+                    hashtree_core::key_from_hex(key).map_err(|e| format!("Invalid key: {}", e))?,
+                ),
+            })
+        })
+        .transpose()?;
+    hashtree_resolver::nostr::share_event(keys, tree_name, recipient, cid.as_ref())
+        .map_err(|e| e.to_string())?
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+/// First value of tag `name`
+pub fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event.tags.iter().find_map(|tag| {
+        let values = tag.as_slice();
+        (values.len() >= 2 && values[0] == name).then(|| values[1].as_str())
+    })
+}
+
+/// Unwrap a share event addressed to `keys`. Returns None for revoked shares,
+/// shares for someone else and anything that fails to decrypt.
+pub fn unwrap_share_event(keys: &Keys, event: &Event) -> Option<WorkerCid> {
+    if tag_value(event, "p")? != keys.public_key().to_hex() {
+        return None;
+    }
+    let hash = tag_value(event, "hash")?.to_string();
+    let wrapped = tag_value(event, "wrappedKey")?;
+    let key = nip44::decrypt(keys.secret_key(), &event.pubkey, wrapped).ok()?;
+    if key.len() != 64 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(WorkerCid {
+        hash,
+        key: Some(key),
+    })
+}
+
+/// Publish a share event for `recipient` (a revocation when `cid` is None)
+pub async fn publish_share(
+    nostr: &NostrManager,
+    tree_name: &str,
+    recipient: &PublicKey,
+    cid: Option<&WorkerCid>,
+) -> Result<(), String> {
+    let keys = nostr.get_keys().ok_or("No signing identity set")?;
+    let event = build_share_event(&keys, tree_name, recipient, cid)?;
+    let event_json =
+        serde_json::to_value(&event).map_err(|e| format!("Failed to encode event: {}", e))?;
+    nostr.publish(event_json).await.map(|_| ())
+}
+
+/// Fetch and unwrap the share `author` published to us for `tree_name`
+pub async fn fetch_share(
+    nostr: &NostrManager,
+    author: &PublicKey,
+    tree_name: &str,
+) -> Option<WorkerCid> {
+    let keys = nostr.get_keys()?;
+    let filter = Filter::new()
+        .kind(Kind::from(KIND_SHARE))
+        .author(*author)
+        .custom_tag(
+            SingleLetterTag::from_char('d').unwrap(),
+            vec![share_d_tag(tree_name, &keys.public_key())],
+        );
+    let events = nostr.fetch_events(vec![filter]).await.ok()?;
+    let latest = events.iter().max_by_key(|e| e.created_at)?;
+    unwrap_share_event(&keys, latest)
+}
+
+/// Recipients granted access, per owner and tree, persisted as JSON
+pub struct ShareRegistry {
+    path: PathBuf,
+    /// "owner_hex/tree_name" -> recipient hex pubkeys
+    grants: RwLock<BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl ShareRegistry {
+    pub fn new(data_dir: &Path) -> Self {
+        let path = data_dir.join("shares.json");
+        let grants = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            grants: RwLock::new(grants),
+        }
+    }
+
+    fn key(owner: &str, tree_name: &str) -> String {
+        format!("{}/{}", owner, tree_name)
+    }
+
+    fn save(&self, grants: &BTreeMap<String, BTreeSet<String>>) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(grants)
+            .map_err(|e| format!("Failed to encode shares: {}", e))?;
+        std::fs::write(&self.path, data).map_err(|e| format!("Failed to save shares: {}", e))
+    }
+
+    pub fn grant(&self, owner: &str, tree_name: &str, recipient: &str) -> Result<(), String> {
+        let mut grants = self.grants.write();
+        grants
+            .entry(Self::key(owner, tree_name))
+            .or_default()
+            .insert(recipient.to_string());
+        self.save(&grants)
+    }
+
+    pub fn revoke(&self, owner: &str, tree_name: &str, recipient: &str) -> Result<(), String> {
+        let mut grants = self.grants.write();
+        let key = Self::key(owner, tree_name);
+        if let Some(recipients) = grants.get_mut(&key) {
+            recipients.remove(recipient);
+            if recipients.is_empty() {
+                grants.remove(&key);
+            }
+        }
+        self.save(&grants)
+    }
+
+    pub fn recipients(&self, owner: &str, tree_name: &str) -> Vec<String> {
+        self.grants
+            .read()
+            .get(&Self::key(owner, tree_name))
+            .map(|r| r.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn test_cid() -> WorkerCid {
+        WorkerCid {
+            hash: "a".repeat(64),
+            key: Some("b".repeat(64)),
+        }
+    }
+
+    #[test]
+    fn test_share_event_roundtrip() {
+        let owner = Keys::generate();
+        let recipient = Keys::generate();
+        let cid = test_cid();
+
+        let event =
+            build_share_event(&owner, "photos", &recipient.public_key(), Some(&cid)).unwrap();
+        assert!(event.verify().is_ok());
+        assert_eq!(
+            tag_value(&event, "d"),
+            Some(share_d_tag("photos", &recipient.public_key()).as_str())
+        );
+        assert!(!serde_json::to_string(&event)
+            .unwrap()
+            .contains(&"b".repeat(64)));
+
+        let unwrapped = unwrap_share_event(&recipient, &event).unwrap();
+        assert_eq!(unwrapped.hash, cid.hash);
+        assert_eq!(unwrapped.key, cid.key);
+
+        // Nobody else can unwrap it
+        assert!(unwrap_share_event(&Keys::generate(), &event).is_none());
+        assert!(unwrap_share_event(&owner, &event).is_none());
+    }
+
+    #[test]
+    fn test_revoked_share_has_no_key() {
+        let owner = Keys::generate();
+        let recipient = Keys::generate();
+        let event = build_share_event(&owner, "photos", &recipient.public_key(), None).unwrap();
+        assert_eq!(tag_value(&event, "wrappedKey"), None);
+        assert!(unwrap_share_event(&recipient, &event).is_none());
+
+        let unencrypted = WorkerCid {
+            hash: "a".repeat(64),
+            key: None,
+        };
+        assert!(build_share_event(
+            &owner,
+            "photos",
+            &recipient.public_key(),
+            Some(&unencrypted)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_share_registry_persists() {
+        let dir = TempDir::new().unwrap();
+        let registry = ShareRegistry::new(dir.path());
+        registry.grant("owner", "photos", "alice").unwrap();
+        registry.grant("owner", "photos", "bob").unwrap();
+        registry.grant("owner", "docs", "carol").unwrap();
+        registry.revoke("owner", "photos", "alice").unwrap();
+
+        let reopened = ShareRegistry::new(dir.path());
+        assert_eq!(reopened.recipients("owner", "photos"), vec!["bob"]);
+        assert_eq!(reopened.recipients("owner", "docs"), vec!["carol"]);
+        assert!(reopened.recipients("other", "photos").is_empty());
+    }
+}
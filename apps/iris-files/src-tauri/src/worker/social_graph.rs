@@ -0,0 +1,180 @@
+//! Cached follow-distance levels of the social graph
+//!
+//! `getUsersWithinDistance` walks the graph breadth first from our pubkey,
+//! one `get_followed` query per user, which takes seconds at distance 3 on a
+//! large graph. The levels found are kept and reused until a contact list
+//! (kind 3) comes in, which bumps the graph version; a request for a larger
+//! distance continues the walk from the deepest cached level.
+
+use nostrdb::{Ndb, Transaction};
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Users by follow distance from `root`, as of graph `version`
+struct Levels {
+    root: [u8; 32],
+    version: u64,
+    /// `levels[0]` is at distance 1
+    levels: Vec<Vec<[u8; 32]>>,
+    /// The walk ran out of users before reaching the distance asked for
+    exhausted: bool,
+}
+
+impl Levels {
+    fn new(root: [u8; 32], version: u64) -> Self {
+        Self {
+            root,
+            version,
+            levels: Vec::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Walk on until `max_distance` is reached or nobody's left, calling
+    /// `on_level` with each level, cached ones included
+    fn extend(
+        &mut self,
+        max_distance: usize,
+        followed: impl Fn(&[u8; 32]) -> Vec<[u8; 32]>,
+        mut on_level: impl FnMut(usize, &[[u8; 32]]),
+    ) {
+        for (i, level) in self.levels.iter().take(max_distance).enumerate() {
+            on_level(i + 1, level);
+        }
+        if self.exhausted || self.levels.len() >= max_distance {
+            return;
+        }
+
+        let mut visited: HashSet<[u8; 32]> = self.levels.iter().flatten().copied().collect();
+        visited.insert(self.root);
+        let mut current = self
+            .levels
+            .last()
+            .cloned()
+            .unwrap_or_else(|| vec![self.root]);
+        while self.levels.len() < max_distance {
+            let mut next = Vec::new();
+            for pk in &current {
+                for followed_pk in followed(pk) {
+                    if visited.insert(followed_pk) {
+                        next.push(followed_pk);
+                    }
+                }
+            }
+            if next.is_empty() {
+                self.exhausted = true;
+                return;
+            }
+            on_level(self.levels.len() + 1, &next);
+            self.levels.push(next.clone());
+            current = next;
+        }
+    }
+}
+
+/// Follow-distance levels kept between requests
+#[derive(Default)]
+pub struct SocialGraphCache {
+    version: AtomicU64,
+    levels: Mutex<Option<Levels>>,
+}
+
+impl SocialGraphCache {
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// Drop the cached levels after a contact list changed, returning the
+    /// new graph version
+    pub fn invalidate(&self) -> u64 {
+        self.version.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Users within `max_distance` of `root`, passed to `on_level` a level
+    /// at a time as each one completes
+    pub fn users_within(
+        &self,
+        ndb: &Ndb,
+        root: [u8; 32],
+        max_distance: usize,
+        on_level: impl FnMut(usize, &[[u8; 32]]),
+    ) -> Result<(), String> {
+        let txn = Transaction::new(ndb).map_err(|e| format!("Transaction error: {:?}", e))?;
+        self.walk(
+            root,
+            max_distance,
+            |pk| nostrdb::socialgraph::get_followed(&txn, ndb, pk, 10000),
+            on_level,
+        );
+        Ok(())
+    }
+
+    fn walk(
+        &self,
+        root: [u8; 32],
+        max_distance: usize,
+        followed: impl Fn(&[u8; 32]) -> Vec<[u8; 32]>,
+        on_level: impl FnMut(usize, &[[u8; 32]]),
+    ) {
+        let version = self.version();
+        let mut cached = self.levels.lock();
+        let levels = match cached.take() {
+            Some(levels) if levels.root == root && levels.version == version => levels,
+            _ => Levels::new(root, version),
+        };
+        let levels = cached.insert(levels);
+        levels.extend(max_distance, followed, on_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+
+    fn pk(n: u8) -> [u8; 32] {
+        [n; 32]
+    }
+
+    #[test]
+    fn test_levels_are_cached_and_extended() {
+        // 1 follows 2 and 3, 2 follows 4, 4 follows 1 and 5
+        let graph: HashMap<[u8; 32], Vec<[u8; 32]>> = [
+            (pk(1), vec![pk(2), pk(3)]),
+            (pk(2), vec![pk(4)]),
+            (pk(4), vec![pk(1), pk(5)]),
+        ]
+        .into_iter()
+        .collect();
+        let queries = Cell::new(0);
+        let followed = |pk: &[u8; 32]| {
+            queries.set(queries.get() + 1);
+            graph.get(pk).cloned().unwrap_or_default()
+        };
+        let cache = SocialGraphCache::default();
+        let collect = |max_distance| {
+            let mut found = Vec::new();
+            cache.walk(pk(1), max_distance, followed, |distance, users| {
+                found.push((distance, users.to_vec()))
+            });
+            found
+        };
+
+        assert_eq!(collect(2), vec![(1, vec![pk(2), pk(3)]), (2, vec![pk(4)])]);
+        assert_eq!(queries.get(), 3);
+
+        // Cached, and only the new level is looked up
+        assert_eq!(collect(1), vec![(1, vec![pk(2), pk(3)])]);
+        assert_eq!(queries.get(), 3);
+        assert_eq!(collect(5).last(), Some(&(3, vec![pk(5)])));
+        assert_eq!(queries.get(), 5);
+        collect(5);
+        assert_eq!(queries.get(), 5);
+
+        cache.invalidate();
+        collect(1);
+        assert_eq!(queries.get(), 6);
+    }
+}
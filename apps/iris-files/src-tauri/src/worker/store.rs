@@ -3,10 +3,12 @@
 //! Provides a hex-string API for worker commands while using FsBlobStore
 //! from hashtree-fs for the actual storage implementation.
 
-use hashtree_fs::FsBlobStore;
+use hashtree_fs::{FsBlobStore, ScrubAction, ScrubReport};
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use super::combined_store::BlockCache;
+
 /// Default max storage: 1GB
 const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
 
@@ -14,6 +16,8 @@ const DEFAULT_MAX_BYTES: u64 = 1024 * 1024 * 1024;
 /// The underlying FsBlobStore implements hashtree_core::Store directly.
 pub struct BlobStore {
     inner: Arc<FsBlobStore>,
+    /// In-memory copies of blocks read through the tree's CombinedStore
+    cache: Arc<BlockCache>,
 }
 
 impl BlobStore {
@@ -23,6 +27,7 @@ impl BlobStore {
             .expect("Failed to create blob store");
         Self {
             inner: Arc::new(store),
+            cache: Arc::new(BlockCache::with_default_size()),
         }
     }
 
@@ -31,6 +36,11 @@ impl BlobStore {
         self.inner.clone()
     }
 
+    /// Block cache to share with a CombinedStore over `inner()`
+    pub(crate) fn block_cache(&self) -> Arc<BlockCache> {
+        self.cache.clone()
+    }
+
     /// Set maximum storage size in bytes
     pub fn set_max_bytes(&self, max: u64) {
         use hashtree_core::Store;
@@ -46,7 +56,25 @@ impl BlobStore {
     /// Evict oldest blobs if storage exceeds limit
     pub async fn evict_if_needed(&self) -> u64 {
         use hashtree_core::Store;
-        self.inner.evict_if_needed().await.unwrap_or(0)
+        let freed = self.inner.evict_if_needed().await.unwrap_or(0);
+        if freed > 0 {
+            // Which blocks went isn't reported, so forget them all
+            self.cache.clear();
+        }
+        freed
+    }
+
+    /// Re-hash every stored blob, moving corrupted ones to quarantine.
+    /// `on_blob` gets the size of each blob checked.
+    pub fn scrub(&self, on_blob: impl FnMut(u64)) -> Result<ScrubReport, String> {
+        let report = self
+            .inner
+            .scrub(ScrubAction::Quarantine, on_blob)
+            .map_err(|e| e.to_string())?;
+        for hash in &report.corrupted {
+            self.cache.remove(hash);
+        }
+        Ok(report)
     }
 
     /// Get blob by hex-encoded hash
@@ -74,12 +102,52 @@ impl BlobStore {
         self.inner.exists(&hash)
     }
 
+    /// Get many blobs by hex hash, in input order (None for missing or invalid)
+    pub async fn get_many(&self, hashes_hex: &[String]) -> Vec<Option<Vec<u8>>> {
+        let (positions, hashes) = parse_hashes(hashes_hex);
+        let mut results = vec![None; hashes_hex.len()];
+        use hashtree_core::Store;
+        if let Ok(found) = self.inner.get_many(&hashes).await {
+            for (i, data) in positions.into_iter().zip(found) {
+                results[i] = data;
+            }
+        }
+        results
+    }
+
+    /// Store many blobs; returns whether each was newly stored
+    pub async fn put_many(&self, items: Vec<(String, Vec<u8>)>) -> Result<Vec<bool>, String> {
+        let items = items
+            .into_iter()
+            .map(|(hash_hex, data)| {
+                let hash = hex_to_hash(&hash_hex).ok_or("Invalid hash hex")?;
+                Ok((hash, data))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        use hashtree_core::Store;
+        self.inner.put_many(items).await.map_err(|e| e.to_string())
+    }
+
+    /// Check which of many blobs exist, in input order
+    pub async fn has_many(&self, hashes_hex: &[String]) -> Vec<bool> {
+        let (positions, hashes) = parse_hashes(hashes_hex);
+        let mut results = vec![false; hashes_hex.len()];
+        use hashtree_core::Store;
+        if let Ok(found) = self.inner.has_many(&hashes).await {
+            for (i, exists) in positions.into_iter().zip(found) {
+                results[i] = exists;
+            }
+        }
+        results
+    }
+
     /// Delete blob by hash
     pub async fn delete(&self, hash_hex: &str) -> bool {
         let Some(hash) = hex_to_hash(hash_hex) else {
             return false;
         };
         use hashtree_core::Store;
+        self.cache.remove(&hash);
         self.inner.delete(&hash).await.unwrap_or(false)
     }
 
@@ -139,6 +207,15 @@ fn hex_to_hash(hex: &str) -> Option<[u8; 32]> {
     Some(hash)
 }
 
+/// Parse the valid hashes of a batch along with their positions in it
+fn parse_hashes(hashes_hex: &[String]) -> (Vec<usize>, Vec<[u8; 32]>) {
+    hashes_hex
+        .iter()
+        .enumerate()
+        .filter_map(|(i, hex)| Some((i, hex_to_hash(hex)?)))
+        .unzip()
+}
+
 /// Storage statistics
 #[derive(Debug, Clone)]
 pub struct StorageStats {
@@ -188,6 +265,36 @@ mod tests {
         assert!(store.has(&hash));
     }
 
+    #[tokio::test]
+    async fn test_batch_operations() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::new(dir.path().to_path_buf());
+
+        let stored = vec![
+            ("c".repeat(64), b"one".to_vec()),
+            ("d".repeat(64), b"two".to_vec()),
+        ];
+        assert_eq!(store.put_many(stored).await.unwrap(), vec![true, true]);
+        let invalid = vec![("xyz".to_string(), vec![])];
+        assert!(store.put_many(invalid).await.is_err());
+
+        // Missing and malformed hashes keep their place in the results
+        let hashes = vec![
+            "d".repeat(64),
+            "e".repeat(64),
+            "xyz".to_string(),
+            "c".repeat(64),
+        ];
+        assert_eq!(
+            store.has_many(&hashes).await,
+            vec![true, false, false, true]
+        );
+        assert_eq!(
+            store.get_many(&hashes).await,
+            vec![Some(b"two".to_vec()), None, None, Some(b"one".to_vec())]
+        );
+    }
+
     #[tokio::test]
     async fn test_get_nonexistent() {
         let dir = tempdir().unwrap();
@@ -211,6 +318,24 @@ mod tests {
         assert!(!store.has(&hash));
     }
 
+    #[tokio::test]
+    async fn test_delete_drops_cached_copy() {
+        use crate::worker::combined_store::CombinedStore;
+        use hashtree_core::Store;
+
+        let dir = tempdir().unwrap();
+        let store = BlobStore::new(dir.path().to_path_buf());
+        let combined = CombinedStore::new(store.inner()).with_block_cache(store.block_cache());
+
+        let hash = [0xdd; 32];
+        combined.put(hash, b"cached".to_vec()).await.unwrap();
+        combined.get(&hash).await.unwrap();
+        assert_eq!(combined.cache_stats().items, 1);
+
+        assert!(store.delete(&"dd".repeat(32)).await);
+        assert_eq!(combined.cache_stats().items, 0);
+    }
+
     #[tokio::test]
     async fn test_delete_nonexistent() {
         let dir = tempdir().unwrap();
@@ -233,6 +358,26 @@ mod tests {
         assert_eq!(stats.bytes, 9); // "test data" = 9 bytes
     }
 
+    #[tokio::test]
+    async fn test_scrub_quarantines_corrupted() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::new(dir.path().to_path_buf());
+
+        let data = b"scrub me";
+        let hash = hex::encode(hashtree_core::sha256(data));
+        store.put(&hash, data).await.unwrap();
+        assert!(store.scrub(|_| {}).unwrap().corrupted.is_empty());
+
+        // A blob stored under the wrong hash reads as corrupted
+        let wrong = "1".repeat(64);
+        store.put(&wrong, data).await.unwrap();
+        let report = store.scrub(|_| {}).unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(!store.has(&wrong));
+        assert!(store.has(&hash));
+    }
+
     #[tokio::test]
     async fn test_pin_and_unpin() {
         let dir = tempdir().unwrap();
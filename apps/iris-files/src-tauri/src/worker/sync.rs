@@ -0,0 +1,177 @@
+//! Pausing background sync, and the sync status at a glance
+//!
+//! While sync is paused, WebRTC is shut down and requests that push to
+//! Blossom or republish trees wait before taking a scheduler slot, so they
+//! go out once sync is resumed. Everything else keeps working against the
+//! local store and the relays. The pause isn't persisted; a restart syncs
+//! again.
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use super::scheduler::JobInfo;
+use super::types::RelayStatEntry;
+
+/// Wire names of the requests held while sync is paused
+pub const SYNC_KINDS: &[&str] = &[
+    "pushToBlossom",
+    "blossomUpload",
+    "republishTree",
+    "republishTrees",
+    "syncHistory",
+];
+
+/// Pause switch for background sync
+pub struct SyncControl {
+    paused: watch::Sender<bool>,
+}
+
+impl Default for SyncControl {
+    fn default() -> Self {
+        Self {
+            paused: watch::channel(false).0,
+        }
+    }
+}
+
+impl SyncControl {
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Pause or resume, false if it already was
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.send_if_modified(|current| {
+            let changed = *current != paused;
+            *current = paused;
+            changed
+        })
+    }
+
+    /// Returns once sync isn't paused
+    pub async fn wait_resumed(&self) {
+        let mut paused = self.paused.subscribe();
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+}
+
+/// Aggregate sync state, for the tray and status indicators
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    pub paused: bool,
+    /// Connected WebRTC peers
+    pub peers: usize,
+    /// Connected relays, out of `relays_total`
+    pub relays: usize,
+    pub relays_total: usize,
+    /// Push and republish requests running
+    pub pushing: usize,
+    /// Push and republish requests waiting for a slot
+    pub queued: usize,
+}
+
+impl SyncStatus {
+    pub fn new(paused: bool, peers: usize, relays: &[RelayStatEntry], jobs: &[JobInfo]) -> Self {
+        let sync_jobs = || {
+            jobs.iter()
+                .filter(|job| SYNC_KINDS.contains(&job.kind.as_str()))
+        };
+        Self {
+            paused,
+            peers,
+            relays: relays.iter().filter(|relay| relay.connected).count(),
+            relays_total: relays.len(),
+            pushing: sync_jobs().filter(|job| job.running).count(),
+            queued: sync_jobs().filter(|job| !job.running).count(),
+        }
+    }
+
+    /// One line for a tooltip
+    pub fn summary(&self) -> String {
+        let state = if self.paused {
+            "Sync paused".to_string()
+        } else if self.pushing + self.queued > 0 {
+            format!("Syncing ({} queued)", self.queued)
+        } else {
+            "Up to date".to_string()
+        };
+        format!(
+            "{} · {} peers · {}/{} relays",
+            state, self.peers, self.relays, self.relays_total
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::scheduler::Priority;
+
+    fn job(kind: &str, running: bool) -> JobInfo {
+        JobInfo {
+            id: kind.to_string(),
+            kind: kind.to_string(),
+            priority: Priority::Background,
+            running,
+            age_ms: 0,
+        }
+    }
+
+    #[test]
+    fn test_status_counts_sync_jobs_and_relays() {
+        let relays = vec![
+            RelayStatEntry {
+                url: "wss://a".into(),
+                connected: true,
+                connecting: false,
+                ..Default::default()
+            },
+            RelayStatEntry {
+                url: "wss://b".into(),
+                connected: false,
+                connecting: true,
+                ..Default::default()
+            },
+        ];
+        let jobs = vec![
+            job("pushToBlossom", true),
+            job("republishTrees", false),
+            job("writeFile", true),
+        ];
+        let status = SyncStatus::new(false, 3, &relays, &jobs);
+        assert_eq!(
+            status,
+            SyncStatus {
+                paused: false,
+                peers: 3,
+                relays: 1,
+                relays_total: 2,
+                pushing: 1,
+                queued: 1,
+            }
+        );
+        assert_eq!(
+            status.summary(),
+            "Syncing (1 queued) · 3 peers · 1/2 relays"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause_holds_until_resumed() {
+        let control = std::sync::Arc::new(SyncControl::default());
+        control.wait_resumed().await;
+        assert!(control.set_paused(true));
+        assert!(!control.set_paused(true));
+        assert!(control.is_paused());
+
+        let waiter = tokio::spawn({
+            let control = control.clone();
+            async move { control.wait_resumed().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        control.set_paused(false);
+        waiter.await.unwrap();
+    }
+}
@@ -2,14 +2,22 @@
 //!
 //! Provides read/write/list operations for content-addressed merkle trees.
 
-use hashtree_core::{try_decode_tree_node, Cid, HashTree, HashTreeConfig, LinkType, Store};
+use hashtree_core::{
+    nhash_encode_full, try_decode_tree_node, Cid, DirEntry, HashTree, HashTreeConfig, LinkType,
+    Log, NHashData, PackRef, Store, TreeEntry,
+};
 use hashtree_core::crypto::decrypt_chk;
-use std::collections::HashSet;
+use futures::{Stream, StreamExt};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 
-use super::combined_store::CombinedStore;
+use super::combined_store::{self, AdmissionSettings, CacheStats, CombinedStore};
 use super::store::BlobStore;
-use super::types::{WorkerCid, WorkerDirEntry};
+use super::types::{ChangeKind, DuplicateGroup, FileCopy, TreeChange, WorkerCid, WorkerDirEntry};
+use hashtree_gateway::manifest::{manifest_digest, sign_manifest, MANIFEST_FILENAME};
+use crate::tracks::encode_relative_url;
 
 /// Block from tree walk
 pub struct WalkBlock {
@@ -17,9 +25,19 @@ pub struct WalkBlock {
     pub data: Vec<u8>,
 }
 
+/// Result of a three-way merge
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeOutcome {
+    Merged(WorkerCid),
+    /// Paths changed differently on both sides
+    Conflicts(Vec<String>),
+}
+
 /// Tree manager for worker operations
 pub struct TreeManager {
     tree: HashTree<CombinedStore>,
+    /// CHK-encrypting tree for private content
+    encrypted_tree: HashTree<CombinedStore>,
     combined_store: Arc<CombinedStore>,
     store: Arc<BlobStore>,
 }
@@ -27,10 +45,39 @@ pub struct TreeManager {
 impl TreeManager {
     pub fn new(store: Arc<BlobStore>) -> Self {
         // Create combined store with Blossom fallback
-        let combined_store = Arc::new(CombinedStore::new(store.inner()));
+        let combined_store =
+            Arc::new(CombinedStore::new(store.inner()).with_block_cache(store.block_cache()));
         let config = HashTreeConfig::new(combined_store.clone()).public();
         let tree = HashTree::new(config);
-        Self { tree, combined_store, store }
+        let encrypted_tree = HashTree::new(HashTreeConfig::new(combined_store.clone()));
+        Self { tree, encrypted_tree, combined_store, store }
+    }
+
+    /// A manager over the same stores and settings, for long reads and
+    /// writes that shouldn't hold the lock around the shared one
+    pub fn detached(&self) -> Self {
+        let pack_threshold = self.tree.pack_threshold();
+        let config = HashTreeConfig::new(self.combined_store.clone()).public();
+        let tree = HashTree::new(config.with_packing(pack_threshold));
+        let encrypted_tree = HashTree::new(
+            HashTreeConfig::new(self.combined_store.clone()).with_packing(pack_threshold),
+        );
+        Self {
+            tree,
+            encrypted_tree,
+            combined_store: self.combined_store.clone(),
+            store: self.store.clone(),
+        }
+    }
+
+    /// Tree used for writes: encrypted when requested or when the parent
+    /// already is, so private trees never get plaintext nodes mixed in
+    fn writer(&self, encrypted: bool) -> &HashTree<CombinedStore> {
+        if encrypted {
+            &self.encrypted_tree
+        } else {
+            &self.tree
+        }
     }
 
     /// Update Blossom read servers for remote fetching
@@ -38,14 +85,73 @@ impl TreeManager {
         self.combined_store.set_blossom_servers(read_servers, None).await;
     }
 
+    /// Rebuild the Blossom client with the current proxy setting
+    pub async fn apply_proxy(&self) {
+        self.combined_store.apply_proxy().await;
+    }
+
+    /// Keep blocks of large files fetched from Blossom off disk until read
+    /// again, see [`Self::admit_read`]
+    pub fn set_cache_admission(&self, settings: AdmissionSettings) {
+        self.combined_store.set_admission(settings);
+    }
+
+    /// Run `read` of file `cid`, putting the blocks it fetches from Blossom
+    /// on probation if the file is over the admission size. Callers skip
+    /// this for files of trees they keep.
+    pub async fn admit_read<F: Future>(&self, cid: &WorkerCid, read: F) -> F::Output {
+        let settings = self.combined_store.admission();
+        if settings.enabled {
+            if let Some(size) = self.file_size(cid).await {
+                if settings.probation(size) {
+                    return combined_store::on_probation(read).await;
+                }
+            }
+        }
+        read.await
+    }
+
+    /// Pack blob entries of up to `threshold` bytes into shared blocks when
+    /// writing directories (0 = off), see `hashtree_core::pack`
+    pub fn set_pack_threshold(&self, threshold: usize) {
+        self.tree.set_pack_threshold(threshold);
+        self.encrypted_tree.set_pack_threshold(threshold);
+    }
+
+    /// Hit/miss counters of the in-memory block cache
+    pub fn cache_stats(&self) -> CacheStats {
+        self.combined_store.cache_stats()
+    }
+
     /// Get blob from combined store (tries local first, then Blossom)
     pub async fn get_blob(&self, hash_hex: &str) -> Option<Vec<u8>> {
         let hash = hashtree_core::from_hex(hash_hex).ok()?;
         self.combined_store.get(&hash).await.ok().flatten()
     }
 
+    /// Drop a blob from local storage and the block cache, then fetch a
+    /// fresh copy from Blossom
+    pub async fn refetch_blob(&self, hash: &[u8; 32]) -> Option<Vec<u8>> {
+        let _ = self.combined_store.delete(hash).await;
+        self.combined_store.get(hash).await.ok().flatten()
+    }
+
+    /// Get many blobs from the combined store, in input order
+    pub async fn get_blobs(&self, hashes_hex: &[String]) -> Result<Vec<Option<Vec<u8>>>, String> {
+        let hashes = hashes_hex
+            .iter()
+            .map(|hex| hashtree_core::from_hex(hex).map_err(|e| format!("Invalid hash: {}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.combined_store
+            .get_many(&hashes)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
     /// Walk all blocks in a merkle tree, returning each block's hash and data.
-    /// Handles both encrypted and unencrypted trees.
+    /// Handles both encrypted and unencrypted trees. Packed entries are
+    /// returned as their blocks, for readers without pack support, and as
+    /// their packs, for readers that fetch a directory a pack at a time.
     pub async fn walk_blocks(&self, cid: &WorkerCid) -> Result<Vec<WalkBlock>, String> {
         let hash = hashtree_core::from_hex(&cid.hash)
             .map_err(|e| format!("Invalid hash: {}", e))?;
@@ -95,6 +201,7 @@ impl TreeManager {
             if let Ok(decrypted) = decrypt_chk(&data, key) {
                 if let Some(node) = try_decode_tree_node(&decrypted) {
                     for link in node.links {
+                        self.walk_pack(&link, blocks, visited).await;
                         Box::pin(self.walk_blocks_recursive(&link.hash, link.key.as_ref(), blocks, visited)).await?;
                     }
                 }
@@ -103,6 +210,7 @@ impl TreeManager {
             // Unencrypted tree - try decode directly
             if let Some(node) = try_decode_tree_node(&data) {
                 for link in node.links {
+                    self.walk_pack(&link, blocks, visited).await;
                     Box::pin(self.walk_blocks_recursive(&link.hash, link.key.as_ref(), blocks, visited)).await?;
                 }
             }
@@ -111,6 +219,30 @@ impl TreeManager {
         Ok(())
     }
 
+    /// Add the pack of a packed link, if it's stored
+    async fn walk_pack(
+        &self,
+        link: &hashtree_core::Link,
+        blocks: &mut Vec<WalkBlock>,
+        visited: &mut HashSet<[u8; 32]>,
+    ) {
+        let Some(pack) = PackRef::from_meta(link.meta.as_ref()) else {
+            return;
+        };
+        if !visited.insert(pack.hash) {
+            return;
+        }
+        match self.store.get(&hashtree_core::to_hex(&pack.hash)).await {
+            Some(data) => blocks.push(WalkBlock {
+                hash: pack.hash,
+                data,
+            }),
+            None => {
+                visited.remove(&pack.hash);
+            }
+        }
+    }
+
     /// Convert WorkerCid to hashtree_core::Cid
     fn to_cid(worker_cid: &WorkerCid) -> Result<Cid, String> {
         let hash = hashtree_core::from_hex(&worker_cid.hash)
@@ -146,6 +278,39 @@ impl TreeManager {
             .ok_or_else(|| "File not found".to_string())
     }
 
+    /// Read file content chunk by chunk, decrypting if the CID has a key
+    pub fn read_file_stream(
+        &self,
+        cid: &WorkerCid,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Vec<u8>, String>> + Send + '_>>, String> {
+        let cid = Self::to_cid(cid)?;
+        Ok(Box::pin(self.tree.get_stream(&cid).map(|chunk| {
+            chunk.map_err(|e| format!("Read error: {}", e))
+        })))
+    }
+
+    /// Whether the CID points to a directory, including an empty one
+    pub async fn is_dir(&self, cid: &WorkerCid) -> Result<bool, String> {
+        let cid = Self::to_cid(cid)?;
+        let node = self
+            .tree
+            .get_node(&cid)
+            .await
+            .map_err(|e| format!("Read error: {}", e))?;
+        Ok(node.is_some_and(|node| node.node_type == LinkType::Dir))
+    }
+
+    /// Size of a file without reading its content, if known
+    pub async fn file_size(&self, cid: &WorkerCid) -> Option<u64> {
+        let cid = Self::to_cid(cid).ok()?;
+        match self.tree.get_node(&cid).await.ok()? {
+            Some(node) => Some(node.links.iter().map(|link| link.size).sum()),
+            None if cid.key.is_none() => self.tree.get_size(&cid.hash).await.ok(),
+            // A single encrypted chunk, only known once decrypted
+            None => None,
+        }
+    }
+
     /// Read a byte range from a file (fetches only necessary chunks)
     pub async fn read_file_range(
         &self,
@@ -176,16 +341,19 @@ impl TreeManager {
             .ok_or_else(|| "File not found".to_string())
     }
 
-    /// Write file to tree, returns new root CID
+    /// Write file to tree, returns new root CID.
+    /// Content is CHK-encrypted if `encrypted` is set or the parent has a key.
     pub async fn write_file(
         &self,
         parent_cid: Option<&WorkerCid>,
         path: &str,
         data: &[u8],
+        encrypted: bool,
     ) -> Result<WorkerCid, String> {
+        let tree = self.writer(encrypted || parent_cid.is_some_and(|p| p.key.is_some()));
+
         // First, store the file content
-        let (file_cid, file_size) = self
-            .tree
+        let (file_cid, file_size) = tree
             .put(data)
             .await
             .map_err(|e| format!("Write error: {}", e))?;
@@ -203,8 +371,7 @@ impl TreeManager {
             let filename = path_parts.last().unwrap();
             let dir_path: Vec<&str> = path_parts[..path_parts.len() - 1].to_vec();
 
-            let new_root = self
-                .tree
+            let new_root = tree
                 .set_entry(
                     &parent_cid,
                     &dir_path,
@@ -223,6 +390,81 @@ impl TreeManager {
         }
     }
 
+    /// Store content read in chunks from `reader`, returns its CID and size
+    pub async fn put_reader<R: futures::io::AsyncRead + Send + Unpin>(
+        &self,
+        reader: R,
+        encrypted: bool,
+    ) -> Result<(WorkerCid, u64), String> {
+        let (cid, size) = self
+            .writer(encrypted)
+            .put_stream(reader)
+            .await
+            .map_err(|e| format!("Write error: {}", e))?;
+        Ok((Self::from_cid(&cid), size))
+    }
+
+    /// Store a directory of already stored entries, returns its CID
+    pub async fn put_dir(
+        &self,
+        entries: &[WorkerDirEntry],
+        encrypted: bool,
+    ) -> Result<WorkerCid, String> {
+        let entries = entries
+            .iter()
+            .map(Self::to_dir_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+        let cid = self
+            .writer(encrypted)
+            .put_directory(entries)
+            .await
+            .map_err(|e| format!("Create dir error: {}", e))?;
+        Ok(Self::from_cid(&cid))
+    }
+
+    /// Add an already stored entry to directory `dir_path` of `parent_cid`,
+    /// replacing one with the same name. Returns the new root CID.
+    pub async fn set_entry(
+        &self,
+        parent_cid: &WorkerCid,
+        dir_path: &str,
+        entry: &WorkerDirEntry,
+    ) -> Result<WorkerCid, String> {
+        let parent_cid = Self::to_cid(parent_cid)?;
+        let entry = Self::to_dir_entry(entry)?;
+        let dir_path: Vec<&str> = dir_path.split('/').filter(|s| !s.is_empty()).collect();
+
+        let new_root = self
+            .writer(parent_cid.key.is_some())
+            .set_entry(
+                &parent_cid,
+                &dir_path,
+                &entry.name,
+                &Cid {
+                    hash: entry.hash,
+                    key: entry.key,
+                },
+                entry.size,
+                entry.link_type,
+            )
+            .await
+            .map_err(|e| format!("Set entry error: {}", e))?;
+
+        Ok(Self::from_cid(&new_root))
+    }
+
+    fn to_dir_entry(entry: &WorkerDirEntry) -> Result<DirEntry, String> {
+        let cid = Self::to_cid(&WorkerCid {
+            hash: entry.hash.clone(),
+            key: entry.key.clone(),
+        })?;
+        let link_type = LinkType::from_u8(entry.link_type)
+            .ok_or_else(|| format!("Invalid link type: {}", entry.link_type))?;
+        Ok(DirEntry::from_cid(&entry.name, &cid)
+            .with_size(entry.size)
+            .with_link_type(link_type))
+    }
+
     /// Delete file from tree, returns new root CID
     pub async fn delete_file(
         &self,
@@ -241,7 +483,7 @@ impl TreeManager {
         let dir_path: Vec<&str> = path_parts[..path_parts.len() - 1].to_vec();
 
         let new_root = self
-            .tree
+            .writer(parent_cid.key.is_some())
             .remove_entry(&parent_cid, &dir_path, filename)
             .await
             .map_err(|e| format!("Delete error: {}", e))?;
@@ -271,10 +513,329 @@ impl TreeManager {
             .collect())
     }
 
-    /// Create an empty directory, returns CID
-    pub async fn create_empty_dir(&self) -> Result<WorkerCid, String> {
-        let cid = self
+    /// Statistics of the tree at `cid`, listing its `largest` biggest files.
+    /// Leaves are only fetched when their links don't record a size.
+    pub async fn tree_stats(
+        &self,
+        cid: &WorkerCid,
+        largest: usize,
+    ) -> Result<hashtree_core::TreeStats, String> {
+        let cid = Self::to_cid(cid)?;
+        hashtree_core::tree_stats(&self.tree, &cid, largest)
+            .await
+            .map_err(|e| format!("Stats error: {}", e))
+    }
+
+    /// Append `entries` to the log at `log_cid`, or to a new log, returns
+    /// the log's new CID and length. Encrypted if requested or the log is.
+    pub async fn log_append(
+        &self,
+        log_cid: Option<&WorkerCid>,
+        entries: &[Vec<u8>],
+        encrypted: bool,
+    ) -> Result<(WorkerCid, u64), String> {
+        let log_cid = log_cid.map(Self::to_cid).transpose()?;
+        let tree = self.writer(encrypted || log_cid.as_ref().is_some_and(|c| c.key.is_some()));
+        let log = Log::new(tree);
+        let cid = log
+            .append(log_cid.as_ref(), entries)
+            .await
+            .map_err(|e| format!("Log append error: {}", e))?;
+        let length = log
+            .len(&cid)
+            .await
+            .map_err(|e| format!("Log read error: {}", e))?;
+        Ok((Self::from_cid(&cid), length))
+    }
+
+    /// Entries `start..end` of the log at `cid` (to its end if `end` is
+    /// None) and the log's length. Only the segments holding them are fetched.
+    pub async fn log_read(
+        &self,
+        cid: &WorkerCid,
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<(Vec<Vec<u8>>, u64), String> {
+        let cid = Self::to_cid(cid)?;
+        let log = Log::new(&self.tree);
+        let length = log
+            .len(&cid)
+            .await
+            .map_err(|e| format!("Log read error: {}", e))?;
+        let entries = log
+            .read_range(&cid, start, end.unwrap_or(length))
+            .await
+            .map_err(|e| format!("Log read error: {}", e))?;
+        Ok((entries, length))
+    }
+
+    /// Capability link for `path` within `cid`: an nhash embedding the
+    /// subtree's hash and decryption key, and its `htree://` URL.
+    /// Anyone with the link can read the subtree and nothing above it.
+    pub async fn share_link(
+        &self,
+        cid: &WorkerCid,
+        path: Option<&str>,
+    ) -> Result<(String, String), String> {
+        let root = Self::to_cid(cid)?;
+        let path = path.unwrap_or("").trim_matches('/');
+        let target = if path.is_empty() {
+            root
+        } else {
+            self.tree
+                .resolve_path(&root, path)
+                .await
+                .map_err(|e| format!("Resolve error: {}", e))?
+                .ok_or_else(|| format!("Path not found: {}", path))?
+        };
+
+        let nhash = nhash_encode_full(&NHashData {
+            hash: target.hash,
+            path: Vec::new(),
+            decrypt_key: target.key,
+            ..Default::default()
+        })
+        .map_err(|e| format!("nhash encode error: {}", e))?;
+
+        let is_dir = self
             .tree
+            .is_dir(&target)
+            .await
+            .map_err(|e| format!("Read error: {}", e))?;
+        // Files keep their name so the viewer can pick the right mime type
+        let url = match path.rsplit('/').next() {
+            Some(name) if !is_dir && !name.is_empty() => {
+                format!("htree://{}/{}", nhash, encode_relative_url(name))
+            }
+            _ => format!("htree://{}", nhash),
+        };
+
+        Ok((nhash, url))
+    }
+
+    /// Re-encrypt an encrypted tree under fresh directory keys, reusing the
+    /// convergent keys of file content. Returns the new root CID.
+    pub async fn rotate_key(&self, cid: &WorkerCid) -> Result<WorkerCid, String> {
+        let cid = Self::to_cid(cid)?;
+        let rotated = self
+            .encrypted_tree
+            .rotate_keys(&cid)
+            .await
+            .map_err(|e| format!("Rotate error: {}", e))?;
+
+        Ok(Self::from_cid(&rotated))
+    }
+
+    /// Embed a manifest signed with `keys` over the root's entries, so
+    /// readers can check the root really is the owner's. Returns the new root.
+    pub async fn embed_manifest(
+        &self,
+        cid: &WorkerCid,
+        keys: &nostr_sdk::Keys,
+    ) -> Result<WorkerCid, String> {
+        let root = Self::to_cid(cid)?;
+        let entries = self
+            .tree
+            .list_directory(&root)
+            .await
+            .map_err(|e| format!("List error: {}", e))?;
+        let digest = manifest_digest(&entries)?;
+        let manifest = sign_manifest(keys, &digest)?;
+
+        self.write_file(Some(cid), MANIFEST_FILENAME, &manifest, false)
+            .await
+    }
+
+    /// Entries of the directory at `cid` by name
+    async fn entries(&self, cid: &Cid) -> Result<BTreeMap<String, TreeEntry>, String> {
+        let entries = self
+            .tree
+            .list_directory(cid)
+            .await
+            .map_err(|e| format!("List error: {}", e))?;
+        Ok(entries.into_iter().map(|e| (e.name.clone(), e)).collect())
+    }
+
+    /// Paths, hashes (hex) and sizes of the files of the tree at `cid`.
+    /// Only directory nodes are read, file sizes come from their links.
+    pub async fn files(&self, cid: &WorkerCid) -> Result<Vec<(String, String, u64)>, String> {
+        let mut files = Vec::new();
+        self.collect_files(Self::to_cid(cid)?, "", &mut files)
+            .await?;
+        Ok(files)
+    }
+
+    async fn collect_files(
+        &self,
+        dir: Cid,
+        path: &str,
+        files: &mut Vec<(String, String, u64)>,
+    ) -> Result<(), String> {
+        for (name, entry) in self.entries(&dir).await? {
+            let path = join_path(path, &name);
+            if is_dir(&entry) {
+                Box::pin(self.collect_files(entry_cid(&entry), &path, files)).await?;
+            } else {
+                files.push((path, hashtree_core::to_hex(&entry.hash), entry.size));
+            }
+        }
+        Ok(())
+    }
+
+    /// What changed from `base` (an empty tree if unknown) to `other`
+    pub async fn diff(
+        &self,
+        base: Option<&WorkerCid>,
+        other: &WorkerCid,
+    ) -> Result<Vec<TreeChange>, String> {
+        let base = base.map(Self::to_cid).transpose()?;
+        let mut changes = Vec::new();
+        self.diff_dirs(base, Self::to_cid(other)?, "", &mut changes)
+            .await?;
+        Ok(changes)
+    }
+
+    async fn diff_dirs(
+        &self,
+        base: Option<Cid>,
+        other: Cid,
+        path: &str,
+        changes: &mut Vec<TreeChange>,
+    ) -> Result<(), String> {
+        let base = match base {
+            Some(base) => self.entries(&base).await?,
+            None => BTreeMap::new(),
+        };
+        let other = self.entries(&other).await?;
+        let names: BTreeSet<&String> = base.keys().chain(other.keys()).collect();
+        for name in names {
+            let path = join_path(path, name);
+            let (kind, size) = match (base.get(name), other.get(name)) {
+                (Some(b), Some(o)) if b.hash == o.hash => continue,
+                (Some(b), Some(o)) if is_dir(b) && is_dir(o) => {
+                    Box::pin(self.diff_dirs(Some(entry_cid(b)), entry_cid(o), &path, changes))
+                        .await?;
+                    continue;
+                }
+                (_, Some(o)) if base.contains_key(name) => (ChangeKind::Modified, o.size),
+                (_, Some(o)) => (ChangeKind::Added, o.size),
+                (Some(b), None) => (ChangeKind::Removed, b.size),
+                (None, None) => continue,
+            };
+            changes.push(TreeChange { path, kind, size });
+        }
+        Ok(())
+    }
+
+    /// Three-way merge of `proposed` into `current`, both changed from
+    /// `base` (an empty tree if unknown). An entry changed on one side only
+    /// takes that side's version; a directory changed on both is merged in
+    /// turn. The root's manifest stays `current`'s, to be signed again.
+    pub async fn merge(
+        &self,
+        base: Option<&WorkerCid>,
+        current: &WorkerCid,
+        proposed: &WorkerCid,
+    ) -> Result<MergeOutcome, String> {
+        let base = base.map(Self::to_cid).transpose()?;
+        let current = Self::to_cid(current)?;
+        let proposed = Self::to_cid(proposed)?;
+        let encrypted = current.key.is_some();
+        let mut conflicts = Vec::new();
+        let (merged, _) = self
+            .merge_dirs(base, current, proposed, encrypted, "", &mut conflicts)
+            .await?;
+        if conflicts.is_empty() {
+            Ok(MergeOutcome::Merged(Self::from_cid(&merged)))
+        } else {
+            Ok(MergeOutcome::Conflicts(conflicts))
+        }
+    }
+
+    /// Merged directory and its size
+    async fn merge_dirs(
+        &self,
+        base: Option<Cid>,
+        current: Cid,
+        proposed: Cid,
+        encrypted: bool,
+        path: &str,
+        conflicts: &mut Vec<String>,
+    ) -> Result<(Cid, u64), String> {
+        let base = match base {
+            Some(base) => self.entries(&base).await?,
+            None => BTreeMap::new(),
+        };
+        let current = self.entries(&current).await?;
+        let proposed = self.entries(&proposed).await?;
+        let names: BTreeSet<&String> = base
+            .keys()
+            .chain(current.keys())
+            .chain(proposed.keys())
+            .collect();
+
+        let mut merged = Vec::new();
+        for name in names {
+            let (b, c, p) = (base.get(name), current.get(name), proposed.get(name));
+            let same = |x: Option<&TreeEntry>, y: Option<&TreeEntry>| {
+                x.map(|e| e.hash) == y.map(|e| e.hash)
+            };
+            let manifest = path.is_empty() && name == MANIFEST_FILENAME;
+            let entry = if manifest || same(c, p) || same(b, p) {
+                c.cloned()
+            } else if same(b, c) {
+                p.cloned()
+            } else {
+                match (c, p) {
+                    (Some(c), Some(p)) if is_dir(c) && is_dir(p) => {
+                        let b = b.filter(|b| is_dir(b)).map(entry_cid);
+                        let (cid, size) = Box::pin(self.merge_dirs(
+                            b,
+                            entry_cid(c),
+                            entry_cid(p),
+                            encrypted,
+                            &join_path(path, name),
+                            conflicts,
+                        ))
+                        .await?;
+                        Some(TreeEntry {
+                            hash: cid.hash,
+                            key: cid.key,
+                            size,
+                            ..c.clone()
+                        })
+                    }
+                    _ => {
+                        conflicts.push(join_path(path, name));
+                        c.cloned()
+                    }
+                }
+            };
+            if let Some(e) = entry {
+                merged.push(DirEntry {
+                    name: e.name,
+                    hash: e.hash,
+                    size: e.size,
+                    key: e.key,
+                    link_type: e.link_type,
+                    meta: e.meta,
+                });
+            }
+        }
+
+        let size = merged.iter().map(|e| e.size).sum();
+        let cid = self
+            .writer(encrypted)
+            .put_directory(merged)
+            .await
+            .map_err(|e| format!("Create dir error: {}", e))?;
+        Ok((cid, size))
+    }
+
+    /// Create an empty directory, returns CID (with key if encrypted)
+    pub async fn create_empty_dir(&self, encrypted: bool) -> Result<WorkerCid, String> {
+        let cid = self
+            .writer(encrypted)
             .put_directory(vec![])
             .await
             .map_err(|e| format!("Create dir error: {}", e))?;
@@ -283,6 +844,51 @@ impl TreeManager {
     }
 }
 
+/// Groups of files with the same hash among `files` (copy, hash, size),
+/// most savings first. Empty files aren't duplicates of anything.
+pub fn duplicate_groups(
+    files: impl IntoIterator<Item = (FileCopy, String, u64)>,
+) -> Vec<DuplicateGroup> {
+    let mut by_hash: BTreeMap<String, (u64, Vec<FileCopy>)> = BTreeMap::new();
+    for (copy, hash, size) in files {
+        if size > 0 {
+            let (_, copies) = by_hash.entry(hash).or_insert((size, Vec::new()));
+            copies.push(copy);
+        }
+    }
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .filter(|(_, (_, copies))| copies.len() > 1)
+        .map(|(hash, (size, copies))| DuplicateGroup {
+            savings: size * (copies.len() as u64 - 1),
+            hash,
+            size,
+            copies,
+        })
+        .collect();
+    groups.sort_by(|a, b| b.savings.cmp(&a.savings));
+    groups
+}
+
+fn is_dir(entry: &TreeEntry) -> bool {
+    entry.link_type == LinkType::Dir
+}
+
+fn entry_cid(entry: &TreeEntry) -> Cid {
+    Cid {
+        hash: entry.hash,
+        key: entry.key,
+    }
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -301,7 +907,7 @@ mod tests {
 
         // Write file without parent
         let data = b"Hello, World!";
-        let cid = manager.write_file(None, "test.txt", data).await.unwrap();
+        let cid = manager.write_file(None, "test.txt", data, false).await.unwrap();
 
         // Read it back
         let result = manager.read_file(&cid).await.unwrap();
@@ -312,7 +918,7 @@ mod tests {
     async fn test_create_empty_dir() {
         let (manager, _dir) = create_test_manager().await;
 
-        let dir_cid = manager.create_empty_dir().await.unwrap();
+        let dir_cid = manager.create_empty_dir(false).await.unwrap();
 
         // List should be empty
         let entries = manager.list_dir(&dir_cid).await.unwrap();
@@ -324,12 +930,12 @@ mod tests {
         let (manager, _dir) = create_test_manager().await;
 
         // Create empty dir
-        let dir_cid = manager.create_empty_dir().await.unwrap();
+        let dir_cid = manager.create_empty_dir(false).await.unwrap();
 
         // Write file to it
         let data = b"File content";
         let new_root = manager
-            .write_file(Some(&dir_cid), "test.txt", data)
+            .write_file(Some(&dir_cid), "test.txt", data, false)
             .await
             .unwrap();
 
@@ -345,9 +951,9 @@ mod tests {
         let (manager, _dir) = create_test_manager().await;
 
         // Create dir with file
-        let dir_cid = manager.create_empty_dir().await.unwrap();
+        let dir_cid = manager.create_empty_dir(false).await.unwrap();
         let with_file = manager
-            .write_file(Some(&dir_cid), "test.txt", b"content")
+            .write_file(Some(&dir_cid), "test.txt", b"content", false)
             .await
             .unwrap();
 
@@ -364,13 +970,361 @@ mod tests {
         let (manager, _dir) = create_test_manager().await;
 
         let data = b"test content";
-        let cid = manager.write_file(None, "file.txt", data).await.unwrap();
+        let cid = manager.write_file(None, "file.txt", data, false).await.unwrap();
 
         // The CID should be valid and readable
         let content = manager.read_file(&cid).await.unwrap();
         assert_eq!(content, data);
     }
 
+    #[tokio::test]
+    async fn test_encrypted_tree_writes() {
+        let (manager, _dir) = create_test_manager().await;
+
+        let dir_cid = manager.create_empty_dir(true).await.unwrap();
+        assert!(dir_cid.key.is_some());
+
+        // Writes into an encrypted parent stay encrypted without the flag
+        let root = manager
+            .write_file(Some(&dir_cid), "secret.txt", b"private data", false)
+            .await
+            .unwrap();
+        assert!(root.key.is_some());
+
+        let entries = manager.list_dir(&root).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].key.is_some());
+
+        let file_cid = WorkerCid {
+            hash: entries[0].hash.clone(),
+            key: entries[0].key.clone(),
+        };
+        assert_eq!(manager.read_file(&file_cid).await.unwrap(), b"private data");
+
+        // Stored blocks don't contain the plaintext
+        let raw = manager.get_blob(&file_cid.hash).await.unwrap();
+        assert!(!raw.windows(12).any(|w| w == b"private data"));
+
+        // Without the key the content can't be read
+        let keyless = WorkerCid {
+            hash: file_cid.hash.clone(),
+            key: None,
+        };
+        assert_ne!(
+            manager.read_file(&keyless).await.ok(),
+            Some(b"private data".to_vec())
+        );
+
+        let after_delete = manager.delete_file(&root, "secret.txt").await.unwrap();
+        assert!(after_delete.key.is_some());
+        assert!(manager.list_dir(&after_delete).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_share_link_embeds_subtree_key() {
+        let (manager, _dir) = create_test_manager().await;
+
+        let root = manager.create_empty_dir(true).await.unwrap();
+        let root = manager
+            .write_file(Some(&root), "my notes.txt", b"shared text", false)
+            .await
+            .unwrap();
+        let entry = manager.list_dir(&root).await.unwrap().remove(0);
+
+        let (nhash, url) = manager
+            .share_link(&root, Some("my notes.txt"))
+            .await
+            .unwrap();
+        assert_eq!(url, format!("htree://{}/my%20notes.txt", nhash));
+
+        let decoded = hashtree_core::nhash_decode(&nhash).unwrap();
+        assert_eq!(hashtree_core::to_hex(&decoded.hash), entry.hash);
+        assert_eq!(
+            decoded.decrypt_key.map(|k| hashtree_core::key_to_hex(&k)),
+            entry.key
+        );
+
+        let (root_nhash, root_url) = manager.share_link(&root, None).await.unwrap();
+        assert_eq!(root_url, format!("htree://{}", root_nhash));
+        assert!(manager.share_link(&root, Some("missing")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_key_keeps_content_readable() {
+        let (manager, _dir) = create_test_manager().await;
+
+        let root = manager.create_empty_dir(true).await.unwrap();
+        let root = manager
+            .write_file(Some(&root), "plan.txt", b"the plan", false)
+            .await
+            .unwrap();
+        let before = manager.list_dir(&root).await.unwrap().remove(0);
+
+        let rotated = manager.rotate_key(&root).await.unwrap();
+        assert_ne!(rotated.key, root.key);
+        let stale = WorkerCid {
+            hash: rotated.hash.clone(),
+            key: root.key.clone(),
+        };
+        assert!(manager.list_dir(&stale).await.is_err());
+
+        // File content keeps its convergent key
+        let after = manager.list_dir(&rotated).await.unwrap().remove(0);
+        assert_eq!((&after.hash, &after.key), (&before.hash, &before.key));
+        let plan = WorkerCid {
+            hash: after.hash,
+            key: after.key,
+        };
+        assert_eq!(manager.read_file(&plan).await.unwrap(), b"the plan");
+
+        let public = manager.create_empty_dir(false).await.unwrap();
+        assert!(manager.rotate_key(&public).await.is_err());
+    }
+
+    async fn write_all(
+        manager: &TreeManager,
+        root: &WorkerCid,
+        files: &[(&str, &str)],
+    ) -> WorkerCid {
+        let mut root = root.clone();
+        for (path, content) in files {
+            root = manager
+                .write_file(Some(&root), path, content.as_bytes(), false)
+                .await
+                .unwrap();
+        }
+        root
+    }
+
+    async fn read_path(manager: &TreeManager, root: &WorkerCid, path: &str) -> String {
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut cid = root.clone();
+        for segment in dir.split('/').filter(|s| !s.is_empty()) {
+            let entries = manager.list_dir(&cid).await.unwrap();
+            let entry = entries.iter().find(|e| e.name == segment).unwrap();
+            cid = WorkerCid {
+                hash: entry.hash.clone(),
+                key: entry.key.clone(),
+            };
+        }
+        let entries = manager.list_dir(&cid).await.unwrap();
+        let entry = entries.iter().find(|e| e.name == name).unwrap();
+        let data = manager
+            .read_file(&WorkerCid {
+                hash: entry.hash.clone(),
+                key: entry.key.clone(),
+            })
+            .await
+            .unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_merge_and_diff() {
+        let (manager, _dir) = create_test_manager().await;
+        let empty = manager.create_empty_dir(false).await.unwrap();
+        let docs = write_all(&manager, &empty, &[("n.txt", "n")]).await;
+        let base = write_all(&manager, &empty, &[("a.txt", "a"), ("b.txt", "b")]).await;
+        let base = manager
+            .set_entry(
+                &base,
+                "",
+                &WorkerDirEntry {
+                    name: "docs".to_string(),
+                    hash: docs.hash.clone(),
+                    size: 1,
+                    link_type: LinkType::Dir as u8,
+                    key: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let current = write_all(&manager, &base, &[("a.txt", "a2"), ("docs/m.txt", "m")]).await;
+        let proposed = write_all(
+            &manager,
+            &base,
+            &[("b.txt", "b2"), ("c.txt", "c"), ("docs/p.txt", "p")],
+        )
+        .await;
+
+        let changes = manager.diff(Some(&base), &proposed).await.unwrap();
+        let changes: Vec<(&str, ChangeKind)> = changes
+            .iter()
+            .map(|change| (change.path.as_str(), change.kind))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                ("b.txt", ChangeKind::Modified),
+                ("c.txt", ChangeKind::Added),
+                ("docs/p.txt", ChangeKind::Added),
+            ]
+        );
+
+        // Changes on both sides in different files are kept
+        let MergeOutcome::Merged(merged) = manager
+            .merge(Some(&base), &current, &proposed)
+            .await
+            .unwrap()
+        else {
+            panic!("Expected a merge");
+        };
+        for (path, content) in [
+            ("a.txt", "a2"),
+            ("b.txt", "b2"),
+            ("c.txt", "c"),
+            ("docs/m.txt", "m"),
+            ("docs/n.txt", "n"),
+            ("docs/p.txt", "p"),
+        ] {
+            assert_eq!(read_path(&manager, &merged, path).await, content);
+        }
+
+        // The same file changed on both sides is a conflict
+        let conflicting = write_all(&manager, &base, &[("a.txt", "a3")]).await;
+        assert_eq!(
+            manager
+                .merge(Some(&base), &current, &conflicting)
+                .await
+                .unwrap(),
+            MergeOutcome::Conflicts(vec!["a.txt".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embed_manifest_signs_root_entries() {
+        use hashtree_gateway::manifest::{verify_manifest, SignatureStatus};
+
+        let (manager, _dir) = create_test_manager().await;
+        let keys = nostr_sdk::Keys::generate();
+
+        let root = manager.create_empty_dir(true).await.unwrap();
+        let root = manager
+            .write_file(Some(&root), "index.html", b"<html></html>", false)
+            .await
+            .unwrap();
+        let signed = manager.embed_manifest(&root, &keys).await.unwrap();
+        assert!(signed.key.is_some());
+
+        let root_cid = TreeManager::to_cid(&signed).unwrap();
+        let entries = manager.tree.list_directory(&root_cid).await.unwrap();
+        let entry = entries
+            .iter()
+            .find(|e| e.name == MANIFEST_FILENAME)
+            .unwrap();
+        let manifest = manager
+            .read_file(&WorkerCid {
+                hash: hashtree_core::to_hex(&entry.hash),
+                key: entry.key.map(|k| hashtree_core::key_to_hex(&k)),
+            })
+            .await
+            .unwrap();
+        let digest = manifest_digest(&entries).unwrap();
+        assert_eq!(
+            verify_manifest(&manifest, &keys.public_key(), &digest),
+            SignatureStatus::Verified
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_groups() {
+        let (manager, _dir) = create_test_manager().await;
+        let mut root = manager.create_empty_dir(true).await.unwrap();
+        for (path, data) in [
+            ("a.txt", "same"),
+            ("docs/b.txt", "same"),
+            ("docs/deep/c.txt", "same"),
+            ("big.bin", "0123456789"),
+            ("copy.bin", "0123456789"),
+            ("unique.txt", "only once"),
+            ("empty1", ""),
+            ("empty2", ""),
+        ] {
+            root = manager
+                .write_file(Some(&root), path, data.as_bytes(), false)
+                .await
+                .unwrap();
+        }
+
+        let files = manager.files(&root).await.unwrap();
+        assert_eq!(files.len(), 8);
+        let groups = duplicate_groups(
+            files
+                .into_iter()
+                .map(|(path, hash, size)| (FileCopy { tree: None, path }, hash, size)),
+        );
+        let paths = |group: &DuplicateGroup| -> Vec<String> {
+            group.copies.iter().map(|c| c.path.clone()).collect()
+        };
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].savings, 10);
+        assert_eq!(paths(&groups[0]), vec!["big.bin", "copy.bin"]);
+        assert_eq!(groups[1].savings, 8);
+        assert_eq!(
+            paths(&groups[1]),
+            vec!["a.txt", "docs/b.txt", "docs/deep/c.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_packed_tree_walks_blocks_and_pack() {
+        let (manager, _dir) = create_test_manager().await;
+        manager.set_pack_threshold(1024);
+
+        let mut root = manager.create_empty_dir(false).await.unwrap();
+        for i in 0..4 {
+            let data = format!("small file {}", i);
+            root = manager
+                .write_file(Some(&root), &format!("{}.txt", i), data.as_bytes(), false)
+                .await
+                .unwrap();
+        }
+        let entries = manager.list_dir(&root).await.unwrap();
+        let blocks = manager.walk_blocks(&root).await.unwrap();
+        let walked: HashSet<String> = blocks
+            .iter()
+            .map(|b| hashtree_core::to_hex(&b.hash))
+            .collect();
+        assert!(entries.iter().all(|e| walked.contains(&e.hash)));
+        // The root, the files, and the one pack they were added to
+        assert_eq!(blocks.len(), 6);
+
+        let file = entries.iter().find(|e| e.name == "2.txt").unwrap();
+        let content = manager
+            .read_file(&WorkerCid {
+                hash: file.hash.clone(),
+                key: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(content, b"small file 2");
+    }
+
+    #[tokio::test]
+    async fn test_log_append_and_read() {
+        let (manager, _dir) = create_test_manager().await;
+
+        let first = vec![b"one".to_vec(), b"two".to_vec()];
+        let (log, length) = manager.log_append(None, &first, true).await.unwrap();
+        assert_eq!(length, 2);
+        assert!(log.key.is_some());
+
+        // Appends to an encrypted log stay encrypted
+        let (log, length) = manager
+            .log_append(Some(&log), &[b"three".to_vec()], false)
+            .await
+            .unwrap();
+        assert_eq!(length, 3);
+        assert!(log.key.is_some());
+
+        let (entries, length) = manager.log_read(&log, 1, None).await.unwrap();
+        assert_eq!(length, 3);
+        assert_eq!(entries, vec![b"two".to_vec(), b"three".to_vec()]);
+        let (entries, _) = manager.log_read(&log, 0, Some(1)).await.unwrap();
+        assert_eq!(entries, vec![b"one".to_vec()]);
+    }
+
     #[test]
     fn test_cid_conversion() {
         let worker_cid = WorkerCid {
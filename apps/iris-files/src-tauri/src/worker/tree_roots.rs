@@ -0,0 +1,765 @@
+//! Tree root events in the nostrdb cache
+//!
+//! A tree's root is published as a kind 30078 event labelled `l` =
+//! hashtree, with the tree name as its `d` tag (see
+//! `nostr::build_tree_root_event`). `tree_root` reads one back, recovering
+//! the key of our own private and link-visible trees; the cache lookups
+//! keep the newest root per tree name.
+//!
+//! Deleting a tree publishes a tombstone, a root event without a hash, for
+//! relays and clients that ignore NIP-09, then a kind 5 deletion of the
+//! tree's address. A tree whose newest event is a tombstone isn't listed.
+//!
+//! Like a git commit, a root event can carry a message and the hash of the
+//! root it replaces (`parent`); messages of private and link-visible trees
+//! are NIP-44 encrypted to the author. Relays keep only a tree's newest
+//! event, but nostrdb keeps the ones we've seen, so `tree_log` follows the
+//! parents back as far as the cache reaches.
+//!
+//! A root also lists the tree's collaborators, who may propose new roots
+//! (see `collab`), and names the collaborator whose proposal it merged as
+//! its `author`. The list of a private or link-visible tree is encrypted to
+//! the author, with a tag per collaborator encrypted to them so they know.
+
+use nostr_sdk::nips::nip44;
+use nostr_sdk::{Event, EventBuilder, EventId, Keys, Kind, PublicKey, Tag};
+use nostrdb::{Ndb, Transaction};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::nostr::decrypt_self_encrypted_key;
+use super::types::WorkerCid;
+use crate::htree::TreeVisibility;
+
+/// Kind of tree root events
+pub const KIND_TREE_ROOT: u16 = 30078;
+
+/// Events of an author scanned per lookup; kind 30078 is shared with other
+/// app data
+const MAX_EVENTS: i32 = 1000;
+
+/// Commits returned by a log lookup unless asked for fewer
+pub const DEFAULT_LOG_LIMIT: usize = 100;
+
+const TAG_MESSAGE: &str = "message";
+const TAG_SELF_ENCRYPTED_MESSAGE: &str = "selfEncryptedMessage";
+const TAG_PARENT: &str = "parent";
+const TAG_AUTHOR: &str = "author";
+const TAG_COLLABORATOR: &str = "collaborator";
+const TAG_SELF_ENCRYPTED_COLLABORATORS: &str = "selfEncryptedCollaborators";
+const TAG_ENCRYPTED_COLLABORATOR: &str = "encryptedCollaborator";
+
+/// A tree's root as published
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeRoot {
+    pub name: String,
+    #[serde(flatten)]
+    pub cid: WorkerCid,
+    pub visibility: TreeVisibility,
+    /// created_at of the root event
+    pub updated_at: u64,
+    pub event_id: String,
+    /// Someone else's private root, whose key only a share can unlock
+    pub locked: bool,
+    /// Commit message, if the author gave one and we can read it
+    pub message: Option<String>,
+    /// Hash of the root this one replaced
+    pub parent: Option<String>,
+    /// Hex pubkey of the collaborator who made the change, if not the owner
+    pub author: Option<String>,
+    /// Hex pubkeys allowed to propose new roots; of someone else's
+    /// encrypted tree, only ours if we are one
+    pub collaborators: Vec<String>,
+    /// Secret masking the key of our own link-visible trees
+    #[serde(skip)]
+    pub link_secret: Option<[u8; 32]>,
+    #[serde(skip)]
+    pub event: Event,
+}
+
+/// Read the tree root in `event`, None if it isn't one. The key of a
+/// private or link-visible root is only recovered with the author's `keys`.
+pub fn tree_root(event: &Event, keys: Option<&Keys>) -> Option<TreeRoot> {
+    if !is_labelled(event) {
+        return None;
+    }
+    let tag = |name: &str| tag_value(event, name);
+    let name = tag("d")?;
+    let hash = tag("hash").filter(|hash| !hash.is_empty())?;
+    let own = keys.filter(|keys| keys.public_key() == event.pubkey);
+
+    let (key, visibility, link_secret) = if let Some(ciphertext) = tag("selfEncryptedKey") {
+        let key = own.and_then(|keys| decrypt_self_encrypted_key(keys, &ciphertext));
+        (key, TreeVisibility::Private, None)
+    } else if let Some(masked) = tag("encryptedKey") {
+        let secret = own
+            .zip(tag("selfEncryptedLinkKey"))
+            .and_then(|(keys, ciphertext)| decrypt_self_encrypted_key(keys, &ciphertext))
+            .and_then(|secret| hashtree_core::key_from_hex(&secret).ok());
+        let key = secret
+            .zip(hashtree_core::key_from_hex(&masked).ok())
+            .map(|(secret, masked)| {
+                hashtree_core::to_hex(&hashtree_core::xor_keys(&masked, &secret))
+            });
+        (key, TreeVisibility::LinkVisible, secret)
+    } else {
+        let key = tag("key").filter(|key| !key.is_empty());
+        (key, TreeVisibility::Public, None)
+    };
+
+    let message = tag(TAG_MESSAGE).or_else(|| {
+        own.zip(tag(TAG_SELF_ENCRYPTED_MESSAGE))
+            .and_then(|(keys, ciphertext)| {
+                nip44::decrypt(keys.secret_key(), &keys.public_key(), ciphertext).ok()
+            })
+    });
+
+    Some(TreeRoot {
+        name,
+        locked: visibility == TreeVisibility::Private && key.is_none(),
+        message,
+        parent: tag(TAG_PARENT).filter(|parent| !parent.is_empty()),
+        author: tag(TAG_AUTHOR).filter(|author| !author.is_empty()),
+        collaborators: collaborators(event, keys),
+        cid: WorkerCid { hash, key },
+        visibility,
+        updated_at: event.created_at.as_u64(),
+        event_id: event.id.to_hex(),
+        link_secret,
+        event: event.clone(),
+    })
+}
+
+/// Collaborators listed by `event`, as far as the owner of `keys` may know
+fn collaborators(event: &Event, keys: Option<&Keys>) -> Vec<String> {
+    let Some(keys) = keys else {
+        return tag_values(event, TAG_COLLABORATOR);
+    };
+    if keys.public_key() == event.pubkey {
+        if let Some(ciphertext) = tag_value(event, TAG_SELF_ENCRYPTED_COLLABORATORS) {
+            return nip44::decrypt(keys.secret_key(), &keys.public_key(), ciphertext)
+                .ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default();
+        }
+        return tag_values(event, TAG_COLLABORATOR);
+    }
+    let own = keys.public_key().to_hex();
+    let listed = tag_values(event, TAG_ENCRYPTED_COLLABORATOR)
+        .iter()
+        .any(|ciphertext| {
+            nip44::decrypt(keys.secret_key(), &event.pubkey, ciphertext)
+                .is_ok_and(|plaintext| plaintext == own)
+        });
+    if listed {
+        vec![own]
+    } else {
+        tag_values(event, TAG_COLLABORATOR)
+    }
+}
+
+/// Message, parent root and collaborators published with a new root
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Commit {
+    pub message: Option<String>,
+    /// Hex hash of the root being replaced
+    pub parent: Option<String>,
+    /// Hex pubkey of the collaborator whose proposal this is
+    pub author: Option<String>,
+    pub collaborators: Vec<String>,
+}
+
+impl Commit {
+    /// Commit of `cid` replacing the tree's `previous` root, keeping its
+    /// collaborators. Publishing the same root again keeps its parent and
+    /// author, and its message unless given one.
+    pub fn after(previous: Option<&TreeRoot>, cid: &WorkerCid, message: Option<String>) -> Self {
+        let collaborators = previous
+            .map(|root| root.collaborators.clone())
+            .unwrap_or_default();
+        match previous {
+            Some(previous) if previous.cid.hash == cid.hash => Commit {
+                message: message.or_else(|| previous.message.clone()),
+                parent: previous.parent.clone(),
+                author: previous.author.clone(),
+                collaborators,
+            },
+            previous => Commit {
+                message,
+                parent: previous.map(|root| root.cid.hash.clone()),
+                author: None,
+                collaborators,
+            },
+        }
+    }
+
+    /// Tags of the commit on a root event of a `visibility` tree
+    pub fn tags(&self, keys: &Keys, visibility: &TreeVisibility) -> Result<Vec<Tag>, String> {
+        let mut tags = Vec::new();
+        if let Some(message) = self.message.as_deref().filter(|m| !m.is_empty()) {
+            let tag = if *visibility == TreeVisibility::Public {
+                Tag::parse(&[TAG_MESSAGE, message])
+            } else {
+                let ciphertext = nip44::encrypt(
+                    keys.secret_key(),
+                    &keys.public_key(),
+                    message,
+                    nip44::Version::V2,
+                )
+                .map_err(|e| format!("Failed to encrypt commit message: {}", e))?;
+                Tag::parse(&[TAG_SELF_ENCRYPTED_MESSAGE, ciphertext.as_str()])
+            };
+            tags.push(tag.map_err(|e| e.to_string())?);
+        }
+        if let Some(parent) = &self.parent {
+            tags.push(Tag::parse(&[TAG_PARENT, parent.as_str()]).map_err(|e| e.to_string())?);
+        }
+        if let Some(author) = &self.author {
+            tags.push(Tag::parse(&[TAG_AUTHOR, author.as_str()]).map_err(|e| e.to_string())?);
+        }
+        if *visibility == TreeVisibility::Public {
+            for collaborator in &self.collaborators {
+                tags.push(
+                    Tag::parse(&[TAG_COLLABORATOR, collaborator.as_str()])
+                        .map_err(|e| e.to_string())?,
+                );
+            }
+        } else if !self.collaborators.is_empty() {
+            let encrypt = |pubkey: &PublicKey, plaintext: &str| {
+                nip44::encrypt(keys.secret_key(), pubkey, plaintext, nip44::Version::V2)
+                    .map_err(|e| format!("Failed to encrypt collaborators: {}", e))
+            };
+            let list = serde_json::to_string(&self.collaborators).map_err(|e| e.to_string())?;
+            let ciphertext = encrypt(&keys.public_key(), &list)?;
+            tags.push(
+                Tag::parse(&[TAG_SELF_ENCRYPTED_COLLABORATORS, ciphertext.as_str()])
+                    .map_err(|e| e.to_string())?,
+            );
+            for collaborator in &self.collaborators {
+                let pubkey = PublicKey::from_hex(collaborator)
+                    .map_err(|e| format!("Invalid collaborator: {}", e))?;
+                let ciphertext = encrypt(&pubkey, collaborator)?;
+                tags.push(
+                    Tag::parse(&[TAG_ENCRYPTED_COLLABORATOR, ciphertext.as_str()])
+                        .map_err(|e| e.to_string())?,
+                );
+            }
+        }
+        Ok(tags)
+    }
+}
+
+/// Name of the tree `event` is a tombstone of, if it is one
+fn tombstone_name(event: &Event) -> Option<String> {
+    if !is_labelled(event) || tag_value(event, "hash").is_some_and(|hash| !hash.is_empty()) {
+        return None;
+    }
+    tag_value(event, "d")
+}
+
+/// Whether `event` is of the tree root kind and labelled as hashtree's
+fn is_labelled(event: &Event) -> bool {
+    event.kind.as_u16() == KIND_TREE_ROOT
+        && event.tags.iter().any(|tag| {
+            let values = tag.as_slice();
+            values.len() >= 2 && values[0] == "l" && values[1] == "hashtree"
+        })
+}
+
+fn tag_value(event: &Event, name: &str) -> Option<String> {
+    event.tags.iter().find_map(|tag| {
+        let values = tag.as_slice();
+        (values.len() >= 2 && values[0] == name).then(|| values[1].clone())
+    })
+}
+
+fn tag_values(event: &Event, name: &str) -> Vec<String> {
+    event
+        .tags
+        .iter()
+        .filter_map(|tag| {
+            let values = tag.as_slice();
+            (values.len() >= 2 && values[0] == name && !values[1].is_empty())
+                .then(|| values[1].clone())
+        })
+        .collect()
+}
+
+/// Tombstone of our tree `tree_name`: a root event without a hash
+pub fn tombstone_event(keys: &Keys, tree_name: &str) -> Result<Event, String> {
+    let tags = vec![
+        Tag::identifier(tree_name),
+        Tag::parse(&["l", "hashtree"]).map_err(|e| e.to_string())?,
+    ];
+    EventBuilder::new(Kind::from(KIND_TREE_ROOT), "", tags)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+/// NIP-09 deletion of every root event of our tree `tree_name` up to now,
+/// naming `root_event` too for relays that only delete by id
+pub fn deletion_event(
+    keys: &Keys,
+    tree_name: &str,
+    root_event: Option<EventId>,
+) -> Result<Event, String> {
+    let address = format!(
+        "{}:{}:{}",
+        KIND_TREE_ROOT,
+        keys.public_key().to_hex(),
+        tree_name
+    );
+    let mut tags = vec![
+        Tag::parse(&["a".to_string(), address]).map_err(|e| e.to_string())?,
+        Tag::parse(&["k".to_string(), KIND_TREE_ROOT.to_string()]).map_err(|e| e.to_string())?,
+    ];
+    if let Some(id) = root_event {
+        tags.push(Tag::event(id));
+    }
+    EventBuilder::new(Kind::EventDeletion, "Tree deleted", tags)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign event: {}", e))
+}
+
+/// Cached root events of `author`'s trees
+fn cached_root_events(ndb: &Ndb, author: &[u8; 32]) -> Result<Vec<Event>, String> {
+    let txn = Transaction::new(ndb).map_err(|e| format!("Transaction error: {:?}", e))?;
+    let filter = nostrdb::Filter::new()
+        .kinds(vec![KIND_TREE_ROOT as u64])
+        .authors(vec![author])
+        .build();
+    let results = ndb
+        .query(&txn, &[filter], MAX_EVENTS)
+        .map_err(|e| format!("Failed to query nostrdb: {:?}", e))?;
+
+    Ok(results
+        .iter()
+        .filter_map(|result| result.note.json().ok())
+        .filter_map(|json| serde_json::from_str(&json).ok())
+        .collect())
+}
+
+/// Newest cached root of each of `author`'s trees, by name
+pub fn cached_tree_roots(
+    ndb: &Ndb,
+    author: &[u8; 32],
+    keys: Option<&Keys>,
+) -> Result<Vec<TreeRoot>, String> {
+    Ok(newest_roots(&cached_root_events(ndb, author)?, keys))
+}
+
+/// The tree roots among `events`, the newest of each tree by name. Trees
+/// whose newest event is a tombstone are left out.
+pub fn newest_roots(events: &[Event], keys: Option<&Keys>) -> Vec<TreeRoot> {
+    let mut latest: BTreeMap<String, (u64, Option<TreeRoot>)> = BTreeMap::new();
+    for event in events {
+        let (name, root) = match tree_root(event, keys) {
+            Some(root) => (root.name.clone(), Some(root)),
+            None => match tombstone_name(event) {
+                Some(name) => (name, None),
+                None => continue,
+            },
+        };
+        let created_at = event.created_at.as_u64();
+        let older = latest
+            .get(&name)
+            .is_some_and(|(latest_at, _)| *latest_at >= created_at);
+        if !older {
+            latest.insert(name, (created_at, root));
+        }
+    }
+    latest.into_values().filter_map(|(_, root)| root).collect()
+}
+
+/// History of the tree `tree_name` among `events`, newest first: its
+/// newest root, then each root's parent for as long as an event of it is
+/// found, up to `limit` commits. A deleted tree has none.
+pub fn tree_log(
+    events: &[Event],
+    tree_name: &str,
+    keys: Option<&Keys>,
+    limit: usize,
+) -> Vec<TreeRoot> {
+    let Some(head) = newest_roots(events, keys)
+        .into_iter()
+        .find(|root| root.name == tree_name)
+    else {
+        return Vec::new();
+    };
+
+    // Newest event of each root, should one have been published twice
+    let mut by_hash: HashMap<String, TreeRoot> = HashMap::new();
+    let roots = events
+        .iter()
+        .filter_map(|event| tree_root(event, keys))
+        .filter(|root| root.name == tree_name);
+    for root in roots {
+        let older = by_hash
+            .get(&root.cid.hash)
+            .is_some_and(|seen| seen.updated_at >= root.updated_at);
+        if !older {
+            by_hash.insert(root.cid.hash.clone(), root);
+        }
+    }
+
+    let mut log = Vec::new();
+    let mut seen = HashSet::new();
+    let mut next = Some(head.cid.hash);
+    while let Some(hash) = next {
+        if log.len() >= limit || !seen.insert(hash.clone()) {
+            break;
+        }
+        let Some(root) = by_hash.remove(&hash) else {
+            break;
+        };
+        next = root.parent.clone();
+        log.push(root);
+    }
+    log
+}
+
+/// Cached history of `author`'s tree `tree_name` (see `tree_log`)
+pub fn cached_tree_log(
+    ndb: &Ndb,
+    author: &[u8; 32],
+    tree_name: &str,
+    keys: Option<&Keys>,
+    limit: usize,
+) -> Result<Vec<TreeRoot>, String> {
+    Ok(tree_log(
+        &cached_root_events(ndb, author)?,
+        tree_name,
+        keys,
+        limit,
+    ))
+}
+
+/// Newest cached root of `author`'s tree `tree_name`
+pub fn cached_tree_root(
+    ndb: &Ndb,
+    author: &[u8; 32],
+    tree_name: &str,
+    keys: Option<&Keys>,
+) -> Option<TreeRoot> {
+    cached_tree_roots(ndb, author, keys)
+        .ok()?
+        .into_iter()
+        .find(|root| root.name == tree_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::nostr::{build_tree_commit_event, build_tree_root_event};
+    use nostr_sdk::{EventBuilder, Kind, Tag, Timestamp};
+
+    fn cid() -> WorkerCid {
+        WorkerCid {
+            hash: "ab".repeat(32),
+            key: Some("cd".repeat(32)),
+        }
+    }
+
+    #[test]
+    fn test_tree_root_by_visibility() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+        let secret = [7u8; 32];
+
+        let public =
+            build_tree_root_event(&keys, "music", &cid(), &TreeVisibility::Public, None).unwrap();
+        let root = tree_root(&public, None).unwrap();
+        assert_eq!(root.name, "music");
+        assert_eq!(root.cid.key, cid().key);
+        assert_eq!(root.visibility, TreeVisibility::Public);
+        assert_eq!(root.updated_at, public.created_at.as_u64());
+        assert!(!root.locked);
+
+        let private =
+            build_tree_root_event(&keys, "docs", &cid(), &TreeVisibility::Private, None).unwrap();
+        assert_eq!(tree_root(&private, Some(&keys)).unwrap().cid.key, cid().key);
+        let locked = tree_root(&private, Some(&other)).unwrap();
+        assert_eq!(locked.cid.key, None);
+        assert!(locked.locked);
+
+        let link = build_tree_root_event(
+            &keys,
+            "photos",
+            &cid(),
+            &TreeVisibility::LinkVisible,
+            Some(&secret),
+        )
+        .unwrap();
+        let own = tree_root(&link, Some(&keys)).unwrap();
+        assert_eq!(own.cid.key, cid().key);
+        assert_eq!(own.link_secret, Some(secret));
+        let theirs = tree_root(&link, None).unwrap();
+        assert_eq!(theirs.visibility, TreeVisibility::LinkVisible);
+        assert_eq!(theirs.cid.key, None);
+        assert!(!theirs.locked);
+    }
+
+    #[test]
+    fn test_other_app_data_is_not_a_tree_root() {
+        let keys = Keys::generate();
+        let tags = vec![Tag::parse(&["d", "settings"]).unwrap()];
+        let event = EventBuilder::new(Kind::from(KIND_TREE_ROOT), "{}", tags)
+            .to_event(&keys)
+            .unwrap();
+        assert!(tree_root(&event, Some(&keys)).is_none());
+        assert!(tombstone_name(&event).is_none());
+    }
+
+    #[test]
+    fn test_newest_root_per_tree() {
+        let keys = Keys::generate();
+        let root = |name: &str, created_at: u64| {
+            let event =
+                build_tree_root_event(&keys, name, &cid(), &TreeVisibility::Public, None).unwrap();
+            EventBuilder::new(event.kind, event.content, event.tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let tombstone = |name: &str, created_at: u64| {
+            let event = tombstone_event(&keys, name).unwrap();
+            EventBuilder::new(event.kind, event.content, event.tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let events = vec![
+            root("music", 10),
+            root("docs", 5),
+            root("music", 20),
+            root("old", 5),
+            tombstone("old", 8),
+            tombstone("docs", 1),
+        ];
+        let roots = newest_roots(&events, None);
+        let listed: Vec<(&str, u64)> = roots
+            .iter()
+            .map(|root| (root.name.as_str(), root.updated_at))
+            .collect();
+        assert_eq!(listed, [("docs", 5), ("music", 20)]);
+    }
+
+    #[test]
+    fn test_commit_message_and_parent() {
+        let keys = Keys::generate();
+        let other = Keys::generate();
+        let commit = Commit {
+            message: Some("Added vacation photos".to_string()),
+            parent: Some("ef".repeat(32)),
+            ..Default::default()
+        };
+
+        let public = build_tree_commit_event(
+            &keys,
+            "photos",
+            &cid(),
+            &TreeVisibility::Public,
+            None,
+            &commit,
+        )
+        .unwrap();
+        let root = tree_root(&public, None).unwrap();
+        assert_eq!(root.message, commit.message);
+        assert_eq!(root.parent, commit.parent);
+
+        // Only the author reads the message of a private tree
+        let private = build_tree_commit_event(
+            &keys,
+            "docs",
+            &cid(),
+            &TreeVisibility::Private,
+            None,
+            &commit,
+        )
+        .unwrap();
+        assert!(!private
+            .tags
+            .iter()
+            .any(|tag| tag.as_slice()[0] == TAG_MESSAGE));
+        assert_eq!(
+            tree_root(&private, Some(&keys)).unwrap().message,
+            commit.message
+        );
+        let theirs = tree_root(&private, Some(&other)).unwrap();
+        assert_eq!(theirs.message, None);
+        assert_eq!(theirs.parent, commit.parent);
+
+        let plain =
+            build_tree_root_event(&keys, "music", &cid(), &TreeVisibility::Public, None).unwrap();
+        let root = tree_root(&plain, None).unwrap();
+        assert_eq!((root.message, root.parent), (None, None));
+    }
+
+    #[test]
+    fn test_commit_after_previous_root() {
+        let keys = Keys::generate();
+        let event = build_tree_commit_event(
+            &keys,
+            "docs",
+            &cid(),
+            &TreeVisibility::Public,
+            None,
+            &Commit {
+                message: Some("First".to_string()),
+                parent: Some("01".repeat(32)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let previous = tree_root(&event, None).unwrap();
+        let next = WorkerCid {
+            hash: "02".repeat(32),
+            key: None,
+        };
+
+        assert_eq!(
+            Commit::after(Some(&previous), &next, Some("Second".to_string())),
+            Commit {
+                message: Some("Second".to_string()),
+                parent: Some(cid().hash),
+                ..Default::default()
+            }
+        );
+        // The same root published again keeps its place in the log
+        assert_eq!(
+            Commit::after(Some(&previous), &cid(), None),
+            Commit {
+                message: Some("First".to_string()),
+                parent: Some("01".repeat(32)),
+                ..Default::default()
+            }
+        );
+        assert_eq!(Commit::after(None, &next, None), Commit::default());
+    }
+
+    #[test]
+    fn test_tree_log_follows_parents() {
+        let keys = Keys::generate();
+        let hash = |n: u8| format!("{:02x}", n).repeat(32);
+        let commit = |name: &str, n: u8, parent: Option<u8>, created_at: u64| {
+            let root = WorkerCid {
+                hash: hash(n),
+                key: None,
+            };
+            let commit = Commit {
+                message: Some(format!("commit {}", n)),
+                parent: parent.map(hash),
+                ..Default::default()
+            };
+            let event =
+                build_tree_commit_event(&keys, name, &root, &TreeVisibility::Public, None, &commit)
+                    .unwrap();
+            EventBuilder::new(event.kind, event.content, event.tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let events = vec![
+            commit("docs", 1, Some(9), 10),
+            commit("docs", 3, Some(2), 30),
+            commit("docs", 2, Some(1), 20),
+            commit("music", 4, Some(3), 40),
+            commit("docs", 5, Some(3), 50),
+        ];
+        let messages = |log: Vec<TreeRoot>| -> Vec<String> {
+            log.into_iter().filter_map(|root| root.message).collect()
+        };
+
+        // Root 9 isn't cached, so the log ends at its child
+        assert_eq!(
+            messages(tree_log(&events, "docs", None, DEFAULT_LOG_LIMIT)),
+            ["commit 5", "commit 3", "commit 2", "commit 1"]
+        );
+        assert_eq!(
+            messages(tree_log(&events, "docs", None, 2)),
+            ["commit 5", "commit 3"]
+        );
+        assert_eq!(messages(tree_log(&events, "music", None, 10)), ["commit 4"]);
+        assert!(tree_log(&events, "photos", None, 10).is_empty());
+
+        // A parent loop ends the log instead of repeating it
+        let looped = vec![
+            commit("loop", 1, Some(2), 10),
+            commit("loop", 2, Some(1), 20),
+        ];
+        assert_eq!(
+            messages(tree_log(&looped, "loop", None, 10)),
+            ["commit 2", "commit 1"]
+        );
+    }
+
+    #[test]
+    fn test_commit_collaborators_and_author() {
+        let keys = Keys::generate();
+        let collaborators = vec!["01".repeat(32), "02".repeat(32)];
+        let event = build_tree_commit_event(
+            &keys,
+            "docs",
+            &cid(),
+            &TreeVisibility::Public,
+            None,
+            &Commit {
+                author: Some("01".repeat(32)),
+                collaborators: collaborators.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let root = tree_root(&event, None).unwrap();
+        assert_eq!(root.author, Some("01".repeat(32)));
+        assert_eq!(root.collaborators, collaborators);
+
+        // The owner's next root keeps the collaborators, not the author
+        let next = WorkerCid {
+            hash: "03".repeat(32),
+            key: None,
+        };
+        let commit = Commit::after(Some(&root), &next, None);
+        assert_eq!(commit.author, None);
+        assert_eq!(commit.collaborators, collaborators);
+        assert_eq!(
+            Commit::after(Some(&root), &cid(), None).author,
+            Some("01".repeat(32))
+        );
+    }
+
+    #[test]
+    fn test_collaborators_of_private_trees_are_encrypted() {
+        let (keys, collaborator, other) = (Keys::generate(), Keys::generate(), Keys::generate());
+        let collaborators = vec![
+            collaborator.public_key().to_hex(),
+            Keys::generate().public_key().to_hex(),
+        ];
+        let event = build_tree_commit_event(
+            &keys,
+            "docs",
+            &cid(),
+            &TreeVisibility::Private,
+            None,
+            &Commit {
+                collaborators: collaborators.clone(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(!event
+            .tags
+            .iter()
+            .any(|tag| tag.as_slice()[0] == TAG_COLLABORATOR));
+
+        let read = |keys: Option<&Keys>| tree_root(&event, keys).unwrap().collaborators;
+        assert_eq!(read(Some(&keys)), collaborators);
+        assert_eq!(
+            read(Some(&collaborator)),
+            [collaborator.public_key().to_hex()]
+        );
+        assert!(read(Some(&other)).is_empty());
+        assert!(read(None).is_empty());
+    }
+}
@@ -1,5 +1,22 @@
 use serde::{Deserialize, Serialize};
 
+use super::accounts::AccountInfo;
+use super::activity::ActivityEntry;
+use super::collab::Proposal;
+use super::comments::CommentParent;
+use super::inbox::SharedItem;
+use super::media::{MediaKind, MediaMetadata};
+use super::ndb_maintenance::{KindCount, PurgeRule};
+use super::notify::NotifyRules;
+use super::outbox::OutboxEntry;
+use super::pow::PowPolicy;
+use super::scheduler::{JobInfo, Priority};
+use super::sync::SyncStatus;
+use super::tree_roots::TreeRoot;
+use super::wot::WotPolicy;
+use crate::htree::TreeVisibility;
+use crate::proxy::ProxyConfig;
+
 /// CID (Content Identifier) - hash + optional encryption key
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkerCid {
@@ -18,6 +35,55 @@ pub struct WorkerDirEntry {
     pub key: Option<String>,
 }
 
+/// How a path differs between two versions of a tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A file or directory that differs between two versions of a tree; a
+/// directory added or removed as a whole is one change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeChange {
+    pub path: String,
+    pub kind: ChangeKind,
+    /// Size after the change, before it for removals
+    pub size: u64,
+}
+
+/// Where a file was found by `findDuplicates`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileCopy {
+    /// Name of our tree holding it, when searching all of them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tree: Option<String>,
+    pub path: String,
+}
+
+/// Files of identical content, which share their blocks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub size: u64,
+    pub copies: Vec<FileCopy>,
+    /// Bytes the trees would shrink by keeping a single copy. Stored
+    /// bytes don't change, the content is stored once either way.
+    pub savings: u64,
+}
+
+/// One blob of a `putMany` request
+#[derive(Debug, Clone, Deserialize)]
+pub struct PutItem {
+    pub hash: String,
+    pub data: String, // base64
+}
+
 /// Worker request messages from frontend
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
@@ -31,6 +97,9 @@ pub enum WorkerRequest {
     Put { id: String, hash: String, data: String },
     Has { id: String, hash: String },
     Delete { id: String, hash: String },
+    GetMany { id: String, hashes: Vec<String> },
+    PutMany { id: String, items: Vec<PutItem> },
+    HasMany { id: String, hashes: Vec<String> },
 
     // Tree operations
     ReadFile { id: String, cid: WorkerCid },
@@ -46,6 +115,14 @@ pub enum WorkerRequest {
         parent_cid: Option<WorkerCid>,
         path: String,
         data: String, // base64
+        /// CHK-encrypt the content (implied when the parent has a key)
+        #[serde(default)]
+        encrypted: bool,
+    },
+    CreateDir {
+        id: String,
+        #[serde(default)]
+        encrypted: bool,
     },
     DeleteFile {
         id: String,
@@ -54,16 +131,63 @@ pub enum WorkerRequest {
         path: String,
     },
     ListDir { id: String, cid: WorkerCid },
+    /// Size, block and file counts of a tree, its largest files and a
+    /// breakdown per directory
+    TreeStats {
+        id: String,
+        cid: WorkerCid,
+        /// Largest files to list, 10 by default
+        largest: Option<usize>,
+    },
+    /// Files of identical content within the tree at `root`, or across all
+    /// of our trees without one
+    FindDuplicates {
+        id: String,
+        root: Option<WorkerCid>,
+    },
+    /// Append entries to an append-only log (see `hashtree_core::log`),
+    /// starting a new one if `cid` is None
+    AppendLog {
+        id: String,
+        cid: Option<WorkerCid>,
+        entries: Vec<String>, // base64
+        /// CHK-encrypt a new log (implied when the log has a key)
+        #[serde(default)]
+        encrypted: bool,
+    },
+    /// Entries `start..end` of a log, to its end if `end` is missing
+    ReadLog {
+        id: String,
+        cid: WorkerCid,
+        start: u64,
+        end: Option<u64>,
+    },
     ResolveRoot {
         id: String,
         npub: String,
         path: Option<String>,
     },
+    /// All of a user's trees (npub or hex), newest root of each
+    ListTrees {
+        id: String,
+        pubkey: String,
+    },
+    /// A tree's roots with their commit messages, newest first, following
+    /// each root's parent
+    GetTreeLog {
+        id: String,
+        pubkey: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        limit: Option<usize>,
+    },
 
     // Nostr operations (Phase 3)
     Subscribe {
         id: String,
         filters: Vec<serde_json::Value>,
+        /// Send only to these relays, e.g. an author's NIP-65 write relays
+        relays: Option<Vec<String>>,
     },
     Unsubscribe {
         id: String,
@@ -71,6 +195,27 @@ pub enum WorkerRequest {
         sub_id: String,
     },
     Publish { id: String, event: serde_json::Value },
+    /// Stream comments on a tree path as `event`s of subscription `id`
+    SubscribeComments {
+        id: String,
+        npub: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        #[serde(default)]
+        path: String,
+    },
+    /// Publish a comment on a tree path, or a reply to one of its comments
+    PostComment {
+        id: String,
+        npub: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        #[serde(default)]
+        path: String,
+        content: String,
+        #[serde(rename = "replyTo")]
+        reply_to: Option<CommentParent>,
+    },
 
     // Identity
     SetIdentity {
@@ -78,6 +223,20 @@ pub enum WorkerRequest {
         pubkey: String,
         nsec: Option<String>,
     },
+    /// Identities remembered from setIdentity
+    ListAccounts {
+        id: String,
+    },
+    /// Make a remembered identity the active one
+    SwitchAccount {
+        id: String,
+        pubkey: String,
+    },
+    /// Turn the ephemeral guest session on or off
+    SetGuestMode {
+        id: String,
+        enabled: bool,
+    },
 
     // Relay management
     SetRelays {
@@ -98,6 +257,26 @@ pub enum WorkerRequest {
         id: String,
         pubkey: String,
     },
+    /// Tree updates from follows, newest first, older than `before` (seconds)
+    GetActivityFeed {
+        id: String,
+        before: Option<u64>,
+        limit: Option<usize>,
+    },
+    /// Profile metadata (kind 0) of `pubkey` (hex or npub)
+    GetProfile {
+        id: String,
+        pubkey: String,
+    },
+    /// Follow `pubkey` (hex or npub) by publishing our edited contact list
+    Follow {
+        id: String,
+        pubkey: String,
+    },
+    Unfollow {
+        id: String,
+        pubkey: String,
+    },
     GetFollowers {
         id: String,
         pubkey: String,
@@ -112,6 +291,26 @@ pub enum WorkerRequest {
         max_distance: usize,
     },
 
+    // NIP-51 mute list
+    GetMutes {
+        id: String,
+    },
+    MutePubkey {
+        id: String,
+        pubkey: String,
+    },
+    UnmutePubkey {
+        id: String,
+        pubkey: String,
+    },
+    /// Take over our mute list from the relays if it's newer
+    FetchMutes {
+        id: String,
+    },
+    PublishMutes {
+        id: String,
+    },
+
     // Blossom operations (Phase 6)
     BlossomUpload {
         id: String,
@@ -140,15 +339,80 @@ pub enum WorkerRequest {
         #[serde(rename = "maxBytes")]
         max_bytes: u64,
     },
+    /// Write blocks fetched from Blossom for files over `large_file_bytes`
+    /// that aren't kept to disk only when read a second time (disabled =
+    /// write all)
+    SetCacheAdmission {
+        id: String,
+        enabled: bool,
+        #[serde(rename = "largeFileBytes")]
+        large_file_bytes: u64,
+    },
+    /// Pack blob entries of up to `threshold` bytes into shared blocks when
+    /// writing directories, 0 to stop
+    SetPacking {
+        id: String,
+        threshold: u64,
+    },
     RunEviction {
         id: String,
     },
+    /// Re-hash stored blobs, quarantine corrupted ones and re-fetch them
+    RunScrub {
+        id: String,
+    },
+    /// Cap our own trees and other people's cached trees separately (0 = unlimited)
+    SetQuota {
+        id: String,
+        #[serde(rename = "ownBytes")]
+        own_bytes: u64,
+        #[serde(rename = "othersBytes")]
+        others_bytes: u64,
+    },
+    /// Size of the nostrdb event cache and its events by kind
+    GetNdbStats {
+        id: String,
+    },
+    /// Stage a purge of cached events; nostrdb is rewritten without them on
+    /// the next start. Without `older_than` and `kinds` it only compacts.
+    PurgeNdb {
+        id: String,
+        /// Unix seconds; replaceable events are kept unless `kinds` names them
+        #[serde(rename = "olderThan")]
+        older_than: Option<u64>,
+        kinds: Option<Vec<u32>>,
+    },
+    /// Write blobs, databases and settings to one archive file
+    ExportState {
+        id: String,
+        path: String,
+        /// Also export the identity key, encrypted with this passphrase
+        passphrase: Option<String>,
+    },
+    /// Stage an archive from ExportState; it's applied on the next start
+    ImportState {
+        id: String,
+        path: String,
+        /// Decrypts the identity key in the archive, if there is one
+        passphrase: Option<String>,
+    },
 
     // Relay statistics
     GetRelayStats {
         id: String,
     },
 
+    /// Published events and which relays accepted them
+    GetOutbox {
+        id: String,
+    },
+    /// Send the given outbox events again now, or all undelivered ones
+    RetryOutbox {
+        id: String,
+        #[serde(rename = "eventIds")]
+        event_ids: Option<Vec<String>>,
+    },
+
     // Blossom server configuration
     SetBlossomServers {
         id: String,
@@ -161,6 +425,16 @@ pub enum WorkerRequest {
         id: String,
     },
 
+    // Network proxy
+    /// Route relay, Blossom and other web traffic through a SOCKS5 proxy
+    SetProxy {
+        id: String,
+        proxy: ProxyConfig,
+    },
+    GetProxy {
+        id: String,
+    },
+
     // Tree push to Blossom
     PushToBlossom {
         id: String,
@@ -169,6 +443,110 @@ pub enum WorkerRequest {
         tree_name: Option<String>,
     },
 
+    // Sign and publish a tree root event with the worker's identity
+    PublishTree {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        cid: WorkerCid,
+        #[serde(default)]
+        visibility: TreeVisibility,
+        /// Hex secret masking the key of link-visible trees (generated if absent)
+        #[serde(rename = "linkSecret")]
+        link_secret: Option<String>,
+        /// Embed a manifest signed by our key before publishing
+        #[serde(default)]
+        sign: bool,
+        /// Commit message published with the root
+        message: Option<String>,
+        /// Hash of the root this one replaces, if not the tree's current root
+        parent: Option<String>,
+    },
+    /// Add shared files, links or text to the private inbox tree and publish it
+    AddToInbox {
+        id: String,
+        items: Vec<SharedItem>,
+    },
+
+    // Per-recipient sharing of private trees
+    GrantAccess {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        cid: WorkerCid,
+        recipient: String,
+    },
+    RevokeAccess {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        recipient: String,
+    },
+    ListGrants {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+    },
+    // Collaborative trees: collaborators propose roots, the owner merges them
+    /// Republish one of our trees listing who may propose changes to it
+    SetCollaborators {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        /// npubs or hex pubkeys
+        collaborators: Vec<String>,
+    },
+    /// Propose a new root for a tree we collaborate on; its blocks must be
+    /// on Blossom (pushToBlossom) for the owner to read them
+    ProposeRoot {
+        id: String,
+        /// Owner of the tree (npub or hex)
+        owner: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        cid: WorkerCid,
+        /// Hash of the root the change was made on, the tree's current root if absent
+        base: Option<String>,
+        message: Option<String>,
+    },
+    /// Open proposals of collaborators for our trees, only `treeName`'s if given
+    ListProposals {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: Option<String>,
+    },
+    /// What a proposal changes compared to the root it was made on
+    DiffProposal {
+        id: String,
+        #[serde(rename = "eventId")]
+        event_id: String,
+    },
+    /// Merge a proposal into its tree and publish the result
+    MergeProposal {
+        id: String,
+        #[serde(rename = "eventId")]
+        event_id: String,
+    },
+    /// Stop listing a proposal without merging it
+    DismissProposal {
+        id: String,
+        #[serde(rename = "eventId")]
+        event_id: String,
+    },
+    // Re-encrypt one of our trees under a new key after a leak
+    RotateTreeKey {
+        id: String,
+        npub: String,
+        tree: String,
+    },
+
+    // Capability URL (nhash with embedded key) for a subtree
+    CreateShareLink {
+        id: String,
+        cid: WorkerCid,
+        path: Option<String>,
+    },
+
     // Republish tree event to Nostr
     RepublishTree {
         id: String,
@@ -184,11 +562,93 @@ pub enum WorkerRequest {
         pubkey_prefix: Option<String>,
     },
 
+    /// Unpublish one of our trees and stop keeping its blocks
+    DeleteTree {
+        id: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        /// Also delete its blocks from our Blossom write servers
+        #[serde(rename = "deleteBlobs", default)]
+        delete_blobs: bool,
+    },
+
     // Streaming file read
     ReadFileStream {
         id: String,
         cid: WorkerCid,
     },
+    /// Save a file, or a directory recursively, to a local path
+    DownloadToDisk {
+        id: String,
+        cid: WorkerCid,
+        #[serde(rename = "destPath")]
+        dest_path: String,
+    },
+    /// Hold a running DownloadToDisk between chunks
+    PauseDownload {
+        id: String,
+        #[serde(rename = "downloadId")]
+        download_id: String,
+    },
+    ResumeDownload {
+        id: String,
+        #[serde(rename = "downloadId")]
+        download_id: String,
+    },
+
+    // Background sync
+    GetSyncStatus {
+        id: String,
+    },
+    /// Disconnect WebRTC peers and hold pushes, or resume
+    SetSyncPaused {
+        id: String,
+        paused: bool,
+    },
+
+    // Notification rules
+    GetNotifyRules {
+        id: String,
+    },
+    SetNotifyRules {
+        id: String,
+        rules: NotifyRules,
+    },
+
+    // Web-of-trust policy
+    GetWotPolicy {
+        id: String,
+    },
+    SetWotPolicy {
+        id: String,
+        policy: WotPolicy,
+    },
+
+    // NIP-13 proof of work for published events
+    GetPowPolicy {
+        id: String,
+    },
+    SetPowPolicy {
+        id: String,
+        policy: PowPolicy,
+    },
+    /// Stop mining in progress; the publishes waiting on it fail
+    CancelPow {
+        id: String,
+    },
+
+    // History sync between devices
+    GetHistorySync {
+        id: String,
+    },
+    SetHistorySync {
+        id: String,
+        enabled: bool,
+    },
+    /// Merge the synced history now and publish the result
+    SyncHistory {
+        id: String,
+    },
 
     // WebRTC operations
     GetPeerStats {
@@ -209,6 +669,191 @@ pub enum WorkerRequest {
         #[serde(rename = "otherSatisfied")]
         other_satisfied: usize,
     },
+
+    // Tree content search
+    IndexTree {
+        id: String,
+        npub: String,
+        #[serde(rename = "treeName")]
+        tree_name: String,
+        cid: WorkerCid,
+    },
+    SearchTrees {
+        id: String,
+        query: String,
+        npub: Option<String>,
+        limit: Option<usize>,
+    },
+    QueryMedia {
+        id: String,
+        #[serde(default)]
+        filter: MediaFilter,
+        #[serde(default)]
+        sort: MediaSort,
+        limit: Option<usize>,
+    },
+
+    // Scheduler queue inspection
+    GetJobs {
+        id: String,
+    },
+}
+
+/// Request id, wire name and scheduling class of every request type. Listing
+/// them here makes a new variant fail to compile until it is classified.
+macro_rules! request_classes {
+    ($($variant:ident => $kind:literal, $priority:expr;)*) => {
+        impl WorkerRequest {
+            pub fn id(&self) -> &str {
+                match self {
+                    $(WorkerRequest::$variant { id, .. } => id,)*
+                }
+            }
+
+            /// Wire `type` of the request
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    $(WorkerRequest::$variant { .. } => $kind,)*
+                }
+            }
+
+            /// Scheduling class; `None` runs without waiting for a slot
+            pub fn priority(&self) -> Option<Priority> {
+                match self {
+                    $(WorkerRequest::$variant { .. } => $priority,)*
+                }
+            }
+        }
+    };
+}
+
+request_classes! {
+    Init => "init", None;
+    Ping => "ping", None;
+    GetJobs => "getJobs", None;
+    PauseDownload => "pauseDownload", None;
+    ResumeDownload => "resumeDownload", None;
+    GetSyncStatus => "getSyncStatus", None;
+    CancelPow => "cancelPow", None;
+    Get => "get", Some(Priority::Interactive);
+    Has => "has", Some(Priority::Interactive);
+    GetMany => "getMany", Some(Priority::Interactive);
+    HasMany => "hasMany", Some(Priority::Interactive);
+    ReadFile => "readFile", Some(Priority::Interactive);
+    ReadFileRange => "readFileRange", Some(Priority::Interactive);
+    ReadFileStream => "readFileStream", Some(Priority::Interactive);
+    ListDir => "listDir", Some(Priority::Interactive);
+    SearchTrees => "searchTrees", Some(Priority::Interactive);
+    QueryMedia => "queryMedia", Some(Priority::Interactive);
+    Delete => "delete", Some(Priority::Metadata);
+    CreateDir => "createDir", Some(Priority::Metadata);
+    DeleteFile => "deleteFile", Some(Priority::Metadata);
+    ResolveRoot => "resolveRoot", Some(Priority::Metadata);
+    ListTrees => "listTrees", Some(Priority::Metadata);
+    GetTreeLog => "getTreeLog", Some(Priority::Metadata);
+    Subscribe => "subscribe", Some(Priority::Metadata);
+    Unsubscribe => "unsubscribe", Some(Priority::Metadata);
+    Publish => "publish", Some(Priority::Metadata);
+    SubscribeComments => "subscribeComments", Some(Priority::Metadata);
+    PostComment => "postComment", Some(Priority::Metadata);
+    SetIdentity => "setIdentity", Some(Priority::Metadata);
+    ListAccounts => "listAccounts", Some(Priority::Metadata);
+    SwitchAccount => "switchAccount", Some(Priority::Metadata);
+    SetGuestMode => "setGuestMode", Some(Priority::Metadata);
+    SetRelays => "setRelays", Some(Priority::Metadata);
+    GetRelays => "getRelays", Some(Priority::Metadata);
+    UpdateFollows => "updateFollows", Some(Priority::Metadata);
+    GetFollows => "getFollows", Some(Priority::Metadata);
+    GetActivityFeed => "getActivityFeed", Some(Priority::Metadata);
+    GetProfile => "getProfile", Some(Priority::Metadata);
+    Follow => "follow", Some(Priority::Metadata);
+    Unfollow => "unfollow", Some(Priority::Metadata);
+    GetFollowers => "getFollowers", Some(Priority::Metadata);
+    GetWotDistance => "getWotDistance", Some(Priority::Metadata);
+    GetUsersWithinDistance => "getUsersWithinDistance", Some(Priority::Metadata);
+    GetMutes => "getMutes", Some(Priority::Metadata);
+    MutePubkey => "mutePubkey", Some(Priority::Metadata);
+    UnmutePubkey => "unmutePubkey", Some(Priority::Metadata);
+    FetchMutes => "fetchMutes", Some(Priority::Metadata);
+    PublishMutes => "publishMutes", Some(Priority::Metadata);
+    BlossomDownload => "blossomDownload", Some(Priority::Metadata);
+    BlossomExists => "blossomExists", Some(Priority::Metadata);
+    GetStorageStats => "getStorageStats", Some(Priority::Metadata);
+    GetSocialGraphSize => "getSocialGraphSize", Some(Priority::Metadata);
+    SetStorageMaxBytes => "setStorageMaxBytes", Some(Priority::Metadata);
+    SetCacheAdmission => "setCacheAdmission", Some(Priority::Metadata);
+    SetPacking => "setPacking", Some(Priority::Metadata);
+    SetQuota => "setQuota", Some(Priority::Metadata);
+    GetRelayStats => "getRelayStats", Some(Priority::Metadata);
+    GetOutbox => "getOutbox", Some(Priority::Metadata);
+    RetryOutbox => "retryOutbox", Some(Priority::Metadata);
+    SetBlossomServers => "setBlossomServers", Some(Priority::Metadata);
+    GetBlossomServers => "getBlossomServers", Some(Priority::Metadata);
+    SetProxy => "setProxy", Some(Priority::Metadata);
+    GetProxy => "getProxy", Some(Priority::Metadata);
+    PublishTree => "publishTree", Some(Priority::Metadata);
+    GrantAccess => "grantAccess", Some(Priority::Metadata);
+    RevokeAccess => "revokeAccess", Some(Priority::Metadata);
+    ListGrants => "listGrants", Some(Priority::Metadata);
+    CreateShareLink => "createShareLink", Some(Priority::Metadata);
+    RepublishTree => "republishTree", Some(Priority::Metadata);
+    SetCollaborators => "setCollaborators", Some(Priority::Metadata);
+    ProposeRoot => "proposeRoot", Some(Priority::Metadata);
+    ListProposals => "listProposals", Some(Priority::Metadata);
+    DiffProposal => "diffProposal", Some(Priority::Interactive);
+    MergeProposal => "mergeProposal", Some(Priority::Interactive);
+    DismissProposal => "dismissProposal", Some(Priority::Metadata);
+    GetPeerStats => "getPeerStats", Some(Priority::Metadata);
+    SendHello => "sendHello", Some(Priority::Metadata);
+    SetWebRTCPools => "setWebRTCPools", Some(Priority::Metadata);
+    SetSyncPaused => "setSyncPaused", Some(Priority::Metadata);
+    GetNotifyRules => "getNotifyRules", Some(Priority::Metadata);
+    SetNotifyRules => "setNotifyRules", Some(Priority::Metadata);
+    GetWotPolicy => "getWotPolicy", Some(Priority::Metadata);
+    SetWotPolicy => "setWotPolicy", Some(Priority::Metadata);
+    GetPowPolicy => "getPowPolicy", Some(Priority::Metadata);
+    SetPowPolicy => "setPowPolicy", Some(Priority::Metadata);
+    GetHistorySync => "getHistorySync", Some(Priority::Metadata);
+    SetHistorySync => "setHistorySync", Some(Priority::Metadata);
+    Put => "put", Some(Priority::Background);
+    PutMany => "putMany", Some(Priority::Background);
+    WriteFile => "writeFile", Some(Priority::Background);
+    BlossomUpload => "blossomUpload", Some(Priority::Background);
+    RunEviction => "runEviction", Some(Priority::Background);
+    RunScrub => "runScrub", Some(Priority::Background);
+    GetNdbStats => "getNdbStats", Some(Priority::Background);
+    PurgeNdb => "purgeNdb", Some(Priority::Background);
+    ExportState => "exportState", Some(Priority::Background);
+    ImportState => "importState", Some(Priority::Background);
+    PushToBlossom => "pushToBlossom", Some(Priority::Background);
+    RotateTreeKey => "rotateTreeKey", Some(Priority::Background);
+    RepublishTrees => "republishTrees", Some(Priority::Background);
+    DeleteTree => "deleteTree", Some(Priority::Metadata);
+    IndexTree => "indexTree", Some(Priority::Background);
+    TreeStats => "treeStats", Some(Priority::Background);
+    FindDuplicates => "findDuplicates", Some(Priority::Background);
+    AppendLog => "appendLog", Some(Priority::Background);
+    ReadLog => "readLog", Some(Priority::Interactive);
+    DownloadToDisk => "downloadToDisk", Some(Priority::Background);
+    AddToInbox => "addToInbox", Some(Priority::Background);
+    SyncHistory => "syncHistory", Some(Priority::Background);
+}
+
+/// Blob reads served over the binary IPC channel (`worker_blob`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum BlobRequest {
+    Get {
+        hash: String,
+    },
+    ReadFile {
+        cid: WorkerCid,
+    },
+    ReadFileRange {
+        cid: WorkerCid,
+        start: u64,
+        end: Option<u64>,
+    },
 }
 
 /// Worker response messages to frontend
@@ -226,11 +871,50 @@ pub enum WorkerResponse {
         data: Option<String>,
     }, // base64 data
     Bool { id: String, value: bool },
+    /// Batch results, one base64 blob (or None) per requested hash
+    Results { id: String, data: Vec<Option<String>> },
+    Bools { id: String, values: Vec<bool> },
     Cid { id: String, cid: Option<WorkerCid> },
+    Trees {
+        id: String,
+        trees: Vec<TreeRoot>,
+    },
+    TreeLog {
+        id: String,
+        commits: Vec<TreeRoot>,
+    },
+    Proposals {
+        id: String,
+        proposals: Vec<Proposal>,
+    },
+    TreeDiff {
+        id: String,
+        changes: Vec<TreeChange>,
+    },
+    Duplicates {
+        id: String,
+        groups: Vec<DuplicateGroup>,
+        /// Sum of the groups' savings
+        savings: u64,
+    },
     DirListing {
         id: String,
         entries: Option<Vec<WorkerDirEntry>>,
     },
+    TreeStats {
+        id: String,
+        stats: hashtree_core::TreeStats,
+    },
+    LogAppended {
+        id: String,
+        cid: WorkerCid,
+        length: u64,
+    },
+    LogEntries {
+        id: String,
+        entries: Vec<String>, // base64
+        length: u64,
+    },
     Void { id: String },
 
     // Nostr events (Phase 3)
@@ -238,6 +922,9 @@ pub enum WorkerResponse {
         #[serde(rename = "subId")]
         sub_id: String,
         event: serde_json::Value,
+        /// Author is beyond the WoT policy's flag distance
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        untrusted: bool,
     },
     Eose {
         #[serde(rename = "subId")]
@@ -263,6 +950,29 @@ pub enum WorkerResponse {
         id: String,
         users: Vec<(String, usize)>,
     },
+    /// Muted pubkeys, hex
+    Mutes {
+        id: String,
+        pubkeys: Vec<String>,
+    },
+    ActivityFeed {
+        id: String,
+        entries: Vec<ActivityEntry>,
+        #[serde(rename = "hasMore")]
+        has_more: bool,
+    },
+    /// Content of the newest kind 0 event, None if there's none
+    Profile {
+        id: String,
+        profile: Option<serde_json::Value>,
+    },
+    /// One level of a `getUsersWithinDistance` walk, sent as it completes
+    /// and before the full `UsersWithDistance` result
+    UsersAtDistance {
+        id: String,
+        distance: usize,
+        users: Vec<String>,
+    },
 
     // Stats
     StorageStats {
@@ -275,6 +985,34 @@ pub enum WorkerResponse {
         pinned_bytes: u64,
         #[serde(rename = "maxBytes")]
         max_bytes: u64,
+        #[serde(rename = "cacheHits")]
+        cache_hits: u64,
+        #[serde(rename = "cacheMisses")]
+        cache_misses: u64,
+        #[serde(rename = "cacheItems")]
+        cache_items: u64,
+        #[serde(rename = "cacheBytes")]
+        cache_bytes: u64,
+        #[serde(rename = "cacheMaxBytes")]
+        cache_max_bytes: u64,
+        #[serde(rename = "ownBytes")]
+        own_bytes: u64,
+        #[serde(rename = "ownTrees")]
+        own_trees: u64,
+        #[serde(rename = "ownQuotaBytes")]
+        own_quota_bytes: u64,
+        #[serde(rename = "othersBytes")]
+        others_bytes: u64,
+        #[serde(rename = "othersTrees")]
+        others_trees: u64,
+        #[serde(rename = "othersQuotaBytes")]
+        others_quota_bytes: u64,
+        /// Bytes of tagged trees counting shared blocks once per tree
+        #[serde(rename = "logicalBytes")]
+        logical_bytes: u64,
+        /// Logical over stored bytes of tagged trees, 1 without sharing
+        #[serde(rename = "dedupRatio")]
+        dedup_ratio: f64,
     },
     SocialGraphSize {
         id: String,
@@ -287,6 +1025,53 @@ pub enum WorkerResponse {
         #[serde(rename = "bytesFreed")]
         bytes_freed: u64,
     },
+    ScrubResult {
+        id: String,
+        checked: u64,
+        bytes: u64,
+        /// Hashes of blobs that failed verification
+        corrupted: Vec<String>,
+        /// How many of them were fetched again
+        refetched: u64,
+    },
+    NdbStats {
+        id: String,
+        bytes: u64,
+        events: u64,
+        kinds: Vec<KindCount>,
+        /// Purge staged for the next start
+        #[serde(rename = "pendingPurge")]
+        pending_purge: Option<PurgeRule>,
+        /// Events the staged purge removes
+        purgeable: u64,
+    },
+    NdbPurgeScheduled {
+        id: String,
+        /// Events that will be removed
+        events: u64,
+        #[serde(rename = "restartRequired")]
+        restart_required: bool,
+    },
+    StateExported {
+        id: String,
+        files: u64,
+        bytes: u64,
+    },
+    StateImported {
+        id: String,
+        files: u64,
+        bytes: u64,
+        settings: StateSettings,
+        /// Restored identity key (hex), if the passphrase opened it
+        nsec: Option<String>,
+        #[serde(rename = "restartRequired")]
+        restart_required: bool,
+    },
+    Downloaded {
+        id: String,
+        files: u64,
+        bytes: u64,
+    },
 
     // Relay statistics
     RelayStats {
@@ -294,6 +1079,14 @@ pub enum WorkerResponse {
         relays: Vec<RelayStatEntry>,
     },
 
+    // Outbox of published events, newest first
+    Outbox {
+        id: String,
+        entries: Vec<OutboxEntry>,
+        /// Entries no relay has accepted yet
+        pending: usize,
+    },
+
     // Blossom servers
     BlossomServers {
         id: String,
@@ -303,6 +1096,11 @@ pub enum WorkerResponse {
         write_servers: Vec<String>,
     },
 
+    Proxy {
+        id: String,
+        proxy: ProxyConfig,
+    },
+
     // Push to Blossom result
     PushResult {
         id: String,
@@ -312,7 +1110,23 @@ pub enum WorkerResponse {
         errors: Option<Vec<String>>,
     },
 
-    // Push progress
+    // Progress of a bulk operation (push, republish, index, import/export
+    // stream, eviction), keyed by the id of the request that started it
+    Progress {
+        #[serde(rename = "opId")]
+        op_id: String,
+        phase: String,
+        #[serde(rename = "bytesDone")]
+        bytes_done: u64,
+        #[serde(rename = "bytesTotal")]
+        bytes_total: Option<u64>,
+        #[serde(rename = "itemsDone")]
+        items_done: u64,
+        #[serde(rename = "itemsTotal")]
+        items_total: Option<u64>,
+    },
+
+    // Push progress (block counts only; see Progress)
     PushProgress {
         #[serde(rename = "treeName")]
         tree_name: String,
@@ -332,6 +1146,46 @@ pub enum WorkerResponse {
         version: u64,
     },
 
+    // Tree root event published
+    Published {
+        id: String,
+        /// Root as published (differs from the request when a manifest was embedded)
+        cid: WorkerCid,
+        #[serde(rename = "eventId")]
+        event_id: String,
+        #[serde(rename = "linkSecret")]
+        link_secret: Option<String>,
+    },
+    TreeDeleted {
+        id: String,
+        /// The NIP-09 deletion
+        #[serde(rename = "eventId")]
+        event_id: String,
+        /// Local blocks no other tree of ours uses, now evictable
+        released: usize,
+        /// Blocks deleted from Blossom
+        #[serde(rename = "blobsDeleted")]
+        blobs_deleted: usize,
+    },
+    // Tree re-encrypted and republished under a new key
+    Rotated {
+        id: String,
+        cid: WorkerCid,
+        #[serde(rename = "eventId")]
+        event_id: String,
+        #[serde(rename = "linkSecret")]
+        link_secret: Option<String>,
+    },
+    ShareLink {
+        id: String,
+        nhash: String,
+        url: String,
+    },
+    Grants {
+        id: String,
+        recipients: Vec<String>,
+    },
+
     // Batch republish result
     RepublishResult {
         id: String,
@@ -345,6 +1199,65 @@ pub enum WorkerResponse {
         id: String,
         peers: Vec<PeerStatEntry>,
     },
+
+    // Aggregate sync status
+    SyncStatus {
+        id: String,
+        status: SyncStatus,
+    },
+
+    NotifyRules {
+        id: String,
+        rules: NotifyRules,
+    },
+
+    WotPolicy {
+        id: String,
+        policy: WotPolicy,
+    },
+    PowPolicy {
+        id: String,
+        policy: PowPolicy,
+    },
+
+    // History entries changed by a history sync
+    HistorySynced {
+        id: String,
+        merged: usize,
+    },
+
+    // Tree content search
+    IndexResult {
+        id: String,
+        count: u32,
+    },
+    SearchResults {
+        id: String,
+        hits: Vec<SearchHit>,
+    },
+    MediaItems {
+        id: String,
+        items: Vec<MediaItem>,
+    },
+
+    // Scheduler queue: running jobs first, then queued ones in start order
+    Jobs {
+        id: String,
+        jobs: Vec<JobInfo>,
+    },
+
+    // Remembered identities
+    Accounts {
+        id: String,
+        accounts: Vec<AccountInfo>,
+    },
+
+    // Guest session state, with its ephemeral pubkey (hex) while on
+    GuestMode {
+        id: String,
+        enabled: bool,
+        pubkey: Option<String>,
+    },
 }
 
 /// WebRTC peer statistics entry
@@ -356,12 +1269,125 @@ pub struct PeerStatEntry {
     pub pool: String,
 }
 
-/// Relay connection statistics entry
+/// Tree content search hit
 #[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub npub: String,
+    pub tree_name: String,
+    pub path: String,
+    pub name: String,
+    pub hash: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub snippet: Option<String>,
+    pub score: f64,
+}
+
+/// Filter for media library queries
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaFilter {
+    pub kind: Option<MediaKind>,
+    pub npub: Option<String>,
+    pub tree_name: Option<String>,
+    /// Only items captured at or after this unix timestamp
+    pub since: Option<u64>,
+    /// Only items captured at or before this unix timestamp
+    pub until: Option<u64>,
+}
+
+impl MediaFilter {
+    pub fn matches(&self, npub: &str, tree_name: &str, media: &MediaMetadata) -> bool {
+        if self.kind.is_some() && media.kind != self.kind {
+            return false;
+        }
+        if self.npub.as_deref().is_some_and(|n| n != npub) {
+            return false;
+        }
+        if self.tree_name.as_deref().is_some_and(|t| t != tree_name) {
+            return false;
+        }
+        if self.since.is_some() || self.until.is_some() {
+            let Some(captured_at) = media.captured_at else {
+                return false;
+            };
+            if self.since.is_some_and(|s| captured_at < s) || self.until.is_some_and(|u| captured_at > u) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Sort order for media library queries
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MediaSort {
+    /// Newest first (photo timeline)
+    #[default]
+    DateDesc,
+    DateAsc,
+    Name,
+    /// Longest first
+    Duration,
+    /// By album, then title (music library)
+    Album,
+}
+
+/// Media library entry
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaItem {
+    pub npub: String,
+    pub tree_name: String,
+    pub path: String,
+    pub name: String,
+    pub hash: String,
+    pub size: u64,
+    #[serde(flatten)]
+    pub media: MediaMetadata,
+}
+
+/// Settings restored with an imported state archive, held by the frontend
+/// and the worker rather than in files
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StateSettings {
+    #[serde(default)]
+    pub pubkey: Option<String>,
+    #[serde(default)]
+    pub relays: Vec<String>,
+    #[serde(default)]
+    pub read_servers: Vec<String>,
+    #[serde(default)]
+    pub write_servers: Vec<String>,
+}
+
+/// Relay connection statistics entry
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct RelayStatEntry {
     pub url: String,
     pub connected: bool,
     pub connecting: bool,
+    /// 0-100, from connection failures, EOSE latency and notices
+    pub score: u8,
+    /// Failing persistently, only retried every half hour
+    pub demoted: bool,
+    /// Failed connection attempts in a row
+    pub failures: u32,
+    /// Seconds until the next connection attempt, when disconnected
+    pub next_retry_secs: Option<u64>,
+    /// Last failed attempt, notice, CLOSED or rejected event
+    pub last_error: Option<String>,
+    pub messages_per_sec: f64,
+    /// Average time from REQ to EOSE
+    pub eose_latency_ms: Option<u64>,
+    /// Notices since the relay last connected
+    pub notices: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
 }
 
 #[cfg(test)]
@@ -415,16 +1441,91 @@ mod tests {
                 parent_cid,
                 path,
                 data,
+                encrypted,
             } => {
                 assert_eq!(id, "test-4");
                 assert!(parent_cid.is_none());
                 assert_eq!(path, "test.txt");
                 assert_eq!(data, "SGVsbG8=");
+                assert!(!encrypted);
             }
             _ => panic!("Expected WriteFile"),
         }
     }
 
+    #[test]
+    fn test_worker_request_deserialize_publish_tree() {
+        let json = r#"{"type":"publishTree","id":"p-1","treeName":"docs","cid":{"hash":"ab","key":"cd"},"visibility":"private"}"#;
+        let req: WorkerRequest = serde_json::from_str(json).unwrap();
+        match req {
+            WorkerRequest::PublishTree {
+                tree_name,
+                cid,
+                visibility,
+                sign,
+                message,
+                ..
+            } => {
+                assert_eq!(tree_name, "docs");
+                assert_eq!(cid.key.as_deref(), Some("cd"));
+                assert_eq!(visibility, TreeVisibility::Private);
+                assert!(!sign);
+                assert_eq!(message, None);
+            }
+            _ => panic!("Expected PublishTree"),
+        }
+
+        let json = r#"{"type":"publishTree","id":"p-2","treeName":"docs","cid":{"hash":"ab"},"message":"Added notes","parent":"ef"}"#;
+        match serde_json::from_str::<WorkerRequest>(json).unwrap() {
+            WorkerRequest::PublishTree {
+                message, parent, ..
+            } => {
+                assert_eq!(message.as_deref(), Some("Added notes"));
+                assert_eq!(parent.as_deref(), Some("ef"));
+            }
+            _ => panic!("Expected PublishTree"),
+        }
+
+        let json = r#"{"type":"getTreeLog","id":"l-1","pubkey":"npub1x","treeName":"docs"}"#;
+        match serde_json::from_str::<WorkerRequest>(json).unwrap() {
+            WorkerRequest::GetTreeLog {
+                tree_name, limit, ..
+            } => {
+                assert_eq!(tree_name, "docs");
+                assert_eq!(limit, None);
+            }
+            _ => panic!("Expected GetTreeLog"),
+        }
+
+        let json = r#"{"type":"proposeRoot","id":"c-1","owner":"npub1x","treeName":"docs","cid":{"hash":"ab"},"message":"Fix typo"}"#;
+        match serde_json::from_str::<WorkerRequest>(json).unwrap() {
+            WorkerRequest::ProposeRoot {
+                owner,
+                tree_name,
+                base,
+                message,
+                ..
+            } => {
+                assert_eq!((owner.as_str(), tree_name.as_str()), ("npub1x", "docs"));
+                assert_eq!(base, None);
+                assert_eq!(message.as_deref(), Some("Fix typo"));
+            }
+            _ => panic!("Expected ProposeRoot"),
+        }
+
+        let json = r#"{"type":"mergeProposal","id":"c-2","eventId":"ef"}"#;
+        match serde_json::from_str::<WorkerRequest>(json).unwrap() {
+            WorkerRequest::MergeProposal { event_id, .. } => assert_eq!(event_id, "ef"),
+            _ => panic!("Expected MergeProposal"),
+        }
+
+        let json = r#"{"type":"createDir","id":"d-1","encrypted":true}"#;
+        match serde_json::from_str::<WorkerRequest>(json).unwrap() {
+            WorkerRequest::CreateDir { encrypted, .. } => assert!(encrypted),
+            _ => panic!("Expected CreateDir"),
+        }
+    }
+
     #[test]
     fn test_worker_response_serialize_ready() {
         let resp = WorkerResponse::Ready {
@@ -467,4 +1568,204 @@ mod tests {
         assert!(json.contains(r#""name":"file.txt""#));
         assert!(json.contains(r#""linkType":0"#));
     }
+
+    #[test]
+    fn test_worker_request_deserialize_search_trees() {
+        let json = r#"{"type":"searchTrees","id":"test-5","query":"readme","npub":null}"#;
+        let req: WorkerRequest = serde_json::from_str(json).unwrap();
+        match req {
+            WorkerRequest::SearchTrees {
+                id,
+                query,
+                npub,
+                limit,
+            } => {
+                assert_eq!(id, "test-5");
+                assert_eq!(query, "readme");
+                assert!(npub.is_none());
+                assert!(limit.is_none());
+            }
+            _ => panic!("Expected SearchTrees"),
+        }
+    }
+
+    #[test]
+    fn test_worker_response_serialize_search_results() {
+        let resp = WorkerResponse::SearchResults {
+            id: "test-6".to_string(),
+            hits: vec![SearchHit {
+                npub: "npub1abc".to_string(),
+                tree_name: "public".to_string(),
+                path: "docs/readme.md".to_string(),
+                name: "readme.md".to_string(),
+                hash: "abc123".to_string(),
+                size: 12,
+                is_dir: false,
+                snippet: None,
+                score: 8.5,
+            }],
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        assert!(json.contains(r#""type":"searchResults""#));
+        assert!(json.contains(r#""treeName":"public""#));
+        assert!(json.contains(r#""isDir":false"#));
+    }
+
+    #[test]
+    fn test_worker_request_deserialize_query_media() {
+        let json = r#"{"type":"queryMedia","id":"test-7","filter":{"kind":"image","treeName":"photos"},"sort":"dateAsc"}"#;
+        let req: WorkerRequest = serde_json::from_str(json).unwrap();
+        match req {
+            WorkerRequest::QueryMedia { id, filter, sort, .. } => {
+                assert_eq!(id, "test-7");
+                assert_eq!(filter.kind, Some(MediaKind::Image));
+                assert_eq!(filter.tree_name.as_deref(), Some("photos"));
+                assert!(matches!(sort, MediaSort::DateAsc));
+            }
+            _ => panic!("Expected QueryMedia"),
+        }
+
+        // filter and sort are optional
+        let json = r#"{"type":"queryMedia","id":"test-8"}"#;
+        let req: WorkerRequest = serde_json::from_str(json).unwrap();
+        assert!(matches!(
+            req,
+            WorkerRequest::QueryMedia {
+                sort: MediaSort::DateDesc,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_request_kind_matches_wire_type() {
+        for (json, priority) in [
+            (
+                r#"{"type":"readFile","id":"a","cid":{"hash":"00"}}"#,
+                Some(Priority::Interactive),
+            ),
+            (r#"{"type":"getRelays","id":"b"}"#, Some(Priority::Metadata)),
+            (
+                r#"{"type":"runEviction","id":"c"}"#,
+                Some(Priority::Background),
+            ),
+            (r#"{"type":"getJobs","id":"d"}"#, None),
+            (
+                r#"{"type":"putMany","id":"e","items":[{"hash":"00","data":""}]}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"setQuota","id":"f","ownBytes":0,"othersBytes":1024}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"setCacheAdmission","id":"f2","enabled":true,"largeFileBytes":67108864}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"switchAccount","id":"g","pubkey":"00"}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"downloadToDisk","id":"h","cid":{"hash":"00"},"destPath":"/tmp/a"}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"pauseDownload","id":"i","downloadId":"h"}"#,
+                None,
+            ),
+            (
+                r#"{"type":"addToInbox","id":"j","items":[{"kind":"url","url":"https://a.example"}]}"#,
+                Some(Priority::Background),
+            ),
+            (r#"{"type":"getSyncStatus","id":"k"}"#, None),
+            (
+                r#"{"type":"setNotifyRules","id":"m","rules":{"maxDistance":1}}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"setSyncPaused","id":"l","paused":true}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"syncHistory","id":"n"}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"setWotPolicy","id":"o","policy":{"enabled":true}}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"mutePubkey","id":"p","pubkey":"00"}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"unfollow","id":"q","pubkey":"00"}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"getProfile","id":"r","pubkey":"00"}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"getActivityFeed","id":"t","before":1700000000}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"postComment","id":"s","npub":"npub1a","treeName":"t","content":"hi","replyTo":{"id":"00","pubkey":"00"}}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"purgeNdb","id":"u","olderThan":1700000000,"kinds":[1,7]}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"retryOutbox","id":"v","eventIds":["00"]}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"setPowPolicy","id":"w","policy":{"enabled":true,"kinds":{"1":20}}}"#,
+                Some(Priority::Metadata),
+            ),
+            (r#"{"type":"cancelPow","id":"x"}"#, None),
+            (
+                r#"{"type":"listTrees","id":"y","pubkey":"ab"}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"deleteTree","id":"z","treeName":"docs"}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"treeStats","id":"za","cid":{"hash":"00"}}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"findDuplicates","id":"ze"}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"setPacking","id":"zd","threshold":16384}"#,
+                Some(Priority::Metadata),
+            ),
+            (
+                r#"{"type":"appendLog","id":"zb","cid":null,"entries":["aGk="]}"#,
+                Some(Priority::Background),
+            ),
+            (
+                r#"{"type":"readLog","id":"zc","cid":{"hash":"00"},"start":0}"#,
+                Some(Priority::Interactive),
+            ),
+            (
+                r#"{"type":"setProxy","id":"zf","proxy":{"socks5":"127.0.0.1:9050","relays":{"mode":"direct"}}}"#,
+                Some(Priority::Metadata),
+            ),
+        ] {
+            let req: WorkerRequest = serde_json::from_str(json).unwrap();
+            let value: serde_json::Value = serde_json::from_str(json).unwrap();
+            assert_eq!(req.kind(), value["type"]);
+            assert_eq!(req.id(), value["id"]);
+            assert_eq!(req.priority(), priority);
+        }
+    }
 }
@@ -1,7 +1,10 @@
 //! WebRTC peer connection manager for Tauri
 //!
 //! Integrates hashtree-webrtc with Tauri, sharing the Nostr client
-//! with NostrManager to avoid duplicate relay connections.
+//! with NostrManager to avoid duplicate relay connections. Signaling from
+//! peers the WoT policy doesn't let us serve, or that we've muted, is
+//! dropped, so they never get a connection or a place in the peer pools.
+//! WebRTC doesn't start while a network proxy is set.
 
 use hashtree_webrtc::{
     ClassifyRequest, NostrRelayTransport, PeerPool, PoolConfig, PoolSettings,
@@ -14,6 +17,8 @@ use tokio::sync::{mpsc, RwLock};
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
+use super::wot::Wot;
+
 /// Peer statistics for frontend display
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct PeerStats {
@@ -40,6 +45,8 @@ pub struct WebRTCManager {
     classifier_tx: Arc<RwLock<Option<mpsc::Sender<ClassifyRequest>>>>,
     /// Running flag for background task
     running: Arc<RwLock<bool>>,
+    /// Policy deciding which peers we answer
+    wot: Option<Arc<Wot>>,
     /// Debug mode
     debug: bool,
 }
@@ -63,10 +70,17 @@ impl WebRTCManager {
             follows: Arc::new(RwLock::new(HashSet::new())),
             classifier_tx: Arc::new(RwLock::new(None)),
             running: Arc::new(RwLock::new(false)),
+            wot: None,
             debug: false,
         }
     }
 
+    /// Only answer peers `wot` allows serving
+    pub fn with_wot(mut self, wot: Arc<Wot>) -> Self {
+        self.wot = Some(wot);
+        self
+    }
+
     /// Initialize WebRTC with a shared Nostr client
     ///
     /// Call this after NostrManager has been initialized and identity has been set.
@@ -76,6 +90,11 @@ impl WebRTCManager {
             debug!("WebRTC already initialized");
             return Ok(());
         }
+        // ICE would reveal our address to peers and STUN servers
+        if crate::proxy::is_proxied() {
+            info!("WebRTC disabled while a proxy is set");
+            return Ok(());
+        }
 
         info!("Initializing WebRTC with shared Nostr client...");
 
@@ -173,6 +192,7 @@ impl WebRTCManager {
         let transport = self.transport.clone();
         let signaling = self.signaling.clone();
         let running = self.running.clone();
+        let wot = self.wot.clone();
 
         tokio::spawn(async move {
             loop {
@@ -188,6 +208,12 @@ impl WebRTCManager {
                     // Use try_recv instead of recv to avoid losing the receiver on timeout
                     if let Some(msg) = transport.try_recv() {
                         debug!("Received signaling message: {:?}", msg);
+                        // Peer ids are "pubkey:uuid"
+                        let pubkey = msg.peer_id().split(':').next().unwrap_or("");
+                        if wot.as_ref().is_some_and(|wot| !wot.may_serve(pubkey)) {
+                            debug!("Ignoring signaling from peer outside WoT: {}", pubkey);
+                            continue;
+                        }
                         if let Err(e) = signaling.handle_message(msg).await {
                             warn!("Failed to handle signaling message: {:?}", e);
                         }
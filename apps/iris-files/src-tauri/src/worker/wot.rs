@@ -0,0 +1,212 @@
+//! Web-of-trust policy for fetching, serving and showing content
+//!
+//! Decisions go by the nostrdb social-graph distance of the author or peer:
+//! trees of other people are only prefetched and indexed from within
+//! `prefetchMaxDistance` (1 is people we follow), WebRTC signaling is only
+//! answered for peers within `serveMaxDistance`, so nobody further away gets
+//! connected to fetch blocks from us, and events and htree responses from
+//! beyond `flagMaxDistance` are flagged as untrusted for the frontend to
+//! blur. HTML the htree server serves from trees of owners beyond
+//! `sandboxMaxDistance` gets a restrictive Content Security Policy. The
+//! policy is off until enabled and kept in `wot_policy.json`.
+//! Muted pubkeys are refused and flagged whether it's on or not.
+
+use nostrdb::Ndb;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::mutes::MuteList;
+use super::notify::follow_distance;
+use super::shares::parse_pubkey;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WotPolicy {
+    pub enabled: bool,
+    /// Follow distance of owners whose trees are prefetched
+    pub prefetch_max_distance: usize,
+    /// Follow distance of WebRTC peers we connect to and serve
+    pub serve_max_distance: usize,
+    /// Flag content of authors beyond `flag_max_distance`
+    pub flag_unknown: bool,
+    pub flag_max_distance: usize,
+    /// Sandbox HTML from trees of owners beyond `sandbox_max_distance`
+    pub sandbox_html: bool,
+    pub sandbox_max_distance: usize,
+}
+
+impl Default for WotPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefetch_max_distance: 1,
+            serve_max_distance: 2,
+            flag_unknown: true,
+            flag_max_distance: 2,
+            sandbox_html: true,
+            sandbox_max_distance: 1,
+        }
+    }
+}
+
+impl WotPolicy {
+    /// Whether `distance` (None if not connected) is within `max`, or the
+    /// policy is off
+    fn within(&self, distance: Option<usize>, max: usize) -> bool {
+        !self.enabled || distance.is_some_and(|d| d <= max)
+    }
+
+    pub fn allows_prefetch(&self, distance: Option<usize>) -> bool {
+        self.within(distance, self.prefetch_max_distance)
+    }
+
+    pub fn allows_serving(&self, distance: Option<usize>) -> bool {
+        self.within(distance, self.serve_max_distance)
+    }
+
+    pub fn flags(&self, distance: Option<usize>) -> bool {
+        self.flag_unknown && !self.within(distance, self.flag_max_distance)
+    }
+
+    pub fn sandboxes(&self, distance: Option<usize>) -> bool {
+        self.sandbox_html && !self.within(distance, self.sandbox_max_distance)
+    }
+}
+
+/// The policy and the social graph it's applied against
+pub struct Wot {
+    path: PathBuf,
+    policy: RwLock<WotPolicy>,
+    ndb: Arc<Ndb>,
+    mutes: Arc<MuteList>,
+}
+
+impl Wot {
+    pub fn new(data_dir: &Path, ndb: Arc<Ndb>, mutes: Arc<MuteList>) -> Self {
+        let path = data_dir.join("wot_policy.json");
+        let policy = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            policy: RwLock::new(policy),
+            ndb,
+            mutes,
+        }
+    }
+
+    pub fn policy(&self) -> WotPolicy {
+        self.policy.read().clone()
+    }
+
+    pub fn set_policy(&self, policy: WotPolicy) -> Result<(), String> {
+        let data = serde_json::to_vec_pretty(&policy)
+            .map_err(|e| format!("Failed to encode WoT policy: {}", e))?;
+        std::fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to save WoT policy: {}", e))?;
+        *self.policy.write() = policy;
+        Ok(())
+    }
+
+    /// Apply `decide` to the distance of `pubkey` (hex or npub). The graph
+    /// is only looked up while the policy is on.
+    fn decide(&self, pubkey: &str, decide: impl Fn(&WotPolicy, Option<usize>) -> bool) -> bool {
+        let policy = self.policy();
+        if !policy.enabled {
+            return decide(&policy, None);
+        }
+        let distance = parse_pubkey(pubkey)
+            .ok()
+            .and_then(|pubkey| follow_distance(&self.ndb, &pubkey));
+        decide(&policy, distance)
+    }
+
+    /// Whether trees of `owner` may be fetched in the background
+    pub fn may_prefetch(&self, owner: &str) -> bool {
+        !self.mutes.is_muted(owner) && self.decide(owner, WotPolicy::allows_prefetch)
+    }
+
+    /// Whether WebRTC peer `pubkey` may connect to us
+    pub fn may_serve(&self, pubkey: &str) -> bool {
+        !self.mutes.is_muted(pubkey) && self.decide(pubkey, WotPolicy::allows_serving)
+    }
+
+    /// Whether content by `author` is flagged as untrusted
+    pub fn flags(&self, author: &str) -> bool {
+        self.mutes.is_muted(author) || self.decide(author, WotPolicy::flags)
+    }
+
+    /// Whether HTML from trees of `owner` is served sandboxed
+    pub fn sandboxes(&self, owner: &str) -> bool {
+        self.mutes.is_muted(owner) || self.decide(owner, WotPolicy::sandboxes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostrdb::Config;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_policy_decisions() {
+        let off = WotPolicy::default();
+        assert!(off.allows_prefetch(None));
+        assert!(off.allows_serving(Some(5)));
+        assert!(!off.flags(None));
+
+        let on = WotPolicy {
+            enabled: true,
+            ..WotPolicy::default()
+        };
+        assert!(on.allows_prefetch(Some(0)));
+        assert!(on.allows_prefetch(Some(1)));
+        assert!(!on.allows_prefetch(Some(2)));
+        assert!(on.allows_serving(Some(2)));
+        assert!(!on.allows_serving(None));
+        assert!(!on.flags(Some(2)));
+        assert!(on.flags(Some(3)));
+        assert!(on.flags(None));
+        assert!(!off.sandboxes(None));
+        assert!(!on.sandboxes(Some(1)));
+        assert!(on.sandboxes(Some(2)));
+        assert!(on.sandboxes(None));
+        assert!(!WotPolicy {
+            flag_unknown: false,
+            ..on
+        }
+        .flags(None));
+    }
+
+    #[test]
+    fn test_policy_persists() {
+        let dir = TempDir::new().unwrap();
+        let ndb_dir = dir.path().join("nostrdb");
+        std::fs::create_dir_all(&ndb_dir).unwrap();
+        let ndb = Arc::new(Ndb::new(ndb_dir.to_str().unwrap(), &Config::new()).unwrap());
+        let mutes = Arc::new(MuteList::new(dir.path()));
+        let wot = Wot::new(dir.path(), ndb.clone(), mutes.clone());
+        let stranger = nostr_sdk::Keys::generate().public_key().to_hex();
+        assert!(wot.may_serve(&stranger));
+        let muted = nostr_sdk::Keys::generate().public_key().to_hex();
+        mutes.set_muted(&muted, true).unwrap();
+        assert!(!wot.may_serve(&muted));
+        assert!(!wot.may_prefetch(&muted));
+        assert!(wot.flags(&muted));
+
+        let policy = WotPolicy {
+            enabled: true,
+            serve_max_distance: 3,
+            ..WotPolicy::default()
+        };
+        wot.set_policy(policy.clone()).unwrap();
+        assert_eq!(Wot::new(dir.path(), ndb, mutes).policy(), policy);
+        assert!(!wot.may_serve(&stranger));
+        assert!(!wot.may_prefetch("not a pubkey"));
+        assert!(wot.flags(&stranger));
+        assert!(wot.sandboxes(&stranger));
+    }
+}
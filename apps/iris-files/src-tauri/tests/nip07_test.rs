@@ -2,7 +2,10 @@
 //!
 //! Tests the NIP-07 handler functions directly without Tauri runtime.
 
-use app_lib::nip07::{handle_nip07_request, Nip07State};
+use app_lib::nip07::{
+    find_script, handle_nip07_request, htree_origin_from_nhash, htree_origin_from_npub,
+    htree_url_from_nhash, htree_url_from_npub, parse_htree_host, Nip07State, WebviewPolicy,
+};
 use app_lib::permissions::PermissionStore;
 use app_lib::worker::{BlobStore, WorkerState};
 use nostr_sdk::{Keys, ToBech32};
@@ -265,7 +268,7 @@ async fn test_session_token_validation() {
     let origin = "https://example.com";
 
     // Generate a session token
-    let token = nip07_state.new_session(origin);
+    let token = nip07_state.new_session(origin, true);
     assert!(!token.is_empty(), "Token should not be empty");
 
     // Valid token should pass
@@ -321,6 +324,23 @@ async fn test_permission_denied_get_public_key() {
     );
 }
 
+#[tokio::test]
+async fn test_session_without_nostr_is_refused() {
+    let nip07_state = Nip07State::new(Arc::new(PermissionStore::new(None)));
+    let untrusted = "htree://nhash1untrusted";
+    let trusted = "htree://nhash1trusted";
+
+    // A webview policy without window.nostr gives its session no access
+    let token = nip07_state.new_session(untrusted, false);
+    let trusted_token = nip07_state.new_session(trusted, true);
+    assert!(nip07_state.validate_token(untrusted, &token));
+    assert!(!nip07_state.allows_nostr(untrusted, &token));
+    assert!(nip07_state.allows_nostr(trusted, &trusted_token));
+
+    // Claiming another origin doesn't help without its token
+    assert!(!nip07_state.allows_nostr(trusted, &token));
+}
+
 #[tokio::test]
 async fn test_sign_event_with_tags() {
     let (worker_state, _keys) = create_test_worker_state();
@@ -373,11 +393,6 @@ async fn test_sign_event_with_tags() {
 // htree:// URL helper tests
 // ============================================
 
-use app_lib::nip07::{
-    htree_origin_from_nhash, htree_origin_from_npub, htree_url_from_nhash, htree_url_from_npub,
-    parse_htree_host,
-};
-
 #[test]
 fn test_htree_origin_from_nhash() {
     let origin = htree_origin_from_nhash("nhash1abc123");
@@ -474,3 +489,50 @@ fn test_parse_htree_host_invalid() {
     let result2 = parse_htree_host("example.com");
     assert!(result2.is_none());
 }
+
+#[test]
+fn test_webview_policy_navigation() {
+    let server = "http://127.0.0.1:21417";
+    let url = |s: &str| tauri::Url::parse(s).unwrap();
+    let sandboxed = WebviewPolicy::sandboxed();
+
+    assert!(sandboxed.allows_navigation(&url("htree://nhash1abc/index.html"), server));
+    assert!(sandboxed.allows_navigation(&url("about:blank"), server));
+    assert!(sandboxed.allows_navigation(&url("http://127.0.0.1:21417/htree/x"), server));
+    assert!(!sandboxed.allows_navigation(&url("https://example.com/"), server));
+    assert!(!sandboxed.allows_navigation(&url("http://127.0.0.1:8080/"), server));
+
+    let trusted = WebviewPolicy::trusted();
+    assert!(trusted.allows_navigation(&url("https://example.com/"), server));
+    assert!(trusted.script(server).is_none());
+    let script = sandboxed.script(server).unwrap();
+    assert!(script.contains("BLOCK_NETWORK = true"));
+
+    // The CSP is what enforces it
+    assert!(trusted.csp(server).is_none());
+    let csp = sandboxed.csp(server).unwrap();
+    assert!(csp.starts_with("default-src 'self' htree: data: blob: http://127.0.0.1:21417 "));
+    assert!(!csp.contains('*'));
+}
+
+#[test]
+fn test_webview_policy_wire_format() {
+    let policy: WebviewPolicy =
+        serde_json::from_value(json!({"externalNetwork": true, "nostr": false, "downloads": true}))
+            .unwrap();
+    assert!(policy.external_network && policy.downloads);
+    assert!(!policy.nostr);
+}
+
+#[test]
+fn test_find_script() {
+    assert_eq!(
+        find_script("a \"quoted\" word", "previous").unwrap(),
+        r#"window.find("a \"quoted\" word", false, true, true, false, false, false)"#
+    );
+    assert!(find_script("</script>", "next")
+        .unwrap()
+        .starts_with("window.find(\"</script>\", false, false"));
+    assert!(find_script("", "next").unwrap().contains("removeAllRanges"));
+    assert!(find_script("x", "sideways").is_err());
+}
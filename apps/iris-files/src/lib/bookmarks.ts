@@ -0,0 +1,69 @@
+/**
+ * Bookmarks (Tauri only)
+ *
+ * Kept in a heed store next to history. Matching bookmarks are listed first
+ * in history search results.
+ */
+
+import { isTauri } from '../tauri';
+
+export interface Bookmark {
+  path: string;
+  label: string;
+  entry_type: string;
+  npub?: string;
+  tree_name?: string;
+  folder?: string;
+  tags: string[];
+  created_at: number;
+}
+
+export interface BookmarkSearchResult {
+  bookmark: Bookmark;
+  score: number;
+}
+
+/** Bookmark a route, or update its folder, tags and label */
+export async function addBookmark(
+  path: string,
+  label: string,
+  entryType: string,
+  options: { npub?: string; treeName?: string; folder?: string; tags?: string[] } = {}
+): Promise<Bookmark | null> {
+  if (!isTauri()) return null;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<Bookmark>('add_bookmark', {
+    path,
+    label,
+    entryType,
+    npub: options.npub ?? null,
+    treeName: options.treeName ?? null,
+    folder: options.folder ?? null,
+    tags: options.tags ?? null,
+  });
+}
+
+export async function removeBookmark(path: string): Promise<boolean> {
+  if (!isTauri()) return false;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<boolean>('remove_bookmark', { path });
+}
+
+/** Bookmarks, newest first, optionally only one folder or tag */
+export async function listBookmarks(folder?: string, tag?: string): Promise<Bookmark[]> {
+  if (!isTauri()) return [];
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<Bookmark[]>('list_bookmarks', { folder: folder ?? null, tag: tag ?? null });
+}
+
+export async function searchBookmarks(query: string, limit = 20): Promise<BookmarkSearchResult[]> {
+  if (!isTauri()) return [];
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<BookmarkSearchResult[]>('search_bookmarks', { query, limit });
+}
+
+/** Merge the bookmarks into our NIP-51 bookmark list as private items and publish it; returns the event id */
+export async function publishBookmarks(): Promise<string> {
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<string>('publish_bookmarks');
+}
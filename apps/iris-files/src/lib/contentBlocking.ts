@@ -0,0 +1,49 @@
+/**
+ * Ad and tracker blocking for external web pages (Tauri only)
+ *
+ * Requests of http(s) pages in app frames to blocklisted domains are refused
+ * when enabled; blocked requests are counted per site.
+ */
+
+import { isTauri } from '../tauri';
+
+export interface ContentBlockingStatus {
+  enabled: boolean;
+  /** Sites blocking is turned off for */
+  disabledOrigins: string[];
+  /** Number of blocked domains */
+  rules: number;
+  /** Requests blocked since start, by site origin */
+  blocked: Record<string, number>;
+}
+
+export async function getContentBlocking(): Promise<ContentBlockingStatus | null> {
+  if (!isTauri()) return null;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<ContentBlockingStatus>('get_content_blocking');
+}
+
+/** Turn blocking on or off everywhere, or for one site origin; applies to app frames opened afterwards */
+export async function setContentBlocking(enabled: boolean, origin?: string): Promise<void> {
+  if (!isTauri()) return;
+  const { invoke } = await import('@tauri-apps/api/core');
+  await invoke('set_content_blocking', { enabled, origin: origin ?? null });
+}
+
+/** Compile the rules again after blocklist.txt in the data dir changed; returns the number of blocked domains */
+export async function reloadBlocklist(): Promise<number> {
+  if (!isTauri()) return 0;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<number>('reload_blocklist');
+}
+
+/** Call `listener` with a site's blocked count whenever it grows; returns an unsubscribe function */
+export async function onContentBlocked(
+  listener: (origin: string, blocked: number) => void
+): Promise<() => void> {
+  if (!isTauri()) return () => {};
+  const { listen } = await import('@tauri-apps/api/event');
+  return listen<{ origin: string; blocked: number }>('content-blocked', (event) => {
+    listener(event.payload.origin, event.payload.blocked);
+  });
+}
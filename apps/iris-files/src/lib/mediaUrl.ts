@@ -6,15 +6,17 @@
  *
  * URL formats:
  * - Web:   /htree/{npub}/{treeName}/{path} or /htree/{nhash}/{filename}
- * - Tauri: http://127.0.0.1:21417/htree/{...} (same path structure, fixed port)
+ * - Tauri: http://127.0.0.1:21417/htree/{...} (same path structure; the
+ *   server's bind address and port are configurable, so the actual URL is
+ *   asked from the backend)
  */
 
 import { nhashEncode, type CID } from '@hashtree/core';
-import { isTauri } from '../tauri';
+import { hasTauriInvoke, isTauri } from '../tauri';
 import { getMediaClientId } from './mediaClient';
 import { logHtreeDebug } from './htreeDebug';
 
-/** Fixed port for Tauri htree server */
+/** Default port of the Tauri htree server */
 const TAURI_HTREE_PORT = 21417;
 const LOCAL_PROBE_TIMEOUT_MS = 500;
 const LOCAL_PROBE_INTERVAL_MS = 1000;
@@ -56,6 +58,17 @@ async function probeLocalHtreeServer(baseUrl: string): Promise<boolean> {
   return localProbePromise;
 }
 
+/** URL the Tauri htree server is actually bound to, once it's running */
+async function getBoundServerUrl(): Promise<string | null> {
+  if (!hasTauriInvoke()) return null;
+  try {
+    const { invoke } = await import('@tauri-apps/api/core');
+    return await invoke<string | null>('get_htree_server_url');
+  } catch {
+    return null;
+  }
+}
+
 function isLocalHtreePrefix(prefix: string): boolean {
   return LOCAL_HTREE_PREFIXES.has(prefix);
 }
@@ -100,7 +113,7 @@ function getHtreeServerOverride(): string | null {
 /**
  * Get the URL prefix based on runtime environment
  * - Web: "" (uses relative /htree paths, service worker intercepts)
- * - Tauri: http://127.0.0.1:21417 (fixed htree server URL)
+ * - Tauri: the htree server URL, http://127.0.0.1:21417 until the bound one is known
  */
 export function getHtreePrefix(): string {
   const override = getHtreeServerOverride();
@@ -205,7 +218,7 @@ export async function initHtreePrefix(): Promise<void> {
   const intervalMs = 100;
   const maxAttempts = Math.ceil(maxWaitMs / intervalMs);
   let lastProbeAt = 0;
-  const localBaseUrl = `http://127.0.0.1:${TAURI_HTREE_PORT}`;
+  const defaultBaseUrl = `http://127.0.0.1:${TAURI_HTREE_PORT}`;
   for (let i = 0; i < maxAttempts; i++) {
     await new Promise(resolve => setTimeout(resolve, intervalMs));
     const nextPrefix = getHtreePrefix();
@@ -216,6 +229,7 @@ export async function initHtreePrefix(): Promise<void> {
     const now = Date.now();
     if (now - lastProbeAt >= LOCAL_PROBE_INTERVAL_MS) {
       lastProbeAt = now;
+      const localBaseUrl = (await getBoundServerUrl()) ?? defaultBaseUrl;
       const reachable = await probeLocalHtreeServer(localBaseUrl);
       if (reachable) {
         updateCachedPrefix(localBaseUrl, 'local-probe');
@@ -0,0 +1,118 @@
+/**
+ * Pairing of trusted devices over the LAN (Tauri only)
+ *
+ * One device shows a pairing URL as a QR code; the other scans it and
+ * trades the one-time code in it for a device token. Paired devices can
+ * read (or also write) through the local server as their permission allows.
+ * Pairing runs over HTTPS only, so the showing device needs LAN access with
+ * TLS on.
+ */
+
+import { isTauri } from '../tauri';
+
+export type DevicePermission = 'read' | 'readWrite';
+
+export interface PairingOffer {
+  /** Pairing URL, holding the code */
+  url: string;
+  code: string;
+  permission: DevicePermission;
+  /** Unix seconds */
+  expiresAt: number;
+  /** The URL as an SVG QR code */
+  qrSvg: string;
+}
+
+/** A device paired with this one */
+export interface PairedDevice {
+  id: string;
+  name: string;
+  permission: DevicePermission;
+  pairedAt: number;
+}
+
+/** A device this one paired with */
+export interface RemoteDevice {
+  id: string;
+  url: string;
+  permission: DevicePermission;
+  fingerprint: string | null;
+  pairedAt: number;
+}
+
+/** LAN access rules of the local server (`acl.json`) */
+export interface LanAccessRules {
+  lan: boolean;
+  bind?: string | null;
+  port?: number | null;
+  tls: boolean;
+  tlsPort?: number | null;
+  allow: string[];
+  token?: string | null;
+  defaultExposure: 'public' | 'private';
+  trees: Record<string, 'public' | 'private'>;
+}
+
+export async function getLanAccessRules(): Promise<LanAccessRules | null> {
+  if (!isTauri()) return null;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<LanAccessRules>('get_acl_rules');
+}
+
+/** Save the rules; a change of lan, bind, port or tls applies after a restart */
+export async function setLanAccessRules(rules: LanAccessRules): Promise<void> {
+  if (!isTauri()) return;
+  const { invoke } = await import('@tauri-apps/api/core');
+  await invoke('set_acl_rules', { rules });
+}
+
+/** Make a pairing code for another device; needs LAN access over HTTPS */
+export async function startPairing(permission: DevicePermission): Promise<PairingOffer | null> {
+  if (!isTauri()) return null;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<PairingOffer>('start_pairing', { permission });
+}
+
+/** Pair with the device that showed `url`, introducing this one as `name` */
+export async function pairWithDevice(url: string, name: string): Promise<RemoteDevice | null> {
+  if (!isTauri()) return null;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<RemoteDevice>('pair_with_device', { url, name });
+}
+
+export async function listPairedDevices(): Promise<{
+  devices: PairedDevice[];
+  remotes: RemoteDevice[];
+}> {
+  if (!isTauri()) return { devices: [], remotes: [] };
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke('list_paired_devices');
+}
+
+export async function setDevicePermission(id: string, permission: DevicePermission): Promise<void> {
+  if (!isTauri()) return;
+  const { invoke } = await import('@tauri-apps/api/core');
+  await invoke('set_device_permission', { id, permission });
+}
+
+/** Forget a paired device, or a device this one paired with */
+export async function unpairDevice(id: string): Promise<void> {
+  if (!isTauri()) return;
+  const { invoke } = await import('@tauri-apps/api/core');
+  await invoke('unpair_device', { id });
+}
+
+/**
+ * Send a worker request to a device this one paired with, using its storage
+ * and bandwidth, and return its response. Only `readWrite` pairings may
+ * browse, write, publish and push trees this way; `read` ones get the
+ * public trees over HTTP instead.
+ */
+export async function remoteRequest<T = unknown>(
+  deviceId: string,
+  request: { type: string; id: string; [key: string]: unknown }
+): Promise<T> {
+  if (!isTauri()) throw new Error('Remote devices need the native app');
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<T>('remote_request', { deviceId, request });
+}
@@ -26,17 +26,34 @@ interface TauriHistoryEntry {
 interface TauriHistorySearchResult {
   entry: TauriHistoryEntry;
   score: number;
+  /** Matched a bookmark; these come first */
+  bookmarked?: boolean;
 }
 
-/** Record a history visit (Tauri only) */
+/** Visits to one tree, profile or hash, from the Tauri backend */
+export interface TopSite {
+  path: string;
+  label: string;
+  npub?: string;
+  tree_name?: string;
+  visit_count: number;
+  last_visited: number;
+  frecency: number;
+}
+
+/**
+ * Record a history visit (Tauri only). Private visits, and visits in a
+ * webview opened with `private: true`, aren't recorded.
+ */
 export async function recordHistoryVisit(
   path: string,
   label: string,
   entryType: string,
   npub?: string,
-  treeName?: string
+  treeName?: string,
+  options: { webviewLabel?: string; private?: boolean } = {}
 ): Promise<void> {
-  if (!isTauri()) return;
+  if (!isTauri() || options.private) return;
 
   try {
     const { invoke } = await import('@tauri-apps/api/core');
@@ -46,6 +63,8 @@ export async function recordHistoryVisit(
       entryType,
       npub: npub ?? null,
       treeName: treeName ?? null,
+      webviewLabel: options.webviewLabel ?? null,
+      private: options.private ?? null,
     });
   } catch (e) {
     console.warn('[history] Failed to record visit:', e);
@@ -65,10 +84,11 @@ async function searchHistoryTauri(query: string, limit: number): Promise<SearchR
       id: `history:${r.entry.path}`,
       type: mapEntryType(r.entry.entry_type),
       label: r.entry.label,
-      sublabel: formatTimeAgo(r.entry.last_visited),
+      sublabel: r.bookmarked ? 'Bookmarked' : formatTimeAgo(r.entry.last_visited),
       path: r.entry.path,
-      score: normalizeScore(r.score),
-      icon: getIconForType(r.entry.entry_type),
+      // Bookmarks rank above everything else that matches
+      score: r.bookmarked ? 1 : normalizeScore(r.score),
+      icon: r.bookmarked ? 'i-lucide-bookmark' : getIconForType(r.entry.entry_type),
       timestamp: r.entry.last_visited,
     }));
   } catch (e) {
@@ -228,3 +248,50 @@ export async function getRecentHistory(limit: number): Promise<SearchResult[]> {
       timestamp: r.timestamp,
     }));
 }
+
+/** Most frecent trees, profiles and hashes, for a new-tab page (Tauri only) */
+export async function getTopSites(limit: number): Promise<TopSite[]> {
+  if (!isTauri()) return [];
+  try {
+    const { invoke } = await import('@tauri-apps/api/core');
+    return await invoke<TopSite[]>('get_top_sites', { limit });
+  } catch (e) {
+    console.warn('[history] Failed to get top sites:', e);
+    return [];
+  }
+}
+
+/** Delete one history entry (Tauri only) */
+export async function deleteHistoryEntry(path: string): Promise<boolean> {
+  if (!isTauri()) return false;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<boolean>('delete_history_entry', { path });
+}
+
+/** Delete entries last visited between two timestamps in ms; returns the count (Tauri only) */
+export async function deleteHistoryRange(fromTs: number, toTs: number): Promise<number> {
+  if (!isTauri()) return 0;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<number>('delete_history_range', { fromTs, toTs });
+}
+
+/** Delete all history; returns the count (Tauri only) */
+export async function clearHistory(): Promise<number> {
+  if (!isTauri()) return 0;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<number>('clear_history');
+}
+
+/** Days history is kept after the last visit, null for no limit (Tauri only) */
+export async function getHistoryRetention(): Promise<number | null> {
+  if (!isTauri()) return null;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<number | null>('get_history_retention');
+}
+
+/** Set the retention window in days, null for no limit; returns how many entries expired */
+export async function setHistoryRetention(days: number | null): Promise<number> {
+  if (!isTauri()) return 0;
+  const { invoke } = await import('@tauri-apps/api/core');
+  return invoke<number>('set_history_retention', { days });
+}
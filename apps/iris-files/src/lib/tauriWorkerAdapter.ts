@@ -17,6 +17,7 @@ import { invoke } from '@tauri-apps/api/core';
 import { listen, type UnlistenFn } from '@tauri-apps/api/event';
 import { hexEncode, hexDecode, base64Encode, base64Decode } from '../utils/encoding';
 import { LRUCache } from '../utils/lruCache';
+import { openFile, saveFile } from '../tauri';
 import type {
   WorkerConfig,
   WorkerNostrFilter as NostrFilter,
@@ -49,6 +50,8 @@ interface WorkerResponse {
   distance?: number | null;
   users?: Array<[string, number]>;
   event?: unknown;
+  /** Event author is beyond the WoT policy's flag distance */
+  untrusted?: boolean;
 }
 
 interface PendingRequest {
@@ -62,6 +65,205 @@ const BLOB_CACHE_MAX_BYTES = 256 * 1024;
 const DIR_CACHE_SIZE = 200;
 const FILE_CACHE_SIZE = 100;
 const FILE_CACHE_MAX_BYTES = 128 * 1024;
+// Must match BLOB_NOT_FOUND in src-tauri/src/worker/mod.rs
+const BLOB_NOT_FOUND = 'Blob not found';
+
+/** Progress of a bulk backend operation, keyed by the request that started it */
+export interface OperationProgress {
+  opId: string;
+  phase: string;
+  bytesDone: number;
+  bytesTotal: number | null;
+  itemsDone: number;
+  itemsTotal: number | null;
+}
+
+/** A queued or running backend request */
+export interface BackendJob {
+  id: string;
+  kind: string;
+  priority: 'interactive' | 'metadata' | 'background';
+  running: boolean;
+  ageMs: number;
+}
+
+/** Aggregate background sync state */
+export interface SyncStatus {
+  paused: boolean;
+  peers: number;
+  relays: number;
+  relaysTotal: number;
+  /** Push and republish requests running */
+  pushing: number;
+  /** Push and republish requests waiting for a slot */
+  queued: number;
+}
+
+/** Which backend events show an OS notification */
+export interface NotifyRules {
+  enabled: boolean;
+  /** Trees that mention us, from within maxDistance follows */
+  mentions: boolean;
+  maxDistance: number;
+  /** Trees shared with us */
+  shares: boolean;
+  syncCompleted: boolean;
+  syncFailed: boolean;
+}
+
+/** Social-graph limits on prefetching, serving peers and trusting authors */
+export interface WotPolicy {
+  enabled: boolean;
+  /** Follow distance of owners whose trees are prefetched; 1 is follows */
+  prefetchMaxDistance: number;
+  /** Follow distance of WebRTC peers we connect to */
+  serveMaxDistance: number;
+  /** Flag events and trees of authors beyond flagMaxDistance */
+  flagUnknown: boolean;
+  flagMaxDistance: number;
+  /** Serve HTML from trees of owners beyond sandboxMaxDistance with a restrictive CSP */
+  sandboxHtml: boolean;
+  sandboxMaxDistance: number;
+}
+
+/** A tree root published by someone we follow */
+export interface ActivityEntry {
+  eventId: string;
+  author: string;
+  treeName: string;
+  hash: string;
+  key: string | null;
+  createdAt: number;
+  /** Top-level entries changed since the tree's previous root, if both were cached */
+  diff: { added: number; removed: number; changed: number } | null;
+}
+
+/** NIP-13 proof of work mined into published events of ours */
+export interface PowPolicy {
+  enabled: boolean;
+  /** Leading zero bits for kinds not in `kinds`; 0 mines nothing */
+  defaultDifficulty: number;
+  /** Difficulty by kind */
+  kinds: Record<number, number>;
+  /** Mining longer than this fails the publish */
+  maxSeconds: number;
+  /** Mining threads; 0 uses all cores but one */
+  threads: number;
+}
+
+/** One of a user's trees, as listTrees reports it */
+export interface TreeListing {
+  name: string;
+  /** Root; without a key when it can't be recovered */
+  cid: CID;
+  visibility: 'public' | 'link-visible' | 'private';
+  /** Unix seconds the root was published */
+  updatedAt: number;
+  eventId: string;
+  /** Someone else's private tree, unreadable without a share */
+  locked: boolean;
+}
+
+/** Size and shape of a tree, as treeStats reports it */
+export interface TreeStats {
+  /** Sum of file sizes */
+  totalSize: number;
+  /** Distinct blocks, nodes and leaves */
+  blocks: number;
+  files: number;
+  directories: number;
+  /** Path components of the deepest entry */
+  depth: number;
+  /** Largest files, largest first */
+  largest: { path: string; size: number }[];
+  /** Every directory with its totals; the root's path is '' */
+  dirs: { path: string; size: number; files: number }[];
+}
+
+/** Files of identical content, as findDuplicates reports them */
+export interface DuplicateGroup {
+  hash: string;
+  size: number;
+  /** tree is set when searching all of our trees */
+  copies: { tree?: string; path: string }[];
+  savings: number;
+}
+
+/** A collaborator's proposed root for one of our trees */
+export interface TreeProposal {
+  eventId: string;
+  /** Hex pubkey of the collaborator */
+  author: string;
+  treeName: string;
+  hash: string;
+  key?: string;
+  /** Hash of the root the change was made on */
+  base?: string;
+  message?: string;
+  createdAt: number;
+}
+
+/** A path that differs between two versions of a tree */
+export interface TreeChange {
+  path: string;
+  kind: 'added' | 'removed' | 'modified';
+  /** Size after the change, before it for removals */
+  size: number;
+}
+
+/** Proxy of relays or Blossom in place of the global one */
+export type ProxyOverride = { mode: 'direct' } | { mode: 'socks5'; address: string };
+
+/** SOCKS5 proxies, as `host:port`, network traffic goes through */
+export interface ProxyConfig {
+  socks5?: string;
+  relays?: ProxyOverride;
+  blossom?: ProxyOverride;
+}
+
+/** Connection health of a relay, as getRelayStats reports it */
+export interface RelayHealth {
+  /** 0-100, from connection failures, EOSE latency and notices */
+  score: number;
+  /** Failing persistently, only retried every half hour */
+  demoted: boolean;
+  /** Failed connection attempts in a row */
+  failures: number;
+  nextRetrySecs: number | null;
+  /** Last failed attempt, notice, CLOSED or rejected event */
+  lastError: string | null;
+  messagesPerSec: number;
+  eoseLatencyMs: number | null;
+  notices: number;
+  bytesSent: number;
+  bytesReceived: number;
+}
+
+/** A published event in the outbox and how its delivery went */
+export interface OutboxEntry {
+  eventId: string;
+  kind: number;
+  event: SignedEvent;
+  queuedAt: number;
+  attempts: number;
+  lastAttemptAt: number | null;
+  /** When it's sent next; null once delivered or given up */
+  nextAttemptAt: number | null;
+  /** Accepted by at least one relay */
+  delivered: boolean;
+  /** Relays that accepted it */
+  accepted: string[];
+  /** Relays that didn't, with their reason */
+  rejected: Record<string, string>;
+  lastError: string | null;
+}
+
+/** Which cached events purgeNdb removes */
+export interface NdbPurgeRule {
+  /** Unix seconds; replaceable events are kept unless `kinds` names them */
+  olderThan?: number;
+  kinds?: number[];
+}
 
 // Subscription callback handlers
 interface SubscriptionCallbacks {
@@ -87,6 +289,7 @@ export class TauriWorkerAdapter {
 
   // Streaming callbacks
   private streamCallbacks = new Map<string, (chunk: Uint8Array, done: boolean) => void>();
+  private distanceLevelCallbacks = new Map<string, (distance: number, users: string[]) => void>();
 
   // Social graph version callback
   private socialGraphVersionCallback: ((version: number) => void) | null = null;
@@ -96,6 +299,9 @@ export class TauriWorkerAdapter {
   private blossomPushProgressCallback: ((treeName: string, current: number, total: number) => void) | null = null;
   private blossomPushCompleteCallback: ((treeName: string, pushed: number, skipped: number, failed: number) => void) | null = null;
 
+  // Bulk operation progress callback
+  private progressCallback: ((progress: OperationProgress) => void) | null = null;
+
   async init(config: WorkerConfig): Promise<void> {
     if (this.ready) return;
 
@@ -167,11 +373,15 @@ export class TauriWorkerAdapter {
     // Handle subscription events (no request id)
     if (response.type === 'event' && response.subId) {
       const callbacks = this.subscriptions.get(response.subId);
+      // Untrusted events carry the flag along, for the UI to blur them
+      const event = (
+        response.untrusted ? { ...(response.event as object), untrusted: true } : response.event
+      ) as SignedEvent;
       if (callbacks?.onEvent && response.event) {
-        callbacks.onEvent(response.event as SignedEvent);
+        callbacks.onEvent(event);
       }
       if (this.globalEventCallback && response.event) {
-        this.globalEventCallback(response.event as SignedEvent);
+        this.globalEventCallback(event);
       }
       return;
     }
@@ -202,6 +412,13 @@ export class TauriWorkerAdapter {
       return;
     }
 
+    // Handle social graph levels as a walk completes them
+    if (response.type === 'usersAtDistance' && response.id) {
+      const payload = response as { distance?: number; users?: string[] };
+      this.distanceLevelCallbacks.get(response.id)?.(payload.distance ?? 0, payload.users ?? []);
+      return;
+    }
+
     // Handle push progress
     if (response.type === 'pushProgress') {
       const payload = response as { treeName?: string; current?: number; total?: number };
@@ -211,6 +428,12 @@ export class TauriWorkerAdapter {
       return;
     }
 
+    // Handle bulk operation progress
+    if (response.type === 'progress') {
+      this.progressCallback?.(response as unknown as OperationProgress);
+      return;
+    }
+
     // Handle social graph version
     if (response.type === 'socialGraphVersion') {
       const version = (response as { version?: number }).version ?? 0;
@@ -260,6 +483,24 @@ export class TauriWorkerAdapter {
     });
   }
 
+  /**
+   * Blob reads over the binary IPC channel: the bytes arrive as an
+   * ArrayBuffer instead of base64 in a worker_response event.
+   */
+  private async requestBlob(request: {
+    type: 'get' | 'readFile' | 'readFileRange';
+    [key: string]: unknown;
+  }): Promise<Uint8Array | null> {
+    this.trackRequest(request.type);
+    try {
+      const data = await invoke<ArrayBuffer>('worker_blob', { request });
+      return new Uint8Array(data);
+    } catch (err) {
+      if (String(err) === BLOB_NOT_FOUND) return null;
+      throw new Error(String(err));
+    }
+  }
+
   // ============================================================================
   // Phase 1: Store Operations
   // ============================================================================
@@ -277,11 +518,7 @@ export class TauriWorkerAdapter {
       return data ? this.cloneFileData(data) : null;
     }
 
-    const fetchPromise = this.request<WorkerResponse>({
-      type: 'get',
-      id: this.nextId(),
-      hash: cacheKey,
-    }).then((res) => (res.data ? base64Decode(res.data) : null));
+    const fetchPromise = this.requestBlob({ type: 'get', hash: cacheKey });
 
     this.blobInFlight.set(cacheKey, fetchPromise);
 
@@ -320,6 +557,58 @@ export class TauriWorkerAdapter {
     return res.value ?? false;
   }
 
+  /** Fetch many blobs in one round trip; results follow the order of `hashes` */
+  async getMany(hashes: Uint8Array[]): Promise<Array<Uint8Array | null>> {
+    const keys = hashes.map(hexEncode);
+    const results: Array<Uint8Array | null> = keys.map((key) => {
+      const cached = this.blobCache.get(key);
+      return cached ? this.cloneFileData(cached) : null;
+    });
+    const missing = keys.map((_, i) => i).filter((i) => !results[i]);
+    if (missing.length === 0) return results;
+
+    const res = await this.request<{ data: Array<string | null> }>({
+      type: 'getMany',
+      id: this.nextId(),
+      hashes: missing.map((i) => keys[i]),
+    });
+    missing.forEach((i, j) => {
+      const encoded = res.data[j];
+      if (!encoded) return;
+      const data = base64Decode(encoded);
+      if (data.length <= BLOB_CACHE_MAX_BYTES) {
+        this.blobCache.set(keys[i], data.slice());
+      }
+      results[i] = data;
+    });
+    return results;
+  }
+
+  /** Store many blobs in one round trip; returns whether each was newly stored */
+  async putMany(items: Array<{ hash: Uint8Array; data: Uint8Array }>): Promise<boolean[]> {
+    const res = await this.request<{ values: boolean[] }>({
+      type: 'putMany',
+      id: this.nextId(),
+      items: items.map(({ hash, data }) => ({ hash: hexEncode(hash), data: base64Encode(data) })),
+    });
+    for (const { hash, data } of items) {
+      if (data.length <= BLOB_CACHE_MAX_BYTES) {
+        this.blobCache.set(hexEncode(hash), data.slice());
+      }
+    }
+    return res.values;
+  }
+
+  /** Check which of many blobs exist in one round trip */
+  async hasMany(hashes: Uint8Array[]): Promise<boolean[]> {
+    const res = await this.request<{ values: boolean[] }>({
+      type: 'hasMany',
+      id: this.nextId(),
+      hashes: hashes.map(hexEncode),
+    });
+    return res.values;
+  }
+
   async delete(hash: Uint8Array): Promise<boolean> {
     const cacheKey = hexEncode(hash);
     const res = await this.request<WorkerResponse>({
@@ -363,11 +652,7 @@ export class TauriWorkerAdapter {
       return data ? this.cloneFileData(data) : null;
     }
 
-    const fetchPromise = this.request<WorkerResponse>({
-      type: 'readFile',
-      id: this.nextId(),
-      cid: this.cidToRust(cid),
-    }).then((res) => (res.data ? base64Decode(res.data) : null));
+    const fetchPromise = this.requestBlob({ type: 'readFile', cid: this.cidToRust(cid) });
 
     this.fileInFlight.set(cacheKey, fetchPromise);
 
@@ -384,14 +669,12 @@ export class TauriWorkerAdapter {
   }
 
   async readFileRange(cid: CID, start: number, end?: number): Promise<Uint8Array | null> {
-    const res = await this.request<WorkerResponse>({
+    return this.requestBlob({
       type: 'readFileRange',
-      id: this.nextId(),
       cid: this.cidToRust(cid),
       start,
       end: end ?? null,
     });
-    return res.data ? base64Decode(res.data) : null;
   }
 
   async *readFileStream(cid: CID): AsyncGenerator<Uint8Array> {
@@ -454,6 +737,27 @@ export class TauriWorkerAdapter {
     return this.rustToCid(res.cid);
   }
 
+  /**
+   * Import files and directories dropped on the window from their native
+   * paths into `dirPath` of `parentCid` (a new directory when null). The
+   * backend reads them from disk, so nothing is copied through JS.
+   * Progress is reported through onProgress like other bulk operations.
+   */
+  async importDroppedFiles(paths: string[], parentCid: CID | null, dirPath = ''): Promise<CID> {
+    const id = this.nextId();
+    try {
+      const cid = await invoke<{ hash: string; key?: string }>('import_dropped_files', {
+        id,
+        paths,
+        parentCid: parentCid ? this.cidToRust(parentCid) : null,
+        dirPath,
+      });
+      return this.rustToCid(cid);
+    } catch (err) {
+      throw new Error(String(err));
+    }
+  }
+
   async deleteFile(parentCid: CID, path: string): Promise<CID> {
     const res = await this.request<WorkerResponse>({
       type: 'deleteFile',
@@ -508,6 +812,65 @@ export class TauriWorkerAdapter {
     }
   }
 
+  /** Size, block and file counts of a tree, without reading file contents */
+  async treeStats(cid: CID, largest?: number): Promise<TreeStats> {
+    const res = await this.request<WorkerResponse & { stats: TreeStats }>({
+      type: 'treeStats',
+      id: this.nextId(),
+      cid: this.cidToRust(cid),
+      largest,
+    });
+    return res.stats;
+  }
+
+  /**
+   * Files with identical content within the tree at root, or across all of
+   * our trees without one, most savings first. Savings are bytes the trees
+   * would shrink by keeping one copy; the content is stored once either way.
+   */
+  async findDuplicates(root?: CID): Promise<{ groups: DuplicateGroup[]; savings: number }> {
+    const res = await this.request<WorkerResponse & { groups: DuplicateGroup[]; savings: number }>({
+      type: 'findDuplicates',
+      id: this.nextId(),
+      root: root ? this.cidToRust(root) : null,
+    });
+    return { groups: res.groups, savings: res.savings };
+  }
+
+  /**
+   * Append entries to an append-only log, or start one when logCid is null.
+   * Only the log's last segment is rewritten, however long it is.
+   */
+  async appendLog(
+    logCid: CID | null,
+    entries: Uint8Array[],
+    encrypted = false
+  ): Promise<{ cid: CID; length: number }> {
+    const res = await this.request<WorkerResponse & { length: number }>({
+      type: 'appendLog',
+      id: this.nextId(),
+      cid: logCid ? this.cidToRust(logCid) : null,
+      entries: entries.map(base64Encode),
+      encrypted,
+    });
+    if (!res.cid) {
+      throw new Error('appendLog returned no CID');
+    }
+    return { cid: this.rustToCid(res.cid), length: res.length };
+  }
+
+  /** Entries start..end of a log (to its end when end is omitted) and its length */
+  async readLog(logCid: CID, start = 0, end?: number): Promise<{ entries: Uint8Array[]; length: number }> {
+    const res = await this.request<Omit<WorkerResponse, 'entries'> & { entries: string[]; length: number }>({
+      type: 'readLog',
+      id: this.nextId(),
+      cid: this.cidToRust(logCid),
+      start,
+      end,
+    });
+    return { entries: res.entries.map(base64Decode), length: res.length };
+  }
+
   async resolveRoot(npub: string, path?: string): Promise<CID | null> {
     const res = await this.request<WorkerResponse>({
       type: 'resolveRoot',
@@ -524,6 +887,38 @@ export class TauriWorkerAdapter {
     };
   }
 
+  /**
+   * All of a user's trees (npub or hex pubkey), with the newest root of
+   * each. Keys of others' private and link-visible trees are left out.
+   */
+  async listTrees(pubkey: string): Promise<TreeListing[]> {
+    const res = await this.request<
+      WorkerResponse & {
+        trees?: Array<{
+          name: string;
+          hash: string;
+          key?: string | null;
+          visibility: 'public' | 'link-visible' | 'private';
+          updatedAt: number;
+          eventId: string;
+          locked: boolean;
+        }>;
+      }
+    >({
+      type: 'listTrees',
+      id: this.nextId(),
+      pubkey,
+    });
+    return (res.trees ?? []).map((tree) => ({
+      name: tree.name,
+      cid: this.rustToCid({ hash: tree.hash, key: tree.key ?? undefined }),
+      visibility: tree.visibility,
+      updatedAt: tree.updatedAt,
+      eventId: tree.eventId,
+      locked: tree.locked,
+    }));
+  }
+
   // ============================================================================
   // Phase 3: Nostr Operations
   // ============================================================================
@@ -532,10 +927,15 @@ export class TauriWorkerAdapter {
     this.globalEventCallback = callback;
   }
 
+  /**
+   * Subscribe to events. With `relays`, e.g. an author's NIP-65 write
+   * relays, the query goes only to those instead of every configured relay.
+   */
   subscribe(
     filters: NostrFilter[],
     callback?: (event: SignedEvent) => void,
-    eose?: () => void
+    eose?: () => void,
+    relays?: string[]
   ): string {
     const subId = this.nextId();
 
@@ -550,6 +950,7 @@ export class TauriWorkerAdapter {
       type: 'subscribe',
       id: subId,
       filters: filters,
+      relays,
     }).catch((err) => {
       console.error('[TauriWorkerAdapter] Subscribe error:', err);
       this.subscriptions.delete(subId);
@@ -579,6 +980,50 @@ export class TauriWorkerAdapter {
     });
   }
 
+  /** Stream NIP-22 comments on a path of a tree; stop with unsubscribe(subId) */
+  subscribeComments(
+    npub: string,
+    treeName: string,
+    path: string,
+    callback: (event: SignedEvent) => void
+  ): string {
+    const subId = this.nextId();
+    this.subscriptions.set(subId, { onEvent: callback });
+
+    this.request<WorkerResponse>({
+      type: 'subscribeComments',
+      id: subId,
+      npub,
+      treeName,
+      path,
+    }).catch((err) => {
+      console.error('[TauriWorkerAdapter] Comment subscribe error:', err);
+      this.subscriptions.delete(subId);
+    });
+
+    return subId;
+  }
+
+  /** Comment on a path of a tree, or reply to one of its comments; returns the event id */
+  async postComment(
+    npub: string,
+    treeName: string,
+    path: string,
+    content: string,
+    replyTo?: { id: string; pubkey: string }
+  ): Promise<string> {
+    const res = await this.request<WorkerResponse>({
+      type: 'postComment',
+      id: this.nextId(),
+      npub,
+      treeName,
+      path,
+      content,
+      replyTo,
+    });
+    return res.data ?? '';
+  }
+
   // ============================================================================
   // Phase 4: Social Graph Operations
   // ============================================================================
@@ -592,6 +1037,56 @@ export class TauriWorkerAdapter {
     });
   }
 
+  /** Identities set with a secret key, in this or earlier sessions */
+  async listAccounts(): Promise<
+    Array<{
+      pubkey: string;
+      active: boolean;
+      canSign: boolean;
+      readServers: string[];
+      writeServers: string[];
+      trees: string[];
+    }>
+  > {
+    const res = await this.request<
+      WorkerResponse & {
+        accounts?: Array<{
+          pubkey: string;
+          active: boolean;
+          canSign: boolean;
+          readServers: string[];
+          writeServers: string[];
+          trees: string[];
+        }>;
+      }
+    >({
+      type: 'listAccounts',
+      id: this.nextId(),
+    });
+    return res.accounts ?? [];
+  }
+
+  async switchAccount(pubkey: string): Promise<void> {
+    await this.request<WorkerResponse>({
+      type: 'switchAccount',
+      id: this.nextId(),
+      pubkey,
+    });
+  }
+
+  /**
+   * Toggle guest mode: ephemeral keys, and blobs and history that are wiped
+   * when it's turned off or the app exits. Returns the guest pubkey while on.
+   */
+  async setGuestMode(enabled: boolean): Promise<{ enabled: boolean; pubkey?: string }> {
+    const res = await this.request<WorkerResponse & { enabled?: boolean; pubkey?: string | null }>({
+      type: 'setGuestMode',
+      id: this.nextId(),
+      enabled,
+    });
+    return { enabled: res.enabled ?? enabled, pubkey: res.pubkey ?? undefined };
+  }
+
   onSocialGraphVersion(callback: (version: number) => void): void {
     this.socialGraphVersionCallback = callback;
   }
@@ -651,6 +1146,46 @@ export class TauriWorkerAdapter {
     return res.pubkeys ?? [];
   }
 
+  /** Tree updates from follows, newest first; pass the last createdAt as `before` for the next page */
+  async getActivityFeed(before?: number, limit?: number): Promise<{ entries: ActivityEntry[]; hasMore: boolean }> {
+    const res = await this.request<Omit<WorkerResponse, 'entries'> & { entries?: ActivityEntry[]; hasMore?: boolean }>({
+      type: 'getActivityFeed',
+      id: this.nextId(),
+      before,
+      limit,
+    });
+    return { entries: res.entries ?? [], hasMore: res.hasMore ?? false };
+  }
+
+  /** Profile metadata (kind 0 content) of a pubkey (hex or npub), fetched from relays if not cached */
+  async getProfile(pubkey: string): Promise<Record<string, unknown> | null> {
+    const res = await this.request<WorkerResponse & { profile?: Record<string, unknown> | null }>({
+      type: 'getProfile',
+      id: this.nextId(),
+      pubkey,
+    });
+    return res.profile ?? null;
+  }
+
+  /** Follow a pubkey (hex or npub), returning who we follow afterwards */
+  async follow(pubkey: string): Promise<string[]> {
+    const res = await this.request<WorkerResponse>({
+      type: 'follow',
+      id: this.nextId(),
+      pubkey,
+    });
+    return res.pubkeys ?? [];
+  }
+
+  async unfollow(pubkey: string): Promise<string[]> {
+    const res = await this.request<WorkerResponse>({
+      type: 'unfollow',
+      id: this.nextId(),
+      pubkey,
+    });
+    return res.pubkeys ?? [];
+  }
+
   async getFollowers(pubkey: string): Promise<string[]> {
     const res = await this.request<WorkerResponse>({
       type: 'getFollowers',
@@ -694,15 +1229,70 @@ export class TauriWorkerAdapter {
   }
 
   async getUsersByDistance(distance: number): Promise<string[]> {
+    const users = await this.getUsersWithinDistance(distance);
+    // Filter to exact distance
+    return users.filter(([_, d]) => d === distance).map(([pubkey]) => pubkey);
+  }
+
+  /**
+   * Users within maxDistance follows of us. onLevel gets each distance's
+   * users as the walk completes it, before the whole result resolves.
+   */
+  async getUsersWithinDistance(
+    maxDistance: number,
+    onLevel?: (distance: number, users: string[]) => void
+  ): Promise<Array<[string, number]>> {
+    const id = this.nextId();
+    if (onLevel) {
+      this.distanceLevelCallbacks.set(id, onLevel);
+    }
+    try {
+      const res = await this.request<WorkerResponse>({
+        type: 'getUsersWithinDistance',
+        id,
+        maxDistance,
+      });
+      return res.users ?? [];
+    } finally {
+      this.distanceLevelCallbacks.delete(id);
+    }
+  }
+
+  /** Pubkeys (hex) on our mute list */
+  async getMutes(): Promise<string[]> {
+    const res = await this.request<WorkerResponse & { pubkeys?: string[] }>({
+      type: 'getMutes',
+      id: this.nextId(),
+    });
+    return res.pubkeys ?? [];
+  }
+
+  /** Mute or unmute a pubkey (hex or npub) locally; publishMutes shares it */
+  async setMuted(pubkey: string, muted: boolean): Promise<string[]> {
+    const res = await this.request<WorkerResponse & { pubkeys?: string[] }>({
+      type: muted ? 'mutePubkey' : 'unmutePubkey',
+      id: this.nextId(),
+      pubkey,
+    });
+    return res.pubkeys ?? [];
+  }
+
+  /** Take over our mute list from the relays if it's newer than the local one */
+  async fetchMutes(): Promise<string[]> {
+    const res = await this.request<WorkerResponse & { pubkeys?: string[] }>({
+      type: 'fetchMutes',
+      id: this.nextId(),
+    });
+    return res.pubkeys ?? [];
+  }
+
+  /** Publish our mute list, returning the event id */
+  async publishMutes(): Promise<string | null> {
     const res = await this.request<WorkerResponse>({
-      type: 'getUsersWithinDistance',
+      type: 'publishMutes',
       id: this.nextId(),
-      maxDistance: distance,
     });
-    // Filter to exact distance
-    return (res.users ?? [])
-      .filter(([_, d]) => d === distance)
-      .map(([pubkey]) => pubkey);
+    return res.data ?? null;
   }
 
   // ============================================================================
@@ -717,6 +1307,11 @@ export class TauriWorkerAdapter {
     this.blossomPushProgressCallback = callback;
   }
 
+  /** Byte- and item-level progress of pushes, republishes, indexing, downloads, imports, exports and eviction */
+  onProgress(callback: (progress: OperationProgress) => void): void {
+    this.progressCallback = callback;
+  }
+
   onBlossomPushComplete(
     callback: (treeName: string, pushed: number, skipped: number, failed: number) => void
   ): void {
@@ -791,6 +1386,42 @@ export class TauriWorkerAdapter {
     };
   }
 
+  /**
+   * Build, sign, store and publish the root event of one of our trees.
+   * Link-visible trees get a fresh link secret unless one is given.
+   */
+  async publishTree(
+    treeName: string,
+    cid: CID,
+    visibility: 'public' | 'link-visible' | 'private' = 'public',
+    linkSecret?: string,
+    sign = false
+  ): Promise<{ cid: CID; eventId: string; linkSecret?: string }> {
+    const res = await this.request<
+      WorkerResponse & {
+        cid?: { hash: string; key?: string };
+        eventId?: string;
+        linkSecret?: string;
+      }
+    >({
+      type: 'publishTree',
+      id: this.nextId(),
+      treeName,
+      cid: this.cidToRust(cid),
+      visibility,
+      linkSecret,
+      sign,
+    });
+    if (!res.cid) {
+      throw new Error('publishTree returned no CID');
+    }
+    return {
+      cid: this.rustToCid(res.cid),
+      eventId: res.eventId ?? '',
+      linkSecret: res.linkSecret ?? undefined,
+    };
+  }
+
   async republishTrees(prefix?: string): Promise<{ count: number; encryptionErrors?: string[] }> {
     const res = await this.request<{ count?: number; encryptionErrors?: string[] }>({
       type: 'republishTrees',
@@ -803,6 +1434,77 @@ export class TauriWorkerAdapter {
     };
   }
 
+  /**
+   * Unpublish one of our trees (tombstone plus NIP-09 deletion) and stop
+   * keeping its blocks. With deleteBlobs, blocks no other tree of ours
+   * uses are deleted from the Blossom write servers too.
+   */
+  async deleteTree(
+    treeName: string,
+    deleteBlobs = false
+  ): Promise<{ eventId: string; released: number; blobsDeleted: number }> {
+    const res = await this.request<
+      WorkerResponse & { eventId?: string; released?: number; blobsDeleted?: number }
+    >({
+      type: 'deleteTree',
+      id: this.nextId(),
+      treeName,
+      deleteBlobs,
+    });
+    return {
+      eventId: res.eventId ?? '',
+      released: res.released ?? 0,
+      blobsDeleted: res.blobsDeleted ?? 0,
+    };
+  }
+
+  /** Open proposals of collaborators for our trees, only treeName's if given */
+  async listProposals(treeName?: string): Promise<TreeProposal[]> {
+    const res = await this.request<WorkerResponse & { proposals: TreeProposal[] }>({
+      type: 'listProposals',
+      id: this.nextId(),
+      treeName,
+    });
+    return res.proposals ?? [];
+  }
+
+  /** What a proposal changes compared to the root it was made on */
+  async diffProposal(eventId: string): Promise<TreeChange[]> {
+    const res = await this.request<WorkerResponse & { changes: TreeChange[] }>({
+      type: 'diffProposal',
+      id: this.nextId(),
+      eventId,
+    });
+    return res.changes ?? [];
+  }
+
+  /**
+   * Merge a proposal into its tree and publish the result with the
+   * collaborator as its author. Fails listing the paths both sides changed.
+   */
+  async mergeProposal(eventId: string): Promise<{ cid: CID; eventId: string }> {
+    const res = await this.request<
+      WorkerResponse & { cid?: { hash: string; key?: string }; eventId?: string }
+    >({
+      type: 'mergeProposal',
+      id: this.nextId(),
+      eventId,
+    });
+    if (!res.cid) {
+      throw new Error('mergeProposal returned no CID');
+    }
+    return { cid: this.rustToCid(res.cid), eventId: res.eventId ?? '' };
+  }
+
+  /** Stop listing a proposal without merging it */
+  async dismissProposal(eventId: string): Promise<void> {
+    await this.request({
+      type: 'dismissProposal',
+      id: this.nextId(),
+      eventId,
+    });
+  }
+
   async republishTree(pubkey: string, treeName: string): Promise<boolean> {
     const res = await this.request<{ value: boolean }>({
       type: 'republishTree',
@@ -813,6 +1515,28 @@ export class TauriWorkerAdapter {
     return res.value ?? false;
   }
 
+  /**
+   * Add files (local paths), links or text shared with the app to the
+   * private `inbox` tree and publish it. Returns the new inbox root.
+   */
+  async addToInbox(
+    items: Array<
+      | { kind: 'file'; path: string }
+      | { kind: 'url'; url: string; title?: string }
+      | { kind: 'text'; text: string }
+    >
+  ): Promise<{ cid: CID; eventId: string }> {
+    const res = await this.request<WorkerResponse & { eventId?: string }>({
+      type: 'addToInbox',
+      id: this.nextId(),
+      items,
+    });
+    if (!res.cid) {
+      throw new Error('addToInbox returned no CID');
+    }
+    return { cid: this.rustToCid(res.cid), eventId: res.eventId ?? '' };
+  }
+
   // ============================================================================
   // Stats Operations
   // ============================================================================
@@ -833,10 +1557,11 @@ export class TauriWorkerAdapter {
     }));
   }
 
+  /** Relay connections with their health; demoted relays are only retried every half hour */
   async getRelayStats(): Promise<RelayStats[]> {
     const res = await this.request<
       WorkerResponse & {
-        relays?: Array<{ url: string; connected: boolean; connecting: boolean }>;
+        relays?: Array<{ url: string; connected: boolean; connecting: boolean } & RelayHealth>;
       }
     >({
       type: 'getRelayStats',
@@ -846,15 +1571,176 @@ export class TauriWorkerAdapter {
       url: r.url,
       connected: r.connected,
       connecting: r.connecting,
+      score: r.score,
+      demoted: r.demoted,
+      failures: r.failures,
+      nextRetrySecs: r.nextRetrySecs,
+      lastError: r.lastError,
+      messagesPerSec: r.messagesPerSec,
+      eoseLatencyMs: r.eoseLatencyMs,
+      notices: r.notices,
+      bytesSent: r.bytesSent,
+      bytesReceived: r.bytesReceived,
     }));
   }
 
+  /**
+   * Published events, newest first. Events no relay accepted are kept and
+   * sent again, with backoff and whenever a relay connects.
+   */
+  async getOutbox(): Promise<{ entries: OutboxEntry[]; pending: number }> {
+    const res = await this.request<WorkerResponse & { entries?: OutboxEntry[]; pending?: number }>({
+      type: 'getOutbox',
+      id: this.nextId(),
+    });
+    return { entries: res.entries ?? [], pending: res.pending ?? 0 };
+  }
+
+  /** Send the given outbox events again now, or all undelivered ones */
+  async retryOutbox(eventIds?: string[]): Promise<{ entries: OutboxEntry[]; pending: number }> {
+    const res = await this.request<WorkerResponse & { entries?: OutboxEntry[]; pending?: number }>({
+      type: 'retryOutbox',
+      id: this.nextId(),
+      eventIds,
+    });
+    return { entries: res.entries ?? [], pending: res.pending ?? 0 };
+  }
+
+  /** Backend request queue: running jobs first, then queued ones in start order */
+  async getJobs(): Promise<BackendJob[]> {
+    const res = await this.request<WorkerResponse & { jobs?: BackendJob[] }>({
+      type: 'getJobs',
+      id: this.nextId(),
+    });
+    return res.jobs ?? [];
+  }
+
+  async getSyncStatus(): Promise<SyncStatus> {
+    const res = await this.request<WorkerResponse & { status: SyncStatus }>({
+      type: 'getSyncStatus',
+      id: this.nextId(),
+    });
+    return res.status;
+  }
+
+  async getNotifyRules(): Promise<NotifyRules> {
+    const res = await this.request<WorkerResponse & { rules: NotifyRules }>({
+      type: 'getNotifyRules',
+      id: this.nextId(),
+    });
+    return res.rules;
+  }
+
+  async setNotifyRules(rules: NotifyRules): Promise<NotifyRules> {
+    const res = await this.request<WorkerResponse & { rules: NotifyRules }>({
+      type: 'setNotifyRules',
+      id: this.nextId(),
+      rules,
+    });
+    return res.rules;
+  }
+
+  async getWotPolicy(): Promise<WotPolicy> {
+    const res = await this.request<WorkerResponse & { policy: WotPolicy }>({
+      type: 'getWotPolicy',
+      id: this.nextId(),
+    });
+    return res.policy;
+  }
+
+  async setWotPolicy(policy: WotPolicy): Promise<WotPolicy> {
+    const res = await this.request<WorkerResponse & { policy: WotPolicy }>({
+      type: 'setWotPolicy',
+      id: this.nextId(),
+      policy,
+    });
+    return res.policy;
+  }
+
+  async getPowPolicy(): Promise<PowPolicy> {
+    const res = await this.request<WorkerResponse & { policy: PowPolicy }>({
+      type: 'getPowPolicy',
+      id: this.nextId(),
+    });
+    return res.policy;
+  }
+
+  async setPowPolicy(policy: PowPolicy): Promise<PowPolicy> {
+    const res = await this.request<WorkerResponse & { policy: PowPolicy }>({
+      type: 'setPowPolicy',
+      id: this.nextId(),
+      policy,
+    });
+    return res.policy;
+  }
+
+  /** Stop mining in progress, failing the publishes waiting on it. Returns whether any was running. */
+  async cancelPow(): Promise<boolean> {
+    const res = await this.request<WorkerResponse & { value: boolean }>({
+      type: 'cancelPow',
+      id: this.nextId(),
+    });
+    return res.value;
+  }
+
+  async getHistorySync(): Promise<boolean> {
+    const res = await this.request<WorkerResponse & { value: boolean }>({
+      type: 'getHistorySync',
+      id: this.nextId(),
+    });
+    return res.value;
+  }
+
+  /** Turn syncing history with our other devices through a private tree on or off */
+  async setHistorySync(enabled: boolean): Promise<boolean> {
+    const res = await this.request<WorkerResponse & { value: boolean }>({
+      type: 'setHistorySync',
+      id: this.nextId(),
+      enabled,
+    });
+    return res.value;
+  }
+
+  /** Sync history now; returns how many local entries changed */
+  async syncHistory(): Promise<number> {
+    const res = await this.request<WorkerResponse & { merged: number }>({
+      type: 'syncHistory',
+      id: this.nextId(),
+    });
+    return res.merged;
+  }
+
+  /** Disconnect WebRTC peers and hold pushes until resumed */
+  async setSyncPaused(paused: boolean): Promise<SyncStatus> {
+    const res = await this.request<WorkerResponse & { status: SyncStatus }>({
+      type: 'setSyncPaused',
+      id: this.nextId(),
+      paused,
+    });
+    return res.status;
+  }
+
   async getStorageStats(): Promise<{
     items: number;
     bytes: number;
     pinnedItems: number;
     pinnedBytes: number;
     maxBytes: number;
+    cacheHits: number;
+    cacheMisses: number;
+    cacheItems: number;
+    cacheBytes: number;
+    cacheMaxBytes: number;
+    ownBytes: number;
+    ownTrees: number;
+    ownQuotaBytes: number;
+    othersBytes: number;
+    othersTrees: number;
+    othersQuotaBytes: number;
+    /** Bytes of tagged trees counting shared blocks once per tree */
+    logicalBytes: number;
+    /** logicalBytes over the bytes they're stored in, 1 without sharing */
+    dedupRatio: number;
   }> {
     const res = await this.request<
       WorkerResponse & {
@@ -863,6 +1749,19 @@ export class TauriWorkerAdapter {
         pinnedItems?: number;
         pinnedBytes?: number;
         maxBytes?: number;
+        cacheHits?: number;
+        cacheMisses?: number;
+        cacheItems?: number;
+        cacheBytes?: number;
+        cacheMaxBytes?: number;
+        ownBytes?: number;
+        ownTrees?: number;
+        ownQuotaBytes?: number;
+        othersBytes?: number;
+        othersTrees?: number;
+        othersQuotaBytes?: number;
+        logicalBytes?: number;
+        dedupRatio?: number;
       }
     >({
       type: 'getStorageStats',
@@ -874,6 +1773,19 @@ export class TauriWorkerAdapter {
       pinnedItems: res.pinnedItems ?? 0,
       pinnedBytes: res.pinnedBytes ?? 0,
       maxBytes: res.maxBytes ?? 0,
+      cacheHits: res.cacheHits ?? 0,
+      cacheMisses: res.cacheMisses ?? 0,
+      cacheItems: res.cacheItems ?? 0,
+      cacheBytes: res.cacheBytes ?? 0,
+      cacheMaxBytes: res.cacheMaxBytes ?? 0,
+      ownBytes: res.ownBytes ?? 0,
+      ownTrees: res.ownTrees ?? 0,
+      ownQuotaBytes: res.ownQuotaBytes ?? 0,
+      othersBytes: res.othersBytes ?? 0,
+      othersTrees: res.othersTrees ?? 0,
+      othersQuotaBytes: res.othersQuotaBytes ?? 0,
+      logicalBytes: res.logicalBytes ?? 0,
+      dedupRatio: res.dedupRatio ?? 1,
     };
   }
 
@@ -885,6 +1797,43 @@ export class TauriWorkerAdapter {
     });
   }
 
+  /**
+   * Write blocks fetched from Blossom for files over largeFileBytes that
+   * aren't kept to disk only when read again; saved across restarts
+   */
+  async setCacheAdmission(enabled: boolean, largeFileBytes: number): Promise<void> {
+    await this.request<WorkerResponse>({
+      type: 'setCacheAdmission',
+      id: this.nextId(),
+      enabled,
+      largeFileBytes,
+    });
+  }
+
+  /**
+   * Also pack files of up to `threshold` bytes into shared blocks when
+   * writing directories, so hashtree-core readers can fetch trees of many
+   * tiny files as a few blobs (0 = off). Files are still pushed one by one
+   * for other readers. Kept across restarts.
+   */
+  async setPacking(threshold: number): Promise<void> {
+    await this.request<WorkerResponse>({
+      type: 'setPacking',
+      id: this.nextId(),
+      threshold,
+    });
+  }
+
+  /** Cap our own trees and other people's cached trees separately (0 = unlimited) */
+  async setQuota(ownBytes: number, othersBytes: number): Promise<void> {
+    await this.request<WorkerResponse>({
+      type: 'setQuota',
+      id: this.nextId(),
+      ownBytes,
+      othersBytes,
+    });
+  }
+
   async runEviction(): Promise<number> {
     const res = await this.request<WorkerResponse & { bytesFreed?: number }>({
       type: 'runEviction',
@@ -893,6 +1842,176 @@ export class TauriWorkerAdapter {
     return res.bytesFreed ?? 0;
   }
 
+  /** Re-hash stored blobs, quarantining corrupted ones and re-fetching them from Blossom */
+  async runScrub(): Promise<{
+    checked: number;
+    bytes: number;
+    corrupted: string[];
+    refetched: number;
+  }> {
+    const res = await this.request<
+      WorkerResponse & {
+        checked?: number;
+        bytes?: number;
+        corrupted?: string[];
+        refetched?: number;
+      }
+    >({
+      type: 'runScrub',
+      id: this.nextId(),
+    });
+    return {
+      checked: res.checked ?? 0,
+      bytes: res.bytes ?? 0,
+      corrupted: res.corrupted ?? [],
+      refetched: res.refetched ?? 0,
+    };
+  }
+
+  /** Size of the nostrdb event cache, its events by kind and any staged purge */
+  async getNdbStats(): Promise<{
+    bytes: number;
+    events: number;
+    kinds: Array<{ kind: number; count: number }>;
+    pendingPurge: NdbPurgeRule | null;
+    purgeable: number;
+  }> {
+    const res = await this.request<
+      WorkerResponse & {
+        bytes?: number;
+        events?: number;
+        kinds?: Array<{ kind: number; count: number }>;
+        pendingPurge?: NdbPurgeRule | null;
+        purgeable?: number;
+      }
+    >({
+      type: 'getNdbStats',
+      id: this.nextId(),
+    });
+    return {
+      bytes: res.bytes ?? 0,
+      events: res.events ?? 0,
+      kinds: res.kinds ?? [],
+      pendingPurge: res.pendingPurge ?? null,
+      purgeable: res.purgeable ?? 0,
+    };
+  }
+
+  /**
+   * Stage a purge of cached events, replacing any staged before. nostrdb is
+   * rewritten without them, reclaiming disk, on the next start. An empty
+   * rule only compacts. Our own events are always kept.
+   */
+  async purgeNdb(rule: NdbPurgeRule = {}): Promise<{ events: number; restartRequired: boolean }> {
+    const res = await this.request<WorkerResponse & { events?: number; restartRequired?: boolean }>({
+      type: 'purgeNdb',
+      id: this.nextId(),
+      olderThan: rule.olderThan,
+      kinds: rule.kinds,
+    });
+    return { events: res.events ?? 0, restartRequired: res.restartRequired ?? true };
+  }
+
+  /**
+   * Write the whole local state to one archive file. The identity key is
+   * only included, encrypted, when a passphrase is given.
+   */
+  async exportState(path: string, passphrase?: string): Promise<{ files: number; bytes: number }> {
+    const res = await this.request<WorkerResponse & { files?: number; bytes?: number }>({
+      type: 'exportState',
+      id: this.nextId(),
+      path,
+      passphrase,
+    });
+    return { files: res.files ?? 0, bytes: res.bytes ?? 0 };
+  }
+
+  /** Stage an exported archive; it replaces the local state on the next start */
+  async importState(
+    path: string,
+    passphrase?: string
+  ): Promise<{
+    files: number;
+    bytes: number;
+    settings: { pubkey?: string; relays: string[]; readServers: string[]; writeServers: string[] };
+    nsec?: string;
+    restartRequired: boolean;
+  }> {
+    const res = await this.request<
+      WorkerResponse & {
+        files?: number;
+        bytes?: number;
+        settings?: { pubkey?: string; relays: string[]; readServers: string[]; writeServers: string[] };
+        nsec?: string | null;
+        restartRequired?: boolean;
+      }
+    >({
+      type: 'importState',
+      id: this.nextId(),
+      path,
+      passphrase,
+    });
+    return {
+      files: res.files ?? 0,
+      bytes: res.bytes ?? 0,
+      settings: res.settings ?? { relays: [], readServers: [], writeServers: [] },
+      nsec: res.nsec ?? undefined,
+      restartRequired: res.restartRequired ?? true,
+    };
+  }
+
+  /**
+   * Save a file, or a directory recursively, to disk. Without `destPath` the
+   * user picks the destination: a save dialog for a file, a folder for a
+   * directory, which is saved into it as `name`. Returns null if the dialog
+   * is cancelled. The download can be paused and resumed with the opId of
+   * its progress events.
+   */
+  async downloadToDisk(
+    cid: CID,
+    options: { destPath?: string; name?: string; isDirectory?: boolean } = {}
+  ): Promise<{ files: number; bytes: number; path: string } | null> {
+    let destPath = options.destPath;
+    if (!destPath) {
+      if (options.isDirectory) {
+        const dir = (await openFile({ directory: true }))?.[0];
+        if (dir) {
+          const { join } = await import('@tauri-apps/api/path');
+          destPath = await join(dir, options.name || 'download');
+        }
+      } else {
+        destPath = (await saveFile({ defaultPath: options.name })) ?? undefined;
+      }
+      if (!destPath) return null;
+    }
+    const res = await this.request<WorkerResponse & { files?: number; bytes?: number }>({
+      type: 'downloadToDisk',
+      id: this.nextId(),
+      cid: this.cidToRust(cid),
+      destPath,
+    });
+    return { files: res.files ?? 0, bytes: res.bytes ?? 0, path: destPath };
+  }
+
+  /** Hold a running download between chunks; false if it isn't running */
+  async pauseDownload(downloadId: string): Promise<boolean> {
+    const res = await this.request<WorkerResponse>({
+      type: 'pauseDownload',
+      id: this.nextId(),
+      downloadId,
+    });
+    return res.value ?? false;
+  }
+
+  async resumeDownload(downloadId: string): Promise<boolean> {
+    const res = await this.request<WorkerResponse>({
+      type: 'resumeDownload',
+      id: this.nextId(),
+      downloadId,
+    });
+    return res.value ?? false;
+  }
+
   async blockPeer(_pubkey: string): Promise<void> {
     // WebRTC peer blocking not applicable for Tauri native backend
     // Tauri uses native networking, not browser WebRTC
@@ -952,6 +2071,22 @@ export class TauriWorkerAdapter {
     };
   }
 
+  async setProxy(proxy: ProxyConfig): Promise<void> {
+    await this.request<WorkerResponse>({
+      type: 'setProxy',
+      id: this.nextId(),
+      proxy,
+    });
+  }
+
+  async getProxy(): Promise<ProxyConfig> {
+    const res = await this.request<WorkerResponse & { proxy?: ProxyConfig }>({
+      type: 'getProxy',
+      id: this.nextId(),
+    });
+    return res.proxy ?? {};
+  }
+
   async setRelays(relays: string[]): Promise<void> {
     await this.request<WorkerResponse>({
       type: 'setRelays',
@@ -72,6 +72,24 @@ export const isLinux = (): boolean => {
     navigator.userAgent?.toLowerCase().includes('linux');
 };
 
+// Tab this window shows: the main window, or one opened by open_app_tab
+export interface AppTab {
+  /** Label of the window's app frame webview */
+  frameLabel: string;
+  /** Whether pages of the tab are kept out of history */
+  private: boolean;
+}
+
+export const currentTab = (): AppTab => {
+  const tab = typeof window === 'undefined'
+    ? undefined
+    : (window as unknown as { __IRIS_TAB__?: AppTab }).__IRIS_TAB__;
+  return {
+    frameLabel: tab?.frameLabel ?? 'app-frame',
+    private: tab?.private === true,
+  };
+};
+
 // Autostart management
 export interface AutostartAPI {
   isEnabled: () => Promise<boolean>;
@@ -16,7 +16,7 @@ use hashtree_config::{Config, StorageBackend};
 use hashtree_core::store::{Store, StoreError};
 use hashtree_core::types::Hash;
 use hashtree_core::{Cid, DirEntry, HashTree, HashTreeConfig, LinkType};
-use hashtree_fs::FsBlobStore;
+use hashtree_fs::{FsBlobStore, PackBlobStore};
 #[cfg(feature = "lmdb")]
 use hashtree_lmdb::LmdbBlobStore;
 use sha1::{Sha1, Digest};
@@ -49,9 +49,10 @@ impl RuntimeExecutor {
     }
 }
 
-/// Local blob store - wraps either FsBlobStore or LmdbBlobStore
+/// Local blob store - wraps FsBlobStore, PackBlobStore or LmdbBlobStore
 pub enum LocalStore {
     Fs(FsBlobStore),
+    Pack(PackBlobStore),
     #[cfg(feature = "lmdb")]
     Lmdb(LmdbBlobStore),
 }
@@ -64,6 +65,9 @@ impl LocalStore {
             StorageBackend::Fs => {
                 Ok(LocalStore::Fs(FsBlobStore::new(path)?))
             }
+            StorageBackend::Pack => {
+                Ok(LocalStore::Pack(PackBlobStore::new(path)?))
+            }
             #[cfg(feature = "lmdb")]
             StorageBackend::Lmdb => {
                 Ok(LocalStore::Lmdb(LmdbBlobStore::new(path)?))
@@ -80,6 +84,7 @@ impl LocalStore {
     pub fn list(&self) -> std::result::Result<Vec<Hash>, StoreError> {
         match self {
             LocalStore::Fs(store) => store.list(),
+            LocalStore::Pack(store) => store.list(),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.list(),
         }
@@ -89,6 +94,7 @@ impl LocalStore {
     pub fn get_sync(&self, hash: &Hash) -> std::result::Result<Option<Vec<u8>>, StoreError> {
         match self {
             LocalStore::Fs(store) => store.get_sync(hash),
+            LocalStore::Pack(store) => store.get_sync(hash),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.get_sync(hash),
         }
@@ -100,6 +106,7 @@ impl Store for LocalStore {
     async fn put(&self, hash: Hash, data: Vec<u8>) -> std::result::Result<bool, StoreError> {
         match self {
             LocalStore::Fs(store) => store.put(hash, data).await,
+            LocalStore::Pack(store) => store.put(hash, data).await,
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.put(hash, data).await,
         }
@@ -108,14 +115,30 @@ impl Store for LocalStore {
     async fn get(&self, hash: &Hash) -> std::result::Result<Option<Vec<u8>>, StoreError> {
         match self {
             LocalStore::Fs(store) => store.get(hash).await,
+            LocalStore::Pack(store) => store.get(hash).await,
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.get(hash).await,
         }
     }
 
+    async fn get_range(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        len: u64,
+    ) -> std::result::Result<Option<Vec<u8>>, StoreError> {
+        match self {
+            LocalStore::Fs(store) => store.get_range(hash, offset, len).await,
+            LocalStore::Pack(store) => store.get_range(hash, offset, len).await,
+            #[cfg(feature = "lmdb")]
+            LocalStore::Lmdb(store) => store.get_range(hash, offset, len).await,
+        }
+    }
+
     async fn has(&self, hash: &Hash) -> std::result::Result<bool, StoreError> {
         match self {
             LocalStore::Fs(store) => store.has(hash).await,
+            LocalStore::Pack(store) => store.has(hash).await,
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.has(hash).await,
         }
@@ -124,6 +147,7 @@ impl Store for LocalStore {
     async fn delete(&self, hash: &Hash) -> std::result::Result<bool, StoreError> {
         match self {
             LocalStore::Fs(store) => store.delete(hash).await,
+            LocalStore::Pack(store) => store.delete(hash).await,
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.delete(hash).await,
         }
@@ -23,6 +23,7 @@ use crate::nostr_client::{BlossomResult, NostrClient, RelayResult};
 // CachedStore: local store first, then Blossom fallback
 mod cached_store {
     use hashtree_blossom::BlossomStore;
+    use hashtree_core::store::slice_range;
     use hashtree_core::{Hash, Store, StoreError};
     use std::sync::Arc;
 
@@ -58,6 +59,23 @@ mod cached_store {
             result
         }
 
+        async fn get_range(
+            &self,
+            hash: &Hash,
+            offset: u64,
+            len: u64,
+        ) -> Result<Option<Vec<u8>>, StoreError> {
+            // Read in place locally; Blossom blobs are fetched whole, so
+            // they're verified and cached
+            if let Ok(Some(data)) = self.local.get_range(hash, offset, len).await {
+                return Ok(Some(data));
+            }
+            Ok(self
+                .get(hash)
+                .await?
+                .map(|data| slice_range(&data, offset, len).to_vec()))
+        }
+
         async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
             // Check local first
             if self.local.has(hash).await? {
@@ -89,6 +107,9 @@ fn create_local_store(path: &std::path::Path) -> Result<std::sync::Arc<dyn hasht
         StorageBackend::Fs => {
             Ok(std::sync::Arc::new(FsBlobStore::new(path)?))
         }
+        StorageBackend::Pack => {
+            Ok(std::sync::Arc::new(hashtree_fs::PackBlobStore::new(path)?))
+        }
         #[cfg(feature = "lmdb")]
         StorageBackend::Lmdb => {
             Ok(std::sync::Arc::new(hashtree_lmdb::LmdbBlobStore::new(path)?))
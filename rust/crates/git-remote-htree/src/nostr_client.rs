@@ -84,20 +84,53 @@ impl StoredKey {
         let secret_hex = hex::encode(secret_key.to_secret_bytes());
         Self::from_secret_hex(&secret_hex, petname)
     }
+
+    /// Create from a NIP-49 ncryptsec, decrypted with `passphrase`
+    pub fn from_ncryptsec(
+        ncryptsec: &str,
+        passphrase: &str,
+        petname: Option<String>,
+    ) -> Result<Self> {
+        let secret_key = nostr::nips::nip49::EncryptedSecretKey::from_bech32(ncryptsec)
+            .map_err(|e| anyhow::anyhow!("Invalid ncryptsec format: {}", e))?
+            .to_secret_key(passphrase)
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase for encrypted key"))?;
+        let secret_hex = hex::encode(secret_key.to_secret_bytes());
+        Self::from_secret_hex(&secret_hex, petname)
+    }
 }
 
 /// Load all keys from config files
-pub fn load_keys() -> Vec<StoredKey> {
+pub fn load_keys() -> Result<Vec<StoredKey>> {
     let mut keys = Vec::new();
 
     // Primary: ~/.hashtree/keys (multi-key format)
-    let keys_path = hashtree_config::get_keys_path();
+    let keys_path = hashtree_config::get_keys_path()?;
     if let Ok(content) = std::fs::read_to_string(&keys_path) {
         for entry in hashtree_config::parse_keys_file(&content) {
             let key = if entry.secret.starts_with("nsec1") {
                 StoredKey::from_nsec(&entry.secret, entry.alias)
             } else if entry.secret.len() == 64 {
                 StoredKey::from_secret_hex(&entry.secret, entry.alias)
+            } else if entry.is_encrypted() {
+                // Git talks to the helper over stdin, so it can't prompt.
+                // Skipping the key instead would have "self" generate a new
+                // identity and push under it.
+                let Ok(passphrase) = std::env::var(hashtree_config::PASSPHRASE_ENV) else {
+                    anyhow::bail!(
+                        "Passphrase required for encrypted key {:?}: set {}",
+                        entry.alias.as_deref().unwrap_or("(no alias)"),
+                        hashtree_config::PASSPHRASE_ENV
+                    );
+                };
+                let key = StoredKey::from_ncryptsec(&entry.secret, &passphrase, entry.alias)
+                    .with_context(|| {
+                        format!(
+                            "Failed to decrypt key with {}",
+                            hashtree_config::PASSPHRASE_ENV
+                        )
+                    })?;
+                Ok(key)
             } else {
                 continue;
             };
@@ -112,7 +145,7 @@ pub fn load_keys() -> Vec<StoredKey> {
         }
     }
 
-    keys
+    Ok(keys)
 }
 
 /// Resolve an identifier to (pubkey_hex, secret_hex)
@@ -122,7 +155,7 @@ pub fn load_keys() -> Vec<StoredKey> {
 /// - pubkey hex (64 chars)
 /// - npub bech32
 pub fn resolve_identity(identifier: &str) -> Result<(String, Option<String>)> {
-    let keys = load_keys();
+    let keys = load_keys()?;
 
     // Special "self" alias - use default key or first available, auto-generate if none
     if identifier == "self" {
@@ -194,7 +227,7 @@ fn generate_and_save_key(petname: &str) -> Result<StoredKey> {
     let pubkey_hex = hex::encode(keys.public_key().to_bytes());
 
     // Ensure directory exists
-    let keys_path = hashtree_config::get_keys_path();
+    let keys_path = hashtree_config::get_keys_path()?;
     if let Some(parent) = keys_path.parent() {
         fs::create_dir_all(parent)?;
     }
@@ -7,7 +7,7 @@
 
 mod common;
 
-use common::{test_relay::TestRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
+use common::{MockRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
 use std::process::Command;
 use tempfile::TempDir;
 
@@ -19,7 +19,7 @@ fn test_git_push_and_clone_local() {
     }
 
     // Start local nostr relay
-    let relay = TestRelay::new(19300);
+    let relay = MockRelay::start();
     println!("Started local nostr relay at: {}", relay.url());
 
     // Start local blossom server
@@ -4,7 +4,7 @@
 
 mod common;
 
-use common::{test_relay::TestRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
+use common::{MockRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
 use std::process::{Command, Stdio};
 
 /// Test diff-based push - second push should upload fewer blobs
@@ -15,7 +15,7 @@ fn test_diff_based_push() {
     }
 
     // Start local servers
-    let relay = TestRelay::new(19202);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19203) {
         Some(s) => s,
         None => {
@@ -4,7 +4,7 @@
 
 mod common;
 
-use common::{test_relay::TestRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
+use common::{MockRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
 use std::process::{Command, Stdio};
 use tempfile::TempDir;
 
@@ -16,7 +16,7 @@ fn test_non_fast_forward_rejected() {
     }
 
     // Start local servers
-    let relay = TestRelay::new(19220);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19221) {
         Some(s) => s,
         None => {
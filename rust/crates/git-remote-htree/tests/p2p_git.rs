@@ -1,12 +1,12 @@
 //! E2E test: Git push/pull between two peers via WebRTC
 //!
 //! Tests bidirectional git operations with multiple commits going back and forth.
-//! Uses local TestRelay for WebRTC signaling - no external network needed.
+//! Uses local MockRelay for WebRTC signaling - no external network needed.
 
 mod common;
 
 use common::create_test_repo;
-use common::test_relay::TestRelay;
+use common::MockRelay;
 use nostr::{Keys, ToBech32};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
@@ -191,7 +191,7 @@ fn test_p2p_git_roundtrip() {
     println!("=== P2P Git Roundtrip Test ===\n");
 
     // Start local relay
-    let relay = TestRelay::new(19090);
+    let relay = MockRelay::start();
     let relay_url = relay.url();
     println!("Relay: {}", relay_url);
 
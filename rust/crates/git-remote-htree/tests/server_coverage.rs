@@ -4,7 +4,7 @@
 
 mod common;
 
-use common::{test_relay::TestRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
+use common::{MockRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
 use std::process::{Command, Stdio};
 
 /// Test that adding a new blossom server triggers full upload to it
@@ -15,7 +15,7 @@ fn test_server_coverage_full_upload() {
     }
 
     // Start local relay
-    let relay = TestRelay::new(19210);
+    let relay = MockRelay::start();
     println!("Started local nostr relay at: {}", relay.url());
 
     // Start TWO blossom servers
@@ -2,7 +2,7 @@
 
 mod common;
 
-use common::{create_test_repo, skip_if_no_binary, test_relay::TestRelay, TestEnv, TestServer};
+use common::{create_test_repo, skip_if_no_binary, MockRelay, TestEnv, TestServer};
 use std::path::Path;
 use std::process::Command;
 use tempfile::TempDir;
@@ -28,7 +28,7 @@ fn test_fetch_does_not_update_checked_out_branch() {
         return;
     }
 
-    let relay = TestRelay::new(19310);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19311) {
         Some(s) => s,
         None => {
@@ -6,7 +6,7 @@
 
 mod common;
 
-use common::{test_relay::TestRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
+use common::{MockRelay, TestServer, TestEnv, create_test_repo, skip_if_no_binary};
 use std::process::Command;
 use tempfile::TempDir;
 
@@ -27,7 +27,7 @@ fn test_link_visible_push_and_clone_with_secret() {
         return;
     }
 
-    let relay = TestRelay::new(19400);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19401) {
         Some(s) => s,
         None => {
@@ -106,7 +106,7 @@ fn test_link_visible_cannot_clone_without_secret() {
         return;
     }
 
-    let relay = TestRelay::new(19402);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19403) {
         Some(s) => s,
         None => {
@@ -190,7 +190,7 @@ fn test_link_visible_cannot_clone_with_wrong_secret() {
         return;
     }
 
-    let relay = TestRelay::new(19404);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19405) {
         Some(s) => s,
         None => {
@@ -276,7 +276,7 @@ fn test_private_push_and_clone_as_author() {
         return;
     }
 
-    let relay = TestRelay::new(19406);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19407) {
         Some(s) => s,
         None => {
@@ -352,7 +352,7 @@ fn test_private_cannot_clone_without_private_flag() {
         return;
     }
 
-    let relay = TestRelay::new(19408);
+    let relay = MockRelay::start();
     let server = match TestServer::new(19409) {
         Some(s) => s,
         None => {
@@ -33,6 +33,9 @@ use std::time::Duration;
 use thiserror::Error;
 use tracing::{debug, warn};
 
+/// HEAD requests kept in flight per server by batch existence checks
+const MAX_PIPELINED_HEADS: usize = 16;
+
 #[derive(Error, Debug)]
 pub enum BlossomError {
     #[error("HTTP error: {0}")]
@@ -47,6 +50,13 @@ pub enum BlossomError {
     #[error("Download failed on all servers: {0}")]
     DownloadFailed(String),
 
+    /// Every read server answered that it doesn't have the blob
+    #[error("Not found on any server: {0}")]
+    NotFound(String),
+
+    #[error("Delete failed on all servers: {0}")]
+    DeleteFailed(String),
+
     #[error("Hash mismatch: expected {expected}, got {actual}")]
     HashMismatch { expected: String, actual: String },
 
@@ -64,6 +74,7 @@ pub struct BlossomClient {
     write_servers: Vec<String>,
     http: reqwest::Client,
     timeout: Duration,
+    proxy: Option<reqwest::Proxy>,
 }
 
 impl BlossomClient {
@@ -92,6 +103,7 @@ impl BlossomClient {
                 .build()
                 .unwrap(),
             timeout: Duration::from_secs(30),
+            proxy: None,
         }
     }
 
@@ -107,6 +119,7 @@ impl BlossomClient {
                 .build()
                 .unwrap(),
             timeout: Duration::from_secs(30),
+            proxy: None,
         }
     }
 
@@ -121,6 +134,7 @@ impl BlossomClient {
                 .build()
                 .unwrap(),
             timeout: Duration::from_secs(30),
+            proxy: None,
         }
     }
 
@@ -146,13 +160,25 @@ impl BlossomClient {
     /// Set request timeout
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
-        self.http = reqwest::Client::builder()
-            .timeout(timeout)
-            .build()
-            .unwrap();
+        self.rebuild_http();
         self
     }
 
+    /// Send all requests through `proxy`, e.g. a SOCKS5 proxy like Tor
+    pub fn with_proxy(mut self, proxy: Option<reqwest::Proxy>) -> Self {
+        self.proxy = proxy;
+        self.rebuild_http();
+        self
+    }
+
+    fn rebuild_http(&mut self) {
+        let mut builder = reqwest::Client::builder().timeout(self.timeout);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        self.http = builder.build().unwrap();
+    }
+
     /// Set local daemon URL (prioritized for reads)
     /// The local daemon is prepended to read_servers if not already present
     pub fn with_local_daemon(mut self, url: String) -> Self {
@@ -308,6 +334,35 @@ impl BlossomClient {
         false
     }
 
+    /// Check which of `hashes` exist on any write server
+    pub async fn exists_many(&self, hashes: &[&str]) -> Vec<bool> {
+        self.exists_many_on_servers(hashes, &self.write_servers)
+            .await
+    }
+
+    /// Check which of `hashes` exist on any of `servers`, keeping input order.
+    /// HEAD requests are pipelined per server, and later servers are only
+    /// asked about hashes the earlier ones didn't have.
+    pub async fn exists_many_on_servers(&self, hashes: &[&str], servers: &[String]) -> Vec<bool> {
+        use futures::stream::{self, StreamExt};
+        let mut found = vec![false; hashes.len()];
+        for server in servers {
+            let missing: Vec<usize> = (0..hashes.len()).filter(|&i| !found[i]).collect();
+            if missing.is_empty() {
+                break;
+            }
+            let results: Vec<(usize, bool)> = stream::iter(missing)
+                .map(|i| async move { (i, self.exists_on_server(hashes[i], server).await) })
+                .buffered(MAX_PIPELINED_HEADS)
+                .collect()
+                .await;
+            for (i, exists) in results {
+                found[i] = exists;
+            }
+        }
+        found
+    }
+
     /// Check if server has a tree by sampling hashes (parallel checks)
     pub async fn server_has_tree_samples(&self, server: &str, hashes: &[&str], sample_size: usize) -> bool {
         use futures::future::join_all;
@@ -342,12 +397,15 @@ impl BlossomClient {
 
     /// Download data from Blossom servers
     /// Verifies the hash matches before returning
+    #[tracing::instrument(level = "debug", skip(self))]
     pub async fn download(&self, hash: &str) -> Result<Vec<u8>, BlossomError> {
         if self.read_servers.is_empty() {
             return Err(BlossomError::NoServers);
         }
 
         let mut last_error = String::new();
+        // Until a server answers other than 404
+        let mut not_found = true;
 
         for server in &self.read_servers {
             let url = format!("{}/{}.bin", server.trim_end_matches('/'), hash);
@@ -383,6 +441,10 @@ impl BlossomClient {
                         }
                     }
                 }
+                Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => {
+                    debug!("Blob {} not found on {}", hash, server);
+                    continue;
+                }
                 Ok(resp) => {
                     last_error = format!("{} returned {}", server, resp.status());
                     debug!("Download {} from {} returned status {}", hash, server, resp.status());
@@ -391,11 +453,126 @@ impl BlossomClient {
                     last_error = e.to_string();
                 }
             }
+            not_found = false;
         }
 
+        if not_found {
+            return Err(BlossomError::NotFound(hash.to_string()));
+        }
         Err(BlossomError::DownloadFailed(last_error))
     }
 
+    /// Download up to `len` bytes of a blob at `offset` with an HTTP Range
+    /// request. A partial response can't be checked against the hash; a
+    /// server that ignores the range sends the whole blob, which is.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn download_range(
+        &self,
+        hash: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, BlossomError> {
+        if self.read_servers.is_empty() {
+            return Err(BlossomError::NoServers);
+        }
+        if len == 0 {
+            for server in &self.read_servers {
+                if self.exists_on_server(hash, server).await {
+                    return Ok(Vec::new());
+                }
+            }
+            return Err(BlossomError::DownloadFailed(format!("{} not found", hash)));
+        }
+
+        let range = format!("bytes={}-{}", offset, offset.saturating_add(len - 1));
+        let mut last_error = String::new();
+
+        for server in &self.read_servers {
+            let url = format!("{}/{}.bin", server.trim_end_matches('/'), hash);
+            let resp = match self.http.get(&url).header("Range", &range).send().await {
+                Ok(resp) => resp,
+                Err(e) => {
+                    last_error = e.to_string();
+                    continue;
+                }
+            };
+            match resp.status().as_u16() {
+                206 => match resp.bytes().await {
+                    Ok(bytes) => {
+                        let end = bytes.len().min(len as usize);
+                        return Ok(bytes[..end].to_vec());
+                    }
+                    Err(e) => last_error = e.to_string(),
+                },
+                // Ranges past the end of the blob
+                416 => return Ok(Vec::new()),
+                status if resp.status().is_success() => match resp.bytes().await {
+                    Ok(bytes) if compute_sha256(&bytes) == hash => {
+                        let start = (offset as usize).min(bytes.len());
+                        let end = offset.saturating_add(len).min(bytes.len() as u64) as usize;
+                        return Ok(bytes[start..end].to_vec());
+                    }
+                    Ok(_) => last_error = format!("hash mismatch from {}", server),
+                    Err(e) => last_error = format!("{} returned {}: {}", server, status, e),
+                },
+                _ => {
+                    last_error = format!("{} returned {}", server, resp.status());
+                    debug!(
+                        "Range download {} from {} returned status {}",
+                        hash,
+                        server,
+                        resp.status()
+                    );
+                }
+            }
+        }
+
+        Err(BlossomError::DownloadFailed(last_error))
+    }
+
+    /// Delete a blob from all write servers (BUD-02)
+    /// Returns how many servers no longer have it; one that never had it counts
+    pub async fn delete(&self, hash: &str) -> Result<usize, BlossomError> {
+        if self.write_servers.is_empty() {
+            return Err(BlossomError::NoServers);
+        }
+
+        let auth_header = self.create_auth("delete", hash).await?;
+        let mut deleted = 0;
+        let mut last_error = String::new();
+
+        for server in &self.write_servers {
+            let url = format!("{}/{}", server.trim_end_matches('/'), hash);
+            match self
+                .http
+                .delete(&url)
+                .header("Authorization", &auth_header)
+                .send()
+                .await
+            {
+                Ok(resp) if resp.status().is_success() || resp.status().as_u16() == 404 => {
+                    debug!("Deleted {} from {}", &hash[..12.min(hash.len())], server);
+                    deleted += 1;
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let text = resp.text().await.unwrap_or_default();
+                    last_error = format!("{} returned {}: {}", server, status, text);
+                    warn!("Delete from {} failed: {} {}", server, status, text);
+                }
+                Err(e) => {
+                    last_error = format!("{}: {}", server, e);
+                    warn!("Delete from {} failed: {}", server, e);
+                }
+            }
+        }
+
+        if deleted == 0 {
+            return Err(BlossomError::DeleteFailed(last_error));
+        }
+        Ok(deleted)
+    }
+
     /// Download if available, returns None if not found
     pub async fn try_download(&self, hash: &str) -> Option<Vec<u8>> {
         self.download(hash).await.ok()
@@ -434,6 +611,11 @@ impl BlossomClient {
     }
 
     async fn create_upload_auth(&self, hash: &str) -> Result<String, BlossomError> {
+        self.create_auth("upload", hash).await
+    }
+
+    /// Authorization header for `verb` ("upload", "delete") on `hash`
+    async fn create_auth(&self, verb: &str, hash: &str) -> Result<String, BlossomError> {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
@@ -441,14 +623,18 @@ impl BlossomClient {
         let expiration = now + 300; // 5 minutes
 
         let tags = vec![
-            Tag::custom(TagKind::custom("t"), vec!["upload".to_string()]),
+            Tag::custom(TagKind::custom("t"), vec![verb.to_string()]),
             Tag::custom(TagKind::custom("x"), vec![hash.to_string()]),
             Tag::custom(
                 TagKind::custom("expiration"),
                 vec![expiration.to_string()],
             ),
         ];
-        let event = EventBuilder::new(Kind::Custom(24242), "Upload", tags)
+        let content = match verb {
+            "delete" => "Delete",
+            _ => "Upload",
+        };
+        let event = EventBuilder::new(Kind::Custom(24242), content, tags)
             .to_event(&self.keys)
             .map_err(|e| BlossomError::Signing(e.to_string()))?;
 
@@ -470,6 +656,7 @@ pub fn compute_sha256(data: &[u8]) -> String {
 mod store_impl {
     use super::*;
     use async_trait::async_trait;
+    use hashtree_core::store::slice_range;
     use hashtree_core::{to_hex, Hash, Store, StoreError};
     use std::collections::HashMap;
     use std::sync::RwLock;
@@ -539,6 +726,20 @@ mod store_impl {
             }
         }
 
+        /// Served from the whole blob, which is checked against its hash;
+        /// a `download_range` response couldn't be
+        async fn get_range(
+            &self,
+            hash: &Hash,
+            offset: u64,
+            len: u64,
+        ) -> Result<Option<Vec<u8>>, StoreError> {
+            Ok(self
+                .get(hash)
+                .await?
+                .map(|data| slice_range(&data, offset, len).to_vec()))
+        }
+
         async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
             let key = to_hex(hash);
 
@@ -554,6 +755,23 @@ mod store_impl {
             Ok(self.client.exists(&key).await)
         }
 
+        async fn has_many(&self, hashes: &[Hash]) -> Result<Vec<bool>, StoreError> {
+            let keys: Vec<String> = hashes.iter().map(to_hex).collect();
+            let mut found: Vec<bool> = {
+                let cache = self.cache.read().unwrap();
+                keys.iter().map(|key| cache.contains_key(key)).collect()
+            };
+
+            // Ask Blossom about the rest in one pipelined batch
+            let missing: Vec<usize> = (0..keys.len()).filter(|&i| !found[i]).collect();
+            let missing_keys: Vec<&str> = missing.iter().map(|&i| keys[i].as_str()).collect();
+            let remote = self.client.exists_many(&missing_keys).await;
+            for (i, exists) in missing.into_iter().zip(remote) {
+                found[i] = exists;
+            }
+            Ok(found)
+        }
+
         async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
             // Only delete from local cache (can't delete from Blossom)
             let key = to_hex(hash);
@@ -624,6 +842,85 @@ mod tests {
         assert!(result.is_err()); // Expected to fail - servers don't exist
     }
 
+    /// Answer one request like a Blossom server serving byte ranges of
+    /// `blob`; the task returns the request it got
+    async fn range_server(blob: &'static [u8]) -> (String, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+            let range = request
+                .lines()
+                .find_map(|line| line.strip_prefix("range: bytes="))
+                .unwrap();
+            let (start, end) = range.split_once('-').unwrap();
+            let start: usize = start.parse().unwrap();
+            let end = end.parse::<usize>().unwrap().min(blob.len() - 1);
+            let body = &blob[start..=end];
+            let head = format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                body.len(),
+                start,
+                end,
+                blob.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body).await.unwrap();
+            request
+        });
+        (url, task)
+    }
+
+    #[tokio::test]
+    async fn test_download_range() {
+        let blob = b"hello world";
+        let hash = compute_sha256(blob);
+        let (url, server) = range_server(blob).await;
+        let client = BlossomClient::new_empty(Keys::generate()).with_servers(vec![url]);
+
+        let data = client.download_range(&hash, 6, 100).await.unwrap();
+        assert_eq!(data, b"world");
+        let request = server.await.unwrap();
+        assert!(request.starts_with(&format!("get /{}.bin", hash)));
+        assert!(request.contains("range: bytes=6-105"));
+
+        let client = BlossomClient::new_empty(Keys::generate());
+        assert!(matches!(
+            client.download_range(&hash, 0, 1).await,
+            Err(BlossomError::NoServers)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_auth() {
+        let keys = Keys::generate();
+        let client = BlossomClient::new_empty(keys.clone());
+        let hash = compute_sha256(b"gone");
+
+        let header = client.create_auth("delete", &hash).await.unwrap();
+        let json = base64::engine::general_purpose::STANDARD
+            .decode(header.strip_prefix("Nostr ").unwrap())
+            .unwrap();
+        let event = Event::from_json(json).unwrap();
+        assert!(event.verify().is_ok());
+        assert_eq!(event.pubkey, keys.public_key());
+        assert_eq!(event.kind, Kind::Custom(24242));
+        let tags: Vec<Vec<String>> = event.tags.iter().map(|t| t.as_slice().to_vec()).collect();
+        assert!(tags.contains(&vec!["t".to_string(), "delete".to_string()]));
+        assert!(tags.contains(&vec!["x".to_string(), hash]));
+
+        // No write servers, nothing to delete from
+        assert!(matches!(
+            client.delete("abc123").await,
+            Err(BlossomError::NoServers)
+        ));
+    }
+
     #[test]
     fn test_local_daemon_priority() {
         let keys = Keys::generate();
@@ -0,0 +1,311 @@
+//! Tar import and export
+//!
+//! `htree import --stdin-tar` stores a tar stream as a directory tree and
+//! `htree export --tar` writes a tree back out as one, so hashtree composes
+//! with standard tools: `tar c dir | htree import --stdin-tar`. File data is
+//! streamed in both directions. Only regular files and directories are
+//! kept; modes, owners and times aren't, and exported entries get fixed ones.
+
+use anyhow::{Context, Result};
+use futures::io::AllowStdIo;
+use futures::StreamExt;
+use hashtree_core::{Cid, DirEntry, HashTree, LinkType, Store};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Component, Path};
+use tar::{Archive, Builder, EntryType, Header};
+
+/// Outcome of importing a tar stream
+#[derive(Debug, Clone)]
+pub struct TarImport {
+    /// Root directory of the imported tree
+    pub cid: Cid,
+    pub files: usize,
+    pub bytes: u64,
+    /// Links, devices and other entries that aren't files or directories
+    pub skipped: usize,
+}
+
+/// A directory being built from tar entries
+#[derive(Default)]
+struct Dir {
+    files: BTreeMap<String, DirEntry>,
+    dirs: BTreeMap<String, Dir>,
+}
+
+impl Dir {
+    /// The directory at `path` below this one, created if missing. A file
+    /// in the way is replaced, as extracting the archive would.
+    fn dir(&mut self, path: &[String]) -> &mut Dir {
+        path.iter().fold(self, |dir, name| {
+            dir.files.remove(name);
+            dir.dirs.entry(name.clone()).or_default()
+        })
+    }
+}
+
+/// Store the tar stream `reader` as a directory tree
+pub async fn import_tar<S: Store, R: Read>(tree: &HashTree<S>, reader: R) -> Result<TarImport> {
+    let mut archive = Archive::new(reader);
+    let mut root = Dir::default();
+    let (mut files, mut bytes, mut skipped) = (0, 0, 0);
+
+    for entry in archive.entries().context("Failed to read tar stream")? {
+        let mut entry = entry.context("Failed to read tar entry")?;
+        let path = entry.path().context("Invalid tar entry path")?.into_owned();
+        let mut components = components(&path)?;
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                root.dir(&components);
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let Some(name) = components.pop() else {
+                    continue;
+                };
+                let (cid, size) = tree
+                    .put_stream(AllowStdIo::new(&mut entry))
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to add {}: {}", path.display(), e))?;
+                // Larger files are chunked under a file node
+                let link_type = if size > tree.chunk_size() as u64 {
+                    LinkType::File
+                } else {
+                    LinkType::Blob
+                };
+                let dir = root.dir(&components);
+                dir.dirs.remove(&name);
+                let file = DirEntry::from_cid(name.clone(), &cid)
+                    .with_size(size)
+                    .with_link_type(link_type);
+                dir.files.insert(name, file);
+                files += 1;
+                bytes += size;
+            }
+            _ => skipped += 1,
+        }
+    }
+
+    let cid = put_dir(tree, root).await?;
+    Ok(TarImport {
+        cid,
+        files,
+        bytes,
+        skipped,
+    })
+}
+
+/// Names in a tar entry path; a leading `/` is dropped, `..` is refused
+fn components(path: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = name
+                    .to_str()
+                    .with_context(|| format!("Non-UTF-8 tar entry path: {}", path.display()))?;
+                names.push(name.to_string());
+            }
+            Component::ParentDir => {
+                anyhow::bail!("Tar entry path escapes the archive: {}", path.display())
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    Ok(names)
+}
+
+async fn put_dir<S: Store>(tree: &HashTree<S>, dir: Dir) -> Result<Cid> {
+    let mut entries: Vec<DirEntry> = dir.files.into_values().collect();
+    for (name, sub) in dir.dirs {
+        let cid = Box::pin(put_dir(tree, sub)).await?;
+        entries.push(DirEntry::from_cid(name, &cid).with_link_type(LinkType::Dir));
+    }
+    tree.put_directory(entries)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to create directory node: {}", e))
+}
+
+/// Write the tree at `root` to `writer` as a tar stream. A directory's
+/// entries are written at their paths within it; a file is written as a
+/// single entry called `name`.
+pub async fn export_tar<S: Store, W: Write>(
+    tree: &HashTree<S>,
+    root: &Cid,
+    name: &str,
+    writer: W,
+) -> Result<W> {
+    let mut builder = Builder::new(writer);
+    if tree.is_dir(root).await? {
+        export_dir(tree, root, "", &mut builder).await?;
+    } else {
+        let size = tree.get_size_cid(root).await?;
+        append_file(tree, root, name, size, &mut builder).await?;
+    }
+    builder.into_inner().context("Failed to finish tar stream")
+}
+
+async fn export_dir<S: Store, W: Write>(
+    tree: &HashTree<S>,
+    cid: &Cid,
+    path: &str,
+    builder: &mut Builder<W>,
+) -> Result<()> {
+    for entry in tree.list_directory(cid).await? {
+        let child = Cid {
+            hash: entry.hash,
+            key: entry.key,
+        };
+        let child_path = if path.is_empty() {
+            entry.name.clone()
+        } else {
+            format!("{}/{}", path, entry.name)
+        };
+        // Trees added without link types only tell directories by content
+        let is_dir = match entry.link_type {
+            LinkType::Dir => true,
+            LinkType::File => false,
+            LinkType::Blob => tree.is_dir(&child).await?,
+        };
+
+        if is_dir {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            builder.append_data(&mut header, format!("{}/", child_path), std::io::empty())?;
+            Box::pin(export_dir(tree, &child, &child_path, builder)).await?;
+        } else {
+            let size = match entry.size {
+                0 => tree.get_size_cid(&child).await?,
+                size => size,
+            };
+            append_file(tree, &child, &child_path, size, builder).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Append the file `cid` of `size` bytes, streaming its chunks
+async fn append_file<S: Store, W: Write>(
+    tree: &HashTree<S>,
+    cid: &Cid,
+    path: &str,
+    size: u64,
+    builder: &mut Builder<W>,
+) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_mode(0o644);
+    header.set_size(size);
+    // Writes only the header (and a long name entry if needed); the data
+    // follows, padded to a whole block like append_data would
+    builder.append_data(&mut header, path, std::io::empty())?;
+    let out = builder.get_mut();
+
+    let mut written = 0u64;
+    let mut chunks = tree.get_stream(cid);
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk?;
+        written += chunk.len() as u64;
+        out.write_all(&chunk)?;
+    }
+    if written != size {
+        anyhow::bail!(
+            "{} has {} bytes, but its link records {}",
+            path,
+            written,
+            size
+        );
+    }
+    let padding = (512 - size % 512) % 512;
+    out.write_all(&[0; 512][..padding as usize])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::{HashTreeConfig, MemoryStore};
+    use std::sync::Arc;
+
+    fn tar_of(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "./empty/", std::io::empty())
+            .unwrap();
+        for (path, data) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            builder
+                .append_data(&mut header, path, data.as_slice())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tar_round_trip() {
+        // Small enough chunks for the first file to be chunked
+        let config = HashTreeConfig::new(Arc::new(MemoryStore::new())).with_chunk_size(1024);
+        let tree = HashTree::new(config);
+        let big = b"hello world".repeat(200);
+        // Needs a long name entry, over 100 bytes
+        let long_dir = format!("{}/{}", "d".repeat(60), "e".repeat(60));
+        let long = format!("{}/deep.txt", long_dir);
+        let input = tar_of(&[
+            ("./a.txt", big.clone()),
+            ("docs/b.txt", b"hi".to_vec()),
+            (&long, vec![]),
+        ]);
+
+        let imported = import_tar(&tree, input.as_slice()).await.unwrap();
+        assert_eq!((imported.files, imported.bytes), (3, 2202));
+        assert_eq!(imported.skipped, 0);
+
+        let output = export_tar(&tree, &imported.cid, "root", Vec::new())
+            .await
+            .unwrap();
+        let mut archive = Archive::new(output.as_slice());
+        let mut entries = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            entries.push((path, data));
+        }
+        let expected: Vec<(String, Vec<u8>)> = vec![
+            ("a.txt".into(), big),
+            (format!("{}/", "d".repeat(60)), vec![]),
+            (format!("{}/", long_dir), vec![]),
+            (long.clone(), vec![]),
+            ("docs/".into(), vec![]),
+            ("docs/b.txt".into(), b"hi".to_vec()),
+            ("empty/".into(), vec![]),
+        ];
+        assert_eq!(entries, expected);
+
+        // The same content imports to the same tree
+        let again = import_tar(&tree, output.as_slice()).await.unwrap();
+        assert_eq!(again.cid, imported.cid);
+    }
+
+    #[tokio::test]
+    async fn test_import_refuses_parent_paths() {
+        let tree = HashTree::new(HashTreeConfig::new(Arc::new(MemoryStore::new())));
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_size(1);
+        // append_data refuses `..`, so set the name directly
+        header.as_gnu_mut().unwrap().name[..7].copy_from_slice(b"../evil");
+        header.set_cksum();
+        builder.append(&header, &b"x"[..]).unwrap();
+        let input = builder.into_inner().unwrap();
+
+        let err = import_tar(&tree, input.as_slice()).await.unwrap_err();
+        assert!(err.to_string().contains("escapes"));
+    }
+}
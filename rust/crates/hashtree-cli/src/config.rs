@@ -1,8 +1,13 @@
 use anyhow::{Context, Result};
 use nostr::nips::nip19::{FromBech32, ToBech32};
+use nostr::nips::nip49::{EncryptedSecretKey, KeySecurity};
 use nostr::{Keys, SecretKey};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub use hashtree_config::{KeyEntry, StorageBackend};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -16,6 +21,8 @@ pub struct Config {
     pub blossom: BlossomConfig,
     #[serde(default)]
     pub sync: SyncConfig,
+    #[serde(default)]
+    pub gateway: GatewayConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +49,9 @@ fn default_public_writes() -> bool {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageConfig {
+    /// Blob storage backend: "fs" (default), "lmdb" or "pack"
+    #[serde(default)]
+    pub backend: StorageBackend,
     #[serde(default = "default_data_dir")]
     pub data_dir: String,
     #[serde(default = "default_max_size_gb")]
@@ -49,6 +59,9 @@ pub struct StorageConfig {
     /// Optional S3/R2 backend for blob storage
     #[serde(default)]
     pub s3: Option<S3Config>,
+    /// Chunk size in bytes for files added; the hashtree default if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
 }
 
 /// S3-compatible storage configuration (works with AWS S3, Cloudflare R2, MinIO, etc.)
@@ -86,6 +99,9 @@ pub struct NostrConfig {
     /// List of npubs allowed to write (blossom uploads). If empty, uses public_writes setting.
     #[serde(default)]
     pub allowed_npubs: Vec<String>,
+    /// Keys file to use instead of ~/.hashtree/keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keys_file: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -139,6 +155,41 @@ pub struct SyncConfig {
     pub blossom_timeout_ms: u64,
 }
 
+/// Read-only public gateway (`htree serve`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    #[serde(default = "default_gateway_listen")]
+    pub listen: String,
+    /// Max size of the local blob cache in MB
+    #[serde(default = "default_gateway_cache_mb")]
+    pub cache_mb: u64,
+    /// Seconds a resolved npub root is served before re-resolving
+    #[serde(default = "default_gateway_root_ttl_secs")]
+    pub root_ttl_secs: u64,
+    /// Requests per minute per client IP (0 = unlimited)
+    #[serde(default = "default_gateway_rate_limit")]
+    pub rate_limit_per_minute: u32,
+    /// Public URL of a gateway serving published trees, for the links
+    /// `htree deploy` prints (e.g. "https://gateway.example.com")
+    #[serde(default)]
+    pub public_url: Option<String>,
+}
+
+fn default_gateway_listen() -> String {
+    "0.0.0.0:8080".to_string()
+}
+
+fn default_gateway_cache_mb() -> u64 {
+    1024
+}
+
+fn default_gateway_root_ttl_secs() -> u64 {
+    60
+}
+
+fn default_gateway_rate_limit() -> u32 {
+    600
+}
 
 fn default_sync_enabled() -> bool {
     true
@@ -215,9 +266,11 @@ impl Default for ServerConfig {
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
+            backend: StorageBackend::default(),
             data_dir: default_data_dir(),
             max_size_gb: default_max_size_gb(),
             s3: None,
+            chunk_size: None,
         }
     }
 }
@@ -227,6 +280,7 @@ impl Default for NostrConfig {
         Self {
             relays: default_relays(),
             allowed_npubs: Vec::new(),
+            keys_file: None,
         }
     }
 }
@@ -255,6 +309,18 @@ impl Default for SyncConfig {
     }
 }
 
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_gateway_listen(),
+            cache_mb: default_gateway_cache_mb(),
+            root_ttl_secs: default_gateway_root_ttl_secs(),
+            rate_limit_per_minute: default_gateway_rate_limit(),
+            public_url: None,
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -263,19 +329,21 @@ impl Default for Config {
             nostr: NostrConfig::default(),
             blossom: BlossomConfig::default(),
             sync: SyncConfig::default(),
+            gateway: GatewayConfig::default(),
         }
     }
 }
 
 impl Config {
-    /// Load config from file, or create default if doesn't exist
+    /// Load config from file, or create default if doesn't exist.
+    /// The profile named by HTREE_PROFILE is applied on top.
     pub fn load() -> Result<Self> {
         let config_path = get_config_path();
 
         if config_path.exists() {
             let content = fs::read_to_string(&config_path)
                 .context("Failed to read config file")?;
-            toml::from_str(&content).context("Failed to parse config file")
+            hashtree_config::parse_config(&content)
         } else {
             let config = Config::default();
             config.save()?;
@@ -300,7 +368,10 @@ impl Config {
 }
 
 // Re-export path functions from hashtree_config
-pub use hashtree_config::{get_auth_cookie_path, get_config_path, get_hashtree_dir, get_keys_path};
+pub use hashtree_config::{
+    active_profile, get_auth_cookie_path, get_config_path, get_hashtree_dir, get_keys_path,
+    set_config_value,
+};
 
 /// Generate and save auth cookie if it doesn't exist
 pub fn ensure_auth_cookie() -> Result<(String, String)> {
@@ -330,86 +401,140 @@ pub fn read_auth_cookie() -> Result<(String, String)> {
 /// Ensure keys file exists, generating one if not present
 /// Returns (Keys, was_generated)
 pub fn ensure_keys() -> Result<(Keys, bool)> {
-    let keys_path = get_keys_path();
-
-    if keys_path.exists() {
-        let content = fs::read_to_string(&keys_path)
-            .context("Failed to read keys file")?;
-        let entries = hashtree_config::parse_keys_file(&content);
-        let nsec_str = entries.into_iter().next()
-            .map(|e| e.secret)
-            .context("Keys file is empty")?;
-        let secret_key = SecretKey::from_bech32(&nsec_str)
-            .context("Invalid nsec format")?;
-        let keys = Keys::new(secret_key);
-        Ok((keys, false))
+    if get_keys_path()?.exists() {
+        Ok((read_keys()?, false))
     } else {
-        let keys = generate_keys()?;
-        Ok((keys, true))
+        Ok((generate_keys()?, true))
     }
 }
 
-/// Read existing keys
+/// Read existing keys, the first entry of the keys file
 pub fn read_keys() -> Result<Keys> {
-    let keys_path = get_keys_path();
-    let content = fs::read_to_string(&keys_path)
-        .context("Failed to read keys file")?;
-    let entries = hashtree_config::parse_keys_file(&content);
-    let nsec_str = entries.into_iter().next()
-        .map(|e| e.secret)
+    let entry = read_key_entries()?
+        .into_iter()
+        .next()
         .context("Keys file is empty")?;
-    let secret_key = SecretKey::from_bech32(&nsec_str)
-        .context("Invalid nsec format")?;
-    Ok(Keys::new(secret_key))
+    Ok(Keys::new(entry_secret_key(&entry.secret)?))
 }
 
 /// Get nsec string, ensuring keys file exists (generate if needed)
 /// Returns (nsec_string, was_generated)
 pub fn ensure_keys_string() -> Result<(String, bool)> {
-    let keys_path = get_keys_path();
-
-    if keys_path.exists() {
-        let content = fs::read_to_string(&keys_path)
-            .context("Failed to read keys file")?;
-        let entries = hashtree_config::parse_keys_file(&content);
-        let nsec_str = entries.into_iter().next()
-            .map(|e| e.secret)
-            .context("Keys file is empty")?;
-        Ok((nsec_str, false))
-    } else {
-        let keys = generate_keys()?;
-        let nsec = keys.secret_key().to_bech32()
-            .context("Failed to encode nsec")?;
-        Ok((nsec, true))
-    }
+    let (keys, was_generated) = ensure_keys()?;
+    let nsec = keys.secret_key().to_bech32()
+        .context("Failed to encode nsec")?;
+    Ok((nsec, was_generated))
 }
 
 /// Generate new keys and save to file
 pub fn generate_keys() -> Result<Keys> {
-    let keys_path = get_keys_path();
+    let keys = Keys::generate();
+    let nsec = keys.secret_key().to_bech32()
+        .context("Failed to encode nsec")?;
+    write_key_entries(&[KeyEntry { secret: nsec, alias: None }])?;
+    Ok(keys)
+}
+
+/// Entries of the keys file, none if it doesn't exist
+pub fn read_key_entries() -> Result<Vec<KeyEntry>> {
+    let keys_path = get_keys_path()?;
+    if !keys_path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(&keys_path)
+        .context("Failed to read keys file")?;
+    Ok(hashtree_config::parse_keys_file(&content))
+}
+
+/// Replace the keys file with `entries`, readable by the owner only
+pub fn write_key_entries(entries: &[KeyEntry]) -> Result<()> {
+    let keys_path = get_keys_path()?;
 
     // Ensure parent directory exists
     if let Some(parent) = keys_path.parent() {
         fs::create_dir_all(parent)?;
     }
 
-    // Generate new keys
-    let keys = Keys::generate();
-    let nsec = keys.secret_key().to_bech32()
-        .context("Failed to encode nsec")?;
-
-    // Save to file
-    fs::write(&keys_path, &nsec)?;
-
-    // Set permissions to 0600 (owner read/write only)
+    // Written to a new file that only the owner can read, then renamed
+    // over the keys file, so the keys are never exposed or half written
+    let mut tmp_path = keys_path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let _ = fs::remove_file(&tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
     #[cfg(unix)]
     {
-        use std::os::unix::fs::PermissionsExt;
-        let perms = fs::Permissions::from_mode(0o600);
-        fs::set_permissions(&keys_path, perms)?;
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
     }
+    let mut file = options.open(&tmp_path).context("Failed to create keys file")?;
+    file.write_all(hashtree_config::format_keys_file(entries).as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &keys_path).context("Failed to replace keys file")?;
 
-    Ok(keys)
+    Ok(())
+}
+
+/// Make `entry` the active key (the first), keeping the others after it
+pub fn add_active_key(mut entry: KeyEntry) -> Result<()> {
+    let mut entries = read_key_entries()?;
+    if let Some(pos) = entries.iter().position(|e| e.secret == entry.secret) {
+        let existing = entries.remove(pos);
+        entry.alias = entry.alias.or(existing.alias);
+    }
+    entries.insert(0, entry);
+    write_key_entries(&entries)
+}
+
+/// scrypt cost (log2 of N) of keys encrypted with a passphrase
+const KEY_LOG_N: u8 = 16;
+
+/// Secret key of a keys file entry: an nsec, hex, or NIP-49 ncryptsec,
+/// which is decrypted with the passphrase
+pub fn entry_secret_key(secret: &str) -> Result<SecretKey> {
+    if secret.starts_with("ncryptsec1") {
+        let passphrase = read_passphrase("Passphrase for your nostr key: ", false)?;
+        return decrypt_secret_key(secret, &passphrase);
+    }
+    SecretKey::parse(secret).context("Invalid nsec format")
+}
+
+/// Encrypt `secret_key` with `passphrase` as a NIP-49 ncryptsec
+pub fn encrypt_secret_key(secret_key: &SecretKey, passphrase: &str) -> Result<String> {
+    EncryptedSecretKey::new(secret_key, passphrase, KEY_LOG_N, KeySecurity::Unknown)
+        .map_err(|e| anyhow::anyhow!("Failed to encrypt key: {}", e))?
+        .to_bech32()
+        .context("Failed to encode ncryptsec")
+}
+
+/// Decrypt a NIP-49 ncryptsec with `passphrase`
+pub fn decrypt_secret_key(ncryptsec: &str, passphrase: &str) -> Result<SecretKey> {
+    EncryptedSecretKey::from_bech32(ncryptsec)
+        .context("Invalid ncryptsec format")?
+        .to_secret_key(passphrase)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase for encrypted key"))
+}
+
+/// Passphrase from HTREE_PASSPHRASE, or else prompted for on the terminal.
+/// `confirm` asks twice, for setting a new passphrase.
+pub fn read_passphrase(prompt: &str, confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(hashtree_config::PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password(prompt)
+        .context("Failed to read passphrase")?;
+    if confirm {
+        if passphrase.is_empty() {
+            anyhow::bail!("Passphrase can't be empty");
+        }
+        let again = rpassword::prompt_password("Repeat passphrase: ")
+            .context("Failed to read passphrase")?;
+        if again != passphrase {
+            anyhow::bail!("Passphrases don't match");
+        }
+    }
+    Ok(passphrase)
 }
 
 /// Get 32-byte pubkey bytes from Keys (for nostrdb)
@@ -476,6 +601,38 @@ mod tests {
         assert_eq!(config.server.bind_address, "127.0.0.1:8080");
         assert_eq!(config.server.enable_auth, true);
         assert_eq!(config.storage.max_size_gb, 10);
+        assert_eq!(config.gateway.listen, "0.0.0.0:8080");
+    }
+
+    #[test]
+    fn test_gateway_config_partial() {
+        let config: Config = toml::from_str("[gateway]\nrate_limit_per_minute = 0\n").unwrap();
+        assert_eq!(config.gateway.rate_limit_per_minute, 0);
+        assert_eq!(config.gateway.cache_mb, 1024);
+        assert_eq!(config.gateway.public_url, None);
+    }
+
+    #[test]
+    fn test_profile_applies_to_cli_config() {
+        let toml = "[gateway]\ncache_mb = 10\n[profiles.small.gateway]\ncache_mb = 1\n";
+        let config: Config = hashtree_config::parse_config_with_profile(toml, None).unwrap();
+        assert_eq!(config.gateway.cache_mb, 10);
+        let config: Config =
+            hashtree_config::parse_config_with_profile(toml, Some("small")).unwrap();
+        assert_eq!(config.gateway.cache_mb, 1);
+        assert_eq!(config.gateway.listen, "0.0.0.0:8080");
+    }
+
+    #[test]
+    fn test_encrypted_key_round_trip() {
+        let keys = Keys::generate();
+        let ncryptsec = encrypt_secret_key(keys.secret_key(), "hunter2").unwrap();
+        assert!(ncryptsec.starts_with("ncryptsec1"));
+
+        let decrypted = decrypt_secret_key(&ncryptsec, "hunter2").unwrap();
+        assert_eq!(decrypted, *keys.secret_key());
+        assert!(decrypt_secret_key(&ncryptsec, "wrong").is_err());
+        assert!(decrypt_secret_key("nsec1abc", "hunter2").is_err());
     }
 
     #[test]
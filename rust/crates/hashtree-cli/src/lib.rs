@@ -1,8 +1,14 @@
+pub mod archive;
 pub mod config;
 pub mod fetch;
+pub mod mirror;
+pub mod output;
+pub mod remote;
+pub mod rpc;
 pub mod server;
 pub mod storage;
 pub mod sync;
+pub mod verify;
 
 #[cfg(feature = "p2p")]
 pub mod webrtc;
@@ -3,21 +3,35 @@
 //! Usage:
 //!   htree start [--addr 127.0.0.1:8080] [--daemon]
 //!   htree stop [--pid-file <path>]
+//!   htree serve [--listen 0.0.0.0:8080] [--relays <urls>] [--blossom <urls>]
 //!   htree add <path> [--only-hash] [--public] [--no-ignore] [--publish <ref_name>]
 //!   htree get <cid> [-o output]
-//!   htree cat <cid>
+//!   htree import [--stdin-tar] [--public]
+//!   htree export <cid> [--tar]
+//!   htree cat <cid> [--range <start-end>]
+//!   htree ls <path> [--long]
+//!   htree stat <path>
+//!   htree resolve <nhash|npub/tree/path>
+//!   htree du <path> [--largest <n>] [--all]
+//!   htree verify <root> [--local]
 //!   htree pins
 //!   htree pin <cid>
 //!   htree unpin <cid>
 //!   htree info <cid>
 //!   htree stats
-//!   htree gc
+//!   htree gc [--dry-run]
 //!   htree user [<nsec>]
 //!   htree publish <ref_name> <hash> [--key <key>]
+//!   htree deploy <dir> --tree <name> [--alias <name>] [--gateway <url>]
+//!   htree rotate-key <tree> [--link-key <key>]
+//!
+//! Most commands that report a result take `--json` to print it as JSON.
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use hashtree_cli::config::{ensure_auth_cookie, ensure_keys, ensure_keys_string, parse_npub, pubkey_bytes};
+use hashtree_cli::config::{ensure_auth_cookie, ensure_keys, ensure_keys_string, parse_npub, pubkey_bytes, StorageBackend};
+use hashtree_cli::output;
+use hashtree_cli::storage::migrate_blobs;
 use hashtree_cli::{
     BackgroundSync, Config, HashtreeServer, HashtreeStore,
     NostrKeys, NostrResolverConfig, NostrRootResolver, NostrToBech32, RootResolver,
@@ -42,22 +56,34 @@ struct Cli {
     #[arg(long, global = true, env = "HTREE_DATA_DIR")]
     data_dir: Option<PathBuf>,
 
+    /// Write logs as JSON lines, with span timings
+    #[arg(long, global = true, env = "HTREE_LOG_JSON")]
+    log_json: bool,
+
+    /// Config profile to apply ([profiles.<name>] in config.toml)
+    #[arg(long, global = true, env = "HTREE_PROFILE")]
+    profile: Option<String>,
+
+    /// Print the result as JSON, for scripts (see `hashtree_cli::output`)
+    #[arg(long, global = true)]
+    json: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 impl Cli {
-    /// Get the data directory, defaulting to ~/.hashtree/data
+    /// Get the data directory, defaulting to the config's (~/.hashtree/data)
     fn data_dir(&self) -> PathBuf {
-        self.data_dir.clone().unwrap_or_else(|| {
-            hashtree_cli::config::get_hashtree_dir().join("data")
-        })
+        self.data_dir.clone().unwrap_or_else(hashtree_config::get_data_dir)
     }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Start the hashtree daemon
+    /// Start the hashtree daemon, with a JSON-RPC API at /rpc that other
+    /// htree commands use when it's running
+    #[command(visible_alias = "daemon")]
     Start {
         #[arg(long, default_value = "127.0.0.1:8080")]
         addr: String,
@@ -116,6 +142,10 @@ enum Commands {
         /// Don't push to file servers (local only)
         #[arg(long)]
         local: bool,
+        /// Same contents, same hash on any machine: default chunking whatever
+        /// the config, and only ignore files inside the directory apply
+        #[arg(long)]
+        reproducible: bool,
     },
     /// Get/download content by CID
     Get {
@@ -125,16 +155,75 @@ enum Commands {
         #[arg(short, long)]
         output: Option<PathBuf>,
     },
+    /// Store stdin as a file, or with --stdin-tar a tar archive as a directory
+    Import {
+        /// Read a tar archive (e.g. `tar c dir | htree import --stdin-tar`)
+        #[arg(long)]
+        stdin_tar: bool,
+        /// Store without encryption (public, unencrypted)
+        #[arg(long)]
+        public: bool,
+    },
+    /// Write a file, or with --tar a whole tree, to stdout
+    Export {
+        /// CID or path to export (nhash1..., <hash[:key]>, npub1.../tree/path)
+        cid: String,
+        /// Write a tar archive (e.g. `htree export <cid> --tar > out.tar`)
+        #[arg(long)]
+        tar: bool,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
     /// Output file content to stdout (like cat)
     Cat {
-        /// CID to read
+        /// CID or path to read (nhash1..., <hash[:key]>, npub1.../tree/path)
         cid: String,
+        /// Only output bytes START-END (inclusive; START- reads to the end)
+        #[arg(long)]
+        range: Option<String>,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
+    /// List a directory, fetching from file servers what isn't local
+    Ls {
+        /// Directory to list (nhash1..., <hash[:key]>, npub1.../tree/path)
+        path: String,
+        /// Show entry hashes
+        #[arg(short, long)]
+        long: bool,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
+    /// Show the type, size and hash of a path
+    Stat {
+        /// Path to inspect (nhash1..., <hash[:key]>, npub1.../tree/path)
+        path: String,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
+    /// Show disk usage of a tree: totals, largest files and size per directory
+    Du {
+        /// Tree or directory (nhash1..., <hash[:key]>, npub1.../tree/path)
+        path: String,
+        /// Number of largest files to list
+        #[arg(long, default_value = "10")]
+        largest: usize,
+        /// List every directory, not just the top level
+        #[arg(short, long)]
+        all: bool,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
     },
     /// List all pinned CIDs
     Pins,
-    /// Pin a CID
+    /// Pin a tree, keeping all of its local blocks through GC and eviction
     Pin {
-        /// CID to pin
+        /// CID to pin (nhash1..., <hash[:key]>, npub1.../tree)
         cid: String,
     },
     /// Unpin a CID
@@ -142,11 +231,30 @@ enum Commands {
         /// CID to unpin
         cid: String,
     },
+    /// Resolve an nhash, npub/tree path or htree:// URL to a CID
+    Resolve {
+        /// What to resolve (nhash1..., <hash[:key]>, npub1.../tree/path)
+        input: String,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
     /// Get information about a CID
     Info {
         /// CID to inspect
         cid: String,
     },
+    /// Check every block of a tree, exiting non-zero if any is missing or corrupt
+    Verify {
+        /// Root to verify (nhash1..., <hash[:key]>, npub1.../tree/path)
+        root: String,
+        /// Only check local storage, not file servers
+        #[arg(long)]
+        local: bool,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
     /// Get storage statistics
     Stats,
     /// Show daemon status (peers, storage, etc.)
@@ -161,13 +269,49 @@ enum Commands {
         #[arg(long)]
         pid_file: Option<PathBuf>,
     },
-    /// Run garbage collection
-    Gc,
+    /// Run a public read-only gateway for npub and nhash paths
+    Serve {
+        /// Address to listen on (default: [gateway] listen, 0.0.0.0:8080)
+        #[arg(long)]
+        listen: Option<String>,
+        /// Override Nostr relays (comma-separated)
+        #[arg(long)]
+        relays: Option<String>,
+        /// Override Blossom servers to fetch from (comma-separated)
+        #[arg(long)]
+        blossom: Option<String>,
+        /// Max size of the local blob cache in MB
+        #[arg(long)]
+        cache_mb: Option<u64>,
+        /// Requests per minute per client IP (0 = unlimited)
+        #[arg(long)]
+        rate_limit: Option<u32>,
+    },
+    /// Delete local blocks that aren't part of a pinned tree
+    Gc {
+        /// Only report what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Re-hash local blobs, quarantine corrupted ones and re-fetch them
+    Scrub {
+        /// Delete corrupted blobs instead of keeping them in <data-dir>/quarantine
+        #[arg(long)]
+        delete: bool,
+        /// Don't try to fetch fresh copies from file servers
+        #[arg(long)]
+        no_refetch: bool,
+    },
     /// Show or set your nostr identity
     User {
         /// npub or nsec to set as active identity (omit to show current)
         identity: Option<String>,
     },
+    /// Manage the nostr keys used for publishing
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
     /// Publish a hash to Nostr under a ref name
     Publish {
         /// The ref name to publish under (e.g., "mydata" -> npub.../mydata)
@@ -178,6 +322,36 @@ enum Commands {
         #[arg(long)]
         key: Option<String>,
     },
+    /// Deploy a static site: add a directory publicly, push it to file
+    /// servers and publish it as one of your trees
+    Deploy {
+        /// Directory to deploy (e.g. ./dist)
+        path: PathBuf,
+        /// Tree name to publish under (e.g., "site" -> npub.../site)
+        #[arg(long)]
+        tree: String,
+        /// Also point this tree at the same root (e.g. "production")
+        #[arg(long)]
+        alias: Option<String>,
+        /// Gateway base URL for the printed link [default: gateway.public_url]
+        #[arg(long)]
+        gateway: Option<String>,
+        /// Include files ignored by .gitignore
+        #[arg(long)]
+        no_ignore: bool,
+        /// Build the tree reproducibly, as `htree add --reproducible` does
+        #[arg(long)]
+        reproducible: bool,
+    },
+    /// Re-encrypt one of your private trees under a new key and republish it
+    /// (use after a share link or key has leaked)
+    RotateKey {
+        /// Tree name (e.g., "mydata" -> npub.../mydata)
+        tree: String,
+        /// Current link key, for link-visible trees (hex). A new one is generated.
+        #[arg(long)]
+        link_key: Option<String>,
+    },
     /// Follow a user (adds to your contact list)
     Follow {
         /// npub of user to follow
@@ -210,7 +384,13 @@ enum Commands {
         #[arg(long, short)]
         server: Option<String>,
     },
-    /// Manage storage limits and eviction
+    /// Work with file servers (Blossom)
+    Blossom {
+        #[command(subcommand)]
+        command: BlossomCommands,
+    },
+    /// Manage storage limits, eviction and backends
+    #[command(alias = "store")]
     Storage {
         #[command(subcommand)]
         command: StorageCommands,
@@ -223,6 +403,32 @@ enum Commands {
     },
 }
 
+impl Commands {
+    /// Whether the command can print its result as JSON (`--json`)
+    fn supports_json(&self) -> bool {
+        matches!(
+            self,
+            Commands::Add { .. }
+                | Commands::Import { .. }
+                | Commands::Resolve { .. }
+                | Commands::Ls { .. }
+                | Commands::Stat { .. }
+                | Commands::Du { .. }
+                | Commands::Verify { .. }
+                | Commands::Pins
+                | Commands::Pin { .. }
+                | Commands::Unpin { .. }
+                | Commands::Stats
+                | Commands::Gc { .. }
+                | Commands::Publish { .. }
+                | Commands::Deploy { .. }
+                | Commands::Push { .. }
+                | Commands::Blossom { .. }
+                | Commands::User { identity: None }
+        )
+    }
+}
+
 #[derive(Subcommand)]
 enum StorageCommands {
     /// Show storage usage statistics by priority tier
@@ -240,6 +446,67 @@ enum StorageCommands {
         #[arg(long)]
         r2: bool,
     },
+    /// Convert the blob store to another backend (fs, lmdb or pack)
+    Migrate {
+        /// Backend to convert to
+        to: StorageBackend,
+    },
+}
+
+#[derive(Subcommand)]
+enum BlossomCommands {
+    /// Copy a tree's blocks that one file server lacks from another.
+    /// Run it again to resume an interrupted mirror.
+    Mirror {
+        /// Tree to mirror: nhash, hash[:key], npub/tree or htree:// URL
+        root: String,
+        /// File server to copy from
+        #[arg(long)]
+        from: String,
+        /// File server to copy to
+        #[arg(long)]
+        to: String,
+        /// Requests to run at a time
+        #[arg(long, short = 'j', default_value = "8")]
+        jobs: usize,
+        /// Link key of a link-visible tree (hex)
+        #[arg(long)]
+        link_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// Generate a new key and make it active (other keys are kept)
+    Generate {
+        /// Alias (petname) for the key
+        #[arg(long)]
+        alias: Option<String>,
+        /// Store it encrypted with a passphrase (NIP-49)
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Import an nsec, hex or NIP-49 ncryptsec key and make it active
+    Import {
+        /// The key, or - to read it from stdin
+        key: String,
+        /// Alias (petname) for the key
+        #[arg(long)]
+        alias: Option<String>,
+        /// Store it encrypted with a passphrase (NIP-49)
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Print the active secret key
+    Export {
+        /// Print it as a NIP-49 ncryptsec, encrypted with a new passphrase
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// List the keys in the keys file; the first is active
+    Show,
+    /// Encrypt the active key in the keys file with a passphrase (NIP-49)
+    Encrypt,
 }
 
 
@@ -326,6 +593,22 @@ async fn resolve_cid_input_with_opts(input: &str, opts: &ResolveOptions) -> Resu
             let key = format!("{}/{}", npub, repo);
             eprintln!("Resolving {}...", key);
 
+            // A running daemon resolves with relays it's already connected to
+            if !opts.private && opts.relays.is_none() {
+                if let Some(client) = hashtree_cli::rpc::RpcClient::detect() {
+                    match client.resolve(&key, opts.link_key.as_ref()).await {
+                        Ok(Some(cid)) => {
+                            eprintln!("Resolved to: {}", hashtree_core::to_hex(&cid.hash));
+                            return Ok(ResolvedCid { cid, path: subpath });
+                        }
+                        Ok(None) => anyhow::bail!("No content found for {}", key),
+                        Err(e) => {
+                            tracing::debug!("Daemon resolve failed, resolving directly: {}", e)
+                        }
+                    }
+                }
+            }
+
             let mut config = NostrResolverConfig::default();
             if let Some(relays) = &opts.relays {
                 config.relays = relays.clone();
@@ -361,6 +644,45 @@ async fn resolve_cid_input_with_opts(input: &str, opts: &ResolveOptions) -> Resu
     anyhow::bail!("Invalid format. Use nhash1..., <hash>, <hash:key>, or npub1.../name")
 }
 
+/// Resolve a CID input, with the hex `link_key` of a link-visible tree
+async fn resolve_cid_input_with_link_key(
+    input: &str,
+    link_key: Option<&str>,
+) -> Result<ResolvedCid> {
+    let link_key = link_key
+        .map(hashtree_core::key_from_hex)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid link key: {}", e))?;
+    let opts = ResolveOptions {
+        link_key,
+        ..Default::default()
+    };
+    resolve_cid_input_with_opts(input, &opts).await
+}
+
+/// Resolve `input` to the Cid of its path, in a tree read from local
+/// storage first and then file servers
+async fn open_remote_path(
+    input: &str,
+    link_key: Option<&str>,
+    data_dir: &std::path::Path,
+) -> Result<(
+    hashtree_core::HashTree<hashtree_cli::remote::RemoteStore>,
+    hashtree_core::Cid,
+)> {
+    use hashtree_cli::remote::{remote_tree, resolve};
+    use hashtree_cli::{FetchConfig, Fetcher};
+
+    let resolved = resolve_cid_input_with_link_key(input, link_key).await?;
+
+    // BlossomClient auto-loads servers from config
+    let store = HashtreeStore::new(data_dir)?;
+    let fetcher = Fetcher::new(FetchConfig::default());
+    let tree = remote_tree(store.store_arc(), fetcher.blossom().clone());
+    let cid = resolve(&tree, &resolved.cid, resolved.path.as_deref()).await?;
+    Ok((tree, cid))
+}
+
 #[cfg(feature = "fuse")]
 struct MountVisibility {
     visibility: hashtree_core::TreeVisibility,
@@ -613,15 +935,42 @@ async fn mount_fuse(
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     // Install rustls crypto provider (required for TLS connections)
     let _ = rustls::crypto::ring::default_provider().install_default();
 
+    let cli = Cli::parse();
+
+    // Config is loaded in many places, including the library; they all
+    // apply the profile named in the environment. It's set before the
+    // runtime starts any threads that could be reading the environment.
+    if let Some(profile) = &cli.profile {
+        std::env::set_var(hashtree_config::PROFILE_ENV, profile);
+        Config::load()?;
+    }
+
+    tokio::runtime::Runtime::new()?.block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> Result<()> {
+
     // Initialize tracing (respects RUST_LOG env var)
-    tracing_subscriber::fmt::init();
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+    if cli.log_json {
+        subscriber
+            .json()
+            .with_span_list(true)
+            .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+            .init();
+    } else {
+        subscriber.init();
+    }
 
-    let cli = Cli::parse();
+    if cli.json && !cli.command.supports_json() {
+        anyhow::bail!("--json is not supported by this command");
+    }
+    let json = cli.json;
 
     // Get data_dir early to avoid borrow issues in match arms
     let data_dir = cli.data_dir();
@@ -749,6 +1098,16 @@ async fn main() -> Result<()> {
                 .with_public_writes(config.server.public_writes)
                 .with_upstream_blossom(upstream_blossom);
 
+            // Keep one resolver connected for RPC clients
+            let rpc_resolver = NostrRootResolver::new(NostrResolverConfig {
+                relays: config.nostr.relays.clone(),
+                secret_key: Some(keys.clone()),
+                ..Default::default()
+            })
+            .await
+            .context("Failed to create nostr resolver")?;
+            server = server.with_resolver(Arc::new(rpc_resolver));
+
             // Add WebRTC peer state for P2P queries from HTTP handler
             if let Some(ref webrtc_state) = webrtc_state {
                 server = server.with_webrtc_peers(webrtc_state.clone());
@@ -828,6 +1187,7 @@ async fn main() -> Result<()> {
             }
             println!("Relays: {} configured", config.nostr.relays.len());
             println!("Git remote: http://{}/git/<pubkey>/<repo>", addr);
+            println!("JSON-RPC: http://{}{}", addr, hashtree_cli::rpc::RPC_PATH);
             #[cfg(feature = "p2p")]
             if let Some(ref handle) = stun_handle {
                 println!("STUN server: {}", handle.addr);
@@ -848,8 +1208,10 @@ async fn main() -> Result<()> {
                 println!("Background sync: enabled ({})", sync_features.join(", "));
             }
 
+            // RPC clients prove they're the user by reading the cookie
+            let (username, password) = ensure_auth_cookie()?;
+            server = server.with_rpc_auth(username.clone(), password.clone());
             if config.server.enable_auth {
-                let (username, password) = ensure_auth_cookie()?;
                 println!();
                 println!("Web UI: http://{}/#{}:{}", addr, username, password);
                 server = server.with_auth(username, password);
@@ -888,7 +1250,7 @@ async fn main() -> Result<()> {
         Commands::Mount { target, mountpoint, visibility, link_key, private, relays, allow_other } => {
             mount_fuse(target, mountpoint, visibility, link_key, private, relays, allow_other, data_dir).await?;
         }
-        Commands::Add { path, only_hash, public, no_ignore, publish, local } => {
+        Commands::Add { path, only_hash, public, no_ignore, publish, local, reproducible } => {
             let is_dir = path.is_dir();
 
             if only_hash {
@@ -904,19 +1266,31 @@ async fn main() -> Result<()> {
                 } else {
                     HashTreeConfig::new(store.clone())
                 };
+                // Same chunking as storing would use, for the same hash
+                let config = if reproducible {
+                    config.reproducible()
+                } else {
+                    match Config::load()?.storage.chunk_size {
+                        Some(chunk_size) => config.with_chunk_size(chunk_size),
+                        None => config,
+                    }
+                };
                 let tree = HashTree::new(config);
 
-                if is_dir {
+                let cid = if is_dir {
                     // For directories, use the recursive helper
-                    let cid = add_directory(&tree, &path, !no_ignore).await?;
-                    println!("hash: {}", to_hex(&cid.hash));
-                    if let Some(key) = cid.key {
-                        println!("key:  {}", to_hex(&key));
-                    }
+                    add_directory(&tree, &path, !no_ignore, reproducible).await?
                 } else {
                     let data = std::fs::read(&path)?;
                     let (cid, _size) = tree.put(&data).await
                         .map_err(|e| anyhow::anyhow!("Failed to hash file: {}", e))?;
+                    cid
+                };
+                if json {
+                    let mut value = output::cid_json(&cid);
+                    value["path"] = serde_json::json!(path.display().to_string());
+                    output::print(&value);
+                } else {
                     println!("hash: {}", to_hex(&cid.hash));
                     if let Some(key) = cid.key {
                         println!("key:  {}", to_hex(&key));
@@ -926,7 +1300,10 @@ async fn main() -> Result<()> {
                 // Store in local hashtree
                 use hashtree_core::{nhash_encode, nhash_encode_full, NHashData, from_hex, key_from_hex, Cid};
 
-                let store = HashtreeStore::new(&data_dir)?;
+                let mut store = HashtreeStore::new(&data_dir)?;
+                if reproducible {
+                    store = store.reproducible();
+                }
 
                 // Store and capture hash/key for potential publishing
                 let (hash_hex, key_hex): (String, Option<String>) = if public {
@@ -943,9 +1320,11 @@ async fn main() -> Result<()> {
                     let filename = path.file_name()
                         .map(|n| n.to_string_lossy().to_string())
                         .unwrap_or_default();
-                    println!("added {}", path.display());
-                    println!("  url:   {}/{}", nhash, filename);
-                    println!("  hash:  {}", hash_hex);
+                    if !json {
+                        println!("added {}", path.display());
+                        println!("  url:   {}/{}", nhash, filename);
+                        println!("  hash:  {}", hash_hex);
+                    }
                     (hash_hex, None)
                 } else {
                     let cid_str = if is_dir {
@@ -968,17 +1347,20 @@ async fn main() -> Result<()> {
                         hash,
                         path: vec![],
                         decrypt_key: key,
+                        ..Default::default()
                     };
                     let nhash = nhash_encode_full(&nhash_data)
                         .map_err(|e| anyhow::anyhow!("Failed to encode nhash: {}", e))?;
                     let filename = path.file_name()
                         .map(|n| n.to_string_lossy().to_string())
                         .unwrap_or_default();
-                    println!("added {}", path.display());
-                    println!("  url:   {}/{}", nhash, filename);
-                    println!("  hash:  {}", hash_hex);
-                    if let Some(ref k) = key_hex {
-                        println!("  key:   {}", k);
+                    if !json {
+                        println!("added {}", path.display());
+                        println!("  url:   {}/{}", nhash, filename);
+                        println!("  hash:  {}", hash_hex);
+                        if let Some(ref k) = key_hex {
+                            println!("  key:   {}", k);
+                        }
                     }
                     (hash_hex, key_hex)
                 };
@@ -1010,6 +1392,7 @@ async fn main() -> Result<()> {
                 }
 
                 // Publish to Nostr if --publish was specified
+                let mut published = None;
                 if let Some(ref_name) = publish {
                     // Load config for relay list
                     let config = Config::load()?;
@@ -1024,7 +1407,7 @@ async fn main() -> Result<()> {
                         .context("Failed to encode npub")?;
 
                     if was_generated {
-                        println!("  identity: {} (new)", npub);
+                        eprintln!("  identity: {} (new)", npub);
                     }
 
                     // Create resolver config with secret key for publishing
@@ -1050,7 +1433,10 @@ async fn main() -> Result<()> {
                     // Publish
                     match resolver.publish(&nostr_key, &cid).await {
                         Ok(_) => {
-                            println!("  published: {}", nostr_key);
+                            if !json {
+                                println!("  published: {}", nostr_key);
+                            }
+                            published = Some(nostr_key);
                         }
                         Err(e) => {
                             eprintln!("  publish failed: {}", e);
@@ -1062,6 +1448,7 @@ async fn main() -> Result<()> {
                 }
 
                 // Push to Blossom (unless --local)
+                let mut pushed = None;
                 if !local {
                     let config = Config::load()?;
                     // Combine legacy servers with write_servers for pushing
@@ -1069,11 +1456,36 @@ async fn main() -> Result<()> {
                     write_servers.extend(config.blossom.write_servers.clone());
                     if !write_servers.is_empty() {
                         // Await the upload to ensure it completes before exiting
-                        if let Err(e) = background_blossom_push(&data_dir, &hash_hex, &write_servers).await {
-                            eprintln!("  file server push failed: {}", e);
+                        match background_blossom_push(&data_dir, &hash_hex, &write_servers).await {
+                            Ok((uploaded, skipped)) => {
+                                if !json && (uploaded > 0 || skipped > 0) {
+                                    println!(
+                                        "  file servers: {} uploaded, {} already exist",
+                                        uploaded, skipped
+                                    );
+                                }
+                                pushed = Some((uploaded, skipped));
+                            }
+                            Err(e) => eprintln!("  file server push failed: {}", e),
                         }
                     }
                 }
+
+                if json {
+                    let hash = from_hex(&hash_hex).context("Invalid hash")?;
+                    let key = key_hex.as_ref().map(|k| key_from_hex(k)).transpose()
+                        .map_err(|e| anyhow::anyhow!("Invalid key: {}", e))?;
+                    let mut value = output::cid_json(&Cid { hash, key });
+                    value["path"] = serde_json::json!(path.display().to_string());
+                    value["published"] = serde_json::json!(published);
+                    value["pushed"] = match pushed {
+                        Some((uploaded, skipped)) => {
+                            serde_json::json!({ "uploaded": uploaded, "skipped": skipped })
+                        }
+                        None => serde_json::Value::Null,
+                    };
+                    output::print(&value);
+                }
             }
         }
         Commands::Get { cid: cid_input, output } => {
@@ -1172,31 +1584,215 @@ async fn main() -> Result<()> {
                 }
             }
         }
-        Commands::Cat { cid: cid_input } => {
-            use hashtree_cli::{FetchConfig, Fetcher};
-            use hashtree_core::to_hex;
+        Commands::Import { stdin_tar, public } => {
+            use hashtree_core::{nhash_encode_full, to_hex, NHashData};
 
-            // Resolve npub/repo or htree:// URLs to CID
-            let resolved = resolve_cid_input(&cid_input).await?;
-            let cid_hex = to_hex(&resolved.cid.hash);
-
-            let store = Arc::new(HashtreeStore::new(&data_dir)?);
+            let store = HashtreeStore::new(&data_dir)?;
+            let stdin = std::io::stdin().lock();
+            let (cid, counts) = if stdin_tar {
+                let imported = store.import_tar(stdin, public)?;
+                let counts = serde_json::json!({
+                    "files": imported.files,
+                    "bytes": imported.bytes,
+                    "skipped": imported.skipped,
+                });
+                if !json {
+                    println!(
+                        "imported {} files ({})",
+                        imported.files,
+                        format_bytes(imported.bytes)
+                    );
+                    if imported.skipped > 0 {
+                        println!(
+                            "  skipped {} links and other special entries",
+                            imported.skipped
+                        );
+                    }
+                }
+                (imported.cid, counts)
+            } else {
+                let (cid, size) = store.import_stream(stdin, public)?;
+                if !json {
+                    println!("imported {}", format_bytes(size));
+                }
+                (cid, serde_json::json!({ "bytes": size }))
+            };
+            if json {
+                let mut value = output::cid_json(&cid);
+                for (field, count) in counts.as_object().into_iter().flatten() {
+                    value[field] = count.clone();
+                }
+                output::print(&value);
+                return Ok(());
+            }
 
-            // Create fetcher (BlossomClient auto-loads servers from config)
-            let fetcher = Fetcher::new(FetchConfig::default());
+            let nhash = nhash_encode_full(&NHashData {
+                hash: cid.hash,
+                path: vec![],
+                decrypt_key: cid.key,
+                ..Default::default()
+            })
+            .map_err(|e| anyhow::anyhow!("Failed to encode nhash: {}", e))?;
+            println!("  url:   {}", nhash);
+            println!("  hash:  {}", to_hex(&cid.hash));
+            if let Some(key) = cid.key {
+                println!("  key:   {}", to_hex(&key));
+            }
+        }
+        Commands::Export {
+            cid: cid_input,
+            tar,
+            link_key,
+        } => {
+            use futures::StreamExt;
+            use std::io::Write;
+
+            let (tree, cid) = open_remote_path(&cid_input, link_key.as_deref(), &data_dir).await?;
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            if tar {
+                // A single file is archived under its name in the path
+                let name = match cid_input.trim_end_matches('/').rsplit_once('/') {
+                    Some((_, name)) => name.to_string(),
+                    None => hashtree_core::to_hex(&cid.hash),
+                };
+                stdout = hashtree_cli::archive::export_tar(&tree, &cid, &name, stdout).await?;
+            } else {
+                if tree.is_dir(&cid).await? {
+                    anyhow::bail!("{} is a directory; use --tar", cid_input);
+                }
+                let mut chunks = tree.get_stream(&cid);
+                while let Some(chunk) = chunks.next().await {
+                    stdout.write_all(&chunk?)?;
+                }
+            }
+            stdout.flush()?;
+        }
+        Commands::Cat {
+            cid: cid_input,
+            range,
+            link_key,
+        } => {
+            use hashtree_cli::remote::parse_range;
+
+            let range = range.as_deref().map(parse_range).transpose()?;
+            let (tree, cid) = open_remote_path(&cid_input, link_key.as_deref(), &data_dir).await?;
+            if tree.is_dir(&cid).await? {
+                anyhow::bail!("{} is a directory", cid_input);
+            }
 
-            // Fetch file (local first, then Blossom)
-            if let Some(content) = fetcher.fetch_file(&store, None, &resolved.cid.hash).await? {
+            // Fetch only the blocks read (local first, then Blossom)
+            let content = match range {
+                Some((start, end)) => tree.read_file_range_cid(&cid, start, end).await?,
+                None => tree.get(&cid).await?,
+            };
+            if let Some(content) = content {
                 use std::io::Write;
                 std::io::stdout().write_all(&content)?;
             } else {
-                anyhow::bail!("CID not found locally or on remote servers: {}", cid_hex);
+                anyhow::bail!(
+                    "CID not found locally or on remote servers: {}",
+                    hashtree_core::to_hex(&cid.hash)
+                );
+            }
+        }
+        Commands::Ls {
+            path,
+            long,
+            link_key,
+        } => {
+            use hashtree_cli::remote::{format_entry, stat};
+
+            let (tree, cid) = open_remote_path(&path, link_key.as_deref(), &data_dir).await?;
+            if stat(&tree, &cid).await?.link_type != hashtree_core::LinkType::Dir {
+                anyhow::bail!("{} is not a directory", path);
+            }
+            let entries = tree.list_directory(&cid).await?;
+            if json {
+                let entries: Vec<_> = entries.iter().map(output::entry_json).collect();
+                output::print(&serde_json::json!(entries));
+                return Ok(());
+            }
+            for entry in entries {
+                println!("{}", format_entry(&entry, long));
+            }
+        }
+        Commands::Stat { path, link_key } => {
+            use hashtree_cli::remote::{stat, type_name};
+            use hashtree_core::{nhash_encode_full, to_hex, NHashData};
+
+            let (tree, cid) = open_remote_path(&path, link_key.as_deref(), &data_dir).await?;
+            let stat = stat(&tree, &cid).await?;
+            if json {
+                output::print(&output::stat_json(&path, &stat));
+                return Ok(());
+            }
+            let nhash = nhash_encode_full(&NHashData {
+                hash: cid.hash,
+                path: Vec::new(),
+                decrypt_key: cid.key,
+                ..Default::default()
+            })
+            .unwrap_or_else(|_| to_hex(&cid.hash));
+
+            println!("Path: {}", path);
+            println!("Type: {}", type_name(stat.link_type));
+            println!("Size: {} bytes ({})", stat.size, format_bytes(stat.size));
+            if let Some(entries) = stat.entries {
+                println!("Entries: {}", entries);
+            }
+            println!("Hash: {}", to_hex(&cid.hash));
+            println!("Encrypted: {}", cid.key.is_some());
+            println!("nhash: {}", nhash);
+        }
+        Commands::Du {
+            path,
+            largest,
+            all,
+            link_key,
+        } => {
+            let (tree, cid) = open_remote_path(&path, link_key.as_deref(), &data_dir).await?;
+            let stats = hashtree_core::tree_stats(&tree, &cid, largest).await?;
+            if json {
+                output::print(&serde_json::to_value(&stats)?);
+                return Ok(());
+            }
+
+            for dir in &stats.dirs {
+                let level = dir.path.split('/').filter(|p| !p.is_empty()).count();
+                if all || level <= 1 {
+                    let name = if dir.path.is_empty() { "." } else { &dir.path };
+                    println!("{:>12}  {}", format_bytes(dir.size), name);
+                }
+            }
+            println!();
+            println!(
+                "Total: {} in {} files, {} directories",
+                format_bytes(stats.total_size),
+                stats.files,
+                stats.directories
+            );
+            println!("Blocks: {}", stats.blocks);
+            println!("Depth: {}", stats.depth);
+            if !stats.largest.is_empty() {
+                println!("\nLargest files:");
+                for file in &stats.largest {
+                    println!("{:>12}  {}", format_bytes(file.size), file.path);
+                }
             }
         }
         Commands::Pins => {
             let store = HashtreeStore::new(&data_dir)?;
             let pins = store.list_pins_with_names()?;
-            if pins.is_empty() {
+            if json {
+                let pins: Vec<_> = pins
+                    .iter()
+                    .map(|pin| {
+                        let kind = if pin.is_directory { "dir" } else { "file" };
+                        serde_json::json!({ "cid": pin.cid, "name": pin.name, "type": kind })
+                    })
+                    .collect();
+                output::print(&serde_json::json!(pins));
+            } else if pins.is_empty() {
                 println!("No pinned CIDs");
             } else {
                 println!("Pinned items ({}):", pins.len());
@@ -1212,7 +1808,11 @@ async fn main() -> Result<()> {
             // Resolve npub/repo or htree:// URLs to CID
             let resolved = resolve_cid_input(&cid_input).await?;
             let store = HashtreeStore::new(&data_dir)?;
-            store.pin(&resolved.cid.hash)?;
+            store.pin_cid(&resolved.cid)?;
+            if json {
+                output::print(&output::cid_json(&resolved.cid));
+                return Ok(());
+            }
             let nhash = nhash_encode(&resolved.cid.hash)
                 .unwrap_or_else(|_| to_hex(&resolved.cid.hash));
             println!("Pinned: {}", nhash);
@@ -1224,10 +1824,25 @@ async fn main() -> Result<()> {
             let resolved = resolve_cid_input(&cid_input).await?;
             let store = HashtreeStore::new(&data_dir)?;
             store.unpin(&resolved.cid.hash)?;
+            if json {
+                output::print(&output::cid_json(&resolved.cid));
+                return Ok(());
+            }
             let nhash = nhash_encode(&resolved.cid.hash)
                 .unwrap_or_else(|_| to_hex(&resolved.cid.hash));
             println!("Unpinned: {}", nhash);
         }
+        Commands::Resolve { input, link_key } => {
+            let (_, cid) = open_remote_path(&input, link_key.as_deref(), &data_dir).await?;
+            let value = output::cid_json(&cid);
+            if json {
+                let mut value = value;
+                value["input"] = serde_json::json!(input);
+                output::print(&value);
+            } else {
+                println!("{}", value["nhash"].as_str().unwrap_or_default());
+            }
+        }
         Commands::Info { cid: cid_input } => {
             use hashtree_core::{nhash_encode, to_hex};
 
@@ -1276,9 +1891,88 @@ async fn main() -> Result<()> {
                 println!("Hash not found: {}", nhash);
             }
         }
+        Commands::Verify {
+            root,
+            local,
+            link_key,
+        } => {
+            use hashtree_cli::remote::{remote_tree, resolve};
+            use hashtree_cli::verify::verify_tree;
+            use hashtree_cli::{FetchConfig, Fetcher};
+            use hashtree_core::{HashTree, HashTreeConfig};
+
+            let resolved = resolve_cid_input_with_link_key(&root, link_key.as_deref()).await?;
+            let store = HashtreeStore::new(&data_dir)?;
+            let fetcher = Fetcher::new(FetchConfig::default());
+            let blossom = (!local).then(|| fetcher.blossom());
+
+            // Verify from the path within the tree, if one was given
+            let cid = match resolved.path.as_deref() {
+                None => resolved.cid,
+                Some(path) if local => {
+                    let tree = HashTree::new(HashTreeConfig::new(store.store_arc()));
+                    resolve(&tree, &resolved.cid, Some(path)).await?
+                }
+                Some(path) => {
+                    let tree = remote_tree(store.store_arc(), fetcher.blossom().clone());
+                    resolve(&tree, &resolved.cid, Some(path)).await?
+                }
+            };
+
+            let report = verify_tree(store.store_arc(), blossom, &cid).await?;
+            if json {
+                output::print(&output::verify_json(&report));
+                if !report.is_ok() {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            for bad in &report.bad {
+                let path = if bad.path.is_empty() { "/" } else { &bad.path };
+                match bad.source {
+                    Some(source) => println!(
+                        "CORRUPT {} at {} ({}): {}",
+                        hashtree_core::to_hex(&bad.hash),
+                        path,
+                        source,
+                        bad.reason
+                    ),
+                    None => println!(
+                        "MISSING {} at {}: {}",
+                        hashtree_core::to_hex(&bad.hash),
+                        path,
+                        bad.reason
+                    ),
+                }
+            }
+            println!(
+                "Checked {} blocks ({}): {} local, {} from file servers",
+                report.blocks,
+                format_bytes(report.bytes),
+                report.local,
+                report.blossom
+            );
+            if !report.is_ok() {
+                eprintln!(
+                    "Verification failed: {} missing, {} corrupt",
+                    report.missing(),
+                    report.corrupt()
+                );
+                std::process::exit(1);
+            }
+            println!("OK");
+        }
         Commands::Stats => {
             let store = HashtreeStore::new(&data_dir)?;
             let stats = store.get_storage_stats()?;
+            if json {
+                output::print(&serde_json::json!({
+                    "totalDags": stats.total_dags,
+                    "pinnedDags": stats.pinned_dags,
+                    "totalBytes": stats.total_bytes,
+                }));
+                return Ok(());
+            }
             println!("Storage Statistics:");
             println!("  Total DAGs: {}", stats.total_dags);
             println!("  Pinned DAGs: {}", stats.pinned_dags);
@@ -1305,19 +1999,101 @@ async fn main() -> Result<()> {
         Commands::Stop { pid_file } => {
             stop_daemon(pid_file.as_ref())?;
         }
-        Commands::Gc => {
+        Commands::Serve { listen, relays, blossom, cache_mb, rate_limit } => {
+            let config = Config::load()?;
+            let split = |s: &str| s.split(',').map(|s| s.trim().to_string()).collect::<Vec<_>>();
+
+            let mut gateway = hashtree_gateway::GatewayConfig::new(data_dir.join("gateway"));
+            gateway.listen = listen.unwrap_or(config.gateway.listen);
+            gateway.relays = relays.as_deref().map(split).unwrap_or(config.nostr.relays);
+            gateway.blossom_servers = blossom.as_deref().map(split).unwrap_or_else(|| {
+                // Combine legacy servers with read_servers
+                let mut servers = config.blossom.servers.clone();
+                servers.extend(config.blossom.read_servers.clone());
+                servers
+            });
+            gateway.cache_max_bytes = cache_mb.unwrap_or(config.gateway.cache_mb) * 1024 * 1024;
+            gateway.root_ttl = Duration::from_secs(config.gateway.root_ttl_secs);
+            let per_minute = rate_limit.unwrap_or(config.gateway.rate_limit_per_minute);
+            gateway.rate_limit = (per_minute > 0)
+                .then(|| hashtree_gateway::RateLimit::per_minute(per_minute));
+
+            println!("Serving read-only gateway on http://{}", gateway.listen);
+            println!("  relays: {}", gateway.relays.join(", "));
+            println!("  blossom: {}", gateway.blossom_servers.join(", "));
+            hashtree_gateway::serve(gateway).await
+                .map_err(|e| anyhow::anyhow!("Gateway failed: {}", e))?;
+        }
+        Commands::Gc { dry_run } => {
             let store = HashtreeStore::new(&data_dir)?;
-            println!("Running garbage collection...");
-            let gc_stats = store.gc()?;
+            eprintln!("Running garbage collection...");
+            let gc_stats = store.gc_with_options(dry_run)?;
+            if json {
+                output::print(&serde_json::json!({
+                    "dryRun": dry_run,
+                    "deletedBlocks": gc_stats.deleted_dags,
+                    "freedBytes": gc_stats.freed_bytes,
+                }));
+                return Ok(());
+            }
+            if dry_run {
+                println!(
+                    "Would delete {} blocks ({})",
+                    gc_stats.deleted_dags,
+                    format_bytes(gc_stats.freed_bytes)
+                );
+                return Ok(());
+            }
             println!("Deleted {} DAGs", gc_stats.deleted_dags);
             println!("Freed {} bytes ({:.2} KB)",
                 gc_stats.freed_bytes,
                 gc_stats.freed_bytes as f64 / 1024.0);
         }
+        Commands::Scrub { delete, no_refetch } => {
+            use hashtree_cli::{FetchConfig, Fetcher};
+            use hashtree_core::to_hex;
+
+            let store = HashtreeStore::new(&data_dir)?;
+            let quarantine = data_dir.join("quarantine");
+            println!("Scrubbing local blobs...");
+            let result = store.scrub((!delete).then_some(quarantine.as_path()))?;
+            println!("Checked {} blobs ({})", result.checked, format_bytes(result.bytes));
+            if !result.unreadable.is_empty() {
+                println!("Could not read {} blobs; left them in place", result.unreadable.len());
+            }
+
+            if result.corrupted.is_empty() {
+                if result.unreadable.is_empty() {
+                    println!("All blobs verified successfully!");
+                }
+                return Ok(());
+            }
+            println!("Found {} corrupted blobs", result.corrupted.len());
+            if !delete {
+                println!("Corrupted data moved to {}", quarantine.display());
+            }
+            if no_refetch {
+                return Ok(());
+            }
+
+            // Blossom downloads are verified against the hash
+            let fetcher = Fetcher::new(FetchConfig::default());
+            let mut refetched = 0;
+            for hash in &result.corrupted {
+                let hash_hex = to_hex(hash);
+                match fetcher.fetch_chunk(None, &hash_hex).await {
+                    Ok(data) => {
+                        store.put_blob(&data)?;
+                        refetched += 1;
+                    }
+                    Err(e) => println!("  LOST: {} ({})", &hash_hex[..16], e),
+                }
+            }
+            println!("Re-fetched {} of {} corrupted blobs", refetched, result.corrupted.len());
+        }
         Commands::User { identity } => {
-            use hashtree_cli::config::get_keys_path;
+            use hashtree_cli::config::{add_active_key, KeyEntry};
             use nostr::nips::nip19::FromBech32;
-            use std::fs;
 
             match identity {
                 None => {
@@ -1330,7 +2106,13 @@ async fn main() -> Result<()> {
                     // Try to fetch profile name
                     let config = Config::load()?;
                     let profile_name = fetch_profile_name(&config.nostr.relays, &keys.public_key().to_hex()).await;
-                    if let Some(name) = profile_name {
+                    if json {
+                        output::print(&serde_json::json!({
+                            "npub": npub,
+                            "pubkey": keys.public_key().to_hex(),
+                            "name": profile_name,
+                        }));
+                    } else if let Some(name) = profile_name {
                         println!("{} ({})", npub, name);
                     } else {
                         println!("{}", npub);
@@ -1347,19 +2129,8 @@ async fn main() -> Result<()> {
                         anyhow::bail!("Identity must be an nsec (secret key). Use 'htree user' to see your current npub.");
                     };
 
-                    // Save to keys file
-                    let keys_path = get_keys_path();
-                    if let Some(parent) = keys_path.parent() {
-                        fs::create_dir_all(parent)?;
-                    }
-                    fs::write(&keys_path, &nsec)?;
-
-                    // Set permissions to 0600
-                    #[cfg(unix)]
-                    {
-                        use std::os::unix::fs::PermissionsExt;
-                        fs::set_permissions(&keys_path, fs::Permissions::from_mode(0o600))?;
-                    }
+                    // Make it the active key, keeping the other stored keys
+                    add_active_key(KeyEntry { secret: nsec.clone(), alias: None })?;
 
                     // Show the new npub
                     let secret_key = nostr::SecretKey::from_bech32(&nsec)?;
@@ -1369,6 +2140,9 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Commands::Key { command } => {
+            run_key_command(command)?;
+        }
         Commands::Publish { ref_name, hash, key } => {
             use hashtree_core::{from_hex, key_from_hex, Cid};
 
@@ -1385,7 +2159,7 @@ async fn main() -> Result<()> {
                 .context("Failed to encode npub")?;
 
             if was_generated {
-                println!("Identity: {} (new)", npub);
+                eprintln!("Identity: {} (new)", npub);
             }
 
             // Parse hash and optional key
@@ -1417,6 +2191,11 @@ async fn main() -> Result<()> {
 
             // Publish
             match resolver.publish(&nostr_key, &cid).await {
+                Ok(_) if json => {
+                    let mut value = output::cid_json(&cid);
+                    value["published"] = serde_json::json!(nostr_key);
+                    output::print(&value);
+                }
                 Ok(_) => {
                     println!("Published: {}", nostr_key);
                     println!("  hash: {}", hash);
@@ -1433,6 +2212,196 @@ async fn main() -> Result<()> {
             // Clean up
             let _ = resolver.stop().await;
         }
+        Commands::Deploy {
+            path,
+            tree,
+            alias,
+            gateway,
+            no_ignore,
+            reproducible,
+        } => {
+            use hashtree_core::{from_hex, Cid};
+
+            if !path.is_dir() {
+                anyhow::bail!("{} is not a directory", path.display());
+            }
+            let config = Config::load()?;
+            let (nsec_str, was_generated) = ensure_keys_string()?;
+            let keys = NostrKeys::parse(&nsec_str).context("Failed to parse nsec")?;
+            let npub =
+                NostrToBech32::to_bech32(&keys.public_key()).context("Failed to encode npub")?;
+            if was_generated {
+                eprintln!("Identity: {} (new)", npub);
+            }
+
+            // Public, so the gateway and anyone with the link can read it
+            let mut store = HashtreeStore::new(&data_dir)?;
+            if reproducible {
+                store = store.reproducible();
+            }
+            let hash_hex = store
+                .upload_dir_with_options(&path, !no_ignore)
+                .context("Failed to add directory")?;
+            let cid = Cid {
+                hash: from_hex(&hash_hex).context("Invalid hash")?,
+                key: None,
+            };
+            let ref_key = format!("{}/{}", npub, tree);
+            if let Err(e) = store.index_tree(
+                &cid.hash,
+                &npub,
+                Some(tree.as_str()),
+                hashtree_cli::PRIORITY_OWN,
+                Some(ref_key.as_str()),
+            ) {
+                tracing::warn!("Failed to index tree: {}", e);
+            }
+
+            // Publishing a root whose blocks didn't all reach a file server
+            // would serve a broken site
+            let (uploaded, skipped, errors) = push_to_blossom(&data_dir, &hash_hex, None).await?;
+            if errors > 0 {
+                anyhow::bail!("{} blocks failed to upload; not publishing", errors);
+            }
+
+            let resolver = NostrRootResolver::new(NostrResolverConfig {
+                relays: config.nostr.relays.clone(),
+                resolve_timeout: Duration::from_secs(5),
+                secret_key: Some(keys),
+            })
+            .await
+            .context("Failed to create Nostr resolver")?;
+            let names: Vec<&str> = std::iter::once(tree.as_str())
+                .chain(alias.as_deref())
+                .collect();
+            for name in &names {
+                let result = resolver.publish(&format!("{}/{}", npub, name), &cid).await;
+                if let Err(e) = result {
+                    let _ = resolver.stop().await;
+                    anyhow::bail!("Failed to publish {}: {}", name, e);
+                }
+            }
+            let _ = resolver.stop().await;
+
+            let gateway = gateway.or(config.gateway.public_url);
+            let index = path.join("index.html").is_file();
+            let urls: Vec<(String, Option<String>)> = names
+                .iter()
+                .map(|name| {
+                    let gateway_url = gateway
+                        .as_deref()
+                        .map(|base| deploy_gateway_url(base, &npub, name, index));
+                    (format!("htree://{}/{}", npub, name), gateway_url)
+                })
+                .collect();
+
+            if json {
+                let mut value = output::cid_json(&cid);
+                value["path"] = serde_json::json!(path.display().to_string());
+                value["uploaded"] = serde_json::json!(uploaded);
+                value["skipped"] = serde_json::json!(skipped);
+                value["trees"] = names
+                    .iter()
+                    .zip(&urls)
+                    .map(|(name, (url, gateway_url))| {
+                        serde_json::json!({ "name": name, "url": url, "gatewayUrl": gateway_url })
+                    })
+                    .collect();
+                output::print(&value);
+                return Ok(());
+            }
+            println!("deployed {}", path.display());
+            println!("  hash:    {}", hash_hex);
+            println!(
+                "  blocks:  {} uploaded, {} already there",
+                uploaded, skipped
+            );
+            for (url, gateway_url) in &urls {
+                println!("  url:     {}", url);
+                if let Some(gateway_url) = gateway_url {
+                    println!("  gateway: {}", gateway_url);
+                }
+            }
+        }
+        Commands::RotateKey { tree, link_key } => {
+            use hashtree_core::{generate_key, key_from_hex, key_to_hex, to_hex};
+
+            let config = Config::load()?;
+            let (nsec_str, _) = ensure_keys_string()?;
+            let keys = NostrKeys::parse(&nsec_str)
+                .context("Failed to parse nsec")?;
+            let npub = NostrToBech32::to_bech32(&keys.public_key())
+                .context("Failed to encode npub")?;
+            let link_key = link_key.as_ref()
+                .map(|k| key_from_hex(k))
+                .transpose()
+                .map_err(|e| anyhow::anyhow!("Invalid link key: {}", e))?;
+
+            let resolver_config = NostrResolverConfig {
+                relays: config.nostr.relays.clone(),
+                resolve_timeout: Duration::from_secs(5),
+                secret_key: Some(keys.clone()),
+            };
+            let resolver = NostrRootResolver::new(resolver_config).await
+                .context("Failed to create Nostr resolver")?;
+
+            let nostr_key = format!("{}/{}", npub, tree);
+            let current = match &link_key {
+                Some(secret) => resolver.resolve_shared(&nostr_key, secret).await,
+                None => resolver.resolve(&nostr_key).await,
+            }
+            .map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", nostr_key, e))?
+            .ok_or_else(|| anyhow::anyhow!("Tree not found: {}", nostr_key))?;
+            if current.key.is_none() {
+                anyhow::bail!("{} is public or its key is unavailable; nothing to rotate", nostr_key);
+            }
+
+            let store = HashtreeStore::new(&data_dir)?;
+            let rotated = store.rotate_tree_key(&current)?;
+
+            // As the app does: upload the new blocks, re-wrap the key for
+            // everyone the tree is shared with, and only then publish the
+            // root, so nobody gets a root they can't open
+            let new_link_key = link_key.map(|_| generate_key());
+            let published = async {
+                let client = hashtree_blossom::BlossomClient::new(keys);
+                if client.write_servers().is_empty() {
+                    anyhow::bail!("No file servers configured; add write_servers to config.toml");
+                }
+                let target = hashtree_cli::mirror::UploadStore::new(client);
+                hashtree_cli::mirror::mirror_tree(store.store_arc().as_ref(), &target, &rotated, 8)
+                    .await
+                    .context("Failed to upload the re-encrypted tree; not publishing")?;
+
+                if new_link_key.is_none() {
+                    let recipients = resolver.share_recipients(&tree).await
+                        .map_err(|e| anyhow::anyhow!("Failed to look up recipients: {}", e))?;
+                    for recipient in &recipients {
+                        resolver.publish_share(&tree, recipient, Some(&rotated)).await
+                            .map_err(|e| anyhow::anyhow!("Failed to re-share with {}: {}", recipient.to_hex(), e))?;
+                    }
+                    if !recipients.is_empty() {
+                        println!("Re-shared with {} recipient(s)", recipients.len());
+                    }
+                }
+
+                match &new_link_key {
+                    Some(secret) => resolver.publish_shared(&nostr_key, &rotated, secret).await,
+                    None => resolver.publish_private(&nostr_key, &rotated).await,
+                }
+                .map_err(|e| anyhow::anyhow!("Publish failed: {}", e))
+            }
+            .await;
+            let _ = resolver.stop().await;
+            published?;
+
+            println!("Rotated: {}", nostr_key);
+            println!("  hash: {}", to_hex(&rotated.hash));
+            if let Some(secret) = new_link_key {
+                println!("  link key: {}", key_to_hex(&secret));
+                println!("Old share links no longer open new versions of the tree.");
+            }
+        }
         Commands::Follow { npub } => {
             follow_user(&data_dir, &npub, true).await?;
         }
@@ -1451,8 +2420,68 @@ async fn main() -> Result<()> {
             // Resolve npub/repo or htree:// URLs to CID
             let resolved = resolve_cid_input(&cid_input).await?;
             let cid_hex = to_hex(&resolved.cid.hash);
-            push_to_blossom(&data_dir, &cid_hex, server).await?;
+            let (uploaded, skipped, errors) = push_to_blossom(&data_dir, &cid_hex, server).await?;
+            if json {
+                let mut value = output::cid_json(&resolved.cid);
+                value["uploaded"] = serde_json::json!(uploaded);
+                value["skipped"] = serde_json::json!(skipped);
+                value["errors"] = serde_json::json!(errors);
+                output::print(&value);
+            } else {
+                println!(
+                    "\nUploaded: {}, Skipped: {}, Errors: {}",
+                    uploaded, skipped, errors
+                );
+                println!("Done!");
+            }
         }
+        Commands::Blossom { command } => match command {
+            BlossomCommands::Mirror {
+                root,
+                from,
+                to,
+                jobs,
+                link_key,
+            } => {
+                use hashtree_blossom::BlossomClient;
+                use hashtree_cli::mirror::{mirror_tree, UploadStore};
+                use hashtree_core::{HashTree, HashTreeConfig};
+
+                let resolved = resolve_cid_input_with_link_key(&root, link_key.as_deref()).await?;
+                let (keys, _) = ensure_keys()?;
+                let source = Arc::new(UploadStore::new(
+                    BlossomClient::new_empty(keys.clone()).with_servers(vec![from.clone()]),
+                ));
+                // exists() checks write servers, so the target is one too
+                let target =
+                    UploadStore::new(BlossomClient::new_empty(keys).with_servers(vec![to.clone()]));
+
+                let cid = match resolved.path.as_deref() {
+                    Some(path) => {
+                        let tree = HashTree::new(HashTreeConfig::new(source.clone()));
+                        hashtree_cli::remote::resolve(&tree, &resolved.cid, Some(path)).await?
+                    }
+                    None => resolved.cid,
+                };
+
+                eprintln!("Mirroring {} from {} to {}...", cid, from, to);
+                let stats = mirror_tree(source.as_ref(), &target, &cid, jobs).await?;
+                if json {
+                    let mut value = output::cid_json(&cid);
+                    value["copied"] = serde_json::json!(stats.copied);
+                    value["bytes"] = serde_json::json!(stats.bytes);
+                    value["present"] = serde_json::json!(stats.present);
+                    output::print(&value);
+                    return Ok(());
+                }
+                println!(
+                    "Copied {} blocks ({:.2} MB), {} already present",
+                    stats.copied,
+                    stats.bytes as f64 / 1024.0 / 1024.0,
+                    stats.present
+                );
+            }
+        },
         Commands::Storage { command } => {
             // Load config
             let config = Config::load()?;
@@ -1462,6 +2491,18 @@ async fn main() -> Result<()> {
                 PathBuf::from(&config.storage.data_dir)
             });
 
+            // Runs before the store is opened below, which would hold the blobs open
+            if let StorageCommands::Migrate { to } = command {
+                let from = config.storage.backend.clone();
+                println!("Migrating blobs from {} to {}...", from.as_str(), to.as_str());
+                let stats = migrate_blobs(&data_dir.join("blobs"), &from, &to)?;
+
+                hashtree_cli::config::set_config_value("storage", "backend", to.as_str())?;
+                println!("Migrated {} blobs ({:.2} MB)", stats.blobs, stats.bytes as f64 / 1024.0 / 1024.0);
+                println!("Old store kept at {}; delete it once everything works", stats.backup_path.display());
+                return Ok(());
+            }
+
             let max_size_bytes = config.storage.max_size_gb * 1024 * 1024 * 1024;
             let store = HashtreeStore::with_options(&data_dir, config.storage.s3.as_ref(), max_size_bytes)?;
 
@@ -1580,6 +2621,7 @@ async fn main() -> Result<()> {
                         println!("All blobs verified successfully!");
                     }
                 }
+                StorageCommands::Migrate { .. } => unreachable!("handled before opening the store"),
             }
         }
         Commands::Peer { addr } => {
@@ -1846,6 +2888,97 @@ fn signal_process(pid: i32, signal: i32) -> Result<()> {
     anyhow::bail!("Failed to signal pid {}: {}", pid, err);
 }
 
+fn run_key_command(command: KeyCommands) -> Result<()> {
+    use hashtree_cli::config::{
+        add_active_key, encrypt_secret_key, entry_secret_key, get_keys_path, read_key_entries,
+        read_passphrase, write_key_entries, KeyEntry,
+    };
+
+    // Stored form of a secret key: ncryptsec if encrypting, else nsec
+    let stored = |secret_key: &nostr::SecretKey, encrypt: bool| -> Result<String> {
+        if encrypt {
+            let passphrase = read_passphrase("New passphrase: ", true)?;
+            encrypt_secret_key(secret_key, &passphrase)
+        } else {
+            Ok(secret_key.to_bech32()?)
+        }
+    };
+
+    match command {
+        KeyCommands::Generate { alias, encrypt } => {
+            let keys = nostr::Keys::generate();
+            let secret = stored(keys.secret_key(), encrypt)?;
+            add_active_key(KeyEntry { secret, alias })?;
+            println!("{}", keys.public_key().to_bech32()?);
+        }
+        KeyCommands::Import {
+            key,
+            alias,
+            encrypt,
+        } => {
+            let key = if key == "-" {
+                let mut line = String::new();
+                std::io::stdin().read_line(&mut line)?;
+                line.trim().to_string()
+            } else {
+                key
+            };
+            // Checks the passphrase of an ncryptsec before storing it
+            let secret_key = entry_secret_key(&key)?;
+            let secret = if key.starts_with("ncryptsec1") && !encrypt {
+                key
+            } else {
+                stored(&secret_key, encrypt)?
+            };
+            add_active_key(KeyEntry { secret, alias })?;
+            println!("{}", nostr::Keys::new(secret_key).public_key().to_bech32()?);
+        }
+        KeyCommands::Export { encrypt } => {
+            let entry = read_key_entries()?
+                .into_iter()
+                .next()
+                .context("No keys; create one with: htree key generate")?;
+            let secret_key = entry_secret_key(&entry.secret)?;
+            println!("{}", stored(&secret_key, encrypt)?);
+        }
+        KeyCommands::Show => {
+            let entries = read_key_entries()?;
+            if entries.is_empty() {
+                println!("No keys in {}", get_keys_path()?.display());
+                return Ok(());
+            }
+            for (i, entry) in entries.iter().enumerate() {
+                let active = if i == 0 { "*" } else { " " };
+                // Encrypted keys aren't decrypted just to list them
+                let id = if entry.is_encrypted() {
+                    "(encrypted)".to_string()
+                } else {
+                    match nostr::SecretKey::parse(&entry.secret) {
+                        Ok(secret_key) => nostr::Keys::new(secret_key).public_key().to_bech32()?,
+                        Err(_) => "(invalid)".to_string(),
+                    }
+                };
+                println!("{} {} {}", active, id, entry.alias.as_deref().unwrap_or(""));
+            }
+            println!("Keys file: {}", get_keys_path()?.display());
+        }
+        KeyCommands::Encrypt => {
+            let mut entries = read_key_entries()?;
+            let entry = entries
+                .first_mut()
+                .context("No keys; create one with: htree key generate")?;
+            if entry.is_encrypted() {
+                anyhow::bail!("The active key is already encrypted");
+            }
+            let secret_key = entry_secret_key(&entry.secret)?;
+            entry.secret = stored(&secret_key, true)?;
+            write_key_entries(&entries)?;
+            println!("Encrypted the active key in {}", get_keys_path()?.display());
+        }
+    }
+    Ok(())
+}
+
 fn stop_daemon(pid_file: Option<&PathBuf>) -> Result<()> {
     let pid_path = pid_file.cloned().unwrap_or_else(default_daemon_pid_file);
     let pid = read_pid_file(&pid_path)?;
@@ -2260,8 +3393,24 @@ async fn fetch_profile_name(relays: &[String], pubkey_hex: &str) -> Option<Strin
         .map(|s| s.to_string())
 }
 
-/// Push content to Blossom servers
-async fn push_to_blossom(data_dir: &PathBuf, cid_str: &str, server_override: Option<String>) -> Result<()> {
+/// Gateway URL of the published tree `npub/tree`, at its index page if
+/// the site has one (the gateway doesn't serve directories)
+fn deploy_gateway_url(base: &str, npub: &str, tree: &str, index: bool) -> String {
+    let url = format!("{}/htree/{}/{}", base.trim_end_matches('/'), npub, tree);
+    if index {
+        format!("{}/index.html", url)
+    } else {
+        url
+    }
+}
+
+/// Push content to Blossom servers, returning blocks uploaded, already
+/// there and failed
+async fn push_to_blossom(
+    data_dir: &PathBuf,
+    cid_str: &str,
+    server_override: Option<String>,
+) -> Result<(usize, usize, usize)> {
     use hashtree_blossom::BlossomClient;
     use hashtree_core::from_hex;
     use nostr::Keys;
@@ -2292,7 +3441,7 @@ async fn push_to_blossom(data_dir: &PathBuf, cid_str: &str, server_override: Opt
     };
 
     // Collect all blocks to push (walk the DAG)
-    println!("Collecting blocks...");
+    eprintln!("Collecting blocks...");
     let mut blocks_to_push: Vec<Vec<u8>> = Vec::new();
     let mut visited: std::collections::HashSet<[u8; 32]> = std::collections::HashSet::new();
     let root_hash = from_hex(&hash_hex).context("Invalid hash")?;
@@ -2332,7 +3481,7 @@ async fn push_to_blossom(data_dir: &PathBuf, cid_str: &str, server_override: Opt
         }
     }
 
-    println!("Found {} blocks to push", blocks_to_push.len());
+    eprintln!("Found {} blocks to push", blocks_to_push.len());
 
     let mut uploaded = 0;
     let mut skipped = 0;
@@ -2354,13 +3503,16 @@ async fn push_to_blossom(data_dir: &PathBuf, cid_str: &str, server_override: Opt
         }
     }
 
-    println!("\nUploaded: {}, Skipped: {}, Errors: {}", uploaded, skipped, errors);
-    println!("Done!");
-    Ok(())
+    Ok((uploaded, skipped, errors))
 }
 
-/// Push tree to Blossom servers using BlossomClient
-async fn background_blossom_push(data_dir: &PathBuf, cid_str: &str, _servers: &[String]) -> Result<()> {
+/// Push tree to Blossom servers using BlossomClient, returning blocks
+/// uploaded and already there
+async fn background_blossom_push(
+    data_dir: &PathBuf,
+    cid_str: &str,
+    _servers: &[String],
+) -> Result<(usize, usize)> {
     use hashtree_blossom::BlossomClient;
     use hashtree_core::from_hex;
     use nostr::Keys;
@@ -2421,7 +3573,7 @@ async fn background_blossom_push(data_dir: &PathBuf, cid_str: &str, _servers: &[
     }
 
     if blocks_to_push.is_empty() {
-        return Ok(());
+        return Ok((0, 0));
     }
 
     // Use BlossomClient (auto-loads servers from config)
@@ -2444,11 +3596,7 @@ async fn background_blossom_push(data_dir: &PathBuf, cid_str: &str, _servers: &[
         }
     }
 
-    if total_uploaded > 0 || total_skipped > 0 {
-        println!("  file servers: {} uploaded, {} already exist", total_uploaded, total_skipped);
-    }
-
-    Ok(())
+    Ok((total_uploaded, total_skipped))
 }
 
 /// Recursively add a directory (handles encryption automatically based on tree config)
@@ -2456,21 +3604,16 @@ async fn add_directory<S: hashtree_core::store::Store>(
     tree: &hashtree_core::HashTree<S>,
     dir: &std::path::Path,
     respect_gitignore: bool,
+    reproducible: bool,
 ) -> Result<hashtree_core::Cid> {
-    use ignore::WalkBuilder;
     use hashtree_core::DirEntry;
     use std::collections::HashMap;
 
     // Collect files by their parent directory path
     let mut dir_contents: HashMap<String, Vec<(String, hashtree_core::Cid)>> = HashMap::new();
 
-    // Use ignore crate for gitignore-aware walking
-    let walker = WalkBuilder::new(dir)
-        .git_ignore(respect_gitignore)
-        .git_global(respect_gitignore)
-        .git_exclude(respect_gitignore)
-        .hidden(false)
-        .build();
+    // Same walk as storing would use, for the same hash
+    let walker = hashtree_cli::storage::dir_walker(dir, respect_gitignore, reproducible);
 
     for result in walker {
         let entry = result?;
@@ -2680,4 +3823,16 @@ mod tests {
         assert_eq!(resolved.cid.hash, hash);
         assert!(resolved.cid.key.is_none());
     }
+
+    #[test]
+    fn test_deploy_gateway_url() {
+        assert_eq!(
+            deploy_gateway_url("https://gw.example/", "npub1abc", "site", true),
+            "https://gw.example/htree/npub1abc/site/index.html"
+        );
+        assert_eq!(
+            deploy_gateway_url("https://gw.example", "npub1abc", "production", false),
+            "https://gw.example/htree/npub1abc/production"
+        );
+    }
 }
@@ -0,0 +1,224 @@
+//! Copying a tree between file servers
+//!
+//! `htree blossom mirror` walks a tree on one Blossom server and uploads
+//! the blocks another one lacks. A node is uploaded only after everything
+//! below it, so a node already on the target means its subtree is too: an
+//! interrupted mirror resumes by running it again, skipping what was
+//! copied.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use hashtree_blossom::BlossomClient;
+use hashtree_core::{
+    decode_tree_node, decrypt_chk, is_tree_node, sha256, to_hex, Cid, Hash, LinkType, Store,
+    StoreError,
+};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use tokio::sync::Semaphore;
+
+/// Store on a client's servers: reads download, writes upload
+pub struct UploadStore {
+    client: BlossomClient,
+}
+
+impl UploadStore {
+    pub fn new(client: BlossomClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Store for UploadStore {
+    async fn put(&self, _hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.client
+            .upload_if_missing(&data)
+            .await
+            .map(|(_, uploaded)| uploaded)
+            .map_err(|e| StoreError::Other(e.to_string()))
+    }
+
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.client.try_download(&to_hex(hash)).await)
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        Ok(self.client.exists(&to_hex(hash)).await)
+    }
+
+    async fn delete(&self, _hash: &Hash) -> Result<bool, StoreError> {
+        // Never delete from remote
+        Ok(false)
+    }
+}
+
+/// Outcome of mirroring a tree
+#[derive(Debug, Clone, Default)]
+pub struct MirrorStats {
+    /// Blocks uploaded to the target
+    pub copied: usize,
+    /// Bytes of the blocks uploaded
+    pub bytes: u64,
+    /// Blocks the target already had, with everything below them
+    pub present: usize,
+}
+
+struct Mirror<'a, S: Store, D: Store> {
+    source: &'a S,
+    target: &'a D,
+    permits: Semaphore,
+    seen: Mutex<HashSet<Hash>>,
+    stats: Mutex<MirrorStats>,
+}
+
+/// Copy the blocks of the tree at `root` that `target` lacks from
+/// `source`, with at most `concurrency` requests at a time. Fails if a
+/// block can't be fetched or is corrupt; blocks above it aren't uploaded,
+/// so a later run retries them.
+pub async fn mirror_tree<S: Store, D: Store>(
+    source: &S,
+    target: &D,
+    root: &Cid,
+    concurrency: usize,
+) -> Result<MirrorStats> {
+    let mirror = Mirror {
+        source,
+        target,
+        permits: Semaphore::new(concurrency.max(1)),
+        seen: Mutex::new(HashSet::new()),
+        stats: Mutex::new(MirrorStats::default()),
+    };
+    mirror.block(root.hash, root.key, false).await?;
+    Ok(mirror.stats.into_inner().unwrap())
+}
+
+impl<S: Store, D: Store> Mirror<'_, S, D> {
+    /// Mirror the block `hash` and, unless it's a `leaf`, what's below it
+    async fn block(&self, hash: Hash, key: Option<[u8; 32]>, leaf: bool) -> Result<()> {
+        if !self.seen.lock().unwrap().insert(hash) {
+            return Ok(());
+        }
+
+        let permit = self.permits.acquire().await?;
+        let present = self
+            .target
+            .has(&hash)
+            .await
+            .map_err(|e| anyhow::anyhow!("Target error: {}", e))?;
+        if present {
+            self.stats.lock().unwrap().present += 1;
+            return Ok(());
+        }
+        let data = self
+            .source
+            .get(&hash)
+            .await
+            .map_err(|e| anyhow::anyhow!("Source error: {}", e))?
+            .with_context(|| format!("Block {} not found on source", to_hex(&hash)))?;
+        if sha256(&data) != hash {
+            anyhow::bail!("Block {} is corrupt on source", to_hex(&hash));
+        }
+
+        if !leaf {
+            let plain = match &key {
+                Some(key) => decrypt_chk(&data, key)
+                    .map_err(|e| anyhow::anyhow!("Failed to decrypt {}: {}", to_hex(&hash), e))?,
+                None => data.clone(),
+            };
+            if is_tree_node(&plain) {
+                // Don't hold a request slot while the children are copied
+                drop(permit);
+                let node = decode_tree_node(&plain)?;
+                let children = node.links.iter().map(|link| {
+                    Box::pin(self.block(link.hash, link.key, link.link_type == LinkType::Blob))
+                });
+                for result in join_all(children).await {
+                    result?;
+                }
+                let _permit = self.permits.acquire().await?;
+                return self.upload(hash, data).await;
+            }
+        }
+        self.upload(hash, data).await
+    }
+
+    async fn upload(&self, hash: Hash, data: Vec<u8>) -> Result<()> {
+        let size = data.len() as u64;
+        self.target
+            .put(hash, data)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to upload {}: {}", to_hex(&hash), e))?;
+        let mut stats = self.stats.lock().unwrap();
+        stats.copied += 1;
+        stats.bytes += size;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::{DirEntry, HashTree, HashTreeConfig, MemoryStore};
+    use std::sync::Arc;
+
+    async fn build(store: Arc<MemoryStore>) -> (Cid, Cid) {
+        // A file of 3 chunks, in directories small enough to be one block
+        let chunked = HashTree::new(HashTreeConfig::new(store.clone()).with_chunk_size(4));
+        let (file, size) = chunked.put(b"hello world").await.unwrap();
+        let tree = HashTree::new(HashTreeConfig::new(store));
+        let entry = DirEntry::from_cid("a.txt", &file)
+            .with_size(size)
+            .with_link_type(LinkType::File);
+        let inner = tree.put_directory(vec![entry]).await.unwrap();
+        let docs = DirEntry::from_cid("docs", &inner).with_link_type(LinkType::Dir);
+        (tree.put_directory(vec![docs]).await.unwrap(), file)
+    }
+
+    #[tokio::test]
+    async fn test_mirror_copies_missing_blocks_and_resumes() {
+        let source = Arc::new(MemoryStore::new());
+        let (root, file) = build(source.clone()).await;
+        let target = MemoryStore::new();
+
+        // Root, docs, the file node and its 3 chunks
+        let stats = mirror_tree(source.as_ref(), &target, &root, 2)
+            .await
+            .unwrap();
+        assert_eq!(stats.copied, 6);
+        assert_eq!(stats.present, 0);
+        for hash in source.keys() {
+            assert!(target.has(&hash).await.unwrap());
+        }
+
+        // Everything is there now, so only the root is checked
+        let stats = mirror_tree(source.as_ref(), &target, &root, 2)
+            .await
+            .unwrap();
+        assert_eq!((stats.copied, stats.present), (0, 1));
+
+        // An interrupted run left the file, but not the blocks above it
+        let target = MemoryStore::new();
+        mirror_tree(source.as_ref(), &target, &file, 2)
+            .await
+            .unwrap();
+        let stats = mirror_tree(source.as_ref(), &target, &root, 2)
+            .await
+            .unwrap();
+        assert_eq!((stats.copied, stats.present), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_fails_without_uploading_parents() {
+        let source = Arc::new(MemoryStore::new());
+        let (root, file) = build(source.clone()).await;
+        source.delete(&file.hash).await.unwrap();
+        let target = MemoryStore::new();
+
+        let err = mirror_tree(source.as_ref(), &target, &root, 4)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not found on source"));
+        assert!(!target.has(&root.hash).await.unwrap());
+    }
+}
@@ -0,0 +1,147 @@
+//! Machine-readable output of CLI commands
+//!
+//! With `--json`, commands print one JSON value to stdout instead of their
+//! text output, built by the functions here so the schemas stay stable;
+//! progress and warnings still go to stderr. Hashes and keys are hex, sizes
+//! are in bytes and field names are camelCase.
+
+use hashtree_core::{nhash_encode_full, to_hex, Cid, NHashData, TreeEntry};
+use serde_json::{json, Value};
+
+use crate::remote::{type_name, PathStat};
+use crate::verify::VerifyReport;
+
+/// `{hash, key, nhash}`; `key` is null for public content
+pub fn cid_json(cid: &Cid) -> Value {
+    let nhash = nhash_encode_full(&NHashData {
+        hash: cid.hash,
+        path: Vec::new(),
+        decrypt_key: cid.key,
+        ..Default::default()
+    })
+    .ok();
+    json!({
+        "hash": to_hex(&cid.hash),
+        "key": cid.key.map(|k| to_hex(&k)),
+        "nhash": nhash,
+    })
+}
+
+/// A directory entry: `{name, type, size, hash, key}`, where `type` is
+/// `blob`, `file` or `dir`
+pub fn entry_json(entry: &TreeEntry) -> Value {
+    json!({
+        "name": entry.name,
+        "type": type_name(entry.link_type),
+        "size": entry.size,
+        "hash": to_hex(&entry.hash),
+        "key": entry.key.map(|k| to_hex(&k)),
+    })
+}
+
+/// `stat` of `path`: the fields of [`cid_json`] and `{path, type, size,
+/// entries}`, with `entries` null unless it's a directory
+pub fn stat_json(path: &str, stat: &PathStat) -> Value {
+    let mut value = cid_json(&stat.cid);
+    value["path"] = json!(path);
+    value["type"] = json!(type_name(stat.link_type));
+    value["size"] = json!(stat.size);
+    value["entries"] = json!(stat.entries);
+    value
+}
+
+/// `{ok, blocks, bytes, local, blossom, missing, corrupt, bad}`, where
+/// `bad` lists `{hash, path, source, reason}` and `source` is `local`,
+/// `blossom` or null for a missing block
+pub fn verify_json(report: &VerifyReport) -> Value {
+    let bad: Vec<Value> = report
+        .bad
+        .iter()
+        .map(|bad| {
+            json!({
+                "hash": to_hex(&bad.hash),
+                "path": bad.path,
+                "source": bad.source.map(|s| s.to_string()),
+                "reason": bad.reason,
+            })
+        })
+        .collect();
+    json!({
+        "ok": report.is_ok(),
+        "blocks": report.blocks,
+        "bytes": report.bytes,
+        "local": report.local,
+        "blossom": report.blossom,
+        "missing": report.missing(),
+        "corrupt": report.corrupt(),
+        "bad": bad,
+    })
+}
+
+/// Print `value` as the output of a command
+pub fn print(value: &Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::{BadBlock, BlockSource};
+    use hashtree_core::LinkType;
+
+    #[test]
+    fn test_cid_and_entry_schemas() {
+        let cid = Cid {
+            hash: [0xab; 32],
+            key: None,
+        };
+        let value = cid_json(&cid);
+        assert_eq!(value["hash"], "ab".repeat(32));
+        assert!(value["key"].is_null());
+        assert!(value["nhash"].as_str().unwrap().starts_with("nhash1"));
+
+        let entry = TreeEntry {
+            name: "docs".to_string(),
+            hash: [1; 32],
+            size: 42,
+            link_type: LinkType::Dir,
+            key: Some([2; 32]),
+            meta: None,
+        };
+        assert_eq!(
+            entry_json(&entry),
+            json!({
+                "name": "docs",
+                "type": "dir",
+                "size": 42,
+                "hash": "01".repeat(32),
+                "key": "02".repeat(32),
+            })
+        );
+    }
+
+    #[test]
+    fn test_verify_schema() {
+        let report = VerifyReport {
+            blocks: 3,
+            bytes: 100,
+            local: 2,
+            blossom: 1,
+            bad: vec![BadBlock {
+                hash: [3; 32],
+                path: "a.txt".to_string(),
+                source: Some(BlockSource::Blossom),
+                reason: "hash mismatch".to_string(),
+            }],
+        };
+        let value = verify_json(&report);
+        assert_eq!(value["ok"], false);
+        assert_eq!(value["missing"], 0);
+        assert_eq!(value["corrupt"], 1);
+        assert_eq!(value["bad"][0]["source"], "blossom");
+        assert_eq!(value["bad"][0]["path"], "a.txt");
+    }
+}
@@ -0,0 +1,260 @@
+//! Reading trees that may only be on file servers
+//!
+//! `htree ls`, `cat` and `stat` read a tree through `RemoteStore`: local
+//! storage first, then the configured Blossom servers, keeping fetched
+//! blocks locally. Only the blocks on the way to a path, and those of what
+//! is read, are fetched.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use hashtree_blossom::{BlossomClient, BlossomStore};
+use hashtree_core::store::slice_range;
+use hashtree_core::{Cid, Hash, HashTree, HashTreeConfig, LinkType, Store, StoreError, TreeEntry};
+use std::sync::Arc;
+
+use crate::storage::StorageRouter;
+
+/// Local store with Blossom fallback, caching what it fetches
+pub struct RemoteStore {
+    local: Arc<StorageRouter>,
+    blossom: BlossomStore,
+}
+
+impl RemoteStore {
+    pub fn new(local: Arc<StorageRouter>, blossom: BlossomClient) -> Self {
+        Self {
+            local,
+            blossom: BlossomStore::new(blossom),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for RemoteStore {
+    async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.local.put(hash, data).await
+    }
+
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Ok(Some(data)) = self.local.get(hash).await {
+            return Ok(Some(data));
+        }
+        let result = self.blossom.get(hash).await;
+        if let Ok(Some(ref data)) = result {
+            let _ = self.local.put(*hash, data.clone()).await;
+        }
+        result
+    }
+
+    /// Local blobs are read in place; remote ones are fetched whole, so
+    /// they're verified and kept for the next range
+    async fn get_range(&self, hash: &Hash, offset: u64, len: u64) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Ok(Some(data)) = self.local.get_range(hash, offset, len).await {
+            return Ok(Some(data));
+        }
+        Ok(self
+            .get(hash)
+            .await?
+            .map(|data| slice_range(&data, offset, len).to_vec()))
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        if self.local.has(hash).await? {
+            return Ok(true);
+        }
+        self.blossom.has(hash).await
+    }
+
+    async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
+        // Never delete from remote
+        self.local.delete(hash).await
+    }
+}
+
+/// A tree read through `RemoteStore`
+pub fn remote_tree(local: Arc<StorageRouter>, blossom: BlossomClient) -> HashTree<RemoteStore> {
+    HashTree::new(HashTreeConfig::new(Arc::new(RemoteStore::new(
+        local, blossom,
+    ))))
+}
+
+/// What a path of a tree points to
+#[derive(Debug, Clone)]
+pub struct PathStat {
+    pub cid: Cid,
+    pub link_type: LinkType,
+    /// Content size; the sum of entry sizes for directories
+    pub size: u64,
+    /// Entries of a directory
+    pub entries: Option<usize>,
+}
+
+/// Cid of `path` under `root`; the root itself for no or an empty path
+pub async fn resolve<S: Store>(tree: &HashTree<S>, root: &Cid, path: Option<&str>) -> Result<Cid> {
+    let path = path.map(|p| p.trim_matches('/')).filter(|p| !p.is_empty());
+    let Some(path) = path else {
+        return Ok(root.clone());
+    };
+    tree.resolve_path(root, path)
+        .await?
+        .with_context(|| format!("Path not found: {}", path))
+}
+
+/// Type and size of `cid`
+pub async fn stat<S: Store>(tree: &HashTree<S>, cid: &Cid) -> Result<PathStat> {
+    let exists = tree
+        .get_store()
+        .has(&cid.hash)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    if !exists {
+        anyhow::bail!(
+            "Not found locally or on file servers: {}",
+            hashtree_core::to_hex(&cid.hash)
+        );
+    }
+
+    let Some(node) = tree.get_node(cid).await? else {
+        return Ok(PathStat {
+            cid: cid.clone(),
+            link_type: LinkType::Blob,
+            size: tree.get_size_cid(cid).await?,
+            entries: None,
+        });
+    };
+    if tree.is_dir(cid).await? || node.node_type == LinkType::Dir {
+        let entries = tree.list_directory(cid).await?;
+        return Ok(PathStat {
+            cid: cid.clone(),
+            link_type: LinkType::Dir,
+            size: entries.iter().map(|e| e.size).sum(),
+            entries: Some(entries.len()),
+        });
+    }
+    Ok(PathStat {
+        cid: cid.clone(),
+        link_type: LinkType::File,
+        size: node.links.iter().map(|l| l.size).sum(),
+        entries: None,
+    })
+}
+
+/// Short name of a link type, as `ls` and `stat` print it
+pub fn type_name(link_type: LinkType) -> &'static str {
+    match link_type {
+        LinkType::Blob => "blob",
+        LinkType::File => "file",
+        LinkType::Dir => "dir",
+    }
+}
+
+/// One line of `ls`: type, size and name, with a slash after directories.
+/// `long` adds the entry's hash.
+pub fn format_entry(entry: &TreeEntry, long: bool) -> String {
+    let is_dir = entry.link_type == LinkType::Dir;
+    let size = if is_dir {
+        "-".to_string()
+    } else {
+        entry.size.to_string()
+    };
+    let name = if is_dir {
+        format!("{}/", entry.name)
+    } else {
+        entry.name.clone()
+    };
+    if long {
+        format!(
+            "{:<4} {:>12} {} {}",
+            type_name(entry.link_type),
+            size,
+            hashtree_core::to_hex(&entry.hash),
+            name
+        )
+    } else {
+        format!("{:<4} {:>12} {}", type_name(entry.link_type), size, name)
+    }
+}
+
+/// Parse a `--range` of bytes `START-END`, inclusive like an HTTP range;
+/// `START-` reads to the end. Returns the start and exclusive end.
+pub fn parse_range(range: &str) -> Result<(u64, Option<u64>)> {
+    let (start, end) = range
+        .split_once('-')
+        .with_context(|| format!("Invalid range '{}', expected START-END", range))?;
+    let start: u64 = start
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid range start '{}'", start))?;
+    let end = end.trim();
+    if end.is_empty() {
+        return Ok((start, None));
+    }
+    let end: u64 = end
+        .parse()
+        .with_context(|| format!("Invalid range end '{}'", end))?;
+    if end < start {
+        anyhow::bail!("Range end {} is before its start {}", end, start);
+    }
+    Ok((start, Some(end + 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::{DirEntry, MemoryStore};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("0-99").unwrap(), (0, Some(100)));
+        assert_eq!(parse_range("100-").unwrap(), (100, None));
+        assert_eq!(parse_range("5-5").unwrap(), (5, Some(6)));
+        assert!(parse_range("10-5").is_err());
+        assert!(parse_range("10").is_err());
+        assert!(parse_range("-5").is_err());
+        assert!(parse_range("a-b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stat_and_list_paths() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let (file, size) = tree.put(b"hello world").await.unwrap();
+        let inner = tree
+            .put_directory(vec![DirEntry::from_cid("a.txt", &file).with_size(size)])
+            .await
+            .unwrap();
+        let root = tree
+            .put_directory(vec![
+                DirEntry::from_cid("docs", &inner).with_link_type(LinkType::Dir),
+                DirEntry::from_cid("b.txt", &file).with_size(size),
+            ])
+            .await
+            .unwrap();
+
+        let docs = resolve(&tree, &root, Some("/docs/")).await.unwrap();
+        let docs_stat = stat(&tree, &docs).await.unwrap();
+        assert_eq!(docs_stat.link_type, LinkType::Dir);
+        assert_eq!(docs_stat.entries, Some(1));
+
+        let a = resolve(&tree, &root, Some("docs/a.txt")).await.unwrap();
+        let a_stat = stat(&tree, &a).await.unwrap();
+        assert_eq!(a_stat.link_type, LinkType::Blob);
+        assert_eq!(a_stat.size, 11);
+        let range = tree.read_file_range_cid(&a, 6, Some(11)).await.unwrap();
+        assert_eq!(range.unwrap(), b"world");
+
+        assert!(resolve(&tree, &root, Some("missing")).await.is_err());
+        assert_eq!(resolve(&tree, &root, None).await.unwrap(), root);
+
+        let mut lines: Vec<String> = tree
+            .list_directory(&root)
+            .await
+            .unwrap()
+            .iter()
+            .map(|entry| format_entry(entry, false))
+            .collect();
+        lines.sort();
+        assert_eq!(lines[0], format!("blob {:>12} b.txt", 11));
+        assert_eq!(lines[1], format!("dir  {:>12} docs/", "-"));
+    }
+}
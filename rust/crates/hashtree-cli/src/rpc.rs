@@ -0,0 +1,222 @@
+//! JSON-RPC API of a running daemon
+//!
+//! `htree start` (or `htree daemon`) answers JSON-RPC 2.0 requests at
+//! `POST /rpc`, from localhost only, carrying the auth cookie
+//! (`~/.hashtree/auth.cookie`, `user:password`) as HTTP Basic Auth so a web
+//! page can't make them through DNS rebinding. It keeps one Nostr resolver
+//! connected for its lifetime, so CLI commands that find a daemon resolve
+//! through it rather than connecting to relays themselves. Third-party
+//! tools can use the same API.
+//!
+//! Methods:
+//! - `ping`: `{version}`
+//! - `resolve` `{key, linkKey?}`: `{hash, key, cid}`, or null if not found
+//! - `listTrees` `{pubkey}`: `[{name, hash, cid}]`
+//! - `stats`: storage statistics
+//! - `pin` `{hash, key?}`, `unpin` `{hash}`: `{hash}`
+
+use anyhow::{Context, Result};
+use hashtree_core::{from_hex, Cid};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Path of the JSON-RPC endpoint
+pub const RPC_PATH: &str = "/rpc";
+
+/// Invalid JSON or not a JSON-RPC 2.0 request
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl RpcRequest {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id: json!(id),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Client of a daemon's JSON-RPC API
+pub struct RpcClient {
+    url: String,
+    http: reqwest::Client,
+    /// Auth cookie credentials
+    auth: Option<(String, String)>,
+}
+
+impl RpcClient {
+    /// Client of the daemon at `base_url`, e.g. `http://127.0.0.1:8080`
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            url: format!("{}{}", base_url.trim_end_matches('/'), RPC_PATH),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap_or_default(),
+            auth: None,
+        }
+    }
+
+    /// Authenticate with the auth cookie's `username` and `password`
+    pub fn with_auth(mut self, username: String, password: String) -> Self {
+        self.auth = Some((username, password));
+        self
+    }
+
+    /// Client of the local daemon, if one is listening on the configured
+    /// bind address and the auth cookie can be read
+    pub fn detect() -> Option<Self> {
+        let bind_address = crate::Config::load()
+            .ok()
+            .map(|cfg| cfg.server.bind_address);
+        let url = hashtree_config::detect_local_daemon_url(bind_address.as_deref())?;
+        let (username, password) = crate::config::read_auth_cookie().ok()?;
+        Some(Self::new(&url).with_auth(username, password))
+    }
+
+    /// Call `method`, returning its result
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let request = RpcRequest::new(1, method, params);
+        let mut http_request = self.http.post(&self.url).json(&request);
+        if let Some((username, password)) = &self.auth {
+            http_request = http_request.basic_auth(username, Some(password));
+        }
+        let response: RpcResponse = http_request
+            .send()
+            .await
+            .context("Daemon RPC request failed")?
+            .error_for_status()
+            .context("Daemon RPC request failed")?
+            .json()
+            .await
+            .context("Invalid daemon RPC response")?;
+        if let Some(error) = response.error {
+            anyhow::bail!("Daemon RPC error {}: {}", error.code, error.message);
+        }
+        Ok(response.result.unwrap_or(Value::Null))
+    }
+
+    /// Resolve `npub/tree` through the daemon's resolver
+    pub async fn resolve(&self, key: &str, link_key: Option<&[u8; 32]>) -> Result<Option<Cid>> {
+        let mut params = json!({ "key": key });
+        if let Some(link_key) = link_key {
+            params["linkKey"] = json!(hex::encode(link_key));
+        }
+        let result = self.call("resolve", params).await?;
+        if result.is_null() {
+            return Ok(None);
+        }
+        parse_cid(&result).map(Some)
+    }
+}
+
+/// Cid of a `{hash, key}` result
+pub fn parse_cid(value: &Value) -> Result<Cid> {
+    let hash = value["hash"]
+        .as_str()
+        .context("Missing hash in daemon RPC result")?;
+    let hash = from_hex(hash).context("Invalid hash in daemon RPC result")?;
+    let key = match value["key"].as_str() {
+        Some(key) => Some(
+            hashtree_core::key_from_hex(key)
+                .map_err(|e| anyhow::anyhow!("Invalid key in daemon RPC result: {}", e))?,
+        ),
+        None => None,
+    };
+    Ok(Cid { hash, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_response_wire_format() {
+        let request = RpcRequest::new(7, "ping", Value::Null);
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            value,
+            json!({"jsonrpc": "2.0", "id": 7, "method": "ping", "params": null})
+        );
+
+        let ok = RpcResponse::new(json!(7), Ok(json!({"version": "1"})));
+        let value = serde_json::to_value(&ok).unwrap();
+        assert_eq!(
+            value,
+            json!({"jsonrpc": "2.0", "id": 7, "result": {"version": "1"}})
+        );
+
+        let err = RpcResponse::new(json!(7), Err(RpcError::new(METHOD_NOT_FOUND, "nope")));
+        let value = serde_json::to_value(&err).unwrap();
+        assert_eq!(value["error"]["code"], METHOD_NOT_FOUND);
+        assert!(value.get("result").is_none());
+    }
+
+    #[test]
+    fn test_parse_cid() {
+        let hash = "ab".repeat(32);
+        let cid = parse_cid(&json!({ "hash": hash, "key": null })).unwrap();
+        assert_eq!(cid.hash, [0xab; 32]);
+        assert_eq!(cid.key, None);
+
+        let cid = parse_cid(&json!({ "hash": hash, "key": "cd".repeat(32) })).unwrap();
+        assert_eq!(cid.key, Some([0xcd; 32]));
+
+        assert!(parse_cid(&json!({ "key": null })).is_err());
+    }
+}
@@ -1,12 +1,13 @@
 use axum::{
     body::Body,
     extract::State,
-    http::{header, Request, Response, StatusCode},
+    http::{header, HeaderMap, Request, Response, StatusCode},
     middleware::Next,
     extract::ws::Message,
 };
 use crate::storage::HashtreeStore;
 use crate::webrtc::WebRTCState;
+use hashtree_resolver::nostr::NostrRootResolver;
 use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
 use tokio::sync::{mpsc, Mutex};
@@ -54,6 +55,11 @@ pub struct AppState {
     pub allowed_pubkeys: HashSet<String>,
     /// Upstream Blossom servers for cascade fetching
     pub upstream_blossom: Vec<String>,
+    /// Resolver kept connected for the JSON-RPC API
+    pub resolver: Option<Arc<NostrRootResolver>>,
+    /// Credentials JSON-RPC requests must carry (the auth cookie); without
+    /// them the API is off
+    pub rpc_auth: Option<AuthCredentials>,
 }
 
 #[derive(Clone)]
@@ -62,6 +68,25 @@ pub struct AuthCredentials {
     pub password: String,
 }
 
+impl AuthCredentials {
+    /// Whether `headers` carry these credentials as HTTP Basic Auth
+    pub fn matches(&self, headers: &HeaderMap) -> bool {
+        use base64::Engine;
+        let Some(credentials) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Basic "))
+        else {
+            return false;
+        };
+        base64::engine::general_purpose::STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .is_some_and(|decoded| decoded == format!("{}:{}", self.username, self.password))
+    }
+}
+
 /// Auth middleware - validates HTTP Basic Auth
 pub async fn auth_middleware(
     State(state): State<AppState>,
@@ -73,34 +98,7 @@ pub async fn auth_middleware(
         return Ok(next.run(request).await);
     };
 
-    // Check Authorization header
-    let auth_header = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok());
-
-    let authorized = if let Some(header_value) = auth_header {
-        if let Some(credentials) = header_value.strip_prefix("Basic ") {
-            use base64::Engine;
-            let engine = base64::engine::general_purpose::STANDARD;
-            if let Ok(decoded) = engine.decode(credentials) {
-                if let Ok(decoded_str) = String::from_utf8(decoded) {
-                    let expected = format!("{}:{}", auth.username, auth.password);
-                    decoded_str == expected
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        } else {
-            false
-        }
-    } else {
-        false
-    };
-
-    if authorized {
+    if auth.matches(request.headers()) {
         Ok(next.run(request).await)
     } else {
         Ok(Response::builder()
@@ -6,7 +6,7 @@ use axum::{
 };
 use bytes::Bytes;
 use futures::stream::{self, StreamExt};
-use hashtree_core::{from_hex, to_hex};
+use hashtree_core::{from_hex, to_hex, Cid};
 use hashtree_resolver::{nostr::{NostrRootResolver, NostrResolverConfig}, RootResolver};
 use serde_json::json;
 use std::sync::Arc;
@@ -508,19 +508,20 @@ pub async fn list_pins(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
+/// Pin `hash` or `hash:key`; the key lets GC walk an encrypted tree
 pub async fn pin_cid(
     State(state): State<AppState>,
     Path(cid): Path<String>,
 ) -> impl IntoResponse {
-    let hash = match from_hex(&cid) {
-        Ok(h) => h,
+    let parsed = match Cid::parse(&cid) {
+        Ok(c) => c,
         Err(e) => return Json(json!({
             "success": false,
-            "error": format!("Invalid CID format: {}", e)
+            "error": format!("Invalid CID format: {:?}", e)
         })),
     };
     let store = &state.store;
-    match store.pin(&hash) {
+    match store.pin_cid(&parsed) {
         Ok(_) => Json(json!({
             "success": true,
             "cid": cid
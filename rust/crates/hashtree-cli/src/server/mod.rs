@@ -3,6 +3,7 @@ pub mod blossom;
 mod handlers;
 mod ws_relay;
 mod mime;
+mod rpc;
 #[cfg(feature = "p2p")]
 pub mod stun;
 mod ui;
@@ -16,6 +17,7 @@ use axum::{
 };
 use crate::storage::HashtreeStore;
 use crate::webrtc::WebRTCState;
+use hashtree_resolver::nostr::NostrRootResolver;
 use std::collections::HashSet;
 use std::sync::Arc;
 
@@ -38,6 +40,8 @@ impl HashtreeServer {
                 public_writes: true, // Allow anyone with valid Nostr auth by default
                 allowed_pubkeys: HashSet::new(), // No pubkeys allowed by default (use public_writes)
                 upstream_blossom: Vec::new(),
+                resolver: None,
+                rpc_auth: None,
             },
             addr,
         }
@@ -79,6 +83,18 @@ impl HashtreeServer {
         self
     }
 
+    /// Set the resolver kept connected for the JSON-RPC API
+    pub fn with_resolver(mut self, resolver: Arc<NostrRootResolver>) -> Self {
+        self.state.resolver = Some(resolver);
+        self
+    }
+
+    /// Require `username`/`password` (the auth cookie) on JSON-RPC requests
+    pub fn with_rpc_auth(mut self, username: String, password: String) -> Self {
+        self.state.rpc_auth = Some(AuthCredentials { username, password });
+        self
+    }
+
     pub async fn run(self) -> Result<()> {
         // Public endpoints (no auth required)
         // Note: /:id serves both CID and blossom SHA256 hash lookups
@@ -109,6 +125,9 @@ impl HashtreeServer {
             // Resolver API endpoints
             .route("/api/resolve/:pubkey/:treename", get(handlers::resolve_to_hash))
             .route("/api/trees/:pubkey", get(handlers::list_trees))
+            // JSON-RPC API for CLI invocations and local tools (localhost
+            // only, authenticated with the auth cookie)
+            .route(crate::rpc::RPC_PATH, post(rpc::rpc))
             .with_state(self.state.clone());
 
         // Protected endpoints (require auth if enabled)
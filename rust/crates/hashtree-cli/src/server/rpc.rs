@@ -0,0 +1,187 @@
+//! `POST /rpc`: the daemon's JSON-RPC API, see [`crate::rpc`]
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+};
+use hashtree_core::{from_hex, to_hex, Cid};
+use hashtree_resolver::RootResolver;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+
+use super::auth::AppState;
+use crate::rpc::{
+    RpcError, RpcRequest, RpcResponse, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST,
+    METHOD_NOT_FOUND,
+};
+
+pub async fn rpc(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> impl IntoResponse {
+    if !addr.ip().is_loopback() {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "localhost only"})),
+        )
+            .into_response();
+    }
+    // A page in a browser reaches localhost too (DNS rebinding), but it
+    // can't read the auth cookie
+    if !state.rpc_auth.as_ref().is_some_and(|auth| auth.matches(&headers)) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "auth cookie required"})),
+        )
+            .into_response();
+    }
+    let id = body.get("id").cloned().unwrap_or(Value::Null);
+    let response = match serde_json::from_value::<RpcRequest>(body) {
+        Ok(request) if request.jsonrpc == "2.0" => {
+            let id = request.id.clone();
+            RpcResponse::new(id, dispatch(&state, request).await)
+        }
+        Ok(_) => RpcResponse::new(
+            id,
+            Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"")),
+        ),
+        Err(e) => RpcResponse::new(id, Err(RpcError::new(INVALID_REQUEST, e.to_string()))),
+    };
+    Json(response).into_response()
+}
+
+async fn dispatch(state: &AppState, request: RpcRequest) -> Result<Value, RpcError> {
+    let params = &request.params;
+    match request.method.as_str() {
+        "ping" => Ok(json!({ "version": env!("CARGO_PKG_VERSION") })),
+        "resolve" => {
+            let key = str_param(params, "key")?;
+            let link_key = match params.get("linkKey").and_then(Value::as_str) {
+                Some(hex) => Some(hashtree_core::key_from_hex(hex).map_err(|e| {
+                    RpcError::new(INVALID_PARAMS, format!("Invalid linkKey: {}", e))
+                })?),
+                None => None,
+            };
+            let resolver = resolver(state)?;
+            let resolved = match link_key {
+                Some(link_key) => resolver.resolve_shared(key, &link_key).await,
+                None => resolver.resolve(key).await,
+            };
+            match resolved.map_err(internal)? {
+                Some(cid) => Ok(cid_json(&cid)),
+                None => Ok(Value::Null),
+            }
+        }
+        "listTrees" => {
+            let pubkey = str_param(params, "pubkey")?;
+            let entries = resolver(state)?.list(pubkey).await.map_err(internal)?;
+            let trees: Vec<Value> = entries
+                .iter()
+                .map(|e| {
+                    let mut tree = cid_json(&e.cid);
+                    tree["name"] = json!(e.key.rsplit('/').next().unwrap_or(&e.key));
+                    tree
+                })
+                .collect();
+            Ok(json!(trees))
+        }
+        "stats" => {
+            let stats = state.store.get_storage_stats().map_err(internal)?;
+            Ok(json!({
+                "totalDags": stats.total_dags,
+                "pinnedDags": stats.pinned_dags,
+                "totalBytes": stats.total_bytes,
+            }))
+        }
+        "pin" | "unpin" => {
+            let hex = str_param(params, "hash")?;
+            let hash = from_hex(hex)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid hash: {}", e)))?;
+            if request.method == "pin" {
+                // The key lets GC walk an encrypted tree
+                let key = params
+                    .get("key")
+                    .and_then(Value::as_str)
+                    .map(from_hex)
+                    .transpose()
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid key: {}", e)))?;
+                state.store.pin_cid(&Cid { hash, key }).map_err(internal)?;
+            } else {
+                state.store.unpin(&hash).map_err(internal)?;
+            }
+            Ok(json!({ "hash": hex }))
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method: {}", method),
+        )),
+    }
+}
+
+fn resolver(state: &AppState) -> Result<&hashtree_resolver::nostr::NostrRootResolver, RpcError> {
+    state
+        .resolver
+        .as_deref()
+        .ok_or_else(|| RpcError::new(INTERNAL_ERROR, "Resolver not running"))
+}
+
+fn str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Missing string param '{}'", name)))
+}
+
+fn internal(e: impl std::fmt::Display) -> RpcError {
+    RpcError::new(INTERNAL_ERROR, e.to_string())
+}
+
+fn cid_json(cid: &Cid) -> Value {
+    json!({
+        "hash": to_hex(&cid.hash),
+        "key": cid.key.map(|k| to_hex(&k)),
+        "cid": cid.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::HashtreeStore;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    async fn call(state: &AppState, method: &str, params: Value) -> Result<Value, RpcError> {
+        dispatch(state, RpcRequest::new(1, method, params)).await
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = Arc::new(HashtreeStore::new(temp_dir.path().join("db")).unwrap());
+        let state = crate::HashtreeServer::new(store, "127.0.0.1:0".to_string()).state;
+
+        let pong = call(&state, "ping", Value::Null).await.unwrap();
+        assert_eq!(pong["version"], env!("CARGO_PKG_VERSION"));
+
+        let stats = call(&state, "stats", Value::Null).await.unwrap();
+        assert_eq!(stats["totalDags"], 0);
+
+        let err = call(&state, "nope", Value::Null).await.unwrap_err();
+        assert_eq!(err.code, METHOD_NOT_FOUND);
+        let err = call(&state, "resolve", json!({})).await.unwrap_err();
+        assert_eq!(err.code, INVALID_PARAMS);
+        let err = call(&state, "pin", json!({ "hash": "zz" }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, INVALID_PARAMS);
+        // No resolver was given to the server
+        let err = call(&state, "resolve", json!({ "key": "npub1x/tree" }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, INTERNAL_ERROR);
+    }
+}
@@ -2,18 +2,19 @@ use anyhow::{Context, Result};
 use async_trait::async_trait;
 use heed::{Database, EnvOpenOptions};
 use heed::types::*;
-use hashtree_fs::FsBlobStore;
+use hashtree_fs::{FsBlobStore, PackBlobStore};
 #[cfg(feature = "lmdb")]
 use hashtree_lmdb::LmdbBlobStore;
 use hashtree_core::{
     HashTree, HashTreeConfig, Cid,
-    sha256, to_hex, from_hex, TreeNode, DirEntry as HashTreeDirEntry,
+    sha256, to_hex, from_hex, is_tree_node, TreeNode, DirEntry as HashTreeDirEntry,
     types::Hash,
 };
-use hashtree_core::store::{Store, StoreError};
+use hashtree_core::store::{slice_range, Store, StoreError};
 use hashtree_config::StorageBackend;
+use crate::archive::TarImport;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::collections::HashSet;
 use std::io::Read;
 use std::sync::Arc;
@@ -60,9 +61,10 @@ pub struct LocalStoreStats {
     pub total_bytes: u64,
 }
 
-/// Local blob store - wraps either FsBlobStore or LmdbBlobStore
+/// Local blob store - wraps FsBlobStore, PackBlobStore or LmdbBlobStore
 pub enum LocalStore {
     Fs(FsBlobStore),
+    Pack(PackBlobStore),
     #[cfg(feature = "lmdb")]
     Lmdb(LmdbBlobStore),
 }
@@ -74,6 +76,9 @@ impl LocalStore {
             StorageBackend::Fs => {
                 Ok(LocalStore::Fs(FsBlobStore::new(path)?))
             }
+            StorageBackend::Pack => {
+                Ok(LocalStore::Pack(PackBlobStore::new(path)?))
+            }
             #[cfg(feature = "lmdb")]
             StorageBackend::Lmdb => {
                 Ok(LocalStore::Lmdb(LmdbBlobStore::new(path)?))
@@ -90,6 +95,7 @@ impl LocalStore {
     pub fn put_sync(&self, hash: Hash, data: &[u8]) -> Result<bool, StoreError> {
         match self {
             LocalStore::Fs(store) => store.put_sync(hash, data),
+            LocalStore::Pack(store) => store.put_sync(hash, data),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.put_sync(hash, data),
         }
@@ -99,6 +105,7 @@ impl LocalStore {
     pub fn get_sync(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
         match self {
             LocalStore::Fs(store) => store.get_sync(hash),
+            LocalStore::Pack(store) => store.get_sync(hash),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.get_sync(hash),
         }
@@ -108,6 +115,7 @@ impl LocalStore {
     pub fn exists(&self, hash: &Hash) -> Result<bool, StoreError> {
         match self {
             LocalStore::Fs(store) => Ok(store.exists(hash)),
+            LocalStore::Pack(store) => Ok(store.exists(hash)),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.exists(hash),
         }
@@ -117,6 +125,7 @@ impl LocalStore {
     pub fn delete_sync(&self, hash: &Hash) -> Result<bool, StoreError> {
         match self {
             LocalStore::Fs(store) => store.delete_sync(hash),
+            LocalStore::Pack(store) => store.delete_sync(hash),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.delete_sync(hash),
         }
@@ -132,6 +141,13 @@ impl LocalStore {
                     total_bytes: stats.total_bytes,
                 })
             }
+            LocalStore::Pack(store) => {
+                let stats = store.stats()?;
+                Ok(LocalStoreStats {
+                    count: stats.count(),
+                    total_bytes: stats.total_bytes(),
+                })
+            }
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => {
                 let stats = store.stats()?;
@@ -147,6 +163,7 @@ impl LocalStore {
     pub fn list(&self) -> Result<Vec<Hash>, StoreError> {
         match self {
             LocalStore::Fs(store) => store.list(),
+            LocalStore::Pack(store) => store.list(),
             #[cfg(feature = "lmdb")]
             LocalStore::Lmdb(store) => store.list(),
         }
@@ -163,6 +180,15 @@ impl Store for LocalStore {
         self.get_sync(hash)
     }
 
+    async fn get_range(&self, hash: &Hash, offset: u64, len: u64) -> Result<Option<Vec<u8>>, StoreError> {
+        match self {
+            LocalStore::Fs(store) => store.get_range(hash, offset, len).await,
+            LocalStore::Pack(store) => store.get_range(hash, offset, len).await,
+            #[cfg(feature = "lmdb")]
+            LocalStore::Lmdb(store) => store.get_range(hash, offset, len).await,
+        }
+    }
+
     async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
         self.exists(hash)
     }
@@ -172,6 +198,113 @@ impl Store for LocalStore {
     }
 }
 
+/// Result of converting a blob directory to another backend
+#[derive(Debug, Clone)]
+pub struct MigrateStats {
+    pub blobs: usize,
+    pub bytes: u64,
+    /// Where the blobs in the old backend were moved
+    pub backup_path: PathBuf,
+}
+
+/// Open the lock file of the blob directory at `blobs_path`. Open stores
+/// hold it shared, and migration exclusively.
+fn open_store_lock(blobs_path: &Path) -> Result<std::fs::File> {
+    let mut name = blobs_path.file_name().context("Invalid blobs path")?.to_os_string();
+    name.push(".lock");
+    let path = blobs_path.with_file_name(name);
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// Copy every blob in `blobs_path` from the `from` backend into a new `to`
+/// store, then swap the new store into place. The old directory is kept
+/// next to it (`blobs.<from>-old`) until the user deletes it. Refuses to
+/// run while a daemon or another command has the store open.
+pub fn migrate_blobs(blobs_path: &Path, from: &StorageBackend, to: &StorageBackend) -> Result<MigrateStats> {
+    if from == to {
+        anyhow::bail!("Blobs are already stored in the {} backend", to.as_str());
+    }
+    #[cfg(not(feature = "lmdb"))]
+    if *from == StorageBackend::Lmdb || *to == StorageBackend::Lmdb {
+        anyhow::bail!("LMDB backend requires building with the lmdb feature");
+    }
+
+    let store_lock = open_store_lock(blobs_path)?;
+    match store_lock.try_lock() {
+        Ok(()) => {}
+        Err(std::fs::TryLockError::WouldBlock) => {
+            anyhow::bail!("The blob store is in use; stop the daemon (htree stop) and try again")
+        }
+        Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+    }
+
+    let name = blobs_path
+        .file_name()
+        .context("Invalid blobs path")?
+        .to_string_lossy()
+        .into_owned();
+    let staging_path = blobs_path.with_file_name(format!("{}.migrating", name));
+    let backup_path = blobs_path.with_file_name(format!("{}.{}-old", name, from.as_str()));
+    if backup_path.exists() {
+        anyhow::bail!("{} already exists; remove it first", backup_path.display());
+    }
+    if staging_path.exists() {
+        // Leftover from an interrupted migration
+        std::fs::remove_dir_all(&staging_path)?;
+    }
+
+    let mut blobs = 0;
+    let mut bytes = 0;
+    {
+        let source = LocalStore::new(blobs_path, from)?;
+        let target = LocalStore::new(&staging_path, to)?;
+        let hashes = source.list()?;
+        for hash in &hashes {
+            let data = source
+                .get_sync(hash)?
+                .with_context(|| format!("Blob {} disappeared during migration", to_hex(hash)))?;
+            target.put_sync(*hash, &data)?;
+            blobs += 1;
+            bytes += data.len() as u64;
+        }
+
+        let copied = target.stats()?.count;
+        if copied != hashes.len() {
+            anyhow::bail!("Copied {} of {} blobs; old store left untouched", copied, hashes.len());
+        }
+    }
+
+    std::fs::rename(blobs_path, &backup_path)
+        .with_context(|| format!("Failed to move {} aside", blobs_path.display()))?;
+    std::fs::rename(&staging_path, blobs_path)
+        .with_context(|| format!("Failed to move {} into place", staging_path.display()))?;
+
+    Ok(MigrateStats { blobs, bytes, backup_path })
+}
+
+/// Walk `dir` for import, skipping what .gitignore files ignore if
+/// `respect_gitignore`. Reproducibly, only ignore files inside `dir`
+/// count, whether or not it's in a git checkout: not the global gitignore,
+/// .git/info/exclude or ignore files above `dir`, which vary by machine.
+pub fn dir_walker(dir: &Path, respect_gitignore: bool, reproducible: bool) -> ignore::Walk {
+    let mut builder = ignore::WalkBuilder::new(dir);
+    builder
+        .git_ignore(respect_gitignore)
+        .git_global(respect_gitignore && !reproducible)
+        .git_exclude(respect_gitignore && !reproducible)
+        .hidden(false);
+    if reproducible {
+        builder.parents(false).require_git(false);
+    }
+    builder.build()
+}
+
 #[cfg(feature = "s3")]
 use tokio::sync::mpsc;
 
@@ -453,6 +586,16 @@ impl Store for StorageRouter {
         self.get_sync(hash)
     }
 
+    /// Read in place locally; a blob only in S3 is fetched whole and cached
+    async fn get_range(&self, hash: &Hash, offset: u64, len: u64) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Some(data) = self.local.get_range(hash, offset, len).await? {
+            return Ok(Some(data));
+        }
+        Ok(self
+            .get_sync(hash)?
+            .map(|data| slice_range(&data, offset, len).to_vec()))
+    }
+
     async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
         self.exists(hash)
     }
@@ -466,6 +609,8 @@ pub struct HashtreeStore {
     env: heed::Env,
     /// Set of pinned hashes (32-byte raw hashes, prevents garbage collection)
     pins: Database<Bytes, Unit>,
+    /// Decryption keys of pinned encrypted roots: hash (32 bytes) -> key (32 bytes)
+    pin_keys: Database<Bytes, Bytes>,
     /// Blob ownership: sha256 (32 bytes) ++ pubkey (32 bytes) -> () (composite key for multi-owner)
     blob_owners: Database<Bytes, Unit>,
     /// Maps pubkey (32 bytes) -> blob metadata JSON (for blossom list)
@@ -482,6 +627,12 @@ pub struct HashtreeStore {
     router: Arc<StorageRouter>,
     /// Maximum storage size in bytes (from config)
     max_size_bytes: u64,
+    /// Chunk size for uploads (from config; hashtree default if None)
+    chunk_size: Option<usize>,
+    /// Build trees independently of config and environment (see `reproducible`)
+    reproducible: bool,
+    /// Shared lock on the blob directory (see `migrate_blobs`)
+    _store_lock: std::fs::File,
 }
 
 impl HashtreeStore {
@@ -503,12 +654,13 @@ impl HashtreeStore {
         let env = unsafe {
             EnvOpenOptions::new()
                 .map_size(10 * 1024 * 1024 * 1024) // 10GB virtual address space
-                .max_dbs(8)  // pins, blob_owners, pubkey_blobs, tree_meta, blob_trees, tree_refs, cached_roots, blobs
+                .max_dbs(9)  // pins, pin_keys, blob_owners, pubkey_blobs, tree_meta, blob_trees, tree_refs, cached_roots, blobs
                 .open(path)?
         };
 
         let mut wtxn = env.write_txn()?;
         let pins = env.create_database(&mut wtxn, Some("pins"))?;
+        let pin_keys = env.create_database(&mut wtxn, Some("pin_keys"))?;
         let blob_owners = env.create_database(&mut wtxn, Some("blob_owners"))?;
         let pubkey_blobs = env.create_database(&mut wtxn, Some("pubkey_blobs"))?;
         let tree_meta = env.create_database(&mut wtxn, Some("tree_meta"))?;
@@ -520,6 +672,17 @@ impl HashtreeStore {
         // Get storage backend from config
         let config = hashtree_config::Config::load_or_default();
         let backend = &config.storage.backend;
+        let chunk_size = config.storage.chunk_size;
+
+        // Held while open so the blobs aren't migrated under us
+        let store_lock = open_store_lock(&path.join("blobs"))?;
+        match store_lock.try_lock_shared() {
+            Ok(()) => {}
+            Err(std::fs::TryLockError::WouldBlock) => {
+                anyhow::bail!("The blob store is being migrated; try again once it's done")
+            }
+            Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
 
         // Create local blob store based on configured backend
         let local_store = Arc::new(LocalStore::new(path.join("blobs"), backend)
@@ -549,6 +712,7 @@ impl HashtreeStore {
         Ok(Self {
             env,
             pins,
+            pin_keys,
             blob_owners,
             pubkey_blobs,
             tree_meta,
@@ -557,9 +721,21 @@ impl HashtreeStore {
             cached_roots,
             router,
             max_size_bytes,
+            chunk_size,
+            reproducible: false,
+            _store_lock: store_lock,
         })
     }
 
+    /// Build trees reproducibly: importing the same directory contents
+    /// gives the same root on any machine. Chunking ignores the configured
+    /// chunk size, and directories are walked with `dir_walker`'s
+    /// reproducible rules.
+    pub fn reproducible(mut self) -> Self {
+        self.reproducible = true;
+        self
+    }
+
     /// Get the storage router
     pub fn router(&self) -> &StorageRouter {
         &self.router
@@ -571,6 +747,19 @@ impl HashtreeStore {
         Arc::clone(&self.router)
     }
 
+    /// Tree config for writing, with the configured chunk size unless
+    /// reproducible
+    fn write_config(&self) -> HashTreeConfig<StorageRouter> {
+        let config = HashTreeConfig::new(self.store_arc());
+        if self.reproducible {
+            return config.reproducible();
+        }
+        match self.chunk_size {
+            Some(chunk_size) => config.with_chunk_size(chunk_size),
+            None => config,
+        }
+    }
+
     /// Upload a file and return its CID (public/unencrypted), with auto-pin
     pub fn upload_file<P: AsRef<Path>>(&self, file_path: P) -> Result<String> {
         self.upload_file_internal(file_path, true)
@@ -586,8 +775,7 @@ impl HashtreeStore {
         let file_content = std::fs::read(file_path)?;
 
         // Use hashtree to store the file (public mode - no encryption)
-        let store = self.store_arc();
-        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let tree = HashTree::new(self.write_config().public());
 
         let (cid, _size) = sync_block_on(async {
             tree.put(&file_content).await
@@ -595,9 +783,7 @@ impl HashtreeStore {
 
         // Only pin if requested (htree add = pin, blossom upload = no pin)
         if pin {
-            let mut wtxn = self.env.write_txn()?;
-            self.pins.put(&mut wtxn, cid.hash.as_slice(), &())?;
-            wtxn.commit()?;
+            self.pin_cid(&cid)?;
         }
 
         Ok(to_hex(&cid.hash))
@@ -617,8 +803,7 @@ impl HashtreeStore {
         reader.read_to_end(&mut data)?;
 
         // Use HashTree.put for upload (public mode)
-        let store = self.store_arc();
-        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let tree = HashTree::new(self.write_config().public());
 
         let (cid, _size) = sync_block_on(async {
             tree.put(&data).await
@@ -628,9 +813,7 @@ impl HashtreeStore {
         callback(&root_hex);
 
         // Auto-pin on upload
-        let mut wtxn = self.env.write_txn()?;
-        self.pins.put(&mut wtxn, cid.hash.as_slice(), &())?;
-        wtxn.commit()?;
+        self.pin_cid(&cid)?;
 
         Ok(root_hex)
     }
@@ -645,8 +828,7 @@ impl HashtreeStore {
     pub fn upload_dir_with_options<P: AsRef<Path>>(&self, dir_path: P, respect_gitignore: bool) -> Result<String> {
         let dir_path = dir_path.as_ref();
 
-        let store = self.store_arc();
-        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let tree = HashTree::new(self.write_config().public());
 
         let root_cid = sync_block_on(async {
             self.upload_dir_recursive(&tree, dir_path, dir_path, respect_gitignore).await
@@ -654,9 +836,7 @@ impl HashtreeStore {
 
         let root_hex = to_hex(&root_cid.hash);
 
-        let mut wtxn = self.env.write_txn()?;
-        self.pins.put(&mut wtxn, root_cid.hash.as_slice(), &())?;
-        wtxn.commit()?;
+        self.pin_cid(&root_cid)?;
 
         Ok(root_hex)
     }
@@ -668,19 +848,13 @@ impl HashtreeStore {
         current_path: &Path,
         respect_gitignore: bool,
     ) -> Result<Cid> {
-        use ignore::WalkBuilder;
         use std::collections::HashMap;
 
         // Build directory structure from flat file list - store full Cid with key
         let mut dir_contents: HashMap<String, Vec<(String, Cid)>> = HashMap::new();
         dir_contents.insert(String::new(), Vec::new()); // Root
 
-        let walker = WalkBuilder::new(current_path)
-            .git_ignore(respect_gitignore)
-            .git_global(respect_gitignore)
-            .git_exclude(respect_gitignore)
-            .hidden(false)
-            .build();
+        let walker = dir_walker(current_path, respect_gitignore, self.reproducible);
 
         for result in walker {
             let entry = result?;
@@ -775,8 +949,7 @@ impl HashtreeStore {
         let file_content = std::fs::read(file_path)?;
 
         // Use unified API with encryption enabled (default)
-        let store = self.store_arc();
-        let tree = HashTree::new(HashTreeConfig::new(store));
+        let tree = HashTree::new(self.write_config());
 
         let (cid, _size) = sync_block_on(async {
             tree.put(&file_content).await
@@ -784,9 +957,7 @@ impl HashtreeStore {
 
         let cid_str = cid.to_string();
 
-        let mut wtxn = self.env.write_txn()?;
-        self.pins.put(&mut wtxn, cid.hash.as_slice(), &())?;
-        wtxn.commit()?;
+        self.pin_cid(&cid)?;
 
         Ok(cid_str)
     }
@@ -801,10 +972,9 @@ impl HashtreeStore {
     /// Returns CID as "hash:key" format for encrypted directories
     pub fn upload_dir_encrypted_with_options<P: AsRef<Path>>(&self, dir_path: P, respect_gitignore: bool) -> Result<String> {
         let dir_path = dir_path.as_ref();
-        let store = self.store_arc();
 
         // Use unified API with encryption enabled (default)
-        let tree = HashTree::new(HashTreeConfig::new(store));
+        let tree = HashTree::new(self.write_config());
 
         let root_cid = sync_block_on(async {
             self.upload_dir_recursive(&tree, dir_path, dir_path, respect_gitignore).await
@@ -812,14 +982,61 @@ impl HashtreeStore {
 
         let cid_str = root_cid.to_string(); // Returns "hash:key" or "hash"
 
-        let mut wtxn = self.env.write_txn()?;
-        // Pin by hash only (the key is for decryption, not identification)
-        self.pins.put(&mut wtxn, root_cid.hash.as_slice(), &())?;
-        wtxn.commit()?;
+        self.pin_cid(&root_cid)?;
 
         Ok(cid_str)
     }
 
+    /// Re-encrypt an encrypted directory tree under fresh directory keys
+    /// (see `HashTree::rotate_keys`), pins the new root and returns it
+    pub fn rotate_tree_key(&self, cid: &Cid) -> Result<Cid> {
+        let tree = HashTree::new(self.write_config());
+
+        let rotated = sync_block_on(async {
+            tree.rotate_keys(cid).await
+        }).map_err(|e| anyhow::anyhow!("Failed to rotate tree key: {}", e))?;
+
+        self.pin_cid(&rotated)?;
+
+        Ok(rotated)
+    }
+
+    /// Store a tar stream as a directory tree (see `archive::import_tar`),
+    /// encrypted unless `public`, and pin it
+    pub fn import_tar<R: Read>(&self, reader: R, public: bool) -> Result<TarImport> {
+        let config = if public {
+            self.write_config().public()
+        } else {
+            self.write_config()
+        };
+        let tree = HashTree::new(config);
+
+        let imported = sync_block_on(crate::archive::import_tar(&tree, reader))?;
+
+        self.pin_cid(&imported.cid)?;
+
+        Ok(imported)
+    }
+
+    /// Store a stream as one file, encrypted unless `public`, and pin it.
+    /// Returns the Cid and size.
+    pub fn import_stream<R: Read>(&self, reader: R, public: bool) -> Result<(Cid, u64)> {
+        let config = if public {
+            self.write_config().public()
+        } else {
+            self.write_config()
+        };
+        let tree = HashTree::new(config);
+
+        let reader = futures::io::AllowStdIo::new(reader);
+        let (cid, size) = sync_block_on(tree.put_stream(reader))
+            .map_err(|e| anyhow::anyhow!("Failed to store stream: {}", e))?;
+
+        self.pin_cid(&cid)?;
+
+        Ok((cid, size))
+    }
+
     /// Get tree node by hash (raw bytes)
     pub fn get_tree_node(&self, hash: &[u8; 32]) -> Result<Option<TreeNode>> {
         let store = self.store_arc();
@@ -1261,7 +1478,8 @@ impl HashtreeStore {
         })
     }
 
-    /// Pin a hash (prevent garbage collection)
+    /// Pin a hash (prevent garbage collection). Without a key, GC can only
+    /// walk it if it's a plain tree node; prefer `pin_cid`.
     pub fn pin(&self, hash: &[u8; 32]) -> Result<()> {
         let mut wtxn = self.env.write_txn()?;
         self.pins.put(&mut wtxn, hash.as_slice(), &())?;
@@ -1269,10 +1487,25 @@ impl HashtreeStore {
         Ok(())
     }
 
+    /// Pin a tree, keeping its key so GC can walk an encrypted tree. A
+    /// public pin stores an empty key, telling it apart from a bare `pin`.
+    pub fn pin_cid(&self, cid: &Cid) -> Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        self.pins.put(&mut wtxn, cid.hash.as_slice(), &())?;
+        let key: &[u8] = match &cid.key {
+            Some(key) => key.as_slice(),
+            None => &[],
+        };
+        self.pin_keys.put(&mut wtxn, cid.hash.as_slice(), key)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
     /// Unpin a hash (allow garbage collection)
     pub fn unpin(&self, hash: &[u8; 32]) -> Result<()> {
         let mut wtxn = self.env.write_txn()?;
         self.pins.delete(&mut wtxn, hash.as_slice())?;
+        self.pin_keys.delete(&mut wtxn, hash.as_slice())?;
         wtxn.commit()?;
         Ok(())
     }
@@ -1664,22 +1897,11 @@ impl HashtreeStore {
         let all_hashes = self.router.list()
             .map_err(|e| anyhow::anyhow!("Failed to list hashes: {}", e))?;
 
-        // Get pinned hashes as raw bytes
-        let rtxn = self.env.read_txn()?;
-        let pinned: HashSet<Hash> = self.pins.iter(&rtxn)?
-            .filter_map(|item| item.ok())
-            .filter_map(|(hash_bytes, _)| {
-                if hash_bytes.len() == 32 {
-                    let mut hash = [0u8; 32];
-                    hash.copy_from_slice(hash_bytes);
-                    Some(hash)
-                } else {
-                    None
-                }
-            })
-            .collect();
+        // Blocks of pinned trees
+        let pinned = self.pinned_blocks()?;
 
         // Collect all blob hashes that are in at least one tree
+        let rtxn = self.env.read_txn()?;
         // Key format is blob_hash (32 bytes) ++ tree_hash (32 bytes)
         let mut blobs_in_trees: HashSet<Hash> = HashSet::new();
         for item in self.blob_trees.iter(&rtxn)? {
@@ -1827,25 +2049,63 @@ impl HashtreeStore {
         Ok(deleted)
     }
 
-    /// Garbage collect unpinned content
-    pub fn gc(&self) -> Result<GcStats> {
+    /// Every block of the pinned trees, walking encrypted ones with their
+    /// pinned key. Blocks missing locally are included; nothing is fetched.
+    ///
+    /// Fails if a root pinned without saying whether it has a key isn't a
+    /// plain tree node: it may be an encrypted tree whose blocks can't be
+    /// found, and GC would delete them.
+    pub fn pinned_blocks(&self) -> Result<HashSet<Hash>> {
         let rtxn = self.env.read_txn()?;
+        let mut roots = Vec::new();
+        let mut unknown = Vec::new();
+        for item in self.pins.iter(&rtxn)? {
+            let (hash_bytes, _) = item?;
+            let Ok(hash) = Hash::try_from(hash_bytes) else {
+                continue;
+            };
+            match self.pin_keys.get(&rtxn, hash_bytes)? {
+                Some(key) => roots.push(Cid {
+                    hash,
+                    key: <[u8; 32]>::try_from(key).ok(),
+                }),
+                None => unknown.push(hash),
+            }
+        }
+        drop(rtxn);
 
-        // Get all pinned hashes as raw bytes
-        let pinned: HashSet<Hash> = self.pins.iter(&rtxn)?
-            .filter_map(|item| item.ok())
-            .filter_map(|(hash_bytes, _)| {
-                if hash_bytes.len() == 32 {
-                    let mut hash = [0u8; 32];
-                    hash.copy_from_slice(hash_bytes);
-                    Some(hash)
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let mut unreadable = Vec::new();
+        for hash in unknown {
+            match self.router.get_sync(&hash)? {
+                Some(data) if !is_tree_node(&data) => unreadable.push(to_hex(&hash)),
+                _ => roots.push(Cid::public(hash)),
+            }
+        }
+        if !unreadable.is_empty() {
+            anyhow::bail!(
+                "Pinned without a key, so their trees can't be walked: {}. Pin them again as hash:key (or unpin them) before collecting garbage",
+                unreadable.join(", ")
+            );
+        }
 
-        drop(rtxn);
+        let tree = HashTree::new(HashTreeConfig::new(self.store_arc()));
+        let mut blocks = HashSet::new();
+        for root in roots {
+            let hashes = sync_block_on(hashtree_core::collect_hashes(&tree, &root, 32))
+                .map_err(|e| anyhow::anyhow!("Failed to walk pinned tree {}: {}", to_hex(&root.hash), e))?;
+            blocks.extend(hashes);
+        }
+        Ok(blocks)
+    }
+
+    /// Garbage collect content that isn't part of a pinned tree
+    pub fn gc(&self) -> Result<GcStats> {
+        self.gc_with_options(false)
+    }
+
+    /// Garbage collect, or with `dry_run` only count what would be deleted
+    pub fn gc_with_options(&self, dry_run: bool) -> Result<GcStats> {
+        let pinned = self.pinned_blocks()?;
 
         // Get all stored hashes
         let all_hashes = self.router.list()
@@ -1859,8 +2119,10 @@ impl HashtreeStore {
             if !pinned.contains(&hash) {
                 if let Ok(Some(data)) = self.router.get_sync(&hash) {
                     freed_bytes += data.len() as u64;
-                    // Delete locally only - keep S3 as archive
-                    let _ = self.router.delete_local_only(&hash);
+                    if !dry_run {
+                        // Delete locally only - keep S3 as archive
+                        let _ = self.router.delete_local_only(&hash);
+                    }
                     deleted += 1;
                 }
             }
@@ -1938,6 +2200,49 @@ impl HashtreeStore {
         })
     }
 
+    /// Re-hash every local blob and take corrupted ones out of the store.
+    /// Corrupted data is moved to `quarantine` if given, otherwise deleted.
+    /// S3 copies are left alone.
+    pub fn scrub(&self, quarantine: Option<&Path>) -> Result<ScrubResult> {
+        let local = self.router.local_store();
+        let all_hashes = local.list()
+            .map_err(|e| anyhow::anyhow!("Failed to list hashes: {}", e))?;
+
+        let mut result = ScrubResult {
+            checked: 0,
+            bytes: 0,
+            corrupted: Vec::new(),
+            unreadable: Vec::new(),
+        };
+        for hash in all_hashes {
+            let data = match local.get_sync(&hash) {
+                Ok(Some(data)) => data,
+                // Deleted or evicted since listing
+                Ok(None) => continue,
+                // Could be transient, so the blob stays
+                Err(e) => {
+                    tracing::warn!("Failed to read {}: {}", to_hex(&hash), e);
+                    result.unreadable.push(hash);
+                    continue;
+                }
+            };
+            result.checked += 1;
+            result.bytes += data.len() as u64;
+            if sha256(&data) == hash {
+                continue;
+            }
+
+            if let Some(dir) = quarantine {
+                std::fs::create_dir_all(dir)?;
+                std::fs::write(dir.join(to_hex(&hash)), &data)?;
+            }
+            self.router.delete_local_only(&hash)
+                .map_err(|e| anyhow::anyhow!("Failed to delete {}: {}", to_hex(&hash), e))?;
+            result.corrupted.push(hash);
+        }
+        Ok(result)
+    }
+
     /// Verify R2/S3 blob integrity - lists all objects and verifies hash matches filename
     /// Returns verification statistics and optionally deletes corrupted entries
     #[cfg(feature = "s3")]
@@ -2111,6 +2416,19 @@ pub struct VerifyResult {
     pub deleted: usize,
 }
 
+/// Result of a local blob scrub
+#[derive(Debug, Clone)]
+pub struct ScrubResult {
+    /// Blobs re-hashed
+    pub checked: usize,
+    /// Bytes read while re-hashing
+    pub bytes: u64,
+    /// Blobs that failed verification and were removed
+    pub corrupted: Vec<Hash>,
+    /// Blobs that couldn't be read, left in the store
+    pub unreadable: Vec<Hash>,
+}
+
 #[derive(Debug)]
 pub struct StorageStats {
     pub total_dags: usize,
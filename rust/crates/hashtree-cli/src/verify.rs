@@ -0,0 +1,282 @@
+//! Deep integrity check of a tree
+//!
+//! `htree verify` walks a tree from its root and hashes every block, so a
+//! backup can be checked from cron: blocks come from local storage, or
+//! from the Blossom servers when not local, and each is reported missing
+//! or corrupt with the path it was reached by. Blocks are decrypted with
+//! their key when one is known, so a node that doesn't decrypt or decode
+//! is caught too, and the walk continues below it.
+
+use anyhow::Result;
+use hashtree_blossom::BlossomClient;
+use hashtree_core::{
+    decode_tree_node, decrypt_chk, is_tree_node, sha256, to_hex, Cid, Hash, Store,
+};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Where a block was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSource {
+    Local,
+    Blossom,
+}
+
+impl fmt::Display for BlockSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockSource::Local => write!(f, "local"),
+            BlockSource::Blossom => write!(f, "blossom"),
+        }
+    }
+}
+
+/// A block that failed verification
+#[derive(Debug, Clone)]
+pub struct BadBlock {
+    pub hash: Hash,
+    /// Path in the tree the block was reached by
+    pub path: String,
+    /// Where the bad copy was found; None if it wasn't found anywhere
+    pub source: Option<BlockSource>,
+    pub reason: String,
+}
+
+impl BadBlock {
+    pub fn is_missing(&self) -> bool {
+        self.source.is_none()
+    }
+}
+
+/// Outcome of verifying a tree
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Distinct blocks checked
+    pub blocks: usize,
+    /// Bytes of the blocks as stored
+    pub bytes: u64,
+    /// Blocks read from local storage
+    pub local: usize,
+    /// Blocks fetched from Blossom servers
+    pub blossom: usize,
+    pub bad: Vec<BadBlock>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.bad.is_empty()
+    }
+
+    pub fn missing(&self) -> usize {
+        self.bad.iter().filter(|b| b.is_missing()).count()
+    }
+
+    pub fn corrupt(&self) -> usize {
+        self.bad.len() - self.missing()
+    }
+}
+
+/// Verify every block of the tree at `root`, reading from `local` and,
+/// for blocks not there, from `blossom` if given. Fetched blocks aren't
+/// stored locally.
+pub async fn verify_tree<S: Store>(
+    local: Arc<S>,
+    blossom: Option<&BlossomClient>,
+    root: &Cid,
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut visited: HashSet<Hash> = HashSet::new();
+    let mut stack = vec![(root.hash, root.key, String::new())];
+
+    while let Some((hash, key, path)) = stack.pop() {
+        if !visited.insert(hash) {
+            continue;
+        }
+        let fetched = fetch(&local, blossom, &hash, &path, &mut report).await?;
+        let Some((data, source)) = fetched else {
+            continue;
+        };
+        report.blocks += 1;
+        report.bytes += data.len() as u64;
+
+        let plain = match key {
+            Some(key) => match decrypt_chk(&data, &key) {
+                Ok(plain) => plain,
+                Err(e) => {
+                    report.bad.push(BadBlock {
+                        hash,
+                        path,
+                        source: Some(source),
+                        reason: format!("decryption failed: {}", e),
+                    });
+                    continue;
+                }
+            },
+            None => data,
+        };
+        if !is_tree_node(&plain) {
+            continue;
+        }
+        let node = match decode_tree_node(&plain) {
+            Ok(node) => node,
+            Err(e) => {
+                report.bad.push(BadBlock {
+                    hash,
+                    path,
+                    source: Some(source),
+                    reason: format!("invalid tree node: {}", e),
+                });
+                continue;
+            }
+        };
+        for link in node.links.into_iter().rev() {
+            // Chunks of a file are reached by the file's path
+            let child_path = match &link.name {
+                Some(name) if !name.starts_with('_') => format!("{}/{}", path, name),
+                _ => path.clone(),
+            };
+            stack.push((link.hash, link.key, child_path));
+        }
+    }
+    Ok(report)
+}
+
+/// A good copy of block `hash` and where it came from: the local one, or
+/// else Blossom's. Bad and missing copies go into `report`.
+async fn fetch<S: Store>(
+    local: &Arc<S>,
+    blossom: Option<&BlossomClient>,
+    hash: &Hash,
+    path: &str,
+    report: &mut VerifyReport,
+) -> Result<Option<(Vec<u8>, BlockSource)>> {
+    let bad = |source, reason: String| BadBlock {
+        hash: *hash,
+        path: path.to_string(),
+        source,
+        reason,
+    };
+
+    let local_data = local
+        .get(hash)
+        .await
+        .map_err(|e| anyhow::anyhow!("Local storage error: {}", e))?;
+    if let Some(data) = local_data {
+        if sha256(&data) == *hash {
+            report.local += 1;
+            return Ok(Some((data, BlockSource::Local)));
+        }
+        let reason = format!(
+            "hash mismatch, content hashes to {}",
+            to_hex(&sha256(&data))
+        );
+        report.bad.push(bad(Some(BlockSource::Local), reason));
+        // Still walk below it if Blossom has a good copy
+        let good = match blossom {
+            Some(blossom) => blossom.download(&to_hex(hash)).await.ok(),
+            None => None,
+        };
+        if let Some(data) = good {
+            report.blossom += 1;
+            return Ok(Some((data, BlockSource::Blossom)));
+        }
+        return Ok(None);
+    }
+
+    let Some(blossom) = blossom else {
+        report
+            .bad
+            .push(bad(None, "not in local storage".to_string()));
+        return Ok(None);
+    };
+    // Blossom downloads are checked against the hash
+    match blossom.download(&to_hex(hash)).await {
+        Ok(data) => {
+            report.blossom += 1;
+            Ok(Some((data, BlockSource::Blossom)))
+        }
+        Err(e) => {
+            report.bad.push(bad(None, format!("not found: {}", e)));
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::{DirEntry, HashTree, HashTreeConfig, LinkType, MemoryStore};
+
+    async fn build(encrypted: bool) -> (Arc<MemoryStore>, Cid, Cid) {
+        let store = Arc::new(MemoryStore::new());
+        let tree = |chunk_size| {
+            let config = HashTreeConfig::new(store.clone()).with_chunk_size(chunk_size);
+            HashTree::new(if encrypted { config } else { config.public() })
+        };
+        // A file of 3 chunks, in directories small enough to be one block
+        let (file, size) = tree(4).put(b"hello world").await.unwrap();
+        let tree = tree(hashtree_core::DEFAULT_CHUNK_SIZE);
+        let entry = DirEntry::from_cid("a.txt", &file)
+            .with_size(size)
+            .with_link_type(LinkType::File);
+        let inner = tree.put_directory(vec![entry]).await.unwrap();
+        let docs = DirEntry::from_cid("docs", &inner).with_link_type(LinkType::Dir);
+        let root = tree.put_directory(vec![docs]).await.unwrap();
+        (store, root, file)
+    }
+
+    #[tokio::test]
+    async fn test_intact_tree_verifies() {
+        let (store, root, _) = build(false).await;
+        let report = verify_tree(store, None, &root).await.unwrap();
+        assert!(report.is_ok(), "{:?}", report.bad);
+        // Root, docs, the file node and its 3 chunks
+        assert_eq!(report.blocks, 6);
+        assert_eq!(report.local, 6);
+    }
+
+    #[tokio::test]
+    async fn test_intact_encrypted_tree_verifies() {
+        let (store, root, _) = build(true).await;
+        assert!(root.key.is_some());
+        let report = verify_tree(store, None, &root).await.unwrap();
+        assert!(report.is_ok(), "{:?}", report.bad);
+        assert_eq!(report.blocks, 6);
+    }
+
+    #[tokio::test]
+    async fn test_missing_and_corrupt_blocks_are_reported() {
+        let (store, root, file) = build(false).await;
+        let node = HashTree::new(HashTreeConfig::new(store.clone()).public())
+            .get_node(&file)
+            .await
+            .unwrap()
+            .unwrap();
+        store.delete(&node.links[0].hash).await.unwrap();
+        store.delete(&node.links[1].hash).await.unwrap();
+        store
+            .put(node.links[1].hash, b"oops".to_vec())
+            .await
+            .unwrap();
+
+        let report = verify_tree(store, None, &root).await.unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.missing(), 1);
+        assert_eq!(report.corrupt(), 1);
+        assert!(report.bad.iter().all(|b| b.path == "/docs/a.txt"));
+        assert_eq!(report.bad[1].source, Some(BlockSource::Local));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_key_is_reported() {
+        let (store, root, _) = build(true).await;
+        let wrong = Cid {
+            hash: root.hash,
+            key: Some([9u8; 32]),
+        };
+        let report = verify_tree(store, None, &wrong).await.unwrap();
+        assert_eq!(report.corrupt(), 1);
+        assert!(report.bad[0].reason.starts_with("decryption failed"));
+    }
+}
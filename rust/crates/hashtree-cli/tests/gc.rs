@@ -0,0 +1,75 @@
+//! Integration tests for pinning trees and garbage collection
+//!
+//! Run with: cargo test --package hashtree-cli --test gc
+
+use futures::executor::block_on;
+use hashtree_cli::storage::HashtreeStore;
+use hashtree_core::{from_hex, to_hex, Cid, DirEntry, HashTree, HashTreeConfig, LinkType};
+use tempfile::TempDir;
+
+/// An encrypted directory holding a file of several chunks
+fn put_tree(store: &HashtreeStore) -> Cid {
+    let chunked = HashTree::new(HashTreeConfig::new(store.store_arc()).with_chunk_size(4));
+    let tree = HashTree::new(HashTreeConfig::new(store.store_arc()));
+    block_on(async {
+        let (file, size) = chunked.put(b"hello world").await.unwrap();
+        let entry = DirEntry::from_cid("a.txt", &file)
+            .with_size(size)
+            .with_link_type(LinkType::File);
+        tree.put_directory(vec![entry]).await.unwrap()
+    })
+}
+
+#[test]
+fn test_gc_keeps_every_block_of_pinned_trees() {
+    let temp = TempDir::new().unwrap();
+    let store = HashtreeStore::new(temp.path()).unwrap();
+
+    let root = put_tree(&store);
+    assert!(root.key.is_some());
+    let loose = from_hex(&store.put_blob(b"not pinned").unwrap()).unwrap();
+    store.pin_cid(&root).unwrap();
+    // Root, file node and 3 chunks
+    assert_eq!(store.pinned_blocks().unwrap().len(), 5);
+
+    let dry = store.gc_with_options(true).unwrap();
+    assert_eq!((dry.deleted_dags, dry.freed_bytes), (1, 10));
+    assert!(store.blob_exists(&loose).unwrap());
+
+    let stats = store.gc().unwrap();
+    assert_eq!(stats.deleted_dags, 1);
+    assert!(!store.blob_exists(&loose).unwrap());
+    let file = store.resolve_path(&root, "a.txt").unwrap().unwrap();
+    assert_eq!(
+        store.get_file_by_cid(&file).unwrap().unwrap(),
+        b"hello world"
+    );
+
+    // Unpinned, the whole tree goes
+    store.unpin(&root.hash).unwrap();
+    assert_eq!(store.gc().unwrap().deleted_dags, 5);
+    assert!(!store.blob_exists(&root.hash).unwrap());
+}
+
+#[test]
+fn test_gc_refuses_roots_pinned_without_their_key() {
+    let temp = TempDir::new().unwrap();
+    let store = HashtreeStore::new(temp.path()).unwrap();
+
+    let root = put_tree(&store);
+    store.pin(&root.hash).unwrap();
+    let err = store.gc().unwrap_err();
+    assert!(err.to_string().contains(&to_hex(&root.hash)));
+    assert_eq!(store.pinned_blocks().map(|b| b.len()).ok(), None);
+    assert!(store.resolve_path(&root, "a.txt").unwrap().is_some());
+
+    // With its key the tree is walked again
+    store.pin_cid(&root).unwrap();
+    assert_eq!(store.gc().unwrap().deleted_dags, 0);
+
+    // A public blob pinned as such needs no key
+    let blob = from_hex(&store.put_blob(b"public").unwrap()).unwrap();
+    store.pin_cid(&Cid::public(blob)).unwrap();
+    assert_eq!(store.gc().unwrap().deleted_dags, 0);
+    assert!(store.blob_exists(&blob).unwrap());
+}
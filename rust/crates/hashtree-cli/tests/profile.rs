@@ -5,202 +5,15 @@
 //! Run with: cargo test --package hashtree-cli --test profile -- --nocapture
 
 use anyhow::Result;
+use hashtree_testkit::MockRelay;
 use nostr::{Keys, ToBech32, EventBuilder, Kind, Filter};
 use nostr_sdk::{ClientBuilder, EventSource};
 use std::time::Duration;
 
-mod test_relay {
-    use std::collections::HashMap;
-    use std::net::TcpListener;
-    use nostr::Filter;
-    use std::sync::Arc;
-    use futures::{SinkExt, StreamExt};
-    use tokio::net::TcpStream;
-    use tokio::sync::{broadcast, RwLock};
-    use tokio_tungstenite::{accept_async, tungstenite::Message};
-
-    pub struct TestRelay {
-        port: u16,
-        shutdown: broadcast::Sender<()>,
-    }
-
-    impl TestRelay {
-        pub fn new(port: u16) -> Self {
-            let events: Arc<RwLock<HashMap<String, serde_json::Value>>> = Arc::new(RwLock::new(HashMap::new()));
-            let (shutdown, _) = broadcast::channel(1);
-            let (event_tx, _) = broadcast::channel::<serde_json::Value>(1000);
-
-            let relay = TestRelay {
-                port,
-                shutdown: shutdown.clone(),
-            };
-
-            let events_clone = events.clone();
-            let mut shutdown_rx = shutdown.subscribe();
-            let event_tx_clone = event_tx.clone();
-
-            std::thread::spawn(move || {
-                let rt = tokio::runtime::Builder::new_multi_thread()
-                    .worker_threads(2)
-                    .enable_all()
-                    .build()
-                    .unwrap();
-
-                rt.block_on(async move {
-                    let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap();
-                    listener.set_nonblocking(true).unwrap();
-                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
-
-                    loop {
-                        tokio::select! {
-                            _ = shutdown_rx.recv() => break,
-                            result = listener.accept() => {
-                                if let Ok((stream, _)) = result {
-                                    let events = events_clone.clone();
-                                    let event_tx = event_tx_clone.clone();
-                                    let event_rx = event_tx_clone.subscribe();
-                                    tokio::spawn(handle_connection(stream, events, event_tx, event_rx));
-                                }
-                            }
-                        }
-                    }
-                });
-            });
-
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            relay
-        }
-
-        pub fn url(&self) -> String {
-            format!("ws://127.0.0.1:{}", self.port)
-        }
-    }
-
-    impl Drop for TestRelay {
-        fn drop(&mut self) {
-            let _ = self.shutdown.send(());
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-    }
-
-    async fn handle_connection(
-        stream: TcpStream,
-        events: Arc<RwLock<HashMap<String, serde_json::Value>>>,
-        event_tx: broadcast::Sender<serde_json::Value>,
-        mut event_rx: broadcast::Receiver<serde_json::Value>,
-    ) {
-        let ws_stream = match accept_async(stream).await {
-            Ok(s) => s,
-            Err(_) => return,
-        };
-
-        let (write, mut read) = ws_stream.split();
-        let write = Arc::new(tokio::sync::Mutex::new(write));
-
-        let subscriptions: Arc<RwLock<HashMap<String, Filter>>> = Arc::new(RwLock::new(HashMap::new()));
-
-        // Broadcast task for live events
-        let write_clone = write.clone();
-        let subs_clone = subscriptions.clone();
-        let broadcast_task = tokio::spawn(async move {
-            loop {
-                match event_rx.recv().await {
-                    Ok(event) => {
-                        let subs = subs_clone.read().await;
-                        for (sub_id, _filter) in subs.iter() {
-                            // Simple: broadcast all events to all subs
-                            let event_msg = serde_json::json!(["EVENT", sub_id, &event]);
-                            let mut w = write_clone.lock().await;
-                            let _ = w.send(Message::Text(event_msg.to_string())).await;
-                        }
-                    }
-                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
-                    Err(broadcast::error::RecvError::Closed) => break,
-                }
-            }
-        });
-
-        while let Some(msg) = read.next().await {
-            let msg = match msg {
-                Ok(Message::Text(t)) => t,
-                Ok(Message::Close(_)) => break,
-                Ok(Message::Ping(data)) => {
-                    let mut w = write.lock().await;
-                    let _ = w.send(Message::Pong(data)).await;
-                    continue;
-                }
-                _ => continue,
-            };
-
-            let parsed: Result<Vec<serde_json::Value>, _> = serde_json::from_str(&msg);
-            let parsed = match parsed {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
-
-            if parsed.is_empty() {
-                continue;
-            }
-
-            let msg_type = parsed[0].as_str().unwrap_or("");
-
-            match msg_type {
-                "EVENT" => {
-                    if parsed.len() >= 2 {
-                        let event = parsed[1].clone();
-                        if let Some(id) = event.get("id").and_then(|v| v.as_str()) {
-                            events.write().await.insert(id.to_string(), event.clone());
-
-                            let ok_msg = serde_json::json!(["OK", id, true, ""]);
-                            {
-                                let mut w = write.lock().await;
-                                let _ = w.send(Message::Text(ok_msg.to_string())).await;
-                            }
-
-                            let _ = event_tx.send(event);
-                        }
-                    }
-                }
-                "REQ" => {
-                    if parsed.len() >= 3 {
-                        let sub_id = parsed[1].as_str().unwrap_or("sub").to_string();
-
-                        // Store subscription
-                        subscriptions.write().await.insert(sub_id.clone(), Filter::new());
-
-                        // Send matching stored events
-                        let stored = events.read().await;
-                        for (_id, event) in stored.iter() {
-                            let event_msg = serde_json::json!(["EVENT", &sub_id, event]);
-                            let mut w = write.lock().await;
-                            let _ = w.send(Message::Text(event_msg.to_string())).await;
-                        }
-
-                        // Send EOSE
-                        let eose = serde_json::json!(["EOSE", &sub_id]);
-                        let mut w = write.lock().await;
-                        let _ = w.send(Message::Text(eose.to_string())).await;
-                    }
-                }
-                "CLOSE" => {
-                    if parsed.len() >= 2 {
-                        if let Some(sub_id) = parsed[1].as_str() {
-                            subscriptions.write().await.remove(sub_id);
-                        }
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        broadcast_task.abort();
-    }
-}
-
 #[tokio::test]
 async fn test_profile_publish_and_fetch() -> Result<()> {
     // Start test relay
-    let relay = test_relay::TestRelay::new(14850);
+    let relay = MockRelay::start();
     let relay_url = relay.url();
 
     // Generate test keys
@@ -257,7 +70,7 @@ async fn test_profile_publish_and_fetch() -> Result<()> {
 #[tokio::test]
 async fn test_profile_update_merges_fields() -> Result<()> {
     // Start test relay
-    let relay = test_relay::TestRelay::new(14851);
+    let relay = MockRelay::start();
     let relay_url = relay.url();
 
     // Generate test keys
@@ -342,7 +155,7 @@ async fn test_profile_update_merges_fields() -> Result<()> {
 #[tokio::test]
 async fn test_fetch_peer_profile_name() -> Result<()> {
     // Start test relay
-    let relay = test_relay::TestRelay::new(14852);
+    let relay = MockRelay::start();
     let relay_url = relay.url();
 
     // Generate keys for a "peer"
@@ -402,7 +215,7 @@ async fn test_fetch_peer_profile_name() -> Result<()> {
 #[tokio::test]
 async fn test_fetch_missing_profile_returns_none() -> Result<()> {
     // Start test relay
-    let relay = test_relay::TestRelay::new(14853);
+    let relay = MockRelay::start();
     let relay_url = relay.url();
 
     // Generate keys for a user with NO profile
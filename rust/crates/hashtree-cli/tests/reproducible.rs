@@ -0,0 +1,79 @@
+//! Integration test for reproducible tree building (`htree add --reproducible`)
+//!
+//! Run with: cargo test --package hashtree-cli --test reproducible
+
+use hashtree_cli::storage::HashtreeStore;
+use hashtree_core::from_hex;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Write `files` (path, content) under `dir` in the given order
+fn write_tree(dir: &Path, files: &[(&str, Vec<u8>)]) {
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+#[test]
+fn test_same_contents_same_root() {
+    // A local chunk size, which reproducible builds must not pick up
+    let config_dir = TempDir::new().unwrap();
+    fs::write(
+        config_dir.path().join("config.toml"),
+        "[storage]\nchunk_size = 1024\n",
+    )
+    .unwrap();
+    std::env::set_var("HTREE_CONFIG_DIR", config_dir.path());
+
+    let files = vec![
+        ("index.html", b"<h1>hello</h1>".to_vec()),
+        ("assets/app.js", vec![7u8; 5000]),
+        ("assets/app.js.map", b"{}".to_vec()),
+        ("build/cache", b"local".to_vec()),
+        (".gitignore", b"build/\n".to_vec()),
+    ];
+
+    // One copy written in reverse order, inside a directory whose own
+    // .gitignore would drop the source maps
+    let first = TempDir::new().unwrap();
+    write_tree(first.path(), &files);
+    let outer = TempDir::new().unwrap();
+    fs::write(outer.path().join(".gitignore"), "*.map\n").unwrap();
+    let second = outer.path().join("site");
+    let mut reversed = files.clone();
+    reversed.reverse();
+    write_tree(&second, &reversed);
+
+    let build = |dir: &Path| {
+        let data = TempDir::new().unwrap();
+        let store = HashtreeStore::new(data.path()).unwrap().reproducible();
+        let public = store.upload_dir(dir).unwrap();
+        let encrypted = store.upload_dir_encrypted(dir).unwrap();
+        let names: Vec<String> = store
+            .get_directory_listing(&from_hex(&public).unwrap())
+            .unwrap()
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        (public, encrypted, names)
+    };
+
+    let (public, encrypted, names) = build(first.path());
+    assert_eq!(build(&second), (public, encrypted, names.clone()));
+
+    // The tree's own .gitignore applies without a git checkout
+    assert!(!names.contains(&"build".to_string()));
+    assert!(names.contains(&".gitignore".to_string()));
+
+    // Only a normal build uses the configured chunk size
+    let app = first.path().join("assets/app.js");
+    let data = TempDir::new().unwrap();
+    let store = HashtreeStore::new(data.path()).unwrap();
+    let chunked = store.upload_file(&app).unwrap();
+    assert_ne!(store.reproducible().upload_file(&app).unwrap(), chunked);
+}
@@ -0,0 +1,39 @@
+//! Integration tests for scrubbing corrupted blobs out of the local store
+//!
+//! Run with: cargo test --package hashtree-cli --test scrub
+
+use hashtree_cli::storage::HashtreeStore;
+use hashtree_core::{sha256, to_hex};
+use tempfile::TempDir;
+
+#[test]
+fn test_scrub_quarantines_corrupted_blobs() {
+    let temp = TempDir::new().unwrap();
+    let store = HashtreeStore::new(temp.path()).unwrap();
+
+    let good = store.put_blob(b"intact").unwrap();
+    // Stored under a hash its content doesn't match, like a flipped bit would
+    let bad = sha256(b"original");
+    store.router().put_sync(bad, b"0riginal").unwrap();
+
+    let quarantine = temp.path().join("quarantine");
+    let result = store.scrub(Some(&quarantine)).unwrap();
+    assert_eq!(result.checked, 2);
+    assert_eq!(result.bytes, 14);
+    assert_eq!(result.corrupted, vec![bad]);
+
+    assert!(!store.blob_exists(&bad).unwrap());
+    assert_eq!(
+        std::fs::read(quarantine.join(to_hex(&bad))).unwrap(),
+        b"0riginal"
+    );
+    assert!(store
+        .get_blob(&hashtree_core::from_hex(&good).unwrap())
+        .unwrap()
+        .is_some());
+
+    // Nothing left to find
+    let result = store.scrub(None).unwrap();
+    assert_eq!(result.checked, 1);
+    assert!(result.corrupted.is_empty());
+}
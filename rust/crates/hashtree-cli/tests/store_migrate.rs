@@ -0,0 +1,69 @@
+//! Integration tests for converting the blob store between backends
+//!
+//! Run with: cargo test --package hashtree-cli --test store_migrate
+
+use hashtree_cli::config::StorageBackend;
+use hashtree_cli::storage::{migrate_blobs, HashtreeStore, LocalStore};
+use hashtree_core::sha256;
+use tempfile::TempDir;
+
+#[test]
+fn test_migrate_fs_to_pack_and_back() {
+    let temp = TempDir::new().unwrap();
+    let blobs_path = temp.path().join("blobs");
+
+    // Mix of blobs that get packed and ones that stay files
+    let blobs: Vec<Vec<u8>> = vec![b"small".to_vec(), vec![1u8; 200 * 1024], b"node".to_vec()];
+    {
+        let store = LocalStore::new(&blobs_path, &StorageBackend::Fs).unwrap();
+        for blob in &blobs {
+            store.put_sync(sha256(blob), blob).unwrap();
+        }
+    }
+
+    let stats = migrate_blobs(&blobs_path, &StorageBackend::Fs, &StorageBackend::Pack).unwrap();
+    assert_eq!(stats.blobs, 3);
+    assert_eq!(stats.bytes, blobs.iter().map(|b| b.len() as u64).sum::<u64>());
+    assert_eq!(stats.backup_path, temp.path().join("blobs.fs-old"));
+    assert!(blobs_path.join("pack.dat").exists());
+
+    let store = LocalStore::new(&blobs_path, &StorageBackend::Pack).unwrap();
+    for blob in &blobs {
+        assert_eq!(store.get_sync(&sha256(blob)).unwrap().as_ref(), Some(blob));
+    }
+    drop(store);
+
+    let back = migrate_blobs(&blobs_path, &StorageBackend::Pack, &StorageBackend::Fs).unwrap();
+    assert_eq!(back.backup_path, temp.path().join("blobs.pack-old"));
+    let store = LocalStore::new(&blobs_path, &StorageBackend::Fs).unwrap();
+    assert_eq!(store.list().unwrap().len(), 3);
+
+    // An existing backup is never overwritten
+    drop(store);
+    assert!(migrate_blobs(&blobs_path, &StorageBackend::Fs, &StorageBackend::Pack).is_err());
+}
+
+#[test]
+fn test_migrate_refuses_while_store_is_open() {
+    // Stores open with the default fs backend
+    let config_dir = TempDir::new().unwrap();
+    std::env::set_var("HTREE_CONFIG_DIR", config_dir.path());
+
+    let temp = TempDir::new().unwrap();
+    let store = HashtreeStore::new(temp.path()).unwrap();
+    let blobs_path = temp.path().join("blobs");
+
+    let err = migrate_blobs(&blobs_path, &StorageBackend::Fs, &StorageBackend::Pack).unwrap_err();
+    assert!(err.to_string().contains("in use"));
+    assert!(!temp.path().join("blobs.fs-old").exists());
+
+    drop(store);
+    migrate_blobs(&blobs_path, &StorageBackend::Fs, &StorageBackend::Pack).unwrap();
+}
+
+#[test]
+fn test_migrate_to_same_backend_fails() {
+    let temp = TempDir::new().unwrap();
+    let blobs_path = temp.path().join("blobs");
+    assert!(migrate_blobs(&blobs_path, &StorageBackend::Fs, &StorageBackend::Fs).is_err());
+}
@@ -10,6 +10,7 @@
 
 use anyhow::{Context, Result};
 use hashtree_cli::HashtreeStore;
+use hashtree_testkit::MockRelay;
 use nostr::{Keys, ToBech32};
 use std::fs;
 use std::path::PathBuf;
@@ -17,300 +18,6 @@ use std::process::{Child, Command, Stdio};
 use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
-mod test_relay {
-    use std::collections::HashMap;
-    use std::net::TcpListener;
-    use std::sync::Arc;
-    use futures::{SinkExt, StreamExt};
-    use tokio::net::TcpStream;
-    use tokio::sync::{broadcast, RwLock};
-    use tokio_tungstenite::{accept_async, tungstenite::Message};
-
-    #[derive(Clone)]
-    struct StoredFilter {
-        sub_id: String,
-        kind: Option<u64>,
-        authors: Vec<String>,
-        p_tag: Option<String>,
-        l_tag: Option<String>,
-    }
-
-    impl StoredFilter {
-        fn matches(&self, event: &serde_json::Value) -> bool {
-            if let Some(k) = self.kind {
-                if event.get("kind").and_then(|v| v.as_u64()) != Some(k) {
-                    return false;
-                }
-            }
-
-            if !self.authors.is_empty() {
-                let event_author = event.get("pubkey").and_then(|v| v.as_str()).unwrap_or("");
-                if !self.authors.iter().any(|a| a == event_author) {
-                    return false;
-                }
-            }
-
-            if let Some(ref p) = self.p_tag {
-                let has_p = event.get("tags")
-                    .and_then(|t| t.as_array())
-                    .map(|tags| {
-                        tags.iter().any(|tag| {
-                            tag.as_array().map(|arr| {
-                                arr.len() >= 2 &&
-                                arr[0].as_str() == Some("p") &&
-                                arr[1].as_str() == Some(p.as_str())
-                            }).unwrap_or(false)
-                        })
-                    })
-                    .unwrap_or(false);
-                if !has_p {
-                    return false;
-                }
-            }
-
-            if let Some(ref l) = self.l_tag {
-                let has_l = event.get("tags")
-                    .and_then(|t| t.as_array())
-                    .map(|tags| {
-                        tags.iter().any(|tag| {
-                            tag.as_array().map(|arr| {
-                                arr.len() >= 2 &&
-                                arr[0].as_str() == Some("l") &&
-                                arr[1].as_str() == Some(l.as_str())
-                            }).unwrap_or(false)
-                        })
-                    })
-                    .unwrap_or(false);
-                if !has_l {
-                    return false;
-                }
-            }
-
-            true
-        }
-    }
-
-    pub struct TestRelay {
-        port: u16,
-        shutdown: broadcast::Sender<()>,
-    }
-
-    impl TestRelay {
-        pub fn new(port: u16) -> Self {
-            let events: Arc<RwLock<HashMap<String, serde_json::Value>>> = Arc::new(RwLock::new(HashMap::new()));
-            let (shutdown, _) = broadcast::channel(1);
-            let (event_tx, _) = broadcast::channel::<serde_json::Value>(1000);
-
-            let relay = TestRelay {
-                port,
-                shutdown: shutdown.clone(),
-            };
-
-            let events_clone = events.clone();
-            let mut shutdown_rx = shutdown.subscribe();
-            let event_tx_clone = event_tx.clone();
-
-            std::thread::spawn(move || {
-                let rt = tokio::runtime::Builder::new_multi_thread()
-                    .worker_threads(2)
-                    .enable_all()
-                    .build()
-                    .unwrap();
-
-                rt.block_on(async move {
-                    let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap();
-                    listener.set_nonblocking(true).unwrap();
-                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
-
-                    loop {
-                        tokio::select! {
-                            _ = shutdown_rx.recv() => break,
-                            result = listener.accept() => {
-                                if let Ok((stream, _)) = result {
-                                    let events = events_clone.clone();
-                                    let event_tx = event_tx_clone.clone();
-                                    let event_rx = event_tx_clone.subscribe();
-                                    tokio::spawn(handle_connection(stream, events, event_tx, event_rx));
-                                }
-                            }
-                        }
-                    }
-                });
-            });
-
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            relay
-        }
-
-        pub fn url(&self) -> String {
-            format!("ws://127.0.0.1:{}", self.port)
-        }
-    }
-
-    impl Drop for TestRelay {
-        fn drop(&mut self) {
-            let _ = self.shutdown.send(());
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-    }
-
-    async fn handle_connection(
-        stream: TcpStream,
-        events: Arc<RwLock<HashMap<String, serde_json::Value>>>,
-        event_tx: broadcast::Sender<serde_json::Value>,
-        mut event_rx: broadcast::Receiver<serde_json::Value>,
-    ) {
-        let ws_stream = match accept_async(stream).await {
-            Ok(s) => s,
-            Err(_) => return,
-        };
-
-        let (write, mut read) = ws_stream.split();
-        let write = Arc::new(tokio::sync::Mutex::new(write));
-
-        let subscriptions: Arc<RwLock<HashMap<String, Vec<StoredFilter>>>> = Arc::new(RwLock::new(HashMap::new()));
-
-        let write_clone = write.clone();
-        let subs_clone = subscriptions.clone();
-        let broadcast_task = tokio::spawn(async move {
-            loop {
-                match event_rx.recv().await {
-                    Ok(event) => {
-                        let subs = subs_clone.read().await;
-                        for (_, filters) in subs.iter() {
-                            for filter in filters {
-                                if filter.matches(&event) {
-                                    let event_msg = serde_json::json!(["EVENT", &filter.sub_id, &event]);
-                                    let mut w = write_clone.lock().await;
-                                    let _ = w.send(Message::Text(event_msg.to_string())).await;
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
-                    Err(broadcast::error::RecvError::Closed) => break,
-                }
-            }
-        });
-
-        while let Some(msg) = read.next().await {
-            let msg = match msg {
-                Ok(Message::Text(t)) => t,
-                Ok(Message::Close(_)) => break,
-                Ok(Message::Ping(data)) => {
-                    let mut w = write.lock().await;
-                    let _ = w.send(Message::Pong(data)).await;
-                    continue;
-                }
-                _ => continue,
-            };
-
-            let parsed: Result<Vec<serde_json::Value>, _> = serde_json::from_str(&msg);
-            let parsed = match parsed {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
-
-            if parsed.is_empty() {
-                continue;
-            }
-
-            let msg_type = parsed[0].as_str().unwrap_or("");
-
-            match msg_type {
-                "EVENT" => {
-                    if parsed.len() >= 2 {
-                        let event = parsed[1].clone();
-                        if let Some(id) = event.get("id").and_then(|v| v.as_str()) {
-                            events.write().await.insert(id.to_string(), event.clone());
-
-                            let ok_msg = serde_json::json!(["OK", id, true, ""]);
-                            {
-                                let mut w = write.lock().await;
-                                let _ = w.send(Message::Text(ok_msg.to_string())).await;
-                            }
-
-                            let _ = event_tx.send(event);
-                        }
-                    }
-                }
-                "REQ" => {
-                    if parsed.len() >= 3 {
-                        let sub_id = parsed[1].as_str().unwrap_or("sub").to_string();
-
-                        let mut filters = Vec::new();
-                        for i in 2..parsed.len() {
-                            let filter = &parsed[i];
-
-                            let kind = filter.get("kinds")
-                                .and_then(|k| k.as_array())
-                                .and_then(|a| a.first())
-                                .and_then(|v| v.as_u64());
-
-                            let authors: Vec<String> = filter.get("authors")
-                                .and_then(|a| a.as_array())
-                                .map(|arr| arr.iter()
-                                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                                    .collect())
-                                .unwrap_or_default();
-
-                            let p_tag = filter.get("#p")
-                                .and_then(|p| p.as_array())
-                                .and_then(|a| a.first())
-                                .and_then(|v| v.as_str())
-                                .map(|s| s.to_string());
-
-                            let l_tag = filter.get("#l")
-                                .and_then(|l| l.as_array())
-                                .and_then(|a| a.first())
-                                .and_then(|v| v.as_str())
-                                .map(|s| s.to_string());
-
-                            filters.push(StoredFilter {
-                                sub_id: sub_id.clone(),
-                                kind,
-                                authors,
-                                p_tag,
-                                l_tag,
-                            });
-                        }
-
-                        subscriptions.write().await.insert(sub_id.clone(), filters.clone());
-
-                        let events_lock = events.read().await;
-                        let mut w = write.lock().await;
-
-                        for event in events_lock.values() {
-                            for filter in &filters {
-                                if filter.matches(event) {
-                                    let event_msg = serde_json::json!(["EVENT", &sub_id, event]);
-                                    let _ = w.send(Message::Text(event_msg.to_string())).await;
-                                    break;
-                                }
-                            }
-                        }
-                        drop(events_lock);
-
-                        let eose = serde_json::json!(["EOSE", &sub_id]);
-                        let _ = w.send(Message::Text(eose.to_string())).await;
-                    }
-                }
-                "CLOSE" => {
-                    if parsed.len() >= 2 {
-                        if let Some(sub_id) = parsed[1].as_str() {
-                            subscriptions.write().await.remove(sub_id);
-                        }
-                    }
-                }
-                _ => {}
-            }
-        }
-
-        broadcast_task.abort();
-    }
-}
-
 struct TestInstance {
     _data_dir: TempDir,
     process: Option<Child>,
@@ -933,7 +640,7 @@ fn extract_cid(text: &str) -> Option<String> {
 #[test]
 fn test_two_instances_connect_local_relay() -> Result<()> {
     let htree_bin = find_htree_binary();
-    let relay = test_relay::TestRelay::new(19110);
+    let relay = MockRelay::start();
     let relay_url = relay.url();
 
     let keys_a = Keys::generate();
@@ -1,8 +1,23 @@
 //! Shared configuration for hashtree tools
 //!
-//! Reads from ~/.hashtree/config.toml
+//! Reads from ~/.hashtree/config.toml. Named `[profiles.<name>]` tables
+//! override the settings above them when selected with `HTREE_PROFILE`
+//! (`htree --profile <name>`):
+//!
+//! ```toml
+//! [nostr]
+//! relays = ["wss://relay.damus.io"]
+//!
+//! [profiles.work.nostr]
+//! relays = ["wss://relay.example.com"]
+//! keys_file = "~/.hashtree/work.keys"
+//!
+//! [profiles.work.storage]
+//! data_dir = "/srv/hashtree"
+//! ```
 
 use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
@@ -27,6 +42,13 @@ pub const DEFAULT_RELAYS: &[&str] = &[
     "wss://offchain.pub",
 ];
 
+/// Environment variable naming the config profile to apply
+pub const PROFILE_ENV: &str = "HTREE_PROFILE";
+
+/// Environment variable holding the passphrase of encrypted keys, for
+/// scripts and tools that can't prompt
+pub const PASSPHRASE_ENV: &str = "HTREE_PASSPHRASE";
+
 /// Top-level config structure
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
@@ -85,6 +107,8 @@ pub enum StorageBackend {
     Fs,
     /// LMDB storage - requires lmdb feature
     Lmdb,
+    /// Packfile storage - small blobs in one pack file, large ones as files
+    Pack,
 }
 
 impl Default for StorageBackend {
@@ -93,10 +117,34 @@ impl Default for StorageBackend {
     }
 }
 
+impl StorageBackend {
+    /// Name used in the config file
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fs => "fs",
+            Self::Lmdb => "lmdb",
+            Self::Pack => "pack",
+        }
+    }
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "fs" => Ok(Self::Fs),
+            "lmdb" => Ok(Self::Lmdb),
+            "pack" => Ok(Self::Pack),
+            _ => Err(format!("unknown storage backend '{}' (expected fs, lmdb or pack)", s)),
+        }
+    }
+}
+
 /// Storage configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageConfig {
-    /// Storage backend: "fs" (default) or "lmdb"
+    /// Storage backend: "fs" (default), "lmdb" or "pack"
     #[serde(default)]
     pub backend: StorageBackend,
     #[serde(default = "default_data_dir")]
@@ -105,6 +153,13 @@ pub struct StorageConfig {
     pub max_size_gb: u64,
     #[serde(default)]
     pub s3: Option<S3Config>,
+    /// Chunk size in bytes for files added; the hashtree default if unset
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_chunk_size"
+    )]
+    pub chunk_size: Option<usize>,
 }
 
 impl Default for StorageConfig {
@@ -114,10 +169,23 @@ impl Default for StorageConfig {
             data_dir: default_data_dir(),
             max_size_gb: default_max_size_gb(),
             s3: None,
+            chunk_size: None,
         }
     }
 }
 
+/// A zero chunk size would never make progress splitting a file
+fn deserialize_chunk_size<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<usize>, D::Error> {
+    match Option::<usize>::deserialize(deserializer)? {
+        Some(0) => Err(serde::de::Error::custom(
+            "chunk_size must be greater than 0",
+        )),
+        chunk_size => Ok(chunk_size),
+    }
+}
+
 fn default_data_dir() -> String {
     get_hashtree_dir()
         .join("data")
@@ -146,6 +214,9 @@ pub struct NostrConfig {
     pub relays: Vec<String>,
     #[serde(default)]
     pub allowed_npubs: Vec<String>,
+    /// Keys file to use instead of ~/.hashtree/keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keys_file: Option<String>,
 }
 
 impl Default for NostrConfig {
@@ -153,6 +224,7 @@ impl Default for NostrConfig {
         Self {
             relays: default_relays(),
             allowed_npubs: vec![],
+            keys_file: None,
         }
     }
 }
@@ -275,7 +347,7 @@ impl Config {
         if config_path.exists() {
             let content = fs::read_to_string(&config_path)
                 .context("Failed to read config file")?;
-            toml::from_str(&content).context("Failed to parse config file")
+            parse_config(&content)
         } else {
             let config = Config::default();
             config.save()?;
@@ -303,6 +375,97 @@ impl Config {
     }
 }
 
+/// The profile named by `HTREE_PROFILE`, if any
+pub fn active_profile() -> Option<String> {
+    std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty())
+}
+
+/// Parse config file content, applying the active profile
+pub fn parse_config<T: DeserializeOwned>(content: &str) -> Result<T> {
+    parse_config_with_profile(content, active_profile().as_deref())
+}
+
+/// Parse config file content, with the settings of `profile` on top
+pub fn parse_config_with_profile<T: DeserializeOwned>(
+    content: &str,
+    profile: Option<&str>,
+) -> Result<T> {
+    let mut table: toml::Table = toml::from_str(content).context("Failed to parse config file")?;
+    let profiles = table.remove("profiles");
+    if let Some(name) = profile {
+        let overlay = profiles
+            .as_ref()
+            .and_then(|profiles| profiles.get(name))
+            .and_then(|profile| profile.as_table())
+            .with_context(|| format!("Unknown config profile '{}'", name))?;
+        merge_tables(&mut table, overlay);
+    }
+    toml::Value::Table(table)
+        .try_into()
+        .context("Failed to parse config file")
+}
+
+/// Overlay `overlay` on `base`, merging tables and replacing other values
+fn merge_tables(base: &mut toml::Table, overlay: &toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Set `key` of `[section]` in the config file, in the active profile's
+/// table if there is one, leaving the rest of the file (comments included)
+/// as it is
+pub fn set_config_value(section: &str, key: &str, value: impl Into<toml::Value>) -> Result<()> {
+    let config_path = get_config_path();
+    let content = if config_path.exists() {
+        fs::read_to_string(&config_path).context("Failed to read config file")?
+    } else {
+        String::new()
+    };
+    let mut document: toml_edit::DocumentMut =
+        content.parse().context("Failed to parse config file")?;
+    let value: toml_edit::Value = value
+        .into()
+        .to_string()
+        .parse()
+        .context("Invalid config value")?;
+
+    let mut target = document.as_table_mut();
+    if let Some(profile) = active_profile() {
+        target = child_table(target, "profiles")?;
+        target = child_table(target, &profile)?;
+    }
+    child_table(target, section)?.insert(key, toml_edit::Item::Value(value));
+    let content = document.to_string();
+    parse_config::<Config>(&content)?;
+
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&config_path, content)?;
+    Ok(())
+}
+
+fn child_table<'a>(table: &'a mut toml_edit::Table, key: &str) -> Result<&'a mut toml_edit::Table> {
+    table
+        .entry(key)
+        .or_insert_with(|| {
+            // Implicit, so `[profiles]` isn't written above `[profiles.work]`
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            toml_edit::Item::Table(table)
+        })
+        .as_table_mut()
+        .with_context(|| format!("'{}' in config file is not a table", key))
+}
+
 /// Get the hashtree directory (~/.hashtree)
 pub fn get_hashtree_dir() -> PathBuf {
     if let Ok(dir) = std::env::var("HTREE_CONFIG_DIR") {
@@ -318,23 +481,51 @@ pub fn get_config_path() -> PathBuf {
     get_hashtree_dir().join("config.toml")
 }
 
-/// Get the keys file path (~/.hashtree/keys)
-pub fn get_keys_path() -> PathBuf {
-    get_hashtree_dir().join("keys")
+/// Get the keys file path (~/.hashtree/keys, or the config's `keys_file`)
+///
+/// Fails if the config file can't be read, rather than guessing a path
+/// the keys may not be at.
+pub fn get_keys_path() -> Result<PathBuf> {
+    let config_path = get_config_path();
+    let keys_file = if config_path.exists() {
+        let content = fs::read_to_string(&config_path).context("Failed to read config file")?;
+        parse_config::<Config>(&content)?.nostr.keys_file
+    } else {
+        None
+    };
+    Ok(match keys_file {
+        Some(path) => expand_home(&path),
+        None => get_hashtree_dir().join("keys"),
+    })
+}
+
+/// Expand a leading `~/` to the home directory
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
 }
 
 /// A stored key entry from the keys file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct KeyEntry {
-    /// The nsec or hex secret key
+    /// The nsec, hex or NIP-49 ncryptsec secret key
     pub secret: String,
     /// Optional alias/petname
     pub alias: Option<String>,
 }
 
+impl KeyEntry {
+    /// Whether the secret is encrypted with a passphrase (NIP-49)
+    pub fn is_encrypted(&self) -> bool {
+        self.secret.starts_with("ncryptsec1")
+    }
+}
+
 /// Parse the keys file content into key entries
-/// Format: `nsec1... [alias]` or `hex... [alias]` per line
-/// Lines starting with # are comments
+/// Format: `nsec1... [alias]`, `ncryptsec1... [alias]` or `hex... [alias]`
+/// per line. Lines starting with # are comments
 pub fn parse_keys_file(content: &str) -> Vec<KeyEntry> {
     let mut entries = Vec::new();
     for line in content.lines() {
@@ -350,13 +541,26 @@ pub fn parse_keys_file(content: &str) -> Vec<KeyEntry> {
     entries
 }
 
+/// Keys file content for `entries`, the inverse of `parse_keys_file`
+pub fn format_keys_file(entries: &[KeyEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| match &entry.alias {
+            Some(alias) => format!("{} {}\n", entry.secret, alias),
+            None => format!("{}\n", entry.secret),
+        })
+        .collect()
+}
+
 /// Read and parse keys file, returning the first key's secret
 /// Returns None if file doesn't exist or is empty
-pub fn read_first_key() -> Option<String> {
-    let keys_path = get_keys_path();
-    let content = std::fs::read_to_string(&keys_path).ok()?;
+pub fn read_first_key() -> Result<Option<String>> {
+    let keys_path = get_keys_path()?;
+    let Ok(content) = std::fs::read_to_string(&keys_path) else {
+        return Ok(None);
+    };
     let entries = parse_keys_file(&content);
-    entries.into_iter().next().map(|e| e.secret)
+    Ok(entries.into_iter().next().map(|e| e.secret))
 }
 
 /// Get the auth cookie path (~/.hashtree/auth.cookie)
@@ -465,6 +669,18 @@ backend = "lmdb"
         assert_eq!(config.storage.backend, StorageBackend::Lmdb);
     }
 
+    #[test]
+    fn test_storage_backend_pack() {
+        let toml = r#"
+[storage]
+backend = "pack"
+"#;
+        let config: Config = toml::from_str(toml).unwrap();
+        assert_eq!(config.storage.backend, StorageBackend::Pack);
+        assert_eq!("pack".parse::<StorageBackend>(), Ok(StorageBackend::Pack));
+        assert!("sqlite".parse::<StorageBackend>().is_err());
+    }
+
     #[test]
     fn test_storage_backend_fs_explicit() {
         let toml = r#"
@@ -475,6 +691,55 @@ backend = "fs"
         assert_eq!(config.storage.backend, StorageBackend::Fs);
     }
 
+    #[test]
+    fn test_profile_overrides_settings() {
+        let toml = r#"
+[nostr]
+relays = ["wss://a"]
+allowed_npubs = ["npub1x"]
+
+[storage]
+data_dir = "/data"
+
+[profiles.work.nostr]
+relays = ["wss://b"]
+keys_file = "~/work.keys"
+
+[profiles.work.storage]
+chunk_size = 65536
+"#;
+        let base: Config = parse_config_with_profile(toml, None).unwrap();
+        assert_eq!(base.nostr.relays, vec!["wss://a"]);
+        assert_eq!(base.nostr.keys_file, None);
+        assert_eq!(base.storage.chunk_size, None);
+
+        let work: Config = parse_config_with_profile(toml, Some("work")).unwrap();
+        assert_eq!(work.nostr.relays, vec!["wss://b"]);
+        // Settings the profile doesn't set are kept
+        assert_eq!(work.nostr.allowed_npubs, vec!["npub1x"]);
+        assert_eq!(work.storage.data_dir, "/data");
+        assert_eq!(work.storage.chunk_size, Some(65536));
+        assert_eq!(work.nostr.keys_file.as_deref(), Some("~/work.keys"));
+
+        let missing = parse_config_with_profile::<Config>(toml, Some("home"));
+        assert!(missing.unwrap_err().to_string().contains("home"));
+    }
+
+    #[test]
+    fn test_zero_chunk_size_is_rejected() {
+        let err =
+            parse_config_with_profile::<Config>("[storage]\nchunk_size = 0\n", None).unwrap_err();
+        assert!(format!("{:#}", err).contains("chunk_size must be greater than 0"));
+    }
+
+    #[test]
+    fn test_expand_home() {
+        assert_eq!(expand_home("/etc/keys"), PathBuf::from("/etc/keys"));
+        if let Some(home) = dirs::home_dir() {
+            assert_eq!(expand_home("~/keys"), home.join("keys"));
+        }
+    }
+
     #[test]
     fn test_parse_keys_file() {
         let content = r#"
@@ -492,6 +757,11 @@ nsec1ghi789
         assert_eq!(entries[1].alias, Some("work".to_string()));
         assert_eq!(entries[2].secret, "nsec1ghi789");
         assert_eq!(entries[2].alias, None);
+        assert!(!entries[0].is_encrypted());
+
+        assert_eq!(parse_keys_file(&format_keys_file(&entries)), entries);
+        let encrypted = parse_keys_file("ncryptsec1qgg9 work");
+        assert!(encrypted[0].is_encrypted());
     }
 
     #[test]
@@ -23,6 +23,11 @@ use std::collections::{BTreeMap, HashMap};
 use crate::hash::sha256;
 use crate::types::{Hash, Link, LinkType, TreeNode};
 
+/// Largest tree node decoded, in bytes. Chunked directories are reassembled
+/// and decoded whole, so this bounds what a crafted tree can make a reader
+/// hold in memory; it's far above any real directory (~500k entries).
+pub const MAX_NODE_SIZE: usize = 64 * 1024 * 1024;
+
 /// Error type for codec operations
 #[derive(Debug, thiserror::Error)]
 pub enum CodecError {
@@ -38,6 +43,18 @@ pub enum CodecError {
     MsgpackDecode(String),
     #[error("Invalid hash length: expected 32, got {0}")]
     InvalidHashLength(usize),
+    #[error("Tree node too large: {0} bytes")]
+    NodeTooLarge(usize),
+    #[error("Overflow adding up {0}")]
+    Overflow(&'static str),
+}
+
+/// Total size of `links`, which can't be trusted not to overflow
+pub fn links_size(links: &[Link]) -> Result<u64, CodecError> {
+    links
+        .iter()
+        .try_fold(0u64, |total, link| total.checked_add(link.size))
+        .ok_or(CodecError::Overflow("link sizes"))
 }
 
 /// Wire format for a link (compact keys)
@@ -127,6 +144,9 @@ pub fn encode_tree_node(node: &TreeNode) -> Result<Vec<u8>, CodecError> {
 
 /// Decode MessagePack to a tree node
 pub fn decode_tree_node(data: &[u8]) -> Result<TreeNode, CodecError> {
+    if data.len() > MAX_NODE_SIZE {
+        return Err(CodecError::NodeTooLarge(data.len()));
+    }
     let wire: WireTreeNode =
         rmp_serde::from_slice(data).map_err(|e| CodecError::MsgpackDecode(e.to_string()))?;
 
@@ -348,6 +368,17 @@ mod tests {
         assert!(!is_tree_node(&invalid));
     }
 
+    #[test]
+    fn test_decode_refuses_oversized_node() {
+        let mut data = encode_tree_node(&TreeNode::dir(vec![])).unwrap();
+        data.resize(MAX_NODE_SIZE + 1, 0);
+        assert!(matches!(
+            decode_tree_node(&data),
+            Err(CodecError::NodeTooLarge(_))
+        ));
+        assert!(!is_tree_node(&data));
+    }
+
     #[test]
     fn test_is_directory_node() {
         let node = TreeNode::dir(vec![Link {
@@ -3,21 +3,36 @@
 //! Single struct for creating, reading, and editing content-addressed merkle trees.
 //! Mirrors the hashtree-ts HashTree class API.
 
+use std::collections::VecDeque;
+use std::future::Future;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
-use futures::stream::{self, Stream};
+use futures::stream::{self, FuturesOrdered, Stream, StreamExt};
 use futures::io::AsyncRead;
 use futures::AsyncReadExt;
 
 use crate::builder::{BuilderError, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_LINKS};
-use crate::codec::{decode_tree_node, encode_and_hash, is_directory_node, is_tree_node, try_decode_tree_node};
+use crate::codec::{decode_tree_node, encode_and_hash, is_directory_node, is_tree_node, links_size, try_decode_tree_node, CodecError, MAX_NODE_SIZE};
 use crate::hash::sha256;
+use crate::pack::pack_entries;
 use crate::reader::{ReaderError, TreeEntry, WalkEntry};
-use crate::store::Store;
+use crate::store::{slice_range, Store, StoreError};
 use crate::types::{to_hex, Cid, DirEntry, Hash, Link, LinkType, TreeNode};
 
-use crate::crypto::{decrypt_chk, encrypt_chk, EncryptionKey};
+use crate::crypto::{decrypt_chk, encrypt_chk, generate_key, EncryptionKey};
+
+/// Link metadata key holding the salt added by [`HashTree::rotate_keys`]
+pub const KEY_SALT_META: &str = "keySalt";
+
+/// Leaf blocks a file stream fetches ahead of the one being read
+pub const DEFAULT_PREFETCH: usize = 4;
+
+/// Levels of internal nodes followed below a node when reassembling it or
+/// looking up a name. Default fanout reaches petabytes in under 8, so
+/// deeper trees are crafted to make readers recurse.
+pub const MAX_TREE_DEPTH: usize = 32;
 
 /// HashTree configuration
 #[derive(Clone)]
@@ -27,6 +42,14 @@ pub struct HashTreeConfig<S: Store> {
     pub max_links: usize,
     /// Whether to encrypt content (default: true when encryption feature enabled)
     pub encrypted: bool,
+    /// Leaf blocks [`HashTree::get_stream`] fetches ahead of the one being
+    /// read; 0 reads one block at a time. Set with
+    /// [`HashTreeConfig::with_prefetch`]
+    prefetch: usize,
+    /// Blob entries up to this size are packed when directories are
+    /// written (see [`crate::pack`]); 0 disables packing. Set with
+    /// [`HashTreeConfig::with_packing`]
+    pack_threshold: usize,
 }
 
 impl<S: Store> HashTreeConfig<S> {
@@ -36,6 +59,8 @@ impl<S: Store> HashTreeConfig<S> {
             chunk_size: DEFAULT_CHUNK_SIZE,
             max_links: DEFAULT_MAX_LINKS,
             encrypted: true,
+            prefetch: DEFAULT_PREFETCH,
+            pack_threshold: 0,
         }
     }
 
@@ -54,6 +79,29 @@ impl<S: Store> HashTreeConfig<S> {
         self.encrypted = false;
         self
     }
+
+    /// Fetch up to `blocks` leaf blocks ahead while streaming a file
+    pub fn with_prefetch(mut self, blocks: usize) -> Self {
+        self.prefetch = blocks;
+        self
+    }
+
+    /// Pack blob entries of up to `threshold` bytes into shared blocks
+    pub fn with_packing(mut self, threshold: usize) -> Self {
+        self.pack_threshold = threshold;
+        self
+    }
+
+    /// Reset chunking to the defaults and turn packing off, whatever was
+    /// configured, so the same content always gets the same root. Entry
+    /// order needs nothing: directories are sorted by name, and CHK
+    /// encryption derives keys from content.
+    pub fn reproducible(mut self) -> Self {
+        self.chunk_size = DEFAULT_CHUNK_SIZE;
+        self.max_links = DEFAULT_MAX_LINKS;
+        self.pack_threshold = 0;
+        self
+    }
 }
 
 /// HashTree error type
@@ -73,6 +121,10 @@ pub enum HashTreeError {
     Encryption(String),
     #[error("Decryption error: {0}")]
     Decryption(String),
+    #[error("Tree deeper than {0} levels")]
+    TooDeep(usize),
+    #[error("Invalid log: {0}")]
+    InvalidLog(String),
 }
 
 impl From<BuilderError> for HashTreeError {
@@ -103,6 +155,8 @@ pub struct HashTree<S: Store> {
     chunk_size: usize,
     max_links: usize,
     encrypted: bool,
+    prefetch: usize,
+    pack_threshold: AtomicUsize,
 }
 
 impl<S: Store> HashTree<S> {
@@ -112,6 +166,8 @@ impl<S: Store> HashTree<S> {
             chunk_size: config.chunk_size,
             max_links: config.max_links,
             encrypted: config.encrypted,
+            prefetch: config.prefetch,
+            pack_threshold: AtomicUsize::new(config.pack_threshold),
         }
     }
 
@@ -234,131 +290,13 @@ impl<S: Store> HashTree<S> {
     ///
     /// Returns an async stream that yields chunks as they are read.
     /// Useful for large files or when you want to process data incrementally.
+    /// While a chunk is read, the next ones linked from the same tree node
+    /// are fetched concurrently, up to the configured prefetch window.
     pub fn get_stream(
         &self,
         cid: &Cid,
     ) -> Pin<Box<dyn Stream<Item = Result<Vec<u8>, HashTreeError>> + Send + '_>> {
-        let hash = cid.hash;
-        let key = cid.key;
-
-        if let Some(k) = key {
-            // Encrypted stream
-            Box::pin(self.read_file_stream_encrypted(hash, k))
-        } else {
-            // Unencrypted stream
-            self.read_file_stream(hash)
-        }
-    }
-
-    /// Read encrypted file as stream (internal)
-    fn read_file_stream_encrypted(
-        &self,
-        hash: Hash,
-        key: EncryptionKey,
-    ) -> impl Stream<Item = Result<Vec<u8>, HashTreeError>> + Send + '_ {
-        stream::unfold(
-            EncryptedStreamState::Init { hash, key, tree: self },
-            |state| async move {
-                match state {
-                    EncryptedStreamState::Init { hash, key, tree } => {
-                        let data = match tree.store.get(&hash).await {
-                            Ok(Some(d)) => d,
-                            Ok(None) => return None,
-                            Err(e) => return Some((Err(HashTreeError::Store(e.to_string())), EncryptedStreamState::Done)),
-                        };
-
-                        // Try to decrypt
-                        let decrypted = match decrypt_chk(&data, &key) {
-                            Ok(d) => d,
-                            Err(e) => return Some((Err(HashTreeError::Decryption(e.to_string())), EncryptedStreamState::Done)),
-                        };
-
-                        if !is_tree_node(&decrypted) {
-                            // Single blob - yield decrypted data
-                            return Some((Ok(decrypted), EncryptedStreamState::Done));
-                        }
-
-                        // Tree node - parse and traverse
-                        let node = match decode_tree_node(&decrypted) {
-                            Ok(n) => n,
-                            Err(e) => return Some((Err(HashTreeError::Codec(e)), EncryptedStreamState::Done)),
-                        };
-
-                        let mut stack: Vec<EncryptedStackItem> = Vec::new();
-                        for link in node.links.into_iter().rev() {
-                            stack.push(EncryptedStackItem { hash: link.hash, key: link.key });
-                        }
-
-                        tree.process_encrypted_stream_stack(&mut stack).await
-                    }
-                    EncryptedStreamState::Processing { mut stack, tree } => {
-                        tree.process_encrypted_stream_stack(&mut stack).await
-                    }
-                    EncryptedStreamState::Done => None,
-                }
-            },
-        )
-    }
-
-    async fn process_encrypted_stream_stack<'a>(
-        &'a self,
-        stack: &mut Vec<EncryptedStackItem>,
-    ) -> Option<(Result<Vec<u8>, HashTreeError>, EncryptedStreamState<'a, S>)> {
-        while let Some(item) = stack.pop() {
-            let data = match self.store.get(&item.hash).await {
-                Ok(Some(d)) => d,
-                Ok(None) => {
-                    return Some((
-                        Err(HashTreeError::MissingChunk(to_hex(&item.hash))),
-                        EncryptedStreamState::Done,
-                    ))
-                }
-                Err(e) => {
-                    return Some((
-                        Err(HashTreeError::Store(e.to_string())),
-                        EncryptedStreamState::Done,
-                    ))
-                }
-            };
-
-            // Decrypt if we have a key
-            let decrypted = if let Some(key) = item.key {
-                match decrypt_chk(&data, &key) {
-                    Ok(d) => d,
-                    Err(e) => {
-                        return Some((
-                            Err(HashTreeError::Decryption(e.to_string())),
-                            EncryptedStreamState::Done,
-                        ))
-                    }
-                }
-            } else {
-                data
-            };
-
-            if is_tree_node(&decrypted) {
-                // Nested tree node - add children to stack
-                let node = match decode_tree_node(&decrypted) {
-                    Ok(n) => n,
-                    Err(e) => {
-                        return Some((
-                            Err(HashTreeError::Codec(e)),
-                            EncryptedStreamState::Done,
-                        ))
-                    }
-                };
-                for link in node.links.into_iter().rev() {
-                    stack.push(EncryptedStackItem { hash: link.hash, key: link.key });
-                }
-            } else {
-                // Leaf chunk - yield decrypted data
-                return Some((
-                    Ok(decrypted),
-                    EncryptedStreamState::Processing { stack: std::mem::take(stack), tree: self },
-                ));
-            }
-        }
-        None
+        FileStream::new(self, cid.hash, cid.key).into_stream()
     }
 
     /// Store a chunk with optional encryption
@@ -564,6 +502,11 @@ impl<S: Store> HashTree<S> {
         let mut sorted = entries;
         sorted.sort_by(|a, b| a.name.cmp(&b.name));
 
+        let pack_threshold = self.pack_threshold();
+        if pack_threshold > 0 {
+            pack_entries(self.store.as_ref(), &mut sorted, pack_threshold).await?;
+        }
+
         let links: Vec<Link> = sorted
             .into_iter()
             .map(|e| Link {
@@ -679,9 +622,11 @@ impl<S: Store> HashTree<S> {
 
         let node = decode_tree_node(&decrypted)?;
 
-        // If this is a file tree (chunked data), reassemble to get actual directory
-        if node.node_type == LinkType::File {
-            let assembled = self.assemble_chunks(&node).await?;
+        // If this is a file tree (chunked data), reassemble to get actual directory.
+        // Files too large to be a directory node are left unread.
+        let size = links_size(&node.links)?;
+        if node.node_type == LinkType::File && size <= MAX_NODE_SIZE as u64 {
+            let assembled = self.assemble_chunks(&node, 0).await?;
             if is_tree_node(&assembled) {
                 let inner_node = decode_tree_node(&assembled)?;
                 return Ok(Some(inner_node));
@@ -733,7 +678,7 @@ impl<S: Store> HashTree<S> {
 
         // It's a tree - reassemble chunks
         let node = decode_tree_node(&data)?;
-        let assembled = self.assemble_chunks(&node).await?;
+        let assembled = self.assemble_chunks(&node, 0).await?;
         Ok(Some(assembled))
     }
 
@@ -802,107 +747,82 @@ impl<S: Store> HashTree<S> {
         start: u64,
         end: Option<u64>,
     ) -> Result<Vec<u8>, HashTreeError> {
-        // First, flatten the tree to get all leaf chunks with their byte offsets
-        let chunks_info = self.collect_chunk_offsets(node).await?;
-
-        if chunks_info.is_empty() {
-            return Ok(vec![]);
+        let end = end.unwrap_or(u64::MAX);
+        let mut result = Vec::new();
+        if start < end {
+            self.read_range_below(node, 0, start, end, &mut result).await?;
         }
-
-        // Calculate total size and actual end
-        let total_size: u64 = chunks_info.iter().map(|(_, _, size)| size).sum();
-        let actual_end = end.unwrap_or(total_size).min(total_size);
-
-        if start >= actual_end {
-            return Ok(vec![]);
-        }
-
-        // Find chunks that overlap with [start, actual_end)
-        let mut result = Vec::with_capacity((actual_end - start) as usize);
-        let mut current_offset = 0u64;
-
-        for (chunk_hash, _chunk_offset, chunk_size) in &chunks_info {
-            let chunk_start = current_offset;
-            let chunk_end = current_offset + chunk_size;
-
-            // Check if this chunk overlaps with our range
-            if chunk_end > start && chunk_start < actual_end {
-                // Fetch this chunk
-                let chunk_data = self
-                    .store
-                    .get(chunk_hash)
-                    .await
-                    .map_err(|e| HashTreeError::Store(e.to_string()))?
-                    .ok_or_else(|| HashTreeError::MissingChunk(to_hex(chunk_hash)))?;
-
-                // Calculate slice bounds within this chunk
-                let slice_start = if start > chunk_start {
-                    (start - chunk_start) as usize
-                } else {
-                    0
-                };
-                let slice_end = if actual_end < chunk_end {
-                    (actual_end - chunk_start) as usize
-                } else {
-                    chunk_data.len()
-                };
-
-                result.extend_from_slice(&chunk_data[slice_start..slice_end]);
-            }
-
-            current_offset = chunk_end;
-
-            // Early exit if we've passed the requested range
-            if current_offset >= actual_end {
-                break;
-            }
-        }
-
         Ok(result)
     }
 
-    /// Collect all leaf chunk hashes with their byte offsets
-    /// Returns Vec<(hash, offset, size)>
-    async fn collect_chunk_offsets(
-        &self,
-        node: &TreeNode,
-    ) -> Result<Vec<(Hash, u64, u64)>, HashTreeError> {
-        let mut chunks = Vec::new();
-        let mut offset = 0u64;
-        self.collect_chunk_offsets_recursive(node, &mut chunks, &mut offset).await?;
-        Ok(chunks)
-    }
-
-    async fn collect_chunk_offsets_recursive(
+    /// Append the bytes in `[start, end)` of the file below `node`, whose
+    /// data begins at `offset`, and return the offset after it. Link sizes
+    /// let subtrees outside the range go unfetched and leaves it only
+    /// partly covers be read with `get_range`; links without a size are
+    /// fetched to find it.
+    async fn read_range_below(
         &self,
         node: &TreeNode,
-        chunks: &mut Vec<(Hash, u64, u64)>,
-        offset: &mut u64,
-    ) -> Result<(), HashTreeError> {
+        mut offset: u64,
+        start: u64,
+        end: u64,
+        out: &mut Vec<u8>,
+    ) -> Result<u64, HashTreeError> {
         for link in &node.links {
-            let child_data = self
+            if offset >= end {
+                break;
+            }
+            let link_end = offset
+                .checked_add(link.size)
+                .ok_or(CodecError::Overflow("link sizes"))?;
+            if link.size > 0 && link_end <= start {
+                offset = link_end;
+                continue;
+            }
+
+            if link.size > 0 && link.link_type == LinkType::Blob {
+                let slice_start = start.saturating_sub(offset);
+                let slice_end = end.min(link_end) - offset;
+                let data = if slice_start == 0 && slice_end == link.size {
+                    self.store.get(&link.hash).await
+                } else {
+                    self.store
+                        .get_range(&link.hash, slice_start, slice_end - slice_start)
+                        .await
+                }
+                .map_err(|e| HashTreeError::Store(e.to_string()))?
+                .ok_or_else(|| HashTreeError::MissingChunk(to_hex(&link.hash)))?;
+                out.extend_from_slice(&data);
+                offset = link_end;
+                continue;
+            }
+
+            let data = self
                 .store
                 .get(&link.hash)
                 .await
                 .map_err(|e| HashTreeError::Store(e.to_string()))?
                 .ok_or_else(|| HashTreeError::MissingChunk(to_hex(&link.hash)))?;
-
-            if is_tree_node(&child_data) {
-                // Intermediate node - recurse
-                let child_node = decode_tree_node(&child_data)?;
-                Box::pin(self.collect_chunk_offsets_recursive(&child_node, chunks, offset)).await?;
+            if is_tree_node(&data) {
+                let child = decode_tree_node(&data)?;
+                offset = Box::pin(self.read_range_below(&child, offset, start, end, out)).await?;
             } else {
-                // Leaf chunk
-                let size = child_data.len() as u64;
-                chunks.push((link.hash, *offset, size));
-                *offset += size;
+                let from = start.saturating_sub(offset);
+                let to = end.saturating_sub(offset);
+                out.extend_from_slice(slice_range(&data, from, to.saturating_sub(from)));
+                offset = offset
+                    .checked_add(data.len() as u64)
+                    .ok_or(CodecError::Overflow("link sizes"))?;
             }
         }
-        Ok(())
+        Ok(offset)
     }
 
     /// Recursively assemble chunks from tree
-    async fn assemble_chunks(&self, node: &TreeNode) -> Result<Vec<u8>, HashTreeError> {
+    async fn assemble_chunks(&self, node: &TreeNode, depth: usize) -> Result<Vec<u8>, HashTreeError> {
+        if depth >= MAX_TREE_DEPTH {
+            return Err(HashTreeError::TooDeep(MAX_TREE_DEPTH));
+        }
         let mut parts: Vec<Vec<u8>> = Vec::new();
 
         for link in &node.links {
@@ -915,7 +835,7 @@ impl<S: Store> HashTree<S> {
 
             if is_tree_node(&child_data) {
                 let child_node = decode_tree_node(&child_data)?;
-                parts.push(Box::pin(self.assemble_chunks(&child_node)).await?);
+                parts.push(Box::pin(self.assemble_chunks(&child_node, depth + 1)).await?);
             } else {
                 parts.push(child_data);
             }
@@ -937,86 +857,7 @@ impl<S: Store> HashTree<S> {
         &self,
         hash: Hash,
     ) -> Pin<Box<dyn Stream<Item = Result<Vec<u8>, HashTreeError>> + Send + '_>> {
-        Box::pin(stream::unfold(
-            ReadStreamState::Init { hash, tree: self },
-            |state| async move {
-                match state {
-                    ReadStreamState::Init { hash, tree } => {
-                        let data = match tree.store.get(&hash).await {
-                            Ok(Some(d)) => d,
-                            Ok(None) => return None,
-                            Err(e) => return Some((Err(HashTreeError::Store(e.to_string())), ReadStreamState::Done)),
-                        };
-
-                        if !is_tree_node(&data) {
-                            // Single blob - yield it and finish
-                            return Some((Ok(data), ReadStreamState::Done));
-                        }
-
-                        // Tree node - start streaming chunks
-                        let node = match decode_tree_node(&data) {
-                            Ok(n) => n,
-                            Err(e) => return Some((Err(HashTreeError::Codec(e)), ReadStreamState::Done)),
-                        };
-
-                        // Create stack with all links to process
-                        let mut stack: Vec<StreamStackItem> = Vec::new();
-                        for link in node.links.into_iter().rev() {
-                            stack.push(StreamStackItem::Hash(link.hash));
-                        }
-
-                        // Process first item
-                        tree.process_stream_stack(&mut stack).await
-                    }
-                    ReadStreamState::Processing { mut stack, tree } => {
-                        tree.process_stream_stack(&mut stack).await
-                    }
-                    ReadStreamState::Done => None,
-                }
-            },
-        ))
-    }
-
-    async fn process_stream_stack<'a>(
-        &'a self,
-        stack: &mut Vec<StreamStackItem>,
-    ) -> Option<(Result<Vec<u8>, HashTreeError>, ReadStreamState<'a, S>)> {
-        while let Some(item) = stack.pop() {
-            match item {
-                StreamStackItem::Hash(hash) => {
-                    let data = match self.store.get(&hash).await {
-                        Ok(Some(d)) => d,
-                        Ok(None) => {
-                            return Some((
-                                Err(HashTreeError::MissingChunk(to_hex(&hash))),
-                                ReadStreamState::Done,
-                            ))
-                        }
-                        Err(e) => {
-                            return Some((
-                                Err(HashTreeError::Store(e.to_string())),
-                                ReadStreamState::Done,
-                            ))
-                        }
-                    };
-
-                    if is_tree_node(&data) {
-                        // Nested tree - push its children to stack
-                        let node = match decode_tree_node(&data) {
-                            Ok(n) => n,
-                            Err(e) => return Some((Err(HashTreeError::Codec(e)), ReadStreamState::Done)),
-                        };
-                        for link in node.links.into_iter().rev() {
-                            stack.push(StreamStackItem::Hash(link.hash));
-                        }
-                    } else {
-                        // Leaf blob - yield it
-                        return Some((Ok(data), ReadStreamState::Processing { stack: std::mem::take(stack), tree: self }));
-                    }
-                }
-            }
-        }
-        None
+        FileStream::new(self, hash, None).into_stream()
     }
 
     /// Read file chunks as Vec (non-streaming version)
@@ -1148,7 +989,7 @@ impl<S: Store> HashTree<S> {
                 };
             } else {
                 // Check internal nodes
-                match self.find_link_in_subtrees_cid(&node, part, &current_cid).await? {
+                match self.find_link_in_subtrees_cid(&node, part, 0).await? {
                     Some(link) => {
                         current_cid = Cid {
                             hash: link.hash,
@@ -1175,8 +1016,12 @@ impl<S: Store> HashTree<S> {
             .cloned()
     }
 
-    /// Find a link in subtrees using Cid (with decryption support)
-    async fn find_link_in_subtrees_cid(&self, node: &TreeNode, name: &str, _parent_cid: &Cid) -> Result<Option<Link>, HashTreeError> {
+    /// Find a link in subtrees using Cid (with decryption support), `depth`
+    /// levels below the directory's node
+    async fn find_link_in_subtrees_cid(&self, node: &TreeNode, name: &str, depth: usize) -> Result<Option<Link>, HashTreeError> {
+        if depth >= MAX_TREE_DEPTH {
+            return Err(HashTreeError::TooDeep(MAX_TREE_DEPTH));
+        }
         for link in &node.links {
             if !link.name.as_ref().map(|n| n.starts_with('_')).unwrap_or(false) {
                 continue;
@@ -1197,7 +1042,7 @@ impl<S: Store> HashTree<S> {
                 return Ok(Some(found));
             }
 
-            if let Some(deep_found) = Box::pin(self.find_link_in_subtrees_cid(&sub_node, name, &sub_cid)).await? {
+            if let Some(deep_found) = Box::pin(self.find_link_in_subtrees_cid(&sub_node, name, depth + 1)).await? {
                 return Ok(Some(deep_found));
             }
         }
@@ -1755,6 +1600,57 @@ impl<S: Store> HashTree<S> {
         self.set_entry(&new_root, target_path, name, &entry_cid, entry_size, entry_link_type).await
     }
 
+    /// Re-encrypt a directory tree under fresh directory keys
+    ///
+    /// Every directory entry gets a random `keySalt` in its metadata, which
+    /// changes each directory's content and therefore its CHK key, so a leaked
+    /// root key no longer opens the rotated tree or anything written after it.
+    /// File contents keep their convergent keys: whoever held the old key
+    /// could already read them, and re-encrypting them would only cost dedup.
+    /// Returns the new root Cid.
+    pub async fn rotate_keys(&self, root: &Cid) -> Result<Cid, HashTreeError> {
+        if !self.encrypted || root.key.is_none() {
+            return Err(HashTreeError::Encryption(
+                "key rotation requires an encrypted tree".to_string(),
+            ));
+        }
+        let salt = serde_json::Value::String(to_hex(&generate_key()));
+        self.rotate_dir_keys(root, &salt).await
+    }
+
+    async fn rotate_dir_keys(
+        &self,
+        dir: &Cid,
+        salt: &serde_json::Value,
+    ) -> Result<Cid, HashTreeError> {
+        let entries = self.list_directory(dir).await?;
+        let mut new_entries = Vec::with_capacity(entries.len());
+
+        for e in entries {
+            let (hash, key) = if e.link_type == LinkType::Dir {
+                let child = Cid { hash: e.hash, key: e.key };
+                let rotated = Box::pin(self.rotate_dir_keys(&child, salt)).await?;
+                (rotated.hash, rotated.key)
+            } else {
+                (e.hash, e.key)
+            };
+
+            let mut meta = e.meta.unwrap_or_default();
+            meta.insert(KEY_SALT_META.to_string(), salt.clone());
+
+            new_entries.push(DirEntry {
+                name: e.name,
+                hash,
+                size: e.size,
+                key,
+                link_type: e.link_type,
+                meta: Some(meta),
+            });
+        }
+
+        self.put_directory(new_entries).await
+    }
+
     async fn resolve_path_array(&self, root: &Cid, path: &[&str]) -> Result<Option<Cid>, HashTreeError> {
         if path.is_empty() {
             return Ok(Some(root.clone()));
@@ -1835,18 +1731,144 @@ impl<S: Store> HashTree<S> {
     pub fn max_links(&self) -> usize {
         self.max_links
     }
+
+    /// Largest blob entry packed when writing directories, 0 if packing is off
+    pub fn pack_threshold(&self) -> usize {
+        self.pack_threshold.load(Ordering::Relaxed)
+    }
+
+    /// Change the packing threshold for directories written from now on
+    pub fn set_pack_threshold(&self, threshold: usize) {
+        self.pack_threshold.store(threshold, Ordering::Relaxed);
+    }
 }
 
 // Internal state types for streaming
 
-enum StreamStackItem {
-    Hash(Hash),
+/// A block of a file not yet read, in read order
+struct PendingBlock {
+    hash: Hash,
+    key: Option<EncryptionKey>,
+    /// Linked as a leaf, so it can be fetched ahead
+    leaf: bool,
 }
 
-enum ReadStreamState<'a, S: Store> {
-    Init { hash: Hash, tree: &'a HashTree<S> },
-    Processing { stack: Vec<StreamStackItem>, tree: &'a HashTree<S> },
-    Done,
+type BlockFetch<'a> =
+    Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>, StoreError>> + Send + 'a>>;
+
+/// Reads a file's leaf blocks in order, keeping fetches of the next
+/// `prefetch` leaves in flight while one is read
+struct FileStream<'a, S: Store> {
+    tree: &'a HashTree<S>,
+    /// Fetches of the blocks before `queue`, in order
+    fetching: FuturesOrdered<BlockFetch<'a>>,
+    fetching_blocks: VecDeque<PendingBlock>,
+    /// Blocks not fetched yet, next first
+    queue: VecDeque<PendingBlock>,
+    /// The root is being read: if it's missing the stream is empty
+    at_root: bool,
+}
+
+impl<'a, S: Store> FileStream<'a, S> {
+    fn new(tree: &'a HashTree<S>, hash: Hash, key: Option<EncryptionKey>) -> Self {
+        let root = PendingBlock {
+            hash,
+            key,
+            leaf: false,
+        };
+        Self {
+            tree,
+            fetching: FuturesOrdered::new(),
+            fetching_blocks: VecDeque::new(),
+            queue: VecDeque::from([root]),
+            at_root: true,
+        }
+    }
+
+    fn into_stream(
+        self,
+    ) -> Pin<Box<dyn Stream<Item = Result<Vec<u8>, HashTreeError>> + Send + 'a>> {
+        Box::pin(stream::unfold(Some(self), |reader| async move {
+            let mut reader = reader?;
+            match reader.next_chunk().await? {
+                Ok(chunk) => Some((Ok(chunk), Some(reader))),
+                // Nothing is read after an error
+                Err(e) => Some((Err(e), None)),
+            }
+        }))
+    }
+
+    fn fetch(&self, hash: Hash) -> BlockFetch<'a> {
+        let store = &self.tree.store;
+        Box::pin(async move { store.get(&hash).await })
+    }
+
+    /// Start fetching the leaves at the front of the queue, up to the one
+    /// being read and `prefetch` more
+    fn fill(&mut self) {
+        while self.fetching_blocks.len() <= self.tree.prefetch {
+            match self.queue.front() {
+                Some(block) if block.leaf => {
+                    let block = self.queue.pop_front().unwrap();
+                    self.fetching.push_back(self.fetch(block.hash));
+                    self.fetching_blocks.push_back(block);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// The next leaf's data, or None at the end of the file
+    async fn next_chunk(&mut self) -> Option<Result<Vec<u8>, HashTreeError>> {
+        loop {
+            self.fill();
+            let (block, result) = match self.fetching.next().await {
+                Some(result) => (self.fetching_blocks.pop_front().unwrap(), result),
+                None => {
+                    // A tree node next: nothing is fetched ahead of it
+                    let block = self.queue.pop_front()?;
+                    let result = self.fetch(block.hash).await;
+                    (block, result)
+                }
+            };
+            let at_root = std::mem::take(&mut self.at_root);
+
+            let data = match result {
+                Ok(Some(data)) => data,
+                Ok(None) if at_root => return None,
+                Ok(None) => return Some(Err(HashTreeError::MissingChunk(to_hex(&block.hash)))),
+                Err(e) => return Some(Err(HashTreeError::Store(e.to_string()))),
+            };
+            let data = match &block.key {
+                Some(key) => match decrypt_chk(&data, key) {
+                    Ok(data) => data,
+                    Err(e) => return Some(Err(HashTreeError::Decryption(e.to_string()))),
+                },
+                None => data,
+            };
+            if !is_tree_node(&data) {
+                return Some(Ok(data));
+            }
+
+            let node = match decode_tree_node(&data) {
+                Ok(node) => node,
+                Err(e) => return Some(Err(HashTreeError::Codec(e))),
+            };
+            // A node linked as a leaf: what was fetched ahead comes after
+            // its children, so it's fetched again later
+            while let Some(ahead) = self.fetching_blocks.pop_back() {
+                self.queue.push_front(ahead);
+            }
+            self.fetching = FuturesOrdered::new();
+            for link in node.links.into_iter().rev() {
+                self.queue.push_front(PendingBlock {
+                    hash: link.hash,
+                    key: link.key,
+                    leaf: link.link_type == LinkType::Blob,
+                });
+            }
+        }
+    }
 }
 
 struct WalkStackItem {
@@ -1861,18 +1883,6 @@ enum WalkStreamState<'a, S: Store> {
     Done,
 }
 
-// Encrypted stream state types
-struct EncryptedStackItem {
-    hash: Hash,
-    key: Option<[u8; 32]>,
-}
-
-enum EncryptedStreamState<'a, S: Store> {
-    Init { hash: Hash, key: [u8; 32], tree: &'a HashTree<S> },
-    Processing { stack: Vec<EncryptedStackItem>, tree: &'a HashTree<S> },
-    Done,
-}
-
 /// Verify tree integrity - checks that all referenced hashes exist
 pub async fn verify_tree<S: Store>(
     store: Arc<S>,
@@ -2072,6 +2082,30 @@ mod tests {
         assert_eq!(cid1.to_string(), cid2.to_string());
     }
 
+    #[tokio::test]
+    async fn test_reproducible_ignores_chunking_config() {
+        let data: Vec<u8> = (0..500).map(|i| (i % 256) as u8).collect();
+        let put = |config: HashTreeConfig<MemoryStore>| {
+            let data = data.clone();
+            async move {
+                let tree = HashTree::new(config);
+                let file = tree.put(&data).await.unwrap().0;
+                tree.put_directory(vec![DirEntry::from_cid("data.bin", &file)])
+                    .await
+                    .unwrap()
+            }
+        };
+        let store = || Arc::new(MemoryStore::new());
+
+        let default = put(HashTreeConfig::new(store())).await;
+        let tuned = HashTreeConfig::new(store())
+            .with_chunk_size(100)
+            .with_max_links(4)
+            .with_packing(1024);
+        assert_ne!(put(tuned.clone()).await, default);
+        assert_eq!(put(tuned.reproducible()).await, default);
+    }
+
     #[tokio::test]
     async fn test_cid_to_string_public() {
         let store = Arc::new(MemoryStore::new());
@@ -46,15 +46,19 @@ pub mod crypto;
 pub mod diff;
 pub mod hash;
 pub mod hashtree;
+pub mod log;
 pub mod nhash;
+pub mod pack;
+pub mod proof;
 pub mod reader;
+pub mod stats;
 pub mod store;
 pub mod types;
 pub mod visibility;
 
 // Re-exports for convenience
 // Main API - unified HashTree
-pub use hashtree::{HashTree, HashTreeConfig, HashTreeError, verify_tree as hashtree_verify_tree};
+pub use hashtree::{HashTree, HashTreeConfig, HashTreeError, DEFAULT_PREFETCH, KEY_SALT_META, MAX_TREE_DEPTH, verify_tree as hashtree_verify_tree};
 
 // Constants
 pub use builder::{BEP52_CHUNK_SIZE, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_LINKS};
@@ -62,13 +66,22 @@ pub use builder::{BEP52_CHUNK_SIZE, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_LINKS};
 // Low-level codec
 pub use codec::{
     decode_tree_node, encode_and_hash, encode_tree_node, get_node_type, is_directory_node,
-    is_tree_node, try_decode_tree_node, CodecError,
+    is_tree_node, links_size, try_decode_tree_node, CodecError, MAX_NODE_SIZE,
 };
 pub use hash::{sha256, verify};
 
 // Reader types (used by HashTree)
 pub use reader::{verify_tree, ReaderError, TreeEntry, VerifyResult, WalkEntry};
 
+// Append-only logs
+pub use log::{Log, DEFAULT_SEGMENT_SIZE};
+
+// Small-file packing
+pub use pack::{PackRef, DEFAULT_PACK_THRESHOLD, MAX_PACK_SIZE, PACK_META};
+
+// Merkle proofs for single entries
+pub use proof::{verify_proof, MerkleProof, ProofBlock};
+
 // Store
 pub use store::{MemoryStore, Store, StoreError};
 pub use types::{from_hex, hash_equals, to_hex, Cid, CidParseError, DirEntry, Hash, Link, LinkType, PutResult, TreeNode};
@@ -86,3 +99,6 @@ pub use visibility::{xor_keys, TreeVisibility};
 
 // Tree diff operations
 pub use diff::{collect_hashes, collect_hashes_with_progress, tree_diff, tree_diff_streaming, tree_diff_with_old_hashes, DiffStats, TreeDiff};
+
+// Tree statistics
+pub use stats::{tree_stats, DirStats, FileSize, TreeStats};
@@ -0,0 +1,367 @@
+//! Append-only logs
+//!
+//! A log is a directory of segments: blobs of entries, each entry prefixed
+//! with its length as a big-endian u32. Segments are named by the index of
+//! their first entry, zero-padded so the names sort in order, and each
+//! segment's link records its entry count in `meta.entries`. The directory
+//! node is the log's index: reading a range only fetches the index and the
+//! segments holding the range.
+//!
+//! Appending rewrites the last segment and the index, nothing else. Once the
+//! last segment holds `segment_size` bytes, new entries start another. The
+//! index has a link per segment and is rewritten whole, so appends slowly
+//! get dearer as the log grows; long logs want a larger `segment_size`.
+//! Logs are ordinary directories, so they sync, pin and encrypt like any
+//! other tree.
+
+use std::collections::HashMap;
+
+use crate::codec::CodecError;
+use crate::hashtree::{HashTree, HashTreeError};
+use crate::store::Store;
+use crate::types::{to_hex, Cid, DirEntry, LinkType};
+
+/// Bytes a segment fills up to before entries go to a new one
+pub const DEFAULT_SEGMENT_SIZE: usize = 64 * 1024;
+
+/// Link metadata holding a segment's entry count
+const ENTRIES_META: &str = "entries";
+
+/// Length prefix of each entry
+const PREFIX_LEN: usize = 4;
+
+/// A segment as listed in the index
+struct Segment {
+    /// Index of its first entry
+    first: u64,
+    entries: u64,
+    cid: Cid,
+    size: u64,
+}
+
+impl Segment {
+    /// Index of the entry after its last
+    fn end(&self) -> Result<u64, HashTreeError> {
+        checked_add(self.first, self.entries)
+    }
+}
+
+/// Entry counts come from the index, which can't be trusted not to overflow
+fn checked_add(a: u64, b: u64) -> Result<u64, HashTreeError> {
+    a.checked_add(b)
+        .ok_or_else(|| CodecError::Overflow("log entry counts").into())
+}
+
+/// Append-only log operations on a tree
+pub struct Log<'a, S: Store> {
+    tree: &'a HashTree<S>,
+    segment_size: usize,
+}
+
+impl<'a, S: Store> Log<'a, S> {
+    pub fn new(tree: &'a HashTree<S>) -> Self {
+        Self {
+            tree,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+        }
+    }
+
+    pub fn with_segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size.max(1);
+        self
+    }
+
+    /// Append `entries` to the log at `log`, or to a new one if None.
+    /// Returns the log's new Cid.
+    pub async fn append<E: AsRef<[u8]>>(
+        &self,
+        log: Option<&Cid>,
+        entries: &[E],
+    ) -> Result<Cid, HashTreeError> {
+        let mut segments = match log {
+            Some(log) => self.segments(log).await?,
+            None => Vec::new(),
+        };
+        let mut next = match segments.last() {
+            Some(last) => last.end()?,
+            None => 0,
+        };
+
+        // The last segment takes more entries while it has room
+        let mut open: Option<(u64, Vec<u8>, u64)> = None;
+        let reopen = segments
+            .last()
+            .is_some_and(|last| (last.size as usize) < self.segment_size);
+        if reopen && !entries.is_empty() {
+            let last = segments.pop().unwrap();
+            let data = self.read_segment(&last).await?;
+            open = Some((last.first, data, last.entries));
+        }
+
+        for entry in entries {
+            let entry = entry.as_ref();
+            let len = u32::try_from(entry.len())
+                .map_err(|_| HashTreeError::InvalidLog("entry larger than 4 GiB".into()))?;
+            let full = open.as_ref().is_some_and(|(_, data, _)| {
+                !data.is_empty() && data.len() + PREFIX_LEN + entry.len() > self.segment_size
+            });
+            if full {
+                let (first, data, count) = open.take().unwrap();
+                segments.push(self.put_segment(first, &data, count).await?);
+            }
+            let (_, data, count) = open.get_or_insert_with(|| (next, Vec::new(), 0));
+            data.extend_from_slice(&len.to_be_bytes());
+            data.extend_from_slice(entry);
+            *count += 1;
+            next = checked_add(next, 1)?;
+        }
+        if let Some((first, data, count)) = open {
+            segments.push(self.put_segment(first, &data, count).await?);
+        }
+
+        let index = segments
+            .iter()
+            .map(|segment| self.index_entry(segment))
+            .collect();
+        self.tree.put_directory(index).await
+    }
+
+    /// Number of entries in the log at `log`
+    pub async fn len(&self, log: &Cid) -> Result<u64, HashTreeError> {
+        match self.segments(log).await?.last() {
+            Some(last) => last.end(),
+            None => Ok(0),
+        }
+    }
+
+    /// Entries `start..end` of the log at `log`, fewer if the log ends first
+    pub async fn read_range(
+        &self,
+        log: &Cid,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<Vec<u8>>, HashTreeError> {
+        let mut entries = Vec::new();
+        for segment in self.segments(log).await? {
+            if segment.first >= end {
+                break;
+            }
+            if segment.end()? <= start {
+                continue;
+            }
+            let data = self.read_segment(&segment).await?;
+            let decoded = decode_entries(&data)?;
+            if decoded.len() as u64 != segment.entries {
+                return Err(HashTreeError::InvalidLog(format!(
+                    "segment {} holds {} entries, its index says {}",
+                    segment.first,
+                    decoded.len(),
+                    segment.entries
+                )));
+            }
+            let skip = start.saturating_sub(segment.first) as usize;
+            let take = (end - segment.first) as usize;
+            entries.extend(decoded.into_iter().take(take).skip(skip));
+        }
+        Ok(entries)
+    }
+
+    /// Segments of the log at `log`, in order
+    async fn segments(&self, log: &Cid) -> Result<Vec<Segment>, HashTreeError> {
+        let mut first = 0;
+        let mut segments = Vec::new();
+        for entry in self.tree.list_directory(log).await? {
+            let entries = entry
+                .meta
+                .as_ref()
+                .and_then(|meta| meta.get(ENTRIES_META))
+                .and_then(|count| count.as_u64())
+                .ok_or_else(|| {
+                    HashTreeError::InvalidLog(format!("segment {} has no entry count", entry.name))
+                })?;
+            segments.push(Segment {
+                first,
+                entries,
+                cid: Cid {
+                    hash: entry.hash,
+                    key: entry.key,
+                },
+                size: entry.size,
+            });
+            first = checked_add(first, entries)?;
+        }
+        Ok(segments)
+    }
+
+    async fn read_segment(&self, segment: &Segment) -> Result<Vec<u8>, HashTreeError> {
+        self.tree
+            .get(&segment.cid)
+            .await?
+            .ok_or_else(|| HashTreeError::MissingChunk(to_hex(&segment.cid.hash)))
+    }
+
+    async fn put_segment(
+        &self,
+        first: u64,
+        data: &[u8],
+        entries: u64,
+    ) -> Result<Segment, HashTreeError> {
+        let (cid, size) = self.tree.put(data).await?;
+        Ok(Segment {
+            first,
+            entries,
+            cid,
+            size,
+        })
+    }
+
+    fn index_entry(&self, segment: &Segment) -> DirEntry {
+        // Segments over the chunk size are stored chunked
+        let link_type = if segment.size as usize > self.tree.chunk_size() {
+            LinkType::File
+        } else {
+            LinkType::Blob
+        };
+        let meta = HashMap::from([(ENTRIES_META.to_string(), segment.entries.into())]);
+        DirEntry::from_cid(format!("{:016}", segment.first), &segment.cid)
+            .with_size(segment.size)
+            .with_link_type(link_type)
+            .with_meta(meta)
+    }
+}
+
+/// Split a segment into its entries
+fn decode_entries(mut data: &[u8]) -> Result<Vec<Vec<u8>>, HashTreeError> {
+    let mut entries = Vec::new();
+    while !data.is_empty() {
+        let truncated = || HashTreeError::InvalidLog("truncated segment".into());
+        let (prefix, rest) = data
+            .split_first_chunk::<PREFIX_LEN>()
+            .ok_or_else(truncated)?;
+        let len = u32::from_be_bytes(*prefix) as usize;
+        if rest.len() < len {
+            return Err(truncated());
+        }
+        entries.push(rest[..len].to_vec());
+        data = &rest[len..];
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashtree::HashTreeConfig;
+    use crate::store::MemoryStore;
+    use std::sync::Arc;
+
+    fn entries(range: std::ops::Range<u64>) -> Vec<Vec<u8>> {
+        range.map(|i| format!("entry {}", i).into_bytes()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_append_and_read_range() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let log = Log::new(&tree).with_segment_size(40);
+
+        let mut cid = log.append::<Vec<u8>>(None, &[]).await.unwrap();
+        assert_eq!(log.len(&cid).await.unwrap(), 0);
+        for batch in [0..1, 1..5, 5..12] {
+            cid = log.append(Some(&cid), &entries(batch)).await.unwrap();
+        }
+        assert_eq!(log.len(&cid).await.unwrap(), 12);
+        assert_eq!(log.read_range(&cid, 0, 100).await.unwrap(), entries(0..12));
+        assert_eq!(log.read_range(&cid, 3, 9).await.unwrap(), entries(3..9));
+        assert_eq!(log.read_range(&cid, 11, 12).await.unwrap(), entries(11..12));
+        assert!(log.read_range(&cid, 12, 20).await.unwrap().is_empty());
+
+        // Entries of 11-12 bytes fill a 40 byte segment with 2 or 3
+        let index = tree.list_directory(&cid).await.unwrap();
+        assert!(index.len() >= 4);
+        assert_eq!(index[0].name, "0000000000000000");
+        assert!(index.iter().all(|e| e.size <= 40));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_append() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store));
+        let log = Log::new(&tree).with_segment_size(40);
+
+        let cid = log.append(None, &entries(0..5)).await.unwrap();
+        let cid = log.append(Some(&cid), &entries(5..12)).await.unwrap();
+        assert!(cid.key.is_some());
+        assert_eq!(log.len(&cid).await.unwrap(), 12);
+        assert_eq!(log.read_range(&cid, 3, 9).await.unwrap(), entries(3..9));
+    }
+
+    #[tokio::test]
+    async fn test_append_rewrites_only_the_last_segment() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let log = Log::new(&tree).with_segment_size(40);
+
+        let before = log.append(None, &entries(0..10)).await.unwrap();
+        let after = log.append(Some(&before), &entries(10..11)).await.unwrap();
+        let before = tree.list_directory(&before).await.unwrap();
+        let after = tree.list_directory(&after).await.unwrap();
+        let kept = before.len() - 1;
+        let hashes = |index: &[crate::reader::TreeEntry]| -> Vec<_> {
+            index.iter().take(kept).map(|e| e.hash).collect()
+        };
+        assert_eq!(hashes(&before), hashes(&after));
+        assert_ne!(before[kept].hash, after[kept].hash);
+    }
+
+    #[tokio::test]
+    async fn test_large_and_empty_entries() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).public().with_chunk_size(64));
+        let log = Log::new(&tree).with_segment_size(16);
+
+        let large = vec![7u8; 200];
+        let cid = log
+            .append(None, &[b"".to_vec(), large.clone(), b"x".to_vec()])
+            .await
+            .unwrap();
+        assert_eq!(
+            log.read_range(&cid, 0, 3).await.unwrap(),
+            vec![Vec::new(), large, b"x".to_vec()]
+        );
+        let index = tree.list_directory(&cid).await.unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index[1].link_type, LinkType::File);
+    }
+
+    #[tokio::test]
+    async fn test_overflowing_entry_counts() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let (segment, _) = tree.put(b"").await.unwrap();
+        let index = ["a", "b"]
+            .iter()
+            .map(|name| {
+                let meta = HashMap::from([(ENTRIES_META.to_string(), u64::MAX.into())]);
+                DirEntry::from_cid(*name, &segment).with_meta(meta)
+            })
+            .collect();
+        let cid = tree.put_directory(index).await.unwrap();
+
+        let log = Log::new(&tree);
+        let overflow = |result| matches!(result, Err(HashTreeError::Codec(_)));
+        assert!(overflow(log.len(&cid).await));
+        assert!(overflow(log.read_range(&cid, 0, 1).await.map(|_| 0)));
+        assert!(overflow(log.append(Some(&cid), &[b"x"]).await.map(|_| 0)));
+    }
+
+    #[test]
+    fn test_decode_truncated_segment() {
+        assert!(decode_entries(&[0, 0, 0, 5, 1, 2]).is_err());
+        assert!(decode_entries(&[0, 0]).is_err());
+        assert_eq!(
+            decode_entries(&[0, 0, 0, 1, 9, 0, 0, 0, 0]).unwrap(),
+            vec![vec![9], vec![]]
+        );
+    }
+}
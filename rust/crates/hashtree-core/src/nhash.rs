@@ -4,7 +4,8 @@
 //! provides human-readable, copy-pasteable identifiers.
 //!
 //! Types:
-//! - nhash: Permalink (hash + optional path + optional decrypt key)
+//! - nhash: Permalink (hash + optional path + optional decrypt key, plus
+//!   optional size, MIME type and filename hints for serving the target)
 //! - nref: Live reference (pubkey + tree + optional path + optional decrypt key)
 
 use crate::types::Hash;
@@ -22,6 +23,12 @@ mod tlv {
     pub const PATH: u8 = 4;
     /// 32-byte decryption key (optional)
     pub const DECRYPT_KEY: u8 = 5;
+    /// Size of the target in bytes, big-endian in 1-8 bytes (optional hint)
+    pub const SIZE: u8 = 6;
+    /// UTF-8 MIME type of the target (optional hint)
+    pub const MIME_TYPE: u8 = 7;
+    /// UTF-8 suggested filename of the target (optional hint)
+    pub const FILENAME: u8 = 8;
 }
 
 /// Errors for nhash/npath encoding/decoding
@@ -48,7 +55,11 @@ pub enum NHashError {
 }
 
 /// NHash data - permalink to content by hash
-#[derive(Debug, Clone, PartialEq)]
+///
+/// The hints describe the target (after `path`) so a server can send its
+/// headers before fetching anything. They're only as trustworthy as the
+/// link; decoders that predate them ignore them.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct NHashData {
     /// 32-byte merkle hash
     pub hash: Hash,
@@ -56,6 +67,12 @@ pub struct NHashData {
     pub path: Vec<String>,
     /// 32-byte decryption key (optional)
     pub decrypt_key: Option<[u8; 32]>,
+    /// Size of the target in bytes (optional hint)
+    pub size: Option<u64>,
+    /// MIME type of the target (optional hint, e.g. "video/mp4")
+    pub mime_type: Option<String>,
+    /// Suggested filename for saving the target (optional hint)
+    pub filename: Option<String>,
 }
 
 /// NRef data - live reference via pubkey + tree + path
@@ -161,14 +178,15 @@ pub fn nhash_encode(hash: &Hash) -> Result<String, NHashError> {
     encode_bech32("nhash", hash)
 }
 
-/// Encode an nhash permalink with optional path and decrypt key
+/// Encode an nhash permalink with optional path, decrypt key and hints
 pub fn nhash_encode_full(data: &NHashData) -> Result<String, NHashError> {
-    // No path or decrypt key - simple encoding (just the hash bytes)
-    if data.path.is_empty() && data.decrypt_key.is_none() {
+    let has_hints = data.size.is_some() || data.mime_type.is_some() || data.filename.is_some();
+    // No path, decrypt key or hints - simple encoding (just the hash bytes)
+    if data.path.is_empty() && data.decrypt_key.is_none() && !has_hints {
         return encode_bech32("nhash", &data.hash);
     }
 
-    // Has path or decrypt key - use TLV
+    // Has path, decrypt key or hints - use TLV
     let mut tlv: std::collections::HashMap<u8, Vec<Vec<u8>>> = std::collections::HashMap::new();
     tlv.insert(tlv::HASH, vec![data.hash.to_vec()]);
 
@@ -183,6 +201,21 @@ pub fn nhash_encode_full(data: &NHashData) -> Result<String, NHashError> {
         tlv.insert(tlv::DECRYPT_KEY, vec![key.to_vec()]);
     }
 
+    if let Some(size) = data.size {
+        // Shortest big-endian form, at least one byte
+        let bytes = size.to_be_bytes();
+        let skip = (size.leading_zeros() / 8).min(7) as usize;
+        tlv.insert(tlv::SIZE, vec![bytes[skip..].to_vec()]);
+    }
+
+    if let Some(mime_type) = &data.mime_type {
+        tlv.insert(tlv::MIME_TYPE, vec![mime_type.as_bytes().to_vec()]);
+    }
+
+    if let Some(filename) = &data.filename {
+        tlv.insert(tlv::FILENAME, vec![filename.as_bytes().to_vec()]);
+    }
+
     encode_bech32("nhash", &encode_tlv(&tlv)?)
 }
 
@@ -206,8 +239,7 @@ pub fn nhash_decode(code: &str) -> Result<NHashData, NHashError> {
         hash.copy_from_slice(&data);
         return Ok(NHashData {
             hash,
-            path: Vec::new(),
-            decrypt_key: None,
+            ..Default::default()
         });
     }
 
@@ -251,7 +283,31 @@ pub fn nhash_decode(code: &str) -> Result<NHashData, NHashError> {
         None
     };
 
-    Ok(NHashData { hash, path, decrypt_key })
+    let size = match tlv.get(&tlv::SIZE).and_then(|v| v.first()) {
+        Some(bytes) if bytes.is_empty() || bytes.len() > 8 => {
+            return Err(NHashError::TlvError(format!(
+                "invalid size length: {} bytes",
+                bytes.len()
+            )));
+        }
+        Some(bytes) => Some(bytes.iter().fold(0u64, |size, b| size << 8 | *b as u64)),
+        None => None,
+    };
+    let text = |t: u8| -> Result<Option<String>, NHashError> {
+        match tlv.get(&t).and_then(|v| v.first()) {
+            Some(bytes) => Ok(Some(String::from_utf8(bytes.clone())?)),
+            None => Ok(None),
+        }
+    };
+
+    Ok(NHashData {
+        hash,
+        path,
+        decrypt_key,
+        size,
+        mime_type: text(tlv::MIME_TYPE)?,
+        filename: text(tlv::FILENAME)?,
+    })
 }
 
 // ============================================================================
@@ -415,6 +471,7 @@ mod tests {
             hash,
             path: vec!["folder".into(), "file.txt".into()],
             decrypt_key: None,
+            ..Default::default()
         };
 
         let encoded = nhash_encode_full(&data).unwrap();
@@ -435,6 +492,7 @@ mod tests {
             hash,
             path: vec![],
             decrypt_key: Some(key),
+            ..Default::default()
         };
 
         let encoded = nhash_encode_full(&data).unwrap();
@@ -455,6 +513,7 @@ mod tests {
             hash,
             path: vec!["docs".into()],
             decrypt_key: Some(key),
+            ..Default::default()
         };
 
         let encoded = nhash_encode_full(&data).unwrap();
@@ -464,6 +523,41 @@ mod tests {
         assert_eq!(decoded.decrypt_key, Some(key));
     }
 
+    #[test]
+    fn test_nhash_with_hints() {
+        let data = NHashData {
+            hash: [0xaa; 32],
+            decrypt_key: Some([0xbb; 32]),
+            size: Some(1_000_000),
+            mime_type: Some("video/mp4".into()),
+            filename: Some("clip.mp4".into()),
+            ..Default::default()
+        };
+        let encoded = nhash_encode_full(&data).unwrap();
+        assert_eq!(nhash_decode(&encoded).unwrap(), data);
+
+        // Sizes of any width round-trip
+        for size in [0, 255, 256, u64::MAX] {
+            let data = NHashData {
+                hash: [0xaa; 32],
+                size: Some(size),
+                ..Default::default()
+            };
+            let decoded = nhash_decode(&nhash_encode_full(&data).unwrap()).unwrap();
+            assert_eq!(decoded.size, Some(size));
+        }
+
+        // Without hints, the encoding is unchanged
+        let plain = NHashData {
+            hash: [0xaa; 32],
+            ..Default::default()
+        };
+        assert_eq!(
+            nhash_encode_full(&plain).unwrap(),
+            nhash_encode(&[0xaa; 32]).unwrap()
+        );
+    }
+
     #[test]
     fn test_nref_simple() {
         let pubkey: [u8; 32] = [0xcc; 32];
@@ -0,0 +1,371 @@
+//! Small-file packing
+//!
+//! A directory of thousands of tiny files is thousands of blocks, and each
+//! block is a separate request to fetch from a blob server. With packing on
+//! ([`HashTreeConfig::with_packing`]), [`HashTree::put_directory`] also
+//! concatenates the stored blocks of its small blob entries into pack blocks
+//! of up to [`MAX_PACK_SIZE`] bytes. Each packed entry keeps its own hash and
+//! key and records where its block sits in `meta.pack`:
+//!
+//! ```json
+//! {"pack": {"hash": "<pack hash>", "offset": 1024, "length": 312}}
+//! ```
+//!
+//! The links of the directory node are thus the pack index. Packs hold the
+//! blocks as stored, so entries of encrypted trees stay encrypted inside them.
+//!
+//! Packed blocks are still stored and pushed individually, so readers that
+//! don't know about packs read packed trees as any other. Packs only save
+//! requests for readers that ask for them: [`unpack_links`] fetches the
+//! packs of a directory's entries and stores the blocks they hold.
+//!
+//! [`HashTreeConfig::with_packing`]: crate::HashTreeConfig::with_packing
+//! [`HashTree::put_directory`]: crate::HashTree::put_directory
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Value};
+
+use crate::hash::sha256;
+use crate::hashtree::HashTreeError;
+use crate::store::Store;
+use crate::types::{from_hex, to_hex, DirEntry, Hash, Link, LinkType};
+
+/// Link metadata key locating a packed entry's block
+pub const PACK_META: &str = "pack";
+
+/// Largest blob packed when packing is enabled without a threshold
+pub const DEFAULT_PACK_THRESHOLD: usize = 16 * 1024;
+
+/// Bytes of blocks a pack is filled with before another is started
+pub const MAX_PACK_SIZE: usize = 1024 * 1024;
+
+/// Where a packed entry's block sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackRef {
+    /// Hash of the pack block
+    pub hash: Hash,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl PackRef {
+    /// The pack reference in link metadata, if any
+    pub fn from_meta(meta: Option<&HashMap<String, Value>>) -> Option<Self> {
+        let pack = meta?.get(PACK_META)?;
+        Some(Self {
+            hash: from_hex(pack.get("hash")?.as_str()?).ok()?,
+            offset: pack.get("offset")?.as_u64()?,
+            length: pack.get("length")?.as_u64()?,
+        })
+    }
+
+    pub fn to_meta(&self) -> Value {
+        json!({
+            "hash": to_hex(&self.hash),
+            "offset": self.offset,
+            "length": self.length,
+        })
+    }
+}
+
+/// Pack the blob entries of at most `threshold` bytes not packed yet.
+/// Nothing is packed unless at least two entries qualify.
+///
+/// Entries already packed stay in their packs, but for those of the
+/// directory's emptiest pack, which is refilled along with the new entries;
+/// otherwise a directory written a file at a time would get a pack per pair.
+pub(crate) async fn pack_entries<S: Store>(
+    store: &S,
+    entries: &mut [DirEntry],
+    threshold: usize,
+) -> Result<(), HashTreeError> {
+    let mut fill: BTreeMap<Hash, u64> = BTreeMap::new();
+    for entry in entries.iter() {
+        if let Some(pack_ref) = PackRef::from_meta(entry.meta.as_ref()) {
+            *fill.entry(pack_ref.hash).or_default() += pack_ref.length;
+        }
+    }
+    let refill = fill
+        .into_iter()
+        .filter(|(_, bytes)| *bytes < MAX_PACK_SIZE as u64)
+        .min_by_key(|(_, bytes)| *bytes)
+        .map(|(hash, _)| hash);
+
+    let mut unpacked = 0;
+    let candidates: Vec<usize> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.link_type == LinkType::Blob && e.size as usize <= threshold)
+        .filter(|(_, e)| match PackRef::from_meta(e.meta.as_ref()) {
+            None => {
+                unpacked += 1;
+                true
+            }
+            Some(pack_ref) => Some(pack_ref.hash) == refill,
+        })
+        .map(|(i, _)| i)
+        .collect();
+    if unpacked == 0 || candidates.len() < 2 {
+        return Ok(());
+    }
+
+    let mut pack = Vec::new();
+    let mut members: Vec<(usize, u64, u64)> = Vec::new();
+    for i in candidates {
+        // Entries whose blocks aren't at hand stay unpacked
+        let Some(block) = store
+            .get(&entries[i].hash)
+            .await
+            .map_err(|e| HashTreeError::Store(e.to_string()))?
+        else {
+            continue;
+        };
+        if !pack.is_empty() && pack.len() + block.len() > MAX_PACK_SIZE {
+            flush_pack(store, entries, &mut pack, &mut members).await?;
+        }
+        members.push((i, pack.len() as u64, block.len() as u64));
+        pack.extend_from_slice(&block);
+    }
+    flush_pack(store, entries, &mut pack, &mut members).await
+}
+
+async fn flush_pack<S: Store>(
+    store: &S,
+    entries: &mut [DirEntry],
+    pack: &mut Vec<u8>,
+    members: &mut Vec<(usize, u64, u64)>,
+) -> Result<(), HashTreeError> {
+    if members.is_empty() {
+        return Ok(());
+    }
+    let hash = sha256(pack);
+    store
+        .put(hash, std::mem::take(pack))
+        .await
+        .map_err(|e| HashTreeError::Store(e.to_string()))?;
+    for (i, offset, length) in members.drain(..) {
+        let pack_ref = PackRef {
+            hash,
+            offset,
+            length,
+        };
+        entries[i]
+            .meta
+            .get_or_insert_with(HashMap::new)
+            .insert(PACK_META.to_string(), pack_ref.to_meta());
+    }
+    Ok(())
+}
+
+/// Fetch the packs of packed links whose blocks are missing from `store`,
+/// a request per pack rather than per block, and store the blocks they hold.
+/// Blocks not matching their hash are left out, and so are the blocks of
+/// packs that can't be fetched; they are read individually as without packs.
+pub async fn unpack_links<S: Store>(store: &S, links: &[Link]) -> Result<(), HashTreeError> {
+    let packed: Vec<(&Link, PackRef)> = links
+        .iter()
+        .filter_map(|link| Some((link, PackRef::from_meta(link.meta.as_ref())?)))
+        .collect();
+    if packed.is_empty() {
+        return Ok(());
+    }
+    let hashes: Vec<Hash> = packed.iter().map(|(link, _)| link.hash).collect();
+    let present = store
+        .has_many(&hashes)
+        .await
+        .map_err(|e| HashTreeError::Store(e.to_string()))?;
+
+    let mut missing: HashMap<Hash, Vec<(Hash, PackRef)>> = HashMap::new();
+    for ((link, pack_ref), present) in packed.into_iter().zip(present) {
+        if !present {
+            missing
+                .entry(pack_ref.hash)
+                .or_default()
+                .push((link.hash, pack_ref));
+        }
+    }
+
+    for (pack_hash, members) in missing {
+        let Some(pack) = store
+            .get(&pack_hash)
+            .await
+            .map_err(|e| HashTreeError::Store(e.to_string()))?
+        else {
+            continue;
+        };
+        let blocks: Vec<(Hash, Vec<u8>)> = members
+            .into_iter()
+            .filter_map(|(hash, pack_ref)| {
+                let start = usize::try_from(pack_ref.offset).ok()?;
+                let end = start.checked_add(usize::try_from(pack_ref.length).ok()?)?;
+                let block = pack.get(start..end)?;
+                (sha256(block) == hash).then(|| (hash, block.to_vec()))
+            })
+            .collect();
+        store
+            .put_many(blocks)
+            .await
+            .map_err(|e| HashTreeError::Store(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashtree::{HashTree, HashTreeConfig};
+    use crate::store::MemoryStore;
+    use crate::types::Cid;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    async fn put_files<S: Store>(tree: &HashTree<S>, count: usize) -> Vec<DirEntry> {
+        let mut entries = Vec::new();
+        for i in 0..count {
+            let data = format!("file number {}", i).into_bytes();
+            let (cid, size) = tree.put(&data).await.unwrap();
+            entries.push(DirEntry::from_cid(format!("f{}.txt", i), &cid).with_size(size));
+        }
+        entries
+    }
+
+    #[tokio::test]
+    async fn test_packed_files_read_from_packs() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store.clone()).public().with_packing(64));
+
+        let mut entries = put_files(&tree, 20).await;
+        let (big, size) = tree.put(&[1u8; 100]).await.unwrap();
+        entries.push(DirEntry::from_cid("big.bin", &big).with_size(size));
+        let dir = tree.put_directory(entries).await.unwrap();
+
+        let listed = tree.list_directory(&dir).await.unwrap();
+        let refs: Vec<_> = listed
+            .iter()
+            .map(|e| PackRef::from_meta(e.meta.as_ref()))
+            .collect();
+        assert!(refs[0].is_none(), "big.bin isn't packed");
+        assert!(refs[1..].iter().all(Option::is_some));
+
+        // A reader holding only the directory node and the pack
+        let reader_store = Arc::new(MemoryStore::new());
+        let pack_hash = refs[1].unwrap().hash;
+        for hash in [dir.hash, pack_hash] {
+            let data = store.get(&hash).await.unwrap().unwrap();
+            reader_store.put(hash, data).await.unwrap();
+        }
+        let reader = HashTree::new(HashTreeConfig::new(reader_store.clone()));
+        let file = reader.resolve_path(&dir, "f7.txt").await.unwrap().unwrap();
+        // Reads don't fetch packs, copying the directory's blocks does
+        assert!(!reader_store.has(&file.hash).await.unwrap());
+        let node = reader.get_directory_node(&dir).await.unwrap().unwrap();
+        unpack_links(reader_store.as_ref(), &node.links)
+            .await
+            .unwrap();
+        assert_eq!(
+            reader.get(&file).await.unwrap().unwrap(),
+            b"file number 7".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_files_are_packed() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).with_packing(64));
+        let entries = put_files(&tree, 5).await;
+        let dir = tree.put_directory(entries).await.unwrap();
+        assert!(dir.key.is_some());
+
+        let listed = tree.list_directory(&dir).await.unwrap();
+        assert!(listed
+            .iter()
+            .all(|e| PackRef::from_meta(e.meta.as_ref()).is_some()));
+        let file = tree.resolve_path(&dir, "f3.txt").await.unwrap().unwrap();
+        assert_eq!(
+            tree.get(&file).await.unwrap().unwrap(),
+            b"file number 3".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_packing_off_and_edits() {
+        let store = Arc::new(MemoryStore::new());
+        let plain = HashTree::new(HashTreeConfig::new(store.clone()).public());
+        let entries = put_files(&plain, 3).await;
+        let dir = plain.put_directory(entries.clone()).await.unwrap();
+        let listed = plain.list_directory(&dir).await.unwrap();
+        assert!(listed.iter().all(|e| e.meta.is_none()));
+
+        // A new file is packed with those of the directory's emptiest pack
+        let packing = HashTree::new(HashTreeConfig::new(store).public().with_packing(64));
+        let packed = packing.put_directory(entries).await.unwrap();
+        let (cid, size) = packing.put(b"new").await.unwrap();
+        let edited = packing
+            .set_entry(&packed, &[], "new.txt", &cid, size, LinkType::Blob)
+            .await
+            .unwrap();
+        let before = packing.list_directory(&packed).await.unwrap();
+        let after = packing.list_directory(&edited).await.unwrap();
+        let pack_of = |entries: &[crate::TreeEntry], name: &str| {
+            let entry = entries.iter().find(|e| e.name == name).unwrap();
+            PackRef::from_meta(entry.meta.as_ref()).map(|pack| pack.hash)
+        };
+        assert!(pack_of(&before, "f1.txt").is_some());
+        assert_eq!(pack_of(&after, "f1.txt"), pack_of(&after, "new.txt"));
+        assert!(pack_of(&after, "new.txt").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_files_added_one_at_a_time_share_a_pack() {
+        let store = Arc::new(MemoryStore::new());
+        let tree = HashTree::new(HashTreeConfig::new(store).public().with_packing(64));
+        let mut dir = tree.put_directory(Vec::new()).await.unwrap();
+        for (i, entry) in put_files(&tree, 5).await.into_iter().enumerate() {
+            let cid = Cid {
+                hash: entry.hash,
+                key: entry.key,
+            };
+            let name = format!("f{}.txt", i);
+            dir = tree
+                .set_entry(&dir, &[], &name, &cid, entry.size, LinkType::Blob)
+                .await
+                .unwrap();
+        }
+        let packs: HashSet<_> = tree
+            .list_directory(&dir)
+            .await
+            .unwrap()
+            .iter()
+            .map(|e| PackRef::from_meta(e.meta.as_ref()).map(|pack| pack.hash))
+            .collect();
+        assert_eq!(packs.len(), 1);
+        assert!(packs.iter().all(Option::is_some));
+    }
+
+    #[tokio::test]
+    async fn test_unpack_skips_corrupt_blocks() {
+        let store = Arc::new(MemoryStore::new());
+        let hash = sha256(b"member");
+        let pack = b"xxmemberyy".to_vec();
+        let pack_hash = sha256(&pack);
+        store.put(pack_hash, pack).await.unwrap();
+        let link = |offset| {
+            let pack_ref = PackRef {
+                hash: pack_hash,
+                offset,
+                length: 6,
+            };
+            Link::new(hash).with_meta(HashMap::from([(PACK_META.to_string(), pack_ref.to_meta())]))
+        };
+
+        unpack_links(store.as_ref(), &[link(1), link(100)])
+            .await
+            .unwrap();
+        assert!(!store.has(&hash).await.unwrap());
+        unpack_links(store.as_ref(), &[link(2)]).await.unwrap();
+        let tree = HashTree::new(HashTreeConfig::new(store).public());
+        let cid = Cid { hash, key: None };
+        assert_eq!(tree.get(&cid).await.unwrap().unwrap(), b"member".to_vec());
+    }
+}
@@ -0,0 +1,242 @@
+//! Merkle proofs for single entries
+//!
+//! A proof holds every block read while resolving a path from a tree root:
+//! the directory nodes (and, for chunked directories, their chunks) along the
+//! way. A thin client that trusts only the root Cid checks each block against
+//! its hash and replays the path walk over the proof's blocks alone, which
+//! yields the entry's Cid without downloading the rest of the tree. The
+//! entry's own content is then verified against that Cid as usual.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::hash::sha256;
+use crate::hashtree::{HashTree, HashTreeConfig, HashTreeError};
+use crate::store::{MemoryStore, Store, StoreError};
+use crate::types::{to_hex, Cid, Hash};
+
+/// A stored block included in a proof
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProofBlock {
+    pub hash: Hash,
+    /// Block bytes exactly as stored (encrypted for encrypted trees)
+    pub data: Vec<u8>,
+}
+
+/// Proof that `path` resolves to `cid` within `root`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub root: Cid,
+    pub path: String,
+    pub cid: Cid,
+    /// Blocks from the root down to the entry's parent, in the order read
+    pub blocks: Vec<ProofBlock>,
+}
+
+impl MerkleProof {
+    /// Hashes of the proof's blocks from the root down
+    pub fn hashes(&self) -> Vec<Hash> {
+        self.blocks.iter().map(|b| b.hash).collect()
+    }
+
+    /// JSON form: hashes and block data as hex, keys omitted
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "root": to_hex(&self.root.hash),
+            "path": self.path,
+            "hash": to_hex(&self.cid.hash),
+            "blocks": self
+                .blocks
+                .iter()
+                .map(|b| serde_json::json!({
+                    "hash": to_hex(&b.hash),
+                    "data": hex::encode(&b.data),
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Store wrapper that remembers every block read through it
+struct RecordingStore<S: Store> {
+    inner: Arc<S>,
+    seen: Mutex<Vec<ProofBlock>>,
+}
+
+#[async_trait]
+impl<S: Store> Store for RecordingStore<S> {
+    async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.inner.put(hash, data).await
+    }
+
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        let data = self.inner.get(hash).await?;
+        if let Some(ref data) = data {
+            let mut seen = self.seen.lock().unwrap();
+            if !seen.iter().any(|b| b.hash == *hash) {
+                seen.push(ProofBlock {
+                    hash: *hash,
+                    data: data.clone(),
+                });
+            }
+        }
+        Ok(data)
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.inner.has(hash).await
+    }
+
+    async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.inner.delete(hash).await
+    }
+}
+
+impl<S: Store> HashTree<S> {
+    /// Build a proof that `path` resolves within `root`
+    pub async fn prove(&self, root: &Cid, path: &str) -> Result<MerkleProof, HashTreeError> {
+        let recorder = Arc::new(RecordingStore {
+            inner: self.get_store(),
+            seen: Mutex::new(Vec::new()),
+        });
+        let tree = HashTree::new(HashTreeConfig::new(recorder.clone()));
+
+        let cid = tree
+            .resolve_path(root, path)
+            .await?
+            .ok_or_else(|| HashTreeError::PathNotFound(path.to_string()))?;
+        let blocks = std::mem::take(&mut *recorder.seen.lock().unwrap());
+
+        Ok(MerkleProof {
+            root: root.clone(),
+            path: path.to_string(),
+            cid,
+            blocks,
+        })
+    }
+}
+
+/// Verify a proof against a trusted root, returning the proven entry's Cid.
+///
+/// Fails if a block doesn't match its hash, a block needed for the walk is
+/// missing, or the walk ends anywhere but `proof.cid`.
+pub async fn verify_proof(root: &Cid, proof: &MerkleProof) -> Result<Cid, HashTreeError> {
+    let mut blocks = HashMap::new();
+    for block in &proof.blocks {
+        if sha256(&block.data) != block.hash {
+            return Err(HashTreeError::Store(format!(
+                "proof block {} doesn't match its hash",
+                to_hex(&block.hash)
+            )));
+        }
+        blocks.insert(block.hash, block.data.clone());
+    }
+
+    let store = Arc::new(MemoryStore::new());
+    for (hash, data) in blocks {
+        store
+            .put(hash, data)
+            .await
+            .map_err(|e| HashTreeError::Store(e.to_string()))?;
+    }
+
+    let tree = HashTree::new(HashTreeConfig::new(store));
+    // A block missing from the proof ends the walk early
+    let cid = tree
+        .resolve_path(root, &proof.path)
+        .await?
+        .ok_or_else(|| HashTreeError::PathNotFound(proof.path.clone()))?;
+
+    if cid.hash != proof.cid.hash {
+        return Err(HashTreeError::PathNotFound(proof.path.clone()));
+    }
+    Ok(cid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{DirEntry, LinkType};
+
+    async fn build_tree(tree: &HashTree<MemoryStore>) -> (Cid, Cid) {
+        let (file, size) = tree.put(b"hello proof").await.unwrap();
+        let (other, other_size) = tree.put(b"unrelated").await.unwrap();
+        let sub = tree
+            .put_directory(vec![
+                DirEntry::from_cid("file.txt", &file)
+                    .with_size(size)
+                    .with_link_type(LinkType::Blob),
+                DirEntry::from_cid("other.txt", &other)
+                    .with_size(other_size)
+                    .with_link_type(LinkType::Blob),
+            ])
+            .await
+            .unwrap();
+        let root = tree
+            .put_directory(vec![
+                DirEntry::from_cid("docs", &sub).with_link_type(LinkType::Dir)
+            ])
+            .await
+            .unwrap();
+        (root, file)
+    }
+
+    #[tokio::test]
+    async fn test_prove_and_verify() {
+        let tree = HashTree::new(HashTreeConfig::new(Arc::new(MemoryStore::new())).public());
+        let (root, file) = build_tree(&tree).await;
+
+        let proof = tree.prove(&root, "docs/file.txt").await.unwrap();
+        assert_eq!(proof.cid, file);
+        assert_eq!(proof.hashes().first(), Some(&root.hash));
+        // Root and docs nodes only, not the file or its sibling
+        assert_eq!(proof.blocks.len(), 2);
+
+        assert_eq!(verify_proof(&root, &proof).await.unwrap(), file);
+    }
+
+    #[tokio::test]
+    async fn test_prove_encrypted_path() {
+        let tree = HashTree::new(HashTreeConfig::new(Arc::new(MemoryStore::new())));
+        let (root, file) = build_tree(&tree).await;
+        assert!(root.key.is_some());
+
+        // Verifying decrypts the proof's nodes with the root's key
+        let proof = tree.prove(&root, "docs/file.txt").await.unwrap();
+        assert_eq!(proof.cid.key, file.key);
+        assert_eq!(verify_proof(&root, &proof).await.unwrap(), file);
+    }
+
+    #[tokio::test]
+    async fn test_verify_rejects_bad_proofs() {
+        let tree = HashTree::new(HashTreeConfig::new(Arc::new(MemoryStore::new())).public());
+        let (root, _) = build_tree(&tree).await;
+        let proof = tree.prove(&root, "docs/file.txt").await.unwrap();
+
+        // Tampered block
+        let mut tampered = proof.clone();
+        tampered.blocks[1].data.push(0);
+        assert!(verify_proof(&root, &tampered).await.is_err());
+
+        // Missing block
+        let mut partial = proof.clone();
+        partial.blocks.pop();
+        assert!(verify_proof(&root, &partial).await.is_err());
+
+        // Claims a different entry
+        let mut wrong = proof.clone();
+        wrong.cid.hash = [0u8; 32];
+        assert!(verify_proof(&root, &wrong).await.is_err());
+
+        // Checked against a root it wasn't built from
+        let (other_root, _) = build_tree(&HashTree::new(HashTreeConfig::new(Arc::new(
+            MemoryStore::new(),
+        ))))
+        .await;
+        assert!(verify_proof(&other_root, &proof).await.is_err());
+
+        assert!(tree.prove(&root, "docs/missing.txt").await.is_err());
+    }
+}
@@ -5,8 +5,10 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::codec::{decode_tree_node, is_directory_node, is_tree_node, try_decode_tree_node};
-use crate::store::Store;
+use crate::codec::{
+    decode_tree_node, is_directory_node, is_tree_node, try_decode_tree_node, CodecError,
+};
+use crate::store::{slice_range, Store};
 use crate::types::{to_hex, Cid, Hash, Link, LinkType, TreeNode};
 
 use crate::crypto::{decrypt_chk, EncryptionKey};
@@ -224,103 +226,76 @@ impl<S: Store> TreeReader<S> {
         start: u64,
         end: Option<u64>,
     ) -> Result<Vec<u8>, ReaderError> {
-        // First, flatten the tree to get all leaf chunks with their byte offsets
-        let chunks_info = self.collect_chunk_offsets(node).await?;
-
-        if chunks_info.is_empty() {
-            return Ok(vec![]);
+        let end = end.unwrap_or(u64::MAX);
+        let mut result = Vec::new();
+        if start < end {
+            self.read_range_below(node, 0, start, end, &mut result)
+                .await?;
         }
-
-        // Calculate total size and actual end
-        let total_size: u64 = chunks_info.iter().map(|(_, _, size)| size).sum();
-        let actual_end = end.unwrap_or(total_size).min(total_size);
-
-        if start >= actual_end {
-            return Ok(vec![]);
-        }
-
-        // Find chunks that overlap with [start, actual_end)
-        let mut result = Vec::with_capacity((actual_end - start) as usize);
-        let mut current_offset = 0u64;
-
-        for (chunk_hash, _chunk_offset, chunk_size) in &chunks_info {
-            let chunk_start = current_offset;
-            let chunk_end = current_offset + chunk_size;
-
-            // Check if this chunk overlaps with our range
-            if chunk_end > start && chunk_start < actual_end {
-                // Fetch this chunk
-                let chunk_data = self
-                    .store
-                    .get(chunk_hash)
-                    .await
-                    .map_err(|e| ReaderError::Store(e.to_string()))?
-                    .ok_or_else(|| ReaderError::MissingChunk(to_hex(chunk_hash)))?;
-
-                // Calculate slice bounds within this chunk
-                let slice_start = if start > chunk_start {
-                    (start - chunk_start) as usize
-                } else {
-                    0
-                };
-                let slice_end = if actual_end < chunk_end {
-                    (actual_end - chunk_start) as usize
-                } else {
-                    chunk_data.len()
-                };
-
-                result.extend_from_slice(&chunk_data[slice_start..slice_end]);
-            }
-
-            current_offset = chunk_end;
-
-            // Early exit if we've passed the requested range
-            if current_offset >= actual_end {
-                break;
-            }
-        }
-
         Ok(result)
     }
 
-    /// Collect all leaf chunk hashes with their byte offsets
-    /// Returns Vec<(hash, offset, size)>
-    async fn collect_chunk_offsets(
-        &self,
-        node: &TreeNode,
-    ) -> Result<Vec<(Hash, u64, u64)>, ReaderError> {
-        let mut chunks = Vec::new();
-        let mut offset = 0u64;
-        self.collect_chunk_offsets_recursive(node, &mut chunks, &mut offset).await?;
-        Ok(chunks)
-    }
-
-    async fn collect_chunk_offsets_recursive(
+    /// Append the bytes in `[start, end)` of the file below `node`, whose
+    /// data begins at `offset`, and return the offset after it. Link sizes
+    /// let subtrees outside the range go unfetched and leaves it only
+    /// partly covers be read with `get_range`; links without a size are
+    /// fetched to find it.
+    async fn read_range_below(
         &self,
         node: &TreeNode,
-        chunks: &mut Vec<(Hash, u64, u64)>,
-        offset: &mut u64,
-    ) -> Result<(), ReaderError> {
+        mut offset: u64,
+        start: u64,
+        end: u64,
+        out: &mut Vec<u8>,
+    ) -> Result<u64, ReaderError> {
         for link in &node.links {
-            let child_data = self
+            if offset >= end {
+                break;
+            }
+            let link_end = offset
+                .checked_add(link.size)
+                .ok_or(CodecError::Overflow("link sizes"))?;
+            if link.size > 0 && link_end <= start {
+                offset = link_end;
+                continue;
+            }
+
+            if link.size > 0 && link.link_type == LinkType::Blob {
+                let slice_start = start.saturating_sub(offset);
+                let slice_end = end.min(link_end) - offset;
+                let data = if slice_start == 0 && slice_end == link.size {
+                    self.store.get(&link.hash).await
+                } else {
+                    self.store
+                        .get_range(&link.hash, slice_start, slice_end - slice_start)
+                        .await
+                }
+                .map_err(|e| ReaderError::Store(e.to_string()))?
+                .ok_or_else(|| ReaderError::MissingChunk(to_hex(&link.hash)))?;
+                out.extend_from_slice(&data);
+                offset = link_end;
+                continue;
+            }
+
+            let data = self
                 .store
                 .get(&link.hash)
                 .await
                 .map_err(|e| ReaderError::Store(e.to_string()))?
                 .ok_or_else(|| ReaderError::MissingChunk(to_hex(&link.hash)))?;
-
-            if is_tree_node(&child_data) {
-                // Intermediate node - recurse
-                let child_node = decode_tree_node(&child_data).map_err(ReaderError::Codec)?;
-                Box::pin(self.collect_chunk_offsets_recursive(&child_node, chunks, offset)).await?;
+            if is_tree_node(&data) {
+                let child = decode_tree_node(&data).map_err(ReaderError::Codec)?;
+                offset = Box::pin(self.read_range_below(&child, offset, start, end, out)).await?;
             } else {
-                // Leaf chunk
-                let size = child_data.len() as u64;
-                chunks.push((link.hash, *offset, size));
-                *offset += size;
+                let from = start.saturating_sub(offset);
+                let to = end.saturating_sub(offset);
+                out.extend_from_slice(slice_range(&data, from, to.saturating_sub(from)));
+                offset = offset
+                    .checked_add(data.len() as u64)
+                    .ok_or(CodecError::Overflow("link sizes"))?;
             }
         }
-        Ok(())
+        Ok(offset)
     }
 
     /// Recursively assemble chunks from tree (unencrypted)
@@ -999,6 +974,83 @@ mod tests {
         assert_eq!(result, Some(b"Short".to_vec()));
     }
 
+    /// Store counting the bytes it returns from whole-blob reads
+    struct Counting {
+        inner: MemoryStore,
+        whole_bytes: std::sync::atomic::AtomicU64,
+    }
+
+    #[async_trait::async_trait]
+    impl Store for Counting {
+        async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, crate::store::StoreError> {
+            self.inner.put(hash, data).await
+        }
+
+        async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, crate::store::StoreError> {
+            let data = self.inner.get(hash).await?;
+            let len = data.as_ref().map_or(0, |d| d.len() as u64);
+            self.whole_bytes
+                .fetch_add(len, std::sync::atomic::Ordering::Relaxed);
+            Ok(data)
+        }
+
+        async fn get_range(
+            &self,
+            hash: &Hash,
+            offset: u64,
+            len: u64,
+        ) -> Result<Option<Vec<u8>>, crate::store::StoreError> {
+            self.inner.get_range(hash, offset, len).await
+        }
+
+        async fn has(&self, hash: &Hash) -> Result<bool, crate::store::StoreError> {
+            self.inner.has(hash).await
+        }
+
+        async fn delete(&self, hash: &Hash) -> Result<bool, crate::store::StoreError> {
+            self.inner.delete(hash).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_file_range_slices_partial_chunks() {
+        let store = Arc::new(Counting {
+            inner: MemoryStore::new(),
+            whole_bytes: Default::default(),
+        });
+        let config = BuilderConfig::new(store.clone())
+            .with_chunk_size(100)
+            .public();
+        let builder = TreeBuilder::new(config);
+        let reader = TreeReader::new(store.clone());
+
+        let data: Vec<u8> = (0..350).map(|i| (i % 256) as u8).collect();
+        let (cid, _size) = builder.put(&data).await.unwrap();
+        let root_len = store.inner.get(&cid.hash).await.unwrap().unwrap().len() as u64;
+        let whole = || {
+            store
+                .whole_bytes
+                .swap(0, std::sync::atomic::Ordering::Relaxed)
+        };
+        whole();
+
+        // Only the root node is read whole
+        let result = reader
+            .read_file_range(&cid.hash, 150, Some(160))
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap(), data[150..160].to_vec());
+        assert_eq!(whole(), root_len);
+
+        // A chunk the range covers is read whole, the ones around it sliced
+        let result = reader
+            .read_file_range(&cid.hash, 50, Some(250))
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap(), data[50..250].to_vec());
+        assert_eq!(whole(), root_len + 100);
+    }
+
     #[tokio::test]
     async fn test_read_file_range_single_byte() {
         let store = make_store();
@@ -0,0 +1,316 @@
+//! Size and shape statistics of a tree
+//!
+//! Walks a tree's directory and file nodes, taking leaf sizes from the
+//! links that point to them, so leaf data is only fetched when a link
+//! doesn't record its size. Reports totals, the largest files and a
+//! breakdown per directory, like `du`.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::codec::{decode_tree_node, is_tree_node};
+use crate::crypto::decrypt_chk;
+use crate::hashtree::{HashTree, HashTreeError};
+use crate::store::Store;
+use crate::types::{to_hex, Cid, Hash, LinkType, TreeNode};
+
+/// Statistics of a tree
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeStats {
+    /// Sum of file sizes
+    pub total_size: u64,
+    /// Distinct blocks, nodes and leaves
+    pub blocks: usize,
+    pub files: usize,
+    pub directories: usize,
+    /// Path components of the deepest entry
+    pub depth: usize,
+    /// Largest files, largest first
+    pub largest: Vec<FileSize>,
+    /// Every directory, parents before their children; the root's path is ""
+    pub dirs: Vec<DirStats>,
+}
+
+/// A file and its size
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileSize {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Totals of a directory, including its subdirectories
+#[derive(Debug, Clone, Serialize)]
+pub struct DirStats {
+    pub path: String,
+    pub size: u64,
+    pub files: usize,
+}
+
+/// A fetched block: a tree node, or a leaf of this many bytes
+enum Block {
+    Node(TreeNode),
+    Leaf(u64),
+}
+
+struct StatsWalk<'a, S: Store> {
+    tree: &'a HashTree<S>,
+    seen: HashSet<Hash>,
+    files: Vec<FileSize>,
+    stats: TreeStats,
+}
+
+/// Statistics of the tree at `root`, keeping the `largest` biggest files
+pub async fn tree_stats<S: Store>(
+    tree: &HashTree<S>,
+    root: &Cid,
+    largest: usize,
+) -> Result<TreeStats, HashTreeError> {
+    let mut walk = StatsWalk {
+        tree,
+        seen: HashSet::new(),
+        files: Vec::new(),
+        stats: TreeStats::default(),
+    };
+    match walk.fetch(root).await? {
+        Block::Leaf(size) => walk.add_file(String::new(), size, 0),
+        Block::Node(node) if is_file_node(&node) => {
+            let size = walk.file(root, node).await?;
+            walk.add_file(String::new(), size, 0);
+        }
+        Block::Node(node) => {
+            walk.dir(root, node, String::new(), 0).await?;
+        }
+    }
+
+    let mut stats = walk.stats;
+    stats.blocks = walk.seen.len();
+    let mut files = walk.files;
+    files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+    files.truncate(largest);
+    stats.largest = files;
+    Ok(stats)
+}
+
+/// A node with only unnamed links is a file's chunk list
+fn is_file_node(node: &TreeNode) -> bool {
+    node.node_type == LinkType::File && node.links.iter().all(|l| l.name.is_none())
+}
+
+impl<S: Store> StatsWalk<'_, S> {
+    async fn fetch(&mut self, cid: &Cid) -> Result<Block, HashTreeError> {
+        let data = self
+            .tree
+            .get_store()
+            .get(&cid.hash)
+            .await
+            .map_err(|e| HashTreeError::Store(e.to_string()))?
+            .ok_or_else(|| HashTreeError::MissingChunk(to_hex(&cid.hash)))?;
+        self.seen.insert(cid.hash);
+        let plain = match &cid.key {
+            Some(key) => {
+                decrypt_chk(&data, key).map_err(|e| HashTreeError::Decryption(e.to_string()))?
+            }
+            None => data,
+        };
+        if !is_tree_node(&plain) {
+            return Ok(Block::Leaf(plain.len() as u64));
+        }
+        Ok(Block::Node(decode_tree_node(&plain)?))
+    }
+
+    fn add_file(&mut self, path: String, size: u64, depth: usize) {
+        self.stats.files += 1;
+        self.stats.total_size += size;
+        self.stats.depth = self.stats.depth.max(depth);
+        self.files.push(FileSize { path, size });
+    }
+
+    /// Size of the file whose chunk list is `node`, walking internal nodes
+    /// but not fetching leaves
+    async fn file(&mut self, cid: &Cid, node: TreeNode) -> Result<u64, HashTreeError> {
+        self.seen.insert(cid.hash);
+        let mut size = 0;
+        for link in node.links {
+            let child = Cid {
+                hash: link.hash,
+                key: link.key,
+            };
+            if link.link_type == LinkType::Blob && link.size > 0 {
+                self.seen.insert(link.hash);
+                size += link.size;
+                continue;
+            }
+            size += match self.fetch(&child).await? {
+                Block::Leaf(leaf) => leaf,
+                Block::Node(node) => Box::pin(self.file(&child, node)).await?,
+            };
+        }
+        Ok(size)
+    }
+
+    /// Walk the directory `node` at `path`, returning its size and files
+    async fn dir(
+        &mut self,
+        cid: &Cid,
+        node: TreeNode,
+        path: String,
+        depth: usize,
+    ) -> Result<(u64, usize), HashTreeError> {
+        // A large directory's node is chunked like a file
+        let node = if is_file_node(&node) {
+            self.file(cid, node).await?;
+            self.tree
+                .get_directory_node(cid)
+                .await?
+                .ok_or_else(|| HashTreeError::MissingChunk(to_hex(&cid.hash)))?
+        } else {
+            node
+        };
+        self.stats.directories += 1;
+        let index = self.stats.dirs.len();
+        self.stats.dirs.push(DirStats {
+            path: path.clone(),
+            size: 0,
+            files: 0,
+        });
+
+        let (mut size, mut files) = (0, 0);
+        for link in node.links {
+            let Some(name) = link.name else { continue };
+            let child_path = if path.is_empty() {
+                name
+            } else {
+                format!("{}/{}", path, name)
+            };
+            let child = Cid {
+                hash: link.hash,
+                key: link.key,
+            };
+            if link.link_type == LinkType::Blob && link.size > 0 {
+                self.seen.insert(link.hash);
+                self.add_file(child_path, link.size, depth + 1);
+                size += link.size;
+                files += 1;
+                continue;
+            }
+            match self.fetch(&child).await? {
+                Block::Leaf(leaf) => {
+                    self.add_file(child_path, leaf, depth + 1);
+                    size += leaf;
+                    files += 1;
+                }
+                Block::Node(node) if link.link_type != LinkType::Dir && is_file_node(&node) => {
+                    let file_size = self.file(&child, node).await?;
+                    self.add_file(child_path, file_size, depth + 1);
+                    size += file_size;
+                    files += 1;
+                }
+                Block::Node(node) => {
+                    self.stats.depth = self.stats.depth.max(depth + 1);
+                    let (dir_size, dir_files) =
+                        Box::pin(self.dir(&child, node, child_path, depth + 1)).await?;
+                    size += dir_size;
+                    files += dir_files;
+                }
+            }
+        }
+
+        self.stats.dirs[index].size = size;
+        self.stats.dirs[index].files = files;
+        Ok((size, files))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashtree::HashTreeConfig;
+    use crate::store::MemoryStore;
+    use crate::types::DirEntry;
+    use std::sync::Arc;
+
+    /// Store that fails reads of one hash, to show it isn't fetched
+    struct Guarded {
+        inner: MemoryStore,
+        forbidden: std::sync::Mutex<Option<Hash>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Store for Guarded {
+        async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, crate::store::StoreError> {
+            self.inner.put(hash, data).await
+        }
+
+        async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, crate::store::StoreError> {
+            assert_ne!(Some(*hash), *self.forbidden.lock().unwrap(), "leaf fetched");
+            self.inner.get(hash).await
+        }
+
+        async fn has(&self, hash: &Hash) -> Result<bool, crate::store::StoreError> {
+            self.inner.has(hash).await
+        }
+
+        async fn delete(&self, hash: &Hash) -> Result<bool, crate::store::StoreError> {
+            self.inner.delete(hash).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tree_stats() {
+        let store = Arc::new(Guarded {
+            inner: MemoryStore::new(),
+            forbidden: std::sync::Mutex::new(None),
+        });
+        let chunked = HashTree::new(HashTreeConfig::new(store.clone()).with_chunk_size(4));
+        let tree = HashTree::new(HashTreeConfig::new(store.clone()));
+
+        let (big, big_size) = chunked.put(b"hello world").await.unwrap();
+        let (small, small_size) = tree.put(b"hi").await.unwrap();
+        let inner = tree
+            .put_directory(vec![
+                DirEntry::from_cid("big.txt", &big)
+                    .with_size(big_size)
+                    .with_link_type(LinkType::File),
+                DirEntry::from_cid("again.txt", &small).with_size(small_size),
+            ])
+            .await
+            .unwrap();
+        let root = tree
+            .put_directory(vec![
+                DirEntry::from_cid("docs", &inner).with_link_type(LinkType::Dir),
+                DirEntry::from_cid("small.txt", &small).with_size(small_size),
+            ])
+            .await
+            .unwrap();
+        *store.forbidden.lock().unwrap() = Some(small.hash);
+
+        let stats = tree_stats(&tree, &root, 2).await.unwrap();
+        assert_eq!(stats.total_size, 15);
+        assert_eq!(stats.files, 3);
+        assert_eq!(stats.directories, 2);
+        assert_eq!(stats.depth, 2);
+        // root, docs, big's node and 3 chunks, small once
+        assert_eq!(stats.blocks, 7);
+        assert_eq!(
+            stats.largest,
+            vec![
+                FileSize {
+                    path: "docs/big.txt".to_string(),
+                    size: 11
+                },
+                FileSize {
+                    path: "docs/again.txt".to_string(),
+                    size: 2
+                },
+            ]
+        );
+        let dirs: Vec<(&str, u64, usize)> = stats
+            .dirs
+            .iter()
+            .map(|d| (d.path.as_str(), d.size, d.files))
+            .collect();
+        assert_eq!(dirs, [("", 15, 3), ("docs", 13, 2)]);
+    }
+}
@@ -37,6 +37,56 @@ pub trait Store: Send + Sync {
     /// Returns true if deleted, false if didn't exist
     async fn delete(&self, hash: &Hash) -> Result<bool, StoreError>;
 
+    /// Retrieve up to `len` bytes of the data at `offset`, fewer if it ends
+    /// first. Returns None if not found.
+    ///
+    /// The default fetches the whole blob; backends that can read a slice
+    /// (files, HTTP ranges) override it. Unlike a whole blob, a slice can't
+    /// be checked against its hash.
+    async fn get_range(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self
+            .get(hash)
+            .await?
+            .map(|data| slice_range(&data, offset, len).to_vec()))
+    }
+
+    // ========================================================================
+    // Optional: Batch operations (default one call per hash; backends that
+    // can do better, e.g. parallel stats or pipelined requests, override)
+    // ========================================================================
+
+    /// Store many items; results are in input order, as from `put`
+    async fn put_many(&self, items: Vec<(Hash, Vec<u8>)>) -> Result<Vec<bool>, StoreError> {
+        let mut results = Vec::with_capacity(items.len());
+        for (hash, data) in items {
+            results.push(self.put(hash, data).await?);
+        }
+        Ok(results)
+    }
+
+    /// Retrieve many hashes; results are in input order, as from `get`
+    async fn get_many(&self, hashes: &[Hash]) -> Result<Vec<Option<Vec<u8>>>, StoreError> {
+        let mut results = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            results.push(self.get(hash).await?);
+        }
+        Ok(results)
+    }
+
+    /// Check many hashes; results are in input order, as from `has`
+    async fn has_many(&self, hashes: &[Hash]) -> Result<Vec<bool>, StoreError> {
+        let mut results = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            results.push(self.has(hash).await?);
+        }
+        Ok(results)
+    }
+
     // ========================================================================
     // Optional: Storage limits and eviction (default no-op implementations)
     // ========================================================================
@@ -85,6 +135,13 @@ pub trait Store: Send + Sync {
     }
 }
 
+/// The up to `len` bytes of `data` at `offset`
+pub fn slice_range(data: &[u8], offset: u64, len: u64) -> &[u8] {
+    let start = offset.min(data.len() as u64) as usize;
+    let end = offset.saturating_add(len).min(data.len() as u64) as usize;
+    &data[start..end]
+}
+
 /// Store error type
 #[derive(Debug, thiserror::Error)]
 pub enum StoreError {
@@ -228,6 +285,20 @@ impl Store for MemoryStore {
         Ok(inner.data.get(&key).map(|e| e.data.clone()))
     }
 
+    async fn get_range(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        let key = to_hex(hash);
+        let inner = self.inner.read().unwrap();
+        Ok(inner
+            .data
+            .get(&key)
+            .map(|e| slice_range(&e.data, offset, len).to_vec()))
+    }
+
     async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
         let key = to_hex(hash);
         Ok(self.inner.read().unwrap().data.contains_key(&key))
@@ -241,6 +312,22 @@ impl Store for MemoryStore {
         Ok(inner.data.remove(&key).is_some())
     }
 
+    async fn get_many(&self, hashes: &[Hash]) -> Result<Vec<Option<Vec<u8>>>, StoreError> {
+        let inner = self.inner.read().unwrap();
+        Ok(hashes
+            .iter()
+            .map(|hash| inner.data.get(&to_hex(hash)).map(|e| e.data.clone()))
+            .collect())
+    }
+
+    async fn has_many(&self, hashes: &[Hash]) -> Result<Vec<bool>, StoreError> {
+        let inner = self.inner.read().unwrap();
+        Ok(hashes
+            .iter()
+            .map(|hash| inner.data.contains_key(&to_hex(hash)))
+            .collect())
+    }
+
     fn set_max_bytes(&self, max: u64) {
         self.inner.write().unwrap().max_bytes = if max > 0 { Some(max) } else { None };
     }
@@ -371,6 +458,21 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[tokio::test]
+    async fn test_get_range() {
+        let store = MemoryStore::new();
+        let data = b"hello world".to_vec();
+        let hash = sha256(&data);
+        store.put(hash, data).await.unwrap();
+
+        let range = |offset, len| store.get_range(&hash, offset, len);
+        assert_eq!(range(6, 5).await.unwrap(), Some(b"world".to_vec()));
+        assert_eq!(range(6, 100).await.unwrap(), Some(b"world".to_vec()));
+        assert_eq!(range(20, 5).await.unwrap(), Some(vec![]));
+        assert_eq!(range(0, u64::MAX).await.unwrap().unwrap().len(), 11);
+        assert!(store.get_range(&[0u8; 32], 0, 1).await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn test_has_returns_true() {
         let store = MemoryStore::new();
@@ -690,4 +792,31 @@ mod tests {
         // Pin should be gone after delete
         assert_eq!(store.pin_count(&hash), 0);
     }
+
+    #[tokio::test]
+    async fn test_batch_operations_keep_input_order() {
+        let store = MemoryStore::new();
+        let a = vec![1u8];
+        let b = vec![2u8];
+        let missing = sha256(&[3u8]);
+
+        let stored = store
+            .put_many(vec![(sha256(&a), a.clone()), (sha256(&b), b.clone())])
+            .await
+            .unwrap();
+        assert_eq!(stored, vec![true, true]);
+        let again = store.put_many(vec![(sha256(&a), a.clone())]).await.unwrap();
+        assert_eq!(again, vec![false]);
+
+        let hashes = [sha256(&b), missing, sha256(&a)];
+        assert_eq!(
+            store.get_many(&hashes).await.unwrap(),
+            vec![Some(b), None, Some(a)]
+        );
+        assert_eq!(
+            store.has_many(&hashes).await.unwrap(),
+            vec![true, false, true]
+        );
+        assert!(store.has_many(&[]).await.unwrap().is_empty());
+    }
 }
@@ -935,6 +935,64 @@ mod edge_cases {
             assert!(result.is_ok());
         }
     }
+
+    #[tokio::test]
+    async fn test_resolve_refuses_overly_deep_trees() {
+        let (_store, tree) = make_tree();
+        let file_hash = tree.put_blob(b"data").await.unwrap();
+
+        // A directory whose entry sits below a chain of internal nodes
+        let mut node = tree
+            .put_tree_node(vec![Link::new(file_hash).with_name("a.txt").with_size(4)])
+            .await
+            .unwrap();
+        for depth in 1..=hashtree_core::MAX_TREE_DEPTH + 1 {
+            node = tree
+                .put_tree_node(vec![Link::new(node).with_name("_0")])
+                .await
+                .unwrap();
+            if depth == hashtree_core::MAX_TREE_DEPTH {
+                let found = tree
+                    .resolve_path(&Cid::public(node), "a.txt")
+                    .await
+                    .unwrap();
+                assert!(found.is_some());
+            }
+        }
+
+        let result = tree.resolve_path(&Cid::public(node), "a.txt").await;
+        assert!(matches!(result, Err(HashTreeError::TooDeep(_))));
+    }
+
+    #[tokio::test]
+    async fn test_directory_node_refuses_overflowing_sizes() {
+        let (store, tree) = make_tree();
+        let chunk = tree.put_blob(b"data").await.unwrap();
+        let node = hashtree_core::TreeNode::file(vec![
+            Link::new(chunk).with_size(u64::MAX),
+            Link::new(chunk).with_size(1),
+        ]);
+        let (data, hash) = hashtree_core::encode_and_hash(&node).unwrap();
+        store.put(hash, data).await.unwrap();
+
+        let result = tree.get_directory_node(&Cid::public(hash)).await;
+        assert!(matches!(result, Err(HashTreeError::Codec(_))));
+    }
+
+    #[tokio::test]
+    async fn test_range_read_refuses_overflowing_sizes() {
+        let (store, tree) = make_tree();
+        let chunk = tree.put_blob(b"data").await.unwrap();
+        let node = hashtree_core::TreeNode::file(vec![
+            Link::new(chunk).with_size(4),
+            Link::new(chunk).with_size(u64::MAX),
+        ]);
+        let (data, hash) = hashtree_core::encode_and_hash(&node).unwrap();
+        store.put(hash, data).await.unwrap();
+
+        let result = tree.read_file_range(&hash, 2, None).await;
+        assert!(matches!(result, Err(HashTreeError::Codec(_))));
+    }
 }
 
 // ============ ENCRYPTION TESTS ============
@@ -1032,6 +1090,55 @@ mod encryption {
         assert_eq!(stored, plaintext.to_vec());
     }
 
+    #[tokio::test]
+    async fn test_rotate_keys_changes_directory_keys_only() {
+        let (_, tree) = make_encrypted_tree();
+
+        let (file_cid, file_size) = tree.put_file(b"secret notes").await.unwrap();
+        let sub = tree
+            .put_directory(vec![DirEntry::new("notes.txt", file_cid.hash)
+                .with_key(file_cid.key.unwrap())
+                .with_size(file_size)
+                .with_link_type(LinkType::File)])
+            .await
+            .unwrap();
+        let root = tree
+            .put_directory(vec![DirEntry::new("docs", sub.hash)
+                .with_key(sub.key.unwrap())
+                .with_link_type(LinkType::Dir)])
+            .await
+            .unwrap();
+
+        let rotated = tree.rotate_keys(&root).await.unwrap();
+        assert_ne!(rotated.key, root.key);
+        assert_ne!(rotated.hash, root.hash);
+
+        // The old key can't open the rotated root
+        let stale = Cid { hash: rotated.hash, key: root.key };
+        assert!(tree.list_directory(&stale).await.is_err());
+
+        // Subdirectories are rekeyed, file content keeps its convergent key
+        let docs = tree.resolve_path(&rotated, "docs").await.unwrap().unwrap();
+        assert_ne!(docs.key, sub.key);
+        let notes = tree.resolve_path(&rotated, "docs/notes.txt").await.unwrap().unwrap();
+        assert_eq!(notes, file_cid);
+        assert_eq!(tree.get(&notes).await.unwrap().unwrap(), b"secret notes");
+
+        // Rotating again yields yet another key
+        let again = tree.rotate_keys(&rotated).await.unwrap();
+        assert_ne!(again.key, rotated.key);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_keys_rejects_public_tree() {
+        let (_, tree) = make_tree();
+        let root = tree.put_directory(vec![]).await.unwrap();
+        assert!(matches!(
+            tree.rotate_keys(&root).await,
+            Err(HashTreeError::Encryption(_))
+        ));
+    }
+
 }
 
 // ============ INTEROPERABILITY TESTS ============
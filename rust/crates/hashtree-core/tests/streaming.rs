@@ -1,7 +1,8 @@
 //! Streaming tests for HashTree put_stream and get_stream API
 
-use hashtree_core::{HashTree, HashTreeConfig, MemoryStore};
+use hashtree_core::{Hash, HashTree, HashTreeConfig, MemoryStore, Store, StoreError};
 use futures::StreamExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 #[tokio::test]
@@ -156,3 +157,57 @@ async fn test_get_stream_chunk_by_chunk() {
     let result: Vec<u8> = chunks.into_iter().flatten().collect();
     assert_eq!(result, data);
 }
+
+/// Store that holds each read for a moment, recording how many overlap
+#[derive(Default)]
+struct SlowStore {
+    inner: MemoryStore,
+    active: AtomicUsize,
+    max_active: AtomicUsize,
+}
+
+#[async_trait::async_trait]
+impl Store for SlowStore {
+    async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.inner.put(hash, data).await
+    }
+
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_active.fetch_max(active, Ordering::SeqCst);
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        self.inner.get(hash).await
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.inner.has(hash).await
+    }
+
+    async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.inner.delete(hash).await
+    }
+}
+
+#[tokio::test]
+async fn test_get_stream_prefetches_leaves() {
+    // 10 chunks under two nodes of up to 8
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    for (prefetch, overlap) in [(0, 1), (3, 4)] {
+        let store = Arc::new(SlowStore::default());
+        let config = HashTreeConfig::new(store.clone())
+            .with_chunk_size(100)
+            .with_max_links(8)
+            .with_prefetch(prefetch);
+        let tree = HashTree::new(config);
+        let (cid, _size) = tree.put(&data).await.unwrap();
+
+        let mut stream = tree.get_stream(&cid);
+        let mut result = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            result.extend(chunk.unwrap());
+        }
+        assert_eq!(result, data);
+        assert_eq!(store.max_active.load(Ordering::SeqCst), overlap);
+    }
+}
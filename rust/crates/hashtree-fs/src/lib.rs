@@ -1,48 +1,159 @@
 //! Filesystem-based content-addressed blob storage.
 //!
-//! Stores blobs in a directory structure similar to git:
-//! `{base_path}/{first 2 chars of hash}/{remaining hash chars}`
+//! Stores blobs in a two-level fanout directory structure:
+//! `{base_path}/{hash[0..2]}/{hash[2..4]}/{remaining hash chars}`
 //!
 //! For example, a blob with hash `abcdef123...` would be stored at:
-//! `~/.hashtree/blobs/ab/cdef123...`
+//! `~/.hashtree/blobs/ab/cd/ef123...`
+//!
+//! Stores created by older versions, with every blob in one flat directory
+//! or a single `ab/` level, are migrated in place in the background once
+//! opened; their blobs stay readable at the old paths until moved.
 
 use async_trait::async_trait;
+use hashtree_core::hash::sha256;
 use hashtree_core::store::{Store, StoreError, StoreStats};
 use hashtree_core::types::Hash;
 use std::collections::HashMap;
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
+use tokio::io::AsyncWriteExt;
+
+mod pack;
+
+pub use pack::{PackBlobStore, PackStats, DEFAULT_INLINE_MAX};
+
+/// File recording the on-disk layout version
+const LAYOUT_FILE: &str = "layout";
+
+/// Current layout: two levels of 256-way fanout
+const LAYOUT_VERSION: &str = "2";
+
+/// Hex chars of a blob file name below the two fanout levels
+const BLOB_NAME_LEN: usize = 60;
+
+/// Batches smaller than this are handled on the calling thread
+const PARALLEL_BATCH_MIN: usize = 32;
+
+/// Upper bound on threads used for one batch
+const MAX_BATCH_THREADS: usize = 8;
+
+/// Directory under the store root holding blobs that failed verification
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// Distinguishes temp files of concurrent writers in this process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Map `f` over `items` on scoped threads, keeping input order
+fn parallel_map<I: Sync, T: Send>(items: &[I], f: impl Fn(&I) -> T + Sync) -> Vec<T> {
+    if items.len() < PARALLEL_BATCH_MIN {
+        return items.iter().map(f).collect();
+    }
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(MAX_BATCH_THREADS);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .chunks(items.len().div_ceil(threads))
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<T>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// When blob writes are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// Leave flushing to the OS; a crash can lose recent writes
+    #[default]
+    Never,
+    /// fsync each blob before it is renamed into place
+    Data,
+    /// Also fsync the directory, so the rename itself survives a crash
+    Full,
+}
+
+/// What a scrub does with blobs whose content no longer matches their hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScrubAction {
+    /// Only report them
+    Report,
+    /// Move them to `quarantine/` under the store root for inspection
+    #[default]
+    Quarantine,
+    /// Delete them
+    Delete,
+}
+
+/// Outcome of a scrub.
+#[derive(Debug, Clone, Default)]
+pub struct ScrubReport {
+    /// Blobs re-hashed
+    pub checked: usize,
+    /// Bytes read while re-hashing
+    pub bytes: u64,
+    /// Blobs that didn't match their hash
+    pub corrupted: Vec<Hash>,
+    /// Blobs that couldn't be read; left in place whatever the action
+    pub unreadable: Vec<Hash>,
+}
 
 /// Filesystem-backed blob store implementing hashtree's Store trait.
 ///
-/// Stores blobs in a 256-way sharded directory structure using
-/// the first 2 hex characters of the hash as the directory prefix.
+/// Stores blobs in a 65536-way sharded directory structure using
+/// the first 4 hex characters of the hash as two directory levels.
 /// Supports storage limits with mtime-based FIFO eviction and pinning.
 pub struct FsBlobStore {
     base_path: PathBuf,
     max_bytes: AtomicU64,
+    fsync: FsyncPolicy,
     /// Pin counts stored in memory, persisted to pins.json
     pins: RwLock<HashMap<String, u32>>,
+    /// Set while blobs in an older layout are being moved
+    migrating: Arc<AtomicBool>,
 }
 
 impl FsBlobStore {
     /// Create a new filesystem blob store at the given path.
     ///
-    /// Creates the directory if it doesn't exist.
+    /// Creates the directory if it doesn't exist, and starts moving blobs
+    /// stored in an older layout into the current one on a background thread.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
         let base_path = path.as_ref().to_path_buf();
         fs::create_dir_all(&base_path)?;
 
+        let layout = fs::read_to_string(base_path.join(LAYOUT_FILE));
+        let migrating = Arc::new(AtomicBool::new(
+            !layout.is_ok_and(|v| v.trim() == LAYOUT_VERSION),
+        ));
+        if migrating.load(Ordering::Acquire) {
+            let (base, flag) = (base_path.clone(), migrating.clone());
+            std::thread::spawn(move || {
+                // On failure the old paths stay readable and the next open retries
+                if Self::migrate_layout(&base).is_ok() {
+                    flag.store(false, Ordering::Release);
+                }
+            });
+        }
+
         // Load existing pins from disk
         let pins = Self::load_pins(&base_path).unwrap_or_default();
 
         Ok(Self {
             base_path,
             max_bytes: AtomicU64::new(0), // 0 = unlimited
+            fsync: FsyncPolicy::default(),
             pins: RwLock::new(pins),
+            migrating,
         })
     }
 
@@ -53,6 +164,55 @@ impl FsBlobStore {
         Ok(store)
     }
 
+    /// Set when writes are flushed to disk (default: never)
+    pub fn with_fsync_policy(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    /// Move blobs from the flat (`{hash}`) and one-level (`ab/{rest}`)
+    /// layouts into the two-level one, then record the layout version.
+    ///
+    /// Safe to run from several processes opening the same store at once.
+    fn migrate_layout(base_path: &Path) -> Result<(), StoreError> {
+        for entry in fs::read_dir(base_path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+
+            if file_type.is_file() && is_hex(&name, 64) {
+                Self::move_blob(base_path, &entry.path(), &name)?;
+            } else if file_type.is_dir() && is_hex(&name, 2) {
+                for child in fs::read_dir(entry.path())? {
+                    let child = child?;
+                    let rest = child.file_name().to_string_lossy().into_owned();
+                    if child.file_type()?.is_file() && is_hex(&rest, 62) {
+                        Self::move_blob(base_path, &child.path(), &format!("{}{}", name, rest))?;
+                    }
+                }
+            }
+        }
+
+        let layout_path = base_path.join(LAYOUT_FILE);
+        let temp_path = temp_path_for(&layout_path);
+        fs::write(&temp_path, LAYOUT_VERSION)?;
+        fs::rename(&temp_path, &layout_path)?;
+        Ok(())
+    }
+
+    /// Rename a legacy blob file to its current path
+    fn move_blob(base_path: &Path, from: &Path, hex: &str) -> Result<(), StoreError> {
+        let to = blob_path_in(base_path, hex);
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::rename(from, to) {
+            // Already moved by another process opening the store
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
     /// Path to pins.json file
     fn pins_path(&self) -> PathBuf {
         self.base_path.join("pins.json")
@@ -76,11 +236,25 @@ impl FsBlobStore {
 
     /// Get the file path for a given hash.
     ///
-    /// Format: `{base_path}/{first 2 hex chars}/{remaining 62 hex chars}`
+    /// Format: `{base_path}/{hex[0..2]}/{hex[2..4]}/{remaining 60 hex chars}`
     fn blob_path(&self, hash: &Hash) -> PathBuf {
+        blob_path_in(&self.base_path, &hex::encode(hash))
+    }
+
+    /// Paths a blob may be at: its own, and while a migration runs its
+    /// legacy paths and then its own again, in case it moved meanwhile
+    fn candidate_paths(&self, hash: &Hash) -> Vec<PathBuf> {
         let hex = hex::encode(hash);
-        let (prefix, rest) = hex.split_at(2);
-        self.base_path.join(prefix).join(rest)
+        let path = blob_path_in(&self.base_path, &hex);
+        if !self.migrating.load(Ordering::Acquire) {
+            return vec![path];
+        }
+        vec![
+            path.clone(),
+            self.base_path.join(&hex),
+            self.base_path.join(&hex[..2]).join(&hex[2..]),
+            path,
+        ]
     }
 
     /// Sync put operation.
@@ -93,192 +267,212 @@ impl FsBlobStore {
         }
 
         // Create parent directory if needed
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
-        }
+        let parent = path.parent().expect("blob path has a parent");
+        fs::create_dir_all(parent)?;
 
         // Write atomically using temp file + rename
-        let temp_path = path.with_extension("tmp");
-        fs::write(&temp_path, data)?;
+        let temp_path = temp_path_for(&path);
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(data)?;
+        if self.fsync != FsyncPolicy::Never {
+            file.sync_data()?;
+        }
+        drop(file);
         fs::rename(&temp_path, &path)?;
+        if self.fsync == FsyncPolicy::Full {
+            fs::File::open(parent)?.sync_all()?;
+        }
 
         Ok(true)
     }
 
     /// Sync get operation.
     pub fn get_sync(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
-        let path = self.blob_path(hash);
-        if path.exists() {
-            Ok(Some(fs::read(&path)?))
-        } else {
-            Ok(None)
-        }
+        read_any(&self.candidate_paths(hash))
+    }
+
+    /// Sync read of up to `len` bytes at `offset`, without reading the
+    /// rest of the blob.
+    pub fn get_range_sync(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        read_range_any(&self.candidate_paths(hash), offset, len)
     }
 
     /// Check if a hash exists.
     pub fn exists(&self, hash: &Hash) -> bool {
-        self.blob_path(hash).exists()
+        self.candidate_paths(hash).iter().any(|path| path.exists())
     }
 
     /// Sync delete operation.
     pub fn delete_sync(&self, hash: &Hash) -> Result<bool, StoreError> {
-        let path = self.blob_path(hash);
-        if path.exists() {
-            fs::remove_file(&path)?;
-            Ok(true)
-        } else {
-            Ok(false)
+        // Legacy copies go too, or the migration would bring them back
+        let mut deleted = false;
+        for path in self.candidate_paths(hash) {
+            match fs::remove_file(path) {
+                Ok(()) => deleted = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
         }
+        Ok(deleted)
     }
 
-    /// List all hashes in the store.
-    pub fn list(&self) -> Result<Vec<Hash>, StoreError> {
-        let mut hashes = Vec::new();
-
-        // Iterate over prefix directories (00-ff)
-        let entries = match fs::read_dir(&self.base_path) {
+    /// Call `f` with the hex hash and directory entry of every stored blob
+    fn walk_blobs(&self, mut f: impl FnMut(String, &fs::DirEntry)) -> io::Result<()> {
+        let top = match fs::read_dir(&self.base_path) {
             Ok(e) => e,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(hashes),
-            Err(e) => return Err(e.into()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
         };
 
-        for prefix_entry in entries {
-            let prefix_entry = prefix_entry?;
-            let prefix_path = prefix_entry.path();
-
-            if !prefix_path.is_dir() {
+        // Iterate over prefix directories (00-ff/00-ff), also reporting
+        // blobs a running migration hasn't moved yet
+        for first in top {
+            let first = first?;
+            let first_name = first.file_name().to_string_lossy().into_owned();
+            if is_hex(&first_name, 64) && first.file_type()?.is_file() {
+                f(first_name, &first);
                 continue;
             }
-
-            let prefix = match prefix_path.file_name().and_then(|n| n.to_str()) {
-                Some(p) if p.len() == 2 => p.to_string(),
-                _ => continue,
-            };
-
-            // Iterate over blobs in this prefix directory
-            for blob_entry in fs::read_dir(&prefix_path)? {
-                let blob_entry = blob_entry?;
-                let rest = match blob_entry.file_name().to_str() {
-                    Some(r) if r.len() == 62 => r.to_string(),
-                    _ => continue,
-                };
-
-                // Reconstruct full hash hex
-                let full_hex = format!("{}{}", prefix, rest);
-                if let Ok(bytes) = hex::decode(&full_hex) {
-                    if bytes.len() == 32 {
-                        let mut hash = [0u8; 32];
-                        hash.copy_from_slice(&bytes);
-                        hashes.push(hash);
+            if !is_hex(&first_name, 2) || !first.file_type()?.is_dir() {
+                continue;
+            }
+            for second in fs::read_dir(first.path())? {
+                let second = second?;
+                let second_name = second.file_name().to_string_lossy().into_owned();
+                if is_hex(&second_name, 62) && second.file_type()?.is_file() {
+                    f(format!("{}{}", first_name, second_name), &second);
+                    continue;
+                }
+                if !is_hex(&second_name, 2) || !second.file_type()?.is_dir() {
+                    continue;
+                }
+                for blob in fs::read_dir(second.path())? {
+                    let blob = blob?;
+                    let rest = blob.file_name().to_string_lossy().into_owned();
+                    // Skips in-flight .tmp files
+                    if is_hex(&rest, BLOB_NAME_LEN) {
+                        f(format!("{}{}{}", first_name, second_name, rest), &blob);
                     }
                 }
             }
         }
+        Ok(())
+    }
 
+    /// List all hashes in the store.
+    pub fn list(&self) -> Result<Vec<Hash>, StoreError> {
+        let mut hashes = Vec::new();
+        self.walk_blobs(|hex, _| {
+            if let Ok(bytes) = hex::decode(&hex) {
+                if let Ok(hash) = bytes.try_into() {
+                    hashes.push(hash);
+                }
+            }
+        })?;
         Ok(hashes)
     }
 
     /// Get storage statistics.
     pub fn stats(&self) -> Result<FsStats, StoreError> {
         let pins = self.pins.read().unwrap();
-        let mut count = 0usize;
-        let mut total_bytes = 0u64;
-        let mut pinned_count = 0usize;
-        let mut pinned_bytes = 0u64;
-
-        let entries = match fs::read_dir(&self.base_path) {
-            Ok(e) => e,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                return Ok(FsStats {
-                    count,
-                    total_bytes,
-                    pinned_count,
-                    pinned_bytes,
-                })
-            }
-            Err(e) => return Err(e.into()),
+        let mut stats = FsStats {
+            count: 0,
+            total_bytes: 0,
+            pinned_count: 0,
+            pinned_bytes: 0,
         };
-
-        for prefix_entry in entries {
-            let prefix_entry = prefix_entry?;
-            let prefix_path = prefix_entry.path();
-
-            if !prefix_path.is_dir() {
-                continue;
-            }
-
-            let prefix = match prefix_path.file_name().and_then(|n| n.to_str()) {
-                Some(p) if p.len() == 2 => p,
-                _ => continue,
+        let mut error = None;
+
+        self.walk_blobs(|hex, entry| {
+            let size = match entry.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(e) => {
+                    error.get_or_insert(e);
+                    return;
+                }
             };
+            stats.count += 1;
+            stats.total_bytes += size;
 
-            for blob_entry in fs::read_dir(&prefix_path)? {
-                let blob_entry = blob_entry?;
-                if blob_entry.path().is_file() {
-                    let size = blob_entry.metadata()?.len();
-                    count += 1;
-                    total_bytes += size;
-
-                    // Check if pinned
-                    if let Some(rest) = blob_entry.file_name().to_str() {
-                        let hex = format!("{}{}", prefix, rest);
-                        if pins.get(&hex).copied().unwrap_or(0) > 0 {
-                            pinned_count += 1;
-                            pinned_bytes += size;
-                        }
-                    }
-                }
+            // Check if pinned
+            if pins.get(&hex).copied().unwrap_or(0) > 0 {
+                stats.pinned_count += 1;
+                stats.pinned_bytes += size;
             }
-        }
+        })?;
 
-        Ok(FsStats {
-            count,
-            total_bytes,
-            pinned_count,
-            pinned_bytes,
-        })
+        match error {
+            Some(e) => Err(e.into()),
+            None => Ok(stats),
+        }
     }
 
-    /// Collect all blobs with their mtime and size for eviction
-    fn collect_blobs_for_eviction(&self) -> Vec<(PathBuf, String, SystemTime, u64)> {
+    /// Re-hash every stored blob and handle the corrupted ones per `action`.
+    ///
+    /// `on_blob` is called with the size of each blob checked, for progress.
+    pub fn scrub(
+        &self,
+        action: ScrubAction,
+        mut on_blob: impl FnMut(u64),
+    ) -> Result<ScrubReport, StoreError> {
         let mut blobs = Vec::new();
+        self.walk_blobs(|hex, entry| blobs.push((hex, entry.path())))?;
 
-        let entries = match fs::read_dir(&self.base_path) {
-            Ok(e) => e,
-            Err(_) => return blobs,
-        };
-
-        for prefix_entry in entries.flatten() {
-            let prefix_path = prefix_entry.path();
-            if !prefix_path.is_dir() {
+        let mut report = ScrubReport::default();
+        for (hex, path) in blobs {
+            let Some(hash) = hex::decode(&hex).ok().and_then(|b| b.try_into().ok()) else {
                 continue;
-            }
-
-            let prefix = match prefix_path.file_name().and_then(|n| n.to_str()) {
-                Some(p) if p.len() == 2 => p.to_string(),
-                _ => continue,
             };
+            let (size, intact) = match fs::read(&path) {
+                Ok(data) => (data.len() as u64, sha256(&data) == hash),
+                // Deleted or evicted since the walk
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(_) => {
+                    report.unreadable.push(hash);
+                    continue;
+                }
+            };
+            report.checked += 1;
+            report.bytes += size;
+            on_blob(size);
+            if intact {
+                continue;
+            }
 
-            if let Ok(blob_entries) = fs::read_dir(&prefix_path) {
-                for blob_entry in blob_entries.flatten() {
-                    let path = blob_entry.path();
-                    if !path.is_file() {
-                        continue;
-                    }
-
-                    if let Ok(metadata) = blob_entry.metadata() {
-                        let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-                        let size = metadata.len();
-
-                        if let Some(rest) = blob_entry.file_name().to_str() {
-                            let hex = format!("{}{}", prefix, rest);
-                            blobs.push((path, hex, mtime, size));
+            match action {
+                ScrubAction::Report => {}
+                ScrubAction::Quarantine => {
+                    let dir = self.base_path.join(QUARANTINE_DIR);
+                    fs::create_dir_all(&dir)?;
+                    fs::rename(&path, dir.join(&hex))?;
+                }
+                ScrubAction::Delete => {
+                    if let Err(e) = fs::remove_file(&path) {
+                        if e.kind() != io::ErrorKind::NotFound {
+                            return Err(e.into());
                         }
                     }
                 }
             }
+            report.corrupted.push(hash);
         }
+        Ok(report)
+    }
 
+    /// Collect all blobs with their mtime and size for eviction
+    fn collect_blobs_for_eviction(&self) -> Vec<(PathBuf, String, SystemTime, u64)> {
+        let mut blobs = Vec::new();
+        let _ = self.walk_blobs(|hex, entry| {
+            if let Ok(metadata) = entry.metadata() {
+                let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                blobs.push((entry.path(), hex, mtime, metadata.len()));
+            }
+        });
         blobs
     }
 
@@ -289,6 +483,9 @@ impl FsBlobStore {
         // Collect all blobs
         let mut blobs = self.collect_blobs_for_eviction();
 
+        // Calculate current total
+        let current_bytes: u64 = blobs.iter().map(|(_, _, _, size)| *size).sum();
+
         // Filter to unpinned only
         blobs.retain(|(_, hex, _, _)| pins.get(hex).copied().unwrap_or(0) == 0);
 
@@ -297,13 +494,6 @@ impl FsBlobStore {
 
         drop(pins); // Release lock before deleting
 
-        // Calculate current total
-        let current_bytes: u64 = self
-            .collect_blobs_for_eviction()
-            .iter()
-            .map(|(_, _, _, size)| *size)
-            .sum();
-
         if current_bytes <= target_bytes {
             return 0;
         }
@@ -324,6 +514,78 @@ impl FsBlobStore {
     }
 }
 
+/// Path of the blob with hex hash `hex` under `base_path`
+fn blob_path_in(base_path: &Path, hex: &str) -> PathBuf {
+    base_path.join(&hex[..2]).join(&hex[2..4]).join(&hex[4..])
+}
+
+/// Unique temp file next to `path`, so concurrent writers of the same blob
+/// don't write into each other's file
+fn temp_path_for(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+/// Read up to `len` bytes at `offset` of the file at `path` with positioned
+/// reads (pread), or None if it doesn't exist
+fn read_range(path: &Path, offset: u64, len: u64) -> Result<Option<Vec<u8>>, StoreError> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let size = file.metadata()?.len();
+    let len = len.min(size.saturating_sub(offset)) as usize;
+    let mut buf = vec![0u8; len];
+    let mut filled = 0;
+    while filled < len {
+        let pos = offset + filled as u64;
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(&file, &mut buf[filled..], pos)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(&file, &mut buf[filled..], pos)?;
+        if n == 0 {
+            // Shrank since the metadata call; blobs aren't rewritten in place
+            buf.truncate(filled);
+            break;
+        }
+        filled += n;
+    }
+    Ok(Some(buf))
+}
+
+/// `read_range` of the first of `paths` that exists
+/// Read the first of `paths` that exists
+fn read_any(paths: &[PathBuf]) -> Result<Option<Vec<u8>>, StoreError> {
+    for path in paths {
+        match fs::read(path) {
+            Ok(data) => return Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+fn read_range_any(paths: &[PathBuf], offset: u64, len: u64) -> Result<Option<Vec<u8>>, StoreError> {
+    for path in paths {
+        if let Some(data) = read_range(path, offset, len)? {
+            return Ok(Some(data));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `name` is exactly `len` lowercase hex chars
+fn is_hex(name: &str, len: usize) -> bool {
+    name.len() == len && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
 /// Storage statistics.
 #[derive(Debug, Clone)]
 pub struct FsStats {
@@ -336,15 +598,60 @@ pub struct FsStats {
 #[async_trait]
 impl Store for FsBlobStore {
     async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
-        self.put_sync(hash, &data)
+        let path = self.blob_path(&hash);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(false);
+        }
+
+        let parent = path.parent().expect("blob path has a parent");
+        tokio::fs::create_dir_all(parent).await?;
+
+        // Write atomically using temp file + rename
+        let temp_path = temp_path_for(&path);
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(&data).await?;
+        if self.fsync != FsyncPolicy::Never {
+            file.sync_data().await?;
+        }
+        drop(file);
+        tokio::fs::rename(&temp_path, &path).await?;
+        if self.fsync == FsyncPolicy::Full {
+            tokio::fs::File::open(parent).await?.sync_all().await?;
+        }
+
+        Ok(true)
     }
 
     async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
-        self.get_sync(hash)
+        for path in self.candidate_paths(hash) {
+            match tokio::fs::read(path).await {
+                Ok(data) => return Ok(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn get_range(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        let paths = self.candidate_paths(hash);
+        tokio::task::spawn_blocking(move || read_range_any(&paths, offset, len))
+            .await
+            .map_err(|e| StoreError::Other(e.to_string()))?
     }
 
     async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
-        Ok(self.exists(hash))
+        for path in self.candidate_paths(hash) {
+            if tokio::fs::try_exists(path).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
     async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
@@ -355,7 +662,42 @@ impl Store for FsBlobStore {
             pins.remove(&hex);
         }
         let _ = self.save_pins(); // Best effort
-        self.delete_sync(hash)
+        let mut deleted = false;
+        for path in self.candidate_paths(hash) {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => deleted = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn get_many(&self, hashes: &[Hash]) -> Result<Vec<Option<Vec<u8>>>, StoreError> {
+        // Paths are worked out here; the reads block, so they run off the runtime
+        let paths: Vec<_> = hashes
+            .iter()
+            .map(|hash| self.candidate_paths(hash))
+            .collect();
+        tokio::task::spawn_blocking(move || {
+            parallel_map(&paths, |paths| read_any(paths))
+                .into_iter()
+                .collect()
+        })
+        .await
+        .map_err(|e| StoreError::Other(e.to_string()))?
+    }
+
+    async fn has_many(&self, hashes: &[Hash]) -> Result<Vec<bool>, StoreError> {
+        let paths: Vec<_> = hashes
+            .iter()
+            .map(|hash| self.candidate_paths(hash))
+            .collect();
+        tokio::task::spawn_blocking(move || {
+            parallel_map(&paths, |paths| paths.iter().any(|path| path.exists()))
+        })
+        .await
+        .map_err(|e| StoreError::Other(e.to_string()))
     }
 
     fn set_max_bytes(&self, max: u64) {
@@ -453,6 +795,26 @@ mod tests {
         assert_eq!(store.get(&hash).await.unwrap(), Some(data.to_vec()));
     }
 
+    #[tokio::test]
+    async fn test_get_range() {
+        let temp = TempDir::new().unwrap();
+        let store = FsBlobStore::new(temp.path().join("blobs")).unwrap();
+
+        let data = b"hello filesystem";
+        let hash = sha256(data);
+        store.put(hash, data.to_vec()).await.unwrap();
+
+        let range = |offset, len| store.get_range(&hash, offset, len);
+        assert_eq!(range(6, 4).await.unwrap(), Some(b"file".to_vec()));
+        assert_eq!(range(6, 100).await.unwrap(), Some(b"filesystem".to_vec()));
+        assert_eq!(range(100, 4).await.unwrap(), Some(vec![]));
+        assert_eq!(
+            store.get_range_sync(&hash, 0, 5).unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(store.get_range(&[0u8; 32], 0, 4).await.unwrap(), None);
+    }
+
     #[tokio::test]
     async fn test_get_missing() {
         let temp = TempDir::new().unwrap();
@@ -554,9 +916,7 @@ mod tests {
         store.put(hash, data.to_vec()).await.unwrap();
 
         // Verify the file exists at the correct path
-        let prefix = &hex[..2];
-        let rest = &hex[2..];
-        let expected_path = blobs_path.join(prefix).join(rest);
+        let expected_path = blobs_path.join(&hex[..2]).join(&hex[2..4]).join(&hex[4..]);
 
         assert!(expected_path.exists(), "Blob should be at {:?}", expected_path);
         assert_eq!(fs::read(&expected_path).unwrap(), data);
@@ -576,10 +936,119 @@ mod tests {
         let path = store.blob_path(&hash);
         let path_str = path.to_string_lossy();
 
-        // Should have "00" as directory prefix
-        assert!(path_str.contains("/00/"), "Path should contain /00/ directory: {}", path_str);
-        // File name should be remaining 62 chars
-        assert!(path.file_name().unwrap().len() == 62);
+        // Should have "00/11" as directory prefix
+        assert!(
+            path_str.contains("/00/11/"),
+            "Path should contain /00/11/ directories: {}",
+            path_str
+        );
+        // File name should be remaining 60 chars
+        assert!(path.file_name().unwrap().len() == 60);
+    }
+
+    #[tokio::test]
+    async fn test_migrates_legacy_layouts() {
+        let temp = TempDir::new().unwrap();
+        let blobs_path = temp.path().join("blobs");
+        fs::create_dir_all(&blobs_path).unwrap();
+
+        // One blob in the flat layout, one in the single-level layout
+        let (flat, sharded) = (b"flat blob", b"one-level blob");
+        let flat_hex = hex::encode(sha256(flat));
+        let sharded_hex = hex::encode(sha256(sharded));
+        fs::write(blobs_path.join(&flat_hex), flat).unwrap();
+        fs::create_dir_all(blobs_path.join(&sharded_hex[..2])).unwrap();
+        let legacy_path = blobs_path.join(&sharded_hex[..2]).join(&sharded_hex[2..]);
+        fs::write(legacy_path, sharded).unwrap();
+        fs::write(blobs_path.join("pins.json"), "{}").unwrap();
+
+        let store = FsBlobStore::new(&blobs_path).unwrap();
+        assert_eq!(store.get(&sha256(flat)).await.unwrap(), Some(flat.to_vec()));
+        assert_eq!(
+            store.get(&sha256(sharded)).await.unwrap(),
+            Some(sharded.to_vec())
+        );
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        for _ in 0..500 {
+            if !store.migrating.load(Ordering::Acquire) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!store.migrating.load(Ordering::Acquire));
+        assert!(!blobs_path.join(&flat_hex).exists());
+        assert!(blobs_path.join("pins.json").exists());
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        // Deleting a blob removes it for good
+        assert!(store.delete(&sha256(flat)).await.unwrap());
+        assert!(!store.has(&sha256(flat)).await.unwrap());
+
+        // Reopening doesn't rescan once the layout is recorded
+        let layout = fs::read_to_string(blobs_path.join(LAYOUT_FILE)).unwrap();
+        assert_eq!(layout, LAYOUT_VERSION);
+        let store = FsBlobStore::new(&blobs_path).unwrap();
+        assert!(!store.migrating.load(Ordering::Acquire));
+        assert_eq!(store.list().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fsync_policies_store_blobs() {
+        for policy in [FsyncPolicy::Never, FsyncPolicy::Data, FsyncPolicy::Full] {
+            let temp = TempDir::new().unwrap();
+            let store = FsBlobStore::new(temp.path().join("blobs"))
+                .unwrap()
+                .with_fsync_policy(policy);
+
+            let data = b"durable";
+            assert!(store.put(sha256(data), data.to_vec()).await.unwrap());
+            assert!(store.put_sync(sha256(b"sync"), b"sync").unwrap());
+            assert_eq!(store.get(&sha256(data)).await.unwrap(), Some(data.to_vec()));
+            assert_eq!(store.list().unwrap().len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrub_quarantines_corrupted_blobs() {
+        let temp = TempDir::new().unwrap();
+        let store = FsBlobStore::new(temp.path().join("blobs")).unwrap();
+
+        let good = b"intact";
+        let bad = b"flipped";
+        store.put(sha256(good), good.to_vec()).await.unwrap();
+        store.put(sha256(bad), bad.to_vec()).await.unwrap();
+        fs::write(store.blob_path(&sha256(bad)), b"fl1pped").unwrap();
+
+        let mut seen = 0;
+        let report = store.scrub(ScrubAction::Report, |_| seen += 1).unwrap();
+        assert_eq!((report.checked, report.bytes, seen), (2, 13, 2));
+        assert_eq!(report.corrupted, vec![sha256(bad)]);
+        assert!(store.exists(&sha256(bad)));
+
+        let report = store.scrub(ScrubAction::Quarantine, |_| {}).unwrap();
+        assert_eq!(report.corrupted, vec![sha256(bad)]);
+        assert!(!store.exists(&sha256(bad)));
+        let quarantined = temp
+            .path()
+            .join("blobs")
+            .join(QUARANTINE_DIR)
+            .join(hex::encode(sha256(bad)));
+        assert_eq!(fs::read(quarantined).unwrap(), b"fl1pped");
+
+        // Quarantined blobs are out of the store
+        let report = store.scrub(ScrubAction::Delete, |_| {}).unwrap();
+        assert_eq!(report.checked, 1);
+        assert!(report.corrupted.is_empty());
+        assert_eq!(store.list().unwrap(), vec![sha256(good)]);
+
+        // A blob that can't be read isn't taken for a corrupted one
+        let unreadable = sha256(b"unreadable");
+        fs::create_dir_all(store.blob_path(&unreadable)).unwrap();
+        let report = store.scrub(ScrubAction::Delete, |_| {}).unwrap();
+        assert!(report.corrupted.is_empty());
+        assert_eq!(report.unreadable, vec![unreadable]);
+        assert!(store.blob_path(&unreadable).exists());
     }
 
     #[tokio::test]
@@ -788,4 +1257,29 @@ mod tests {
         store.delete(&hash).await.unwrap();
         assert_eq!(store.pin_count(&hash), 0);
     }
+
+    #[tokio::test]
+    async fn test_batch_operations_keep_input_order() {
+        let temp = TempDir::new().unwrap();
+        let store = FsBlobStore::new(temp.path().join("blobs")).unwrap();
+
+        // Large enough to take the parallel path; every third blob is missing
+        let blobs: Vec<Vec<u8>> = (0..100u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let hashes: Vec<Hash> = blobs.iter().map(|b| sha256(b)).collect();
+        let stored: Vec<(Hash, Vec<u8>)> = hashes
+            .iter()
+            .zip(&blobs)
+            .enumerate()
+            .filter(|(i, _)| i % 3 != 0)
+            .map(|(_, (hash, data))| (*hash, data.clone()))
+            .collect();
+        store.put_many(stored).await.unwrap();
+
+        let present = store.has_many(&hashes).await.unwrap();
+        let data = store.get_many(&hashes).await.unwrap();
+        for (i, blob) in blobs.iter().enumerate() {
+            assert_eq!(present[i], i % 3 != 0);
+            assert_eq!(data[i].as_ref(), (i % 3 != 0).then_some(blob));
+        }
+    }
 }
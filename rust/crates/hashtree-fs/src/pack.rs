@@ -0,0 +1,616 @@
+//! Packfile blob store.
+//!
+//! Millions of tiny tree nodes as individual files waste inodes and most of
+//! each filesystem block, so small blobs are appended to a single
+//! `pack.dat` instead, and only blobs above the inline limit get their own
+//! file in an [`FsBlobStore`] under `large/`.
+//!
+//! Pack records are `[hash: 32][len: u32 LE][data]`. A delete appends a
+//! tombstone record (`len == u32::MAX`, no data); [`PackBlobStore::compact`]
+//! rewrites the pack without dead records. The index is rebuilt by scanning
+//! the pack on open. A record cut short by a crash stops the store from
+//! opening until [`PackBlobStore::recover`] sets the tail aside, rather
+//! than truncating what may be damaged data. Reads are positional, so they
+//! only take the lock to look up a blob's slot, and check the data they read
+//! against its hash.
+//!
+//! A daemon and CLI commands may have the same pack open. Writers take an
+//! exclusive lock on `pack.dat`, then index what others appended since and
+//! append at the real end of the file; a write that fails partway is cut
+//! off again.
+
+use async_trait::async_trait;
+use hashtree_core::sha256;
+use hashtree_core::store::{Store, StoreError, StoreStats};
+use hashtree_core::types::{to_hex, Hash};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::FsBlobStore;
+
+/// The append-only pack file
+const PACK_FILE: &str = "pack.dat";
+
+/// Directory of blobs too large to inline
+const LARGE_DIR: &str = "large";
+
+/// Blobs up to this size are packed by default
+pub const DEFAULT_INLINE_MAX: usize = 64 * 1024;
+
+/// Record header: hash + length
+const HEADER_LEN: u64 = 36;
+
+/// Length marking a tombstone record
+const TOMBSTONE: u32 = u32::MAX;
+
+/// Location of a packed blob's data
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: u64,
+    len: u32,
+}
+
+struct Pack {
+    path: PathBuf,
+    /// Opened for appending; reads are positional, so they share it
+    /// without a lock once they have their slot
+    file: Arc<File>,
+    /// End of the last complete record
+    len: u64,
+    index: HashMap<Hash, Slot>,
+    /// Bytes taken by deleted records and their tombstones
+    dead_bytes: u64,
+}
+
+/// Exclusive lock on the pack file, released on drop
+struct WriteLock(Arc<File>);
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+impl Pack {
+    /// Open the pack and index it. A record running past the end of the
+    /// file is an error: it may be a torn write, but also damage to data
+    /// after it, so it's left for [`PackBlobStore::recover`] to set aside.
+    fn open(path: &Path) -> Result<Self, StoreError> {
+        let (pack, file_len) = Self::scan(path)?;
+        pack.check_complete(file_len)?;
+        Ok(pack)
+    }
+
+    /// Index the complete records, returning the pack and the file length
+    fn scan(path: &Path) -> Result<(Self, u64), StoreError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut pack = Self {
+            path: path.to_path_buf(),
+            file: Arc::new(file),
+            len: 0,
+            index: HashMap::new(),
+            dead_bytes: 0,
+        };
+        pack.index_to(file_len)?;
+        Ok((pack, file_len))
+    }
+
+    fn check_complete(&self, file_len: u64) -> Result<(), StoreError> {
+        if self.len < file_len {
+            return Err(StoreError::Other(format!(
+                "{}: {} bytes after the last complete record at {}; \
+                 run recovery to set them aside",
+                self.path.display(),
+                file_len - self.len,
+                self.len
+            )));
+        }
+        Ok(())
+    }
+
+    /// Index the complete records between `len` and `file_len`
+    fn index_to(&mut self, file_len: u64) -> Result<(), StoreError> {
+        let mut reader = BufReader::new(&*self.file);
+        reader.seek(SeekFrom::Start(self.len))?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        while self.len + HEADER_LEN <= file_len {
+            reader.read_exact(&mut header)?;
+            let hash: Hash = header[..32].try_into().unwrap();
+            let record_len = u32::from_le_bytes(header[32..].try_into().unwrap());
+
+            if record_len == TOMBSTONE {
+                if let Some(slot) = self.index.remove(&hash) {
+                    self.dead_bytes += HEADER_LEN + slot.len as u64;
+                }
+                self.dead_bytes += HEADER_LEN;
+                self.len += HEADER_LEN;
+                continue;
+            }
+
+            let end = self.len + HEADER_LEN + record_len as u64;
+            if end > file_len {
+                break;
+            }
+            reader.seek_relative(record_len as i64)?;
+            let slot = Slot {
+                offset: self.len + HEADER_LEN,
+                len: record_len,
+            };
+            if let Some(old) = self.index.insert(hash, slot) {
+                self.dead_bytes += HEADER_LEN + old.len as u64;
+            }
+            self.len = end;
+        }
+        Ok(())
+    }
+
+    /// Whether the open file is still the one at `path`, rather than one
+    /// another process compacted away
+    fn is_current(&self) -> io::Result<bool> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let (open, on_disk) = (self.file.metadata()?, fs::metadata(&self.path)?);
+            Ok(open.dev() == on_disk.dev() && open.ino() == on_disk.ino())
+        }
+        #[cfg(not(unix))]
+        Ok(true)
+    }
+
+    /// Lock the pack file for writing. Other processes may share the pack,
+    /// so first switch to the file they compacted it into, if they did,
+    /// and index the records they appended.
+    fn lock(&mut self) -> Result<WriteLock, StoreError> {
+        loop {
+            self.file.lock()?;
+            let lock = WriteLock(self.file.clone());
+            if self.is_current()? {
+                let file_len = self.file.metadata()?.len();
+                if file_len < self.len {
+                    // Cut by recovery in another process
+                    drop(lock);
+                    *self = Self::open(&self.path)?;
+                    continue;
+                }
+                self.index_to(file_len)?;
+                self.check_complete(file_len)?;
+                return Ok(lock);
+            }
+            drop(lock);
+            *self = Self::open(&self.path)?;
+        }
+    }
+
+    /// Append a record, holding the lock from [`Pack::lock`]
+    fn append(&mut self, hash: &Hash, len: u32, data: &[u8]) -> Result<u64, StoreError> {
+        let mut record = Vec::with_capacity(HEADER_LEN as usize + data.len());
+        record.extend_from_slice(hash);
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(data);
+        if let Err(e) = (&*self.file).write_all(&record) {
+            // Cut what got written, so the next record starts where it's indexed
+            let _ = self.file.set_len(self.len);
+            return Err(e.into());
+        }
+
+        let data_offset = self.len + HEADER_LEN;
+        self.len += record.len() as u64;
+        Ok(data_offset)
+    }
+}
+
+/// Record length of a blob, if it fits a record
+fn record_len(len: usize) -> Result<u32, StoreError> {
+    u32::try_from(len)
+        .ok()
+        .filter(|&len| len != TOMBSTONE)
+        .ok_or_else(|| StoreError::Other(format!("{} bytes is too large to pack", len)))
+}
+
+/// Read a packed blob, checking it against its hash
+fn read_blob(file: &File, hash: &Hash, slot: Slot) -> Result<Vec<u8>, StoreError> {
+    let data = read_slot(file, slot)?;
+    if sha256(&data) != *hash {
+        return Err(StoreError::Other(format!(
+            "Packed blob {} at offset {} doesn't match its hash",
+            to_hex(hash),
+            slot.offset
+        )));
+    }
+    Ok(data)
+}
+
+/// Read a slot's data without moving a shared cursor
+fn read_slot(file: &File, slot: Slot) -> Result<Vec<u8>, StoreError> {
+    let mut data = vec![0u8; slot.len as usize];
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(&mut data, slot.offset)?;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        let mut read = 0;
+        while read < data.len() {
+            match file.seek_read(&mut data[read..], slot.offset + read as u64)? {
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                n => read += n,
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Blob store packing small blobs into one file, implementing hashtree's
+/// Store trait.
+pub struct PackBlobStore {
+    base_path: PathBuf,
+    pack: Mutex<Pack>,
+    large: FsBlobStore,
+    inline_max: usize,
+}
+
+impl PackBlobStore {
+    /// Open or create a packfile store at the given path.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let base_path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&base_path)?;
+        let pack = Pack::open(&base_path.join(PACK_FILE))?;
+        let large = FsBlobStore::new(base_path.join(LARGE_DIR))?;
+
+        Ok(Self {
+            base_path,
+            pack: Mutex::new(pack),
+            large,
+            inline_max: DEFAULT_INLINE_MAX,
+        })
+    }
+
+    /// Set the largest blob stored in the pack (default 64 KiB)
+    pub fn with_inline_max(mut self, inline_max: usize) -> Self {
+        self.inline_max = inline_max;
+        self
+    }
+
+    /// Sync put operation.
+    pub fn put_sync(&self, hash: Hash, data: &[u8]) -> Result<bool, StoreError> {
+        if data.len() > self.inline_max {
+            return self.large.put_sync(hash, data);
+        }
+
+        let len = record_len(data.len())?;
+        let mut pack = self.pack.lock().unwrap();
+        if pack.index.contains_key(&hash) || self.large.exists(&hash) {
+            return Ok(false);
+        }
+        let _lock = pack.lock()?;
+        // Another process may have added it
+        if pack.index.contains_key(&hash) {
+            return Ok(false);
+        }
+        let offset = pack.append(&hash, len, data)?;
+        pack.index.insert(hash, Slot { offset, len });
+        Ok(true)
+    }
+
+    /// Recover a pack [`PackBlobStore::new`] refused to open: bytes after
+    /// the last complete record are moved to `pack.dat.<offset>.tail` and
+    /// cut from the pack. Returns how many bytes were set aside.
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<u64, StoreError> {
+        let pack_path = path.as_ref().join(PACK_FILE);
+        let (pack, file_len) = Pack::scan(&pack_path)?;
+        if pack.len == file_len {
+            return Ok(0);
+        }
+
+        let mut tail = vec![0u8; (file_len - pack.len) as usize];
+        let mut file = &*pack.file;
+        file.seek(SeekFrom::Start(pack.len))?;
+        file.read_exact(&mut tail)?;
+        let tail_path = pack_path.with_extension(format!("dat.{}.tail", pack.len));
+        fs::write(&tail_path, &tail)?;
+        File::open(&tail_path)?.sync_all()?;
+        pack.file.set_len(pack.len)?;
+        Ok(tail.len() as u64)
+    }
+
+    /// Sync get operation.
+    pub fn get_sync(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        let packed = {
+            let pack = self.pack.lock().unwrap();
+            pack.index
+                .get(hash)
+                .map(|slot| (pack.file.clone(), *slot))
+        };
+        match packed {
+            Some((file, slot)) => read_blob(&file, hash, slot).map(Some),
+            None => self.large.get_sync(hash),
+        }
+    }
+
+    /// Check if a hash exists.
+    pub fn exists(&self, hash: &Hash) -> bool {
+        self.pack.lock().unwrap().index.contains_key(hash) || self.large.exists(hash)
+    }
+
+    /// Sync delete operation.
+    pub fn delete_sync(&self, hash: &Hash) -> Result<bool, StoreError> {
+        {
+            let mut pack = self.pack.lock().unwrap();
+            let _lock = pack.lock()?;
+            if let Some(slot) = pack.index.get(hash).copied() {
+                pack.append(hash, TOMBSTONE, &[])?;
+                pack.index.remove(hash);
+                pack.dead_bytes += 2 * HEADER_LEN + slot.len as u64;
+                return Ok(true);
+            }
+        }
+        self.large.delete_sync(hash)
+    }
+
+    /// List all hashes in the store.
+    pub fn list(&self) -> Result<Vec<Hash>, StoreError> {
+        let mut hashes: Vec<Hash> = self.pack.lock().unwrap().index.keys().copied().collect();
+        hashes.extend(self.large.list()?);
+        Ok(hashes)
+    }
+
+    /// Get storage statistics.
+    pub fn stats(&self) -> Result<PackStats, StoreError> {
+        let large = self.large.stats()?;
+        let pack = self.pack.lock().unwrap();
+        let packed_bytes = pack.index.values().map(|slot| slot.len as u64).sum();
+        Ok(PackStats {
+            packed_count: pack.index.len(),
+            packed_bytes,
+            large_count: large.count,
+            large_bytes: large.total_bytes,
+            pack_file_bytes: pack.len,
+            dead_bytes: pack.dead_bytes,
+        })
+    }
+
+    /// Rewrite the pack without deleted records, returning the bytes freed.
+    pub fn compact(&self) -> Result<u64, StoreError> {
+        let mut pack = self.pack.lock().unwrap();
+        let _lock = pack.lock()?;
+        if pack.dead_bytes == 0 {
+            return Ok(0);
+        }
+
+        let pack_path = self.base_path.join(PACK_FILE);
+        let temp_path = pack_path.with_extension("tmp");
+        let mut slots: Vec<(Hash, Slot)> = pack.index.iter().map(|(h, s)| (*h, *s)).collect();
+        // Read in file order
+        slots.sort_by_key(|(_, slot)| slot.offset);
+
+        {
+            let mut out = io::BufWriter::new(File::create(&temp_path)?);
+            for (hash, slot) in &slots {
+                let data = read_blob(&pack.file, hash, *slot)?;
+                out.write_all(hash)?;
+                out.write_all(&slot.len.to_le_bytes())?;
+                out.write_all(&data)?;
+            }
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        }
+        fs::rename(&temp_path, &pack_path)?;
+
+        let freed = pack.len;
+        *pack = Pack::open(&pack_path)?;
+        Ok(freed - pack.len)
+    }
+}
+
+/// Packfile store statistics.
+#[derive(Debug, Clone)]
+pub struct PackStats {
+    /// Blobs stored in the pack
+    pub packed_count: usize,
+    pub packed_bytes: u64,
+    /// Blobs stored as their own files
+    pub large_count: usize,
+    pub large_bytes: u64,
+    /// Size of pack.dat, including record headers and dead records
+    pub pack_file_bytes: u64,
+    /// Bytes `compact` would reclaim
+    pub dead_bytes: u64,
+}
+
+impl PackStats {
+    pub fn count(&self) -> usize {
+        self.packed_count + self.large_count
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.packed_bytes + self.large_bytes
+    }
+}
+
+#[async_trait]
+impl Store for PackBlobStore {
+    async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.put_sync(hash, &data)
+    }
+
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        self.get_sync(hash)
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        Ok(self.exists(hash))
+    }
+
+    async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.delete_sync(hash)
+    }
+
+    async fn stats(&self) -> StoreStats {
+        match self.stats() {
+            Ok(stats) => StoreStats {
+                count: stats.count() as u64,
+                bytes: stats.total_bytes(),
+                pinned_count: 0,
+                pinned_bytes: 0,
+            },
+            Err(_) => StoreStats::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::sha256;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_small_blobs_are_packed() {
+        let temp = TempDir::new().unwrap();
+        let store = PackBlobStore::new(temp.path()).unwrap().with_inline_max(16);
+
+        let small = b"tree node";
+        let large = vec![7u8; 100];
+        assert!(store.put(sha256(small), small.to_vec()).await.unwrap());
+        assert!(store.put(sha256(&large), large.clone()).await.unwrap());
+        assert!(!store.put(sha256(small), small.to_vec()).await.unwrap());
+
+        assert_eq!(
+            store.get(&sha256(small)).await.unwrap(),
+            Some(small.to_vec())
+        );
+        assert_eq!(store.get(&sha256(&large)).await.unwrap(), Some(large));
+        assert!(!store.has(&[0u8; 32]).await.unwrap());
+
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.packed_count, stats.large_count), (1, 1));
+        assert_eq!(store.list().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_index_survives_reopen_and_torn_writes() {
+        let temp = TempDir::new().unwrap();
+        let (a, b) = (b"first", b"second");
+        {
+            let store = PackBlobStore::new(temp.path()).unwrap();
+            store.put(sha256(a), a.to_vec()).await.unwrap();
+            store.put(sha256(b), b.to_vec()).await.unwrap();
+            store.delete(&sha256(a)).await.unwrap();
+        }
+
+        // Simulate a crash halfway through appending a record
+        let pack_path = temp.path().join(PACK_FILE);
+        let mut file = OpenOptions::new().append(true).open(&pack_path).unwrap();
+        file.write_all(&[0xab; 40]).unwrap();
+        let len_before = fs::metadata(&pack_path).unwrap().len();
+
+        // Nothing is cut without asking
+        assert!(PackBlobStore::new(temp.path()).is_err());
+        assert_eq!(fs::metadata(&pack_path).unwrap().len(), len_before);
+        assert_eq!(PackBlobStore::recover(temp.path()).unwrap(), 40);
+        assert_eq!(PackBlobStore::recover(temp.path()).unwrap(), 0);
+        let tail = pack_path.with_extension(format!("dat.{}.tail", len_before - 40));
+        assert_eq!(fs::read(tail).unwrap(), vec![0xab; 40]);
+
+        let store = PackBlobStore::new(temp.path()).unwrap();
+        assert!(!store.has(&sha256(a)).await.unwrap());
+        assert_eq!(store.get(&sha256(b)).await.unwrap(), Some(b.to_vec()));
+        assert_eq!(fs::metadata(&pack_path).unwrap().len(), len_before - 40);
+
+        // Appends after recovery land after the last good record
+        let c = b"third";
+        store.put(sha256(c), c.to_vec()).await.unwrap();
+        let store = PackBlobStore::new(temp.path()).unwrap();
+        assert_eq!(store.get(&sha256(c)).await.unwrap(), Some(c.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_compact_drops_deleted_records() {
+        let temp = TempDir::new().unwrap();
+        let store = PackBlobStore::new(temp.path()).unwrap();
+
+        let blobs: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; 10]).collect();
+        for blob in &blobs {
+            store.put(sha256(blob), blob.clone()).await.unwrap();
+        }
+        for blob in &blobs[..5] {
+            assert!(store.delete(&sha256(blob)).await.unwrap());
+        }
+        assert!(!store.delete(&sha256(&blobs[0])).await.unwrap());
+
+        let dead = store.stats().unwrap().dead_bytes;
+        assert_eq!(dead, 5 * (2 * HEADER_LEN + 10));
+        assert_eq!(store.compact().unwrap(), dead);
+
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.dead_bytes, stats.packed_count), (0, 5));
+        assert_eq!(stats.pack_file_bytes, 5 * (HEADER_LEN + 10));
+        for blob in &blobs[5..] {
+            assert_eq!(store.get(&sha256(blob)).await.unwrap(), Some(blob.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_processes_sharing_a_pack() {
+        let temp = TempDir::new().unwrap();
+        let daemon = PackBlobStore::new(temp.path()).unwrap();
+        let cli = PackBlobStore::new(temp.path()).unwrap();
+
+        let (a, b, c) = (b"from daemon", b"from cli", b"daemon again");
+        daemon.put(sha256(a), a.to_vec()).await.unwrap();
+        cli.put(sha256(b), b.to_vec()).await.unwrap();
+        daemon.put(sha256(c), c.to_vec()).await.unwrap();
+        // The other's appends are indexed before writing
+        assert!(!cli.put(sha256(a), a.to_vec()).await.unwrap());
+
+        for blob in [&a[..], &b[..], &c[..]] {
+            assert_eq!(
+                daemon.get(&sha256(blob)).await.unwrap(),
+                Some(blob.to_vec())
+            );
+        }
+        let reopened = PackBlobStore::new(temp.path()).unwrap();
+        assert_eq!(reopened.list().unwrap().len(), 3);
+
+        // Writes after another process compacted go to the new pack
+        daemon.delete(&sha256(a)).await.unwrap();
+        assert!(daemon.compact().unwrap() > 0);
+        let d = b"after compaction";
+        cli.put(sha256(d), d.to_vec()).await.unwrap();
+        let reopened = PackBlobStore::new(temp.path()).unwrap();
+        assert_eq!(reopened.get(&sha256(d)).await.unwrap(), Some(d.to_vec()));
+        assert!(!reopened.has(&sha256(a)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_records_fail_to_read() {
+        let temp = TempDir::new().unwrap();
+        let store = PackBlobStore::new(temp.path()).unwrap();
+        let blob = b"some tree node";
+        store.put(sha256(blob), blob.to_vec()).await.unwrap();
+
+        let pack_path = temp.path().join(PACK_FILE);
+        let mut data = fs::read(&pack_path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        fs::write(&pack_path, data).unwrap();
+
+        assert!(store.get(&sha256(blob)).await.is_err());
+    }
+
+    #[test]
+    fn test_record_len_limit() {
+        assert_eq!(record_len(10).unwrap(), 10);
+        assert!(record_len(TOMBSTONE as usize).is_err());
+        assert!(record_len(u32::MAX as usize + 1).is_err());
+    }
+}
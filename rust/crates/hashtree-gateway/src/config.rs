@@ -0,0 +1,58 @@
+//! Gateway configuration
+
+use hashtree_config::{DEFAULT_READ_SERVERS, DEFAULT_RELAYS};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Settings for a gateway instance
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    /// Address to listen on, e.g. "0.0.0.0:8080"
+    pub listen: String,
+    /// Directory for the local blob cache
+    pub data_dir: PathBuf,
+    /// Nostr relays used to resolve npub tree roots
+    pub relays: Vec<String>,
+    /// Blossom servers blobs are fetched from
+    pub blossom_servers: Vec<String>,
+    /// Max bytes of fetched blobs kept on disk
+    pub cache_max_bytes: u64,
+    /// Number of resolved tree roots kept in memory
+    pub root_cache_entries: usize,
+    /// How long a resolved npub root is served before re-resolving
+    pub root_ttl: Duration,
+    /// Per-client request limit; None disables rate limiting
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// Allow `requests` per client IP in each `window`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    pub fn per_minute(requests: u32) -> Self {
+        Self {
+            requests,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+impl GatewayConfig {
+    /// Config with defaults, caching blobs under `data_dir`
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            listen: "0.0.0.0:8080".to_string(),
+            data_dir: data_dir.into(),
+            relays: DEFAULT_RELAYS.iter().map(|s| s.to_string()).collect(),
+            blossom_servers: DEFAULT_READ_SERVERS.iter().map(|s| s.to_string()).collect(),
+            cache_max_bytes: 1024 * 1024 * 1024, // 1 GB
+            root_cache_entries: 1000,
+            root_ttl: Duration::from_secs(60),
+            rate_limit: Some(RateLimit::per_minute(600)),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Path, query, MIME and range helpers for htree URLs
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Process-wide id tagging one request's tracing span
+pub fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// npub pattern: npub1 followed by 58 bech32 characters
+pub fn is_npub(s: &str) -> bool {
+    s.len() == 63 && s.starts_with("npub1") && s.chars().skip(5).all(|c| c.is_ascii_alphanumeric())
+}
+
+/// URL-decode a string (percent-decode)
+pub fn url_decode(s: &str) -> String {
+    percent_encoding::percent_decode_str(s)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Most segments in a path below a tree root, or in a tree name
+pub const MAX_PATH_SEGMENTS: usize = 64;
+
+/// A decoded path below a tree root, or a tree name, with empty and `.`
+/// segments dropped. `..`, control characters and paths over
+/// `MAX_PATH_SEGMENTS` deep are refused: trees have no parent links, so
+/// such paths only come from crafted or mangled URLs.
+pub fn normalize_path(path: &str) -> Result<String, String> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Err(format!("Parent directory in path: {}", path)),
+            _ if segment.chars().any(char::is_control) => {
+                return Err(format!("Control character in path: {:?}", path))
+            }
+            _ => segments.push(segment),
+        }
+        if segments.len() > MAX_PATH_SEGMENTS {
+            return Err(format!("Path deeper than {} segments", MAX_PATH_SEGMENTS));
+        }
+    }
+    Ok(segments.join("/"))
+}
+
+/// Get a (raw, not decoded) query parameter value
+pub fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Guess MIME type from file path/extension
+pub fn guess_mime_type(path: &str) -> &'static str {
+    let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        // Video
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "ogg" | "ogv" => "video/ogg",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "mkv" => "video/x-matroska",
+        // Audio
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "m4a" | "aac" => "audio/mp4",
+        "oga" => "audio/ogg",
+        // Images
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        // Documents
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "vtt" => "text/vtt",
+        "srt" => "application/x-subrip",
+        // Archives
+        "zip" => "application/zip",
+        "tar" => "application/x-tar",
+        "gz" => "application/gzip",
+        // Code
+        "ts" | "tsx" => "text/typescript",
+        "jsx" => "text/javascript",
+        "py" => "text/x-python",
+        "rs" => "text/x-rust",
+        "go" => "text/x-go",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether `s` is a `type/subtype` MIME type, optionally with parameters,
+/// fit to serve as a `Content-Type`
+pub fn is_mime_type(s: &str) -> bool {
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&^_.+-".contains(c))
+    };
+    let (essence, params) = s.split_once(';').unwrap_or((s, ""));
+    essence
+        .split_once('/')
+        .is_some_and(|(kind, subtype)| is_token(kind) && is_token(subtype))
+        && params.chars().all(|c| c == ' ' || c.is_ascii_graphic())
+}
+
+/// `Content-Disposition` value suggesting `filename` for a download: an
+/// ASCII `filename` for old clients and the exact name as `filename*`
+pub fn content_disposition(filename: &str) -> String {
+    const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+        .remove(b'-')
+        .remove(b'.')
+        .remove(b'_')
+        .remove(b'~');
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or(filename);
+    let ascii: String = name
+        .chars()
+        .map(|c| match c {
+            ' ' => ' ',
+            '"' | '\\' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "inline; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii,
+        utf8_percent_encode(name, ATTR_CHAR)
+    )
+}
+
+/// Parse Range header value like "bytes=0-999" or "bytes=500-"
+pub fn parse_range_header(range_header: &str, total_size: usize) -> Option<(usize, usize)> {
+    if total_size == 0 {
+        return None;
+    }
+    let range = range_header.strip_prefix("bytes=")?;
+    let parts: Vec<&str> = range.split('-').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+
+    let start: usize = if parts[0].is_empty() {
+        // Suffix range like "-500" means last 500 bytes
+        let suffix_len: usize = parts[1].parse().ok()?;
+        total_size.saturating_sub(suffix_len)
+    } else {
+        parts[0].parse().ok()?
+    };
+
+    let end: usize = if parts[1].is_empty() {
+        // Open-ended range like "500-" means from 500 to end
+        total_size - 1
+    } else {
+        parts[1].parse().ok()?
+    };
+
+    // Validate range
+    if start > end || start >= total_size {
+        return None;
+    }
+
+    // Clamp end to file size
+    let end = end.min(total_size - 1);
+
+    Some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_npub() {
+        let npub = format!("npub1{}", "q".repeat(58));
+        assert!(is_npub(&npub));
+        assert!(!is_npub("npub1short"));
+        assert!(!is_npub(&format!("nhash1{}", "q".repeat(57))));
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param(Some("format=hls"), "format"), Some("hls"));
+        assert_eq!(query_param(Some("a=1&proof=1&b"), "proof"), Some("1"));
+        assert_eq!(query_param(Some("a=1&b"), "b"), Some(""));
+        assert_eq!(query_param(Some("a=1"), "format"), None);
+        assert_eq!(query_param(None, "format"), None);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("").unwrap(), "");
+        assert_eq!(normalize_path("/docs//./a b.txt/").unwrap(), "docs/a b.txt");
+        assert_eq!(normalize_path("v1..2/notes").unwrap(), "v1..2/notes");
+        assert!(normalize_path("docs/../secret").is_err());
+        assert!(normalize_path("..").is_err());
+        assert!(normalize_path("a\u{0}b").is_err());
+        assert!(normalize_path("a\nb").is_err());
+        let deep = ["d"; MAX_PATH_SEGMENTS];
+        assert!(normalize_path(&deep.join("/")).is_ok());
+        assert!(normalize_path(&format!("{}/e", deep.join("/"))).is_err());
+    }
+
+    #[test]
+    fn test_parse_range_header() {
+        assert_eq!(parse_range_header("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range_header("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range_header("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range_header("bytes=50-500", 100), Some((50, 99)));
+        assert_eq!(parse_range_header("bytes=100-", 100), None);
+        assert_eq!(parse_range_header("bytes=9-0", 100), None);
+        assert_eq!(parse_range_header("items=0-9", 100), None);
+        assert_eq!(parse_range_header("bytes=0-9", 0), None);
+    }
+
+    #[test]
+    fn test_guess_mime_type() {
+        assert_eq!(guess_mime_type("index.html"), "text/html");
+        assert_eq!(guess_mime_type("videos/clip.MP4"), "video/mp4");
+        assert_eq!(guess_mime_type("README"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_is_mime_type() {
+        assert!(is_mime_type("image/png"));
+        assert!(is_mime_type("text/html; charset=utf-8"));
+        assert!(is_mime_type("application/vnd.apple.mpegurl"));
+        assert!(!is_mime_type("text"));
+        assert!(!is_mime_type("text/"));
+        assert!(!is_mime_type("text/html\r\nX-Evil: 1"));
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("report.pdf"),
+            "inline; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+        );
+        assert_eq!(
+            content_disposition("../my \"café\".txt"),
+            "inline; filename=\"my _caf__.txt\"; filename*=UTF-8''my%20%22caf%C3%A9%22.txt"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Read-only HTTP gateway for hashtree
+//!
+//! Serves npub and nhash paths over HTTP the way the Iris Files app and its
+//! service worker do, without any signing or write routes, so it can run
+//! headless on a public server.
+//!
+//! Routes:
+//! - /htree/{npub}/{treeName}/{path} - Npub-based file access (mutable)
+//! - /htree/{nhash}/{filename} - Direct nhash access (content-addressed)
+//! - /htree/{npub}/{treeName}/...?k={secret} - link-visible tree opened via its share URL
+//! - /htree/...?proof=1 - JSON Merkle proof of the entry against its tree root
+//! - /health - liveness check
+//!
+//! Each request runs in an `htree{id=..}` tracing span, echoed back as
+//! `X-Request-Id`. Npub tree roots are checked against the owner's signed
+//! manifest and the outcome sent as `X-Htree-Signature`; a root whose
+//! manifest doesn't verify is refused with 502.
+//!
+//! The path parsing, MIME and range helpers, the local-then-remote store and
+//! the manifest check are also used by the desktop app's embedded server; build with
+//! `default-features = false` to get them without the HTTP server.
+
+mod config;
+mod http;
+pub mod manifest;
+mod rate_limit;
+#[cfg(feature = "server")]
+mod server;
+mod state;
+mod store;
+
+pub use config::{GatewayConfig, RateLimit};
+pub use http::{
+    content_disposition, guess_mime_type, is_mime_type, is_npub, next_request_id, normalize_path,
+    parse_range_header, query_param, url_decode, MAX_PATH_SEGMENTS,
+};
+pub use rate_limit::RateLimiter;
+#[cfg(feature = "server")]
+pub use server::{router, serve};
+pub use state::{GatewayError, GatewayState, Resolved};
+pub use store::CombinedStore;
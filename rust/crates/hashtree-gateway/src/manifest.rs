@@ -0,0 +1,202 @@
+//! Signed tree manifests
+//!
+//! A tree owner can embed `.htree-manifest.json` in the root directory: a
+//! Nostr event, signed with their key, whose content is the digest of the
+//! root's other entries. When a tree is opened through `npub/tree`, the
+//! signature is checked against that npub, so a relay, root cache or Blossom
+//! server handing out a substituted root for someone else's tree is caught.
+//! Trees without a manifest are still served, reported as unsigned.
+//!
+//! Shared by the gateway and the desktop app's embedded server, which both
+//! check roots with [`check_root`].
+
+use hashtree_core::{
+    encode_and_hash, to_hex, Cid, Hash, HashTree, Link, Store, TreeEntry, TreeNode,
+};
+use nostr::{Event, EventBuilder, Keys, Kind, PublicKey, Tag, TagKind};
+
+/// Name of the manifest entry in a tree's root directory
+pub const MANIFEST_FILENAME: &str = ".htree-manifest.json";
+
+/// `l` label on manifest events
+const MANIFEST_LABEL: &str = "hashtree-manifest";
+
+const KIND_MANIFEST: u16 = 30078;
+
+/// Outcome of checking a tree root against its owner's signature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// Manifest present, signed by the owner and matching the root
+    Verified,
+    /// No manifest in the root
+    Unsigned,
+    /// Manifest present but forged, signed by someone else or stale
+    Invalid,
+}
+
+impl SignatureStatus {
+    /// Value of the `X-Htree-Signature` response header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureStatus::Verified => "verified",
+            SignatureStatus::Unsigned => "unsigned",
+            SignatureStatus::Invalid => "invalid",
+        }
+    }
+}
+
+/// Digest a manifest signs: the hash of the root directory node rebuilt from
+/// every entry except the manifest itself. Independent of encryption and of
+/// how a large directory is chunked.
+pub fn manifest_digest(entries: &[TreeEntry]) -> Result<Hash, String> {
+    let mut links: Vec<Link> = entries
+        .iter()
+        .filter(|e| e.name != MANIFEST_FILENAME)
+        .map(|e| Link {
+            hash: e.hash,
+            name: Some(e.name.clone()),
+            size: e.size,
+            key: e.key,
+            link_type: e.link_type,
+            meta: e.meta.clone(),
+        })
+        .collect();
+    links.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let (_, hash) =
+        encode_and_hash(&TreeNode::dir(links)).map_err(|e| format!("Encode error: {}", e))?;
+    Ok(hash)
+}
+
+/// Sign `digest` with the owner's keys, returning the manifest file contents
+pub fn sign_manifest(keys: &Keys, digest: &Hash) -> Result<Vec<u8>, String> {
+    let tags = vec![
+        Tag::custom(TagKind::custom("d"), vec![MANIFEST_LABEL.to_string()]),
+        Tag::custom(TagKind::custom("l"), vec![MANIFEST_LABEL.to_string()]),
+    ];
+    let event = EventBuilder::new(Kind::from(KIND_MANIFEST), to_hex(digest), tags)
+        .to_event(keys)
+        .map_err(|e| format!("Failed to sign manifest: {}", e))?;
+    serde_json::to_vec(&event).map_err(|e| format!("Failed to encode manifest: {}", e))
+}
+
+/// Check manifest contents against the tree owner and the root's digest
+pub fn verify_manifest(data: &[u8], owner: &PublicKey, digest: &Hash) -> SignatureStatus {
+    let Ok(event) = serde_json::from_slice::<Event>(data) else {
+        return SignatureStatus::Invalid;
+    };
+    let labelled = event.tags.iter().any(|tag| {
+        let values = tag.as_slice();
+        values.len() >= 2 && values[0] == "l" && values[1] == MANIFEST_LABEL
+    });
+
+    if event.pubkey == *owner
+        && labelled
+        && event.content == to_hex(digest)
+        && event.verify().is_ok()
+    {
+        SignatureStatus::Verified
+    } else {
+        SignatureStatus::Invalid
+    }
+}
+
+/// Check the root of `owner`'s tree against the manifest in it
+pub async fn check_root<S: Store>(
+    tree: &HashTree<S>,
+    owner: &PublicKey,
+    root: &Cid,
+) -> Result<SignatureStatus, String> {
+    let entries = tree.list_directory(root).await.map_err(|e| e.to_string())?;
+    let Some(entry) = entries.iter().find(|e| e.name == MANIFEST_FILENAME) else {
+        return Ok(SignatureStatus::Unsigned);
+    };
+    let manifest = tree
+        .get(&Cid {
+            hash: entry.hash,
+            key: entry.key,
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Manifest {} not found", to_hex(&entry.hash)))?;
+    let digest = manifest_digest(&entries)?;
+    Ok(verify_manifest(&manifest, owner, &digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::LinkType;
+
+    fn entry(name: &str, byte: u8) -> TreeEntry {
+        TreeEntry {
+            name: name.to_string(),
+            hash: [byte; 32],
+            size: 10,
+            link_type: LinkType::Blob,
+            key: None,
+            meta: None,
+        }
+    }
+
+    #[test]
+    fn test_digest_ignores_manifest_and_order() {
+        let a = vec![entry("a.txt", 1), entry("b.txt", 2)];
+        let b = vec![
+            entry("b.txt", 2),
+            entry(MANIFEST_FILENAME, 9),
+            entry("a.txt", 1),
+        ];
+        assert_eq!(manifest_digest(&a).unwrap(), manifest_digest(&b).unwrap());
+
+        let changed = vec![entry("a.txt", 1), entry("b.txt", 3)];
+        assert_ne!(
+            manifest_digest(&a).unwrap(),
+            manifest_digest(&changed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_verify_manifest() {
+        let owner = Keys::generate();
+        let digest = manifest_digest(&[entry("index.html", 1)]).unwrap();
+        let manifest = sign_manifest(&owner, &digest).unwrap();
+
+        assert_eq!(
+            verify_manifest(&manifest, &owner.public_key(), &digest),
+            SignatureStatus::Verified
+        );
+        // Substituted root
+        assert_eq!(
+            verify_manifest(&manifest, &owner.public_key(), &[0u8; 32]),
+            SignatureStatus::Invalid
+        );
+        // Signed by someone other than the npub in the path
+        let other = Keys::generate();
+        let forged = sign_manifest(&other, &digest).unwrap();
+        assert_eq!(
+            verify_manifest(&forged, &owner.public_key(), &digest),
+            SignatureStatus::Invalid
+        );
+        assert_eq!(
+            verify_manifest(b"not json", &owner.public_key(), &digest),
+            SignatureStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_tampered_manifest_fails() {
+        let owner = Keys::generate();
+        let digest = [7u8; 32];
+        let manifest = sign_manifest(&owner, &digest).unwrap();
+
+        // Swap the signed digest for another one without re-signing
+        let mut event: serde_json::Value = serde_json::from_slice(&manifest).unwrap();
+        event["content"] = serde_json::Value::String(to_hex(&[8u8; 32]));
+        let tampered = serde_json::to_vec(&event).unwrap();
+        assert_eq!(
+            verify_manifest(&tampered, &owner.public_key(), &[8u8; 32]),
+            SignatureStatus::Invalid
+        );
+    }
+}
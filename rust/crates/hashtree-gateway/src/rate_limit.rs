@@ -0,0 +1,72 @@
+//! Fixed-window request limiting per client IP
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::RateLimit;
+
+/// Clients tracked before expired windows are swept
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Counts requests per client IP in fixed windows
+pub struct RateLimiter {
+    limit: RateLimit,
+    /// IP -> (window start, requests in window)
+    clients: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request from `ip` at `now`; false if it's over the limit
+    pub fn check(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= SWEEP_THRESHOLD {
+            let window = self.limit.window;
+            clients.retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let (start, count) = clients.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= self.limit.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= self.limit.requests {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_limits_per_ip_and_window() {
+        let limiter = RateLimiter::new(RateLimit {
+            requests: 2,
+            window: Duration::from_secs(10),
+        });
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let t0 = Instant::now();
+
+        assert!(limiter.check(a, t0));
+        assert!(limiter.check(a, t0 + Duration::from_secs(1)));
+        assert!(!limiter.check(a, t0 + Duration::from_secs(2)));
+        // Other clients have their own budget
+        assert!(limiter.check(b, t0 + Duration::from_secs(2)));
+        // A new window resets the count
+        assert!(limiter.check(a, t0 + Duration::from_secs(10)));
+    }
+}
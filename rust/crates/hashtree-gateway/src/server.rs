@@ -0,0 +1,260 @@
+//! HTTP routes and server entry point
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use hashtree_resolver::nostr::{NostrResolverConfig, NostrRootResolver};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tower_http::cors::{Any, CorsLayer};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::config::GatewayConfig;
+use crate::http::{next_request_id, query_param};
+use crate::manifest::SignatureStatus;
+use crate::state::{GatewayError, GatewayState};
+
+/// Response header carrying the id of the request's tracing span
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Response header reporting the manifest check of an npub tree root
+const SIGNATURE_HEADER: &str = "x-htree-signature";
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            GatewayError::FileNotFound(_) | GatewayError::TreeNotFound(_) => StatusCode::NOT_FOUND,
+            GatewayError::InvalidPath(_) => StatusCode::BAD_REQUEST,
+            GatewayError::Resolver(_) | GatewayError::BadSignature(_) => StatusCode::BAD_GATEWAY,
+            GatewayError::Store(_) | GatewayError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        if status.is_server_error() {
+            error!("gateway error: {}", self);
+        } else {
+            debug!("gateway {}: {}", status, self);
+        }
+
+        let mut response = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from(self.to_string()))
+            .unwrap();
+        if let GatewayError::BadSignature(_) = self {
+            response.headers_mut().insert(
+                SIGNATURE_HEADER,
+                HeaderValue::from_static(SignatureStatus::Invalid.as_str()),
+            );
+        }
+        response
+    }
+}
+
+/// Build the gateway's routes over `state`
+pub fn router(state: GatewayState) -> Router {
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods(Any)
+        .allow_headers(Any)
+        .expose_headers([
+            header::CONTENT_RANGE,
+            header::ACCEPT_RANGES,
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(SIGNATURE_HEADER),
+        ]);
+
+    Router::new()
+        .route("/htree/*path", get(handle_htree_request))
+        .route("/health", get(|| async { "ok" }))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// Resolve roots from the configured relays and serve until the listener fails
+pub async fn serve(config: GatewayConfig) -> Result<(), GatewayError> {
+    let resolver = NostrRootResolver::new(NostrResolverConfig {
+        relays: config.relays.clone(),
+        resolve_timeout: Duration::from_secs(5),
+        secret_key: None,
+    })
+    .await
+    .map_err(|e| GatewayError::Resolver(e.to_string()))?;
+    let state = GatewayState::new(&config, Arc::new(resolver))?;
+
+    let listener = TcpListener::bind(&config.listen)
+        .await
+        .map_err(|e| GatewayError::Io(format!("Failed to bind {}: {}", config.listen, e)))?;
+    info!("Gateway listening on {}", config.listen);
+
+    axum::serve(
+        listener,
+        router(state).into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .map_err(|e| GatewayError::Io(e.to_string()))
+}
+
+/// Reject clients over their request budget with 429
+async fn rate_limit(State(state): State<GatewayState>, request: Request, next: Next) -> Response {
+    if let Some(limiter) = state.rate_limiter() {
+        // Requests without connection info (in-process calls) aren't limited
+        let client = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+        if let Some(ip) = client {
+            if !limiter.check(ip, Instant::now()) {
+                warn!("Rate limited {}", ip);
+                return (StatusCode::TOO_MANY_REQUESTS, "Too many requests").into_response();
+            }
+        }
+    }
+    next.run(request).await
+}
+
+async fn handle_htree_request(
+    State(state): State<GatewayState>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let id = next_request_id();
+    let span = info_span!("htree", id, path = %uri.path());
+    let mut response = serve_htree_request(&state, &headers, &uri)
+        .instrument(span)
+        .await;
+    response
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, HeaderValue::from(id));
+    response
+}
+
+async fn serve_htree_request(state: &GatewayState, headers: &HeaderMap, uri: &Uri) -> Response {
+    // Raw path keeps percent-encoding; resolution decodes each segment
+    let path = uri.path().strip_prefix("/htree/").unwrap_or(uri.path());
+    let resolved = match state.resolve(path, query_param(uri.query(), "k")).await {
+        Ok(resolved) => resolved,
+        Err(e) => return e.into_response(),
+    };
+
+    if query_param(uri.query(), "proof") == Some("1") {
+        return match state.prove(&resolved).await {
+            Ok(proof) => Json(proof.to_json()).into_response(),
+            Err(e) => e.into_response(),
+        };
+    }
+
+    let range_header = headers.get(header::RANGE).and_then(|h| h.to_str().ok());
+    let (data, range) = match state.read(&resolved.cid, range_header).await {
+        Ok(result) => result,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, resolved.content_type)
+        .header(header::CONTENT_LENGTH, data.len())
+        .header(header::ACCEPT_RANGES, "bytes");
+    response = match range {
+        Some((start, end, total_size)) => response.status(StatusCode::PARTIAL_CONTENT).header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, total_size),
+        ),
+        None => response.status(StatusCode::OK),
+    };
+    if let Some(signature) = resolved.signature {
+        response = response.header(SIGNATURE_HEADER, signature.as_str());
+    }
+    // Content-addressed paths never change; npub paths follow the tree
+    let cache_control = if path.starts_with("nhash1") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "public, max-age=60"
+    };
+    response
+        .header(header::CACHE_CONTROL, cache_control)
+        .body(Body::from(data))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::tests::test_gateway;
+    use axum::body::to_bytes;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn get(app: &Router, uri: &str, range: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_serves_files_ranges_and_proofs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _, site) = test_gateway(dir.path(), Duration::from_secs(60)).await;
+        let app = router(state);
+
+        let response = get(&app, &format!("/htree/{}/index.html", site), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+        assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"<html>hello gateway</html>");
+
+        let response = get(
+            &app,
+            &format!("/htree/{}/index.html", site),
+            Some("bytes=0-5"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-5/26");
+
+        let response = get(&app, &format!("/htree/{}/index.html?proof=1", site), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let proof: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(proof["path"], "index.html");
+
+        let response = get(&app, &format!("/htree/{}/nope.txt", site), None).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = GatewayConfig::new(dir.path());
+        config.blossom_servers = Vec::new();
+        config.rate_limit = Some(crate::config::RateLimit::per_minute(1));
+        let resolver = Arc::new(crate::state::tests::StaticResolver::default());
+        let app = router(GatewayState::new(&config, resolver).unwrap());
+
+        let client: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let request = || {
+            let mut request = Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(client));
+            request
+        };
+        let first = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
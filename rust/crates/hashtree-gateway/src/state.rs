@@ -0,0 +1,565 @@
+//! Tree resolution and reads behind the gateway routes
+
+use hashtree_blossom::BlossomClient;
+use hashtree_core::{
+    key_from_hex, nhash_decode, sha256, to_hex, Cid, HashTree, HashTreeConfig, MerkleProof,
+};
+use hashtree_fs::FsBlobStore;
+use hashtree_resolver::RootResolver;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{debug, instrument};
+
+use crate::config::GatewayConfig;
+use crate::http::{guess_mime_type, is_npub, normalize_path, parse_range_header, url_decode};
+use crate::manifest::{check_root, SignatureStatus};
+use crate::rate_limit::RateLimiter;
+use crate::store::CombinedStore;
+
+/// How long a single root resolution may take
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum GatewayError {
+    #[error("Invalid path: {0}")]
+    InvalidPath(String),
+    #[error("Tree not found: {0}")]
+    TreeNotFound(String),
+    #[error("File not found: {0}")]
+    FileNotFound(String),
+    #[error("Resolver error: {0}")]
+    Resolver(String),
+    #[error("Store error: {0}")]
+    Store(String),
+    #[error("IO error: {0}")]
+    Io(String),
+    #[error("Signature mismatch: {0}")]
+    BadSignature(String),
+}
+
+/// An htree path resolved to its target, without the content loaded
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub cid: Cid,
+    pub content_type: String,
+    /// Tree root the path was resolved from, and the path below it
+    pub root: Cid,
+    pub inner_path: String,
+    /// Manifest check of an npub root; None for nhash paths
+    pub signature: Option<SignatureStatus>,
+}
+
+#[derive(Clone)]
+struct CachedRoot {
+    cid: Cid,
+    resolved_at: Instant,
+}
+
+/// Shared state for the gateway routes
+#[derive(Clone)]
+pub struct GatewayState {
+    resolver: Arc<dyn RootResolver>,
+    store: Arc<CombinedStore>,
+    /// Resolved roots by "npub/tree" (plus a secret digest for share links)
+    roots: Arc<Mutex<LruCache<String, CachedRoot>>>,
+    /// Manifest checks by "npub/roothex"; roots are content-addressed
+    signatures: Arc<Mutex<LruCache<String, SignatureStatus>>>,
+    root_ttl: Duration,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl GatewayState {
+    /// Create state caching blobs under `config.data_dir`
+    pub fn new(
+        config: &GatewayConfig,
+        resolver: Arc<dyn RootResolver>,
+    ) -> Result<Self, GatewayError> {
+        let local =
+            FsBlobStore::with_max_bytes(config.data_dir.join("blobs"), config.cache_max_bytes)
+                .map_err(|e| GatewayError::Store(e.to_string()))?;
+        let blossom = BlossomClient::new_empty(nostr::Keys::generate())
+            .with_read_servers(config.blossom_servers.clone());
+        let capacity = NonZeroUsize::new(config.root_cache_entries.max(1)).unwrap();
+
+        Ok(Self {
+            resolver,
+            store: Arc::new(CombinedStore::new(Arc::new(local), blossom)),
+            roots: Arc::new(Mutex::new(LruCache::new(capacity))),
+            signatures: Arc::new(Mutex::new(LruCache::new(capacity))),
+            root_ttl: config.root_ttl,
+            rate_limiter: config
+                .rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+        })
+    }
+
+    pub fn store(&self) -> &Arc<CombinedStore> {
+        &self.store
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_deref()
+    }
+
+    fn tree(&self) -> HashTree<CombinedStore> {
+        HashTree::new(HashTreeConfig::new(self.store.clone()))
+    }
+
+    /// Resolve npub/treeName to its root, from cache while it's fresh.
+    /// Link-visible trees are unmasked with the share URL's `secret`.
+    #[instrument(level = "debug", skip(self, secret))]
+    async fn resolve_tree(
+        &self,
+        npub: &str,
+        tree_name: &str,
+        secret: Option<&[u8; 32]>,
+    ) -> Result<Cid, GatewayError> {
+        let key = format!("{}/{}", npub, tree_name);
+        let cache_key = match secret {
+            Some(secret) => format!("{}#{}", key, &to_hex(&sha256(secret))[..16]),
+            None => key.clone(),
+        };
+
+        if let Some(entry) = self.roots.lock().unwrap().get(&cache_key) {
+            if entry.resolved_at.elapsed() < self.root_ttl {
+                debug!("Root cache hit for {}", key);
+                return Ok(entry.cid.clone());
+            }
+        }
+
+        let resolve = async {
+            match secret {
+                Some(secret) => self.resolver.resolve_shared(&key, secret).await,
+                None => self.resolver.resolve(&key).await,
+            }
+        };
+        let cid = tokio::time::timeout(RESOLVE_TIMEOUT, resolve)
+            .await
+            .map_err(|_| GatewayError::Resolver("Timeout resolving tree".into()))?
+            .map_err(|e| GatewayError::Resolver(e.to_string()))?
+            .ok_or_else(|| GatewayError::TreeNotFound(key.clone()))?;
+
+        self.roots.lock().unwrap().put(
+            cache_key,
+            CachedRoot {
+                cid: cid.clone(),
+                resolved_at: Instant::now(),
+            },
+        );
+        Ok(cid)
+    }
+
+    /// Check a root reached via `npub` against the owner's signed manifest
+    async fn verify_root(&self, npub: &str, root: &Cid) -> Result<SignatureStatus, GatewayError> {
+        let cache_key = format!("{}/{}", npub, to_hex(&root.hash));
+        if let Some(status) = self.signatures.lock().unwrap().get(&cache_key) {
+            return Ok(*status);
+        }
+
+        let owner =
+            nostr::PublicKey::parse(npub).map_err(|e| GatewayError::InvalidPath(e.to_string()))?;
+        let status = check_root(&self.tree(), &owner, root)
+            .await
+            .map_err(GatewayError::Store)?;
+
+        self.signatures.lock().unwrap().put(cache_key, status);
+        Ok(status)
+    }
+
+    /// Resolve a path within a tree to get the entry's Cid
+    #[instrument(level = "debug", skip(self, root))]
+    async fn resolve_path(&self, root: &Cid, path: &str) -> Result<Cid, GatewayError> {
+        let path = normalize_path(path).map_err(GatewayError::InvalidPath)?;
+        self.tree()
+            .resolve_path(root, &path)
+            .await
+            .map_err(|e| GatewayError::Store(e.to_string()))?
+            .ok_or_else(|| GatewayError::FileNotFound(path.to_string()))
+    }
+
+    /// Resolve an htree path (without the /htree/ prefix) to its target.
+    /// `secret` is the hex `?k=` of a link-visible share URL.
+    pub async fn resolve(
+        &self,
+        path: &str,
+        secret: Option<&str>,
+    ) -> Result<Resolved, GatewayError> {
+        let path = path.trim_start_matches('/');
+        let (first, rest) = path.split_once('/').unwrap_or((path, ""));
+        if first.is_empty() {
+            return Err(GatewayError::InvalidPath("Empty path".into()));
+        }
+
+        if first.starts_with("nhash1") {
+            let filename = normalize_path(&url_decode(rest)).map_err(GatewayError::InvalidPath)?;
+            self.resolve_nhash(first, &filename).await
+        } else if is_npub(first) {
+            let (tree_name, file_path) = rest.split_once('/').unwrap_or((rest, ""));
+            let tree_name =
+                normalize_path(&url_decode(tree_name)).map_err(GatewayError::InvalidPath)?;
+            if tree_name.is_empty() {
+                return Err(GatewayError::InvalidPath("Empty tree name".into()));
+            }
+            let file_path =
+                normalize_path(&url_decode(file_path)).map_err(GatewayError::InvalidPath)?;
+            let secret = secret
+                .map(key_from_hex)
+                .transpose()
+                .map_err(|_| GatewayError::InvalidPath("Invalid link secret".into()))?;
+            self.resolve_npub(first, &tree_name, &file_path, secret.as_ref())
+                .await
+        } else {
+            Err(GatewayError::InvalidPath(format!(
+                "Path must start with npub or nhash: {}",
+                first
+            )))
+        }
+    }
+
+    async fn resolve_nhash(&self, nhash: &str, filename: &str) -> Result<Resolved, GatewayError> {
+        let nhash_data =
+            nhash_decode(nhash).map_err(|e| GatewayError::InvalidPath(e.to_string()))?;
+        let root = Cid {
+            hash: nhash_data.hash,
+            key: nhash_data.decrypt_key,
+        };
+
+        let mut inner_path = nhash_data.path.join("/");
+        let mut cid = if inner_path.is_empty() {
+            root.clone()
+        } else {
+            self.resolve_path(&root, &inner_path).await?
+        };
+
+        // A bare filename after a file nhash only names the download
+        if !filename.is_empty() {
+            let found = match self.resolve_path(&cid, filename).await {
+                Ok(resolved) => {
+                    cid = resolved;
+                    true
+                }
+                Err(GatewayError::FileNotFound(_)) if !filename.contains('/') => false,
+                Err(e) => return Err(e),
+            };
+            if found {
+                inner_path = [inner_path.as_str(), filename]
+                    .iter()
+                    .filter(|p| !p.is_empty())
+                    .copied()
+                    .collect::<Vec<_>>()
+                    .join("/");
+            }
+        }
+
+        let name = if filename.is_empty() {
+            "file"
+        } else {
+            filename
+        };
+        Ok(Resolved {
+            cid,
+            content_type: guess_mime_type(name).to_string(),
+            root,
+            inner_path,
+            signature: None,
+        })
+    }
+
+    async fn resolve_npub(
+        &self,
+        npub: &str,
+        tree_name: &str,
+        file_path: &str,
+        secret: Option<&[u8; 32]>,
+    ) -> Result<Resolved, GatewayError> {
+        let mut tree_name = tree_name.to_string();
+        let mut file_path = file_path.to_string();
+
+        // Tree names may contain a slash ("videos/My Video")
+        let root = match self.resolve_tree(npub, &tree_name, secret).await {
+            Ok(cid) => cid,
+            Err(GatewayError::TreeNotFound(_)) if !file_path.is_empty() => {
+                let (first, rest) = file_path.split_once('/').unwrap_or((&file_path, ""));
+                let alt_tree_name = format!("{}/{}", tree_name, first);
+                let rest = rest.to_string();
+                match self.resolve_tree(npub, &alt_tree_name, secret).await {
+                    Ok(cid) => {
+                        tree_name = alt_tree_name;
+                        file_path = rest;
+                        cid
+                    }
+                    Err(GatewayError::TreeNotFound(_)) => {
+                        return Err(GatewayError::TreeNotFound(format!(
+                            "{}/{}",
+                            npub, tree_name
+                        )));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
+        let signature = self.verify_root(npub, &root).await?;
+        if signature == SignatureStatus::Invalid {
+            return Err(GatewayError::BadSignature(format!(
+                "{}/{}",
+                npub, tree_name
+            )));
+        }
+
+        let cid = if file_path.is_empty() {
+            root.clone()
+        } else {
+            self.resolve_path(&root, &file_path).await?
+        };
+        let name = if file_path.is_empty() {
+            &tree_name
+        } else {
+            &file_path
+        };
+
+        Ok(Resolved {
+            cid,
+            content_type: guess_mime_type(name).to_string(),
+            root,
+            inner_path: file_path,
+            signature: Some(signature),
+        })
+    }
+
+    /// Read a file, or only the byte range asked for. Returns the data and,
+    /// for a satisfiable range, (start, end, total size).
+    #[instrument(level = "debug", skip(self, cid), fields(hash = %to_hex(&cid.hash)))]
+    pub async fn read(
+        &self,
+        cid: &Cid,
+        range_header: Option<&str>,
+    ) -> Result<(Vec<u8>, Option<(usize, usize, usize)>), GatewayError> {
+        let tree = self.tree();
+        let not_found = || GatewayError::FileNotFound(to_hex(&cid.hash));
+
+        // Encrypted files can't be read by range, so slice the whole file
+        if let (Some(range), None) = (range_header, &cid.key) {
+            let total_size =
+                tree.get_size(&cid.hash)
+                    .await
+                    .map_err(|e| GatewayError::Store(e.to_string()))? as usize;
+            if let Some((start, end)) = parse_range_header(range, total_size) {
+                let data = tree
+                    .read_file_range(&cid.hash, start as u64, Some((end + 1) as u64))
+                    .await
+                    .map_err(|e| GatewayError::Store(e.to_string()))?
+                    .ok_or_else(not_found)?;
+                return Ok((data, Some((start, end, total_size))));
+            }
+        }
+
+        let data = tree
+            .get(cid)
+            .await
+            .map_err(|e| GatewayError::Store(e.to_string()))?
+            .ok_or_else(not_found)?;
+        if let Some(range) = range_header {
+            if let Some((start, end)) = parse_range_header(range, data.len()) {
+                let total_size = data.len();
+                return Ok((
+                    data[start..end + 1].to_vec(),
+                    Some((start, end, total_size)),
+                ));
+            }
+        }
+        Ok((data, None))
+    }
+
+    /// Merkle proof of a resolved entry against its tree root
+    pub async fn prove(&self, resolved: &Resolved) -> Result<MerkleProof, GatewayError> {
+        self.tree()
+            .prove(&resolved.root, &resolved.inner_path)
+            .await
+            .map_err(|e| GatewayError::Store(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use hashtree_core::{verify_proof, DirEntry, LinkType};
+    use hashtree_resolver::ResolverError;
+    use nostr::ToBech32;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+
+    /// Resolver serving fixed roots, counting lookups
+    #[derive(Default)]
+    pub(crate) struct StaticResolver {
+        pub roots: Mutex<HashMap<String, Cid>>,
+        pub lookups: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl RootResolver for StaticResolver {
+        async fn resolve(&self, key: &str) -> Result<Option<Cid>, ResolverError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Ok(self.roots.lock().unwrap().get(key).cloned())
+        }
+
+        async fn subscribe(
+            &self,
+            _key: &str,
+        ) -> Result<mpsc::Receiver<Option<Cid>>, ResolverError> {
+            Ok(mpsc::channel(1).1)
+        }
+    }
+
+    /// Gateway over a temp dir with no Blossom servers, plus a published
+    /// "site" tree holding index.html. Returns the tree path prefix.
+    pub(crate) async fn test_gateway(
+        dir: &std::path::Path,
+        root_ttl: Duration,
+    ) -> (GatewayState, Arc<StaticResolver>, String) {
+        let mut config = GatewayConfig::new(dir);
+        config.blossom_servers = Vec::new();
+        config.root_ttl = root_ttl;
+        let resolver = Arc::new(StaticResolver::default());
+        let state = GatewayState::new(&config, resolver.clone()).unwrap();
+
+        let tree = HashTree::new(HashTreeConfig::new(state.store().clone()).public());
+        let data = b"<html>hello gateway</html>";
+        let (file, size) = tree.put(data).await.unwrap();
+        let root = tree
+            .put_directory(vec![DirEntry::from_cid("index.html", &file)
+                .with_size(size)
+                .with_link_type(LinkType::Blob)])
+            .await
+            .unwrap();
+
+        let npub = nostr::Keys::generate().public_key().to_bech32().unwrap();
+        resolver
+            .roots
+            .lock()
+            .unwrap()
+            .insert(format!("{}/site", npub), root);
+        (state, resolver, format!("{}/site", npub))
+    }
+
+    #[tokio::test]
+    async fn test_resolve_and_read_npub_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, _, site) = test_gateway(dir.path(), Duration::from_secs(60)).await;
+
+        let resolved = state
+            .resolve(&format!("{}/index.html", site), None)
+            .await
+            .unwrap();
+        assert_eq!(resolved.content_type, "text/html");
+        assert_eq!(resolved.inner_path, "index.html");
+
+        let (data, range) = state.read(&resolved.cid, None).await.unwrap();
+        assert_eq!(data, b"<html>hello gateway</html>");
+        assert_eq!(range, None);
+
+        let (data, range) = state.read(&resolved.cid, Some("bytes=6-10")).await.unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(range, Some((6, 10, 26)));
+
+        let proof = state.prove(&resolved).await.unwrap();
+        assert_eq!(
+            verify_proof(&resolved.root, &proof).await.unwrap(),
+            resolved.cid
+        );
+
+        assert!(matches!(
+            state.resolve(&format!("{}/missing.html", site), None).await,
+            Err(GatewayError::FileNotFound(_))
+        ));
+        assert!(matches!(
+            state.resolve("not-a-key/site", None).await,
+            Err(GatewayError::InvalidPath(_))
+        ));
+
+        // Encoded traversal and control characters are refused up front
+        let resolved = state
+            .resolve(&format!("{}/./index.html/", site), None)
+            .await
+            .unwrap();
+        assert_eq!(resolved.inner_path, "index.html");
+        for path in ["%2E%2E/index.html", "index.html%00", "a/../index.html"] {
+            assert!(matches!(
+                state.resolve(&format!("{}/{}", site, path), None).await,
+                Err(GatewayError::InvalidPath(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_npub_roots_are_checked_against_manifest() {
+        use crate::manifest::{manifest_digest, sign_manifest, MANIFEST_FILENAME};
+
+        let dir = tempfile::tempdir().unwrap();
+        let (state, resolver, site) = test_gateway(dir.path(), Duration::ZERO).await;
+        let resolved = state.resolve(&site, None).await.unwrap();
+        assert_eq!(resolved.signature, Some(SignatureStatus::Unsigned));
+
+        // Sign a tree under a fresh npub, then swap its content
+        let owner = nostr::Keys::generate();
+        let npub = owner.public_key().to_bech32().unwrap();
+        let tree = state.tree();
+        let entries = tree.list_directory(&resolved.root).await.unwrap();
+        let manifest = sign_manifest(&owner, &manifest_digest(&entries).unwrap()).unwrap();
+        let (manifest_cid, manifest_size) = tree.put(&manifest).await.unwrap();
+        let manifest_entry = DirEntry::from_cid(MANIFEST_FILENAME, &manifest_cid)
+            .with_size(manifest_size)
+            .with_link_type(LinkType::Blob);
+        let index = DirEntry::from_cid("index.html", &resolved.cid)
+            .with_size(entries[0].size)
+            .with_link_type(LinkType::Blob);
+        let signed = tree
+            .put_directory(vec![index, manifest_entry.clone()])
+            .await
+            .unwrap();
+        let (evil, evil_size) = tree.put(b"<html>evil</html>").await.unwrap();
+        let swapped = DirEntry::from_cid("index.html", &evil)
+            .with_size(evil_size)
+            .with_link_type(LinkType::Blob);
+        let substituted = tree
+            .put_directory(vec![swapped, manifest_entry])
+            .await
+            .unwrap();
+
+        let key = format!("{}/signed", npub);
+        resolver.roots.lock().unwrap().insert(key.clone(), signed);
+        let resolved = state.resolve(&key, None).await.unwrap();
+        assert_eq!(resolved.signature, Some(SignatureStatus::Verified));
+
+        resolver
+            .roots
+            .lock()
+            .unwrap()
+            .insert(key.clone(), substituted);
+        assert!(matches!(
+            state.resolve(&format!("{}/index.html", key), None).await,
+            Err(GatewayError::BadSignature(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_root_cache_expires() {
+        let dir = tempfile::tempdir().unwrap();
+        let (state, resolver, site) = test_gateway(dir.path(), Duration::from_secs(60)).await;
+        state.resolve(&site, None).await.unwrap();
+        state.resolve(&site, None).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let (state, resolver, site) = test_gateway(dir.path(), Duration::ZERO).await;
+        state.resolve(&site, None).await.unwrap();
+        state.resolve(&site, None).await.unwrap();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,399 @@
+//! Local-first store with Blossom fallback
+//!
+//! A block missing locally is requested from the Blossom read servers one
+//! at a time, best score first, until one has it, so each block is
+//! downloaded once. A server's score is mostly how often it had the blocks
+//! asked for, then how fast it sent them, so the servers likely to answer
+//! are asked first. Concurrent gets of the same missing block share one
+//! fetch. A block no server has is a miss; if some server failed instead,
+//! the get fails, so an outage isn't reported as missing content.
+
+use async_trait::async_trait;
+use hashtree_blossom::{BlossomClient, BlossomError};
+use hashtree_core::store::slice_range;
+use hashtree_core::{to_hex, Hash, Store, StoreError};
+use hashtree_fs::FsBlobStore;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument, warn};
+
+/// Weight of the newest sample in the latency average
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// Request counters of a read server
+#[derive(Debug, Clone, Default)]
+struct SourceStats {
+    requests: u64,
+    /// Requests answered with the block
+    hits: u64,
+    /// Requests answered without it
+    misses: u64,
+    /// Errors, and blocks that didn't match their hash
+    failures: u64,
+    /// Average time to a hit
+    latency_ms: Option<f64>,
+}
+
+impl SourceStats {
+    /// 0-1, higher is better: mostly how often the server has the blocks
+    /// asked for, then how fast it sends them. New servers score 0.5.
+    fn score(&self) -> f64 {
+        // Failures count double
+        let answered = self.hits + self.misses + 2 * self.failures;
+        let hit_rate = (self.hits as f64 + 1.0) / (answered as f64 + 2.0);
+        // 1 up to 50 ms, 0.18 at 500 ms
+        let speed = self
+            .latency_ms
+            .map_or(0.5, |ms| (100.0 / (ms + 50.0)).min(1.0));
+        0.7 * hit_rate + 0.3 * speed
+    }
+}
+
+/// A Blossom read server blocks are fetched from
+struct Source {
+    server: String,
+    client: BlossomClient,
+    stats: Mutex<SourceStats>,
+}
+
+impl Source {
+    fn score(&self) -> f64 {
+        self.stats.lock().unwrap().score()
+    }
+
+    /// The block if the server has it, None if it doesn't
+    async fn request(&self, hash: &Hash) -> Result<Option<Vec<u8>>, String> {
+        let started = Instant::now();
+        // Downloads are verified against the hash
+        let result = self.client.download(&to_hex(hash)).await;
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        match result {
+            Ok(data) => {
+                let ms = started.elapsed().as_secs_f64() * 1000.0;
+                debug!(
+                    "Fetched blob {} from {} ({} bytes, {:.0} ms)",
+                    &to_hex(hash)[..8],
+                    self.server,
+                    data.len(),
+                    ms
+                );
+                stats.hits += 1;
+                stats.latency_ms = Some(match stats.latency_ms {
+                    Some(avg) => avg + LATENCY_WEIGHT * (ms - avg),
+                    None => ms,
+                });
+                Ok(Some(data))
+            }
+            Err(BlossomError::NotFound(_)) => {
+                stats.misses += 1;
+                Ok(None)
+            }
+            Err(e) => {
+                debug!(
+                    "Fetching blob {} from {} failed: {}",
+                    &to_hex(hash)[..8],
+                    self.server,
+                    e
+                );
+                stats.failures += 1;
+                Err(format!("{}: {}", self.server, e))
+            }
+        }
+    }
+}
+
+/// Combined store that checks local filesystem first, then the Blossom
+/// read servers. Blobs fetched remotely are cached in the local store,
+/// which evicts by its own size limit, so this also serves data stored
+/// locally but not yet synced to Blossom.
+pub struct CombinedStore {
+    local: Arc<FsBlobStore>,
+    blossom: BlossomClient,
+    /// A source per Blossom read server
+    sources: Vec<Source>,
+    /// Remote fetches in progress, awaited by every get of their block
+    in_flight: Mutex<HashMap<Hash, Arc<InFlight>>>,
+}
+
+type InFlight = OnceCell<Result<Option<Vec<u8>>, String>>;
+
+impl CombinedStore {
+    pub fn new(local: Arc<FsBlobStore>, blossom: BlossomClient) -> Self {
+        let sources = blossom
+            .read_servers()
+            .iter()
+            .map(|server| Source {
+                server: server.clone(),
+                client: blossom.clone().with_read_servers(vec![server.clone()]),
+                stats: Mutex::default(),
+            })
+            .collect();
+        Self {
+            local,
+            blossom,
+            sources,
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The local blob cache
+    pub fn local(&self) -> &Arc<FsBlobStore> {
+        &self.local
+    }
+
+    async fn fetch_and_cache(&self, hash: &Hash) -> Result<Option<Vec<u8>>, String> {
+        let Some(data) = self.fetch_remote(hash).await? else {
+            debug!("Blob {} not found locally or remotely", &to_hex(hash)[..8]);
+            return Ok(None);
+        };
+        // Cache locally for future requests
+        match self.local.put(*hash, data.clone()).await {
+            Ok(_) => debug!("Cached blob {} locally", &to_hex(hash)[..8]),
+            Err(e) => warn!("Failed to cache blob locally: {}", e),
+        }
+        Ok(Some(data))
+    }
+
+    /// Request `hash` from the read servers, best scored first, until one
+    /// has it. Fails if none had it and any of them failed.
+    async fn fetch_remote(&self, hash: &Hash) -> Result<Option<Vec<u8>>, String> {
+        let mut ranked: Vec<(f64, &Source)> = self
+            .sources
+            .iter()
+            .map(|source| (source.score(), source))
+            .collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut errors = Vec::new();
+        for (_, source) in ranked {
+            match source.request(hash).await {
+                Ok(Some(data)) => return Ok(Some(data)),
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(None)
+        } else {
+            Err(format!(
+                "Fetching blob {} failed: {}",
+                to_hex(hash),
+                errors.join("; ")
+            ))
+        }
+    }
+}
+
+#[async_trait]
+impl Store for CombinedStore {
+    #[instrument(level = "debug", name = "store_get", skip_all, fields(hash = %to_hex(hash)))]
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Ok(Some(data)) = self.local.get(hash).await {
+            debug!(
+                "Found blob {} in local store ({} bytes)",
+                &to_hex(hash)[..8],
+                data.len()
+            );
+            return Ok(Some(data));
+        }
+
+        // Join a fetch of the block already in progress, or start one. If
+        // the caller running it goes away, a waiting one takes over.
+        let flight = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(*hash)
+            .or_default()
+            .clone();
+        let data = flight
+            .get_or_init(|| self.fetch_and_cache(hash))
+            .await
+            .clone();
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(hash)
+            .is_some_and(|current| Arc::ptr_eq(current, &flight))
+        {
+            in_flight.remove(hash);
+        }
+        data.map_err(StoreError::Other)
+    }
+
+    /// Slices of local blobs are read in place. A remote blob is fetched
+    /// whole, so it's verified and cached for the next range.
+    async fn get_range(
+        &self,
+        hash: &Hash,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Ok(Some(data)) = self.local.get_range(hash, offset, len).await {
+            return Ok(Some(data));
+        }
+        Ok(self
+            .get(hash)
+            .await?
+            .map(|data| slice_range(&data, offset, len).to_vec()))
+    }
+
+    async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.local.put(hash, data).await
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        if self.local.has(hash).await? {
+            return Ok(true);
+        }
+
+        let hex = to_hex(hash);
+        for server in self.blossom.read_servers() {
+            if self.blossom.exists_on_server(&hex, server).await {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn has_many(&self, hashes: &[Hash]) -> Result<Vec<bool>, StoreError> {
+        let mut found = self.local.has_many(hashes).await?;
+
+        // One pipelined round of HEADs for everything not cached locally
+        let missing: Vec<usize> = (0..hashes.len()).filter(|&i| !found[i]).collect();
+        let keys: Vec<String> = missing.iter().map(|&i| to_hex(&hashes[i])).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let remote = self
+            .blossom
+            .exists_many_on_servers(&keys, self.blossom.read_servers())
+            .await;
+        for (&i, exists) in missing.iter().zip(remote) {
+            found[i] = exists;
+        }
+        Ok(found)
+    }
+
+    async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
+        // Only delete from local store
+        self.local.delete(hash).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::sha256;
+    use hashtree_testkit::MockBlossom;
+    use tempfile::TempDir;
+
+    fn combined(dir: &TempDir, servers: &[&MockBlossom]) -> CombinedStore {
+        let local = Arc::new(FsBlobStore::new(dir.path()).unwrap());
+        let blossom = BlossomClient::new_empty(nostr::Keys::generate())
+            .with_read_servers(servers.iter().map(|server| server.url()).collect());
+        CombinedStore::new(local, blossom)
+    }
+
+    fn stats(store: &CombinedStore) -> Vec<SourceStats> {
+        store
+            .sources
+            .iter()
+            .map(|source| source.stats.lock().unwrap().clone())
+            .collect()
+    }
+
+    fn requests(store: &CombinedStore) -> Vec<u64> {
+        stats(store).iter().map(|s| s.requests).collect()
+    }
+
+    #[tokio::test]
+    async fn test_each_block_is_fetched_once() {
+        let dir = TempDir::new().unwrap();
+        let (cdn, mirror) = (MockBlossom::start(), MockBlossom::start());
+        cdn.insert(b"block");
+        mirror.insert(b"block");
+        let store = combined(&dir, &[&cdn, &mirror]);
+        let hash = sha256(b"block");
+
+        assert_eq!(store.get(&hash).await.unwrap(), Some(b"block".to_vec()));
+        assert_eq!(requests(&store), [1, 0]);
+        assert!(store.local().has(&hash).await.unwrap());
+
+        // Concurrent gets share one fetch
+        store.local().delete(&hash).await.unwrap();
+        let gets = (0..5).map(|_| store.get(&hash));
+        for data in futures::future::join_all(gets).await {
+            assert_eq!(data.unwrap(), Some(b"block".to_vec()));
+        }
+        assert_eq!(requests(&store), [2, 0]);
+        assert!(store.in_flight.lock().unwrap().is_empty());
+
+        // A get dropped mid-fetch hands the fetch to one still waiting
+        store.local().delete(&hash).await.unwrap();
+        let mut first = Box::pin(store.get(&hash));
+        let mut second = Box::pin(store.get(&hash));
+        assert!(futures::poll!(first.as_mut()).is_pending());
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        drop(first);
+        assert_eq!(second.await.unwrap(), Some(b"block".to_vec()));
+        assert_eq!(requests(&store), [3, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_blocks_and_failures_differ() {
+        let dir = TempDir::new().unwrap();
+        let (empty, down) = (MockBlossom::start(), MockBlossom::start());
+        let store = combined(&dir, &[&empty, &down]);
+        let hash = sha256(b"block");
+
+        assert!(matches!(store.get(&hash).await, Ok(None)));
+        down.set_available(false);
+        assert!(matches!(store.get(&hash).await, Err(StoreError::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn test_servers_are_tried_by_score() {
+        let dir = TempDir::new().unwrap();
+        let (empty, down, good) = (
+            MockBlossom::start(),
+            MockBlossom::start(),
+            MockBlossom::start(),
+        );
+        down.insert(b"one");
+        down.set_available(false);
+        good.insert(b"one");
+        good.insert(b"two");
+        let store = combined(&dir, &[&empty, &down, &good]);
+
+        // In the order configured while nothing is known
+        assert_eq!(
+            store.get(&sha256(b"one")).await.unwrap(),
+            Some(b"one".to_vec())
+        );
+        let counts = stats(&store);
+        assert_eq!(counts[0].misses, 1);
+        assert_eq!(counts[1].failures, 1);
+        assert_eq!(counts[2].hits, 1);
+        assert!(counts[2].score() > counts[0].score());
+        assert!(counts[0].score() > counts[1].score());
+
+        // Now the server that had the block goes first
+        assert_eq!(
+            store.get(&sha256(b"two")).await.unwrap(),
+            Some(b"two".to_vec())
+        );
+        assert_eq!(requests(&store), [1, 1, 2]);
+
+        // Missing everywhere reachable, but a server was down
+        assert!(store.get(&sha256(b"three")).await.is_err());
+        assert!(store.has(&sha256(b"two")).await.unwrap());
+        assert_eq!(
+            store
+                .has_many(&[sha256(b"one"), sha256(b"three")])
+                .await
+                .unwrap(),
+            [true, false]
+        );
+    }
+}
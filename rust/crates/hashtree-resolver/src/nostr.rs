@@ -11,6 +11,7 @@
 //! - key-tag: CHK decryption key (public)
 //! - encryptedKey-tag: XOR-masked key (link-visible)
 //! - selfEncryptedKey-tag: NIP-44 key encrypted to self (private)
+//! - selfEncryptedLinkKey-tag: NIP-44 link secret encrypted to self (link-visible)
 //! - encrypted_key-tag: legacy AES-GCM shared key (backwards compat)
 
 use crate::{ResolverEntry, ResolverError, RootResolver};
@@ -19,12 +20,12 @@ use hashtree_core::{from_hex, to_hex, Cid};
 use nostr_sdk::prelude::*;
 use nostr_sdk::prelude::nip44;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{mpsc, RwLock};
 
-use hashtree_core::{decrypt, xor_keys};
+use hashtree_core::{decrypt, xor_keys, TreeVisibility};
 
 const HASHTREE_KIND: u16 = 30078;
 const HASHTREE_LABEL: &str = "hashtree";
@@ -59,8 +60,196 @@ const TAG_HASH: &str = "hash";
 const TAG_KEY: &str = "key";
 const TAG_ENCRYPTED_KEY: &str = "encryptedKey";
 const TAG_SELF_ENCRYPTED_KEY: &str = "selfEncryptedKey";
+const TAG_SELF_ENCRYPTED_LINK_KEY: &str = "selfEncryptedLinkKey";
 const TAG_ENCRYPTED_KEY_LEGACY: &str = "encrypted_key";
 
+/// Build the root event of `tree_name` at `cid`, for `keys` to sign.
+///
+/// This is the one place the event format is defined; the resolver, the
+/// CLI and the desktop app all publish roots built here. Tags: `d` with the
+/// tree name, `l` "hashtree", `hash`, then the key as `visibility` needs it:
+/// - public: `key` in the clear (none for unencrypted trees)
+/// - link-visible: `encryptedKey`, the key XOR `link_secret`, and
+///   `selfEncryptedLinkKey`, the link secret NIP-44 encrypted to ourselves;
+///   an unencrypted tree has no key to mask, so it gets only `hash`
+/// - private: `selfEncryptedKey`, the key NIP-44 encrypted to ourselves
+///
+/// Names with slashes also get an `l` per directory prefix, e.g.
+/// "docs/travel" is labeled "docs". The content repeats the hash for older
+/// readers.
+pub fn tree_root_event(
+    keys: &Keys,
+    tree_name: &str,
+    cid: &Cid,
+    visibility: TreeVisibility,
+    link_secret: Option<&[u8; 32]>,
+) -> Result<EventBuilder, ResolverError> {
+    let encrypt_to_self = |plaintext: String| {
+        nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            plaintext,
+            nip44::Version::V2,
+        )
+        .map_err(|e| ResolverError::Other(format!("NIP-44 encryption failed: {}", e)))
+    };
+
+    let mut tags = vec![
+        Tag::identifier(tree_name.to_string()),
+        Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::L)),
+            vec![HASHTREE_LABEL],
+        ),
+        Tag::custom(TagKind::Custom(TAG_HASH.into()), vec![to_hex(&cid.hash)]),
+    ];
+
+    match (visibility, cid.key) {
+        (TreeVisibility::Public, Some(key)) => {
+            tags.push(Tag::custom(
+                TagKind::Custom(TAG_KEY.into()),
+                vec![to_hex(&key)],
+            ));
+        }
+        (TreeVisibility::Public | TreeVisibility::LinkVisible, None) => {}
+        (TreeVisibility::LinkVisible, Some(key)) => {
+            let secret = link_secret.ok_or_else(|| {
+                ResolverError::Other("Link-visible trees need a link secret".into())
+            })?;
+            tags.push(Tag::custom(
+                TagKind::Custom(TAG_ENCRYPTED_KEY.into()),
+                vec![to_hex(&xor_keys(&key, secret))],
+            ));
+            tags.push(Tag::custom(
+                TagKind::Custom(TAG_SELF_ENCRYPTED_LINK_KEY.into()),
+                vec![encrypt_to_self(to_hex(secret))?],
+            ));
+        }
+        (TreeVisibility::Private, Some(key)) => {
+            tags.push(Tag::custom(
+                TagKind::Custom(TAG_SELF_ENCRYPTED_KEY.into()),
+                vec![encrypt_to_self(to_hex(&key))?],
+            ));
+        }
+        (TreeVisibility::Private, None) => {
+            return Err(ResolverError::Other(
+                "private trees must be encrypted (cid has no key)".into(),
+            ));
+        }
+    }
+
+    let parts: Vec<&str> = tree_name.split('/').collect();
+    for i in 1..parts.len() {
+        tags.push(Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::L)),
+            vec![parts[..i].join("/")],
+        ));
+    }
+
+    Ok(EventBuilder::new(
+        Kind::Custom(HASHTREE_KIND),
+        to_hex(&cid.hash),
+        tags,
+    ))
+}
+
+/// Label of share events, so tree listings skip them
+pub const SHARE_LABEL: &str = "hashtree-share";
+
+const TAG_WRAPPED_KEY: &str = "wrappedKey";
+
+/// `d` tag of the share event for `tree_name` and `recipient`
+pub fn share_d_tag(tree_name: &str, recipient: &PublicKey) -> String {
+    format!("{}/{}/{}", SHARE_LABEL, recipient.to_hex(), tree_name)
+}
+
+/// Build the event sharing `tree_name` at `cid` with `recipient`, or
+/// revoking the share when `cid` is None, for `keys` to sign.
+///
+/// Like [`tree_root_event`], this is the one place the format is defined.
+/// Tags: `d` from [`share_d_tag`], `l` [`SHARE_LABEL`], `p` the recipient,
+/// `tree` the tree name, and for a grant `hash` and `wrappedKey`, the root
+/// key NIP-44 encrypted to the recipient.
+pub fn share_event(
+    keys: &Keys,
+    tree_name: &str,
+    recipient: &PublicKey,
+    cid: Option<&Cid>,
+) -> Result<EventBuilder, ResolverError> {
+    let mut tags = vec![
+        Tag::identifier(share_d_tag(tree_name, recipient)),
+        Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::L)),
+            vec![SHARE_LABEL],
+        ),
+        Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::P)),
+            vec![recipient.to_hex()],
+        ),
+        Tag::custom(TagKind::Custom("tree".into()), vec![tree_name.to_string()]),
+    ];
+
+    if let Some(cid) = cid {
+        let key = cid
+            .key
+            .ok_or_else(|| ResolverError::Other("Only encrypted trees can be shared".into()))?;
+        let wrapped = nip44::encrypt(
+            keys.secret_key(),
+            recipient,
+            to_hex(&key),
+            nip44::Version::V2,
+        )
+        .map_err(|e| ResolverError::Other(format!("NIP-44 encryption failed: {}", e)))?;
+        tags.push(Tag::custom(
+            TagKind::Custom(TAG_HASH.into()),
+            vec![to_hex(&cid.hash)],
+        ));
+        tags.push(Tag::custom(
+            TagKind::Custom(TAG_WRAPPED_KEY.into()),
+            vec![wrapped],
+        ));
+    }
+
+    Ok(EventBuilder::new(Kind::Custom(HASHTREE_KIND), "", tags))
+}
+
+/// Recipients `events`, share events of one author, grant `tree_name` to:
+/// those whose latest share of it isn't a revocation
+pub fn share_recipients(events: &[Event], tree_name: &str) -> Vec<PublicKey> {
+    let mut latest: BTreeMap<&str, &Event> = BTreeMap::new();
+    for event in events {
+        if event.kind != Kind::Custom(HASHTREE_KIND) || !has_label(event, SHARE_LABEL) {
+            continue;
+        }
+        let Some(d_tag) = event.identifier() else {
+            continue;
+        };
+        if tag_value(event, "tree") != Some(tree_name) {
+            continue;
+        }
+        match latest.get(d_tag) {
+            Some(newer) if newer.created_at >= event.created_at => {}
+            _ => {
+                latest.insert(d_tag, event);
+            }
+        }
+    }
+
+    // The d tag names the recipient, so each is listed once
+    latest
+        .values()
+        .filter(|event| tag_value(event, TAG_WRAPPED_KEY).is_some())
+        .filter_map(|event| PublicKey::from_hex(tag_value(event, "p")?).ok())
+        .collect()
+}
+
+/// First value of tag `name`
+fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event.tags.iter().find_map(|tag| {
+        let values = tag.as_slice();
+        (values.len() >= 2 && values[0] == name).then(|| values[1].as_str())
+    })
+}
+
 fn has_label(event: &Event, label: &str) -> bool {
     event.tags.iter().any(|tag| {
         let tag_vec = tag.as_slice();
@@ -79,6 +268,15 @@ fn is_hashtree_event(event: &Event) -> bool {
     has_label(event, HASHTREE_LABEL) || !has_any_label(event)
 }
 
+/// Owner and name of the tree `event` is a root of
+fn event_tree(event: &Event) -> Option<(PublicKey, String)> {
+    if event.kind != Kind::Custom(HASHTREE_KIND) || !is_hashtree_event(event) {
+        return None;
+    }
+    let tree_name = event.identifier().filter(|name| !name.is_empty())?;
+    Some((event.pubkey, tree_name.to_string()))
+}
+
 fn parse_legacy_content(content: &str) -> Option<(String, Option<String>)> {
     let trimmed = content.trim();
     if trimmed.is_empty() {
@@ -113,19 +311,29 @@ struct Subscription {
 pub struct NostrRootResolver {
     client: Client,
     config: NostrResolverConfig,
+    /// Options of every relay added, e.g. a proxy to connect through
+    relay_opts: RelayOptions,
     subscriptions: Arc<RwLock<HashMap<String, Subscription>>>,
 }
 
 impl NostrRootResolver {
     /// Create a new NostrRootResolver
     pub async fn new(config: NostrResolverConfig) -> Result<Self, ResolverError> {
+        Self::with_relay_options(config, RelayOptions::new()).await
+    }
+
+    /// Create a new NostrRootResolver connecting to relays with `relay_opts`
+    pub async fn with_relay_options(
+        config: NostrResolverConfig,
+        relay_opts: RelayOptions,
+    ) -> Result<Self, ResolverError> {
         let keys = config.secret_key.clone().unwrap_or_else(Keys::generate);
         let client = Client::new(keys);
 
         // Add relays
         for relay in &config.relays {
             client
-                .add_relay(relay)
+                .add_relay_with_opts(relay, relay_opts.clone())
                 .await
                 .map_err(|e| ResolverError::Network(e.to_string()))?;
         }
@@ -136,6 +344,7 @@ impl NostrRootResolver {
         Ok(Self {
             client,
             config,
+            relay_opts,
             subscriptions: Arc::new(RwLock::new(HashMap::new())),
         })
     }
@@ -327,41 +536,121 @@ impl NostrRootResolver {
             return Err(ResolverError::NotAuthorized);
         }
 
-        let key_bytes = cid.key.ok_or_else(|| ResolverError::Other("Missing CHK key for private publish".into()))?;
-        let key_hex = hex::encode(key_bytes);
+        let event = tree_root_event(keys, &tree_name, cid, TreeVisibility::Private, None)?;
 
-        let encrypted = nip44::encrypt(
-            keys.secret_key(),
-            &keys.public_key(),
-            key_hex,
-            nip44::Version::V2,
-        ).map_err(|e| ResolverError::Other(format!("NIP-44 encryption failed: {}", e)))?;
+        let output = self
+            .client
+            .send_event_builder(event)
+            .await
+            .map_err(|e| ResolverError::Network(e.to_string()))?;
 
-        let tags = vec![
-            Tag::identifier(tree_name.clone()),
-            Tag::custom(
-                TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::L)),
-                vec![HASHTREE_LABEL],
-            ),
-            Tag::custom(TagKind::Custom(TAG_HASH.into()), vec![to_hex(&cid.hash)]),
-            Tag::custom(TagKind::Custom(TAG_SELF_ENCRYPTED_KEY.into()), vec![encrypted]),
-        ];
+        Ok(!output.failed.is_empty() || !output.success.is_empty())
+    }
 
-        let event = EventBuilder::new(Kind::Custom(HASHTREE_KIND), "", tags);
+    /// Recipients we currently share `tree_name` with, from our share events
+    pub async fn share_recipients(&self, tree_name: &str) -> Result<Vec<PublicKey>, ResolverError> {
+        let pubkey = self.pubkey().ok_or(ResolverError::NotAuthorized)?;
+        let filter = Filter::new()
+            .kind(Kind::Custom(HASHTREE_KIND))
+            .author(pubkey)
+            .custom_tag(SingleLetterTag::lowercase(Alphabet::L), vec![SHARE_LABEL]);
+        let source = EventSource::relays(Some(self.config.resolve_timeout));
+        let events = self
+            .client
+            .get_events_of(vec![filter], source)
+            .await
+            .map_err(|e| ResolverError::Network(e.to_string()))?;
+        Ok(share_recipients(&events, tree_name))
+    }
 
+    /// Publish a share of `tree_name` at `cid` with `recipient`, or revoke
+    /// it when `cid` is None (see [`share_event`])
+    pub async fn publish_share(
+        &self,
+        tree_name: &str,
+        recipient: &PublicKey,
+        cid: Option<&Cid>,
+    ) -> Result<(), ResolverError> {
+        let keys = self
+            .config
+            .secret_key
+            .as_ref()
+            .ok_or(ResolverError::NotAuthorized)?;
+        let event = share_event(keys, tree_name, recipient, cid)?;
         let output = self
             .client
             .send_event_builder(event)
             .await
             .map_err(|e| ResolverError::Network(e.to_string()))?;
+        if output.success.is_empty() {
+            return Err(ResolverError::Network(format!(
+                "No relay accepted the share with {}",
+                recipient.to_hex()
+            )));
+        }
+        Ok(())
+    }
 
-        Ok(!output.failed.is_empty() || !output.success.is_empty())
+    /// A read-only resolver for one request that queries `relays`, e.g. the
+    /// relay hints of an naddr or nevent, with the same relay options. Our
+    /// own relays and invalid URLs are skipped; None if no relay is left.
+    /// `stop` it once the request is done.
+    pub async fn for_hints(&self, relays: &[String]) -> Option<Self> {
+        let client = Client::new(Keys::generate());
+        let mut added = Vec::new();
+        for relay in relays {
+            if self.config.relays.contains(relay) || added.contains(relay) {
+                continue;
+            }
+            if client
+                .add_relay_with_opts(relay, self.relay_opts.clone())
+                .await
+                .unwrap_or(false)
+            {
+                added.push(relay.clone());
+            }
+        }
+        if added.is_empty() {
+            return None;
+        }
+        client.connect().await;
+
+        Some(Self {
+            client,
+            config: NostrResolverConfig {
+                relays: added,
+                secret_key: None,
+                ..self.config.clone()
+            },
+            relay_opts: self.relay_opts.clone(),
+            subscriptions: Arc::new(RwLock::new(HashMap::new())),
+        })
     }
-}
 
-#[async_trait]
-impl RootResolver for NostrRootResolver {
-    async fn resolve(&self, key: &str) -> Result<Option<Cid>, ResolverError> {
+    /// Owner and name of the tree whose root event is `id`, or None if the
+    /// event isn't found or isn't a tree root
+    pub async fn tree_of_event(
+        &self,
+        id: &EventId,
+    ) -> Result<Option<(PublicKey, String)>, ResolverError> {
+        let source = EventSource::relays(Some(self.config.resolve_timeout));
+        let events = self
+            .client
+            .get_events_of(vec![Filter::new().id(*id)], source)
+            .await
+            .map_err(|e| ResolverError::Network(e.to_string()))?;
+        Ok(events
+            .iter()
+            .find(|event| event.id == *id)
+            .and_then(event_tree))
+    }
+
+    /// Resolve a key like `resolve()`, along with the `created_at` of the
+    /// root event (unix seconds), i.e. when the tree was last published
+    pub async fn resolve_timestamped(
+        &self,
+        key: &str,
+    ) -> Result<Option<(Cid, u64)>, ResolverError> {
         let (pubkey, tree_name) = Self::parse_key(key)?;
 
         // Create filter for this specific tree
@@ -410,10 +699,17 @@ impl RootResolver for NostrRootResolver {
         }
 
         // Extract Cid from event tags
-        match latest_event {
-            Some(event) => Ok(self.cid_from_event(event)),
-            None => Ok(None),
-        }
+        Ok(latest_event.and_then(|event| {
+            let cid = self.cid_from_event(event)?;
+            Some((cid, event.created_at.as_u64()))
+        }))
+    }
+}
+
+#[async_trait]
+impl RootResolver for NostrRootResolver {
+    async fn resolve(&self, key: &str) -> Result<Option<Cid>, ResolverError> {
+        Ok(self.resolve_timestamped(key).await?.map(|(cid, _)| cid))
     }
 
     async fn resolve_shared(
@@ -572,31 +868,16 @@ impl RootResolver for NostrRootResolver {
         let (pubkey, tree_name) = Self::parse_key(key)?;
 
         // Check we own this key
-        let my_pubkey = self.pubkey().ok_or(ResolverError::NotAuthorized)?;
-        if pubkey != my_pubkey {
+        let keys = self
+            .config
+            .secret_key
+            .as_ref()
+            .ok_or(ResolverError::NotAuthorized)?;
+        if pubkey != keys.public_key() {
             return Err(ResolverError::NotAuthorized);
         }
 
-        // Build event with tags
-        let mut tags = vec![
-            Tag::identifier(tree_name.clone()),
-            Tag::custom(
-                TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::L)),
-                vec![HASHTREE_LABEL],
-            ),
-            Tag::custom(TagKind::Custom(TAG_HASH.into()), vec![to_hex(&cid.hash)]),
-        ];
-
-        // Add key tag if present
-        if let Some(key) = cid.key {
-            tags.push(Tag::custom(
-                TagKind::Custom(TAG_KEY.into()),
-                vec![hex::encode(key)],
-            ));
-        }
-
-        // Content is empty - all data in tags
-        let event = EventBuilder::new(Kind::Custom(HASHTREE_KIND), "", tags);
+        let event = tree_root_event(keys, &tree_name, cid, TreeVisibility::Public, None)?;
 
         // Publish
         let output = self
@@ -626,30 +907,22 @@ impl RootResolver for NostrRootResolver {
     ) -> Result<bool, ResolverError> {
         let (pubkey, tree_name) = Self::parse_key(key)?;
 
-        let my_pubkey = self.pubkey().ok_or(ResolverError::NotAuthorized)?;
-        if pubkey != my_pubkey {
+        let keys = self
+            .config
+            .secret_key
+            .as_ref()
+            .ok_or(ResolverError::NotAuthorized)?;
+        if pubkey != keys.public_key() {
             return Err(ResolverError::NotAuthorized);
         }
 
-        let mut tags = vec![
-            Tag::identifier(tree_name.clone()),
-            Tag::custom(
-                TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::L)),
-                vec![HASHTREE_LABEL],
-            ),
-            Tag::custom(TagKind::Custom(TAG_HASH.into()), vec![to_hex(&cid.hash)]),
-        ];
-
-        // Mask the key with share_secret (XOR)
-        if let Some(key) = cid.key {
-            let masked = xor_keys(&key, share_secret);
-            tags.push(Tag::custom(
-                TagKind::Custom(TAG_ENCRYPTED_KEY.into()),
-                vec![hex::encode(masked)],
-            ));
-        }
-
-        let event = EventBuilder::new(Kind::Custom(HASHTREE_KIND), "", tags);
+        let event = tree_root_event(
+            keys,
+            &tree_name,
+            cid,
+            TreeVisibility::LinkVisible,
+            Some(share_secret),
+        )?;
 
         let output = self
             .client
@@ -758,4 +1031,129 @@ mod tests {
         let result = NostrRootResolver::parse_key(key);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_tree_root_event_format() {
+        let keys = Keys::generate();
+        let cid = Cid {
+            hash: [0x11; 32],
+            key: Some([0x22; 32]),
+        };
+        let secret = [0x33; 32];
+        let event = tree_root_event(
+            &keys,
+            "docs/travel",
+            &cid,
+            TreeVisibility::LinkVisible,
+            Some(&secret),
+        )
+        .unwrap()
+        .to_event(&keys)
+        .unwrap();
+        assert_eq!(event.kind, Kind::Custom(HASHTREE_KIND));
+        assert_eq!(event.content, to_hex(&cid.hash));
+        assert!(has_label(&event, HASHTREE_LABEL));
+        assert!(has_label(&event, "docs"));
+        assert_eq!(
+            NostrRootResolver::cid_from_event_shared(&event, &secret),
+            Some(cid.clone())
+        );
+        // The key itself is never in the clear
+        let tags = serde_json::to_string(&event.tags).unwrap();
+        assert!(!tags.contains(&to_hex(&[0x22; 32])));
+
+        let private = tree_root_event(&keys, "notes", &cid, TreeVisibility::Private, None)
+            .unwrap()
+            .to_event(&keys)
+            .unwrap();
+        let read = NostrRootResolver::cid_from_event_with_keys(&private, Some(&keys));
+        assert_eq!(read, Some(cid.clone()));
+        let unreadable = NostrRootResolver::cid_from_event_with_keys(&private, None);
+        assert_eq!(unreadable.unwrap().key, None);
+
+        let keyless = Cid {
+            hash: cid.hash,
+            key: None,
+        };
+        assert!(tree_root_event(&keys, "notes", &keyless, TreeVisibility::Private, None).is_err());
+        assert!(tree_root_event(&keys, "notes", &keyless, TreeVisibility::Public, None).is_ok());
+        // Sharing an unencrypted tree publishes its hash, as it always has
+        let shared = tree_root_event(
+            &keys,
+            "notes",
+            &keyless,
+            TreeVisibility::LinkVisible,
+            Some(&[7u8; 32]),
+        )
+        .unwrap()
+        .to_event(&keys)
+        .unwrap();
+        let read = NostrRootResolver::cid_from_event_with_keys(&shared, None);
+        assert_eq!(read, Some(keyless.clone()));
+        assert!(tree_root_event(&keys, "notes", &cid, TreeVisibility::LinkVisible, None).is_err());
+    }
+
+    #[test]
+    fn test_share_recipients_follow_latest_share() {
+        let owner = Keys::generate();
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let cid = Cid {
+            hash: [0x11; 32],
+            key: Some([0x22; 32]),
+        };
+        let share = |recipient: &Keys, tree: &str, cid: Option<&Cid>, at: u64| {
+            share_event(&owner, tree, &recipient.public_key(), cid)
+                .unwrap()
+                .custom_created_at(Timestamp::from(at))
+                .to_event(&owner)
+                .unwrap()
+        };
+
+        let granted = share(&alice, "photos", Some(&cid), 10);
+        assert!(has_label(&granted, SHARE_LABEL));
+        assert_eq!(event_tree(&granted), None);
+        let wrapped = tag_value(&granted, TAG_WRAPPED_KEY).unwrap();
+        let key = nip44::decrypt(alice.secret_key(), &owner.public_key(), wrapped).unwrap();
+        assert_eq!(key, to_hex(&[0x22; 32]));
+
+        let events = vec![
+            granted,
+            share(&bob, "photos", Some(&cid), 10),
+            share(&bob, "photos", None, 20),
+            share(&bob, "docs", Some(&cid), 30),
+        ];
+        assert_eq!(
+            share_recipients(&events, "photos"),
+            vec![alice.public_key()]
+        );
+        assert_eq!(share_recipients(&events, "docs"), vec![bob.public_key()]);
+
+        let keyless = Cid {
+            hash: cid.hash,
+            key: None,
+        };
+        assert!(share_event(&owner, "photos", &alice.public_key(), Some(&keyless)).is_err());
+    }
+
+    #[test]
+    fn test_event_tree() {
+        let keys = Keys::generate();
+        let cid = Cid {
+            hash: [0x11; 32],
+            key: None,
+        };
+        let root = tree_root_event(&keys, "videos/Music", &cid, TreeVisibility::Public, None)
+            .unwrap()
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(
+            event_tree(&root),
+            Some((keys.public_key(), "videos/Music".to_string()))
+        );
+
+        let note = EventBuilder::text_note("hello", [])
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(event_tree(&note), None);
+    }
 }
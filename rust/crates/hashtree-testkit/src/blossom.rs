@@ -0,0 +1,406 @@
+//! In-memory Blossom server
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Json, Router,
+};
+use base64::Engine;
+use nostr::Event;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+
+/// Kind of Blossom authorization events
+const AUTH_KIND: u16 = 24242;
+
+/// Blob descriptor as returned by uploads and listings (BUD-02)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobDescriptor {
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+    #[serde(rename = "type")]
+    pub content_type: String,
+    pub uploaded: u64,
+}
+
+/// Blossom server (BUD-01/02) keeping blobs in memory
+///
+/// Serves `GET`/`HEAD /<sha256>[.ext]` with byte ranges, `PUT /upload`,
+/// `DELETE /<sha256>` and `GET /list/<pubkey>`. Authorization events
+/// (kind 24242) are checked when sent; uploads need one only after
+/// [`set_require_auth`](Self::set_require_auth), deletes always do and only
+/// remove the uploader's claim. [`set_available`](Self::set_available)
+/// takes the server down (503) to exercise fallbacks.
+pub struct MockBlossom {
+    port: u16,
+    state: Arc<BlossomState>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+struct BlossomState {
+    base_url: String,
+    blobs: Mutex<HashMap<String, Blob>>,
+    require_auth: AtomicBool,
+    available: AtomicBool,
+}
+
+struct Blob {
+    data: Vec<u8>,
+    content_type: String,
+    uploaded: u64,
+    /// Pubkeys (hex) of the uploaders
+    owners: HashSet<String>,
+}
+
+impl MockBlossom {
+    /// Start a server on a free port
+    pub fn start() -> Self {
+        Self::with_port(0)
+    }
+
+    /// Start a server on `port`, for tests that need a fixed address
+    pub fn with_port(port: u16) -> Self {
+        let listener = crate::bind(port);
+        let port = listener
+            .local_addr()
+            .expect("Listener has no address")
+            .port();
+        let state = Arc::new(BlossomState {
+            base_url: format!("http://127.0.0.1:{}", port),
+            blobs: Mutex::new(HashMap::new()),
+            require_auth: AtomicBool::new(false),
+            available: AtomicBool::new(true),
+        });
+        let (shutdown, shutdown_rx) = oneshot::channel();
+
+        let router = Router::new()
+            .route(
+                "/upload",
+                put(handle_upload).layer(DefaultBodyLimit::disable()),
+            )
+            .route("/list/:pubkey", get(handle_list))
+            .route("/:blob", get(handle_get).delete(handle_delete))
+            .with_state(state.clone());
+        crate::spawn_server(shutdown_rx, move || async move {
+            let listener = tokio::net::TcpListener::from_std(listener)
+                .expect("Failed to register Blossom listener");
+            let _ = axum::serve(listener, router).await;
+        });
+
+        Self {
+            port,
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn url(&self) -> String {
+        self.state.base_url.clone()
+    }
+
+    /// Require a valid authorization event for uploads
+    pub fn set_require_auth(&self, required: bool) {
+        self.state.require_auth.store(required, Ordering::SeqCst);
+    }
+
+    /// Answer every request with 503 while unavailable
+    pub fn set_available(&self, available: bool) {
+        self.state.available.store(available, Ordering::SeqCst);
+    }
+
+    /// Store `data` as if uploaded anonymously, returning its hash
+    pub fn insert(&self, data: &[u8]) -> String {
+        let hash = sha256_hex(data);
+        self.state.blobs.lock().unwrap().insert(
+            hash.clone(),
+            Blob {
+                data: data.to_vec(),
+                content_type: "application/octet-stream".to_string(),
+                uploaded: unix_now(),
+                owners: HashSet::new(),
+            },
+        );
+        hash
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Vec<u8>> {
+        let blobs = self.state.blobs.lock().unwrap();
+        blobs.get(hash).map(|blob| blob.data.clone())
+    }
+
+    pub fn has(&self, hash: &str) -> bool {
+        self.state.blobs.lock().unwrap().contains_key(hash)
+    }
+
+    /// Drop a blob regardless of its uploaders
+    pub fn remove(&self, hash: &str) -> bool {
+        self.state.blobs.lock().unwrap().remove(hash).is_some()
+    }
+
+    /// Hashes of the stored blobs, sorted
+    pub fn hashes(&self) -> Vec<String> {
+        let mut hashes: Vec<String> = self.state.blobs.lock().unwrap().keys().cloned().collect();
+        hashes.sort();
+        hashes
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.blobs.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Drop for MockBlossom {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+impl BlossomState {
+    fn is_available(&self) -> bool {
+        self.available.load(Ordering::SeqCst)
+    }
+
+    fn descriptor(&self, hash: &str, blob: &Blob) -> BlobDescriptor {
+        BlobDescriptor {
+            url: format!("{}/{}", self.base_url, hash),
+            sha256: hash.to_string(),
+            size: blob.data.len() as u64,
+            content_type: blob.content_type.clone(),
+            uploaded: blob.uploaded,
+        }
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Error response with the reason in `X-Reason`, as BUD-01 asks
+fn error(status: StatusCode, reason: &'static str) -> Response {
+    (status, [("X-Reason", reason)], reason).into_response()
+}
+
+/// Pubkey (hex) of a valid authorization event for `verb` on `hash`, or
+/// None without an `Authorization` header. Errors are reasons to answer
+/// 401 with.
+fn authorize(headers: &HeaderMap, verb: &str, hash: &str) -> Result<Option<String>, &'static str> {
+    let Some(value) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let encoded = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.strip_prefix("Nostr "))
+        .ok_or("invalid authorization scheme")?;
+    let json = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .map_err(|_| "invalid authorization encoding")?;
+    let event: Event = serde_json::from_slice(&json).map_err(|_| "invalid authorization event")?;
+    if event.verify().is_err() || event.kind.as_u16() != AUTH_KIND {
+        return Err("invalid authorization event");
+    }
+
+    if !tag_values(&event, "t").any(|t| t == verb) {
+        return Err("authorization is for another action");
+    }
+    let hashes: Vec<&str> = tag_values(&event, "x").collect();
+    if !hashes.is_empty() && !hashes.contains(&hash) {
+        return Err("authorization is for another blob");
+    }
+    let expiration = tag_values(&event, "expiration").find_map(|e| e.parse::<u64>().ok());
+    if !matches!(expiration, Some(e) if e > unix_now()) {
+        return Err("authorization expired");
+    }
+    Ok(Some(event.pubkey.to_hex()))
+}
+
+/// Values of the `name` tags of `event`
+fn tag_values<'a>(event: &'a Event, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    event
+        .tags
+        .iter()
+        .filter_map(move |tag| match tag.as_slice() {
+            [key, value, ..] if key == name => Some(value.as_str()),
+            _ => None,
+        })
+}
+
+/// Byte range of a `Range: bytes=a-b` header within `len` bytes
+fn byte_range(headers: &HeaderMap, len: usize) -> Option<Result<(usize, usize), ()>> {
+    let spec = headers
+        .get(header::RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let start: usize = start.trim().parse().ok()?;
+    let end = match end.trim() {
+        "" => len.saturating_sub(1),
+        end => end.parse::<usize>().ok()?.min(len.saturating_sub(1)),
+    };
+    Some(if start < len && start <= end {
+        Ok((start, end))
+    } else {
+        Err(())
+    })
+}
+
+async fn handle_get(
+    State(state): State<Arc<BlossomState>>,
+    Path(blob): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if !state.is_available() {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "unavailable");
+    }
+    let hash = blob
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let (data, content_type) = {
+        let blobs = state.blobs.lock().unwrap();
+        match blobs.get(&hash) {
+            Some(blob) => (blob.data.clone(), blob.content_type.clone()),
+            None => return error(StatusCode::NOT_FOUND, "not found"),
+        }
+    };
+
+    let total = data.len();
+    let (status, body, content_range) = match byte_range(&headers, total) {
+        Some(Ok((start, end))) => (
+            StatusCode::PARTIAL_CONTENT,
+            data[start..=end].to_vec(),
+            Some(format!("bytes {}-{}/{}", start, end, total)),
+        ),
+        Some(Err(())) => {
+            let mut response = error(StatusCode::RANGE_NOT_SATISFIABLE, "range not satisfiable");
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", total)) {
+                response.headers_mut().insert(header::CONTENT_RANGE, value);
+            }
+            return response;
+        }
+        None => (StatusCode::OK, data, None),
+    };
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, body.len())
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(content_range) = content_range {
+        response = response.header(header::CONTENT_RANGE, content_range);
+    }
+    response.body(Body::from(body)).unwrap()
+}
+
+async fn handle_upload(
+    State(state): State<Arc<BlossomState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !state.is_available() {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "unavailable");
+    }
+    let hash = sha256_hex(&body);
+    let claimed = headers.get("X-SHA-256").and_then(|v| v.to_str().ok());
+    if claimed.is_some_and(|claimed| !claimed.eq_ignore_ascii_case(&hash)) {
+        return error(StatusCode::BAD_REQUEST, "hash mismatch");
+    }
+    let owner = match authorize(&headers, "upload", &hash) {
+        Ok(None) if state.require_auth.load(Ordering::SeqCst) => {
+            return error(StatusCode::UNAUTHORIZED, "authorization required");
+        }
+        Ok(owner) => owner,
+        Err(reason) => return error(StatusCode::UNAUTHORIZED, reason),
+    };
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    let mut blobs = state.blobs.lock().unwrap();
+    let blob = blobs.entry(hash.clone()).or_insert_with(|| Blob {
+        data: body.to_vec(),
+        content_type,
+        uploaded: unix_now(),
+        owners: HashSet::new(),
+    });
+    blob.owners.extend(owner);
+    Json(state.descriptor(&hash, blob)).into_response()
+}
+
+async fn handle_delete(
+    State(state): State<Arc<BlossomState>>,
+    Path(blob): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if !state.is_available() {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "unavailable");
+    }
+    let hash = blob
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let owner = match authorize(&headers, "delete", &hash) {
+        Ok(Some(owner)) => owner,
+        Ok(None) => return error(StatusCode::UNAUTHORIZED, "authorization required"),
+        Err(reason) => return error(StatusCode::UNAUTHORIZED, reason),
+    };
+
+    let mut blobs = state.blobs.lock().unwrap();
+    let Some(blob) = blobs.get_mut(&hash) else {
+        return error(StatusCode::NOT_FOUND, "not found");
+    };
+    if !blob.owners.remove(&owner) {
+        return error(StatusCode::FORBIDDEN, "not an uploader of this blob");
+    }
+    if blob.owners.is_empty() {
+        blobs.remove(&hash);
+    }
+    StatusCode::OK.into_response()
+}
+
+async fn handle_list(
+    State(state): State<Arc<BlossomState>>,
+    Path(pubkey): Path<String>,
+) -> Response {
+    if !state.is_available() {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "unavailable");
+    }
+    let blobs = state.blobs.lock().unwrap();
+    let mut descriptors: Vec<BlobDescriptor> = blobs
+        .iter()
+        .filter(|(_, blob)| blob.owners.contains(&pubkey))
+        .map(|(hash, blob)| state.descriptor(hash, blob))
+        .collect();
+    descriptors.sort_by(|a, b| b.uploaded.cmp(&a.uploaded).then(a.sha256.cmp(&b.sha256)));
+    Json(descriptors).into_response()
+}
@@ -0,0 +1,68 @@
+//! Test harness for hashtree integration tests
+//!
+//! Relay and Blossom servers for the tests that run a client against them
+//! over the network (hashtree-cli, git-remote-htree), so each doesn't carry
+//! its own:
+//!
+//! - [`MockRelay`]: in-memory Nostr relay (NIP-01) on a local port
+//! - [`MockBlossom`]: in-memory Blossom server (BUD-01/02) on a local port
+//! - [`TestStore`]: in-memory [`Store`](hashtree_core::Store) counting its
+//!   calls, with injectable latency and outages
+//!
+//! The servers run on a runtime of their own, so they work from sync tests
+//! and from any `#[tokio::test]`, and stop when dropped. Both bind a free port
+//! unless given one.
+//!
+//! ```rust,ignore
+//! use hashtree_testkit::{MockBlossom, MockRelay};
+//!
+//! let relay = MockRelay::start();
+//! let blossom = MockBlossom::start();
+//! let client = BlossomClient::new(keys).with_servers(vec![blossom.url()]);
+//! let resolver = NostrRootResolver::new(NostrResolverConfig {
+//!     relays: vec![relay.url()],
+//!     ..Default::default()
+//! })
+//! .await?;
+//! ```
+
+mod blossom;
+mod relay;
+mod store;
+
+pub use blossom::{BlobDescriptor, MockBlossom};
+pub use relay::MockRelay;
+pub use store::{StoreCalls, TestStore};
+
+use std::net::TcpListener;
+
+/// Bind 127.0.0.1 at `port`, or a free port if 0
+fn bind(port: u16) -> TcpListener {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .unwrap_or_else(|e| panic!("Failed to bind 127.0.0.1:{}: {}", port, e));
+    listener
+        .set_nonblocking(true)
+        .expect("Failed to make listener non-blocking");
+    listener
+}
+
+/// Run `serve` on a runtime of its own thread until `shutdown` fires
+fn spawn_server<F, Fut>(shutdown: tokio::sync::oneshot::Receiver<()>, serve: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()>,
+{
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .expect("Failed to build test server runtime");
+        rt.block_on(async move {
+            tokio::select! {
+                _ = shutdown => {}
+                _ = serve() => {}
+            }
+        });
+    });
+}
@@ -0,0 +1,246 @@
+//! In-memory Nostr relay
+
+use futures::{SinkExt, StreamExt};
+use nostr::{Event, Filter};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, oneshot};
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+/// Nostr relay (NIP-01) keeping events in memory
+///
+/// Events are checked against their signatures, replaceable and
+/// parameterized replaceable events replace older versions, and ephemeral
+/// ones are only passed on to live subscriptions, as on a real relay.
+/// Filters are matched in full, `limit` included.
+pub struct MockRelay {
+    port: u16,
+    shared: Arc<Shared>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+struct Shared {
+    events: Mutex<Vec<Event>>,
+    live: broadcast::Sender<Event>,
+}
+
+impl MockRelay {
+    /// Start a relay on a free port
+    pub fn start() -> Self {
+        Self::with_port(0)
+    }
+
+    /// Start a relay on `port`, for tests that need a fixed address
+    pub fn with_port(port: u16) -> Self {
+        let listener = crate::bind(port);
+        let port = listener
+            .local_addr()
+            .expect("Listener has no address")
+            .port();
+        let (live, _) = broadcast::channel(1024);
+        let shared = Arc::new(Shared {
+            events: Mutex::new(Vec::new()),
+            live,
+        });
+        let (shutdown, shutdown_rx) = oneshot::channel();
+
+        let server = shared.clone();
+        crate::spawn_server(shutdown_rx, move || async move {
+            let listener = tokio::net::TcpListener::from_std(listener)
+                .expect("Failed to register relay listener");
+            loop {
+                if let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(handle_connection(stream, server.clone()));
+                }
+            }
+        });
+
+        Self {
+            port,
+            shared,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn url(&self) -> String {
+        format!("ws://127.0.0.1:{}", self.port)
+    }
+
+    /// Events currently stored, oldest first
+    pub fn events(&self) -> Vec<Event> {
+        let mut events = self.shared.events.lock().unwrap().clone();
+        events.sort_by_key(|event| event.created_at);
+        events
+    }
+
+    /// Stored events matching `filter`, newest first
+    pub fn query(&self, filter: &Filter) -> Vec<Event> {
+        self.shared.query(std::slice::from_ref(filter))
+    }
+
+    /// Accept `event` as if a client had published it, e.g. to seed the
+    /// relay. Returns false if its signature doesn't verify.
+    pub fn publish(&self, event: Event) -> bool {
+        if event.verify().is_err() {
+            return false;
+        }
+        self.shared.publish(event);
+        true
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+impl Shared {
+    fn publish(&self, event: Event) {
+        if !event.kind.is_ephemeral() {
+            let mut events = self.events.lock().unwrap();
+            if events.iter().any(|e| e.id == event.id) {
+                return;
+            }
+            if event.kind.is_replaceable() || event.kind.is_parameterized_replaceable() {
+                let parameterized = event.kind.is_parameterized_replaceable();
+                let d = identifier(&event);
+                let replaces = |e: &Event| {
+                    e.kind == event.kind
+                        && e.pubkey == event.pubkey
+                        && (!parameterized || identifier(e) == d)
+                };
+                if events
+                    .iter()
+                    .any(|e| replaces(e) && e.created_at > event.created_at)
+                {
+                    return;
+                }
+                events.retain(|e| !replaces(e));
+            }
+            events.push(event.clone());
+        }
+        let _ = self.live.send(event);
+    }
+
+    /// Stored events matching any of `filters`, newest first per filter
+    fn query(&self, filters: &[Filter]) -> Vec<Event> {
+        let events = self.events.lock().unwrap();
+        let mut seen = HashSet::new();
+        let mut results = Vec::new();
+        for filter in filters {
+            let mut matching: Vec<&Event> =
+                events.iter().filter(|e| filter.match_event(e)).collect();
+            matching.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+            if let Some(limit) = filter.limit {
+                matching.truncate(limit);
+            }
+            for event in matching {
+                if seen.insert(event.id) {
+                    results.push(event.clone());
+                }
+            }
+        }
+        results
+    }
+
+    /// Replies to a client message, updating its subscriptions
+    fn handle(&self, text: &str, subscriptions: &mut HashMap<String, Vec<Filter>>) -> Vec<Value> {
+        let Ok(message) = serde_json::from_str::<Vec<Value>>(text) else {
+            return vec![json!(["NOTICE", "invalid: not a JSON array"])];
+        };
+        match message.first().and_then(Value::as_str) {
+            Some("EVENT") if message.len() >= 2 => {
+                let event: Event = match serde_json::from_value(message[1].clone()) {
+                    Ok(event) => event,
+                    Err(e) => return vec![json!(["NOTICE", format!("invalid: {}", e)])],
+                };
+                if event.verify().is_err() {
+                    return vec![json!(["OK", event.id, false, "invalid: bad signature"])];
+                }
+                let id = event.id;
+                self.publish(event);
+                vec![json!(["OK", id, true, ""])]
+            }
+            Some("REQ") if message.len() >= 2 => {
+                let sub_id = message[1].as_str().unwrap_or_default().to_string();
+                let filters: Result<Vec<Filter>, _> = message[2..]
+                    .iter()
+                    .map(|f| serde_json::from_value(f.clone()))
+                    .collect();
+                let filters = match filters {
+                    Ok(filters) => filters,
+                    Err(e) => return vec![json!(["CLOSED", sub_id, format!("invalid: {}", e)])],
+                };
+                let mut replies: Vec<Value> = self
+                    .query(&filters)
+                    .into_iter()
+                    .map(|event| json!(["EVENT", sub_id, event]))
+                    .collect();
+                replies.push(json!(["EOSE", sub_id]));
+                subscriptions.insert(sub_id, filters);
+                replies
+            }
+            Some("CLOSE") if message.len() >= 2 => {
+                if let Some(sub_id) = message[1].as_str() {
+                    subscriptions.remove(sub_id);
+                }
+                Vec::new()
+            }
+            _ => vec![json!(["NOTICE", "unsupported message"])],
+        }
+    }
+}
+
+/// `d` tag of a parameterized replaceable event
+fn identifier(event: &Event) -> Option<String> {
+    event.tags.iter().find_map(|tag| match tag.as_slice() {
+        [name, value, ..] if name == "d" => Some(value.clone()),
+        _ => None,
+    })
+}
+
+async fn handle_connection(stream: TcpStream, shared: Arc<Shared>) {
+    let Ok(ws) = accept_async(stream).await else {
+        return;
+    };
+    let (mut write, mut read) = ws.split();
+    let mut live = shared.live.subscribe();
+    let mut subscriptions: HashMap<String, Vec<Filter>> = HashMap::new();
+
+    loop {
+        let replies = tokio::select! {
+            message = read.next() => match message {
+                Some(Ok(Message::Text(text))) => shared.handle(&text, &mut subscriptions),
+                Some(Ok(Message::Ping(data))) => {
+                    let _ = write.send(Message::Pong(data)).await;
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            event = live.recv() => match event {
+                Ok(event) => subscriptions
+                    .iter()
+                    .filter(|(_, filters)| filters.iter().any(|f| f.match_event(&event)))
+                    .map(|(sub_id, _)| json!(["EVENT", sub_id, event]))
+                    .collect(),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        for reply in replies {
+            if write.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! In-memory store for tests
+
+use async_trait::async_trait;
+use hashtree_core::store::StoreStats;
+use hashtree_core::{Hash, MemoryStore, Store, StoreError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Calls a [`TestStore`] has served, batch calls counted per item
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreCalls {
+    pub gets: u64,
+    pub puts: u64,
+    pub has: u64,
+    pub deletes: u64,
+}
+
+/// [`MemoryStore`] counting its calls, with injectable latency and outages
+///
+/// Clones share the data and counters, so a test can keep one to inspect
+/// while handing another to the code under test.
+#[derive(Clone)]
+pub struct TestStore {
+    memory: MemoryStore,
+    state: Arc<TestState>,
+}
+
+struct TestState {
+    calls: Mutex<StoreCalls>,
+    latency: Mutex<Duration>,
+    available: AtomicBool,
+}
+
+impl TestStore {
+    pub fn new() -> Self {
+        Self {
+            memory: MemoryStore::new(),
+            state: Arc::new(TestState {
+                calls: Mutex::new(StoreCalls::default()),
+                latency: Mutex::new(Duration::ZERO),
+                available: AtomicBool::new(true),
+            }),
+        }
+    }
+
+    /// The underlying store, to seed or inspect without counting
+    pub fn memory(&self) -> &MemoryStore {
+        &self.memory
+    }
+
+    pub fn calls(&self) -> StoreCalls {
+        *self.state.calls.lock().unwrap()
+    }
+
+    pub fn reset_calls(&self) {
+        *self.state.calls.lock().unwrap() = StoreCalls::default();
+    }
+
+    /// Delay every call by `latency`, like a remote store
+    pub fn set_latency(&self, latency: Duration) {
+        *self.state.latency.lock().unwrap() = latency;
+    }
+
+    /// Fail every call while unavailable
+    pub fn set_available(&self, available: bool) {
+        self.state.available.store(available, Ordering::SeqCst);
+    }
+
+    /// Count a call, then wait out the latency or fail if unavailable
+    async fn enter(&self, count: impl FnOnce(&mut StoreCalls)) -> Result<(), StoreError> {
+        count(&mut self.state.calls.lock().unwrap());
+        let latency = *self.state.latency.lock().unwrap();
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        if self.state.available.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(StoreError::Other("store unavailable".to_string()))
+        }
+    }
+}
+
+impl Default for TestStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Store for TestStore {
+    async fn put(&self, hash: Hash, data: Vec<u8>) -> Result<bool, StoreError> {
+        self.enter(|calls| calls.puts += 1).await?;
+        self.memory.put(hash, data).await
+    }
+
+    async fn get(&self, hash: &Hash) -> Result<Option<Vec<u8>>, StoreError> {
+        self.enter(|calls| calls.gets += 1).await?;
+        self.memory.get(hash).await
+    }
+
+    async fn has(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.enter(|calls| calls.has += 1).await?;
+        self.memory.has(hash).await
+    }
+
+    async fn delete(&self, hash: &Hash) -> Result<bool, StoreError> {
+        self.enter(|calls| calls.deletes += 1).await?;
+        self.memory.delete(hash).await
+    }
+
+    fn set_max_bytes(&self, max: u64) {
+        self.memory.set_max_bytes(max)
+    }
+
+    fn max_bytes(&self) -> Option<u64> {
+        self.memory.max_bytes()
+    }
+
+    async fn stats(&self) -> StoreStats {
+        self.memory.stats().await
+    }
+
+    async fn evict_if_needed(&self) -> Result<u64, StoreError> {
+        self.memory.evict_if_needed().await
+    }
+
+    async fn pin(&self, hash: &Hash) -> Result<(), StoreError> {
+        self.memory.pin(hash).await
+    }
+
+    async fn unpin(&self, hash: &Hash) -> Result<(), StoreError> {
+        self.memory.unpin(hash).await
+    }
+
+    fn pin_count(&self, hash: &Hash) -> u32 {
+        self.memory.pin_count(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashtree_core::sha256;
+
+    #[tokio::test]
+    async fn test_counts_calls_and_fails_when_unavailable() {
+        let store = TestStore::new();
+        let data = b"hello".to_vec();
+        let hash = sha256(&data);
+
+        assert!(store.put(hash, data.clone()).await.unwrap());
+        assert_eq!(store.get(&hash).await.unwrap(), Some(data));
+        assert!(store.clone().has(&hash).await.unwrap());
+        assert_eq!(
+            store.calls(),
+            StoreCalls {
+                gets: 1,
+                puts: 1,
+                has: 1,
+                deletes: 0,
+            }
+        );
+
+        store.set_available(false);
+        assert!(store.get(&hash).await.is_err());
+        assert_eq!(store.calls().gets, 2);
+        assert_eq!(store.memory().size(), 1);
+
+        store.set_available(true);
+        store.reset_calls();
+        assert!(store.delete(&hash).await.unwrap());
+        assert_eq!(store.calls().deletes, 1);
+        assert_eq!(store.memory().size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let store = TestStore::new();
+        store.set_latency(Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        store.has(&[0u8; 32]).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,178 @@
+//! The mock servers against real clients
+
+use hashtree_blossom::{compute_sha256, BlossomClient, BlossomError};
+use hashtree_testkit::{BlobDescriptor, MockBlossom, MockRelay};
+use nostr::{EventBuilder, Filter, Keys, Kind, Tag, Timestamp};
+use nostr_sdk::{ClientBuilder, EventSource};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_relay_publish_and_query() {
+    let relay = MockRelay::start();
+    let keys = Keys::generate();
+
+    let client = ClientBuilder::default().build();
+    client.add_relay(relay.url()).await.unwrap();
+    client.connect().await;
+
+    let event = EventBuilder::new(Kind::TextNote, "hello", [])
+        .to_event(&keys)
+        .unwrap();
+    client.send_event(event.clone()).await.unwrap();
+
+    let filter = Filter::new().author(keys.public_key()).kind(Kind::TextNote);
+    let events = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.get_events_of(vec![filter.clone()], EventSource::relays(None)),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].id, event.id);
+    assert_eq!(relay.query(&filter).len(), 1);
+
+    // Filters are matched in full
+    let other = Filter::new().author(Keys::generate().public_key());
+    assert!(relay.query(&other).is_empty());
+
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_relay_replaceable_and_ephemeral_events() {
+    let relay = MockRelay::start();
+    let keys = Keys::generate();
+    let metadata = |content: &str, at: u64| {
+        EventBuilder::new(Kind::Metadata, content, [])
+            .custom_created_at(Timestamp::from(at))
+            .to_event(&keys)
+            .unwrap()
+    };
+    let root = |tree: &str, at: u64| {
+        EventBuilder::new(Kind::Custom(30078), "root", [Tag::identifier(tree)])
+            .custom_created_at(Timestamp::from(at))
+            .to_event(&keys)
+            .unwrap()
+    };
+
+    assert!(relay.publish(metadata("new", 2000)));
+    assert!(relay.publish(metadata("old", 1000)));
+    assert!(relay.publish(root("docs", 1000)));
+    assert!(relay.publish(root("docs", 2000)));
+    assert!(relay.publish(root("photos", 1000)));
+    assert!(relay.publish(
+        EventBuilder::new(Kind::Custom(25050), "hello", [])
+            .to_event(&keys)
+            .unwrap()
+    ));
+
+    let events = relay.events();
+    assert_eq!(events.len(), 3);
+    let metadata = relay.query(&Filter::new().kind(Kind::Metadata));
+    assert_eq!(metadata[0].content, "new");
+    let roots = relay.query(&Filter::new().kind(Kind::Custom(30078)).limit(1));
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0].created_at, Timestamp::from(2000));
+}
+
+#[tokio::test]
+async fn test_relay_sends_live_events() {
+    let relay = MockRelay::start();
+    let keys = Keys::generate();
+
+    let client = ClientBuilder::default().build();
+    client.add_relay(relay.url()).await.unwrap();
+    client.connect().await;
+    let mut notifications = client.notifications();
+    client
+        .subscribe(vec![Filter::new().kind(Kind::Custom(25050))], None)
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let event = EventBuilder::new(Kind::Custom(25050), "hello", [])
+        .to_event(&keys)
+        .unwrap();
+    assert!(relay.publish(event.clone()));
+
+    let received = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(nostr_sdk::RelayPoolNotification::Event {
+                event: received, ..
+            }) = notifications.recv().await
+            {
+                return received.id;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(received, event.id);
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_blossom_round_trip() {
+    let blossom = MockBlossom::start();
+    let keys = Keys::generate();
+    let client = BlossomClient::new_empty(keys.clone()).with_servers(vec![blossom.url()]);
+
+    let data = b"hello blossom".to_vec();
+    let hash = client.upload(&data).await.unwrap();
+    assert_eq!(hash, compute_sha256(&data));
+    assert_eq!(blossom.get(&hash), Some(data.clone()));
+    assert!(client.exists(&hash).await);
+    assert_eq!(client.download(&hash).await.unwrap(), data);
+    assert_eq!(
+        client.download_range(&hash, 6, 7).await.unwrap(),
+        b"blossom"
+    );
+
+    let listed: Vec<BlobDescriptor> = reqwest::get(format!(
+        "{}/list/{}",
+        blossom.url(),
+        keys.public_key().to_hex()
+    ))
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].sha256, hash);
+    assert_eq!(listed[0].size, data.len() as u64);
+
+    // Only an uploader can delete
+    let stranger = BlossomClient::new_empty(Keys::generate()).with_servers(vec![blossom.url()]);
+    assert!(stranger.delete(&hash).await.is_err());
+    assert_eq!(client.delete(&hash).await.unwrap(), 1);
+    assert!(!blossom.has(&hash));
+}
+
+#[tokio::test]
+async fn test_blossom_auth_and_outage() {
+    let blossom = MockBlossom::start();
+    blossom.set_require_auth(true);
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/upload", blossom.url()))
+        .body("anonymous")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert!(blossom.is_empty());
+
+    let client = BlossomClient::new_empty(Keys::generate()).with_servers(vec![blossom.url()]);
+    let hash = client.upload(b"signed").await.unwrap();
+    assert!(blossom.has(&hash));
+
+    blossom.set_available(false);
+    assert!(matches!(
+        client.download(&hash).await,
+        Err(BlossomError::DownloadFailed(_))
+    ));
+    blossom.set_available(true);
+    assert_eq!(client.download(&hash).await.unwrap(), b"signed");
+}